@@ -0,0 +1,32 @@
+/* Copyright 2021 Matt Spraggs
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use yarel::vm::{self, Vm};
+use yarel_bench::BENCHMARKS;
+
+fn criterion_benchmark(c: &mut Criterion) {
+    let mut vm = Vm::with_built_ins();
+
+    for benchmark in &BENCHMARKS {
+        c.bench_function(benchmark.name, |b| {
+            b.iter(|| vm::interpret(&mut vm, benchmark.source.to_string(), None))
+        });
+    }
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,61 @@
+/* Copyright 2021 Matt Spraggs
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Benchmark scripts for the interpreter. Each script is timed with criterion by `cargo bench`,
+//! and the number of instructions each executes is printed by the `yarel-bench` binary, which
+//! gives a measure of regressions in the generated bytecode that doesn't vary between machines.
+//! The scripts can also be run directly with `yarel-cli`.
+
+use yarel::error::Error;
+use yarel::vm::{self, Vm};
+
+pub struct Benchmark {
+    pub name: &'static str,
+    pub source: &'static str,
+}
+
+pub const BENCHMARKS: [Benchmark; 5] = [
+    Benchmark {
+        name: "fib",
+        source: include_str!("../scripts/fib.yl"),
+    },
+    Benchmark {
+        name: "binary_trees",
+        source: include_str!("../scripts/binary_trees.yl"),
+    },
+    Benchmark {
+        name: "string_churn",
+        source: include_str!("../scripts/string_churn.yl"),
+    },
+    Benchmark {
+        name: "method_dispatch",
+        source: include_str!("../scripts/method_dispatch.yl"),
+    },
+    Benchmark {
+        name: "map_stress",
+        source: include_str!("../scripts/map_stress.yl"),
+    },
+];
+
+impl Benchmark {
+    /// Runs the script once on a new virtual machine, returning the number of instructions
+    /// executed by the script itself.
+    pub fn count_instructions(&self) -> Result<u64, Error> {
+        let mut vm = Vm::with_built_ins();
+        vm.enable_instruction_count();
+        vm::interpret(&mut vm, self.source.to_string(), None)?;
+        Ok(vm.instruction_count().unwrap_or_default())
+    }
+}
@@ -0,0 +1,32 @@
+/* Copyright 2021 Matt Spraggs
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Prints the number of instructions executed by each benchmark script.
+
+use std::process;
+
+use yarel_bench::BENCHMARKS;
+
+fn main() {
+    for benchmark in &BENCHMARKS {
+        match benchmark.count_instructions() {
+            Ok(count) => println!("{:<16} {:>12}", benchmark.name, count),
+            Err(error) => {
+                eprintln!("{} failed: {}", benchmark.name, error.messages().join("\n"));
+                process::exit(70);
+            }
+        }
+    }
+}
@@ -15,14 +15,152 @@
 
 use std::env;
 use std::fs;
-use std::io::{self, Write};
+use std::io::{self, Read, Write};
+use std::path::Path;
 use std::process;
+use std::time::Instant;
 
 use yarel::error::{Error, ErrorKind};
+use yarel::loader::FileSystemLoader;
 use yarel::value::Value;
-use yarel::vm::{self, Vm};
+use yarel::vm::{self, EnvAccess, Vm, VmOptions};
+use yarel::warning::WarningKind;
 
-fn repl(vm: &mut Vm) {
+const USAGE: &str = "Usage: ./yarel-cli [-I<dir>] [-Wall] [-W<warning>] [--profile] [--vm-stats] \
+                     [--trace] [--coverage[=lcov-path]] [--allow-env[=read]] \
+                     [--allow-subprocess] [--strict-math] [path | -e source | -] [args...]";
+
+/// Where the program to run comes from.
+enum Program {
+    File(String),
+    /// Source passed on the command line with `-e`.
+    Eval(String),
+    Stdin,
+}
+
+#[derive(Default)]
+struct Options {
+    profile: bool,
+    vm_stats: bool,
+    trace: bool,
+    coverage_path: Option<String>,
+    warnings: Vec<WarningKind>,
+    search_paths: Vec<String>,
+    env_access: Option<EnvAccess>,
+    allow_subprocess: bool,
+    strict_math: bool,
+    program: Option<Program>,
+    script_args: Vec<String>,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Option<Self> {
+        let mut options = Options::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--profile" {
+                options.profile = true;
+            } else if arg == "--vm-stats" {
+                options.vm_stats = true;
+            } else if arg == "--trace" {
+                options.trace = true;
+            } else if arg == "--coverage" {
+                options.coverage_path = Some("lcov.info".to_string());
+            } else if let Some(path) = arg.strip_prefix("--coverage=") {
+                options.coverage_path = Some(path.to_string());
+            } else if let Some(dir) = arg.strip_prefix("-I") {
+                options.search_paths.push(dir.to_string());
+            } else if arg == "-Wall" {
+                options.warnings.extend_from_slice(&WarningKind::ALL);
+            } else if let Some(name) = arg.strip_prefix("-W") {
+                options.warnings.push(WarningKind::from_name(name)?);
+            } else if arg == "--allow-env" {
+                options.env_access = Some(EnvAccess::ReadWrite);
+            } else if arg == "--allow-env=read" {
+                options.env_access = Some(EnvAccess::ReadOnly);
+            } else if arg == "--allow-subprocess" {
+                options.allow_subprocess = true;
+            } else if arg == "--strict-math" {
+                options.strict_math = true;
+            } else if arg.starts_with("--") {
+                return None;
+            } else {
+                options.program = Some(match arg.as_str() {
+                    "-e" => Program::Eval(args.next()?.clone()),
+                    "-" => Program::Stdin,
+                    _ => Program::File(arg.clone()),
+                });
+                // Everything after the program is passed to the script.
+                options.script_args = args.cloned().collect();
+                break;
+            }
+        }
+        Some(options)
+    }
+}
+
+fn exit(vm: &Vm, options: &Options, code: i32) -> ! {
+    if let Some(report) = vm.profile_report() {
+        eprint!("{}", report);
+    }
+    if let Some(stats) = vm.opcode_stats() {
+        eprint!("{}", stats);
+    }
+    if let (Some(report), Some(coverage_path)) = (vm.coverage_report(), &options.coverage_path) {
+        let lcov = report.to_lcov(|module_path| match (module_path, &options.program) {
+            ("main", Some(Program::File(script_path))) => script_path.clone(),
+            _ => Path::new(module_path)
+                .with_extension("yl")
+                .to_string_lossy()
+                .into_owned(),
+        });
+        if let Err(error) = fs::write(coverage_path, lcov) {
+            eprintln!("Unable to write coverage to '{}': {}", coverage_path, error);
+        }
+    }
+    process::exit(code);
+}
+
+/// Prints each error message followed by the snippet of source it refers to, if any.
+fn print_error(error: &Error) {
+    for (i, message) in error.messages().iter().enumerate() {
+        eprintln!("{}", message);
+        if let Some(span) = error.span(i) {
+            eprintln!("{}", span.underline());
+        }
+    }
+    if let Some(flag) = enabling_flag(error) {
+        eprintln!("Pass {} to enable the module.", flag);
+    }
+}
+
+/// Returns the flag that enables the built-in module that the error, or any error that caused
+/// it, reports as disabled by the `VmOptions` the command line sets.
+fn enabling_flag(error: &Error) -> Option<&'static str> {
+    let mut error = Some(error);
+    while let Some(current) = error {
+        for message in current.messages() {
+            if message.contains("VmOptions::allow_subprocess") {
+                return Some("--allow-subprocess");
+            }
+            if message.contains("VmOptions::env_access") {
+                return Some("--allow-env");
+            }
+        }
+        error = current.cause();
+    }
+    None
+}
+
+const REPL_HELP: &str = "\
+:help          Show this message
+:load <path>   Run a file in the current session
+:reset         Forget everything defined in the session
+:dis <name>    Disassemble the function with the given name
+:type <expr>   Show the type of an expression
+:time <expr>   Show how long an expression takes to evaluate";
+
+fn repl(vm: &mut Vm, options: &Options) -> ! {
     loop {
         print!("> ");
         io::stdout().flush().unwrap();
@@ -32,36 +170,113 @@ fn repl(vm: &mut Vm) {
             Ok(bytes) => {
                 if bytes == 0 {
                     println!();
-                    process::exit(0);
+                    exit(vm, options, 0);
                 }
-                match vm::interpret(vm, buffer, None) {
-                    Ok(_) => {}
-                    Err(error) => eprint!("{}", error),
+                let line = buffer.trim();
+                if let Some(command) = line.strip_prefix(':') {
+                    run_command(vm, options, command);
+                } else if !line.is_empty() {
+                    run_repl_source(vm, options, terminate_expression(buffer));
                 }
             }
             _ => {
                 eprintln!("Failed to read from stdin.");
-                process::exit(74);
+                exit(vm, options, 74);
             }
         }
     }
 }
 
-fn run_file(vm: &mut Vm, path: &str) {
-    let source = fs::read_to_string(path);
-    let result = match source {
-        Ok(contents) => vm::interpret(vm, contents, None),
-        _ => panic!("Unable to read from file."),
+/// Adds the semicolon that can be left off source ending in a bare expression, as when it's
+/// typed into the REPL or passed with `-e`.
+fn terminate_expression(source: String) -> String {
+    let trimmed = source.trim_end();
+    if trimmed.ends_with(';') || trimmed.ends_with('}') {
+        source
+    } else {
+        format!("{};", trimmed)
+    }
+}
+
+/// Runs a line of input, showing the value of the expression it ends with, if any.
+fn run_repl_source(vm: &mut Vm, options: &Options, source: String) {
+    let result = vm::interpret(vm, source, None);
+    if let Some(code) = vm.take_exit_code() {
+        exit(vm, options, code);
+    }
+    match result {
+        Ok(Value::None) => {}
+        Ok(value) => println!("{}", value),
+        Err(error) => print_error(&error),
+    }
+}
+
+/// Runs a REPL command, which is a line starting with ':'.
+fn run_command(vm: &mut Vm, options: &Options, command: &str) {
+    let (name, arg) = match command.find(char::is_whitespace) {
+        Some(pos) => (&command[..pos], command[pos..].trim()),
+        None => (command, ""),
     };
+    match (name, arg) {
+        ("help", _) => println!("{}", REPL_HELP),
+        ("load", path) if !path.is_empty() => match fs::read_to_string(path) {
+            Ok(source) => run_repl_source(vm, options, source),
+            Err(error) => eprintln!("Unable to read '{}': {}", path, error),
+        },
+        ("reset", _) => {
+            vm.reset();
+            define_globals(vm, options);
+        }
+        ("dis", name) if !name.is_empty() => {
+            match vm.global("main", name).map(|v| vm.disassemble_value(v)) {
+                Some(Some(listing)) => print!("{}", listing),
+                Some(None) => eprintln!("'{}' isn't a function.", name),
+                None => eprintln!("Undefined variable '{}'.", name),
+            }
+        }
+        ("type", expr) if !expr.is_empty() => {
+            run_repl_source(vm, options, format!("type({});", expr))
+        }
+        ("time", expr) if !expr.is_empty() => {
+            let start = Instant::now();
+            run_repl_source(vm, options, format!("{};", expr));
+            println!("{:?}", start.elapsed());
+        }
+        ("load", _) | ("dis", _) | ("type", _) | ("time", _) => {
+            eprintln!("Expected an argument to ':{}'.", name)
+        }
+        _ => eprintln!(
+            "Unknown command ':{}'. Type ':help' for a list of commands.",
+            name
+        ),
+    }
+}
+
+/// Runs a program, exiting if it fails, and returns the value of the expression it ends with, if
+/// any.
+fn run_source(vm: &mut Vm, options: &Options, source: String) -> Value {
+    let result = vm::interpret(vm, source, None);
 
-    if let Err(error) = result {
+    if let Some(code) = vm.take_exit_code() {
+        exit(vm, options, code);
+    }
+    result.unwrap_or_else(|error| {
         let exit_code = if error.kind() == ErrorKind::CompileError {
             65
         } else {
             70
         };
-        eprint!("{}", error);
-        process::exit(exit_code);
+        print_error(&error);
+        exit(vm, options, exit_code);
+    })
+}
+
+/// Runs source passed with `-e` or read from stdin, which, like a line typed into the REPL, may
+/// end in a bare expression whose value is shown.
+fn run_expression_source(vm: &mut Vm, options: &Options, source: String) {
+    match run_source(vm, options, terminate_expression(source)) {
+        Value::None => {}
+        value => println!("{}", value),
     }
 }
 
@@ -89,18 +304,78 @@ fn read_file(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
     Ok(Value::ObjString(file_contents))
 }
 
+/// Defines the globals the command line adds to the main module.
+fn define_globals(vm: &mut Vm, options: &Options) {
+    vm.define_native("main", "read_file_to_string", read_file);
+    vm.set_args(&options.script_args);
+}
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match Options::parse(&args) {
+        Some(options) => options,
+        None => {
+            eprintln!("{}", USAGE);
+            process::exit(64);
+        }
+    };
 
-    let mut vm = Vm::with_built_ins();
-    vm.define_native("main", "read_file_to_string", read_file);
+    let mut vm = Vm::with_options(VmOptions {
+        env_access: options.env_access.unwrap_or(EnvAccess::None),
+        allow_subprocess: options.allow_subprocess,
+        strict_math: options.strict_math,
+        ..Default::default()
+    });
+    define_globals(&mut vm, &options);
+    if options.profile {
+        vm.enable_profiler();
+    }
+    if options.vm_stats {
+        vm.enable_opcode_stats();
+    }
+    if options.coverage_path.is_some() {
+        vm.enable_coverage();
+    }
+    if options.trace {
+        vm.set_trace_writer(io::stderr());
+        vm.set_trace(true);
+    }
+    vm.enable_warnings(&options.warnings);
+    // Report warnings as each module is compiled, so they come before anything it prints.
+    vm.set_warning_callback(|warning| eprintln!("{}", warning));
+    let mut loader = FileSystemLoader::new();
+    for dir in &options.search_paths {
+        loader.add_search_path(dir);
+    }
 
-    if args.len() == 1 {
-        repl(&mut vm);
-    } else if args.len() == 2 {
-        run_file(&mut vm, &args[1]);
-    } else {
-        eprintln!("Usage: ./yarel-cli [path]");
-        process::exit(64);
+    // Modules next to the script can be imported wherever it's run from.
+    if let Some(Program::File(path)) = &options.program {
+        if let Some(dir) = Path::new(path).parent() {
+            if !dir.as_os_str().is_empty() {
+                loader.add_search_path(dir);
+            }
+        }
+    }
+    vm.set_module_loader(loader);
+
+    match &options.program {
+        Some(Program::File(path)) => {
+            let source = match fs::read_to_string(path) {
+                Ok(contents) => contents,
+                _ => panic!("Unable to read from file."),
+            };
+            run_source(&mut vm, &options, source);
+        }
+        Some(Program::Eval(source)) => run_expression_source(&mut vm, &options, source.clone()),
+        Some(Program::Stdin) => {
+            let mut source = String::new();
+            if io::stdin().read_to_string(&mut source).is_err() {
+                eprintln!("Failed to read from stdin.");
+                exit(&vm, &options, 74);
+            }
+            run_expression_source(&mut vm, &options, source);
+        }
+        None => repl(&mut vm, &options),
     }
+    exit(&vm, &options, 0);
 }
@@ -0,0 +1,104 @@
+/* Copyright 2021 Matt Spraggs
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+/// Runs the command line with the specified arguments, writing `stdin` to its standard input.
+fn run(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_yarel-cli"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Unable to run yarel-cli.");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> &str {
+    std::str::from_utf8(&output.stdout).unwrap()
+}
+
+#[test]
+fn test_eval() {
+    let output = run(&["-e", "print(1 + 2)"], "");
+    assert!(output.status.success());
+    assert_eq!("3\n", stdout(&output));
+
+    let output = run(&["-e", "var x = 2; print(x * 3);\n"], "");
+    assert!(output.status.success());
+    assert_eq!("6\n", stdout(&output));
+
+    let output = run(&["-e", "if true { print(\"yes\"); }"], "");
+    assert!(output.status.success());
+    assert_eq!("yes\n", stdout(&output));
+
+    let output = run(&["-e", "print(1 +"], "");
+    assert_eq!(Some(65), output.status.code());
+
+    let output = run(&["-e", "var x = 2; x * 5"], "");
+    assert!(output.status.success());
+    assert_eq!("10\n", stdout(&output));
+}
+
+#[test]
+fn test_stdin() {
+    let output = run(&["-"], "var x = 4;\nprint(x + 1);\n");
+    assert!(output.status.success());
+    assert_eq!("5\n", stdout(&output));
+
+    let output = run(&["-"], "var x = 2; x * 5\n");
+    assert!(output.status.success());
+    assert_eq!("10\n", stdout(&output));
+
+    let output = run(&["-"], "print(1 +\n");
+    assert_eq!(Some(65), output.status.code());
+}
+
+#[test]
+fn test_script_args() {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("script_args.yl");
+    fs::write(&path, "print(args);\n").unwrap();
+    let output = run(&[path.to_str().unwrap(), "a", "-e", "--trace"], "");
+    assert!(output.status.success());
+    assert_eq!("[\"a\", \"-e\", \"--trace\"]\n", stdout(&output));
+
+    let output = run(&["-e", "print(args)", "b", "c"], "");
+    assert_eq!("[\"b\", \"c\"]\n", stdout(&output));
+
+    let output = run(&["-", "d"], "print(args);\n");
+    assert_eq!("[\"d\"]\n", stdout(&output));
+}
+
+#[test]
+fn test_disabled_module() {
+    let output = run(&["-e", "import \"os\";"], "");
+    assert_eq!(Some(70), output.status.code());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Enable it by setting VmOptions::allow_subprocess."));
+    assert!(stderr.ends_with("Pass --allow-subprocess to enable the module.\n"));
+
+    let output = run(&["--allow-env", "-e", "import \"env\";"], "");
+    assert!(output.status.success());
+}
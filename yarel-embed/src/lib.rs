@@ -0,0 +1,96 @@
+/* Copyright 2020-2021 Matt Spraggs
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Provides the `embed_modules!` macro, re-exported by yarel, which builds a
+//! `MemoryModuleLoader` holding every `.yl` file in a directory at compile time.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use proc_macro::{TokenStream, TokenTree};
+
+/// Expands to a `yarel::loader::MemoryModuleLoader` containing the source of each `.yl` file
+/// beneath the specified directory, which is relative to the root of the crate being compiled.
+/// Each module's path is the file's path relative to the directory, without the extension.
+#[proc_macro]
+pub fn embed_modules(input: TokenStream) -> TokenStream {
+    match expand(input) {
+        Ok(output) => output,
+        Err(message) => format!("compile_error!({:?})", message).parse().unwrap(),
+    }
+}
+
+fn expand(input: TokenStream) -> Result<TokenStream, String> {
+    let mut tokens = input.into_iter();
+    let dir = match (tokens.next(), tokens.next()) {
+        (Some(TokenTree::Literal(literal)), None) => parse_string_literal(&literal.to_string())
+            .ok_or_else(|| "Expected a string literal.".to_string())?,
+        _ => return Err("Expected the path of a directory.".to_string()),
+    };
+
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+    let root = Path::new(&manifest_dir).join(dir);
+    let mut files = Vec::new();
+    find_modules(&root, &mut files)
+        .map_err(|e| format!("Unable to read directory '{}': {}", root.display(), e))?;
+    files.sort();
+
+    let mut output = String::from("{ let mut loader = ::yarel::loader::MemoryModuleLoader::new();");
+    for file in &files {
+        let module_path = module_path(&root, file)
+            .ok_or_else(|| format!("Invalid module path '{}'.", file.display()))?;
+        let file = file
+            .to_str()
+            .ok_or_else(|| format!("Invalid file path '{}'.", file.display()))?;
+        output.push_str(&format!(
+            "loader.insert({:?}, include_str!({:?}));",
+            module_path, file
+        ));
+    }
+    output.push_str("loader }");
+    output
+        .parse()
+        .map_err(|_| "Unable to embed modules.".to_string())
+}
+
+fn parse_string_literal(literal: &str) -> Option<String> {
+    let contents = literal.strip_prefix('"')?.strip_suffix('"')?;
+    if contents.contains('\\') {
+        return None;
+    }
+    Some(contents.to_string())
+}
+
+fn find_modules(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_modules(&path, files)?;
+        } else if path.extension().is_some_and(|ext| ext == "yl") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn module_path(root: &Path, file: &Path) -> Option<String> {
+    let relative = file.strip_prefix(root).ok()?.with_extension("");
+    let components = relative
+        .components()
+        .map(|c| c.as_os_str().to_str())
+        .collect::<Option<Vec<_>>>()?;
+    Some(components.join("/"))
+}
@@ -0,0 +1,132 @@
+/* Copyright 2020-2021 Matt Spraggs
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Formats Yarel source files in place, or standard input to standard output if no files are
+//! given. With `--check`, nothing is written and the files that aren't formatted are listed.
+
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::process;
+
+use yarel::compiler;
+use yarel::diagnostic::Severity;
+
+const USAGE: &str = "Usage: ./yarel-fmt [--check] [path...]";
+
+#[derive(Default)]
+struct Options {
+    check: bool,
+    paths: Vec<String>,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Option<Self> {
+        let mut options = Options::default();
+        for arg in args {
+            if arg == "--check" {
+                options.check = true;
+            } else if arg.starts_with("--") {
+                return None;
+            } else {
+                options.paths.push(arg.clone());
+            }
+        }
+        Some(options)
+    }
+}
+
+/// Prints any syntax errors in the source, returning whether there were any.
+fn report_syntax_errors(name: &str, source: &str) -> bool {
+    let errors: Vec<_> = compiler::analyze(source)
+        .into_iter()
+        .filter(|d| d.severity == Severity::Error && d.code == "syntax-error")
+        .collect();
+    for error in &errors {
+        eprintln!("{}:{}: Error: {}", name, error.line, error.message);
+        if let Some(span) = &error.span {
+            eprintln!("{}", span.underline());
+        }
+    }
+    !errors.is_empty()
+}
+
+fn format_stdin(options: &Options) -> i32 {
+    let mut source = String::new();
+    if io::stdin().read_to_string(&mut source).is_err() {
+        eprintln!("Failed to read from stdin.");
+        return 74;
+    }
+    if report_syntax_errors("<stdin>", &source) {
+        return 65;
+    }
+    let formatted = compiler::format_source(&source);
+    if options.check {
+        return if formatted == source { 0 } else { 1 };
+    }
+    print!("{}", formatted);
+    0
+}
+
+fn format_files(options: &Options) -> i32 {
+    let mut exit_code = 0;
+    for path in &options.paths {
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(error) => {
+                eprintln!("Unable to read '{}': {}", path, error);
+                exit_code = 74;
+                continue;
+            }
+        };
+        if report_syntax_errors(path, &source) {
+            exit_code = 65;
+            continue;
+        }
+
+        let formatted = compiler::format_source(&source);
+        if formatted == source {
+            continue;
+        }
+        if options.check {
+            println!("{}", path);
+            if exit_code == 0 {
+                exit_code = 1;
+            }
+        } else if let Err(error) = fs::write(path, formatted) {
+            eprintln!("Unable to write '{}': {}", path, error);
+            exit_code = 74;
+        }
+    }
+    exit_code
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match Options::parse(&args) {
+        Some(options) => options,
+        None => {
+            eprintln!("{}", USAGE);
+            process::exit(64);
+        }
+    };
+
+    let exit_code = if options.paths.is_empty() {
+        format_stdin(&options)
+    } else {
+        format_files(&options)
+    };
+    process::exit(exit_code);
+}
@@ -0,0 +1,387 @@
+/* Copyright 2020-2021 Matt Spraggs
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! A language server for Yarel, speaking the Language Server Protocol over stdin and stdout.
+
+mod transport;
+
+use std::collections::HashMap;
+use std::io;
+use std::process;
+
+use serde_json::{json, Value};
+
+use yarel::compiler;
+use yarel::diagnostic::{Diagnostic, Severity};
+use yarel::error::Span;
+use yarel::symbol::{Symbol, SymbolKind};
+
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+#[derive(Default)]
+struct Server {
+    documents: HashMap<String, String>,
+    shutdown_requested: bool,
+}
+
+impl Server {
+    fn handle_request(&mut self, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "completionProvider": { "triggerCharacters": ["."] },
+                },
+                "serverInfo": { "name": "yarel-lsp", "version": env!("CARGO_PKG_VERSION") },
+            })),
+            "shutdown" => {
+                self.shutdown_requested = true;
+                Ok(Value::Null)
+            }
+            "textDocument/definition" => {
+                let (uri, text, line, character) = self.position_params(params)?;
+                Ok(definition(uri, text, line, character))
+            }
+            "textDocument/completion" => {
+                let (_, text, line, character) = self.position_params(params)?;
+                Ok(completion(text, line, character))
+            }
+            _ => Err((
+                METHOD_NOT_FOUND,
+                format!("Unsupported method '{}'.", method),
+            )),
+        }
+    }
+
+    /// Handles a notification, returning any notifications to send in response.
+    fn handle_notification(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents.insert(uri.to_owned(), text.to_owned());
+                vec![publish_diagnostics(uri, text)]
+            }
+            "textDocument/didChange" => {
+                // The server only asks for full document syncs, so the last change holds the
+                // whole of the new text.
+                let changes = params["contentChanges"].as_array();
+                let text = changes
+                    .and_then(|c| c.last())
+                    .and_then(|c| c["text"].as_str());
+                match text {
+                    Some(text) => {
+                        self.documents.insert(uri.to_owned(), text.to_owned());
+                        vec![publish_diagnostics(uri, text)]
+                    }
+                    None => Vec::new(),
+                }
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                vec![notification(
+                    "textDocument/publishDiagnostics",
+                    json!({ "uri": uri, "diagnostics": [] }),
+                )]
+            }
+            "exit" => process::exit(if self.shutdown_requested { 0 } else { 1 }),
+            _ => Vec::new(),
+        }
+    }
+
+    fn position_params<'a>(
+        &'a self,
+        params: &'a Value,
+    ) -> Result<(&'a str, &'a str, usize, usize), (i64, String)> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let text = self
+            .documents
+            .get(uri)
+            .ok_or_else(|| (INVALID_PARAMS, format!("Unknown document '{}'.", uri)))?;
+        let position = &params["position"];
+        match (position["line"].as_u64(), position["character"].as_u64()) {
+            (Some(line), Some(character)) => Ok((uri, text, line as usize, character as usize)),
+            _ => Err((INVALID_PARAMS, "Expected a position.".to_owned())),
+        }
+    }
+}
+
+fn notification(method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
+
+fn publish_diagnostics(uri: &str, text: &str) -> Value {
+    let diagnostics: Vec<_> = compiler::analyze(text)
+        .iter()
+        .map(diagnostic_to_json)
+        .collect();
+    notification(
+        "textDocument/publishDiagnostics",
+        json!({ "uri": uri, "diagnostics": diagnostics }),
+    )
+}
+
+fn diagnostic_to_json(diagnostic: &Diagnostic) -> Value {
+    let range = match &diagnostic.span {
+        Some(span) => span_range(span),
+        None => json!({
+            "start": { "line": diagnostic.line.saturating_sub(1), "character": 0 },
+            "end": { "line": diagnostic.line, "character": 0 },
+        }),
+    };
+    json!({
+        "range": range,
+        "severity": if diagnostic.severity == Severity::Error { 1 } else { 2 },
+        "code": diagnostic.code,
+        "source": "yarel",
+        "message": diagnostic.message,
+    })
+}
+
+/// Converts a span, which counts columns in characters from one, into a range, which counts them
+/// in UTF-16 code units from zero.
+fn span_range(span: &Span) -> Value {
+    let utf16_column = |column: usize| -> usize {
+        span.source_line
+            .chars()
+            .take(column.saturating_sub(1))
+            .map(char::len_utf16)
+            .sum()
+    };
+    let line = span.line.saturating_sub(1);
+    json!({
+        "start": { "line": line, "character": utf16_column(span.column) },
+        "end": { "line": line, "character": utf16_column(span.column + span.length) },
+    })
+}
+
+/// Returns the line at the specified position and the byte index of the position in it.
+fn line_at_position(text: &str, line: usize, character: usize) -> (&str, usize) {
+    let line = text.lines().nth(line).unwrap_or_default();
+    let mut utf16_offset = 0;
+    let index = line
+        .char_indices()
+        .find(|(_, c)| {
+            utf16_offset += c.len_utf16();
+            utf16_offset > character
+        })
+        .map_or(line.len(), |(i, _)| i);
+    (line, index)
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Finds the identifier that the specified position is in or next to.
+fn identifier_at(text: &str, line: usize, character: usize) -> &str {
+    let (line, index) = line_at_position(text, line, character);
+    let (before, after) = line.split_at(index);
+    let start = before.trim_end_matches(is_identifier_char).len();
+    let end = line.len() - after.trim_start_matches(is_identifier_char).len();
+    &line[start..end]
+}
+
+fn definition(uri: &str, text: &str, line: usize, character: usize) -> Value {
+    let name = identifier_at(text, line, character);
+    compiler::symbols(text)
+        .iter()
+        .find(|symbol| symbol.name == name)
+        .and_then(|symbol| symbol.span.as_ref())
+        .map_or(
+            Value::Null,
+            |span| json!({ "uri": uri, "range": span_range(span) }),
+        )
+}
+
+fn completion(text: &str, line: usize, character: usize) -> Value {
+    let (line, index) = line_at_position(text, line, character);
+    let before = line[..index].trim_end_matches(is_identifier_char);
+    let symbols = compiler::symbols(text);
+
+    let items: Vec<_> = match before.strip_suffix('.') {
+        Some(receiver) => {
+            let receiver_start = receiver.trim_end_matches(is_identifier_char).len();
+            let receiver = &receiver[receiver_start..];
+            let class = symbols
+                .iter()
+                .find(|s| s.kind == SymbolKind::Class && s.name == receiver);
+            match class {
+                // Only static methods and fields can be accessed on a class itself.
+                Some(class) => class
+                    .members
+                    .iter()
+                    .filter(|m| {
+                        matches!(m.kind, SymbolKind::StaticMethod | SymbolKind::StaticField)
+                    })
+                    .map(completion_item)
+                    .collect(),
+                // The class of any other receiver isn't known, so any of the methods and fields
+                // declared in the module could apply.
+                None => symbols
+                    .iter()
+                    .flat_map(|s| s.members.iter())
+                    .filter(|m| matches!(m.kind, SymbolKind::Method | SymbolKind::Field))
+                    .map(completion_item)
+                    .collect(),
+            }
+        }
+        None => symbols.iter().map(completion_item).collect(),
+    };
+
+    json!(items)
+}
+
+fn completion_item(symbol: &Symbol) -> Value {
+    let kind = match symbol.kind {
+        SymbolKind::Class => 7,
+        SymbolKind::Function => 3,
+        SymbolKind::Variable => 6,
+        SymbolKind::Import => 9,
+        SymbolKind::Method | SymbolKind::StaticMethod => 2,
+        SymbolKind::Field | SymbolKind::StaticField => 5,
+    };
+    let mut item = json!({ "label": symbol.name, "kind": kind });
+    if let Some(doc) = &symbol.doc {
+        item["documentation"] = json!(doc);
+    }
+    item
+}
+
+fn main() {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut input = stdin.lock();
+    let mut output = stdout.lock();
+    let mut server = Server::default();
+
+    loop {
+        let message = match transport::read_message(&mut input) {
+            Ok(Some(message)) => message,
+            Ok(None) => break,
+            Err(error) => {
+                eprintln!("Unable to read message: {}", error);
+                process::exit(1);
+            }
+        };
+
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let responses = match message.get("id") {
+            Some(id) => {
+                let response = match server.handle_request(method, params) {
+                    Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                    Err((code, message)) => json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": { "code": code, "message": message },
+                    }),
+                };
+                vec![response]
+            }
+            None => server.handle_notification(method, params),
+        };
+
+        for response in &responses {
+            if let Err(error) = transport::write_message(&mut output, response) {
+                eprintln!("Unable to write message: {}", error);
+                process::exit(1);
+            }
+        }
+    }
+
+    process::exit(if server.shutdown_requested { 0 } else { 1 });
+}
+
+#[test]
+fn test_span_range() {
+    let span = Span {
+        line: 2,
+        column: 7,
+        length: 3,
+        source_line: "print(\u{1f600}ab);".to_owned(),
+    };
+    assert_eq!(
+        json!({ "start": { "line": 1, "character": 6 }, "end": { "line": 1, "character": 10 } }),
+        span_range(&span)
+    );
+
+    let span = Span {
+        line: 0,
+        column: 0,
+        length: 0,
+        source_line: String::new(),
+    };
+    assert_eq!(
+        json!({ "start": { "line": 0, "character": 0 }, "end": { "line": 0, "character": 0 } }),
+        span_range(&span)
+    );
+}
+
+#[test]
+fn test_diagnostic_to_json() {
+    let diagnostic = Diagnostic {
+        severity: Severity::Error,
+        code: "syntax-error",
+        line: 0,
+        span: None,
+        message: "Expected expression.".to_owned(),
+    };
+    let json = diagnostic_to_json(&diagnostic);
+    assert_eq!(
+        json!({ "start": { "line": 0, "character": 0 }, "end": { "line": 0, "character": 0 } }),
+        json["range"]
+    );
+    assert_eq!(json!(1), json["severity"]);
+}
+
+#[test]
+fn test_line_at_position() {
+    let text = "var a = 1;\nvar \u{1f600}b = 2;\n";
+    assert_eq!(("var a = 1;", 4), line_at_position(text, 0, 4));
+    assert_eq!(("var \u{1f600}b = 2;", 8), line_at_position(text, 1, 6));
+    assert_eq!(("var a = 1;", 10), line_at_position(text, 0, 100));
+    assert_eq!(("", 0), line_at_position(text, 5, 0));
+}
+
+#[test]
+fn test_identifier_at() {
+    let text = "var total = 1;\nprint(total + other_value);\n";
+    assert_eq!("total", identifier_at(text, 0, 4));
+    assert_eq!("total", identifier_at(text, 0, 9));
+    assert_eq!("total", identifier_at(text, 1, 8));
+    assert_eq!("other_value", identifier_at(text, 1, 14));
+    assert_eq!("", identifier_at(text, 1, 12));
+}
+
+#[test]
+fn test_completion() {
+    let text = "class Point {\n    #[static]\n    fn origin() {}\n    fn sum(self) {}\n}\nvar p = Point.origin();\nPoint.\np.\n";
+    let labels = |items: Value| -> Vec<String> {
+        let mut labels: Vec<_> = items
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["label"].as_str().unwrap().to_owned())
+            .collect();
+        labels.sort();
+        labels
+    };
+    assert_eq!(vec!["Point", "p"], labels(completion(text, 5, 0)));
+    assert_eq!(vec!["origin"], labels(completion(text, 6, 6)));
+    assert_eq!(vec!["sum"], labels(completion(text, 7, 2)));
+}
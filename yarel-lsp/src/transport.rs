@@ -0,0 +1,103 @@
+/* Copyright 2020-2021 Matt Spraggs
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Reads and writes JSON-RPC messages framed by `Content-Length` headers.
+
+use std::io::{self, BufRead, Write};
+
+use serde_json::Value;
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Reads the next message, returning `None` if the input has been closed.
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut content_length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(length) = header.strip_prefix("Content-Length:") {
+            let length = length
+                .trim()
+                .parse::<usize>()
+                .map_err(|_| invalid_data("Invalid Content-Length header."))?;
+            content_length = Some(length);
+        }
+    }
+
+    let content_length =
+        content_length.ok_or_else(|| invalid_data("Missing Content-Length header."))?;
+    let mut content = vec![0; content_length];
+    input.read_exact(&mut content)?;
+    serde_json::from_slice(&content)
+        .map(Some)
+        .map_err(|e| invalid_data(&e.to_string()))
+}
+
+pub fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let content = message.to_string();
+    write!(
+        output,
+        "Content-Length: {}\r\n\r\n{}",
+        content.len(),
+        content
+    )?;
+    output.flush()
+}
+
+#[test]
+fn test_read_message() {
+    let mut input = io::Cursor::new(
+        "Content-Length: 17\r\nContent-Type: application/json\r\n\r\n{\"method\":\"exit\"}",
+    );
+    let message = read_message(&mut input).unwrap().unwrap();
+    assert_eq!("exit", message["method"]);
+    assert!(read_message(&mut input).unwrap().is_none());
+
+    let mut input = io::Cursor::new("Content-Type: application/json\r\n\r\n{}");
+    assert_eq!(
+        io::ErrorKind::InvalidData,
+        read_message(&mut input).unwrap_err().kind()
+    );
+
+    let mut input = io::Cursor::new("Content-Length: many\r\n\r\n{}");
+    assert_eq!(
+        io::ErrorKind::InvalidData,
+        read_message(&mut input).unwrap_err().kind()
+    );
+}
+
+#[test]
+fn test_write_message() {
+    let mut output = Vec::new();
+    write_message(&mut output, &serde_json::json!({ "id": 1 })).unwrap();
+    assert_eq!(
+        "Content-Length: 8\r\n\r\n{\"id\":1}",
+        String::from_utf8(output).unwrap()
+    );
+
+    let mut input = io::Cursor::new("Content-Length: 8\r\n\r\n{\"id\":1}".as_bytes().to_vec());
+    assert_eq!(
+        serde_json::json!({ "id": 1 }),
+        read_message(&mut input).unwrap().unwrap()
+    );
+}
@@ -0,0 +1,30 @@
+/* Copyright 2021 Matt Spraggs
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! An extension module whose initialisation always fails, used by the tests of the `yarel` crate
+//! to check the reason for the failure reaches the virtual machine that loaded it.
+
+use yarel::error::{Error, ErrorKind};
+use yarel::native_module;
+use yarel::vm::Vm;
+
+fn init(_vm: &mut Vm) -> Result<(), Error> {
+    Err(Error::with_message(
+        ErrorKind::RuntimeError,
+        "Fixture module refuses to initialise.",
+    ))
+}
+
+native_module!(init);
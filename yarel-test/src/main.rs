@@ -0,0 +1,473 @@
+/* Copyright 2020-2021 Matt Spraggs
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Runs the unit tests in Yarel source files. Each function annotated with `#[test]` in a file
+//! whose name ends in `_test.yl` is a test, and is run in a virtual machine of its own after the
+//! rest of the file has been executed. A test passes if it returns without raising an exception,
+//! unless it's also annotated with `#[should_fail]`, in which case it must raise one. An
+//! exception class may be given, as in `#[should_fail(TypeError)]`, to require that the
+//! unhandled exception is of that class.
+//!
+//! Tests can call `assert(condition, message)` and `assert_eq(left, right)`, which raise an
+//! exception if the condition is false or the values aren't equal. The traceback printed for a
+//! failed test gives the line of the assertion that failed. See the `examples` directory.
+//!
+//! With `--snapshot`, scripts are instead run from start to finish and their output compared
+//! with that recorded in a file alongside them. See the `snapshot` module for details.
+
+mod snapshot;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use yarel::ast::{self, Stmt};
+use yarel::compiler;
+use yarel::error::{Error, ErrorKind};
+use yarel::loader::FileSystemLoader;
+use yarel::value::Value;
+use yarel::vm::{self, Vm};
+
+const USAGE: &str = "Usage: ./yarel-test [--jobs=<n>] [--filter=<text>] [--snapshot] [--update] \
+                     [--wildcards=<path>] [path...]";
+
+const TEST_SUFFIX: &str = "_test.yl";
+
+#[derive(Default)]
+struct Options {
+    jobs: Option<usize>,
+    filter: Option<String>,
+    snapshot: bool,
+    update: bool,
+    wildcards_path: Option<String>,
+    paths: Vec<String>,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Option<Self> {
+        let mut options = Options::default();
+        for arg in args {
+            if let Some(jobs) = arg.strip_prefix("--jobs=") {
+                options.jobs = Some(jobs.parse().ok().filter(|&n| n > 0)?);
+            } else if let Some(filter) = arg.strip_prefix("--filter=") {
+                options.filter = Some(filter.to_string());
+            } else if arg == "--snapshot" {
+                options.snapshot = true;
+            } else if arg == "--update" {
+                options.update = true;
+            } else if let Some(path) = arg.strip_prefix("--wildcards=") {
+                options.wildcards_path = Some(path.to_string());
+            } else if arg.starts_with("--") {
+                return None;
+            } else {
+                options.paths.push(arg.clone());
+            }
+        }
+        if options.paths.is_empty() {
+            options.paths.push(".".to_string());
+        }
+        if !options.snapshot && (options.update || options.wildcards_path.is_some()) {
+            return None;
+        }
+        Some(options)
+    }
+}
+
+/// The reason a test is expected to raise an unhandled exception.
+#[derive(Clone, Debug)]
+enum ExpectedFailure {
+    Any,
+    Class(String),
+}
+
+/// A test function, along with the source of the file that defines it.
+struct TestCase {
+    path: String,
+    source: Arc<str>,
+    function: String,
+    expected_failure: Option<ExpectedFailure>,
+}
+
+impl TestCase {
+    fn name(&self) -> String {
+        format!("{}::{}", self.path, self.function)
+    }
+}
+
+enum Status {
+    Passed,
+    /// A snapshot was recorded rather than checked.
+    Recorded,
+    /// The test failed, for the reasons given.
+    Failed(Vec<String>),
+}
+
+struct Outcome {
+    name: String,
+    status: Status,
+    output: Vec<String>,
+    duration: Duration,
+}
+
+impl Outcome {
+    fn failed(&self) -> bool {
+        matches!(self.status, Status::Failed(_))
+    }
+}
+
+/// Returns the lines of output printed by the script run most recently in the virtual machine,
+/// along with the messages of any error it returned.
+fn take_output(vm: &mut Vm) -> Vec<String> {
+    vm.take_output().lines().map(str::to_owned).collect()
+}
+
+/// Raises an exception if the first argument is falsey, with the message given as the optional
+/// second argument.
+fn assert(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    if !(1..=2).contains(&num_args) {
+        return Err(yarel::error!(
+            ErrorKind::TypeError,
+            "Expected 1 or 2 parameters but found {}.",
+            num_args
+        ));
+    }
+    if vm.native_arg(1).into_bool() {
+        return Ok(Value::None);
+    }
+    if num_args == 1 {
+        return Err(yarel::error!(ErrorKind::RuntimeError, "Assertion failed."));
+    }
+    Err(yarel::error!(
+        ErrorKind::RuntimeError,
+        "Assertion failed: {}",
+        vm.native_arg(2)
+    ))
+}
+
+/// Raises an exception if the two arguments aren't equal, as compared by `==`.
+fn assert_eq(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    if num_args != 2 {
+        return Err(yarel::error!(
+            ErrorKind::TypeError,
+            "Expected 2 parameters but found {}.",
+            num_args
+        ));
+    }
+    let (left, right) = (vm.native_arg(1), vm.native_arg(2));
+    if left == right {
+        return Ok(Value::None);
+    }
+    Err(yarel::error!(
+        ErrorKind::RuntimeError,
+        "Assertion failed: '{}' is not equal to '{}'.",
+        left,
+        right
+    ))
+}
+
+/// Creates a virtual machine that captures printed output and imports modules relative to the
+/// script with the specified path. Tests can call the assertion functions as globals.
+fn new_vm(script_path: &Path) -> Vm {
+    let mut vm = Vm::with_built_ins();
+    vm.define_native("main", "assert", assert);
+    vm.define_native("main", "assert_eq", assert_eq);
+    vm.capture_output();
+    let mut loader = FileSystemLoader::new();
+    if let Some(dir) = script_path.parent() {
+        if !dir.as_os_str().is_empty() {
+            loader.add_search_path(dir);
+        }
+    }
+    vm.set_module_loader(loader);
+    vm
+}
+
+/// Finds the files at or beneath each of the specified paths whose names are accepted by the
+/// filter. Files named explicitly are included whatever their name.
+fn find_files(paths: &[String], filter: fn(&str) -> bool) -> Result<Vec<PathBuf>, String> {
+    fn visit(
+        dir: &Path,
+        filter: fn(&str) -> bool,
+        files: &mut Vec<PathBuf>,
+    ) -> std::io::Result<()> {
+        let mut entries = fs::read_dir(dir)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        entries.sort();
+        for path in entries {
+            if path.is_dir() {
+                visit(&path, filter, files)?;
+            } else if path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(filter)
+            {
+                files.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    let mut files = Vec::new();
+    for path in paths {
+        let path = Path::new(path);
+        if path.is_dir() {
+            visit(path, filter, &mut files)
+                .map_err(|e| format!("Unable to read '{}': {}", path.display(), e))?;
+        } else {
+            files.push(path.to_owned());
+        }
+    }
+    Ok(files)
+}
+
+fn expected_failure(attributes: &[ast::Attribute]) -> Option<ExpectedFailure> {
+    let attribute = ast::find_attribute(attributes, "should_fail")?;
+    Some(match attribute.arguments.first() {
+        Some(class) => ExpectedFailure::Class(class.name.clone()),
+        None => ExpectedFailure::Any,
+    })
+}
+
+/// Collects the test functions declared at the top level of a module.
+fn find_tests(path: &str, source: Arc<str>) -> Result<Vec<TestCase>, Error> {
+    let module = compiler::parse(source.to_string(), None)?;
+    let tests = module
+        .statements
+        .iter()
+        .filter_map(|statement| match statement {
+            Stmt::Fn(decl) if ast::find_attribute(&decl.attributes, "test").is_some() => {
+                Some(TestCase {
+                    path: path.to_owned(),
+                    source: source.clone(),
+                    function: decl.name.name.clone(),
+                    expected_failure: expected_failure(&decl.attributes),
+                })
+            }
+            _ => None,
+        })
+        .collect();
+    Ok(tests)
+}
+
+/// Checks the result of running a test against what was expected, returning the reason for the
+/// test's failure if it failed. The messages of any error are left out of the reason, since
+/// they're part of the test's output.
+fn check_result(test: &TestCase, result: Result<Value, Error>) -> Status {
+    let failure = match (result, &test.expected_failure) {
+        (Ok(_), None) => None,
+        (Ok(_), Some(_)) => Some(vec!["Expected an unhandled exception.".to_string()]),
+        (Err(_), None) => Some(Vec::new()),
+        (Err(error), Some(_)) if error.kind() == ErrorKind::CompileError => Some(Vec::new()),
+        (Err(_), Some(ExpectedFailure::Any)) => None,
+        (Err(error), Some(ExpectedFailure::Class(class))) => {
+            if error.class_name() == Some(class.as_str()) {
+                return Status::Passed;
+            }
+            Some(vec![format!(
+                "Expected an unhandled exception of class '{}'.",
+                class
+            )])
+        }
+    };
+    failure.map_or(Status::Passed, Status::Failed)
+}
+
+/// Runs a test in a virtual machine of its own, executing the rest of the test's file first.
+fn run_test(test: &TestCase) -> Outcome {
+    let start = Instant::now();
+    let mut vm = new_vm(Path::new(&test.path));
+    let source = format!("{}\n{}();\n", test.source, test.function);
+    let result = vm::interpret(&mut vm, source, None);
+
+    Outcome {
+        name: test.name(),
+        status: check_result(test, result),
+        output: take_output(&mut vm),
+        duration: start.elapsed(),
+    }
+}
+
+/// Runs the tests using the specified number of threads, printing the outcome of each test as it
+/// finishes. The outcomes are returned in the same order as the tests.
+fn run_tests<T>(tests: Vec<T>, jobs: usize, run: fn(&T) -> Outcome) -> Vec<Outcome>
+where
+    T: Send + Sync + 'static,
+{
+    let num_tests = tests.len();
+    let tests = Arc::new(tests);
+    let next = Arc::new(AtomicUsize::new(0));
+    let (sender, receiver) = mpsc::channel();
+
+    let workers: Vec<_> = (0..jobs.min(num_tests))
+        .map(|_| {
+            let tests = tests.clone();
+            let next = next.clone();
+            let sender = sender.clone();
+            thread::spawn(move || loop {
+                let index = next.fetch_add(1, Ordering::SeqCst);
+                match tests.get(index) {
+                    Some(test) => sender.send((index, run(test))).unwrap(),
+                    None => break,
+                }
+            })
+        })
+        .collect();
+    drop(sender);
+
+    let mut outcomes: Vec<Option<Outcome>> = (0..num_tests).map(|_| None).collect();
+    for (index, outcome) in receiver {
+        let status = match outcome.status {
+            Status::Passed => "ok",
+            Status::Recorded => "recorded",
+            Status::Failed(_) => "FAILED",
+        };
+        println!(
+            "test {} ... {} ({})",
+            outcome.name,
+            status,
+            format_duration(outcome.duration)
+        );
+        outcomes[index] = Some(outcome);
+    }
+    for worker in workers {
+        if worker.join().is_err() {
+            eprintln!("A test thread panicked.");
+        }
+    }
+
+    outcomes.into_iter().flatten().collect()
+}
+
+fn format_duration(duration: Duration) -> String {
+    format!("{:.2}ms", duration.as_secs_f64() * 1000.0)
+}
+
+fn find_files_or_exit(paths: &[String], filter: fn(&str) -> bool) -> Vec<PathBuf> {
+    match find_files(paths, filter) {
+        Ok(files) => files,
+        Err(message) => {
+            eprintln!("{}", message);
+            process::exit(74);
+        }
+    }
+}
+
+/// Collects the tests in each of the specified files, along with the errors in files that
+/// couldn't be parsed.
+fn collect_tests(files: &[PathBuf]) -> (Vec<TestCase>, Vec<(String, Error)>) {
+    let mut tests = Vec::new();
+    let mut invalid_files = Vec::new();
+    for file in files {
+        let path = file.to_string_lossy().into_owned();
+        let source = match fs::read_to_string(file) {
+            Ok(source) => source,
+            Err(error) => {
+                eprintln!("Unable to read '{}': {}", path, error);
+                process::exit(74);
+            }
+        };
+        match find_tests(&path, source.into()) {
+            Ok(found) => tests.extend(found),
+            Err(error) => invalid_files.push((path, error)),
+        }
+    }
+    (tests, invalid_files)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match Options::parse(&args) {
+        Some(options) => options,
+        None => {
+            eprintln!("{}", USAGE);
+            process::exit(64);
+        }
+    };
+
+    let jobs = options
+        .jobs
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()));
+    let start = Instant::now();
+    let (outcomes, invalid_files) = if options.snapshot {
+        let wildcards = match snapshot::load_wildcards(options.wildcards_path.as_deref()) {
+            Ok(wildcards) => wildcards,
+            Err(message) => {
+                eprintln!("{}", message);
+                process::exit(65);
+            }
+        };
+        let files = find_files_or_exit(&options.paths, snapshot::is_script);
+        let mut snapshots = snapshot::snapshots(&files, options.update, wildcards);
+        if let Some(filter) = &options.filter {
+            snapshots.retain(|snapshot| snapshot.name().contains(filter.as_str()));
+        }
+        println!("running {} snapshots", snapshots.len());
+        (
+            run_tests(snapshots, jobs, snapshot::run_snapshot),
+            Vec::new(),
+        )
+    } else {
+        let files = find_files_or_exit(&options.paths, |name| name.ends_with(TEST_SUFFIX));
+        let (mut tests, invalid_files) = collect_tests(&files);
+        if let Some(filter) = &options.filter {
+            tests.retain(|test| test.name().contains(filter.as_str()));
+        }
+        println!("running {} tests", tests.len());
+        (run_tests(tests, jobs, run_test), invalid_files)
+    };
+    let duration = start.elapsed();
+    let failures: Vec<_> = outcomes.iter().filter(|o| o.failed()).collect();
+    if !failures.is_empty() || !invalid_files.is_empty() {
+        println!("\nfailures:");
+        for (path, error) in &invalid_files {
+            println!("\n---- {} ----", path);
+            for message in error.messages() {
+                println!("{}", message);
+            }
+        }
+        for outcome in &failures {
+            println!("\n---- {} ----", outcome.name);
+            for line in &outcome.output {
+                println!("{}", line);
+            }
+            if let Status::Failed(reason) = &outcome.status {
+                for line in reason {
+                    println!("{}", line);
+                }
+            }
+        }
+    }
+
+    let num_failed = failures.len() + invalid_files.len();
+    let num_recorded = outcomes
+        .iter()
+        .filter(|o| matches!(o.status, Status::Recorded))
+        .count();
+    println!(
+        "\ntest result: {}. {} passed; {} failed; {} recorded; finished in {}",
+        if num_failed == 0 { "ok" } else { "FAILED" },
+        outcomes.len() - failures.len() - num_recorded,
+        num_failed,
+        num_recorded,
+        format_duration(duration)
+    );
+    process::exit(if num_failed == 0 { 0 } else { 1 });
+}
@@ -0,0 +1,277 @@
+/* Copyright 2020-2021 Matt Spraggs
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Snapshot tests, which compare the output of a script with the output recorded the first time
+//! it was run. The output of `path/script.yl`, including the messages of any error that stops it,
+//! is recorded in `path/script.expected`. Snapshots are recorded again with `--update`.
+//!
+//! Expected output may contain wildcards, each of which matches a run of one or more characters
+//! in the actual output. `[MEMADDR]` matches a hexadecimal address prefixed with "0x". Others are
+//! defined in the file given with `--wildcards`, one per line, by the wildcard followed by the
+//! characters it matches, in which `a-z` denotes a range:
+//!
+//! ```text
+//! # Durations such as 1.5ms
+//! [DURATION] 0-9.ms
+//! ```
+
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+
+use yarel::vm;
+
+use crate::{new_vm, take_output, Outcome, Status, TEST_SUFFIX};
+
+const EXTENSION: &str = "expected";
+
+#[derive(Clone, Debug, PartialEq)]
+enum Matcher {
+    MemAddr,
+    Chars(Vec<RangeInclusive<char>>),
+}
+
+impl Matcher {
+    /// Returns the length of the longest non-empty prefix of the text that this matches, if any.
+    fn match_len(&self, text: &str) -> Option<usize> {
+        let (prefix, is_match): (&str, &dyn Fn(char) -> bool) = match self {
+            Matcher::MemAddr => ("0x", &|c: char| c.is_ascii_hexdigit()),
+            Matcher::Chars(ranges) => ("", &move |c| ranges.iter().any(|r| r.contains(&c))),
+        };
+        let rest = text.strip_prefix(prefix)?;
+        let len = rest
+            .char_indices()
+            .find(|&(_, c)| !is_match(c))
+            .map_or(rest.len(), |(i, _)| i);
+        if len == 0 {
+            None
+        } else {
+            Some(prefix.len() + len)
+        }
+    }
+}
+
+/// A placeholder in expected output that matches any of a set of strings.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Wildcard {
+    pattern: String,
+    matcher: Matcher,
+}
+
+fn parse_chars(chars: &str) -> Vec<RangeInclusive<char>> {
+    let chars: Vec<_> = chars.chars().collect();
+    let mut ranges = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if i + 2 < chars.len() && chars[i + 1] == '-' {
+            ranges.push(chars[i]..=chars[i + 2]);
+            i += 3;
+        } else {
+            ranges.push(chars[i]..=chars[i]);
+            i += 1;
+        }
+    }
+    ranges
+}
+
+fn parse_wildcards(source: &str) -> Result<Vec<Wildcard>, usize> {
+    let mut wildcards = Vec::new();
+    for (number, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (pattern, chars) = line.split_once(char::is_whitespace).ok_or(number + 1)?;
+        let name = pattern
+            .strip_prefix('[')
+            .and_then(|p| p.strip_suffix(']'))
+            .ok_or(number + 1)?;
+        if name.is_empty() || name.contains(&['[', ']'][..]) {
+            return Err(number + 1);
+        }
+        wildcards.push(Wildcard {
+            pattern: pattern.to_owned(),
+            matcher: Matcher::Chars(parse_chars(chars.trim())),
+        });
+    }
+    Ok(wildcards)
+}
+
+/// Returns the built-in wildcards along with any defined in the file with the specified path.
+pub(crate) fn load_wildcards(path: Option<&str>) -> Result<Arc<[Wildcard]>, String> {
+    let mut wildcards = vec![Wildcard {
+        pattern: "[MEMADDR]".to_owned(),
+        matcher: Matcher::MemAddr,
+    }];
+    if let Some(path) = path {
+        let source =
+            fs::read_to_string(path).map_err(|e| format!("Unable to read '{}': {}", path, e))?;
+        let defined = parse_wildcards(&source)
+            .map_err(|line| format!("Invalid wildcard on line {} of '{}'.", line, path))?;
+        wildcards.extend(defined);
+    }
+    Ok(wildcards.into())
+}
+
+fn match_line(expected: &str, actual: &str, wildcards: &[Wildcard]) -> bool {
+    let mut expected = expected;
+    let mut actual = actual;
+    loop {
+        if let Some(wildcard) = wildcards
+            .iter()
+            .find(|wildcard| expected.starts_with(wildcard.pattern.as_str()))
+        {
+            match wildcard.matcher.match_len(actual) {
+                Some(len) => {
+                    expected = &expected[wildcard.pattern.len()..];
+                    actual = &actual[len..];
+                    continue;
+                }
+                None => return false,
+            }
+        }
+        let mut expected_chars = expected.chars();
+        let mut actual_chars = actual.chars();
+        match (expected_chars.next(), actual_chars.next()) {
+            (None, None) => return true,
+            (Some(e), Some(a)) if e == a => {
+                expected = expected_chars.as_str();
+                actual = actual_chars.as_str();
+            }
+            _ => return false,
+        }
+    }
+}
+
+fn match_output(expected: &[String], actual: &[String], wildcards: &[Wildcard]) -> bool {
+    expected.len() == actual.len()
+        && expected
+            .iter()
+            .zip(actual)
+            .all(|(e, a)| match_line(e, a, wildcards))
+}
+
+/// Whether a file is a script to be run in snapshot mode, which is the case for any source file
+/// other than those containing unit tests.
+pub(crate) fn is_script(name: &str) -> bool {
+    name.ends_with(".yl") && !name.ends_with(TEST_SUFFIX)
+}
+
+pub(crate) struct Snapshot {
+    path: PathBuf,
+    update: bool,
+    wildcards: Arc<[Wildcard]>,
+}
+
+impl Snapshot {
+    pub(crate) fn name(&self) -> String {
+        self.path.to_string_lossy().into_owned()
+    }
+}
+
+pub(crate) fn snapshots(
+    files: &[PathBuf],
+    update: bool,
+    wildcards: Arc<[Wildcard]>,
+) -> Vec<Snapshot> {
+    files
+        .iter()
+        .map(|path| Snapshot {
+            path: path.clone(),
+            update,
+            wildcards: wildcards.clone(),
+        })
+        .collect()
+}
+
+fn run_script(path: &Path) -> Result<Vec<String>, String> {
+    let source = fs::read_to_string(path)
+        .map_err(|e| format!("Unable to read '{}': {}", path.display(), e))?;
+    let mut vm = new_vm(path);
+    // The messages of any error returned are captured along with the printed output.
+    let _ = vm::interpret(&mut vm, source, None);
+    Ok(take_output(&mut vm))
+}
+
+/// Runs a script, recording its output if there's no snapshot of it or snapshots are being
+/// updated, and otherwise comparing its output with the snapshot.
+pub(crate) fn run_snapshot(snapshot: &Snapshot) -> Outcome {
+    let start = Instant::now();
+    let expected_path = snapshot.path.with_extension(EXTENSION);
+    let status = match run_script(&snapshot.path) {
+        Ok(actual) => check_snapshot(snapshot, &expected_path, actual),
+        Err(message) => Status::Failed(vec![message]),
+    };
+    Outcome {
+        name: snapshot.name(),
+        status,
+        output: Vec::new(),
+        duration: start.elapsed(),
+    }
+}
+
+fn check_snapshot(snapshot: &Snapshot, expected_path: &Path, actual: Vec<String>) -> Status {
+    let expected = match fs::read_to_string(expected_path) {
+        Ok(expected) if !snapshot.update => expected,
+        _ => {
+            let contents: String = actual.iter().map(|line| format!("{}\n", line)).collect();
+            return match fs::write(expected_path, contents) {
+                Ok(()) => Status::Recorded,
+                Err(e) => Status::Failed(vec![format!(
+                    "Unable to write '{}': {}",
+                    expected_path.display(),
+                    e
+                )]),
+            };
+        }
+    };
+
+    let expected: Vec<_> = expected.lines().map(str::to_owned).collect();
+    if match_output(&expected, &actual, &snapshot.wildcards) {
+        return Status::Passed;
+    }
+    let mut reason = vec!["Expected:".to_string()];
+    reason.extend(expected.iter().map(|line| format!("    {}", line)));
+    reason.push("Actual:".to_string());
+    reason.extend(actual.iter().map(|line| format!("    {}", line)));
+    Status::Failed(reason)
+}
+
+#[test]
+fn test_match_line() {
+    let wildcards = parse_wildcards("# Comment\n\n[NUM] 0-9.\n[ID] a-f_\n").unwrap();
+    let mut all = load_wildcards(None).unwrap().to_vec();
+    all.extend(wildcards);
+
+    assert!(match_line("Took [NUM]ms", "Took 1.25ms", &all));
+    assert!(!match_line("Took [NUM]ms", "Took ms", &all));
+    assert!(match_line(
+        "<Foo instance at [MEMADDR]>",
+        "<Foo instance at 0x7f3a>",
+        &all
+    ));
+    assert!(!match_line(
+        "<Foo instance at [MEMADDR]>",
+        "<Foo instance at 7f3a>",
+        &all
+    ));
+    assert!(match_line("[ID] [NUM]", "ab_c 12", &all));
+    assert!(!match_line("[ID]", "ab_c 12", &all));
+    assert!(match_line("[UNKNOWN]", "[UNKNOWN]", &all));
+    assert_eq!(Err(1), parse_wildcards("[NUM]"));
+    assert_eq!(Err(2), parse_wildcards("[NUM] 0-9\nNUM 0-9"));
+}
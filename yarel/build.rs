@@ -107,7 +107,7 @@ fn compile_class_store(man_dir: &OsString, out_dir: &Path) {
                 .map(|s| s.to_owned())
                 .unwrap_or_else(|| to_capcase(&name));
             let kind = y["kind"].as_str().map(|s| ClassKind::from(s)).unwrap();
-            let superclass = y["superclass"].as_str().unwrap_or("object").to_owned();
+            let superclass = y["superclass"].as_str().unwrap_or("object");
             let metaclass = y["metaclass"]
                 .as_str()
                 .unwrap_or("base_metaclass")
@@ -120,7 +120,11 @@ fn compile_class_store(man_dir: &OsString, out_dir: &Path) {
                 },
                 repr,
                 kind,
-                superclass,
+                superclass: if superclass.ends_with("class") {
+                    superclass.to_owned()
+                } else {
+                    format!("{}_class", superclass)
+                },
                 metaclass,
             }
         })
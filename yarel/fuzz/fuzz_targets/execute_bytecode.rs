@@ -0,0 +1,51 @@
+/* Copyright 2020-2021 Matt Spraggs
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use yarel::chunk::{Chunk, OpCode};
+use yarel::error::Error;
+use yarel::value::Value;
+use yarel::vm::Vm;
+
+fn discard_print(_vm: &mut Vm, _num_args: usize) -> Result<Value, Error> {
+    Ok(Value::None)
+}
+
+fuzz_target!(|data: &[u8]| {
+    // Backward jumps can loop forever, which the fuzzer would report as a timeout.
+    if data.contains(&(OpCode::Loop as u8)) {
+        return;
+    }
+
+    let mut vm = Vm::with_built_ins();
+    vm.set_printer(discard_print);
+
+    let mut chunk = Chunk::new();
+    for &number in &[0.0, 1.0, -2.5, 1e300] {
+        chunk.add_constant(Value::Number(number));
+    }
+    for &string in &["", "a", "next"] {
+        let string = vm.new_gc_obj_string(string);
+        chunk.add_constant(Value::ObjString(string));
+    }
+    for &byte in data {
+        chunk.write(byte, 1);
+    }
+
+    let _ = vm.execute_bytecode(chunk);
+});
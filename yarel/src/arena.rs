@@ -0,0 +1,70 @@
+/* Copyright 2021 Matt Spraggs
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+const CHUNK_CAPACITY: usize = 4096;
+
+/// A bump allocator for the short-lived strings made while parsing, such as string literals and
+/// error messages. Strings are never freed individually; they're released wholesale when the arena
+/// is dropped, which saves an allocation per token when compiling many small snippets.
+pub(crate) struct Arena {
+    chunks: RefCell<Vec<String>>,
+}
+
+impl Arena {
+    pub fn new() -> Self {
+        Arena {
+            chunks: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Copies the given string into the arena.
+    pub fn alloc_str(&self, s: &str) -> &str {
+        let mut chunks = self.chunks.borrow_mut();
+        let has_space = match chunks.last() {
+            Some(chunk) => chunk.capacity() - chunk.len() >= s.len(),
+            None => false,
+        };
+        if !has_space {
+            chunks.push(String::with_capacity(CHUNK_CAPACITY.max(s.len())));
+        }
+        let chunk = chunks.last_mut().unwrap();
+        let start = chunk.len();
+        chunk.push_str(s);
+        let allocated: *const str = &chunk[start..];
+        // # Safety
+        // A chunk is never pushed to beyond its capacity, so its buffer is never reallocated, and
+        // chunks are only freed when the arena is dropped, so the string lives as long as the
+        // arena.
+        unsafe { &*allocated }
+    }
+}
+
+#[test]
+fn test_alloc_str() {
+    let arena = Arena::new();
+    let first = arena.alloc_str("first");
+    let large = "x".repeat(CHUNK_CAPACITY * 2);
+    let second = arena.alloc_str(&large);
+    let third = arena.alloc_str("third");
+
+    assert_eq!(first, "first");
+    assert_eq!(second, large);
+    assert_eq!(third, "third");
+    assert_eq!(arena.chunks.borrow().len(), 3);
+}
@@ -0,0 +1,672 @@
+/* Copyright 2020-2021 Matt Spraggs
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! The syntax tree produced by the parser and consumed by the code generator.
+//!
+//! Besides the structure of the source, nodes record the lines on which the bytecode generated
+//! for them should be reported, so that runtime errors point at the same place regardless of how
+//! the tree was produced.
+
+use alloc::borrow::ToOwned;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::common;
+use crate::hash::HashSet;
+
+/// A complete source file.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Module {
+    /// Attributes such as `#![strict]` that apply to the whole file.
+    pub attributes: Vec<Attribute>,
+    pub statements: Vec<Stmt>,
+    /// The line of the end of the file.
+    pub end_line: usize,
+}
+
+impl Module {
+    /// Whether the module opts into the stricter rules enabled by `#![strict]`.
+    pub fn is_strict(&self) -> bool {
+        find_attribute(&self.attributes, "strict").is_some()
+    }
+
+    /// The language version the module is written against, as set by `#![lang(N)]`.
+    pub fn lang_version(&self) -> u32 {
+        find_attribute(&self.attributes, "lang")
+            .and_then(|attr| attr.arguments[0].name.parse().ok())
+            .unwrap_or(common::LANG_VERSION)
+    }
+
+    /// Returns the names of the global variables declared at the top level of the module.
+    pub fn declared_globals(&self) -> HashSet<String> {
+        self.statements
+            .iter()
+            .filter_map(|stmt| match stmt {
+                Stmt::Class(decl) => Some(&decl.name),
+                Stmt::Fn(decl) => Some(&decl.name),
+                Stmt::Var { name, .. } => Some(name),
+                Stmt::Import { binding, .. } => Some(binding),
+                _ => None,
+            })
+            .map(|name| name.name.clone())
+            .collect()
+    }
+}
+
+/// A name appearing in the source, along with the line it appears on.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Identifier {
+    pub name: String,
+    pub line: usize,
+    /// The byte offset of the name in the source, if it appears there.
+    pub offset: Option<usize>,
+}
+
+impl Identifier {
+    pub fn new(name: &str, line: usize) -> Self {
+        Identifier {
+            name: name.to_owned(),
+            line,
+            offset: None,
+        }
+    }
+}
+
+/// An attribute such as `#[derive(Base)]`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Attribute {
+    pub name: Identifier,
+    pub arguments: Vec<Identifier>,
+}
+
+/// Finds the attribute with the specified name, if present.
+pub fn find_attribute<'a>(attributes: &'a [Attribute], name: &str) -> Option<&'a Attribute> {
+    attributes.iter().find(|a| a.name.name == name)
+}
+
+/// A sequence of statements between braces.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    pub statements: Vec<Stmt>,
+    /// The line of the closing brace.
+    pub end_line: usize,
+}
+
+impl Block {
+    fn line(&self) -> usize {
+        self.statements
+            .first()
+            .map_or(self.end_line, |statement| statement.line())
+    }
+}
+
+/// The parameters and body shared by function declarations and methods.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Function {
+    pub parameters: Vec<Identifier>,
+    /// The line of the opening brace of the body.
+    pub body_line: usize,
+    pub body: Block,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FnDecl {
+    pub name: Identifier,
+    pub attributes: Vec<Attribute>,
+    pub doc: Option<String>,
+    pub function: Function,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Method {
+    pub name: Identifier,
+    pub attributes: Vec<Attribute>,
+    pub doc: Option<String>,
+    pub function: Function,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MethodKind {
+    Method,
+    Static,
+    Constructor,
+}
+
+impl Method {
+    pub fn kind(&self) -> MethodKind {
+        if find_attribute(&self.attributes, "constructor").is_some() {
+            MethodKind::Constructor
+        } else if find_attribute(&self.attributes, "static").is_some() {
+            MethodKind::Static
+        } else {
+            MethodKind::Method
+        }
+    }
+
+    pub fn is_generator(&self) -> bool {
+        self.kind() != MethodKind::Constructor
+            && find_attribute(&self.attributes, "generator").is_some()
+    }
+}
+
+/// A variable declared in a class body. Static fields are stored on the class, while other fields
+/// are assigned their initial values on each instance when it's constructed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Field {
+    pub name: Identifier,
+    pub attributes: Vec<Attribute>,
+    pub doc: Option<String>,
+    pub initialiser: Option<Expr>,
+    pub end_line: usize,
+}
+
+impl Field {
+    pub fn is_static(&self) -> bool {
+        find_attribute(&self.attributes, "static").is_some()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClassDecl {
+    pub name: Identifier,
+    pub attributes: Vec<Attribute>,
+    pub doc: Option<String>,
+    /// The line of the opening brace of the class body.
+    pub body_line: usize,
+    pub fields: Vec<Field>,
+    pub methods: Vec<Method>,
+    /// The line of the closing brace of the class body.
+    pub end_line: usize,
+}
+
+impl ClassDecl {
+    fn attribute_argument(&self, name: &str) -> Option<&Identifier> {
+        find_attribute(&self.attributes, name).and_then(|a| a.arguments.first())
+    }
+
+    /// The name of the static method generated to construct instances, if any.
+    pub fn constructor(&self) -> Option<&Identifier> {
+        self.attribute_argument("constructor")
+    }
+
+    pub fn superclass(&self) -> Option<&Identifier> {
+        self.attribute_argument("derive")
+    }
+
+    pub fn protocol(&self) -> Option<&Identifier> {
+        self.attribute_argument("implements")
+    }
+
+    /// The classes whose methods are copied into this one, in the order they're applied.
+    pub fn mixins(&self) -> &[Identifier] {
+        find_attribute(&self.attributes, "mixin").map_or(&[], |a| &a.arguments)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Catch {
+    pub keyword_line: usize,
+    pub variable: Identifier,
+    /// The class that exceptions must derive from to be caught, if any.
+    pub class: Option<Expr>,
+    pub body: Block,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Stmt {
+    Class(ClassDecl),
+    Fn(FnDecl),
+    Var {
+        name: Identifier,
+        initialiser: Option<Expr>,
+        end_line: usize,
+    },
+    Import {
+        attributes: Vec<Attribute>,
+        path: Identifier,
+        alias: Option<Identifier>,
+        /// The name the module is bound to, which is either the alias or the last component of
+        /// the path.
+        binding: Identifier,
+        end_line: usize,
+    },
+    /// Declares names that can be accessed from outside the module.
+    Export {
+        keyword_line: usize,
+        names: Vec<Identifier>,
+        end_line: usize,
+    },
+    Expression {
+        expr: Expr,
+        end_line: usize,
+    },
+    For {
+        variable: Identifier,
+        iterable: Expr,
+        iterable_end_line: usize,
+        body_line: usize,
+        body: Block,
+    },
+    If {
+        condition: Expr,
+        condition_end_line: usize,
+        then_branch: Block,
+        else_branch: Option<Box<Stmt>>,
+    },
+    Return {
+        keyword_line: usize,
+        value: Option<Expr>,
+        end_line: usize,
+    },
+    Yield {
+        keyword_line: usize,
+        value: Expr,
+        end_line: usize,
+    },
+    Break {
+        keyword_line: usize,
+    },
+    Continue {
+        keyword_line: usize,
+    },
+    Throw {
+        value: Expr,
+        end_line: usize,
+    },
+    Try {
+        keyword_line: usize,
+        body: Block,
+        catches: Vec<Catch>,
+        finally: Option<Block>,
+    },
+    While {
+        condition: Expr,
+        condition_end_line: usize,
+        body: Block,
+    },
+    Block(Block),
+    /// A statement that couldn't be parsed.
+    Error,
+}
+
+impl Stmt {
+    /// The line on which the statement starts, as near as the tree records it.
+    pub fn line(&self) -> usize {
+        match self {
+            Stmt::Class(class) => class.name.line,
+            Stmt::Fn(decl) => decl.name.line,
+            Stmt::Var { name, .. } => name.line,
+            Stmt::Import { path, .. } => path.line,
+            Stmt::Expression { expr, .. } => expr.line(),
+            Stmt::For { variable, .. } => variable.line,
+            Stmt::If { condition, .. } | Stmt::While { condition, .. } => condition.line(),
+            Stmt::Return { keyword_line, .. }
+            | Stmt::Yield { keyword_line, .. }
+            | Stmt::Break { keyword_line }
+            | Stmt::Continue { keyword_line }
+            | Stmt::Try { keyword_line, .. }
+            | Stmt::Export { keyword_line, .. } => *keyword_line,
+            Stmt::Throw { value, .. } => value.line(),
+            Stmt::Block(block) => block.line(),
+            Stmt::Error => 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinaryOperator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    BitwiseAnd,
+    BitwiseOr,
+    BitwiseXor,
+    BitShiftLeft,
+    BitShiftRight,
+    Is,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnaryOperator {
+    Negate,
+    Not,
+    BitwiseNot,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogicalOperator {
+    And,
+    Or,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Literal {
+    Nil,
+    True,
+    False,
+}
+
+/// The operator of a compound assignment such as `a += 1`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AssignOperator {
+    pub operator: BinaryOperator,
+    pub line: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Argument {
+    Positional(Expr),
+    Named(Identifier, Expr),
+    Spread { value: Expr, line: usize },
+}
+
+impl Argument {
+    fn line(&self) -> usize {
+        match self {
+            Argument::Positional(expr) => expr.line(),
+            Argument::Named(name, _) => name.line,
+            Argument::Spread { line, .. } => *line,
+        }
+    }
+}
+
+/// A literal piece of an interpolated string followed by the expression after it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InterpolationPart {
+    pub text: String,
+    pub text_line: usize,
+    pub expr: Expr,
+    pub expr_end_line: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LambdaBody {
+    Block(Block),
+    Expr { expr: Box<Expr>, end_line: usize },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ComprehensionKind {
+    /// `[element for variable in iterable]`, which builds a Vec.
+    Vec,
+    /// `(element for variable in iterable)`, which lazily yields the elements from a generator.
+    Generator,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Comprehension {
+    pub kind: ComprehensionKind,
+    pub element: Expr,
+    pub variable: Identifier,
+    pub iterable: Expr,
+    pub condition: Option<Expr>,
+    /// The line of the closing bracket or parenthesis.
+    pub end_line: usize,
+}
+
+impl Comprehension {
+    /// Returns the function equivalent to the comprehension when called with the iterable, so
+    /// that `[x * 2 for x in xs if x > 0]` has the same effect as calling
+    ///
+    /// ```text
+    /// fn (iterable) {
+    ///     var result = [];
+    ///     for x in iterable {
+    ///         if x > 0 {
+    ///             result.push(x * 2);
+    ///         }
+    ///     }
+    ///     return result;
+    /// }
+    /// ```
+    ///
+    /// with `xs`. The generator form yields each element instead, and must be compiled as a
+    /// generator.
+    pub fn function(&self) -> Function {
+        let iterable = Identifier::new("... comprehension-iterable ...", self.variable.line);
+        let result = Identifier::new("... comprehension-result ...", self.variable.line);
+        let element_line = self.element.line();
+
+        let mut action = match self.kind {
+            ComprehensionKind::Vec => Stmt::Expression {
+                expr: Expr::Call {
+                    callee: Box::new(Expr::Get {
+                        object: Box::new(Expr::Variable(result.clone())),
+                        name: Identifier::new("push", element_line),
+                    }),
+                    arguments: vec![Argument::Positional(self.element.clone())],
+                    end_line: self.end_line,
+                },
+                end_line: self.end_line,
+            },
+            ComprehensionKind::Generator => Stmt::Yield {
+                keyword_line: element_line,
+                value: self.element.clone(),
+                end_line: self.end_line,
+            },
+        };
+        if let Some(condition) = &self.condition {
+            action = Stmt::If {
+                condition: condition.clone(),
+                condition_end_line: self.end_line,
+                then_branch: Block {
+                    statements: vec![action],
+                    end_line: self.end_line,
+                },
+                else_branch: None,
+            };
+        }
+
+        let mut statements = vec![Stmt::For {
+            variable: self.variable.clone(),
+            iterable: Expr::Variable(iterable.clone()),
+            iterable_end_line: self.iterable.line(),
+            body_line: self.variable.line,
+            body: Block {
+                statements: vec![action],
+                end_line: self.end_line,
+            },
+        }];
+        if self.kind == ComprehensionKind::Vec {
+            statements.insert(
+                0,
+                Stmt::Var {
+                    name: result.clone(),
+                    initialiser: Some(Expr::Vec {
+                        elements: Vec::new(),
+                        end_line: self.end_line,
+                    }),
+                    end_line: self.end_line,
+                },
+            );
+            statements.push(Stmt::Return {
+                keyword_line: self.end_line,
+                value: Some(Expr::Variable(result)),
+                end_line: self.end_line,
+            });
+        }
+
+        Function {
+            parameters: vec![iterable],
+            body_line: self.variable.line,
+            body: Block {
+                statements,
+                end_line: self.end_line,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Number {
+        value: f64,
+        /// The number as it's written in the source.
+        text: String,
+        line: usize,
+    },
+    Str {
+        value: String,
+        line: usize,
+    },
+    Interpolation {
+        parts: Vec<InterpolationPart>,
+        tail: String,
+        end_line: usize,
+    },
+    Literal {
+        value: Literal,
+        line: usize,
+    },
+    Variable(Identifier),
+    Assign {
+        name: Identifier,
+        operator: Option<AssignOperator>,
+        value: Box<Expr>,
+        line: usize,
+    },
+    SelfRef {
+        line: usize,
+    },
+    SelfClass {
+        line: usize,
+    },
+    Super {
+        keyword_line: usize,
+        method: Identifier,
+        /// The arguments and closing parenthesis line if the superclass method is called.
+        call: Option<(Vec<Argument>, usize)>,
+    },
+    Grouping(Box<Expr>),
+    Tuple {
+        elements: Vec<Expr>,
+        line: usize,
+    },
+    Vec {
+        elements: Vec<Argument>,
+        end_line: usize,
+    },
+    HashMap {
+        entries: Vec<(Expr, Expr)>,
+        end_line: usize,
+    },
+    Unary {
+        operator: UnaryOperator,
+        operand: Box<Expr>,
+        line: usize,
+    },
+    Binary {
+        operator: BinaryOperator,
+        left: Box<Expr>,
+        right: Box<Expr>,
+        line: usize,
+    },
+    Logical {
+        operator: LogicalOperator,
+        left: Box<Expr>,
+        right: Box<Expr>,
+        line: usize,
+    },
+    Range {
+        start: Box<Expr>,
+        end: Box<Expr>,
+        step: Option<Box<Expr>>,
+        line: usize,
+    },
+    Call {
+        callee: Box<Expr>,
+        arguments: Vec<Argument>,
+        end_line: usize,
+    },
+    Get {
+        object: Box<Expr>,
+        name: Identifier,
+    },
+    Set {
+        object: Box<Expr>,
+        name: Identifier,
+        operator: Option<AssignOperator>,
+        value: Box<Expr>,
+        line: usize,
+    },
+    Index {
+        object: Box<Expr>,
+        index: Box<Expr>,
+        end_line: usize,
+    },
+    SetIndex {
+        object: Box<Expr>,
+        index: Box<Expr>,
+        operator: Option<AssignOperator>,
+        value: Box<Expr>,
+        line: usize,
+    },
+    Lambda {
+        parameters: Vec<Identifier>,
+        body: LambdaBody,
+    },
+    Comprehension(Box<Comprehension>),
+    /// An expression that couldn't be parsed.
+    Error,
+}
+
+impl Expr {
+    /// The line on which the expression starts, as near as the tree records it.
+    pub fn line(&self) -> usize {
+        match self {
+            Expr::Number { line, .. }
+            | Expr::Str { line, .. }
+            | Expr::Literal { line, .. }
+            | Expr::SelfRef { line }
+            | Expr::SelfClass { line }
+            | Expr::Unary { line, .. } => *line,
+            Expr::Interpolation {
+                parts, end_line, ..
+            } => parts.first().map_or(*end_line, |part| part.text_line),
+            Expr::Variable(name) | Expr::Assign { name, .. } => name.line,
+            Expr::Super { keyword_line, .. } => *keyword_line,
+            Expr::Grouping(expr) => expr.line(),
+            Expr::Tuple { elements, line } => elements.first().map_or(*line, Expr::line),
+            Expr::Vec { elements, end_line } => elements.first().map_or(*end_line, Argument::line),
+            Expr::HashMap { entries, end_line } => {
+                entries.first().map_or(*end_line, |(key, _)| key.line())
+            }
+            Expr::Binary { left, .. } | Expr::Logical { left, .. } => left.line(),
+            Expr::Range { start, .. } => start.line(),
+            Expr::Call { callee, .. } => callee.line(),
+            Expr::Get { object, .. }
+            | Expr::Set { object, .. }
+            | Expr::Index { object, .. }
+            | Expr::SetIndex { object, .. } => object.line(),
+            Expr::Lambda { parameters, body } => match (parameters.first(), body) {
+                (Some(parameter), _) => parameter.line,
+                (None, LambdaBody::Block(block)) => block.line(),
+                (None, LambdaBody::Expr { expr, .. }) => expr.line(),
+            },
+            Expr::Comprehension(comprehension) => comprehension.element.line(),
+            Expr::Error => 0,
+        }
+    }
+}
@@ -13,11 +13,14 @@
  * limitations under the License.
  */
 
-use std::collections::HashMap;
+use alloc::vec::Vec;
+use core::mem;
 
+use crate::hash::HashMap;
 use crate::memory;
 use crate::value::Value;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 #[repr(u8)]
 pub enum OpCode {
     Constant,
@@ -26,6 +29,7 @@ pub enum OpCode {
     False,
     Pop,
     CopyTop,
+    CopyTopTwo,
     GetLocal,
     SetLocal,
     GetGlobal,
@@ -40,6 +44,7 @@ pub enum OpCode {
     Equal,
     Greater,
     Less,
+    Is,
     Add,
     Subtract,
     Multiply,
@@ -57,37 +62,67 @@ pub enum OpCode {
     SetItem,
     FormatString,
     BuildHashMap,
+    ExtendHashMap,
     BuildRange,
     BuildString,
     BuildTuple,
     BuildVec,
+    ExtendVec,
+    Splice,
+    GetIter,
     IterNext,
     Jump,
+    JumpLong,
     JumpIfFalse,
     JumpIfStopIter,
+    JumpIfNotInstance,
     Loop,
+    LoopLong,
     JumpFinally,
     EndFinally,
     PushExcHandler,
+    PushExcHandlerLong,
     PopExcHandler,
     Throw,
+    Rethrow,
     Call,
+    CallSpread,
+    CallNamed,
     Invoke,
+    InvokeSpread,
+    InvokeNamed,
     Construct,
     SuperInvoke,
     Closure,
+    Generator,
     CloseUpvalue,
     Return,
+    Yield,
     DeclareClass,
     DefineClass,
     Inherit,
+    Implements,
+    Mixin,
     Method,
     StaticMethod,
+    StaticField,
     StartImport,
+    LazyImport,
     FinishImport,
 }
 
 impl OpCode {
+    // FinishImport must remain the last opcode for this to cover every opcode.
+    pub(crate) const COUNT: u8 = OpCode::FinishImport as u8 + 1;
+
+    /// Converts a byte into an opcode without checking that the byte is a valid opcode.
+    ///
+    /// # Safety
+    /// The byte must be less than `OpCode::COUNT`.
+    pub(crate) unsafe fn from_unchecked(byte: u8) -> Self {
+        mem::transmute(byte)
+    }
+
     pub(crate) fn arg_sizes(&self) -> &[usize] {
         match self {
             OpCode::Constant => &[2],
@@ -96,6 +131,7 @@ impl OpCode {
             OpCode::False => &[],
             OpCode::Pop => &[],
             OpCode::CopyTop => &[],
+            OpCode::CopyTopTwo => &[],
             OpCode::GetLocal => &[1],
             OpCode::SetLocal => &[1],
             OpCode::GetGlobal => &[2],
@@ -110,6 +146,7 @@ impl OpCode {
             OpCode::Equal => &[],
             OpCode::Greater => &[],
             OpCode::Less => &[],
+            OpCode::Is => &[],
             OpCode::Add => &[],
             OpCode::Subtract => &[],
             OpCode::Multiply => &[],
@@ -127,33 +164,52 @@ impl OpCode {
             OpCode::SetItem => &[],
             OpCode::FormatString => &[],
             OpCode::BuildHashMap => &[1],
-            OpCode::BuildRange => &[],
+            OpCode::ExtendHashMap => &[1],
+            OpCode::BuildRange => &[1],
             OpCode::BuildString => &[1],
             OpCode::BuildTuple => &[1],
             OpCode::BuildVec => &[1],
+            OpCode::ExtendVec => &[1],
+            OpCode::Splice => &[],
+            OpCode::GetIter => &[],
             OpCode::IterNext => &[],
             OpCode::Jump => &[2],
+            OpCode::JumpLong => &[4],
             OpCode::JumpIfFalse => &[2],
             OpCode::JumpIfStopIter => &[2],
+            OpCode::JumpIfNotInstance => &[2],
             OpCode::Loop => &[2],
+            OpCode::LoopLong => &[4],
             OpCode::JumpFinally => &[],
             OpCode::PushExcHandler => &[2, 2],
-            OpCode::PopExcHandler => &[2, 2],
+            OpCode::PushExcHandlerLong => &[4, 4],
+            OpCode::PopExcHandler => &[],
             OpCode::EndFinally => &[],
             OpCode::Throw => &[],
+            OpCode::Rethrow => &[],
             OpCode::Call => &[1],
+            OpCode::CallSpread => &[],
+            OpCode::CallNamed => &[1, 2],
             OpCode::Invoke => &[2, 1],
-            OpCode::Construct => &[1],
+            OpCode::InvokeSpread => &[2],
+            OpCode::InvokeNamed => &[2, 1, 2],
+            OpCode::Construct => &[1, 1],
             OpCode::SuperInvoke => &[2, 1],
             OpCode::Closure => &[2],
+            OpCode::Generator => &[],
             OpCode::CloseUpvalue => &[],
             OpCode::Return => &[],
+            OpCode::Yield => &[],
             OpCode::DeclareClass => &[2],
             OpCode::DefineClass => &[],
             OpCode::Inherit => &[],
+            OpCode::Implements => &[],
+            OpCode::Mixin => &[],
             OpCode::Method => &[2],
             OpCode::StaticMethod => &[2],
+            OpCode::StaticField => &[2],
             OpCode::StartImport => &[2],
+            OpCode::LazyImport => &[2],
             OpCode::FinishImport => &[],
         }
     }
@@ -161,81 +217,63 @@ impl OpCode {
 
 impl From<u8> for OpCode {
     fn from(value: u8) -> Self {
-        match value {
-            value if value == OpCode::Constant as u8 => OpCode::Constant,
-            value if value == OpCode::Nil as u8 => OpCode::Nil,
-            value if value == OpCode::True as u8 => OpCode::True,
-            value if value == OpCode::False as u8 => OpCode::False,
-            value if value == OpCode::Pop as u8 => OpCode::Pop,
-            value if value == OpCode::CopyTop as u8 => OpCode::CopyTop,
-            value if value == OpCode::GetLocal as u8 => OpCode::GetLocal,
-            value if value == OpCode::SetLocal as u8 => OpCode::SetLocal,
-            value if value == OpCode::GetGlobal as u8 => OpCode::GetGlobal,
-            value if value == OpCode::DefineGlobal as u8 => OpCode::DefineGlobal,
-            value if value == OpCode::SetGlobal as u8 => OpCode::SetGlobal,
-            value if value == OpCode::GetUpvalue as u8 => OpCode::GetUpvalue,
-            value if value == OpCode::SetUpvalue as u8 => OpCode::SetUpvalue,
-            value if value == OpCode::GetProperty as u8 => OpCode::GetProperty,
-            value if value == OpCode::SetProperty as u8 => OpCode::SetProperty,
-            value if value == OpCode::GetClass as u8 => OpCode::GetClass,
-            value if value == OpCode::GetSuper as u8 => OpCode::GetSuper,
-            value if value == OpCode::Equal as u8 => OpCode::Equal,
-            value if value == OpCode::Greater as u8 => OpCode::Greater,
-            value if value == OpCode::Less as u8 => OpCode::Less,
-            value if value == OpCode::Add as u8 => OpCode::Add,
-            value if value == OpCode::Subtract as u8 => OpCode::Subtract,
-            value if value == OpCode::Multiply as u8 => OpCode::Multiply,
-            value if value == OpCode::Divide as u8 => OpCode::Divide,
-            value if value == OpCode::BitwiseAnd as u8 => OpCode::BitwiseAnd,
-            value if value == OpCode::BitwiseOr as u8 => OpCode::BitwiseOr,
-            value if value == OpCode::BitwiseXor as u8 => OpCode::BitwiseXor,
-            value if value == OpCode::Modulo as u8 => OpCode::Modulo,
-            value if value == OpCode::LogicalNot as u8 => OpCode::LogicalNot,
-            value if value == OpCode::BitwiseNot as u8 => OpCode::BitwiseNot,
-            value if value == OpCode::BitShiftLeft as u8 => OpCode::BitShiftLeft,
-            value if value == OpCode::BitShiftRight as u8 => OpCode::BitShiftRight,
-            value if value == OpCode::Negate as u8 => OpCode::Negate,
-            value if value == OpCode::GetItem as u8 => OpCode::GetItem,
-            value if value == OpCode::SetItem as u8 => OpCode::SetItem,
-            value if value == OpCode::FormatString as u8 => OpCode::FormatString,
-            value if value == OpCode::BuildHashMap as u8 => OpCode::BuildHashMap,
-            value if value == OpCode::BuildRange as u8 => OpCode::BuildRange,
-            value if value == OpCode::BuildString as u8 => OpCode::BuildString,
-            value if value == OpCode::BuildTuple as u8 => OpCode::BuildTuple,
-            value if value == OpCode::BuildVec as u8 => OpCode::BuildVec,
-            value if value == OpCode::IterNext as u8 => OpCode::IterNext,
-            value if value == OpCode::Jump as u8 => OpCode::Jump,
-            value if value == OpCode::JumpIfFalse as u8 => OpCode::JumpIfFalse,
-            value if value == OpCode::JumpIfStopIter as u8 => OpCode::JumpIfStopIter,
-            value if value == OpCode::Loop as u8 => OpCode::Loop,
-            value if value == OpCode::JumpFinally as u8 => OpCode::JumpFinally,
-            value if value == OpCode::EndFinally as u8 => OpCode::EndFinally,
-            value if value == OpCode::PushExcHandler as u8 => OpCode::PushExcHandler,
-            value if value == OpCode::PopExcHandler as u8 => OpCode::PopExcHandler,
-            value if value == OpCode::Throw as u8 => OpCode::Throw,
-            value if value == OpCode::Call as u8 => OpCode::Call,
-            value if value == OpCode::Invoke as u8 => OpCode::Invoke,
-            value if value == OpCode::Construct as u8 => OpCode::Construct,
-            value if value == OpCode::SuperInvoke as u8 => OpCode::SuperInvoke,
-            value if value == OpCode::Closure as u8 => OpCode::Closure,
-            value if value == OpCode::CloseUpvalue as u8 => OpCode::CloseUpvalue,
-            value if value == OpCode::Return as u8 => OpCode::Return,
-            value if value == OpCode::DeclareClass as u8 => OpCode::DeclareClass,
-            value if value == OpCode::DefineClass as u8 => OpCode::DefineClass,
-            value if value == OpCode::Inherit as u8 => OpCode::Inherit,
-            value if value == OpCode::Method as u8 => OpCode::Method,
-            value if value == OpCode::StaticMethod as u8 => OpCode::StaticMethod,
-            value if value == OpCode::StartImport as u8 => OpCode::StartImport,
-            value if value == OpCode::FinishImport as u8 => OpCode::FinishImport,
-            _ => panic!("Unknown opcode {}", value),
+        if value >= OpCode::COUNT {
+            panic!("Unknown opcode {}", value);
         }
+        unsafe { OpCode::from_unchecked(value) }
+    }
+}
+
+/// Maps each byte of a chunk's bytecode to the line of source it was compiled from. Consecutive
+/// bytes almost always share a line, so lines are stored as runs rather than one per byte.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LineTable {
+    runs: Vec<LineRun>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct LineRun {
+    line: i32,
+    /// The offset one past the last byte in the run.
+    end: usize,
+}
+
+impl LineTable {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Records the line of the next byte of bytecode.
+    pub fn push(&mut self, line: i32) {
+        match self.runs.last_mut() {
+            Some(run) if run.line == line => run.end += 1,
+            _ => {
+                let end = self.len() + 1;
+                self.runs.push(LineRun { line, end });
+            }
+        }
+    }
+
+    /// The number of bytes of bytecode with a line.
+    pub fn len(&self) -> usize {
+        self.runs.last().map_or(0, |run| run.end)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.runs.is_empty()
+    }
+
+    /// The line of the byte at the given offset, if there is one.
+    pub fn get(&self, offset: usize) -> Option<i32> {
+        let index = self.runs.partition_point(|run| run.end <= offset);
+        self.runs.get(index).map(|run| run.line)
     }
 }
 
 #[derive(Clone, Debug, Default)]
 pub struct Chunk {
     pub code: Vec<u8>,
-    pub lines: Vec<i32>,
+    pub lines: LineTable,
     pub constant_map: HashMap<Value, usize>,
     pub constants: Vec<Value>,
 }
@@ -245,11 +283,34 @@ impl Chunk {
         Default::default()
     }
 
+    pub(crate) fn instruction_len(&self, offset: usize) -> usize {
+        let opcode = OpCode::from(self.code[offset]);
+        let len = 1 + opcode.arg_sizes().iter().sum::<usize>();
+        if opcode != OpCode::Closure {
+            return len;
+        }
+        // Closures are followed by a pair of bytes for each upvalue.
+        let constant = u16::from_ne_bytes([self.code[offset + 1], self.code[offset + 2]]);
+        match self.constants[constant as usize] {
+            Value::ObjFunction(function) => len + 2 * function.upvalue_count,
+            _ => panic!("Expected ObjFunction."),
+        }
+    }
+
     pub fn write(&mut self, byte: u8, line: i32) {
         self.code.push(byte);
         self.lines.push(line);
     }
 
+    /// The line of the instruction at the given offset.
+    pub fn line(&self, offset: usize) -> i32 {
+        self.lines
+            .get(offset)
+            .unwrap_or_else(|| panic!("Expected a line for offset {}.", offset))
+    }
+
+    /// Adds a constant to the chunk, returning its index. Constants equal to one already in the
+    /// chunk reuse its index, so repeated literals and names only take up one slot.
     pub fn add_constant(&mut self, value: Value) -> usize {
         let new_index = self.constants.len();
         let mut new_entry = false;
@@ -277,3 +338,22 @@ impl memory::GcManaged for Chunk {
         self.constants.blacken();
     }
 }
+
+#[test]
+fn test_line_table() {
+    let mut lines = LineTable::new();
+    assert!(lines.is_empty());
+    assert_eq!(lines.get(0), None);
+
+    for &line in &[1, 1, 1, 3, 2, 2] {
+        lines.push(line);
+    }
+    assert_eq!(lines.len(), 6);
+    assert_eq!(lines.runs.len(), 3);
+    let decoded: Vec<_> = (0..6).map(|offset| lines.get(offset)).collect();
+    assert_eq!(
+        decoded,
+        [Some(1), Some(1), Some(1), Some(3), Some(2), Some(2)]
+    );
+    assert_eq!(lines.get(6), None);
+}
@@ -13,6 +13,8 @@
  * limitations under the License.
  */
 
+use alloc::string::String;
+
 use crate::core;
 use crate::memory::{Gc, Root};
 use crate::object::{self, ObjClass};
@@ -62,7 +64,11 @@ impl CoreClassStore {
         let result = vm::interpret(vm, source, None);
         match result {
             Ok(_) => {}
+            #[cfg(feature = "std")]
             Err(error) => eprint!("{}", error),
+            // Without `std` there's nowhere to report errors in the core library.
+            #[cfg(not(feature = "std"))]
+            Err(_) => {}
         }
 
         let mut build_value_type_class = |name| {
@@ -81,7 +87,7 @@ impl CoreClassStore {
         let root_{{ spec.name }} = core::new_root_obj_{{ spec.name }}(
             vm,
             root_{{ spec.metaclass }}.as_gc(),
-            root_{{ spec.superclass }}_class.as_gc(),
+            root_{{ spec.superclass }}.as_gc(),
         );{% else %}
         let root_{{ spec.name }} = vm
             .global("main", "{{ spec.repr }}")
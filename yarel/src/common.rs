@@ -13,7 +13,8 @@
  * limitations under the License.
  */
 
-pub const FRAMES_MAX: usize = 64;
+pub const FRAME_STACK_SLOTS: usize = 2 * LOCALS_MAX;
+pub const INSTRUCTION_STACK_SLOTS: usize = 4;
 pub const LOCALS_MAX: usize = u8::MAX as usize + 1;
 pub const UPVALUES_MAX: usize = u8::MAX as usize + 1;
 pub const JUMP_SIZE_MAX: usize = u16::MAX as usize + 1;
@@ -21,3 +22,7 @@ pub const HEAP_INIT_BYTES_MAX: usize = 65536;
 pub const HEAP_GROWTH_FACTOR: usize = 2;
 pub const VEC_ELEMS_MAX: usize = isize::MAX as usize + 1;
 pub const INTERPOLATION_DEPTH_MAX: usize = 8;
+pub const REENTRANT_DEPTH_MAX: usize = 64;
+pub const LANG_VERSION: u32 = 1;
+pub const FIELD_INITIALISER_NAME: &str = "<fields>";
+pub const STRING_SLICE_LEN_MIN: usize = 32;
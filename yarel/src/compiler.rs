@@ -13,66 +13,34 @@
  * limitations under the License.
  */
 
-use std::cell::{Cell, RefCell};
-use std::collections::HashMap;
-use std::fmt::Write;
-use std::mem;
-use std::path::Path;
-
+use alloc::borrow::ToOwned;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use core::convert::TryFrom;
+use core::fmt::Write;
+use core::mem;
+
+use crate::ast::{
+    self, Argument, BinaryOperator, Block, ClassDecl, Comprehension, ComprehensionKind, Expr,
+    Field, FnDecl, Identifier, LambdaBody, Literal, LogicalOperator, Method, MethodKind, Stmt,
+    UnaryOperator,
+};
 use crate::chunk::{Chunk, OpCode};
 use crate::common;
-use crate::debug;
-use crate::error::{Error, ErrorKind};
+use crate::diagnostic::Diagnostic;
+use crate::error::{Error, ErrorKind, Span};
+use crate::formatter;
+use crate::hash::HashSet;
 use crate::memory::{Gc, Root};
-use crate::object::{ObjFunction, ObjString};
-use crate::scanner::{Scanner, Token, TokenKind};
+use crate::object::{ObjFunction, ObjString, ObjTuple};
+use crate::parser;
+use crate::resolver;
+use crate::symbol::{self, Symbol};
 use crate::value::{self, Value};
 use crate::vm::Vm;
 
-#[derive(Copy, Clone, Debug)]
-enum Precedence {
-    None,
-    Assignment,
-    Or,
-    And,
-    Equality,
-    Comparison,
-    BitwiseOr,
-    BitwiseXor,
-    BitwiseAnd,
-    BitShift,
-    Term,
-    Factor,
-    Range,
-    Unary,
-    Call,
-    Primary,
-}
-
-impl From<usize> for Precedence {
-    fn from(value: usize) -> Self {
-        match value {
-            value if value == Precedence::None as usize => Precedence::None,
-            value if value == Precedence::Assignment as usize => Precedence::Assignment,
-            value if value == Precedence::Or as usize => Precedence::Or,
-            value if value == Precedence::And as usize => Precedence::And,
-            value if value == Precedence::Equality as usize => Precedence::Equality,
-            value if value == Precedence::Comparison as usize => Precedence::Comparison,
-            value if value == Precedence::BitwiseOr as usize => Precedence::BitwiseOr,
-            value if value == Precedence::BitwiseXor as usize => Precedence::BitwiseXor,
-            value if value == Precedence::BitwiseAnd as usize => Precedence::BitwiseAnd,
-            value if value == Precedence::BitShift as usize => Precedence::BitShift,
-            value if value == Precedence::Term as usize => Precedence::Term,
-            value if value == Precedence::Factor as usize => Precedence::Factor,
-            value if value == Precedence::Range as usize => Precedence::Range,
-            value if value == Precedence::Unary as usize => Precedence::Unary,
-            value if value == Precedence::Call as usize => Precedence::Call,
-            value if value == Precedence::Primary as usize => Precedence::Primary,
-            _ => panic!("Unknown precedence {}", value),
-        }
-    }
-}
-
 #[derive(Clone, Copy, PartialEq)]
 enum FunctionKind {
     Function,
@@ -82,29 +50,16 @@ enum FunctionKind {
     StaticMethod,
 }
 
-impl FunctionKind {
-    fn is_bound(&self) -> bool {
-        match self {
-            FunctionKind::Initialiser => true,
-            FunctionKind::Method => true,
-            _ => false,
-        }
-    }
-}
-
 impl Default for FunctionKind {
     fn default() -> Self {
         FunctionKind::Script
     }
 }
 
-type ParseFn = fn(&mut Parser, bool) -> ();
-
-#[derive(Copy, Clone)]
-struct ParseRule {
-    prefix: Option<ParseFn>,
-    infix: Option<ParseFn>,
-    precedence: Precedence,
+enum Arguments {
+    Fixed(u8),
+    Named(u8, u16),
+    Spread,
 }
 
 #[derive(Default)]
@@ -131,11 +86,14 @@ struct Compiler {
     in_try_block: bool,
     loop_stack: Vec<(usize, usize)>,
     break_stack: Vec<Vec<usize>>,
+    /// Whether jumps are emitted with 32-bit offsets, which is only done once a function has
+    /// been found to need them.
+    long_jumps: bool,
+    jump_too_large: bool,
 }
 
 enum CompilerError {
     InvalidCompilerKind,
-    InvalidControlStatement,
     JumpTooLarge,
     LocalNotFound,
     ReadVarInInitialiser,
@@ -166,13 +124,15 @@ impl Compiler {
             in_try_block: false,
             loop_stack: Vec::new(),
             break_stack: Vec::new(),
+            long_jumps: false,
+            jump_too_large: false,
         }
     }
 
     fn allocate_function(&mut self, vm: &mut Vm) -> Root<ObjFunction> {
         let chunk = mem::replace(&mut self.chunk, Chunk::new());
-        let chunk = vm.add_chunk(chunk);
-        self.function.chunk = chunk;
+        let chunk = vm.add_chunk(chunk, self.function.module_path.as_str());
+        self.function.chunk = chunk.as_gc();
         let function = mem::replace(
             &mut self.function,
             ObjFunction::new(Gc::dangling(), 1, 0, Gc::dangling(), Gc::dangling()),
@@ -180,13 +140,13 @@ impl Compiler {
         Root::new(function)
     }
 
-    fn add_local(&mut self, name: &Token) -> bool {
+    fn add_local(&mut self, name: &str) -> bool {
         if self.locals.len() == common::LOCALS_MAX {
             return false;
         }
 
         self.locals.push(Local {
-            name: name.source.clone(),
+            name: name.to_owned(),
             depth: None,
             is_captured: false,
         });
@@ -202,9 +162,9 @@ impl Compiler {
         self.locals.last_mut().unwrap().depth = Some(self.scope_depth);
     }
 
-    fn resolve_local(&self, name: &Token) -> Result<u8, CompilerError> {
+    fn resolve_local(&self, name: &str) -> Result<u8, CompilerError> {
         for (i, local) in self.locals.iter().enumerate().rev() {
-            if local.name == name.source {
+            if local.name == name {
                 if local.depth.is_none() {
                     return Err(CompilerError::ReadVarInInitialiser);
                 }
@@ -234,9 +194,16 @@ impl Compiler {
     }
 
     fn patch_jump(&mut self, offset: usize) -> Result<(), CompilerError> {
+        if self.long_jumps {
+            let jump = self.chunk.code.len() - offset - 4;
+            let jump = u32::try_from(jump).map_err(|_| CompilerError::JumpTooLarge)?;
+            self.chunk.code[offset..offset + 4].copy_from_slice(&jump.to_ne_bytes());
+            return Ok(());
+        }
+
         let jump = self.chunk.code.len() - offset - 2;
 
-        if jump > common::JUMP_SIZE_MAX {
+        if jump >= common::JUMP_SIZE_MAX {
             return Err(CompilerError::JumpTooLarge);
         }
 
@@ -253,13 +220,9 @@ impl Compiler {
         self.break_stack.push(Vec::new());
     }
 
-    fn push_break(&mut self, pos: usize) -> Result<(), CompilerError> {
-        let breaks = self
-            .break_stack
-            .last_mut()
-            .ok_or(CompilerError::InvalidControlStatement)?;
+    fn push_break(&mut self, pos: usize) {
+        let breaks = self.break_stack.last_mut().expect("Expected Vec.");
         breaks.push(pos);
-        Ok(())
     }
 
     fn pop_loop(&mut self) -> Result<(), CompilerError> {
@@ -280,6 +243,44 @@ impl Compiler {
 
 struct ClassCompiler {
     has_superclass: bool,
+    /// The instance fields declared in the class body, which every constructor initialises.
+    fields: Vec<Field>,
+}
+
+/// Parses the source of a module into a syntax tree without compiling it.
+pub fn parse(source: String, module_path: Option<&str>) -> Result<ast::Module, Error> {
+    let parsed = parser::parse(source, module_path.unwrap_or("main"));
+    match parsed.error {
+        Some(error) => Err(error),
+        None => Ok(parsed.module),
+    }
+}
+
+/// Parses the source of a module and resolves the variables in it without compiling it, returning
+/// the errors and warnings found. Unlike `compile`, this doesn't need a `Vm`, which makes it
+/// suitable for tools such as editors.
+pub fn analyze(source: &str) -> Vec<Diagnostic> {
+    let parsed = parser::parse(source.to_owned(), "main");
+    let mut diagnostics = parsed.diagnostics;
+    diagnostics.extend(resolver::resolve(&parsed.module, source, "main").diagnostics);
+    diagnostics
+}
+
+/// Parses the source of a module, returning the symbols declared at its top level. Parts of the
+/// source that can't be parsed are skipped.
+pub fn symbols(source: &str) -> Vec<Symbol> {
+    let parsed = parser::parse(source.to_owned(), "main");
+    symbol::module_symbols(&parsed.module, source)
+}
+
+/// Formats the source of a module in the standard style, keeping its comments. Source with
+/// syntax errors is returned unchanged.
+pub fn format_source(source: &str) -> String {
+    let parsed = parser::parse(source.to_owned(), "main");
+    if parsed.error.is_some() {
+        return source.to_owned();
+    }
+    formatter::format_module(&parsed.module, source, parsed.comments)
 }
 
 pub fn compile(
@@ -287,145 +288,108 @@ pub fn compile(
     source: String,
     module_path: Option<&str>,
 ) -> Result<Root<ObjFunction>, Error> {
-    let mut scanner = Scanner::from_source(source);
-    let mut parser = Parser::new(vm, &mut scanner, module_path);
-    parser.parse()
-}
+    let parsed = parser::parse(source.clone(), module_path.unwrap_or("main"));
+    let mut generator = CodeGenerator::new(vm, module_path, &source);
+    // The tree is still generated when there are syntax errors so that any other errors in the
+    // module are reported alongside them.
+    if let Some(syntax_errors) = parsed.error {
+        generator.errors = syntax_errors;
+    }
+    let function = generator.generate(&parsed.module)?;
+
+    let is_strict = parsed.module.is_strict();
+    if vm.warnings_enabled() || is_strict {
+        let resolution = resolver::resolve(&parsed.module, &source, module_path.unwrap_or("main"));
+        vm.add_warnings(resolution.warnings, is_strict);
+    }
 
-struct Attribute {
-    name: Token,
-    arguments: Vec<Token>,
+    Ok(function)
 }
 
-struct Parser<'a> {
-    current: Token,
-    previous: Token,
-    panic_mode: Cell<bool>,
-    single_target_mode: bool,
-    scanner: &'a mut Scanner,
+/// Walks a syntax tree, emitting the bytecode for each function it contains.
+struct CodeGenerator<'a> {
+    line: usize,
+    panic_mode: bool,
     compilers: Vec<Compiler>,
     class_compilers: Vec<ClassCompiler>,
-    errors: RefCell<Vec<String>>,
+    errors: Error,
     compiled_functions: Vec<Root<ObjFunction>>,
+    name_tables: Vec<Root<ObjTuple>>,
     module_path: Gc<ObjString>,
-    attributes: HashMap<String, Attribute>,
-    attribute_opener: Option<Token>,
+    /// The globals declared by a module using `#![strict]`, which are the only ones it may assign
+    /// to.
+    strict_globals: Option<HashSet<String>>,
+    source: &'a str,
     vm: &'a mut Vm,
 }
 
-impl<'a> Parser<'a> {
-    fn new(vm: &'a mut Vm, scanner: &'a mut Scanner, module_path: Option<&str>) -> Parser<'a> {
+impl<'a> CodeGenerator<'a> {
+    fn new(vm: &'a mut Vm, module_path: Option<&str>, source: &'a str) -> CodeGenerator<'a> {
         let module_path = vm.new_gc_obj_string(module_path.unwrap_or("main"));
         let empty = vm.new_gc_obj_string("");
-        let mut ret = Parser {
-            current: Token::new(),
-            previous: Token::new(),
-            panic_mode: Cell::new(false),
-            single_target_mode: false,
-            scanner,
+        let mut ret = CodeGenerator {
+            line: 0,
+            panic_mode: false,
             compilers: Vec::new(),
             class_compilers: Vec::new(),
-            errors: RefCell::new(Vec::new()),
+            errors: Error::new(ErrorKind::CompileError),
             compiled_functions: Vec::new(),
+            name_tables: Vec::new(),
             module_path,
-            attributes: HashMap::new(),
-            attribute_opener: None,
+            strict_globals: None,
+            source,
             vm,
         };
         ret.new_compiler(FunctionKind::Script, empty, module_path);
         ret
     }
 
-    fn parse(&mut self) -> Result<Root<ObjFunction>, Error> {
-        self.advance();
-
-        while !self.match_token(TokenKind::Eof) {
-            self.declaration();
+    fn generate(&mut self, module: &ast::Module) -> Result<Root<ObjFunction>, Error> {
+        if module.is_strict() {
+            self.strict_globals = Some(module.declared_globals());
         }
-        self.check_no_attributes();
+        self.body(|s| {
+            let (last, rest) = match module.statements.split_last() {
+                Some((last, rest)) => (Some(last), rest),
+                None => (None, &module.statements[..]),
+            };
+            for statement in rest {
+                s.declaration(statement);
+            }
+            match last {
+                // A module ending in an expression returns its value, so that the REPL can show
+                // it. Assignments are left out, as their value is the one just assigned.
+                Some(Stmt::Expression { expr, end_line })
+                    if !matches!(
+                        expr,
+                        Expr::Assign { .. } | Expr::Set { .. } | Expr::SetIndex { .. }
+                    ) =>
+                {
+                    s.expression(expr);
+                    s.line = *end_line;
+                    s.emit_byte(OpCode::Return as u8);
+                }
+                Some(statement) => s.declaration(statement),
+                None => {}
+            }
+            s.line = module.end_line;
+        });
 
-        let had_error = !self.errors.borrow().is_empty();
-        if had_error {
-            return Err(Error::with_messages(
-                ErrorKind::CompileError,
-                &self
-                    .errors
-                    .borrow_mut()
-                    .iter()
-                    .map(String::as_str)
-                    .collect::<Vec<_>>(),
+        if !self.errors.messages().is_empty() {
+            return Err(mem::replace(
+                &mut self.errors,
+                Error::new(ErrorKind::CompileError),
             ));
         }
 
         Ok(self.finalise_compiler().0)
     }
 
-    fn advance(&mut self) {
-        self.previous = self.current.clone();
-
-        loop {
-            self.current = self.scanner.scan_token();
-            if self.current.kind != TokenKind::Error {
-                break;
-            }
-
-            let msg = self.current.source.clone();
-            self.error_at_current(msg.as_str());
+    fn block(&mut self, block: &Block) {
+        for statement in &block.statements {
+            self.declaration(statement);
         }
-    }
-
-    fn consume(&mut self, kind: TokenKind, message: &str) {
-        if self.current.kind == kind {
-            self.advance();
-            return;
-        }
-        self.error_at_current(message);
-    }
-
-    fn check(&self, kind: TokenKind) -> bool {
-        self.current.kind == kind
-    }
-
-    fn check_any(&self, kinds: &[TokenKind]) -> bool {
-        kinds.iter().any(|k| self.check(*k))
-    }
-
-    fn match_token(&mut self, kind: TokenKind) -> bool {
-        if !self.check(kind) {
-            return false;
-        }
-        self.advance();
-        true
-    }
-
-    fn match_binary_assignment(&mut self) -> bool {
-        self.match_token(TokenKind::MinusEqual)
-            || self.match_token(TokenKind::PlusEqual)
-            || self.match_token(TokenKind::SlashEqual)
-            || self.match_token(TokenKind::StarEqual)
-            || self.match_token(TokenKind::AmpEqual)
-            || self.match_token(TokenKind::BarEqual)
-            || self.match_token(TokenKind::CaretEqual)
-            || self.match_token(TokenKind::PercentEqual)
-            || self.match_token(TokenKind::LessLessEqual)
-            || self.match_token(TokenKind::GreaterGreaterEqual)
-    }
-
-    fn expression(&mut self) {
-        let precedence = if self.single_target_mode {
-            Precedence::BitwiseOr
-        } else {
-            Precedence::Assignment
-        };
-        self.parse_precedence(precedence);
-    }
-
-    fn block(&mut self) {
-        while !self.check(TokenKind::RightBrace) && !self.check(TokenKind::Eof) {
-            self.declaration();
-        }
-
-        self.consume(TokenKind::RightBrace, "Expected '}' after block.");
+        self.line = block.end_line;
     }
 
     fn new_compiler(
@@ -437,6 +401,36 @@ impl<'a> Parser<'a> {
         self.compilers.push(Compiler::new(kind, name, module_path));
     }
 
+    /// Generates the body of the function on top of the compiler stack. If any jump in it is too
+    /// large for a 16-bit offset then the body is generated again from scratch with 32-bit jumps,
+    /// so that only functions with huge bodies pay for the larger instructions.
+    fn body(&mut self, generate: impl Fn(&mut Self)) {
+        let num_errors = self.errors.messages().len();
+        let num_functions = self.compiled_functions.len();
+        let num_name_tables = self.name_tables.len();
+        let line = self.line;
+        generate(self);
+
+        // Any other errors make the jumps irrelevant, as no bytecode will be produced.
+        if !self.compiler().jump_too_large || self.errors.messages().len() > num_errors {
+            return;
+        }
+        self.compiled_functions.truncate(num_functions);
+        self.name_tables.truncate(num_name_tables);
+        self.line = line;
+        let compiler = self.compilers.pop().expect("Compiler stack empty.");
+        let mut retry = Compiler::new(
+            compiler.kind,
+            compiler.function.name,
+            compiler.function.module_path,
+        );
+        retry.function.doc = compiler.function.doc;
+        retry.function.is_stub = compiler.function.is_stub;
+        retry.long_jumps = true;
+        self.compilers.push(retry);
+        generate(self);
+    }
+
     fn finalise_compiler(&mut self) -> (Root<ObjFunction>, Vec<Upvalue>) {
         self.emit_return();
 
@@ -444,46 +438,68 @@ impl<'a> Parser<'a> {
         let function = compiler.allocate_function(self.vm);
         self.compiled_functions.push(function.clone());
 
-        if cfg!(feature = "debug_bytecode") && self.errors.borrow().is_empty() {
-            let chunk = function.chunk;
-            let func_name = format!("{}", Value::ObjFunction(function.as_gc()));
-            debug::disassemble_chunk(&chunk, &func_name);
+        if self.vm.disassembling() && self.errors.messages().is_empty() {
+            self.vm.disassemble(&function);
         }
 
         (function, compiler.upvalues)
     }
 
-    fn function(&mut self, kind: FunctionKind) {
-        let name = self.previous.source.clone();
-        let name = self.vm.new_gc_obj_string(name.as_str());
+    fn function(
+        &mut self,
+        kind: FunctionKind,
+        name: &Identifier,
+        doc: &Option<String>,
+        is_generator: bool,
+        function: &ast::Function,
+    ) {
+        let name = self.vm.new_gc_obj_string(name.name.as_str());
         self.new_compiler(kind, name, self.module_path);
-        self.begin_scope();
+        if let Some(doc) = doc {
+            let doc = self.vm.new_gc_obj_string(doc.as_str());
+            self.compiler_mut().function.doc = Some(doc);
+        }
+        self.compiler_mut().function.is_stub =
+            kind == FunctionKind::Method && !is_generator && function.body.statements.is_empty();
+        self.body(|s| {
+            s.begin_scope();
+
+            if kind == FunctionKind::Initialiser {
+                // Fields are initialised before the parameters are declared so that the
+                // initialisers can't refer to them.
+                s.line = function.body_line;
+                s.construct(function.parameters.len() as u8);
+            }
+            s.parameter_list(&function.parameters);
 
-        self.consume(TokenKind::LeftParen, "Expected '(' after function name.");
-        if kind.is_bound() {
-            self.consume(
-                TokenKind::Self_,
-                "Expected 'self' as first parameter in method.",
-            );
-            self.match_token(TokenKind::Comma);
-        } else if self.match_token(TokenKind::Self_) {
-            self.error("Expected parameter name.");
-            self.match_token(TokenKind::Comma);
-        }
-        self.parameter_list(
-            TokenKind::RightParen,
-            "Cannot have more than 255 parameters.",
-            "Expected parameter name.",
-        );
-        self.consume(TokenKind::RightParen, "Expected ')' after parameters.");
+            s.line = function.body_line;
+            if is_generator {
+                s.generator_body(&function.body);
+            } else {
+                s.block(&function.body);
+            }
+        });
 
-        self.consume(TokenKind::LeftBrace, "Expected '{' before function body.");
-        if kind == FunctionKind::Initialiser {
-            let arity = (self.compiler().function.arity - 1) as u8;
-            self.emit_bytes([OpCode::Construct as u8, arity]);
-        }
-        self.block();
+        self.emit_closure();
+    }
 
+    fn generator_body(&mut self, body: &Block) {
+        // The body of a generator is compiled into a closure without parameters that's run
+        // inside a fiber, capturing the generator function's parameters as upvalues. Calling
+        // the generator function just wraps this closure up in a new Generator.
+        let name = self.compiler().function.name;
+        self.new_compiler(FunctionKind::Function, name, self.module_path);
+        self.body(|s| {
+            s.begin_scope();
+            s.block(body);
+        });
+
+        self.emit_closure();
+        self.emit_byte(OpCode::Generator as u8);
+        self.emit_byte(OpCode::Return as u8);
+    }
+
+    fn emit_closure(&mut self) {
         let (function, upvalues) = self.finalise_compiler();
 
         let constant = self.make_constant(value::Value::ObjFunction(function.as_gc()));
@@ -491,35 +507,25 @@ impl<'a> Parser<'a> {
 
         for upvalue in upvalues.iter() {
             self.emit_byte(upvalue.is_local as u8);
-            self.emit_byte(upvalue.index as u8);
+            self.emit_byte(upvalue.index);
         }
     }
 
-    fn method(&mut self) {
-        if self.match_token(TokenKind::Hash) {
-            self.attributes_declaration();
-        }
-
-        let static_attr = self.take_attribute("static", 0);
-        let constructor_attr = self.take_attribute("constructor", 0);
-        self.check_supported_attributes("method");
+    fn method(&mut self, method: &Method) {
+        let constant = self.identifier_constant(&method.name.name);
 
-        self.consume(TokenKind::Fn, "Expected 'fn' before method name.");
-        self.consume(TokenKind::Identifier, "Expected method name.");
-        let previous = self.previous.clone();
-        let constant = self.identifier_constant(&previous);
-
-        let kind = if constructor_attr.is_some() {
-            if let Some(attr) = static_attr {
-                self.error_at(attr.name, "Constructors cannot be static.");
-            }
-            FunctionKind::Initialiser
-        } else if static_attr.is_some() {
-            FunctionKind::StaticMethod
-        } else {
-            FunctionKind::Method
+        let kind = match method.kind() {
+            MethodKind::Constructor => FunctionKind::Initialiser,
+            MethodKind::Static => FunctionKind::StaticMethod,
+            MethodKind::Method => FunctionKind::Method,
         };
-        self.function(kind);
+        self.function(
+            kind,
+            &method.name,
+            &method.doc,
+            method.is_generator(),
+            &method.function,
+        );
         let opcode = if kind == FunctionKind::Method {
             OpCode::Method
         } else {
@@ -528,72 +534,153 @@ impl<'a> Parser<'a> {
         self.emit_constant_op(opcode, constant);
     }
 
-    fn initialiser(&mut self, name: Token) {
-        let name_constant = self.identifier_constant(&name);
+    fn static_field(&mut self, field: &Field) {
+        let constant = self.identifier_constant(&field.name.name);
+        if let Some(initialiser) = &field.initialiser {
+            self.expression(initialiser);
+        } else {
+            self.line = field.name.line;
+            self.emit_byte(OpCode::Nil as u8);
+        }
+        self.line = field.end_line;
+        self.emit_constant_op(OpCode::StaticField, constant);
+    }
+
+    /// Assigns the initial values of the class's instance fields to the instance being
+    /// constructed.
+    fn field_initialisers(&mut self) {
+        let fields = self.class_compilers.last().unwrap().fields.clone();
+        for field in &fields {
+            self.line = field.name.line;
+            self.emit_bytes([OpCode::GetLocal as u8, 0]);
+            if let Some(initialiser) = &field.initialiser {
+                self.expression(initialiser);
+            } else {
+                self.emit_byte(OpCode::Nil as u8);
+            }
+            self.line = field.end_line;
+            let constant = self.identifier_constant(&field.name.name);
+            self.emit_constant_op(OpCode::SetProperty, constant);
+            self.emit_byte(OpCode::Pop as u8);
+        }
+    }
+
+    /// Defines the hidden method that initialises the instance fields declared by the class and
+    /// its superclasses. Constructors call it on each new instance, so the fields are initialised
+    /// whichever class's constructor creates the instance.
+    fn field_initialiser_method(&mut self, has_superclass: bool) {
+        let name_constant = self.identifier_constant(common::FIELD_INITIALISER_NAME);
+
+        let name = self.vm.new_gc_obj_string(common::FIELD_INITIALISER_NAME);
+        self.new_compiler(FunctionKind::Method, name, self.module_path);
+        self.begin_scope();
+        if has_superclass {
+            let line = self.line;
+            self.emit_bytes([OpCode::GetLocal as u8, 0]);
+            self.named_variable(&Identifier::new("super", line));
+            let super_constant = self.identifier_constant(common::FIELD_INITIALISER_NAME);
+            self.emit_constant_op(OpCode::SuperInvoke, super_constant);
+            self.emit_byte(0);
+            self.emit_byte(OpCode::Pop as u8);
+        }
+        self.field_initialisers();
+        self.emit_closure();
+
+        self.emit_constant_op(OpCode::Method, name_constant);
+    }
+
+    /// Creates the instance a constructor initialises, then initialises the fields declared by its
+    /// class and superclasses by calling its `<fields>` method. Construct skips the call when the
+    /// constructor is reached through `super`, since the instance already exists.
+    fn construct(&mut self, arg_count: u8) {
+        self.emit_bytes([OpCode::Construct as u8, arg_count]);
+        self.emit_byte(0xff);
+        let skip_offset = self.chunk().code.len() - 1;
+        let field_initialiser = self.identifier_constant(common::FIELD_INITIALISER_NAME);
+        self.emit_bytes([OpCode::GetLocal as u8, 0]);
+        self.emit_constant_op(OpCode::Invoke, field_initialiser);
+        self.emit_byte(0);
+        self.emit_byte(OpCode::Pop as u8);
+        let skip = self.chunk().code.len() - skip_offset - 1;
+        self.chunk().code[skip_offset] = skip as u8;
+    }
+
+    fn initialiser(&mut self, name: &Identifier) {
+        let name_constant = self.identifier_constant(&name.name);
         let kind = FunctionKind::Initialiser;
 
-        let name = self.vm.new_gc_obj_string(name.source.as_str());
+        let name = self.vm.new_gc_obj_string(name.name.as_str());
         self.new_compiler(kind, name, self.module_path);
         self.begin_scope();
-        self.emit_bytes([OpCode::Construct as u8, 0]);
-        let (function, _) = self.finalise_compiler();
-
-        let constant = self.make_constant(value::Value::ObjFunction(function.as_gc()));
-        self.emit_constant_op(OpCode::Closure, constant);
+        self.construct(0);
+        self.emit_closure();
 
         let opcode = OpCode::StaticMethod;
         self.emit_constant_op(opcode, name_constant);
     }
 
-    fn class_declaration(&mut self) {
-        let constructor_attr = self.take_attribute("constructor", 1);
-        let constructor_name = constructor_attr.map(|a| a.arguments[0].clone());
-        let superclass_attr = self.take_attribute("derive", 1);
-        let superclass_name = superclass_attr.map(|a| a.arguments[0].clone());
-        self.check_supported_attributes("class");
-
-        self.consume(TokenKind::Identifier, "Expected class name.");
-        let name = self.previous.clone();
-        let name_constant = self.identifier_constant(&name);
-        self.declare_variable();
+    fn class_declaration(&mut self, class: &ClassDecl) {
+        let name = &class.name;
+        let name_constant = self.identifier_constant(&name.name);
+        self.declare_variable(name);
+        let global = self.declared_global(name);
 
+        self.line = name.line;
         self.emit_constant_op(OpCode::DeclareClass, name_constant);
-        self.define_variable(name_constant);
+        self.define_variable(global);
 
         self.class_compilers.push(ClassCompiler {
             has_superclass: false,
+            fields: class
+                .fields
+                .iter()
+                .filter(|f| !f.is_static())
+                .cloned()
+                .collect(),
         });
 
-        if let Some(superclass_name) = superclass_name {
-            self.named_variable(superclass_name.clone(), false);
-
-            if name.source == superclass_name.source {
-                self.error("A class cannot inherit from itself.");
-            }
+        if let Some(superclass_name) = class.superclass() {
+            self.named_variable(superclass_name);
 
             self.begin_scope();
-            self.compiler_mut().add_local(&Token::from_string("super"));
+            self.compiler_mut().add_local("super");
             self.define_variable(0);
 
-            self.named_variable(name.clone(), false);
-            self.emit_byte_for_token(OpCode::Inherit as u8, superclass_name);
+            self.named_variable(name);
+            self.emit_byte_for_line(OpCode::Inherit as u8, superclass_name.line);
             self.class_compilers.last_mut().unwrap().has_superclass = true;
         }
 
-        let (_, set_op, arg) = self.resolve_variable(&name);
+        for mixin in class.mixins() {
+            self.named_variable(mixin);
+            self.emit_byte_for_line(OpCode::Mixin as u8, mixin.line);
+        }
+
+        let (_, set_op, arg) = self.resolve_variable(name);
 
-        self.named_variable(name, false);
-        self.consume(TokenKind::LeftBrace, "Expected '{' before class body.");
+        self.named_variable(name);
+        self.line = class.body_line;
 
-        if let Some(name) = constructor_name {
+        let has_superclass = self.class_compilers.last().unwrap().has_superclass;
+        if has_superclass || !self.class_compilers.last().unwrap().fields.is_empty() {
+            self.field_initialiser_method(has_superclass);
+        }
+        if let Some(name) = class.constructor() {
             self.initialiser(name);
         }
 
-        while !self.check(TokenKind::RightBrace) && !self.check(TokenKind::Eof) {
-            self.method();
+        for field in class.fields.iter().filter(|f| f.is_static()) {
+            self.static_field(field);
+        }
+        for method in &class.methods {
+            self.method(method);
         }
-        self.consume(TokenKind::RightBrace, "Expected '}' after class body.");
+        self.line = class.end_line;
         self.emit_byte(OpCode::DefineClass as u8);
+        if let Some(protocol_name) = class.protocol() {
+            self.named_variable(protocol_name);
+            self.emit_byte_for_line(OpCode::Implements as u8, protocol_name.line);
+        }
         self.emit_variable_op(set_op, arg);
         self.emit_byte(OpCode::Pop as u8);
 
@@ -604,157 +691,83 @@ impl<'a> Parser<'a> {
         self.class_compilers.pop();
     }
 
-    fn fn_declaration(&mut self) {
-        self.check_supported_attributes("function");
-        let global = self.parse_variable("Expected function name.");
+    fn fn_declaration(&mut self, decl: &FnDecl) {
+        let global = self.parse_variable(&decl.name);
         self.mark_initialised();
-        self.function(FunctionKind::Function);
+        let is_generator = ast::find_attribute(&decl.attributes, "generator").is_some();
+        self.function(
+            FunctionKind::Function,
+            &decl.name,
+            &decl.doc,
+            is_generator,
+            &decl.function,
+        );
         self.define_variable(global);
     }
 
-    fn take_attribute(&mut self, name: &str, num_args: usize) -> Option<Attribute> {
-        let attr = self.attributes.remove(name);
-        if let Some(attr) = attr {
-            if attr.arguments.len() != num_args {
-                let msg = format!(
-                    "Expected {} argument{} to '{}' attribute.",
-                    num_args,
-                    if num_args != 1 { "s" } else { "" },
-                    attr.name.source
-                );
-                self.error_at(attr.name, &msg);
-                None
-            } else {
-                Some(attr)
-            }
-        } else {
-            None
-        }
-    }
-
-    fn attribute(&mut self) -> Option<Attribute> {
-        if !self.match_token(TokenKind::Identifier) {
-            return None;
-        }
-        let name = self.previous.clone();
-        let mut arguments = Vec::new();
-
-        if self.match_token(TokenKind::LeftParen) {
-            loop {
-                if !self.match_token(TokenKind::Identifier) {
-                    self.error_at_current("Expected an attribute argument.");
-                    return None;
-                }
-                arguments.push(self.previous.clone());
-
-                if !self.match_token(TokenKind::Comma) {
-                    break;
-                }
-            }
-
-            if !self.match_token(TokenKind::RightParen) {
-                self.error_at_current("Expected ')' after attribute arguments.");
-                return None;
-            }
-        }
-
-        Some(Attribute { name, arguments })
-    }
-
-    fn attributes_declaration(&mut self) {
-        self.check_no_attributes();
-        let opener = self.previous.clone();
-        if !self.match_token(TokenKind::LeftBracket) {
-            self.error_at_current("Expected '[' after '#'.");
-            return;
-        }
-        let mut attributes = HashMap::new();
-
-        while let Some(attribute) = self.attribute() {
-            if attributes
-                .insert(attribute.name.source.clone(), attribute)
-                .is_some()
-            {
-                self.error(&format!("Duplicate attribute '{}'.", self.previous.source));
-                break;
-            }
+    fn var_declaration(&mut self, name: &Identifier, initialiser: &Option<Expr>, end_line: usize) {
+        let global = self.parse_variable(name);
 
-            if !self.match_token(TokenKind::Comma) {
-                break;
-            }
-        }
-        if attributes.is_empty() {
-            self.error_at_current("Expected at least one attribute.");
-        }
-        if !self.match_token(TokenKind::RightBracket) {
-            self.error_at_current("Expected ']' after attribute list.");
-            return;
-        }
-        self.attribute_opener = Some(opener);
-        self.attributes = attributes;
-    }
-
-    fn var_declaration(&mut self) {
-        self.check_no_attributes();
-        let global = self.parse_variable("Expected variable name.");
-
-        if self.match_token(TokenKind::Equal) {
-            self.expression();
+        if let Some(initialiser) = initialiser {
+            self.expression(initialiser);
         } else {
+            self.line = name.line;
             self.emit_byte(OpCode::Nil as u8);
         }
-        self.consume(
-            TokenKind::SemiColon,
-            "Expected ';' after variable declaration.",
-        );
 
+        self.line = end_line;
         self.define_variable(global);
     }
 
-    fn expression_statement(&mut self) {
-        self.expression();
-        self.consume(TokenKind::SemiColon, "Expected ';' after expression.");
-        self.emit_byte(OpCode::Pop as u8);
-    }
-
-    fn import_statement(&mut self) {
-        self.consume(TokenKind::Str, "Expected a module path.");
-        let path = &self.previous.clone();
-        if path.source == "main" {
-            self.error("Cannot import top-level module.");
-        }
-        let path_constant = self.identifier_constant(&path);
+    fn import_statement(
+        &mut self,
+        path: &Identifier,
+        binding: &Identifier,
+        is_lazy: bool,
+        end_line: usize,
+    ) {
+        let path_constant = self.identifier_constant(&path.name);
 
-        let name = if self.match_token(TokenKind::As) {
-            self.consume(TokenKind::Identifier, "Expected module name.");
-            self.previous.clone()
+        self.declare_variable(binding);
+        self.line = binding.line;
+        if is_lazy {
+            // The module is loaded when it's first used rather than here.
+            self.emit_constant_op(OpCode::LazyImport, path_constant);
         } else {
-            let result = (|| Some(Path::new(&path.source).file_name()?.to_str()?))();
-            if let Some(filename) = result {
-                Token::from_string_and_line(filename, self.current.line)
-            } else {
-                self.error("Expected a module path.");
-                return;
-            }
-        };
-        // Because the module path syntactically comes before the variable that refers to the
-        // module, we have to inject the token that refers to the module here.
-        self.previous = name.clone();
-        self.declare_variable();
-        self.emit_constant_op(OpCode::StartImport, path_constant);
+            self.emit_constant_op(OpCode::StartImport, path_constant);
 
-        self.consume(TokenKind::SemiColon, "Expected ';' after module import.");
+            self.line = end_line;
+            self.emit_byte(OpCode::FinishImport as u8);
+        }
 
-        self.emit_byte(OpCode::FinishImport as u8);
+        let global = self.declared_global(binding);
+        self.define_variable(global);
+    }
 
-        let name_constant = self.identifier_constant(&name);
-        self.define_variable(name_constant);
+    fn export_statement(&mut self, names: &[Identifier]) {
+        if self.compilers.len() > 1 || self.compiler().scope_depth > 0 {
+            self.error_at(&names[0], "Can only export from module-level code.");
+            return;
+        }
+        let module = self.vm.module(self.module_path.as_str());
+        for name in names {
+            let name_obj = self.vm.new_gc_obj_string(&name.name);
+            module.borrow_mut().export(name_obj);
+        }
     }
 
-    fn for_statement(&mut self) {
+    fn for_statement(
+        &mut self,
+        variable: &Identifier,
+        iterable: &Expr,
+        iterable_end_line: usize,
+        body_line: usize,
+        body: &Block,
+    ) {
         self.begin_scope();
 
         let loop_iter_name = "... temp-iter-var ...";
+        let loop_value_name = "... temp-loop-var ...";
 
         // For loops take the following form:
         // for v in [1, 2, 3] {
@@ -762,35 +775,35 @@ impl<'a> Parser<'a> {
         // }
         //
         // To support this we generate code equivalent to the following:
-        // var v;
+        // var tmp;
         // var it = [1, 2, 3].iter();
-        // while !(v = it.next()).derives(StopIter) {
+        // while !(tmp = it.next()).derives(StopIter) {
+        //     var v = tmp;
         //     ... loop body ...
         // }
+        //
+        // The loop variable is bound afresh on each iteration so that closures created in the
+        // loop body capture that iteration's value. Objects without an iter method but with a
+        // next method are used as their own iterators.
 
         // Set up loop variable
-        if !self.match_token(TokenKind::Identifier) {
-            self.error_at_current("Expected loop variable name.");
-            return;
-        }
-        self.declare_variable();
+        self.declare_variable(variable);
         let loop_var = self.compiler().locals.len() - 1;
+        self.line = variable.line;
         self.emit_byte(OpCode::Nil as u8);
 
-        // Parse for loop syntax
-        self.consume(TokenKind::In, "Expected 'in' after loop variable.");
-
-        // Parse iterable object
-        self.expression();
+        // Compile iterable object
+        self.expression(iterable);
 
+        // The loop variable is only declared under its own name whilst compiling the iterable, so
+        // that referring to it there is an error.
+        self.compiler_mut().locals[loop_var].name = loop_value_name.to_owned();
         self.compiler_mut().mark_initialised(loop_var);
 
-        self.compiler_mut()
-            .add_local(&Token::from_string(loop_iter_name));
-        let iter_method_name = self.identifier_constant(&Token::from_string("iter"));
+        self.compiler_mut().add_local(loop_iter_name);
         // Fetch the iterator itself
-        self.emit_constant_op(OpCode::Invoke, iter_method_name);
-        self.emit_byte(0);
+        self.line = iterable_end_line;
+        self.emit_byte(OpCode::GetIter as u8);
         self.mark_initialised();
 
         self.compiler_mut().push_loop();
@@ -805,31 +818,41 @@ impl<'a> Parser<'a> {
 
         self.emit_byte(OpCode::Pop as u8);
 
-        self.consume(TokenKind::LeftBrace, "Expected '{' after loop expression.");
+        self.line = body_line;
         self.begin_scope();
-        self.block();
+        if !self.compiler_mut().add_local(&variable.name) {
+            self.error_at(variable, "Too many variables in function.");
+        }
+        self.emit_bytes([OpCode::GetLocal as u8, loop_var as u8]);
+        self.mark_initialised();
+        self.begin_scope();
+        self.block(body);
+        self.end_scope();
         self.end_scope();
 
         self.emit_loop(loop_start);
 
         self.patch_jump(exit_jump);
         self.emit_byte(OpCode::Pop as u8);
-        match self.compiler_mut().pop_loop() {
-            Ok(_) => {}
-            Err(e) => self.compiler_error(e),
-        }
+        self.pop_loop();
         self.end_scope();
     }
 
-    fn if_statement(&mut self) {
-        self.expression();
+    fn if_statement(
+        &mut self,
+        condition: &Expr,
+        condition_end_line: usize,
+        then_branch: &Block,
+        else_branch: &Option<Box<Stmt>>,
+    ) {
+        self.expression(condition);
+        self.line = condition_end_line;
 
         let then_jump = self.emit_jump(OpCode::JumpIfFalse);
         self.emit_byte(OpCode::Pop as u8);
 
-        self.consume(TokenKind::LeftBrace, "Expected '{' after condition.");
         self.begin_scope();
-        self.block();
+        self.block(then_branch);
         self.end_scope();
 
         let else_jump = self.emit_jump(OpCode::Jump);
@@ -837,84 +860,86 @@ impl<'a> Parser<'a> {
         self.patch_jump(then_jump);
         self.emit_byte(OpCode::Pop as u8);
 
-        if self.match_token(TokenKind::Else) {
-            if !self.check_any(&[TokenKind::If, TokenKind::LeftBrace]) {
-                self.error_at_current("Expected '{' after 'else'.");
-            }
-            self.statement();
+        if let Some(else_branch) = else_branch {
+            self.statement(else_branch);
         }
         self.patch_jump(else_jump);
     }
 
-    fn return_statement(&mut self) {
-        if self.compiler().kind == FunctionKind::Script {
-            self.error("Cannot return from top-level code.");
-        }
-        if self.match_token(TokenKind::SemiColon) {
-            self.emit_return();
-        } else {
-            if self.compiler().kind == FunctionKind::Initialiser {
-                self.error("Cannot return a value from an initialiser.");
-            }
-            self.expression();
-            self.consume(TokenKind::SemiColon, "Expected ';' after return value.");
+    fn return_statement(&mut self, value: &Option<Expr>, end_line: usize) {
+        if let Some(value) = value {
+            self.expression(value);
+            self.line = end_line;
             if self.compiler().in_try_block {
                 self.emit_byte(OpCode::JumpFinally as u8);
             }
             self.emit_byte(OpCode::Return as u8);
+        } else {
+            self.line = end_line;
+            self.emit_return();
         }
     }
 
-    fn break_statement(&mut self) {
-        let break_pos = self.emit_jump(OpCode::Jump);
-        match self.compiler_mut().push_break(break_pos) {
-            Ok(_) => {}
-            Err(e) => {
-                self.compiler_error(e);
-                return;
-            }
-        }
-        let scope_depth = self
+    fn yield_statement(&mut self, value: &Expr, end_line: usize) {
+        self.expression(value);
+        self.line = end_line;
+        self.emit_byte(OpCode::Yield as u8);
+        self.emit_byte(OpCode::Pop as u8);
+    }
+
+    fn break_statement(&mut self, keyword_line: usize) {
+        let (_, scope_depth) = self
             .compiler()
             .current_loop_header()
-            .expect("Expected tuple.")
-            .1;
+            .expect("Expected loop header.");
+        self.line = keyword_line;
         self.emit_scope_end(false, scope_depth);
-        self.consume(TokenKind::SemiColon, "Expected ';' after 'break'.");
+        let break_pos = self.emit_jump(OpCode::Jump);
+        self.compiler_mut().push_break(break_pos);
     }
 
-    fn continue_statement(&mut self) {
-        let (jump_target, scope_depth) = match self.compiler().current_loop_header() {
-            Some((pos, depth)) => (pos, depth),
-            None => {
-                self.error("Cannot use 'continue' statement outside of loop body.");
-                return;
-            }
-        };
+    fn continue_statement(&mut self, keyword_line: usize) {
+        let (jump_target, scope_depth) = self
+            .compiler()
+            .current_loop_header()
+            .expect("Expected loop header.");
+        self.line = keyword_line;
         self.emit_scope_end(false, scope_depth);
         self.emit_loop(jump_target);
-        self.consume(TokenKind::SemiColon, "Expected ';' after 'continue'.");
     }
 
-    fn throw_statement(&mut self) {
-        self.expression();
-        self.consume(TokenKind::SemiColon, "Expected ';' after throw value.");
+    fn throw_statement(&mut self, value: &Expr, end_line: usize) {
+        self.expression(value);
+        self.line = end_line;
         self.emit_byte(OpCode::Throw as u8);
     }
 
-    fn try_statement(&mut self) {
+    fn try_statement(
+        &mut self,
+        keyword_line: usize,
+        body: &Block,
+        catches: &[ast::Catch],
+        finally: &Option<Block>,
+    ) {
         let prev_in_try_block = self.compiler().in_try_block;
         self.compiler_mut().in_try_block = true;
 
-        self.emit_byte(OpCode::PushExcHandler as u8);
+        self.line = keyword_line;
+        let handler_arg_size = if self.compiler().long_jumps {
+            self.emit_byte(OpCode::PushExcHandlerLong as u8);
+            4
+        } else {
+            self.emit_byte(OpCode::PushExcHandler as u8);
+            2
+        };
         let handler_catch_arg_pos = self.chunk().code.len();
-        self.emit_bytes([0xff, 0xff]);
-        self.emit_bytes([0xff, 0xff]);
+        for _ in 0..2 * handler_arg_size {
+            self.emit_byte(0xff);
+        }
         let post_handler_args_ip_pos = self.chunk().code.len();
 
-        self.consume(TokenKind::LeftBrace, "Expected '{' after 'try'.");
         self.begin_scope();
-        self.block();
+        self.block(body);
         self.end_scope();
         self.compiler_mut().in_try_block = prev_in_try_block;
 
@@ -923,93 +948,71 @@ impl<'a> Parser<'a> {
         self.patch_offset_at(handler_catch_arg_pos, post_handler_args_ip_pos);
         let catch_start_pos = self.chunk().code.len();
 
-        let have_catch = self.match_token(TokenKind::Catch);
+        // The exception handler has already been popped by the time a catch clause is reached,
+        // with the exception on top of the stack.
+        let mut catch_end_jumps = Vec::new();
+        for catch in catches {
+            self.line = catch.keyword_line;
+            let mismatch_jump = catch.class.as_ref().map(|class| {
+                self.expression(class);
+                self.line = catch.keyword_line;
+                self.emit_jump(OpCode::JumpIfNotInstance)
+            });
 
-        if have_catch {
-            self.emit_byte(OpCode::PopExcHandler as u8);
-            if !self.match_token(TokenKind::Identifier) {
-                self.error_at_current("Expected exception variable name.");
-                return;
-            }
             self.begin_scope();
-
-            self.declare_variable();
+            self.declare_variable(&catch.variable);
             self.mark_initialised();
-
-            self.consume(TokenKind::LeftBrace, "Expected '{' after variable.");
-
-            self.block();
+            self.block(&catch.body);
             self.end_scope();
+
+            if let Some(mismatch_jump) = mismatch_jump {
+                catch_end_jumps.push(self.emit_jump(OpCode::Jump));
+                self.patch_jump(mismatch_jump);
+            }
+        }
+        if catches.last().is_some_and(|c| c.class.is_some()) {
+            // No catch clause matched, so carry on raising the exception once any finally block
+            // has run.
+            self.emit_byte(OpCode::Rethrow as u8);
         }
 
+        for jump in catch_end_jumps {
+            self.patch_jump(jump);
+        }
         self.patch_jump(catch_jump_pos);
 
-        self.patch_offset_at(handler_catch_arg_pos + 2, catch_start_pos);
-        let have_finally = self.match_token(TokenKind::Finally);
+        self.patch_offset_at(handler_catch_arg_pos + handler_arg_size, catch_start_pos);
 
-        if have_finally {
-            self.consume(TokenKind::LeftBrace, "Expected '{' after 'finally'.");
+        if let Some(finally) = finally {
             self.begin_scope();
-            self.block();
+            self.block(finally);
             self.end_scope();
-            self.emit_byte(OpCode::EndFinally as u8);
-        }
-
-        if !have_catch && !have_finally {
-            self.error("Expected 'catch' or 'finally' after 'try' block.");
-            return;
         }
+        // This is needed even without a finally block to complete any return from the try block
+        // and to re-raise any exception that wasn't caught.
+        self.emit_byte(OpCode::EndFinally as u8);
     }
 
-    fn while_statement(&mut self) {
+    fn while_statement(&mut self, condition: &Expr, condition_end_line: usize, body: &Block) {
         self.compiler_mut().push_loop();
         let loop_start = self.chunk().code.len();
 
-        self.expression();
+        self.expression(condition);
+        self.line = condition_end_line;
 
         let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
 
         self.emit_byte(OpCode::Pop as u8);
 
-        self.consume(TokenKind::LeftBrace, "Expected '{' after condition.");
         self.begin_scope();
-        self.block();
+        self.block(body);
         self.end_scope();
 
         self.emit_loop(loop_start);
 
         self.patch_jump(exit_jump);
         self.emit_byte(OpCode::Pop as u8);
-        match self.compiler_mut().pop_loop() {
-            Ok(_) => {}
-            Err(e) => self.compiler_error(e),
-        }
-    }
-
-    fn synchronise(&mut self) {
-        self.panic_mode.set(false);
-
-        while self.current.kind != TokenKind::Eof {
-            if self.previous.kind == TokenKind::SemiColon {
-                return;
-            }
-
-            match self.current.kind {
-                TokenKind::Hash => return,
-                TokenKind::Class => return,
-                TokenKind::Fn => return,
-                TokenKind::Var => return,
-                TokenKind::For => return,
-                TokenKind::If => return,
-                TokenKind::While => return,
-                TokenKind::Break => return,
-                TokenKind::Continue => return,
-                TokenKind::Return => return,
-                _ => {}
-            }
-
-            self.advance();
-        }
+        self.pop_loop();
     }
 
     fn begin_scope(&mut self) {
@@ -1022,55 +1025,81 @@ impl<'a> Parser<'a> {
         self.emit_scope_end(true, scope_depth);
     }
 
-    fn statement(&mut self) {
-        self.check_no_attributes();
-        if self.match_token(TokenKind::Import) {
-            self.import_statement();
-        } else if self.match_token(TokenKind::For) {
-            self.for_statement();
-        } else if self.match_token(TokenKind::If) {
-            self.if_statement();
-        } else if self.match_token(TokenKind::Return) {
-            self.return_statement();
-        } else if self.match_token(TokenKind::Break) {
-            self.break_statement();
-        } else if self.match_token(TokenKind::Continue) {
-            self.continue_statement();
-        } else if self.match_token(TokenKind::Throw) {
-            self.throw_statement();
-        } else if self.match_token(TokenKind::Try) {
-            self.try_statement();
-        } else if self.match_token(TokenKind::While) {
-            self.while_statement();
-        } else if self.match_token(TokenKind::LeftBrace) {
-            self.begin_scope();
-            self.block();
-            self.end_scope();
-        } else {
-            self.expression_statement();
+    fn statement(&mut self, statement: &Stmt) {
+        match statement {
+            Stmt::Class(class) => self.class_declaration(class),
+            Stmt::Fn(decl) => self.fn_declaration(decl),
+            Stmt::Var {
+                name,
+                initialiser,
+                end_line,
+            } => self.var_declaration(name, initialiser, *end_line),
+            Stmt::Import {
+                attributes,
+                path,
+                binding,
+                end_line,
+                ..
+            } => {
+                let is_lazy = ast::find_attribute(attributes, "lazy").is_some();
+                self.import_statement(path, binding, is_lazy, *end_line)
+            }
+            Stmt::Export { names, .. } => self.export_statement(names),
+            Stmt::Expression { expr, end_line } => {
+                self.expression(expr);
+                self.line = *end_line;
+                self.emit_byte(OpCode::Pop as u8);
+            }
+            Stmt::For {
+                variable,
+                iterable,
+                iterable_end_line,
+                body_line,
+                body,
+            } => self.for_statement(variable, iterable, *iterable_end_line, *body_line, body),
+            Stmt::If {
+                condition,
+                condition_end_line,
+                then_branch,
+                else_branch,
+            } => self.if_statement(condition, *condition_end_line, then_branch, else_branch),
+            Stmt::Return {
+                value, end_line, ..
+            } => self.return_statement(value, *end_line),
+            Stmt::Yield {
+                value, end_line, ..
+            } => self.yield_statement(value, *end_line),
+            Stmt::Break { keyword_line } => self.break_statement(*keyword_line),
+            Stmt::Continue { keyword_line } => self.continue_statement(*keyword_line),
+            Stmt::Throw { value, end_line } => self.throw_statement(value, *end_line),
+            Stmt::Try {
+                keyword_line,
+                body,
+                catches,
+                finally,
+            } => self.try_statement(*keyword_line, body, catches, finally),
+            Stmt::While {
+                condition,
+                condition_end_line,
+                body,
+            } => self.while_statement(condition, *condition_end_line, body),
+            Stmt::Block(block) => {
+                self.begin_scope();
+                self.block(block);
+                self.end_scope();
+            }
+            Stmt::Error => {}
         }
     }
 
-    fn declaration(&mut self) {
-        if self.match_token(TokenKind::Class) {
-            self.class_declaration();
-        } else if self.match_token(TokenKind::Fn) {
-            self.fn_declaration();
-        } else if self.match_token(TokenKind::Hash) {
-            self.attributes_declaration();
-        } else if self.match_token(TokenKind::Var) {
-            self.var_declaration();
-        } else {
-            self.statement();
-        }
-
-        if self.panic_mode.get() {
-            self.synchronise();
-        }
+    fn declaration(&mut self, statement: &Stmt) {
+        self.statement(statement);
+        // Only the first error in each declaration is reported.
+        self.panic_mode = false;
     }
 
     fn emit_byte(&mut self, byte: u8) {
-        let line = self.previous.line as i32;
+        let line = self.line as i32;
         self.chunk().write(byte, line);
     }
 
@@ -1079,9 +1108,8 @@ impl<'a> Parser<'a> {
         self.emit_byte(bytes[1]);
     }
 
-    fn emit_byte_for_token(&mut self, byte: u8, token: Token) {
-        let line = token.line as i32;
-        self.chunk().write(byte, line);
+    fn emit_byte_for_line(&mut self, byte: u8, line: usize) {
+        self.chunk().write(byte, line as i32);
     }
 
     fn emit_constant_op(&mut self, opcode: OpCode, constant: u16) {
@@ -1098,11 +1126,21 @@ impl<'a> Parser<'a> {
     }
 
     fn emit_loop(&mut self, loop_start: usize) {
+        if self.compiler().long_jumps {
+            self.emit_byte(OpCode::LoopLong as u8);
+            let offset = self.chunk().code.len() - loop_start + 4;
+            match u32::try_from(offset) {
+                Ok(offset) => self.emit_long(offset),
+                Err(_) => self.error("Loop body too large."),
+            }
+            return;
+        }
+
         self.emit_byte(OpCode::Loop as u8);
 
         let offset = self.chunk().code.len() - loop_start + 2;
-        if offset > common::JUMP_SIZE_MAX {
-            self.error("Loop body too large.");
+        if offset >= common::JUMP_SIZE_MAX {
+            self.compiler_mut().jump_too_large = true;
         }
 
         let bytes = (offset as u16).to_ne_bytes();
@@ -1112,9 +1150,29 @@ impl<'a> Parser<'a> {
     }
 
     fn emit_jump(&mut self, instruction: OpCode) -> usize {
-        self.emit_byte(instruction as u8);
-        self.emit_bytes([0xff, 0xff]);
-        self.chunk().code.len() - 2
+        if !self.compiler().long_jumps {
+            self.emit_byte(instruction as u8);
+            self.emit_bytes([0xff, 0xff]);
+            return self.chunk().code.len() - 2;
+        }
+
+        if instruction != OpCode::Jump {
+            // Conditional jumps only have a short form, so they jump to a long jump that the
+            // other path skips over.
+            self.emit_byte(instruction as u8);
+            self.emit_bytes(3_u16.to_ne_bytes());
+            self.emit_byte(OpCode::Jump as u8);
+            self.emit_bytes(5_u16.to_ne_bytes());
+        }
+        self.emit_byte(OpCode::JumpLong as u8);
+        self.emit_long(u32::MAX);
+        self.chunk().code.len() - 4
+    }
+
+    fn emit_long(&mut self, value: u32) {
+        for byte in value.to_ne_bytes().iter() {
+            self.emit_byte(*byte);
+        }
     }
 
     fn emit_return(&mut self) {
@@ -1166,60 +1224,59 @@ impl<'a> Parser<'a> {
     }
 
     fn patch_jump(&mut self, offset: usize) {
-        match self.compiler_mut().patch_jump(offset) {
-            Ok(_) => {}
-            Err(e) => self.compiler_error(e),
+        if self.compiler_mut().patch_jump(offset).is_err() {
+            self.jump_too_large();
         }
     }
 
-    fn patch_offset_at(&mut self, pos: usize, offset: usize) {
-        let jump = self.chunk().code.len() - offset;
-        if jump > common::JUMP_SIZE_MAX {
-            self.error("Too much code in block.");
+    fn pop_loop(&mut self) {
+        if self.compiler_mut().pop_loop().is_err() {
+            self.jump_too_large();
         }
-
-        let bytes = (jump as u16).to_ne_bytes();
-
-        self.chunk().code[pos] = bytes[0];
-        self.chunk().code[pos + 1] = bytes[1];
     }
 
-    fn parse_precedence(&mut self, precedence: Precedence) {
-        self.advance();
-        let kind = self.previous.kind;
-        let prefix_rule = self.get_rule(kind).prefix;
-        let can_assign = precedence as usize <= Precedence::Assignment as usize;
+    fn jump_too_large(&mut self) {
+        if self.compiler().long_jumps {
+            self.error("Too much code to jump over.");
+        } else {
+            self.compiler_mut().jump_too_large = true;
+        }
+    }
 
-        match prefix_rule {
-            Some(ref handler) => handler(self, can_assign),
-            None => {
-                self.error("Expected expression.");
-                return;
+    /// Writes the size of the code generated since the specified offset at the specified
+    /// position, as a 32-bit size if the function is being generated with long jumps.
+    fn patch_offset_at(&mut self, pos: usize, offset: usize) {
+        let jump = self.chunk().code.len() - offset;
+        if self.compiler().long_jumps {
+            match u32::try_from(jump) {
+                Ok(jump) => self.chunk().code[pos..pos + 4].copy_from_slice(&jump.to_ne_bytes()),
+                Err(_) => self.error("Too much code in block."),
             }
+            return;
         }
-
-        while precedence as usize <= self.get_rule(self.current.kind).precedence as usize {
-            self.advance();
-            let infix_rule = self.get_rule(self.previous.kind).infix;
-            infix_rule.unwrap()(self, can_assign);
+        if jump >= common::JUMP_SIZE_MAX {
+            self.jump_too_large();
+            return;
         }
 
-        if can_assign && self.match_token(TokenKind::Equal) {
-            self.error("Invalid assignment target.");
-        }
+        let bytes = (jump as u16).to_ne_bytes();
+
+        self.chunk().code[pos] = bytes[0];
+        self.chunk().code[pos + 1] = bytes[1];
     }
 
-    fn identifier_constant(&mut self, token: &Token) -> u16 {
-        let value = Value::ObjString(self.vm.new_gc_obj_string(&token.source));
+    fn identifier_constant(&mut self, name: &str) -> u16 {
+        let value = Value::ObjString(self.vm.new_gc_obj_string(name));
         self.make_constant(value)
     }
 
-    fn declare_variable(&mut self) {
+    fn declare_variable(&mut self, name: &Identifier) {
         let scope_depth = self.compiler().scope_depth;
         if scope_depth == 0 {
             return;
         }
 
+        let mut already_declared = false;
         for local in self.compilers.last().unwrap().locals.iter().rev() {
             if let Some(value) = local.depth {
                 if value < scope_depth {
@@ -1227,26 +1284,43 @@ impl<'a> Parser<'a> {
                 }
             }
 
-            if self.previous.source == local.name {
-                self.error("Variable with this name already declared in this scope.");
+            if name.name == local.name {
+                already_declared = true;
             }
         }
+        if already_declared {
+            self.error_at(
+                name,
+                "Variable with this name already declared in this scope.",
+            );
+        }
 
-        if !self.compilers.last_mut().unwrap().add_local(&self.previous) {
-            self.error("Too many variables in function.");
+        if !self.compilers.last_mut().unwrap().add_local(&name.name) {
+            self.error_at(name, "Too many variables in function.");
         }
     }
 
-    fn parse_variable(&mut self, error_message: &str) -> u16 {
-        self.consume(TokenKind::Identifier, error_message);
+    fn parse_variable(&mut self, name: &Identifier) -> u16 {
+        self.declare_variable(name);
+        self.declared_global(name)
+    }
 
-        self.declare_variable();
+    fn declared_global(&mut self, name: &Identifier) -> u16 {
         if self.compiler().scope_depth > 0 {
             return 0;
         }
+        self.global_slot(name)
+    }
 
-        let name = self.previous.clone();
-        self.identifier_constant(&name)
+    fn global_slot(&mut self, name: &Identifier) -> u16 {
+        let name_obj = self.vm.new_gc_obj_string(&name.name);
+        let module = self.vm.module(self.module_path.as_str());
+        let slot = module.borrow_mut().slot(name_obj);
+        if slot > u16::MAX as usize {
+            self.error_at(name, "Too many global variables in module.");
+            return 0;
+        }
+        slot as u16
     }
 
     fn mark_initialised(&mut self) {
@@ -1266,136 +1340,153 @@ impl<'a> Parser<'a> {
         self.emit_bytes(global.to_ne_bytes());
     }
 
-    fn argument_list(&mut self, right_delim: TokenKind, count_msg: &str, delim_msg: &str) -> u8 {
+    fn arguments(&mut self, arguments: &[Argument], end_line: usize) -> Arguments {
         let mut arg_count: usize = 0;
-        if !self.check(right_delim) {
-            loop {
-                self.expression();
-                if arg_count == 255 {
-                    self.error(count_msg);
+        let mut spread = false;
+        let mut names = Vec::new();
+        for argument in arguments {
+            match argument {
+                Argument::Spread { value, line } => {
+                    // Any arguments preceding a spread are gathered into a Vec, into which the
+                    // spread iterable and all subsequent arguments are spliced.
+                    self.emit_spread_arguments(arg_count, spread);
+                    spread = true;
+                    arg_count = 0;
+                    self.expression(value);
+                    self.line = *line;
+                    self.emit_splice();
                 }
-                arg_count += 1;
-
-                if !self.match_token(TokenKind::Comma) {
-                    break;
+                Argument::Positional(value) => {
+                    // Only Vec literals can have more elements than an instruction can gather, in
+                    // which case each full batch is spliced into a Vec of the preceding elements.
+                    if arg_count == u8::MAX as usize {
+                        self.emit_spread_arguments(arg_count, spread);
+                        spread = true;
+                        arg_count = 0;
+                    }
+                    self.expression(value);
+                    arg_count += 1;
+                }
+                Argument::Named(name, value) => {
+                    names.push(name);
+                    self.expression(value);
+                    arg_count += 1;
                 }
             }
         }
 
-        self.consume(right_delim, delim_msg);
-        arg_count as u8
+        self.line = end_line;
+        if spread {
+            if arg_count > 0 {
+                self.emit_spread_arguments(arg_count, spread);
+            }
+            Arguments::Spread
+        } else if !names.is_empty() {
+            let names = names
+                .iter()
+                .map(|n| Value::ObjString(self.vm.new_gc_obj_string(n.name.as_str())))
+                .collect();
+            let table = self.vm.new_root_obj_tuple(names);
+            let constant = self.make_constant(Value::ObjTuple(table.as_gc()));
+            self.name_tables.push(table);
+            Arguments::Named(arg_count as u8, constant)
+        } else {
+            Arguments::Fixed(arg_count as u8)
+        }
     }
 
-    fn parameter_list(&mut self, right_delim: TokenKind, count_msg: &str, param_msg: &str) {
-        if !self.check(right_delim) {
-            loop {
-                self.compiler_mut().function.arity += 1;
-                if self.compiler().function.arity > 256 {
-                    self.error_at_current(count_msg);
-                }
+    fn emit_spread_arguments(&mut self, arg_count: usize, spread: bool) {
+        let opcode = if spread {
+            OpCode::ExtendVec
+        } else {
+            OpCode::BuildVec
+        };
+        self.emit_bytes([opcode as u8, arg_count as u8]);
+    }
 
-                let param_constant = self.parse_variable(param_msg);
-                self.define_variable(param_constant);
+    /// Appends each value produced by the iterable on top of the stack to the Vec beneath it,
+    /// iterating in the same way as a for loop.
+    fn emit_splice(&mut self) {
+        self.emit_byte(OpCode::GetIter as u8);
+        let loop_start = self.chunk().code.len();
+        self.emit_byte(OpCode::IterNext as u8);
+        let exit_jump = self.emit_jump(OpCode::JumpIfStopIter);
+        self.emit_byte(OpCode::Splice as u8);
+        self.emit_loop(loop_start);
 
-                if !self.match_token(TokenKind::Comma) {
-                    break;
-                }
-            }
-        }
+        self.patch_jump(exit_jump);
+        // Discard the value that stopped the iteration and the iterator itself.
+        self.emit_bytes([OpCode::Pop as u8, OpCode::Pop as u8]);
     }
 
-    fn get_rule(&self, kind: TokenKind) -> &ParseRule {
-        &RULES[kind as usize]
+    fn parameter_list(&mut self, parameters: &[Identifier]) {
+        for parameter in parameters {
+            self.compiler_mut().function.arity += 1;
+            let param_constant = self.parse_variable(parameter);
+            self.define_variable(param_constant);
+            let name = self.vm.new_gc_obj_string(parameter.name.as_str());
+            self.compiler_mut().function.parameters.push(name);
+        }
     }
 
-    fn error_at_current(&self, message: &str) {
-        self.error_at(self.current.clone(), message);
+    fn error_at(&mut self, name: &Identifier, message: &str) {
+        let location = format!(" at '{}'", name.name);
+        let span = name
+            .offset
+            .map(|offset| Span::from_offset(self.source, offset, name.name.len()));
+        self.report_error(name.line, &location, message, span);
     }
 
-    fn error(&self, message: &str) {
-        self.error_at(self.previous.clone(), message);
+    fn error(&mut self, message: &str) {
+        self.report_error(self.line, "", message, None);
     }
 
-    fn error_at(&self, token: Token, message: &str) {
-        if self.panic_mode.get() {
+    fn report_error(&mut self, line: usize, location: &str, message: &str, span: Option<Span>) {
+        if self.panic_mode {
             return;
         }
-        self.panic_mode.set(true);
+        self.panic_mode = true;
 
         let mut error_string = String::new();
-
         write!(
             error_string,
-            "[module \"{}\", line {}] Error",
+            "[module \"{}\", line {}] Error{}: {}",
             self.module_path.as_str(),
-            token.line
+            line,
+            location,
+            message
         )
         .unwrap();
-
-        match token.kind {
-            TokenKind::Eof => write!(error_string, " at end").unwrap(),
-            TokenKind::Error => {}
-            _ => write!(error_string, " at '{}'", token.source).unwrap(),
-        };
-
-        write!(error_string, ": {}", message).unwrap();
-        self.errors.borrow_mut().push(error_string);
+        self.errors.add_message_with_span(&error_string, span);
     }
 
-    fn compiler_error(&mut self, error: CompilerError) {
+    fn compiler_error(&mut self, error: CompilerError, name: &Identifier) {
         match error {
-            CompilerError::InvalidControlStatement => {
-                let msg = format!(
-                    "Cannot use '{}' statement outside of loop body.",
-                    if self.previous.kind == TokenKind::Break {
-                        "break"
-                    } else {
-                        "continue"
-                    }
-                );
-                self.error(&msg);
-            }
             CompilerError::JumpTooLarge => self.error("Too much code to jump over."),
             CompilerError::ReadVarInInitialiser => {
-                self.error("Cannot read local variable in its own initialiser.");
+                self.error_at(name, "Cannot read local variable in its own initialiser.");
             }
             CompilerError::TooManyClosureVars => {
-                self.error("Too many closure variables in function.");
+                self.error_at(name, "Too many closure variables in function.");
             }
             _ => {}
         }
     }
 
-    fn check_no_attributes(&mut self) {
-        if let Some(opener) = self.attribute_opener.take() {
-            self.error_at(opener, "Unexpected attribute list.");
-        }
-        self.attributes.clear();
-    }
-
-    fn check_supported_attributes(&mut self, kind: &str) {
-        for attr in self.attributes.values() {
-            let msg = format!("Unsupported {} attribute '{}'.", kind, attr.name.source);
-            self.error_at(attr.name.clone(), &msg);
-        }
-        self.attributes.clear();
-        self.attribute_opener = None;
-    }
-
-    fn resolve_local(&mut self, name: &Token) -> Option<u8> {
-        match self.compiler_mut().resolve_local(name) {
+    fn resolve_local(&mut self, name: &Identifier) -> Option<u8> {
+        match self.compiler_mut().resolve_local(&name.name) {
             Ok(index) => Some(index),
             Err(error) => {
-                self.compiler_error(error);
+                self.compiler_error(error, name);
                 None
             }
         }
     }
 
-    fn resolve_upvalue(&mut self, name: &Token) -> Option<u8> {
+    fn resolve_upvalue(&mut self, name: &Identifier) -> Option<u8> {
         if self.compilers.len() < 2 {
             // If there's only one scope then we're not going to find an upvalue.
-            self.compiler_error(CompilerError::InvalidCompilerKind);
+            self.compiler_error(CompilerError::InvalidCompilerKind, name);
             return None;
         }
 
@@ -1403,7 +1494,7 @@ impl<'a> Parser<'a> {
         for enclosing in (0..self.compilers.len() - 1).rev() {
             let current = enclosing + 1;
             // Try and resolve the local in the enclosing compiler's scope.
-            if let Ok(index) = self.compilers[enclosing].resolve_local(name) {
+            if let Ok(index) = self.compilers[enclosing].resolve_local(&name.name) {
                 // If we found it, mark as captured and propagate the upvalue to the compilers that
                 // are enclosed by the current one.
                 self.compilers[enclosing].locals[index as usize].is_captured = true;
@@ -1412,7 +1503,7 @@ impl<'a> Parser<'a> {
                     index = match self.compilers[compiler].add_upvalue(index, compiler == current) {
                         Ok(index) => index,
                         Err(error) => {
-                            self.compiler_error(error);
+                            self.compiler_error(error, name);
                             return None;
                         }
                     };
@@ -1423,59 +1514,34 @@ impl<'a> Parser<'a> {
         None
     }
 
-    fn binary_assign(&mut self, get_op: OpCode, variable: u16) {
-        self.single_target_mode = true;
-        let op_kind = self.previous.kind;
-        self.emit_variable_op(get_op, variable);
-        self.expression();
-        match op_kind {
-            TokenKind::MinusEqual => self.emit_byte(OpCode::Subtract as u8),
-            TokenKind::PlusEqual => self.emit_byte(OpCode::Add as u8),
-            TokenKind::SlashEqual => self.emit_byte(OpCode::Divide as u8),
-            TokenKind::StarEqual => self.emit_byte(OpCode::Multiply as u8),
-            TokenKind::AmpEqual => self.emit_byte(OpCode::BitwiseAnd as u8),
-            TokenKind::BarEqual => self.emit_byte(OpCode::BitwiseOr as u8),
-            TokenKind::CaretEqual => self.emit_byte(OpCode::BitwiseXor as u8),
-            TokenKind::PercentEqual => self.emit_byte(OpCode::Modulo as u8),
-            TokenKind::LessLessEqual => self.emit_byte(OpCode::BitShiftLeft as u8),
-            TokenKind::GreaterGreaterEqual => self.emit_byte(OpCode::BitShiftRight as u8),
-            _ => unreachable!(),
-        }
-        self.single_target_mode = false;
-    }
-
-    fn resolve_variable(&mut self, name: &Token) -> (OpCode, OpCode, u16) {
-        if let Some(result) = self.resolve_local(&name) {
+    fn resolve_variable(&mut self, name: &Identifier) -> (OpCode, OpCode, u16) {
+        if let Some(result) = self.resolve_local(name) {
             (OpCode::GetLocal, OpCode::SetLocal, result as u16)
-        } else if let Some(result) = self.resolve_upvalue(&name) {
+        } else if let Some(result) = self.resolve_upvalue(name) {
             (OpCode::GetUpvalue, OpCode::SetUpvalue, result as u16)
         } else {
-            (
-                OpCode::GetGlobal,
-                OpCode::SetGlobal,
-                self.identifier_constant(&name),
-            )
+            (OpCode::GetGlobal, OpCode::SetGlobal, self.global_slot(name))
         }
     }
 
-    fn named_variable(&mut self, name: Token, can_assign: bool) {
-        let (get_op, set_op, arg) = self.resolve_variable(&name);
-
-        if can_assign && self.match_token(TokenKind::Equal) {
-            self.expression();
-            self.emit_variable_op(set_op, arg);
-        } else if can_assign && self.match_binary_assignment() {
-            self.binary_assign(get_op, arg);
-            self.emit_variable_op(set_op, arg);
-        } else {
-            if get_op.arg_sizes() == &[1] {
-                self.emit_bytes([get_op as u8, arg as u8]);
-            } else {
-                self.emit_constant_op(get_op, arg);
-            }
+    fn check_global_assignment(&mut self, name: &Identifier) {
+        let is_declared = self
+            .strict_globals
+            .as_ref()
+            .is_none_or(|globals| globals.contains(&name.name));
+        if !is_declared {
+            self.error_at(
+                name,
+                "Cannot assign to undeclared global variable in strict mode.",
+            );
         }
     }
 
+    fn named_variable(&mut self, name: &Identifier) {
+        let (get_op, _, arg) = self.resolve_variable(name);
+        self.emit_variable_op(get_op, arg);
+    }
+
     fn compiler(&mut self) -> &Compiler {
         &self.compilers.last().unwrap()
     }
@@ -1488,788 +1554,481 @@ impl<'a> Parser<'a> {
         &mut self.compiler_mut().chunk
     }
 
-    fn grouping(s: &mut Parser, _can_assign: bool) {
-        let mut single_elem_tuple = false;
-        let mut num_elems: usize = 0;
-        if !s.check(TokenKind::RightParen) {
-            loop {
-                s.expression();
-                if num_elems == 255 {
-                    s.error("Cannot have more than 255 Tuple elements.");
+    fn emit_binary_op(&mut self, operator: BinaryOperator) {
+        match operator {
+            BinaryOperator::NotEqual => {
+                self.emit_bytes([OpCode::Equal as u8, OpCode::LogicalNot as u8])
+            }
+            BinaryOperator::Equal => self.emit_byte(OpCode::Equal as u8),
+            BinaryOperator::Greater => self.emit_byte(OpCode::Greater as u8),
+            BinaryOperator::GreaterEqual => {
+                self.emit_bytes([OpCode::Less as u8, OpCode::LogicalNot as u8])
+            }
+            BinaryOperator::Less => self.emit_byte(OpCode::Less as u8),
+            BinaryOperator::LessEqual => {
+                self.emit_bytes([OpCode::Greater as u8, OpCode::LogicalNot as u8])
+            }
+            BinaryOperator::Add => self.emit_byte(OpCode::Add as u8),
+            BinaryOperator::Subtract => self.emit_byte(OpCode::Subtract as u8),
+            BinaryOperator::Multiply => self.emit_byte(OpCode::Multiply as u8),
+            BinaryOperator::Divide => self.emit_byte(OpCode::Divide as u8),
+            BinaryOperator::BitwiseAnd => self.emit_byte(OpCode::BitwiseAnd as u8),
+            BinaryOperator::BitwiseOr => self.emit_byte(OpCode::BitwiseOr as u8),
+            BinaryOperator::BitwiseXor => self.emit_byte(OpCode::BitwiseXor as u8),
+            BinaryOperator::Modulo => self.emit_byte(OpCode::Modulo as u8),
+            BinaryOperator::BitShiftLeft => self.emit_byte(OpCode::BitShiftLeft as u8),
+            BinaryOperator::BitShiftRight => self.emit_byte(OpCode::BitShiftRight as u8),
+            BinaryOperator::Is => self.emit_byte(OpCode::Is as u8),
+        }
+    }
+
+    fn expression(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Number { value, line, .. } => {
+                self.line = *line;
+                self.emit_constant(value::Value::Number(*value));
+            }
+            Expr::Str { value, line } => {
+                self.line = *line;
+                let value = Value::ObjString(self.vm.new_gc_obj_string(value));
+                self.emit_constant(value);
+            }
+            Expr::Interpolation {
+                parts,
+                tail,
+                end_line,
+            } => self.interpolation(parts, tail, *end_line),
+            Expr::Literal { value, line } => {
+                self.line = *line;
+                let opcode = match value {
+                    Literal::False => OpCode::False,
+                    Literal::Nil => OpCode::Nil,
+                    Literal::True => OpCode::True,
+                };
+                self.emit_byte(opcode as u8);
+            }
+            Expr::Variable(name) => {
+                self.line = name.line;
+                self.named_variable(name);
+            }
+            Expr::Assign {
+                name,
+                operator,
+                value,
+                line,
+            } => {
+                let (get_op, set_op, arg) = self.resolve_variable(name);
+                if set_op == OpCode::SetGlobal {
+                    self.check_global_assignment(name);
                 }
-                num_elems += 1;
-
-                if !s.match_token(TokenKind::Comma) {
-                    break;
+                if let Some(operator) = operator {
+                    self.line = operator.line;
+                    self.emit_variable_op(get_op, arg);
+                    self.expression(value);
+                    self.line = *line;
+                    self.emit_binary_op(operator.operator);
+                } else {
+                    self.expression(value);
+                    self.line = *line;
                 }
-                if num_elems == 1 && s.check(TokenKind::RightParen) {
-                    single_elem_tuple = true;
-                    break;
+                self.emit_variable_op(set_op, arg);
+            }
+            Expr::SelfRef { line } => {
+                self.line = *line;
+                self.named_variable(&Identifier::new("self", *line));
+            }
+            Expr::SelfClass { line } => {
+                // TODO: Optimise this access to generate a single opcode
+                self.line = *line;
+                self.named_variable(&Identifier::new("Self", *line));
+                self.emit_byte(OpCode::GetClass as u8);
+            }
+            Expr::Super { method, call, .. } => self.super_(method, call),
+            Expr::Grouping(expr) => self.expression(expr),
+            Expr::Tuple { elements, line } => {
+                for element in elements {
+                    self.expression(element);
                 }
+                self.line = *line;
+                self.emit_bytes([OpCode::BuildTuple as u8, elements.len() as u8]);
             }
-        }
-
-        let is_tuple = num_elems != 1 || single_elem_tuple;
-        if is_tuple {
-            s.emit_bytes([OpCode::BuildTuple as u8, num_elems as u8]);
-        }
-
-        let msg = &format!(
-            "Expected ')' after {}.",
-            if is_tuple { "elements" } else { "expression" }
-        );
-        s.consume(TokenKind::RightParen, msg);
-    }
-
-    fn binary(s: &mut Parser, _can_assign: bool) {
-        let operator_kind = s.previous.kind;
-        let rule_precedence = s.get_rule(operator_kind).precedence;
-        s.parse_precedence(Precedence::from(rule_precedence as usize + 1));
-
-        match operator_kind {
-            TokenKind::BangEqual => s.emit_bytes([OpCode::Equal as u8, OpCode::LogicalNot as u8]),
-            TokenKind::EqualEqual => s.emit_byte(OpCode::Equal as u8),
-            TokenKind::Greater => s.emit_byte(OpCode::Greater as u8),
-            TokenKind::GreaterEqual => s.emit_bytes([OpCode::Less as u8, OpCode::LogicalNot as u8]),
-            TokenKind::Less => s.emit_byte(OpCode::Less as u8),
-            TokenKind::LessEqual => s.emit_bytes([OpCode::Greater as u8, OpCode::LogicalNot as u8]),
-            TokenKind::Plus => s.emit_byte(OpCode::Add as u8),
-            TokenKind::Minus => s.emit_byte(OpCode::Subtract as u8),
-            TokenKind::Star => s.emit_byte(OpCode::Multiply as u8),
-            TokenKind::Slash => s.emit_byte(OpCode::Divide as u8),
-            TokenKind::Amp => s.emit_byte(OpCode::BitwiseAnd as u8),
-            TokenKind::Bar => s.emit_byte(OpCode::BitwiseOr as u8),
-            TokenKind::Caret => s.emit_byte(OpCode::BitwiseXor as u8),
-            TokenKind::Percent => s.emit_byte(OpCode::Modulo as u8),
-            TokenKind::LessLess => s.emit_byte(OpCode::BitShiftLeft as u8),
-            TokenKind::GreaterGreater => s.emit_byte(OpCode::BitShiftRight as u8),
-            _ => {}
-        }
-    }
-
-    fn call(s: &mut Parser, _can_assign: bool) {
-        let arg_count = s.argument_list(
-            TokenKind::RightParen,
-            "Cannot have more than 255 arguments.",
-            "Expected ')' after arguments.",
-        );
-        s.emit_bytes([OpCode::Call as u8, arg_count]);
-    }
-
-    fn dot(s: &mut Parser, can_assign: bool) {
-        s.consume(TokenKind::Identifier, "Expected property name after '.'.");
-        let previous = s.previous.clone();
-        let name = s.identifier_constant(&previous);
-
-        if can_assign && s.match_token(TokenKind::Equal) {
-            s.expression();
-            s.emit_constant_op(OpCode::SetProperty, name);
-        } else if can_assign && s.match_binary_assignment() {
-            s.emit_byte(OpCode::CopyTop as u8);
-            s.binary_assign(OpCode::GetProperty, name);
-            s.emit_constant_op(OpCode::SetProperty, name);
-        } else if s.match_token(TokenKind::LeftParen) {
-            let arg_count = s.argument_list(
-                TokenKind::RightParen,
-                "Cannot have more than 255 arguments.",
-                "Expected ')' after arguments.",
-            );
-            s.emit_constant_op(OpCode::Invoke, name);
-            s.emit_byte(arg_count);
-        } else {
-            s.emit_constant_op(OpCode::GetProperty, name);
-        }
-    }
-
-    fn dotdot(s: &mut Parser, _can_assign: bool) {
-        s.parse_precedence(Precedence::Unary);
-        s.emit_byte(OpCode::BuildRange as u8);
-    }
-
-    fn index(s: &mut Parser, can_assign: bool) {
-        s.expression();
-        s.consume(TokenKind::RightBracket, "Expected ']' after index.");
-
-        let opcode = if can_assign && s.match_token(TokenKind::Equal) {
-            s.expression();
-            OpCode::SetItem
-        } else {
-            OpCode::GetItem
-        };
-        s.emit_byte(opcode as u8);
-    }
-
-    fn lambda(s: &mut Parser, _can_assign: bool) {
-        let lambda_count = s.compiler().lambda_count;
-        s.compiler_mut().lambda_count += 1;
-        let name =
-            s.vm.new_gc_obj_string(format!("lambda-{}", lambda_count).as_str());
-        s.new_compiler(FunctionKind::Function, name, s.module_path);
-        s.begin_scope();
-
-        if s.previous.kind == TokenKind::Bar {
-            s.parameter_list(
-                TokenKind::Bar,
-                "Cannot have more than 255 parameters.",
-                "Expected parameter name.",
-            );
-            s.consume(TokenKind::Bar, "Expected ')' after parameters.");
-        }
-
-        if s.match_token(TokenKind::LeftBrace) {
-            s.block();
-        } else {
-            s.expression();
-            s.emit_byte(OpCode::Return as u8);
-        }
-
-        let (function, upvalues) = s.finalise_compiler();
-
-        let constant = s.make_constant(value::Value::ObjFunction(function.as_gc()));
-        s.emit_constant_op(OpCode::Closure, constant);
-
-        for upvalue in upvalues.iter() {
-            s.emit_byte(upvalue.is_local as u8);
-            s.emit_byte(upvalue.index as u8);
-        }
-    }
-
-    fn hash_map(s: &mut Parser, _can_assign: bool) {
-        let mut num_entries: usize = 0;
-        if !s.check(TokenKind::RightBrace) {
-            loop {
-                s.expression();
-                s.consume(TokenKind::Colon, "Expected ':' after key.");
-                s.expression();
-
-                if num_entries == 255 {
-                    s.error("Cannot have more than 255 HashMap entries.");
+            Expr::Vec { elements, end_line } => {
+                if let Arguments::Fixed(num_elems) = self.arguments(elements, *end_line) {
+                    self.emit_bytes([OpCode::BuildVec as u8, num_elems]);
                 }
-                num_entries += 1;
-
-                if !s.match_token(TokenKind::Comma) {
-                    break;
+            }
+            Expr::HashMap { entries, end_line } => {
+                // Entries are inserted in batches of at most 255, the most a single instruction
+                // can take, so that large data literals can be compiled.
+                let mut opcode = OpCode::BuildHashMap;
+                for batch in entries.chunks(u8::MAX as usize) {
+                    for (key, value) in batch {
+                        self.expression(key);
+                        self.expression(value);
+                    }
+                    self.line = *end_line;
+                    self.emit_bytes([opcode as u8, batch.len() as u8]);
+                    opcode = OpCode::ExtendHashMap;
+                }
+                if entries.is_empty() {
+                    self.line = *end_line;
+                    self.emit_bytes([OpCode::BuildHashMap as u8, 0]);
                 }
             }
+            Expr::Unary {
+                operator,
+                operand,
+                line,
+            } => {
+                self.expression(operand);
+                self.line = *line;
+                let opcode = match operator {
+                    UnaryOperator::Negate => OpCode::Negate,
+                    UnaryOperator::Not => OpCode::LogicalNot,
+                    UnaryOperator::BitwiseNot => OpCode::BitwiseNot,
+                };
+                self.emit_byte(opcode as u8);
+            }
+            Expr::Binary {
+                operator,
+                left,
+                right,
+                line,
+            } => {
+                self.expression(left);
+                self.expression(right);
+                self.line = *line;
+                self.emit_binary_op(*operator);
+            }
+            Expr::Logical {
+                operator,
+                left,
+                right,
+                line,
+            } => {
+                self.expression(left);
+                self.line = *line;
+                match operator {
+                    LogicalOperator::And => {
+                        let end_jump = self.emit_jump(OpCode::JumpIfFalse);
+                        self.emit_byte(OpCode::Pop as u8);
+                        self.expression(right);
+                        self.patch_jump(end_jump);
+                    }
+                    LogicalOperator::Or => {
+                        let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+                        let end_jump = self.emit_jump(OpCode::Jump);
+                        self.patch_jump(else_jump);
+                        self.emit_byte(OpCode::Pop as u8);
+                        self.expression(right);
+                        self.patch_jump(end_jump);
+                    }
+                }
+            }
+            Expr::Range {
+                start,
+                end,
+                step,
+                line,
+            } => {
+                self.expression(start);
+                self.expression(end);
+                if let Some(step) = step {
+                    self.expression(step);
+                }
+                self.line = *line;
+                self.emit_bytes([OpCode::BuildRange as u8, step.is_some() as u8]);
+            }
+            Expr::Call {
+                callee,
+                arguments,
+                end_line,
+            } => self.call(callee, arguments, *end_line),
+            Expr::Get { object, name } => {
+                self.expression(object);
+                let constant = self.identifier_constant(&name.name);
+                self.line = name.line;
+                self.emit_constant_op(OpCode::GetProperty, constant);
+            }
+            Expr::Set {
+                object,
+                name,
+                operator,
+                value,
+                line,
+            } => {
+                self.expression(object);
+                let constant = self.identifier_constant(&name.name);
+                if let Some(operator) = operator {
+                    self.line = operator.line;
+                    self.emit_byte(OpCode::CopyTop as u8);
+                    self.emit_constant_op(OpCode::GetProperty, constant);
+                    self.expression(value);
+                    self.line = *line;
+                    self.emit_binary_op(operator.operator);
+                } else {
+                    self.expression(value);
+                    self.line = *line;
+                }
+                self.emit_constant_op(OpCode::SetProperty, constant);
+            }
+            Expr::Index {
+                object,
+                index,
+                end_line,
+            } => {
+                self.expression(object);
+                self.expression(index);
+                self.line = *end_line;
+                self.emit_byte(OpCode::GetItem as u8);
+            }
+            Expr::SetIndex {
+                object,
+                index,
+                operator,
+                value,
+                line,
+            } => {
+                self.expression(object);
+                self.expression(index);
+                if let Some(operator) = operator {
+                    self.line = operator.line;
+                    self.emit_byte(OpCode::CopyTopTwo as u8);
+                    self.emit_byte(OpCode::GetItem as u8);
+                    self.expression(value);
+                    self.line = *line;
+                    self.emit_binary_op(operator.operator);
+                } else {
+                    self.expression(value);
+                    self.line = *line;
+                }
+                self.emit_byte(OpCode::SetItem as u8);
+            }
+            Expr::Lambda { parameters, body } => self.lambda(parameters, body),
+            Expr::Comprehension(comprehension) => self.comprehension(comprehension),
+            Expr::Error => {}
+        }
+    }
+
+    fn call(&mut self, callee: &Expr, arguments: &[Argument], end_line: usize) {
+        // Calling a property directly is compiled to a single invocation, which avoids creating
+        // a bound method.
+        if let Expr::Get { object, name } = callee {
+            self.expression(object);
+            let name = self.identifier_constant(&name.name);
+            match self.arguments(arguments, end_line) {
+                Arguments::Fixed(arg_count) => {
+                    self.emit_constant_op(OpCode::Invoke, name);
+                    self.emit_byte(arg_count);
+                }
+                Arguments::Named(arg_count, names) => {
+                    self.emit_constant_op(OpCode::InvokeNamed, name);
+                    self.emit_byte(arg_count);
+                    self.emit_bytes(names.to_ne_bytes());
+                }
+                Arguments::Spread => self.emit_constant_op(OpCode::InvokeSpread, name),
+            }
+            return;
         }
 
-        s.consume(TokenKind::RightBrace, "Expected '}' after elements.");
-        s.emit_bytes([OpCode::BuildHashMap as u8, num_entries as u8]);
-    }
-
-    fn vector(s: &mut Parser, _can_assign: bool) {
-        let num_elems = s.argument_list(
-            TokenKind::RightBracket,
-            "Cannot have more than 255 Vec elements.",
-            "Expected ']' after elements.",
-        );
-
-        s.emit_bytes([OpCode::BuildVec as u8, num_elems as u8]);
+        self.expression(callee);
+        match self.arguments(arguments, end_line) {
+            Arguments::Fixed(arg_count) => self.emit_bytes([OpCode::Call as u8, arg_count]),
+            Arguments::Named(arg_count, names) => {
+                self.emit_bytes([OpCode::CallNamed as u8, arg_count]);
+                self.emit_bytes(names.to_ne_bytes());
+            }
+            Arguments::Spread => self.emit_byte(OpCode::CallSpread as u8),
+        }
     }
 
-    fn unary(s: &mut Parser, _can_assign: bool) {
-        let operator_kind = s.previous.kind;
-        s.parse_precedence(Precedence::Unary);
+    fn lambda(&mut self, parameters: &[Identifier], body: &LambdaBody) {
+        let lambda_count = self.compiler().lambda_count;
+        self.compiler_mut().lambda_count += 1;
+        let name = self
+            .vm
+            .new_gc_obj_string(format!("lambda-{}", lambda_count).as_str());
+        self.new_compiler(FunctionKind::Function, name, self.module_path);
+        self.body(|s| {
+            s.begin_scope();
 
-        match operator_kind {
-            TokenKind::Minus => s.emit_byte(OpCode::Negate as u8),
-            TokenKind::Bang => s.emit_byte(OpCode::LogicalNot as u8),
-            TokenKind::Tilde => s.emit_byte(OpCode::BitwiseNot as u8),
-            _ => {}
-        }
-    }
+            s.parameter_list(parameters);
 
-    fn number(s: &mut Parser, _can_assign: bool) {
-        let value = match s.previous.source.as_str().parse::<f64>() {
-            Ok(n) => n,
-            Err(_) => {
-                s.error("Unable to parse number.");
-                return;
+            match body {
+                LambdaBody::Block(block) => s.block(block),
+                LambdaBody::Expr { expr, end_line } => {
+                    s.expression(expr);
+                    s.line = *end_line;
+                    s.emit_byte(OpCode::Return as u8);
+                }
             }
-        };
-        s.emit_constant(value::Value::Number(value));
-    }
+        });
 
-    fn literal(s: &mut Parser, _can_assign: bool) {
-        match s.previous.kind {
-            TokenKind::False => {
-                s.emit_byte(OpCode::False as u8);
-            }
-            TokenKind::Nil => {
-                s.emit_byte(OpCode::Nil as u8);
-            }
-            TokenKind::True => {
-                s.emit_byte(OpCode::True as u8);
-            }
-            _ => {}
-        }
+        self.emit_closure();
     }
 
-    fn string(s: &mut Parser, _can_assign: bool) {
-        let value = Value::ObjString(s.vm.new_gc_obj_string(&s.previous.source));
-        s.emit_constant(value);
+    fn comprehension(&mut self, comprehension: &Comprehension) {
+        // A comprehension is compiled as a call to the function it's equivalent to, passing the
+        // iterable, which is evaluated before any of the elements.
+        let lambda_count = self.compiler().lambda_count;
+        self.compiler_mut().lambda_count += 1;
+        let name = Identifier::new(
+            &format!("comprehension-{}", lambda_count),
+            comprehension.variable.line,
+        );
+        let is_generator = comprehension.kind == ComprehensionKind::Generator;
+        self.function(
+            FunctionKind::Function,
+            &name,
+            &None,
+            is_generator,
+            &comprehension.function(),
+        );
+        self.expression(&comprehension.iterable);
+        self.line = comprehension.end_line;
+        self.emit_bytes([OpCode::Call as u8, 1]);
     }
 
-    fn interpolation(s: &mut Parser, _can_assign: bool) {
+    fn interpolation(&mut self, parts: &[ast::InterpolationPart], tail: &str, end_line: usize) {
         let mut arg_count = 0;
-        loop {
-            if !s.previous.source.is_empty() {
-                let value = Value::ObjString(s.vm.new_gc_obj_string(&s.previous.source));
-                s.emit_constant(value);
+        for part in parts {
+            if !part.text.is_empty() {
+                self.line = part.text_line;
+                let value = Value::ObjString(self.vm.new_gc_obj_string(&part.text));
+                self.emit_constant(value);
                 arg_count += 1;
             }
-            s.expression();
-            s.emit_byte(OpCode::FormatString as u8);
+            self.expression(&part.expr);
+            self.line = part.expr_end_line;
+            self.emit_byte(OpCode::FormatString as u8);
             arg_count += 1;
-            if !s.match_token(TokenKind::Interpolation) {
-                break;
-            }
         }
 
-        s.advance();
-        if !s.previous.source.is_empty() {
-            let value = Value::ObjString(s.vm.new_gc_obj_string(s.previous.source.as_str()));
-            s.emit_constant(value);
+        self.line = end_line;
+        if !tail.is_empty() {
+            let value = Value::ObjString(self.vm.new_gc_obj_string(tail));
+            self.emit_constant(value);
             arg_count += 1;
         }
 
-        s.emit_bytes([OpCode::BuildString as u8, arg_count as u8]);
+        self.emit_bytes([OpCode::BuildString as u8, arg_count as u8]);
     }
 
-    fn variable(s: &mut Parser, can_assign: bool) {
-        s.named_variable(s.previous.clone(), can_assign);
-    }
+    fn super_(&mut self, method: &Identifier, call: &Option<(Vec<Argument>, usize)>) {
+        let name = self.identifier_constant(&method.name);
 
-    fn self_(s: &mut Parser, _can_assign: bool) {
-        if s.class_compilers.is_empty() {
-            s.error("Cannot use 'self' outside of a class.");
-            return;
-        }
-        if s.compiler().kind == FunctionKind::StaticMethod {
-            s.error("Cannot use 'self' in a static method.");
-            return;
-        }
-        Parser::variable(s, false);
-    }
-
-    fn cap_self(s: &mut Parser, _can_assign: bool) {
-        if s.class_compilers.is_empty() {
-            s.error("Cannot use 'Self' outside of a class.");
-            return;
-        }
-        // TODO: Optimise this access to generate a single opcode
-        Parser::variable(s, false);
-        s.emit_byte(OpCode::GetClass as u8);
-    }
-
-    fn super_(s: &mut Parser, _can_assign: bool) {
-        if s.class_compilers.is_empty() {
-            s.error("Cannot use 'super' outside of a class.");
-        } else if !s.class_compilers.last().unwrap().has_superclass {
-            s.error("Cannot use 'super' in a class with no superclass.");
-        }
-
-        s.consume(TokenKind::Dot, "Expected '.' after 'super'.");
-        s.consume(TokenKind::Identifier, "Expected superclass method name.");
-        let previous = s.previous.clone();
-        let name = s.identifier_constant(&previous);
-
-        let instance_local_name = s.compiler().locals[0].name.clone();
-        s.named_variable(Token::from_string(instance_local_name.as_str()), false);
-        if s.match_token(TokenKind::LeftParen) {
-            let arg_count = s.argument_list(
-                TokenKind::RightParen,
-                "Cannot have more than 255 arguments.",
-                "Expected ')' after arguments.",
-            );
-            s.named_variable(Token::from_string("super"), false);
-            s.emit_constant_op(OpCode::SuperInvoke, name);
-            s.emit_byte(arg_count);
+        let instance_local_name = self.compiler().locals[0].name.clone();
+        self.line = method.line;
+        self.named_variable(&Identifier::new(&instance_local_name, method.line));
+        if let Some((arguments, end_line)) = call {
+            let arg_count = match self.arguments(arguments, *end_line) {
+                Arguments::Fixed(arg_count) => arg_count,
+                _ => 0,
+            };
+            self.named_variable(&Identifier::new("super", *end_line));
+            self.emit_constant_op(OpCode::SuperInvoke, name);
+            self.emit_byte(arg_count);
         } else {
-            s.named_variable(Token::from_string("super"), false);
-            s.emit_constant_op(OpCode::GetSuper, name);
+            self.named_variable(&Identifier::new("super", method.line));
+            self.emit_constant_op(OpCode::GetSuper, name);
         }
     }
-
-    fn and(s: &mut Parser, _can_assign: bool) {
-        let end_jump = s.emit_jump(OpCode::JumpIfFalse);
-
-        s.emit_byte(OpCode::Pop as u8);
-        s.parse_precedence(Precedence::And);
-
-        s.patch_jump(end_jump);
-    }
-
-    fn or(s: &mut Parser, _can_assign: bool) {
-        let else_jump = s.emit_jump(OpCode::JumpIfFalse);
-        let end_jump = s.emit_jump(OpCode::Jump);
-
-        s.patch_jump(else_jump);
-        s.emit_byte(OpCode::Pop as u8);
-
-        s.parse_precedence(Precedence::Or);
-        s.patch_jump(end_jump);
-    }
 }
 
-const RULES: [ParseRule; 72] = [
-    // LeftParen
-    ParseRule {
-        prefix: Some(Parser::grouping),
-        infix: Some(Parser::call),
-        precedence: Precedence::Call,
-    },
-    // RightParen
-    ParseRule {
-        prefix: None,
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // LeftBrace
-    ParseRule {
-        prefix: Some(Parser::hash_map),
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // RightBrace
-    ParseRule {
-        prefix: None,
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // LeftBracket
-    ParseRule {
-        prefix: Some(Parser::vector),
-        infix: Some(Parser::index),
-        precedence: Precedence::Call,
-    },
-    // RightBracket
-    ParseRule {
-        prefix: None,
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // Comma
-    ParseRule {
-        prefix: None,
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // Dot
-    ParseRule {
-        prefix: None,
-        infix: Some(Parser::dot),
-        precedence: Precedence::Call,
-    },
-    // DotDot
-    ParseRule {
-        prefix: None,
-        infix: Some(Parser::dotdot),
-        precedence: Precedence::Range,
-    },
-    // Minus
-    ParseRule {
-        prefix: Some(Parser::unary),
-        infix: Some(Parser::binary),
-        precedence: Precedence::Term,
-    },
-    // MinusEqual
-    ParseRule {
-        prefix: None,
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // Plus
-    ParseRule {
-        prefix: None,
-        infix: Some(Parser::binary),
-        precedence: Precedence::Term,
-    },
-    // PlusEqual
-    ParseRule {
-        prefix: None,
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // Colon
-    ParseRule {
-        prefix: None,
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // SemiColon
-    ParseRule {
-        prefix: None,
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // Slash
-    ParseRule {
-        prefix: None,
-        infix: Some(Parser::binary),
-        precedence: Precedence::Factor,
-    },
-    // SlashEqual
-    ParseRule {
-        prefix: None,
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // Star
-    ParseRule {
-        prefix: None,
-        infix: Some(Parser::binary),
-        precedence: Precedence::Factor,
-    },
-    // StarEqual
-    ParseRule {
-        prefix: None,
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // Bang
-    ParseRule {
-        prefix: Some(Parser::unary),
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // BangEqual
-    ParseRule {
-        prefix: None,
-        infix: Some(Parser::binary),
-        precedence: Precedence::Equality,
-    },
-    // Equal
-    ParseRule {
-        prefix: None,
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // EqualEqual
-    ParseRule {
-        prefix: None,
-        infix: Some(Parser::binary),
-        precedence: Precedence::Equality,
-    },
-    // Greater
-    ParseRule {
-        prefix: None,
-        infix: Some(Parser::binary),
-        precedence: Precedence::Comparison,
-    },
-    // GreaterEqual
-    ParseRule {
-        prefix: None,
-        infix: Some(Parser::binary),
-        precedence: Precedence::Comparison,
-    },
-    // Less
-    ParseRule {
-        prefix: None,
-        infix: Some(Parser::binary),
-        precedence: Precedence::Comparison,
-    },
-    // LessEqual
-    ParseRule {
-        prefix: None,
-        infix: Some(Parser::binary),
-        precedence: Precedence::Comparison,
-    },
-    // Amp
-    ParseRule {
-        prefix: None,
-        infix: Some(Parser::binary),
-        precedence: Precedence::BitwiseAnd,
-    },
-    // AmpEqual
-    ParseRule {
-        prefix: None,
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // Bar
-    ParseRule {
-        prefix: Some(Parser::lambda),
-        infix: Some(Parser::binary),
-        precedence: Precedence::BitwiseOr,
-    },
-    // BarEqual
-    ParseRule {
-        prefix: None,
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // Caret
-    ParseRule {
-        prefix: None,
-        infix: Some(Parser::binary),
-        precedence: Precedence::BitwiseXor,
-    },
-    // CaretEqual
-    ParseRule {
-        prefix: None,
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // Percent
-    ParseRule {
-        prefix: None,
-        infix: Some(Parser::binary),
-        precedence: Precedence::Factor,
-    },
-    // PercentEqual
-    ParseRule {
-        prefix: None,
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // GreaterGreater
-    ParseRule {
-        prefix: None,
-        infix: Some(Parser::binary),
-        precedence: Precedence::BitShift,
-    },
-    // GreaterGreaterEqual
-    ParseRule {
-        prefix: None,
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // LessLess
-    ParseRule {
-        prefix: None,
-        infix: Some(Parser::binary),
-        precedence: Precedence::BitShift,
-    },
-    // LessLessEqual
-    ParseRule {
-        prefix: None,
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // AmpAmp
-    ParseRule {
-        prefix: None,
-        infix: Some(Parser::and),
-        precedence: Precedence::And,
-    },
-    // BarBar
-    ParseRule {
-        prefix: Some(Parser::lambda),
-        infix: Some(Parser::or),
-        precedence: Precedence::Or,
-    },
-    // Tilde
-    ParseRule {
-        prefix: Some(Parser::unary),
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // Hash
-    ParseRule {
-        prefix: None,
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // Identifier
-    ParseRule {
-        prefix: Some(Parser::variable),
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // Str
-    ParseRule {
-        prefix: Some(Parser::string),
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // Interpolation
-    ParseRule {
-        prefix: Some(Parser::interpolation),
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // Number
-    ParseRule {
-        prefix: Some(Parser::number),
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // CapSelf
-    ParseRule {
-        prefix: Some(Parser::cap_self),
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // Catch
-    ParseRule {
-        prefix: None,
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // Class
-    ParseRule {
-        prefix: None,
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // Else
-    ParseRule {
-        prefix: None,
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // False
-    ParseRule {
-        prefix: Some(Parser::literal),
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // Finally
-    ParseRule {
-        prefix: None,
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // For
-    ParseRule {
-        prefix: None,
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // Fn
-    ParseRule {
-        prefix: None,
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // If
-    ParseRule {
-        prefix: None,
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // Import
-    ParseRule {
-        prefix: None,
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // As
-    ParseRule {
-        prefix: None,
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // In
-    ParseRule {
-        prefix: None,
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // Nil
-    ParseRule {
-        prefix: Some(Parser::literal),
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // Return
-    ParseRule {
-        prefix: None,
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // Self
-    ParseRule {
-        prefix: Some(Parser::self_),
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // Super
-    ParseRule {
-        prefix: Some(Parser::super_),
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // Break
-    ParseRule {
-        prefix: None,
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // Continue
-    ParseRule {
-        prefix: None,
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // Throw
-    ParseRule {
-        prefix: None,
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // True
-    ParseRule {
-        prefix: Some(Parser::literal),
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // Try
-    ParseRule {
-        prefix: None,
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // Var
-    ParseRule {
-        prefix: None,
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // While
-    ParseRule {
-        prefix: None,
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // Error
-    ParseRule {
-        prefix: None,
-        infix: None,
-        precedence: Precedence::None,
-    },
-    // Eof
-    ParseRule {
-        prefix: None,
-        infix: None,
-        precedence: Precedence::None,
-    },
-];
-
 #[test]
 fn test_make_constant() {
     let mut vm = Vm::new();
-    let mut scanner = Scanner::from_source("".to_string());
-    let mut parser = Parser::new(&mut vm, &mut scanner, None);
+    let mut generator = CodeGenerator::new(&mut vm, None, "");
 
     for i in 0..u16::MAX {
-        let index = parser.make_constant(Value::Number(i as f64));
+        let index = generator.make_constant(Value::Number(i as f64));
         assert_eq!(i, index);
     }
 
-    let index = parser.make_constant(Value::Number((u16::MAX) as f64));
+    let index = generator.make_constant(Value::Number((u16::MAX) as f64));
     assert_eq!(u16::MAX, index);
-    assert!(parser.errors.borrow().is_empty());
+    assert!(generator.errors.messages().is_empty());
 
-    let index = parser.make_constant(Value::Number((u16::MAX) as f64 + 1.0));
+    let index = generator.make_constant(Value::Number((u16::MAX) as f64 + 1.0));
     assert_eq!(0, index);
-    let borrowed_errors = parser.errors.borrow();
-    assert!(!borrowed_errors.is_empty());
+    assert_eq!(
+        vec!["[module \"main\", line 0] Error: Too many constants in one chunk.".to_string()],
+        *generator.errors.messages()
+    )
+}
+
+#[test]
+fn test_constants_are_deduplicated() {
+    let mut vm = Vm::with_built_ins();
+    let source = "var s = \"abc\";\nvar n = s.len() + 1.5;\n".repeat(100);
+    let function = compile(&mut vm, source, None).unwrap();
+    let constants: Vec<_> = function
+        .chunk
+        .constants
+        .iter()
+        .map(|c| format!("{}", c))
+        .collect();
+    assert_eq!(vec!["abc", "len", "1.5"], constants);
+}
+
+#[test]
+fn test_error_spans() {
+    let mut vm = Vm::new();
+    let source = "var a = ;\n{\n  var b;\n  var b;\n}\n";
+    let error = compile(&mut vm, source.to_string(), None).unwrap_err();
+
+    assert_eq!(2, error.messages().len());
+    let span = error.span(0).unwrap();
+    assert_eq!((1, 9, 1), (span.line, span.column, span.length));
+    let span = error.span(1).unwrap();
+    assert_eq!((4, 7, 1), (span.line, span.column, span.length));
+    assert_eq!("  var b;", span.source_line);
+}
+
+#[test]
+fn test_analyze() {
+    use crate::diagnostic::Severity;
+
+    let source = "var a = ;\n{\n  var b = b;\n  var c;\n}\n";
+    let diagnostics: Vec<_> = analyze(source)
+        .into_iter()
+        .map(|d| (d.severity, d.code, d.line, d.span.map(|s| s.column)))
+        .collect();
     assert_eq!(
         vec![
-            "[module \"main\", line 0] Error at end: Too many constants in one chunk.".to_string()
+            (Severity::Error, "syntax-error", 1, Some(9)),
+            (Severity::Error, "uninitialised-variable", 3, Some(11)),
+            (Severity::Warning, "unused-variable", 3, Some(7)),
+            (Severity::Warning, "unused-variable", 4, Some(7)),
         ],
-        *borrowed_errors
-    )
+        diagnostics
+    );
+}
+
+#[test]
+fn test_symbols() {
+    use crate::symbol::SymbolKind;
+
+    let source = "#[constructor(new)]\nclass A {\n  fn f(self) {}\n  #[static]\n  fn g() {}\n  #[static]\n  var h;\n}\nvar b;\n";
+    let symbols = symbols(source);
+    assert_eq!(2, symbols.len());
+    assert_eq!(
+        ("A", SymbolKind::Class, 2),
+        (symbols[0].name.as_str(), symbols[0].kind, symbols[0].line)
+    );
+    let members: Vec<_> = symbols[0]
+        .members
+        .iter()
+        .map(|m| (m.name.as_str(), m.kind))
+        .collect();
+    assert_eq!(
+        vec![
+            ("new", SymbolKind::StaticMethod),
+            ("h", SymbolKind::StaticField),
+            ("f", SymbolKind::Method),
+            ("g", SymbolKind::StaticMethod)
+        ],
+        members
+    );
+    assert_eq!(Some(5), symbols[1].span.as_ref().map(|s| s.column));
 }
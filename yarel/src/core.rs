@@ -13,14 +13,30 @@
  * limitations under the License.
  */
 
-use std::char;
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use core::cell::RefCell;
+use core::char;
+use core::fmt::Write;
+#[cfg(feature = "std")]
+use std::env;
+#[cfg(feature = "std")]
+use std::process;
+#[cfg(feature = "system_clock")]
 use std::time;
 
 use crate::common;
-use crate::error::{Error, ErrorKind};
-use crate::memory::{Gc, Root};
-use crate::object::{self, NativeFn, ObjClass, ObjNative, ObjStringValueMap};
+use crate::error::{Error, ErrorCode, ErrorKind};
+use crate::hash::HashMap;
+use crate::memory::{self, Gc, Root};
+use crate::object::{
+    self, IdentityKey, NativeFn, ObjClass, ObjFunction, ObjNative, ObjString, ObjStringValueMap,
+    ObjVec,
+};
 use crate::utils;
+#[cfg(not(feature = "std"))]
+use crate::utils::FloatExt;
 use crate::value::Value;
 use crate::vm::Vm;
 
@@ -33,7 +49,8 @@ fn check_num_args(num_args: usize, expected: usize) -> Result<(), Error> {
             expected,
             if expected == 1 { "" } else { "s" },
             num_args
-        ));
+        )
+        .with_code(ErrorCode::ArityMismatch));
     }
     Ok(())
 }
@@ -58,6 +75,7 @@ fn build_methods(
 
 /// Global functions
 
+#[cfg(feature = "system_clock")]
 pub(crate) fn clock(_vm: &mut Vm, _num_args: usize) -> Result<Value, Error> {
     let duration = match time::SystemTime::now().duration_since(time::SystemTime::UNIX_EPOCH) {
         Ok(value) => value,
@@ -73,9 +91,39 @@ pub(crate) fn clock(_vm: &mut Vm, _num_args: usize) -> Result<Value, Error> {
     Ok(Value::Number(seconds + nanos))
 }
 
+#[cfg(not(feature = "system_clock"))]
+pub(crate) fn clock(_vm: &mut Vm, _num_args: usize) -> Result<Value, Error> {
+    Err(error!(ErrorKind::RuntimeError, "No clock is available."))
+}
+
+#[cfg(feature = "stdio")]
 pub(crate) fn print(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
     check_num_args(num_args, 1)?;
-    println!("{}", vm.peek(0));
+    println!("{}", vm.display_string(vm.peek(0))?);
+    Ok(Value::None)
+}
+
+#[cfg(not(feature = "stdio"))]
+pub(crate) fn print(_vm: &mut Vm, _num_args: usize) -> Result<Value, Error> {
+    Err(error!(ErrorKind::RuntimeError, "No printer has been set."))
+}
+
+pub(crate) fn capture_print(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 1)?;
+    let text = format!("{}\n", vm.display_string(vm.peek(0))?);
+    vm.capture(&text);
+    Ok(Value::None)
+}
+
+pub(crate) fn eprint(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 1)?;
+    let text = format!("{}\n", vm.display_string(vm.peek(0))?);
+    vm.write_error_output(&text).map_err(|e| {
+        error!(
+            ErrorKind::RuntimeError,
+            "Unable to write to error output: {}", e
+        )
+    })?;
     Ok(Value::None)
 }
 
@@ -85,17 +133,347 @@ pub(crate) fn type_(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
     Ok(Value::ObjClass(vm.get_class(vm.peek(0))))
 }
 
+pub(crate) fn module(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 1)?;
+
+    let path = string_arg(vm, 0)?;
+    let module = vm.import_module(path.as_str())?;
+    Ok(Value::ObjModule(module))
+}
+
+pub(crate) fn sorted(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    let (iterable, key) = iterable_and_key(vm, num_args)?;
+    let values = vm.collect_iterable(iterable)?;
+    let indices = vm.sorted_indices(values.as_gc(), key)?;
+    reorder_elements(values.as_gc(), &indices);
+    Ok(Value::ObjVec(values.as_gc()))
+}
+
+pub(crate) fn min(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    extremum(vm, num_args, "minimum", false)
+}
+
+pub(crate) fn max(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    extremum(vm, num_args, "maximum", true)
+}
+
+fn extremum(vm: &mut Vm, num_args: usize, name: &str, largest: bool) -> Result<Value, Error> {
+    let (iterable, key) = iterable_and_key(vm, num_args)?;
+    let values = vm.collect_iterable(iterable)?;
+    if values.borrow().elements.is_empty() {
+        return Err(error!(
+            ErrorKind::ValueError,
+            "Cannot take the {} of an empty iterable.", name
+        ));
+    }
+    let keys = match key {
+        Some(key) => {
+            let keys = vm.new_root_obj_vec();
+            let len = values.borrow().elements.len();
+            for i in 0..len {
+                let value = values.borrow().elements[i];
+                let key = vm.call_value_reentrant(key, &[value])?;
+                keys.borrow_mut().elements.push(key);
+            }
+            keys
+        }
+        None => values.clone(),
+    };
+    let mut best = 0;
+    let len = keys.borrow().elements.len();
+    for i in 1..len {
+        let (current, candidate) = {
+            let keys = keys.borrow();
+            (keys.elements[best], keys.elements[i])
+        };
+        let replace = if largest {
+            vm.less_than(current, candidate)?
+        } else {
+            vm.less_than(candidate, current)?
+        };
+        if replace {
+            best = i;
+        }
+    }
+    let value = values.borrow().elements[best];
+    Ok(value)
+}
+
+fn iterable_and_key(vm: &mut Vm, num_args: usize) -> Result<(Value, Option<Value>), Error> {
+    if num_args == 0 || num_args > 2 {
+        return Err(error!(
+            ErrorKind::TypeError,
+            "Expected 1 or 2 parameters but found {}.", num_args
+        )
+        .with_code(ErrorCode::ArityMismatch));
+    }
+    let key = if num_args == 2 {
+        Some(vm.peek(0))
+    } else {
+        None
+    };
+    Ok((vm.peek(num_args - 1), key))
+}
+
+fn reorder_elements(vec: Gc<RefCell<ObjVec>>, indices: &[usize]) {
+    let mut borrowed_vec = vec.borrow_mut();
+    let elements = indices.iter().map(|&i| borrowed_vec.elements[i]).collect();
+    borrowed_vec.elements = elements;
+}
+
+pub(crate) fn process_exit(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 1)?;
+
+    let code = match vm.peek(0) {
+        Value::Number(n) if n.fract() == 0.0 => n,
+        value => {
+            return Err(error!(
+                ErrorKind::TypeError,
+                "Expected an integer exit code but found '{}'.", value
+            ));
+        }
+    };
+    // Operating systems only keep the lowest byte of the exit code, so other codes would be
+    // silently truncated.
+    if !(0.0..=255.0).contains(&code) {
+        return Err(error!(
+            ErrorKind::ValueError,
+            "Exit code {} is out of range 0 to 255.", code
+        ));
+    }
+    Err(vm.exit(code as i32))
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn process_pid(_vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 0)?;
+
+    Ok(Value::Number(process::id() as f64))
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn os_run(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 2)?;
+
+    let command = string_arg(vm, 1)?;
+    let args = vm.peek(0).try_as_obj_vec().ok_or_else(|| {
+        error!(
+            ErrorKind::TypeError,
+            "Expected a Vec but found '{}'.",
+            vm.peek(0)
+        )
+    })?;
+    let args = args
+        .borrow()
+        .elements
+        .iter()
+        .map(|arg| match arg {
+            Value::ObjString(arg) => Ok(arg.as_str().to_owned()),
+            _ => Err(error!(
+                ErrorKind::TypeError,
+                "Expected a string but found '{}'.", arg
+            )),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let output = process::Command::new(command.as_str())
+        .args(&args)
+        .output()
+        .map_err(|e| {
+            error!(
+                ErrorKind::RuntimeError,
+                "Unable to run '{}': {}",
+                command.as_str(),
+                e
+            )
+        })?;
+    // The status is nil if the process was terminated by a signal.
+    let status = output
+        .status
+        .code()
+        .map_or(Value::None, |code| Value::Number(code as f64));
+    let stdout = vm.new_root_obj_string(&String::from_utf8_lossy(&output.stdout));
+    let stderr = vm.new_root_obj_string(&String::from_utf8_lossy(&output.stderr));
+    let result = vm.new_root_obj_tuple(vec![
+        status,
+        Value::ObjString(stdout.as_gc()),
+        Value::ObjString(stderr.as_gc()),
+    ]);
+    Ok(Value::ObjTuple(result.as_gc()))
+}
+
+pub(crate) fn gc_collect(_vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 0)?;
+
+    memory::collect();
+    Ok(Value::None)
+}
+
+fn string_arg(vm: &Vm, depth: usize) -> Result<Gc<ObjString>, Error> {
+    vm.peek(depth).try_as_obj_string().ok_or_else(|| {
+        error!(
+            ErrorKind::TypeError,
+            "Expected a string but found '{}'.",
+            vm.peek(depth)
+        )
+    })
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn env_get(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 1)?;
+
+    let name = string_arg(vm, 0)?;
+    match env::var(name.as_str()) {
+        Ok(value) => Ok(Value::ObjString(vm.new_root_obj_string(&value).as_gc())),
+        Err(env::VarError::NotPresent) => Ok(Value::None),
+        Err(env::VarError::NotUnicode(_)) => Err(error!(
+            ErrorKind::ValueError,
+            "Value of environment variable '{}' is not valid Unicode.",
+            name.as_str()
+        )),
+    }
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn env_set(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 2)?;
+
+    let name = string_arg(vm, 1)?;
+    let value = string_arg(vm, 0)?;
+    if name.is_empty() || name.contains('=') || name.contains('\0') {
+        return Err(error!(
+            ErrorKind::ValueError,
+            "Invalid environment variable name '{}'.",
+            name.as_str()
+        ));
+    }
+    if value.contains('\0') {
+        return Err(error!(
+            ErrorKind::ValueError,
+            "Environment variable values cannot contain null characters."
+        ));
+    }
+    env::set_var(name.as_str(), value.as_str());
+    Ok(Value::None)
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn env_set_denied(_vm: &mut Vm, _num_args: usize) -> Result<Value, Error> {
+    Err(error!(
+        ErrorKind::RuntimeError,
+        "Setting environment variables is not permitted."
+    ))
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn env_vars(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 0)?;
+
+    let hash_map = vm.new_root_obj_hash_map();
+    // Variables whose names or values aren't valid Unicode are skipped.
+    for (name, value) in env::vars_os() {
+        if let (Some(name), Some(value)) = (name.to_str(), value.to_str()) {
+            let name = vm.new_root_obj_string(name);
+            let value = vm.new_root_obj_string(value);
+            hash_map.borrow_mut().elements.insert(
+                Value::ObjString(name.as_gc()),
+                Value::ObjString(value.as_gc()),
+            );
+        }
+    }
+    Ok(Value::ObjHashMap(hash_map.as_gc()))
+}
+
 /// Type implementation
 
-pub(crate) unsafe fn bind_type_class(_vm: &mut Vm, class: &mut Root<ObjClass>) {
-    let methods = class
+pub(crate) unsafe fn bind_type_class(vm: &mut Vm, class: &mut Root<ObjClass>) {
+    let inherited_methods = class
         .superclass
         .expect("Expected ObjClass.")
         .methods
         .clone();
+    let method_map = [
+        ("methods", type_methods as NativeFn),
+        ("name", type_name as NativeFn),
+        ("static_methods", type_static_methods as NativeFn),
+        ("superclass", type_superclass as NativeFn),
+    ];
+    let (methods, _native_roots) = build_methods(vm, &method_map, Some(inherited_methods));
     class.as_mut().methods = methods;
 }
 
+/// Lists the names of the methods that instances of the class respond to, including those
+/// inherited from its superclasses. Static methods and constructors are listed by
+/// `static_methods` instead. Private methods are only listed for callers that could call them.
+fn type_methods(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 0)?;
+
+    let class = vm.peek(0).try_as_obj_class().expect("Expected ObjClass.");
+    let can_access_private = vm.can_access_private(vm.peek(0));
+    // Static methods are also stored with the instance methods so that instances can call them.
+    // The method that initialises declared fields is an implementation detail, so isn't listed.
+    let names = class
+        .methods
+        .iter()
+        .filter(|&(name, method)| {
+            !is_static_method(class, name, method)
+                && name.as_str() != common::FIELD_INITIALISER_NAME
+                && (can_access_private || !object::is_private_name(name.as_str()))
+        })
+        .map(|(&name, _)| name);
+    Ok(sorted_names(vm, names))
+}
+
+/// Lists the names of the static methods and constructors defined by the class or inherited from
+/// its superclasses.
+fn type_static_methods(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 0)?;
+
+    let class = vm.peek(0).try_as_obj_class().expect("Expected ObjClass.");
+    let can_access_private = vm.can_access_private(vm.peek(0));
+    let names = class
+        .metaclass
+        .methods
+        .iter()
+        .filter(|&(name, method)| {
+            is_static_method(class, name, method)
+                && (can_access_private || !object::is_private_name(name.as_str()))
+        })
+        .map(|(&name, _)| name);
+    Ok(sorted_names(vm, names))
+}
+
+/// Returns whether the method is one of the class's static methods, as opposed to one inherited
+/// by its metaclass from the base metaclass, which every class responds to.
+fn is_static_method(class: Gc<ObjClass>, name: &Gc<ObjString>, method: &Value) -> bool {
+    let base_metaclass = class.metaclass.metaclass;
+    class.metaclass.methods.get(name) == Some(method)
+        && base_metaclass.methods.get(name) != Some(method)
+}
+
+fn sorted_names(vm: &mut Vm, names: impl Iterator<Item = Gc<ObjString>>) -> Value {
+    let mut names: Vec<_> = names.collect();
+    names.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+    let vec = vm.new_root_obj_vec();
+    vec.borrow_mut().elements = names.into_iter().map(Value::ObjString).collect();
+    Value::ObjVec(vec.as_gc())
+}
+
+fn type_name(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 0)?;
+
+    let class = vm.peek(0).try_as_obj_class().expect("Expected ObjClass.");
+    Ok(Value::ObjString(class.name))
+}
+
+fn type_superclass(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 0)?;
+
+    let class = vm.peek(0).try_as_obj_class().expect("Expected ObjClass.");
+    Ok(class.superclass.map_or(Value::None, Value::ObjClass))
+}
+
 pub(crate) unsafe fn new_base_metaclass() -> Root<ObjClass> {
     // # Safety
     // The root metaclass is its own metaclass, so we need to add a pointer to the metaclass to the
@@ -107,6 +485,7 @@ pub(crate) unsafe fn new_base_metaclass() -> Root<ObjClass> {
         metaclass: Gc::dangling(),
         superclass: None,
         methods: object::new_obj_string_value_map(),
+        fields: RefCell::new(object::new_obj_string_value_map()),
     };
     let mut root = Root::new(data);
     let metaclass = root.as_gc();
@@ -128,23 +507,769 @@ pub(crate) fn object_derives(vm: &mut Vm, num_args: usize) -> Result<Value, Erro
         )
     })?;
 
-    if receiver_class == query_class {
-        return Ok(Value::Boolean(true));
+    Ok(Value::Boolean(object::class_derives(
+        receiver_class,
+        query_class,
+    )))
+}
+
+pub(crate) fn object_conforms_to(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 1)?;
+
+    let receiver_class = vm.get_class(vm.peek(1));
+    let protocol = vm.peek(0).try_as_obj_class().ok_or_else(|| {
+        error!(
+            ErrorKind::ValueError,
+            "Expected a class name but found '{}'.",
+            vm.peek(0)
+        )
+    })?;
+
+    let missing = missing_protocol_methods(receiver_class, protocol);
+    Ok(Value::Boolean(missing.is_empty()))
+}
+
+pub(crate) fn missing_protocol_methods(
+    class: Gc<ObjClass>,
+    protocol: Gc<ObjClass>,
+) -> Vec<Gc<ObjString>> {
+    // Methods inherited from the root of the protocol's class hierarchy (i.e. Object) are
+    // available on every class, so they aren't considered part of the protocol. Nor are methods
+    // with a body, which are default implementations for classes deriving from the protocol.
+    let mut base = protocol;
+    while let Some(parent) = base.superclass {
+        base = parent;
+    }
+    let is_required = |method: &Value| match method {
+        Value::ObjClosure(closure) => closure.function.is_stub,
+        _ => true,
+    };
+    let mut missing: Vec<_> = protocol
+        .methods
+        .iter()
+        .filter(|&(name, method)| {
+            is_required(method)
+                && !base.methods.contains_key(name)
+                && !class.methods.contains_key(name)
+        })
+        .map(|(name, _)| *name)
+        .collect();
+    missing.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+    missing
+}
+
+fn object_fields(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 0)?;
+
+    let hash_map = vm.new_root_obj_hash_map();
+    let fields: Vec<_> = match vm.peek(0) {
+        Value::ObjInstance(instance) => instance
+            .borrow()
+            .fields
+            .iter()
+            .map(|(&k, &v)| (k, v))
+            .collect(),
+        Value::ObjClass(class) => class
+            .fields
+            .borrow()
+            .iter()
+            .map(|(&k, &v)| (k, v))
+            .collect(),
+        _ => Vec::new(),
+    };
+    // Private fields are only listed for callers that could access them directly.
+    let can_access_private = vm.can_access_private(vm.peek(0));
+    let fields = fields
+        .into_iter()
+        .filter(|(name, _)| can_access_private || !object::is_private_name(name.as_str()));
+    for (name, value) in fields {
+        hash_map
+            .borrow_mut()
+            .elements
+            .insert(Value::ObjString(name), value);
+    }
+    Ok(Value::ObjHashMap(hash_map.as_gc()))
+}
+
+fn object_has_field(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 1)?;
+
+    let name = string_arg(vm, 0)?;
+    let has_field = match vm.peek(1) {
+        Value::ObjInstance(instance) => instance
+            .borrow()
+            .fields
+            .keys()
+            .any(|field| field.as_str() == name.as_str()),
+        Value::ObjClass(class) => class
+            .fields
+            .borrow()
+            .keys()
+            .any(|field| field.as_str() == name.as_str()),
+        _ => false,
+    };
+    // As with fields, private fields are hidden from callers that couldn't access them directly.
+    let visible = vm.can_access_private(vm.peek(1)) || !object::is_private_name(name.as_str());
+    Ok(Value::Boolean(has_field && visible))
+}
+
+fn object_send(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    if num_args == 0 {
+        return Err(error!(
+            ErrorKind::TypeError,
+            "Expected at least 1 parameter but found 0."
+        ));
+    }
+
+    let name = string_arg(vm, num_args - 1)?;
+    let receiver = vm.peek(num_args);
+    let args: Vec<_> = (0..num_args - 1)
+        .rev()
+        .map(|depth| vm.peek(depth))
+        .collect();
+    vm.send(receiver, name, &args)
+}
+
+fn object_get(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 1)?;
+
+    let name = string_arg(vm, 0)?;
+    vm.get_property(vm.peek(1), name)
+}
+
+fn object_set(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 2)?;
+
+    let name = string_arg(vm, 1)?;
+    let value = vm.peek(0);
+    vm.set_property(vm.peek(2), name, value)?;
+    Ok(value)
+}
+
+fn object_has(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 1)?;
+
+    let name = string_arg(vm, 0)?;
+    vm.load_if_lazy(vm.peek(1))?;
+    Ok(Value::Boolean(vm.has_property(vm.peek(1), name)))
+}
+
+fn object_clone(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 0)?;
+
+    clone_value(vm, vm.peek(0))
+}
+
+fn object_deep_clone(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 0)?;
+
+    // The copies are kept in a Vec so they aren't collected before they're all connected.
+    let copies = vm.new_root_obj_vec();
+    let mut memo = HashMap::default();
+    deep_clone_value(vm, vm.peek(0), &mut memo, copies.as_gc())
+}
+
+/// Returns a copy of a Vec, HashMap, IdentityMap or instance that refers to the same elements or
+/// fields as the original. Immutable values are returned as they are.
+fn clone_value(vm: &mut Vm, value: Value) -> Result<Value, Error> {
+    match value {
+        Value::ObjVec(vec) => {
+            let copy = vm.new_root_obj_vec();
+            copy.borrow_mut().elements = vec.borrow().elements.clone();
+            Ok(Value::ObjVec(copy.as_gc()))
+        }
+        Value::ObjHashMap(hash_map) => {
+            let copy = vm.new_root_obj_hash_map();
+            {
+                let borrowed_hash_map = hash_map.borrow();
+                let mut borrowed_copy = copy.borrow_mut();
+                borrowed_copy.elements = borrowed_hash_map.elements.clone();
+                borrowed_copy.instance_keys = borrowed_hash_map.instance_keys.clone();
+            }
+            Ok(Value::ObjHashMap(copy.as_gc()))
+        }
+        Value::ObjIdentityMap(identity_map) => {
+            let copy = vm.new_root_obj_identity_map();
+            copy.borrow_mut().elements = identity_map.borrow().elements.clone();
+            Ok(Value::ObjIdentityMap(copy.as_gc()))
+        }
+        Value::ObjInstance(instance) => {
+            let class = instance.borrow().class;
+            let copy = vm.new_root_obj_instance(class);
+            copy.borrow_mut().fields = instance.borrow().fields.clone();
+            Ok(Value::ObjInstance(copy.as_gc()))
+        }
+        Value::ObjStringIter(_)
+        | Value::ObjTupleIter(_)
+        | Value::ObjVecIter(_)
+        | Value::ObjRangeIter(_)
+        | Value::ObjFiber(_) => Err(error!(
+            ErrorKind::TypeError,
+            "Cannot clone value '{}'.", value
+        )),
+        _ => Ok(value),
+    }
+}
+
+/// Returns a copy of a value along with everything it refers to, besides HashMap keys, classes
+/// and functions. Values referred to more than once, including through cycles, are copied once.
+fn deep_clone_value(
+    vm: &mut Vm,
+    value: Value,
+    memo: &mut HashMap<usize, Value>,
+    copies: Gc<RefCell<ObjVec>>,
+) -> Result<Value, Error> {
+    let address = match value.address() {
+        Some(address) => address,
+        None => return Ok(value),
+    };
+    if let Some(&copy) = memo.get(&address) {
+        return Ok(copy);
+    }
+
+    if let Value::ObjTuple(tuple) = value {
+        let elements = tuple
+            .elements
+            .iter()
+            .map(|&element| deep_clone_value(vm, element, memo, copies))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let copy = Value::ObjTuple(vm.new_root_obj_tuple(elements).as_gc());
+        copies.borrow_mut().elements.push(copy);
+        memo.insert(address, copy);
+        return Ok(copy);
     }
-    let mut superclass = receiver_class.superclass;
-    while let Some(parent) = superclass {
-        if parent == query_class {
-            return Ok(Value::Boolean(true));
+
+    let copy = clone_value(vm, value)?;
+    copies.borrow_mut().elements.push(copy);
+    memo.insert(address, copy);
+    match copy {
+        Value::ObjVec(vec) => {
+            let len = vec.borrow().elements.len();
+            for i in 0..len {
+                let element = vec.borrow().elements[i];
+                let element = deep_clone_value(vm, element, memo, copies)?;
+                vec.borrow_mut().elements[i] = element;
+            }
+        }
+        Value::ObjHashMap(hash_map) => {
+            let keys: Vec<_> = hash_map.borrow().elements.keys().copied().collect();
+            for key in keys {
+                let element = hash_map.borrow().elements[&key];
+                let element = deep_clone_value(vm, element, memo, copies)?;
+                hash_map.borrow_mut().elements.insert(key, element);
+            }
+        }
+        Value::ObjIdentityMap(identity_map) => {
+            let keys: Vec<_> = identity_map.borrow().elements.keys().copied().collect();
+            for key in keys {
+                let element = identity_map.borrow().elements[&key];
+                let element = deep_clone_value(vm, element, memo, copies)?;
+                identity_map.borrow_mut().elements.insert(key, element);
+            }
         }
-        superclass = parent.superclass;
+        Value::ObjInstance(instance) => {
+            let names: Vec<_> = instance.borrow().fields.keys().copied().collect();
+            for name in names {
+                let field = instance.borrow().fields[&name];
+                let field = deep_clone_value(vm, field, memo, copies)?;
+                instance.borrow_mut().fields.insert(name, field);
+            }
+        }
+        _ => {}
     }
-    Ok(Value::Boolean(false))
+    Ok(copy)
 }
 
 pub(crate) unsafe fn bind_object_class(vm: &mut Vm, class: &mut Root<ObjClass>) {
-    let method_map = [("derives", object_derives as NativeFn)];
+    let method_map = [
+        ("derives", object_derives as NativeFn),
+        ("conforms_to", object_conforms_to as NativeFn),
+        ("clone", object_clone as NativeFn),
+        ("deep_clone", object_deep_clone as NativeFn),
+        ("fields", object_fields as NativeFn),
+        ("has_field", object_has_field as NativeFn),
+        ("send", object_send as NativeFn),
+        ("get", object_get as NativeFn),
+        ("set", object_set as NativeFn),
+        ("has", object_has as NativeFn),
+    ];
+    let (methods, _native_roots) = build_methods(vm, &method_map, None);
+    class.as_mut().methods = methods;
+}
+
+/// Num implementation
+
+pub fn new_root_obj_num_class(
+    vm: &mut Vm,
+    metaclass: Gc<ObjClass>,
+    superclass: Gc<ObjClass>,
+) -> Root<ObjClass> {
+    let class_name = vm.new_gc_obj_string("Num");
+    let method_map = [
+        ("floor", num_floor as NativeFn),
+        ("ceil", num_ceil as NativeFn),
+        ("round", num_round as NativeFn),
+        ("abs", num_abs as NativeFn),
+        ("to_int", num_to_int as NativeFn),
+        ("is_nan", num_is_nan as NativeFn),
+        ("is_inf", num_is_inf as NativeFn),
+        ("to_string", num_to_string as NativeFn),
+        ("to_fixed", num_to_fixed as NativeFn),
+        ("to_exponential", num_to_exponential as NativeFn),
+        ("wrapping_add", num_wrapping_add as NativeFn),
+        ("wrapping_sub", num_wrapping_sub as NativeFn),
+        ("wrapping_mul", num_wrapping_mul as NativeFn),
+        ("wrapping_shl", num_wrapping_shl as NativeFn),
+        ("wrapping_shr", num_wrapping_shr as NativeFn),
+    ];
+    let (methods, _native_roots) = build_methods(vm, &method_map, None);
+    vm.new_root_obj_class(class_name, metaclass, Some(superclass), methods)
+}
+
+fn num_floor(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 0)?;
+
+    let num = vm.peek(0).try_as_number().expect("Expected number.");
+    Ok(Value::Number(num.floor()))
+}
+
+fn num_ceil(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 0)?;
+
+    let num = vm.peek(0).try_as_number().expect("Expected number.");
+    Ok(Value::Number(num.ceil()))
+}
+
+fn num_round(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 0)?;
+
+    let num = vm.peek(0).try_as_number().expect("Expected number.");
+    Ok(Value::Number(num.round()))
+}
+
+fn num_abs(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 0)?;
+
+    let num = vm.peek(0).try_as_number().expect("Expected number.");
+    Ok(Value::Number(num.abs()))
+}
+
+fn num_to_int(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 0)?;
+
+    let num = vm.peek(0).try_as_number().expect("Expected number.");
+    if !num.is_finite() {
+        return Err(error!(
+            ErrorKind::ValueError,
+            "Unable to convert '{}' to an integer.",
+            vm.peek(0)
+        ));
+    }
+    Ok(Value::Number(num.trunc()))
+}
+
+fn num_is_nan(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 0)?;
+
+    let num = vm.peek(0).try_as_number().expect("Expected number.");
+    Ok(Value::Boolean(num.is_nan()))
+}
+
+fn num_is_inf(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 0)?;
+
+    let num = vm.peek(0).try_as_number().expect("Expected number.");
+    Ok(Value::Boolean(num.is_infinite()))
+}
+
+fn num_to_string(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    if num_args > 1 {
+        return Err(error!(
+            ErrorKind::TypeError,
+            "Expected at most 1 parameter but found {}.", num_args
+        ));
+    }
+
+    let receiver = vm.peek(num_args);
+    let num = receiver.try_as_number().expect("Expected number.");
+    let string = if num_args == 0 {
+        format!("{}", receiver)
+    } else {
+        let base = match vm.peek(0).try_as_number() {
+            Some(base) if base.fract() == 0.0 && (2.0..=36.0).contains(&base) => base as u32,
+            _ => {
+                return Err(error!(
+                    ErrorKind::ValueError,
+                    "Expected an integer base between 2 and 36 but found '{}'.",
+                    vm.peek(0)
+                ));
+            }
+        };
+        // The fractional part of an infinity or NaN is NaN, so they're rejected too.
+        if num.fract() != 0.0 {
+            return Err(error!(
+                ErrorKind::ValueError,
+                "Unable to convert '{}' to base {} as it's not an integer.", receiver, base
+            ));
+        }
+        format_int_in_base(num, base)
+    };
+    let string = vm.new_root_obj_string(&string);
+
+    Ok(Value::ObjString(string.as_gc()))
+}
+
+fn num_to_fixed(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 1)?;
+
+    let num = vm.peek(1).try_as_number().expect("Expected number.");
+    let digits = get_num_digits(vm.peek(0))?;
+    let string = vm.new_root_obj_string(&format!("{:.*}", digits, num));
+
+    Ok(Value::ObjString(string.as_gc()))
+}
+
+fn num_to_exponential(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 1)?;
+
+    let num = vm.peek(1).try_as_number().expect("Expected number.");
+    let digits = get_num_digits(vm.peek(0))?;
+    let string = vm.new_root_obj_string(&format!("{:.*e}", digits, num));
+
+    Ok(Value::ObjString(string.as_gc()))
+}
+
+fn get_num_digits(value: Value) -> Result<usize, Error> {
+    match value.try_as_number() {
+        Some(digits) if digits.fract() == 0.0 && (0.0..=100.0).contains(&digits) => {
+            Ok(digits as usize)
+        }
+        _ => Err(error!(
+            ErrorKind::ValueError,
+            "Expected an integer number of digits between 0 and 100 but found '{}'.", value
+        )),
+    }
+}
+
+fn num_wrapping_add(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    num_wrapping_op(vm, num_args, i64::wrapping_add)
+}
+
+fn num_wrapping_sub(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    num_wrapping_op(vm, num_args, i64::wrapping_sub)
+}
+
+fn num_wrapping_mul(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    num_wrapping_op(vm, num_args, i64::wrapping_mul)
+}
+
+fn num_wrapping_shl(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    num_wrapping_op(vm, num_args, |a, b| a.wrapping_shl(b as u32))
+}
+
+fn num_wrapping_shr(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    num_wrapping_op(vm, num_args, |a, b| a.wrapping_shr(b as u32))
+}
+
+/// Applies an operation to the receiver and argument as 64-bit two's complement integers. Shift
+/// amounts are masked to the range 0 to 63.
+fn num_wrapping_op(vm: &mut Vm, num_args: usize, op: fn(i64, i64) -> i64) -> Result<Value, Error> {
+    check_num_args(num_args, 1)?;
+
+    if vm.peek(0).try_as_number().is_none() {
+        return Err(error!(
+            ErrorKind::TypeError,
+            "Expected a number but found '{}'.",
+            vm.peek(0)
+        ));
+    }
+    let first = get_i64(vm.peek(1))?;
+    let second = get_i64(vm.peek(0))?;
+
+    Ok(Value::Number(op(first, second) as f64))
+}
+
+fn get_i64(value: Value) -> Result<i64, Error> {
+    let num = value.try_as_number().expect("Expected number.");
+    utils::to_i64(num).ok_or_else(|| {
+        error!(
+            ErrorKind::ValueError,
+            "Expected a number within the 64-bit integer range but found '{}'.", value
+        )
+    })
+}
+
+/// Formats a whole number in the specified base. Numbers too large to be represented exactly by
+/// an integer type are converted using their exact binary value.
+fn format_int_in_base(num: f64, base: u32) -> String {
+    let mut limbs = magnitude_limbs(num);
+    let mut digits = Vec::new();
+    loop {
+        // Divides the magnitude by the base in place, starting from its most significant limb.
+        let mut remainder = 0;
+        for limb in limbs.iter_mut().rev() {
+            let value = (remainder << 32) | *limb as u64;
+            *limb = (value / base as u64) as u32;
+            remainder = value % base as u64;
+        }
+        digits.push(core::char::from_digit(remainder as u32, base).expect("Expected digit."));
+        while limbs.last() == Some(&0) {
+            limbs.pop();
+        }
+        if limbs.is_empty() {
+            break;
+        }
+    }
+    if num < 0.0 {
+        digits.push('-');
+    }
+    digits.iter().rev().collect()
+}
+
+/// Splits the magnitude of a whole number into 32-bit limbs, least significant first.
+fn magnitude_limbs(num: f64) -> Vec<u32> {
+    const MANTISSA_BITS: u32 = 52;
+    const EXPONENT_BIAS: u32 = 1023;
+
+    let bits = num.abs().to_bits();
+    let exponent = (bits >> MANTISSA_BITS) as u32;
+    if exponent < EXPONENT_BIAS + MANTISSA_BITS + 1 {
+        // Whole numbers below 2^53 are converted exactly.
+        let magnitude = num.abs() as u64;
+        return vec![magnitude as u32, (magnitude >> 32) as u32];
+    }
+    let mantissa = (bits & ((1 << MANTISSA_BITS) - 1)) | (1 << MANTISSA_BITS);
+    let shift = exponent - EXPONENT_BIAS - MANTISSA_BITS;
+    let mut limbs = vec![0; (shift / 32) as usize];
+    let mantissa = (mantissa as u128) << (shift % 32);
+    limbs.extend_from_slice(&[
+        mantissa as u32,
+        (mantissa >> 32) as u32,
+        (mantissa >> 64) as u32,
+    ]);
+    limbs
+}
+
+/// Func implementation
+
+pub fn new_root_obj_closure_class(
+    vm: &mut Vm,
+    metaclass: Gc<ObjClass>,
+    superclass: Gc<ObjClass>,
+) -> Root<ObjClass> {
+    let class_name = vm.new_gc_obj_string("Func");
+    let method_map = [
+        ("arity", function_arity as NativeFn),
+        ("doc", closure_doc as NativeFn),
+        ("name", function_name as NativeFn),
+        ("partial", function_partial as NativeFn),
+    ];
+    let (methods, _native_roots) = build_methods(vm, &method_map, None);
+    vm.new_root_obj_class(class_name, metaclass, Some(superclass), methods)
+}
+
+fn closure_doc(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 0)?;
+
+    let closure = vm
+        .peek(0)
+        .try_as_obj_closure()
+        .expect("Expected ObjClosure.");
+    Ok(function_doc(&closure.function))
+}
+
+fn function_doc(function: &ObjFunction) -> Value {
+    function.doc.map(Value::ObjString).unwrap_or(Value::None)
+}
+
+/// Returns the number of arguments the callable expects. Built-in functions check their
+/// arguments for themselves rather than declaring how many they expect, so a `TypeError` is
+/// raised for them instead.
+fn function_arity(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 0)?;
+
+    let callee = vm.peek(0);
+    match callable_arity(callee) {
+        Some(arity) => Ok(Value::Number(arity as f64)),
+        None => Err(error!(
+            ErrorKind::TypeError,
+            "Built-in function '{}' doesn't declare its arity.",
+            *callable_name(callee)
+        )),
+    }
+}
+
+fn function_name(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 0)?;
+
+    Ok(Value::ObjString(callable_name(vm.peek(0))))
+}
+
+fn function_partial(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    let (callee, mut args) = match vm.peek(num_args) {
+        Value::ObjPartial(partial) => (partial.callee, partial.args.clone()),
+        callee => (callee, Vec::new()),
+    };
+    args.extend((0..num_args).rev().map(|depth| vm.peek(depth)));
+    if let Some(arity) = callable_arity(callee) {
+        if args.len() > arity {
+            return Err(error!(
+                ErrorKind::TypeError,
+                "Expected at most {} arguments but found {}.",
+                arity,
+                args.len()
+            ));
+        }
+    }
+    if args.len() > 255 {
+        return Err(error!(
+            ErrorKind::TypeError,
+            "Cannot have more than 255 arguments but found {}.",
+            args.len()
+        ));
+    }
+    let partial = vm.new_root_obj_partial(callee, args);
+    Ok(Value::ObjPartial(partial.as_gc()))
+}
+
+/// Returns the number of arguments the callable expects, or nothing if built-in functions check
+/// their arguments for themselves.
+fn callable_arity(callee: Value) -> Option<usize> {
+    match callee {
+        Value::ObjClosure(closure) => Some(closure.function.arity - 1),
+        Value::ObjBoundMethod(bound) => Some(bound.borrow().method.function.arity - 1),
+        Value::ObjPartial(partial) => {
+            callable_arity(partial.callee).map(|arity| arity - partial.args.len())
+        }
+        _ => None,
+    }
+}
+
+fn callable_name(callee: Value) -> Gc<ObjString> {
+    match callee {
+        Value::ObjClosure(closure) => closure.function.name,
+        Value::ObjBoundMethod(bound) => bound.borrow().method.function.name,
+        Value::ObjNative(native) => native.name,
+        Value::ObjBoundNative(bound) => bound.borrow().method.name,
+        Value::ObjPartial(partial) => callable_name(partial.callee),
+        _ => unreachable!(),
+    }
+}
+
+/// Method implementation
+
+pub fn new_root_obj_closure_method_class(
+    vm: &mut Vm,
+    metaclass: Gc<ObjClass>,
+    superclass: Gc<ObjClass>,
+) -> Root<ObjClass> {
+    let class_name = vm.new_gc_obj_string("Method");
+    let method_map = [
+        ("arity", function_arity as NativeFn),
+        ("bind", closure_method_bind as NativeFn),
+        ("doc", closure_method_doc as NativeFn),
+        ("name", function_name as NativeFn),
+        ("partial", function_partial as NativeFn),
+    ];
+    let (methods, _native_roots) = build_methods(vm, &method_map, None);
+    vm.new_root_obj_class(class_name, metaclass, Some(superclass), methods)
+}
+
+fn closure_method_doc(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 0)?;
+
+    let bound = vm
+        .peek(0)
+        .try_as_obj_bound_method()
+        .expect("Expected ObjBoundMethod.");
+    let function = bound.borrow().method.function;
+    Ok(function_doc(&function))
+}
+
+fn closure_method_bind(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 1)?;
+
+    let bound = vm
+        .peek(1)
+        .try_as_obj_bound_method()
+        .expect("Expected ObjBoundMethod.");
+    let method = bound.borrow().method;
+    let rebound = vm.new_root_obj_bound_method(vm.peek(0), method);
+    Ok(Value::ObjBoundMethod(rebound.as_gc()))
+}
+
+/// BuiltIn implementation
+
+pub fn new_root_obj_native_class(
+    vm: &mut Vm,
+    metaclass: Gc<ObjClass>,
+    superclass: Gc<ObjClass>,
+) -> Root<ObjClass> {
+    let class_name = vm.new_gc_obj_string("BuiltIn");
+    let method_map = [
+        ("arity", function_arity as NativeFn),
+        ("name", function_name as NativeFn),
+        ("partial", function_partial as NativeFn),
+    ];
+    let (methods, _native_roots) = build_methods(vm, &method_map, None);
+    vm.new_root_obj_class(class_name, metaclass, Some(superclass), methods)
+}
+
+/// BuiltInMethod implementation
+
+pub fn new_root_obj_native_method_class(
+    vm: &mut Vm,
+    metaclass: Gc<ObjClass>,
+    superclass: Gc<ObjClass>,
+) -> Root<ObjClass> {
+    let class_name = vm.new_gc_obj_string("BuiltInMethod");
+    let method_map = [
+        ("arity", function_arity as NativeFn),
+        ("bind", native_method_bind as NativeFn),
+        ("name", function_name as NativeFn),
+        ("partial", function_partial as NativeFn),
+    ];
+    let (methods, _native_roots) = build_methods(vm, &method_map, None);
+    vm.new_root_obj_class(class_name, metaclass, Some(superclass), methods)
+}
+
+fn native_method_bind(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 1)?;
+
+    let bound = vm
+        .peek(1)
+        .try_as_obj_bound_native()
+        .expect("Expected ObjBoundNative.");
+    let method = bound.borrow().method;
+    let receiver = vm.peek(0);
+    // Built-in methods assume their receiver's type, so may only be bound to values whose class
+    // provides the same method.
+    let class = vm.get_class(receiver);
+    if class.methods.get(&method.name) != Some(&Value::ObjNative(method)) {
+        return Err(error!(
+            ErrorKind::TypeError,
+            "Cannot bind built-in method '{}' to '{}'.", *method.name, receiver
+        ));
+    }
+    let rebound = vm.new_root_obj_bound_method(receiver, method);
+    Ok(Value::ObjBoundNative(rebound.as_gc()))
+}
+
+/// Partial implementation
+
+pub fn new_root_obj_partial_class(
+    vm: &mut Vm,
+    metaclass: Gc<ObjClass>,
+    superclass: Gc<ObjClass>,
+) -> Root<ObjClass> {
+    let class_name = vm.new_gc_obj_string("Partial");
+    let method_map = [
+        ("arity", function_arity as NativeFn),
+        ("name", function_name as NativeFn),
+        ("partial", function_partial as NativeFn),
+    ];
     let (methods, _native_roots) = build_methods(vm, &method_map, None);
-    class.as_mut().methods = methods;
+    vm.new_root_obj_class(class_name, metaclass, Some(superclass), methods)
 }
 
 /// String implementation
@@ -160,7 +1285,13 @@ pub(crate) unsafe fn bind_gc_obj_string_class(
         ("from_utf8", string_from_utf8 as NativeFn),
         ("from_code_points", string_from_code_points as NativeFn),
     ];
-    let (static_methods, _native_roots) = build_methods(vm, &static_method_map, None);
+    let inherited_static_methods = metaclass
+        .superclass
+        .expect("Expected ObjClass.")
+        .methods
+        .clone();
+    let (static_methods, _native_roots) =
+        build_methods(vm, &static_method_map, Some(inherited_static_methods));
 
     metaclass.as_mut().methods = static_methods;
 
@@ -171,13 +1302,18 @@ pub(crate) unsafe fn bind_gc_obj_string_class(
         .clone();
     let method_map = [
         ("iter", string_iter as NativeFn),
+        ("chars", string_iter as NativeFn),
         ("len", string_len as NativeFn),
         ("is_alpha", string_is_alpha as NativeFn),
         ("is_digit", string_is_digit as NativeFn),
         ("is_hexdigit", string_is_hexdigit as NativeFn),
         ("count_chars", string_count_chars as NativeFn),
         ("char_byte_index", string_char_byte_index as NativeFn),
+        ("byte_at", string_byte_at as NativeFn),
+        ("codepoint_at", string_codepoint_at as NativeFn),
+        ("eq_ignore_case", string_eq_ignore_case as NativeFn),
         ("find", string_find as NativeFn),
+        ("join", string_join as NativeFn),
         ("replace", string_replace as NativeFn),
         ("split", string_split as NativeFn),
         ("starts_with", string_starts_with as NativeFn),
@@ -224,14 +1360,14 @@ fn string_from_ascii(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
         }
     }
 
-    let string = vm.new_gc_obj_string(&String::from_utf8(bytes).map_err(|_| {
+    let string = vm.new_root_obj_string(&String::from_utf8(bytes).map_err(|_| {
         Error::with_message(
             ErrorKind::ValueError,
             &format!("Unable to create a string from byte sequence."),
         )
     })?);
 
-    Ok(Value::ObjString(string))
+    Ok(Value::ObjString(string.as_gc()))
 }
 
 fn string_from_utf8(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
@@ -266,7 +1402,7 @@ fn string_from_utf8(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
         })
         .collect();
 
-    let string = vm.new_gc_obj_string(&String::from_utf8(bytes?).map_err(|e| {
+    let string = vm.new_root_obj_string(&String::from_utf8(bytes?).map_err(|e| {
         let index = e.utf8_error().valid_up_to();
         let byte = e.into_bytes()[index];
         Error::with_message(
@@ -278,7 +1414,7 @@ fn string_from_utf8(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
         )
     })?);
 
-    Ok(Value::ObjString(string))
+    Ok(Value::ObjString(string.as_gc()))
 }
 
 fn string_from_code_points(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
@@ -320,17 +1456,17 @@ fn string_from_code_points(vm: &mut Vm, num_args: usize) -> Result<Value, Error>
         })
         .collect();
 
-    let string = vm.new_gc_obj_string(&string?);
+    let string = vm.new_root_obj_string(&string?);
 
-    Ok(Value::ObjString(string))
+    Ok(Value::ObjString(string.as_gc()))
 }
 
 fn string_from(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
     check_num_args(num_args, 1)?;
 
-    Ok(Value::ObjString(
-        vm.new_gc_obj_string(format!("{}", vm.peek(0)).as_str()),
-    ))
+    let text = vm.display_string(vm.peek(0))?;
+    let string = vm.new_root_obj_string(text.as_str());
+    Ok(Value::ObjString(string.as_gc()))
 }
 
 fn string_iter(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
@@ -386,10 +1522,9 @@ fn string_char_byte_index(vm: &mut Vm, num_args: usize) -> Result<Value, Error>
     check_num_args(num_args, 1)?;
 
     let string = vm.peek(1).try_as_obj_string().expect("Expected ObjString.");
-    let char_index = vm.peek(0).try_as_bounded_index(
-        string.as_str().chars().count() as isize,
-        "String",
-    )?;
+    let char_index = vm
+        .peek(0)
+        .try_as_bounded_index(string.as_str().chars().count() as isize, "String")?;
 
     let mut char_count = 0;
     for i in 0..string.len() + 1 {
@@ -406,6 +1541,47 @@ fn string_char_byte_index(vm: &mut Vm, num_args: usize) -> Result<Value, Error>
     ))
 }
 
+fn string_byte_at(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 1)?;
+
+    let string = vm.peek(1).try_as_obj_string().expect("Expected ObjString.");
+    let byte_index = vm
+        .peek(0)
+        .try_as_bounded_index(string.len() as isize, "String")?;
+    Ok(Value::Number(string.as_bytes()[byte_index] as f64))
+}
+
+fn string_codepoint_at(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 1)?;
+
+    let string = vm.peek(1).try_as_obj_string().expect("Expected ObjString.");
+    let char_index = vm
+        .peek(0)
+        .try_as_bounded_index(string.as_str().chars().count() as isize, "String")?;
+    let c = string
+        .as_str()
+        .chars()
+        .nth(char_index)
+        .expect("Expected char.");
+    Ok(Value::Number((c as u32) as f64))
+}
+
+fn string_eq_ignore_case(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 1)?;
+
+    let string = vm.peek(1).try_as_obj_string().expect("Expected ObjString.");
+    let other = vm.peek(0).try_as_obj_string().ok_or_else(|| {
+        error!(
+            ErrorKind::TypeError,
+            "Expected a string but found '{}'.",
+            vm.peek(0)
+        )
+    })?;
+    Ok(Value::Boolean(
+        string.to_lowercase() == other.to_lowercase(),
+    ))
+}
+
 fn string_find(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
     check_num_args(num_args, 2)?;
 
@@ -420,19 +1596,9 @@ fn string_find(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
     if substring.is_empty() {
         return Err(error!(ErrorKind::ValueError, "Cannot find empty string."));
     }
-    let string_len = string.len() as isize;
-    let start = {
-        let i = utils::validate_integer(vm.peek(0))?;
-        if i < 0 {
-            i + string_len
-        } else {
-            i
-        }
-    };
-    if start < 0 || start >= string_len {
-        return Err(error!(ErrorKind::IndexError, "String index out of bounds."));
-    }
-    let start = start as usize;
+    let start = vm
+        .peek(0)
+        .try_as_bounded_index(string.len() as isize, "String")?;
     string.validate_char_boundary(start, "string index")?;
     for i in start..string.as_str().len() {
         if !string.is_char_boundary(i) || !string.is_char_boundary(i + substring.len()) {
@@ -468,8 +1634,29 @@ fn string_replace(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
             &format!("Expected a string but found '{}'.", vm.peek(0)),
         )
     })?;
-    let new_string = vm.new_gc_obj_string(&string.replace(old.as_str(), new.as_str()));
-    Ok(Value::ObjString(new_string))
+    let new_string = vm.new_root_obj_string(&string.replace(old.as_str(), new.as_str()));
+    Ok(Value::ObjString(new_string.as_gc()))
+}
+
+fn string_join(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 1)?;
+
+    let separator = vm.peek(1).try_as_obj_string().expect("Expected ObjString.");
+    let values = vm.collect_iterable(vm.peek(0))?;
+    let joined = join_values(separator.as_str(), &values.borrow().elements);
+    let string = vm.new_root_obj_string(&joined);
+    Ok(Value::ObjString(string.as_gc()))
+}
+
+fn join_values(separator: &str, values: &[Value]) -> String {
+    let mut joined = String::new();
+    for (i, value) in values.iter().enumerate() {
+        if i > 0 {
+            joined.push_str(separator);
+        }
+        write!(joined, "{}", value).expect("Unable to write to string.");
+    }
+    joined
 }
 
 fn string_split(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
@@ -488,10 +1675,21 @@ fn string_split(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
             "Cannot split using an empty string."
         ));
     }
+    let mut ranges = Vec::new();
+    let mut begin = 0;
+    for (end, _) in string.as_str().match_indices(delim.as_str()) {
+        ranges.push(begin..end);
+        begin = end + delim.len();
+    }
+    ranges.push(begin..string.len());
+
     let splits = vm.new_root_obj_vec();
-    for substr in string.as_str().split(delim.as_str()) {
-        let new_str = Value::ObjString(vm.new_gc_obj_string(substr));
-        splits.borrow_mut().elements.push(new_str);
+    for range in ranges {
+        let new_str = vm.new_root_obj_string_slice(string, range);
+        splits
+            .borrow_mut()
+            .elements
+            .push(Value::ObjString(new_str.as_gc()));
     }
     Ok(Value::ObjVec(splits.as_gc()))
 }
@@ -528,12 +1726,12 @@ fn string_to_num(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
     check_num_args(num_args, 0)?;
 
     let string = vm.peek(0).try_as_obj_string().expect("Expected ObjString.");
-    let num = string.parse::<f64>().or_else(|_| {
-        Err(error!(
+    let num = utils::parse_number(string.as_str()).ok_or_else(|| {
+        error!(
             ErrorKind::ValueError,
             "Unable to parse number from '{}'.",
             vm.peek(0)
-        ))
+        )
     })?;
 
     Ok(Value::Number(num))
@@ -583,8 +1781,8 @@ fn string_iter_next(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
     };
     if let Some((begin, end)) = next {
         let slice = &iterable[begin..end];
-        let string = vm.new_gc_obj_string(slice);
-        return Ok(Value::ObjString(string));
+        let string = vm.new_root_obj_string(slice);
+        return Ok(Value::ObjString(string.as_gc()));
     }
     Ok(Value::ObjInstance(vm.new_root_obj_stop_iter().as_gc()))
 }
@@ -673,6 +1871,9 @@ pub fn new_root_obj_vec_class(
         ("pop", vec_pop as NativeFn),
         ("len", vec_len as NativeFn),
         ("iter", vec_iter as NativeFn),
+        ("sort", vec_sort as NativeFn),
+        ("freeze", vec_freeze as NativeFn),
+        ("is_frozen", vec_is_frozen as NativeFn),
     ];
     let (methods, _native_roots) = build_methods(vm, &method_map, None);
     vm.new_root_obj_class(class_name, metaclass, Some(superclass), methods)
@@ -682,6 +1883,7 @@ fn vec_push(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
     check_num_args(num_args, 1)?;
 
     let vec = vm.peek(1).try_as_obj_vec().expect("Expected ObjVec");
+    vec.borrow().check_mutable()?;
 
     if vec.borrow().elements.len() >= common::VEC_ELEMS_MAX {
         return Err(error!(ErrorKind::RuntimeError, "Vec max capcity reached."));
@@ -697,6 +1899,7 @@ fn vec_pop(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
 
     let vec = vm.peek(0).try_as_obj_vec().expect("Expected ObjVec");
     let mut borrowed_vec = vec.borrow_mut();
+    borrowed_vec.check_mutable()?;
     borrowed_vec.elements.pop().ok_or_else(|| {
         Error::with_message(
             ErrorKind::RuntimeError,
@@ -713,6 +1916,46 @@ fn vec_len(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
     Ok(Value::Number(borrowed_vec.elements.len() as f64))
 }
 
+fn vec_sort(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    if num_args > 1 {
+        return Err(error!(
+            ErrorKind::TypeError,
+            "Expected 0 or 1 parameters but found {}.", num_args
+        )
+        .with_code(ErrorCode::ArityMismatch));
+    }
+
+    let vec = vm.peek(num_args).try_as_obj_vec().expect("Expected ObjVec");
+    vec.borrow().check_mutable()?;
+    let key = if num_args == 1 {
+        Some(vm.peek(0))
+    } else {
+        None
+    };
+    // Sort a copy of the elements, since the comparisons may modify the Vec.
+    let values = vm.collect_iterable(Value::ObjVec(vec))?;
+    let indices = vm.sorted_indices(values.as_gc(), key)?;
+    reorder_elements(values.as_gc(), &indices);
+    vec.borrow_mut().elements = values.borrow().elements.clone();
+    Ok(Value::None)
+}
+
+fn vec_freeze(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 0)?;
+
+    let vec = vm.peek(0).try_as_obj_vec().expect("Expected ObjVec");
+    vec.borrow_mut().freeze();
+    Ok(vm.peek(0))
+}
+
+fn vec_is_frozen(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 0)?;
+
+    let vec = vm.peek(0).try_as_obj_vec().expect("Expected ObjVec");
+    let frozen = vec.borrow().is_frozen();
+    Ok(Value::Boolean(frozen))
+}
+
 fn vec_iter(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
     check_num_args(num_args, 0)?;
 
@@ -817,6 +2060,8 @@ pub fn new_root_obj_hash_map_class(
         ("keys", hash_map_keys as NativeFn),
         ("values", hash_map_values as NativeFn),
         ("items", hash_map_items as NativeFn),
+        ("freeze", hash_map_freeze as NativeFn),
+        ("is_frozen", hash_map_is_frozen as NativeFn),
     ];
     let (methods, _native_roots) = build_methods(vm, &method_map, None);
     vm.new_root_obj_class(class_name, metaclass, Some(superclass), methods)
@@ -830,7 +2075,7 @@ fn hash_map_has_key(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
         .try_as_obj_hash_map()
         .expect("Expected ObjHashMap.");
 
-    let key = validate_hash_map_key(vm.peek(0))?;
+    let (key, _) = vm.hash_map_key(hash_map, vm.peek(0))?;
     let borrowed_hash_map = hash_map.borrow();
     Ok(Value::Boolean(
         borrowed_hash_map.elements.contains_key(&key),
@@ -845,7 +2090,7 @@ fn hash_map_get(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
         .try_as_obj_hash_map()
         .expect("Expected ObjHashMap");
 
-    let key = validate_hash_map_key(vm.peek(0))?;
+    let (key, _) = vm.hash_map_key(hash_map, vm.peek(0))?;
 
     let borrowed_hash_map = hash_map.borrow();
     Ok(*borrowed_hash_map.elements.get(&key).unwrap_or(&Value::None))
@@ -858,14 +2103,14 @@ fn hash_map_insert(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
         .peek(2)
         .try_as_obj_hash_map()
         .expect("Expected ObjHashMap");
+    hash_map.borrow().check_mutable()?;
 
-    let key = validate_hash_map_key(vm.peek(1))?;
+    let (key, instance_hash) = vm.hash_map_key(hash_map, vm.peek(1))?;
     let value = vm.peek(0);
 
     let mut borrowed_hash_map = hash_map.borrow_mut();
     Ok(borrowed_hash_map
-        .elements
-        .insert(key, value)
+        .insert(key, instance_hash, value)
         .unwrap_or(Value::None))
 }
 
@@ -876,13 +2121,13 @@ fn hash_map_remove(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
         .peek(1)
         .try_as_obj_hash_map()
         .expect("Expected ObjHashMap");
+    hash_map.borrow().check_mutable()?;
 
-    let key = validate_hash_map_key(vm.peek(0))?;
+    let (key, instance_hash) = vm.hash_map_key(hash_map, vm.peek(0))?;
 
     let mut borrowed_hash_map = hash_map.borrow_mut();
     Ok(borrowed_hash_map
-        .elements
-        .remove(&key)
+        .remove(key, instance_hash)
         .unwrap_or(Value::None))
 }
 
@@ -894,10 +2139,33 @@ fn hash_map_clear(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
         .try_as_obj_hash_map()
         .expect("Expected ObjHashMap");
     let mut borrowed_hash_map = hash_map.borrow_mut();
-    borrowed_hash_map.elements.clear();
+    borrowed_hash_map.check_mutable()?;
+    borrowed_hash_map.clear();
     Ok(Value::None)
 }
 
+fn hash_map_freeze(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 0)?;
+
+    let hash_map = vm
+        .peek(0)
+        .try_as_obj_hash_map()
+        .expect("Expected ObjHashMap");
+    hash_map.borrow_mut().freeze();
+    Ok(vm.peek(0))
+}
+
+fn hash_map_is_frozen(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 0)?;
+
+    let hash_map = vm
+        .peek(0)
+        .try_as_obj_hash_map()
+        .expect("Expected ObjHashMap");
+    let frozen = hash_map.borrow().is_frozen();
+    Ok(Value::Boolean(frozen))
+}
+
 fn hash_map_len(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
     check_num_args(num_args, 0)?;
 
@@ -959,14 +2227,160 @@ fn hash_map_items(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
     Ok(Value::ObjVec(obj_items.as_gc()))
 }
 
-fn validate_hash_map_key(key: Value) -> Result<Value, Error> {
-    if !key.has_hash() {
-        return Err(error!(
-            ErrorKind::ValueError,
-            "Cannot use unhashable value '{}' as HashMap key.", key
-        ));
-    }
-    Ok(key)
+/// IdentityMap implementation
+
+pub fn new_root_obj_identity_map_metaclass(
+    vm: &mut Vm,
+    metaclass: Gc<ObjClass>,
+    superclass: Gc<ObjClass>,
+) -> Root<ObjClass> {
+    let class_name = vm.new_gc_obj_string("IdentityMapClass");
+    let (methods, _native_roots) =
+        build_methods(vm, &[("new", identity_map_init as NativeFn)], None);
+    vm.new_root_obj_class(class_name, metaclass, Some(superclass), methods)
+}
+
+pub fn new_root_obj_identity_map_class(
+    vm: &mut Vm,
+    metaclass: Gc<ObjClass>,
+    superclass: Gc<ObjClass>,
+) -> Root<ObjClass> {
+    let class_name = vm.new_gc_obj_string("IdentityMap");
+    let method_map = [
+        ("has_key", identity_map_has_key as NativeFn),
+        ("get", identity_map_get as NativeFn),
+        ("insert", identity_map_insert as NativeFn),
+        ("remove", identity_map_remove as NativeFn),
+        ("clear", identity_map_clear as NativeFn),
+        ("len", identity_map_len as NativeFn),
+        ("keys", identity_map_keys as NativeFn),
+        ("values", identity_map_values as NativeFn),
+    ];
+    let (methods, _native_roots) = build_methods(vm, &method_map, None);
+    vm.new_root_obj_class(class_name, metaclass, Some(superclass), methods)
+}
+
+fn identity_map_init(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 0)?;
+
+    let identity_map = vm.new_root_obj_identity_map();
+    Ok(Value::ObjIdentityMap(identity_map.as_gc()))
+}
+
+fn identity_map_has_key(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 1)?;
+
+    let identity_map = vm
+        .peek(1)
+        .try_as_obj_identity_map()
+        .expect("Expected ObjIdentityMap.");
+
+    let key = IdentityKey(vm.peek(0));
+    let borrowed_identity_map = identity_map.borrow();
+    Ok(Value::Boolean(
+        borrowed_identity_map.elements.contains_key(&key),
+    ))
+}
+
+fn identity_map_get(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 1)?;
+
+    let identity_map = vm
+        .peek(1)
+        .try_as_obj_identity_map()
+        .expect("Expected ObjIdentityMap.");
+
+    let key = IdentityKey(vm.peek(0));
+    let borrowed_identity_map = identity_map.borrow();
+    Ok(*borrowed_identity_map
+        .elements
+        .get(&key)
+        .unwrap_or(&Value::None))
+}
+
+fn identity_map_insert(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 2)?;
+
+    let identity_map = vm
+        .peek(2)
+        .try_as_obj_identity_map()
+        .expect("Expected ObjIdentityMap.");
+
+    let key = IdentityKey(vm.peek(1));
+    let value = vm.peek(0);
+
+    let mut borrowed_identity_map = identity_map.borrow_mut();
+    Ok(borrowed_identity_map
+        .elements
+        .insert(key, value)
+        .unwrap_or(Value::None))
+}
+
+fn identity_map_remove(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 1)?;
+
+    let identity_map = vm
+        .peek(1)
+        .try_as_obj_identity_map()
+        .expect("Expected ObjIdentityMap.");
+
+    let key = IdentityKey(vm.peek(0));
+
+    let mut borrowed_identity_map = identity_map.borrow_mut();
+    Ok(borrowed_identity_map
+        .elements
+        .remove(&key)
+        .unwrap_or(Value::None))
+}
+
+fn identity_map_clear(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 0)?;
+
+    let identity_map = vm
+        .peek(0)
+        .try_as_obj_identity_map()
+        .expect("Expected ObjIdentityMap.");
+    identity_map.borrow_mut().elements.clear();
+    Ok(Value::None)
+}
+
+fn identity_map_len(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 0)?;
+
+    let identity_map = vm
+        .peek(0)
+        .try_as_obj_identity_map()
+        .expect("Expected ObjIdentityMap.");
+    let borrowed_identity_map = identity_map.borrow();
+    Ok(Value::Number(borrowed_identity_map.elements.len() as f64))
+}
+
+fn identity_map_keys(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 0)?;
+
+    let identity_map = vm
+        .peek(0)
+        .try_as_obj_identity_map()
+        .expect("Expected ObjIdentityMap.");
+    let borrowed_identity_map = identity_map.borrow();
+    let keys: Vec<_> = borrowed_identity_map.elements.keys().map(|k| k.0).collect();
+    let obj_keys = vm.new_root_obj_vec();
+    obj_keys.borrow_mut().elements = keys;
+    Ok(Value::ObjVec(obj_keys.as_gc()))
+}
+
+fn identity_map_values(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 0)?;
+
+    let identity_map = vm
+        .peek(0)
+        .try_as_obj_identity_map()
+        .expect("Expected ObjIdentityMap.");
+    let borrowed_identity_map = identity_map.borrow();
+    let values: Vec<_> = borrowed_identity_map.elements.values().copied().collect();
+    let obj_values = vm.new_root_obj_vec();
+    obj_values.borrow_mut().elements = values;
+    Ok(Value::ObjVec(obj_values.as_gc()))
 }
 
 /// Module implementation
@@ -977,12 +2391,21 @@ pub fn new_root_obj_module_class(
     superclass: Gc<ObjClass>,
 ) -> Root<ObjClass> {
     let class_name = vm.new_gc_obj_string("Module");
-    vm.new_root_obj_class(
-        class_name,
-        metaclass,
-        Some(superclass),
-        object::new_obj_string_value_map(),
-    )
+    let method_map = [("members", module_members as NativeFn)];
+    let (methods, _native_roots) = build_methods(vm, &method_map, None);
+    vm.new_root_obj_class(class_name, metaclass, Some(superclass), methods)
+}
+
+fn module_members(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 0)?;
+
+    let module = vm.peek(0).try_as_obj_module().expect("Expected ObjModule.");
+    vm.load_if_lazy(vm.peek(0))?;
+    let mut names: Vec<_> = module.borrow().members().collect();
+    names.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+    let vec = vm.new_root_obj_vec();
+    vec.borrow_mut().elements = names.into_iter().map(Value::ObjString).collect();
+    Ok(Value::ObjVec(vec.as_gc()))
 }
 
 /// Fiber implementation
@@ -999,10 +2422,37 @@ pub fn new_root_obj_fiber_metaclass(
         fiber_yield as NativeFn,
         true,
     ));
+    let transfer_method_name = vm.new_gc_obj_string("transfer");
+    let transfer_method = Root::new(ObjNative::new(
+        transfer_method_name,
+        fiber_transfer as NativeFn,
+        true,
+    ));
+    let suspend_method_name = vm.new_gc_obj_string("suspend");
+    let suspend_method = Root::new(ObjNative::new(
+        suspend_method_name,
+        fiber_suspend as NativeFn,
+        true,
+    ));
     let mut methods = object::new_obj_string_value_map();
     methods.insert(yield_method_name, Value::ObjNative(yield_method.as_gc()));
-    let (methods, _native_roots) =
-        build_methods(vm, &[("new", fiber_init as NativeFn)], Some(methods));
+    methods.insert(
+        transfer_method_name,
+        Value::ObjNative(transfer_method.as_gc()),
+    );
+    methods.insert(
+        suspend_method_name,
+        Value::ObjNative(suspend_method.as_gc()),
+    );
+    let (methods, _native_roots) = build_methods(
+        vm,
+        &[
+            ("new", fiber_init as NativeFn),
+            ("current", fiber_current as NativeFn),
+            ("schedule", fiber_schedule as NativeFn),
+        ],
+        Some(methods),
+    );
     vm.new_root_obj_class(class_name, metaclass, Some(superclass), methods)
 }
 
@@ -1018,11 +2468,25 @@ pub fn new_root_obj_fiber_class(
         fiber_call as NativeFn,
         true,
     ));
+    let try_call_method_name = vm.new_gc_obj_string("try_call");
+    let try_call_method = Root::new(ObjNative::new(
+        try_call_method_name,
+        fiber_try_call as NativeFn,
+        true,
+    ));
     let mut methods = object::new_obj_string_value_map();
     methods.insert(call_method_name, Value::ObjNative(call_method.as_gc()));
+    methods.insert(
+        try_call_method_name,
+        Value::ObjNative(try_call_method.as_gc()),
+    );
     let (methods, _native_roots) = build_methods(
         vm,
-        &[("has_finished", fiber_has_finished as NativeFn)],
+        &[
+            ("has_finished", fiber_has_finished as NativeFn),
+            ("failed", fiber_failed as NativeFn),
+            ("error", fiber_error as NativeFn),
+        ],
         Some(methods),
     );
     vm.new_root_obj_class(class_name, metaclass, Some(superclass), methods)
@@ -1048,6 +2512,14 @@ fn fiber_init(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
 }
 
 fn fiber_call(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    resume_fiber(vm, num_args, false)
+}
+
+fn fiber_try_call(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    resume_fiber(vm, num_args, true)
+}
+
+fn resume_fiber(vm: &mut Vm, num_args: usize, catches_errors: bool) -> Result<Value, Error> {
     let fiber = vm
         .peek(num_args)
         .try_as_obj_fiber()
@@ -1072,6 +2544,7 @@ fn fiber_call(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
         None
     };
     vm.load_fiber(fiber, arg)?;
+    fiber.borrow_mut().catches_errors = catches_errors;
 
     Ok(vm.peek(0))
 }
@@ -1092,9 +2565,80 @@ fn fiber_yield(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
     Ok(vm.peek(0))
 }
 
+fn fiber_transfer(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    if num_args == 0 || num_args > 2 {
+        return Err(error!(
+            ErrorKind::TypeError,
+            "Expected 1 or 2 parameters but found {}.", num_args
+        ));
+    }
+    let fiber = vm.peek(num_args - 1).try_as_obj_fiber().ok_or_else(|| {
+        error!(
+            ErrorKind::TypeError,
+            "Expected a Fiber but found '{}'.",
+            vm.peek(num_args - 1)
+        )
+    })?;
+    let (is_new, arity) = {
+        let borrowed_fiber = fiber.borrow();
+        (borrowed_fiber.is_new(), borrowed_fiber.call_arity)
+    };
+    if is_new {
+        check_num_args(num_args - 1, arity - 1)?;
+    }
+    let arg = if num_args == 2 {
+        Some(vm.peek(0))
+    } else {
+        None
+    };
+    vm.transfer_fiber(fiber, arg)?;
+
+    Ok(vm.peek(0))
+}
+
+fn fiber_current(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 0)?;
+    Ok(Value::ObjFiber(vm.current_fiber()))
+}
+
+fn fiber_schedule(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 1)?;
+    let fiber = vm.peek(0).try_as_obj_fiber().ok_or_else(|| {
+        error!(
+            ErrorKind::TypeError,
+            "Expected a Fiber but found '{}'.",
+            vm.peek(0)
+        )
+    })?;
+    vm.schedule_fiber(fiber)?;
+    Ok(Value::None)
+}
+
+fn fiber_suspend(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 0)?;
+    vm.suspend_fiber()?;
+    Ok(vm.peek(0))
+}
+
 fn fiber_has_finished(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
     check_num_args(num_args, 0)?;
     let fiber = vm.peek(0).try_as_obj_fiber().expect("Expected ObjFiber.");
     let has_finished = fiber.borrow().has_finished();
     Ok(Value::Boolean(has_finished))
 }
+
+fn fiber_failed(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 0)?;
+    let fiber = vm.peek(0).try_as_obj_fiber().expect("Expected ObjFiber.");
+    let failed = fiber.borrow().error.is_some();
+    Ok(Value::Boolean(failed))
+}
+
+/// Returns the exception that stopped the fiber, or nil if it hasn't raised one that it didn't
+/// handle, which distinguishes an error caught by `try_call` from an error returned normally.
+fn fiber_error(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 0)?;
+    let fiber = vm.peek(0).try_as_obj_fiber().expect("Expected ObjFiber.");
+    let error = fiber.borrow().error.unwrap_or(Value::None);
+    Ok(error)
+}
@@ -0,0 +1,145 @@
+/* Copyright 2020-2021 Matt Spraggs
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use crate::chunk::Chunk;
+use crate::hash::HashMap;
+use crate::memory::{Gc, Root};
+
+/// Records how many times each opcode in each chunk compiled while coverage is enabled has been
+/// executed.
+#[derive(Default)]
+pub(crate) struct Coverage {
+    chunks: Vec<ChunkCoverage>,
+    chunk_indices: HashMap<*const Chunk, usize>,
+}
+
+struct ChunkCoverage {
+    module_path: String,
+    chunk: Root<Chunk>,
+    hits: Vec<u64>,
+}
+
+impl Coverage {
+    pub(crate) fn new() -> Self {
+        Default::default()
+    }
+
+    pub(crate) fn add_chunk(&mut self, chunk: Gc<Chunk>, module_path: &str) {
+        let index = self.chunks.len();
+        self.chunks.push(ChunkCoverage {
+            module_path: module_path.to_string(),
+            chunk: Root::from(chunk),
+            hits: vec![0; chunk.code.len()],
+        });
+        self.chunk_indices.insert(&*chunk as *const Chunk, index);
+    }
+
+    /// Marks the opcode at the given offset as executed. Chunks that were compiled before
+    /// coverage was enabled, such as those of the core library, are ignored.
+    pub(crate) fn record(&mut self, chunk: Gc<Chunk>, offset: usize) {
+        if let Some(&index) = self.chunk_indices.get(&(&*chunk as *const Chunk)) {
+            self.chunks[index].hits[offset] += 1;
+        }
+    }
+
+    pub(crate) fn report(&self) -> CoverageReport {
+        let mut modules: BTreeMap<&str, BTreeMap<i32, u64>> = BTreeMap::new();
+
+        for coverage in &self.chunks {
+            let lines = modules.entry(coverage.module_path.as_str()).or_default();
+            let chunk = &coverage.chunk;
+            let mut offset = 0;
+            while offset < chunk.code.len() {
+                let hits = lines.entry(chunk.line(offset)).or_insert(0);
+                *hits = (*hits).max(coverage.hits[offset]);
+                offset += chunk.instruction_len(offset);
+            }
+        }
+
+        let modules = modules
+            .into_iter()
+            .map(|(module_path, lines)| ModuleCoverage {
+                module_path: module_path.to_string(),
+                lines: lines.into_iter().collect(),
+            })
+            .collect();
+
+        CoverageReport { modules }
+    }
+}
+
+/// The number of times each line of a module containing code was executed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModuleCoverage {
+    pub module_path: String,
+    pub lines: Vec<(i32, u64)>,
+}
+
+impl ModuleCoverage {
+    pub fn lines_hit(&self) -> usize {
+        self.lines.iter().filter(|(_, hits)| *hits > 0).count()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CoverageReport {
+    pub modules: Vec<ModuleCoverage>,
+}
+
+impl CoverageReport {
+    /// Formats the report as an lcov tracefile, using the supplied function to map each module
+    /// path to the path of its source file.
+    pub fn to_lcov<F: Fn(&str) -> String>(&self, source_path: F) -> String {
+        let mut output = String::new();
+        for module in &self.modules {
+            writeln!(output, "TN:").unwrap();
+            writeln!(output, "SF:{}", source_path(&module.module_path)).unwrap();
+            for (line, hits) in &module.lines {
+                writeln!(output, "DA:{},{}", line, hits).unwrap();
+            }
+            writeln!(output, "LH:{}", module.lines_hit()).unwrap();
+            writeln!(output, "LF:{}", module.lines.len()).unwrap();
+            writeln!(output, "end_of_record").unwrap();
+        }
+        output
+    }
+}
+
+#[test]
+fn test_coverage_report() {
+    let mut vm = crate::vm::Vm::with_built_ins();
+    vm.enable_coverage();
+    let source = "fn f() {\n    return 1;\n}\nvar a = false;\nif a {\n    f();\n}\n";
+    crate::vm::interpret(&mut vm, source.to_string(), None).unwrap();
+
+    let report = vm.coverage_report().unwrap();
+    assert_eq!(1, report.modules.len());
+    let lines = &report.modules[0].lines;
+    assert_eq!(
+        &vec![(2, 0), (3, 1), (4, 1), (5, 1), (6, 0), (7, 1), (8, 1)],
+        lines
+    );
+    assert_eq!(
+        "TN:\nSF:main.yl\nDA:2,0\nDA:3,1\nDA:4,1\nDA:5,1\nDA:6,0\nDA:7,1\nDA:8,1\n\
+         LH:5\nLF:7\nend_of_record\n",
+        report.to_lcov(|path| format!("{}.yl", path))
+    );
+}
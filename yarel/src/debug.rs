@@ -13,110 +13,128 @@
  * limitations under the License.
  */
 
+use alloc::string::String;
+use core::fmt::Write;
+
 use crate::chunk::{Chunk, OpCode};
 use crate::value::Value;
 
-pub fn disassemble_chunk(chunk: &Chunk, name: &str) {
-    println!("=== {} ===", name);
-
+/// Disassembles each instruction in a chunk, one per line.
+pub fn disassemble_chunk(chunk: &Chunk) -> String {
+    let mut out = String::new();
     let mut offset = 0;
     while offset < chunk.code.len() {
-        offset = disassemble_instruction(chunk, offset);
+        offset = write_instruction(&mut out, chunk, offset);
     }
+    out
 }
 
-pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> usize {
-    print!("{:04} ", offset);
+/// Writes out an instruction prefixed by its offset and line, returning the offset of the next
+/// instruction.
+pub fn write_instruction(out: &mut String, chunk: &Chunk, offset: usize) -> usize {
+    write!(out, "{:04} ", offset).unwrap();
 
-    if offset > 0 && chunk.lines[offset] == chunk.lines[offset - 1] {
-        print!("   | ");
+    if offset > 0 && chunk.line(offset) == chunk.line(offset - 1) {
+        write!(out, "   | ").unwrap();
     } else {
-        print!("{:4} ", chunk.lines[offset]);
+        write!(out, "{:4} ", chunk.line(offset)).unwrap();
     }
 
+    write_operation(out, chunk, offset)
+}
+
+/// Writes out the name and operands of an instruction, returning the offset of the next
+/// instruction.
+pub fn write_operation(out: &mut String, chunk: &Chunk, offset: usize) -> usize {
     let instruction = OpCode::from(chunk.code[offset]);
     match instruction {
-        OpCode::Constant => constant_instruction("CONSTANT", chunk, offset),
-        OpCode::Nil => simple_instruction("NIL", offset),
-        OpCode::True => simple_instruction("TRUE", offset),
-        OpCode::False => simple_instruction("FALSE", offset),
-        OpCode::Pop => simple_instruction("POP", offset),
-        OpCode::CopyTop => simple_instruction("COPY_TOP", offset),
-        OpCode::GetLocal => byte_instruction("GET_LOCAL", chunk, offset),
-        OpCode::SetLocal => byte_instruction("SET_LOCAL", chunk, offset),
-        OpCode::GetGlobal => constant_instruction("GET_GLOBAL", chunk, offset),
-        OpCode::DefineGlobal => constant_instruction("DEFINE_GLOBAL", chunk, offset),
-        OpCode::SetGlobal => constant_instruction("SET_GLOBAL", chunk, offset),
-        OpCode::GetUpvalue => byte_instruction("GET_UPVALUE", chunk, offset),
-        OpCode::SetUpvalue => byte_instruction("SET_UPVALUE", chunk, offset),
-        OpCode::GetProperty => constant_instruction("GET_PROPERTY", chunk, offset),
-        OpCode::SetProperty => constant_instruction("SET_PROPERTY", chunk, offset),
-        OpCode::GetClass => simple_instruction("GET_CLASS", offset),
-        OpCode::GetSuper => constant_instruction("GET_SUPER", chunk, offset),
-        OpCode::Equal => simple_instruction("EQUAL", offset),
-        OpCode::Greater => simple_instruction("GREATER", offset),
-        OpCode::Less => simple_instruction("LESS", offset),
-        OpCode::Add => simple_instruction("ADD", offset),
-        OpCode::Subtract => simple_instruction("SUBTRACT", offset),
-        OpCode::Multiply => simple_instruction("MULTIPLY", offset),
-        OpCode::Divide => simple_instruction("DIVIDE", offset),
-        OpCode::BitwiseAnd => simple_instruction("BITWISE_AND", offset),
-        OpCode::BitwiseOr => simple_instruction("BITWISE_OR", offset),
-        OpCode::BitwiseXor => simple_instruction("BITWISE_XOR", offset),
-        OpCode::Modulo => simple_instruction("MODULO", offset),
-        OpCode::LogicalNot => simple_instruction("LOGICAL_NOT", offset),
-        OpCode::BitwiseNot => simple_instruction("BITWISE_NOT", offset),
-        OpCode::BitShiftLeft => simple_instruction("BIT_SHIFT_LEFT", offset),
-        OpCode::BitShiftRight => simple_instruction("BIT_SHIFT_RIGHT", offset),
-        OpCode::Negate => simple_instruction("NEGATE", offset),
-        OpCode::GetItem => simple_instruction("GET_ITEM", offset),
-        OpCode::SetItem => simple_instruction("SET_ITEM", offset),
-        OpCode::BuildHashMap => byte_instruction("BUILD_HASH_MAP", chunk, offset),
-        OpCode::BuildRange => simple_instruction("BUILD_RANGE", offset),
-        OpCode::BuildString => byte_instruction("BUILD_STRING", chunk, offset),
-        OpCode::BuildTuple => byte_instruction("BUILD_TUPLE", chunk, offset),
-        OpCode::FormatString => simple_instruction("FORMAT_STRING", offset),
-        OpCode::BuildVec => byte_instruction("BUILD_VEC", chunk, offset),
-        OpCode::IterNext => simple_instruction("ITER_NEXT", offset),
-        OpCode::Jump => jump_instruction("JUMP", 1, chunk, offset),
-        OpCode::JumpIfFalse => jump_instruction("JUMP_IF_FALSE", 1, chunk, offset),
-        OpCode::JumpIfStopIter => jump_instruction("JUMP_IF_STOP_ITER", 1, chunk, offset),
-        OpCode::Loop => jump_instruction("LOOP", -1, chunk, offset),
-        OpCode::JumpFinally => simple_instruction("JUMP_FINALLY", offset),
-        OpCode::EndFinally => simple_instruction("END_FINALLY", offset),
+        OpCode::Constant => constant_instruction(out, "CONSTANT", chunk, offset),
+        OpCode::Nil => simple_instruction(out, "NIL", offset),
+        OpCode::True => simple_instruction(out, "TRUE", offset),
+        OpCode::False => simple_instruction(out, "FALSE", offset),
+        OpCode::Pop => simple_instruction(out, "POP", offset),
+        OpCode::CopyTop => simple_instruction(out, "COPY_TOP", offset),
+        OpCode::CopyTopTwo => simple_instruction(out, "COPY_TOP_TWO", offset),
+        OpCode::GetLocal => byte_instruction(out, "GET_LOCAL", chunk, offset),
+        OpCode::SetLocal => byte_instruction(out, "SET_LOCAL", chunk, offset),
+        OpCode::GetGlobal => short_instruction(out, "GET_GLOBAL", chunk, offset),
+        OpCode::DefineGlobal => short_instruction(out, "DEFINE_GLOBAL", chunk, offset),
+        OpCode::SetGlobal => short_instruction(out, "SET_GLOBAL", chunk, offset),
+        OpCode::GetUpvalue => byte_instruction(out, "GET_UPVALUE", chunk, offset),
+        OpCode::SetUpvalue => byte_instruction(out, "SET_UPVALUE", chunk, offset),
+        OpCode::GetProperty => constant_instruction(out, "GET_PROPERTY", chunk, offset),
+        OpCode::SetProperty => constant_instruction(out, "SET_PROPERTY", chunk, offset),
+        OpCode::GetClass => simple_instruction(out, "GET_CLASS", offset),
+        OpCode::GetSuper => constant_instruction(out, "GET_SUPER", chunk, offset),
+        OpCode::Equal => simple_instruction(out, "EQUAL", offset),
+        OpCode::Greater => simple_instruction(out, "GREATER", offset),
+        OpCode::Less => simple_instruction(out, "LESS", offset),
+        OpCode::Is => simple_instruction(out, "IS", offset),
+        OpCode::Add => simple_instruction(out, "ADD", offset),
+        OpCode::Subtract => simple_instruction(out, "SUBTRACT", offset),
+        OpCode::Multiply => simple_instruction(out, "MULTIPLY", offset),
+        OpCode::Divide => simple_instruction(out, "DIVIDE", offset),
+        OpCode::BitwiseAnd => simple_instruction(out, "BITWISE_AND", offset),
+        OpCode::BitwiseOr => simple_instruction(out, "BITWISE_OR", offset),
+        OpCode::BitwiseXor => simple_instruction(out, "BITWISE_XOR", offset),
+        OpCode::Modulo => simple_instruction(out, "MODULO", offset),
+        OpCode::LogicalNot => simple_instruction(out, "LOGICAL_NOT", offset),
+        OpCode::BitwiseNot => simple_instruction(out, "BITWISE_NOT", offset),
+        OpCode::BitShiftLeft => simple_instruction(out, "BIT_SHIFT_LEFT", offset),
+        OpCode::BitShiftRight => simple_instruction(out, "BIT_SHIFT_RIGHT", offset),
+        OpCode::Negate => simple_instruction(out, "NEGATE", offset),
+        OpCode::GetItem => simple_instruction(out, "GET_ITEM", offset),
+        OpCode::SetItem => simple_instruction(out, "SET_ITEM", offset),
+        OpCode::BuildHashMap => byte_instruction(out, "BUILD_HASH_MAP", chunk, offset),
+        OpCode::ExtendHashMap => byte_instruction(out, "EXTEND_HASH_MAP", chunk, offset),
+        OpCode::BuildRange => byte_instruction(out, "BUILD_RANGE", chunk, offset),
+        OpCode::BuildString => byte_instruction(out, "BUILD_STRING", chunk, offset),
+        OpCode::BuildTuple => byte_instruction(out, "BUILD_TUPLE", chunk, offset),
+        OpCode::FormatString => simple_instruction(out, "FORMAT_STRING", offset),
+        OpCode::BuildVec => byte_instruction(out, "BUILD_VEC", chunk, offset),
+        OpCode::ExtendVec => byte_instruction(out, "EXTEND_VEC", chunk, offset),
+        OpCode::Splice => simple_instruction(out, "SPLICE", offset),
+        OpCode::GetIter => simple_instruction(out, "GET_ITER", offset),
+        OpCode::IterNext => simple_instruction(out, "ITER_NEXT", offset),
+        OpCode::Jump => jump_instruction(out, "JUMP", 1, chunk, offset),
+        OpCode::JumpLong => long_jump_instruction(out, "JUMP_LONG", 1, chunk, offset),
+        OpCode::JumpIfFalse => jump_instruction(out, "JUMP_IF_FALSE", 1, chunk, offset),
+        OpCode::JumpIfStopIter => jump_instruction(out, "JUMP_IF_STOP_ITER", 1, chunk, offset),
+        OpCode::JumpIfNotInstance => {
+            jump_instruction(out, "JUMP_IF_NOT_INSTANCE", 1, chunk, offset)
+        }
+        OpCode::Loop => jump_instruction(out, "LOOP", -1, chunk, offset),
+        OpCode::LoopLong => long_jump_instruction(out, "LOOP_LONG", -1, chunk, offset),
+        OpCode::JumpFinally => simple_instruction(out, "JUMP_FINALLY", offset),
+        OpCode::EndFinally => simple_instruction(out, "END_FINALLY", offset),
         OpCode::PushExcHandler => {
-            let start = offset;
-            let offset = offset + 1;
-            let try_size =
-                u16::from_ne_bytes([chunk.code[offset], chunk.code[offset + 1]]) as usize;
-            let catch_pos = start + try_size + 5;
-            let offset = offset + 2;
-            let catch_size =
-                u16::from_ne_bytes([chunk.code[offset], chunk.code[offset + 1]]) as usize;
-            let finally_pos = catch_pos + catch_size;
-
-            println!(
-                "{:16} {:4} -> catch, {} -> finally",
-                "PUSH_EXC_HANDLER", catch_pos, finally_pos
-            );
-
-            offset + 2
+            exc_handler_instruction(out, "PUSH_EXC_HANDLER", 2, chunk, offset)
         }
-        OpCode::PopExcHandler => simple_instruction("POP_EXC_HANDLER", offset),
-        OpCode::Throw => simple_instruction("THROW", offset),
-        OpCode::Call => byte_instruction("CALL", chunk, offset),
-        OpCode::Invoke => invoke_instruction("INVOKE", chunk, offset),
-        OpCode::Construct => byte_instruction("CONSTRUCT", chunk, offset),
-        OpCode::SuperInvoke => invoke_instruction("SUPER_INVOKE", chunk, offset),
+        OpCode::PushExcHandlerLong => {
+            exc_handler_instruction(out, "PUSH_EXC_HANDLER_LONG", 4, chunk, offset)
+        }
+        OpCode::PopExcHandler => simple_instruction(out, "POP_EXC_HANDLER", offset),
+        OpCode::Throw => simple_instruction(out, "THROW", offset),
+        OpCode::Rethrow => simple_instruction(out, "RETHROW", offset),
+        OpCode::Call => byte_instruction(out, "CALL", chunk, offset),
+        OpCode::CallSpread => simple_instruction(out, "CALL_SPREAD", offset),
+        OpCode::CallNamed => call_named_instruction(out, "CALL_NAMED", chunk, offset),
+        OpCode::Invoke => invoke_instruction(out, "INVOKE", chunk, offset),
+        OpCode::InvokeSpread => constant_instruction(out, "INVOKE_SPREAD", chunk, offset),
+        OpCode::InvokeNamed => invoke_named_instruction(out, "INVOKE_NAMED", chunk, offset),
+        OpCode::Construct => construct_instruction(out, chunk, offset),
+        OpCode::SuperInvoke => invoke_instruction(out, "SUPER_INVOKE", chunk, offset),
         OpCode::Closure => {
             let mut offset = offset + 1;
             let constant =
                 u16::from_ne_bytes([chunk.code[offset], chunk.code[offset + 1]]) as usize;
             offset += 2;
-            println!(
+            writeln!(
+                out,
                 "{:16} {:4} {}",
                 "CLOSURE", constant, chunk.constants[constant]
-            );
+            )
+            .unwrap();
 
             let function = match chunk.constants[constant] {
                 Value::ObjFunction(ref underlying) => underlying,
@@ -133,61 +151,163 @@ pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> usize {
                 let index = chunk.code[offset] as usize;
                 offset += 1;
 
-                println!(
+                writeln!(
+                    out,
                     "{:04}      |                     {} {}",
                     offset - 2,
                     is_local,
                     index
-                );
+                )
+                .unwrap();
             }
 
             offset
         }
-        OpCode::CloseUpvalue => simple_instruction("CLOSE_UPVALUE", offset),
-        OpCode::Return => simple_instruction("RETURN", offset),
-        OpCode::DeclareClass => constant_instruction("DECLARE_CLASS", chunk, offset),
-        OpCode::DefineClass => simple_instruction("DEFINE_CLASS", offset),
-        OpCode::Inherit => simple_instruction("INHERIT", offset),
-        OpCode::Method => constant_instruction("METHOD", chunk, offset),
-        OpCode::StaticMethod => constant_instruction("STATIC_METHOD", chunk, offset),
-        OpCode::StartImport => constant_instruction("START_IMPORT", chunk, offset),
-        OpCode::FinishImport => simple_instruction("FINISH_IMPORT", offset),
+        OpCode::Generator => simple_instruction(out, "GENERATOR", offset),
+        OpCode::CloseUpvalue => simple_instruction(out, "CLOSE_UPVALUE", offset),
+        OpCode::Return => simple_instruction(out, "RETURN", offset),
+        OpCode::Yield => simple_instruction(out, "YIELD", offset),
+        OpCode::DeclareClass => constant_instruction(out, "DECLARE_CLASS", chunk, offset),
+        OpCode::DefineClass => simple_instruction(out, "DEFINE_CLASS", offset),
+        OpCode::Inherit => simple_instruction(out, "INHERIT", offset),
+        OpCode::Implements => simple_instruction(out, "IMPLEMENTS", offset),
+        OpCode::Mixin => simple_instruction(out, "MIXIN", offset),
+        OpCode::Method => constant_instruction(out, "METHOD", chunk, offset),
+        OpCode::StaticMethod => constant_instruction(out, "STATIC_METHOD", chunk, offset),
+        OpCode::StaticField => constant_instruction(out, "STATIC_FIELD", chunk, offset),
+        OpCode::StartImport => constant_instruction(out, "START_IMPORT", chunk, offset),
+        OpCode::LazyImport => constant_instruction(out, "LAZY_IMPORT", chunk, offset),
+        OpCode::FinishImport => simple_instruction(out, "FINISH_IMPORT", offset),
     }
 }
 
-fn simple_instruction(name: &str, offset: usize) -> usize {
-    println!("{}", name);
+fn simple_instruction(out: &mut String, name: &str, offset: usize) -> usize {
+    writeln!(out, "{}", name).unwrap();
     offset + 1
 }
 
-fn byte_instruction(name: &str, chunk: &Chunk, offset: usize) -> usize {
+fn byte_instruction(out: &mut String, name: &str, chunk: &Chunk, offset: usize) -> usize {
     let slot = chunk.code[offset + 1];
-    println!("{:16} {:4}", name, slot as usize);
+    writeln!(out, "{:16} {:4}", name, slot as usize).unwrap();
     offset + 2
 }
 
-fn jump_instruction(name: &str, sign: i32, chunk: &Chunk, offset: usize) -> usize {
+fn short_instruction(out: &mut String, name: &str, chunk: &Chunk, offset: usize) -> usize {
+    let slot = u16::from_ne_bytes([chunk.code[offset + 1], chunk.code[offset + 2]]);
+    writeln!(out, "{:16} {:4}", name, slot as usize).unwrap();
+    offset + 3
+}
+
+fn construct_instruction(out: &mut String, chunk: &Chunk, offset: usize) -> usize {
+    let arg_count = chunk.code[offset + 1];
+    let target = offset + 3 + chunk.code[offset + 2] as usize;
+    writeln!(out, "{:16} {:4} -> {}", "CONSTRUCT", arg_count, target).unwrap();
+    offset + 3
+}
+
+fn jump_instruction(
+    out: &mut String,
+    name: &str,
+    sign: i32,
+    chunk: &Chunk,
+    offset: usize,
+) -> usize {
     let jump = u16::from_ne_bytes([chunk.code[offset + 1], chunk.code[offset + 2]]);
     let target = (offset + 3) as isize + sign as isize * jump as isize;
-    println!("{:16} {:4} -> {}", name, offset, target);
+    writeln!(out, "{:16} {:4} -> {}", name, offset, target).unwrap();
     offset + 3
 }
 
-fn constant_instruction(name: &str, chunk: &Chunk, offset: usize) -> usize {
+fn exc_handler_instruction(
+    out: &mut String,
+    name: &str,
+    arg_size: usize,
+    chunk: &Chunk,
+    offset: usize,
+) -> usize {
+    let code = &chunk.code;
+    let read_size = |pos: usize| match arg_size {
+        2 => u16::from_ne_bytes([code[pos], code[pos + 1]]) as usize,
+        _ => u32::from_ne_bytes([code[pos], code[pos + 1], code[pos + 2], code[pos + 3]]) as usize,
+    };
+    let try_size = read_size(offset + 1);
+    let catch_size = read_size(offset + 1 + arg_size);
+    let next = offset + 1 + 2 * arg_size;
+    let catch_pos = next + try_size;
+    let finally_pos = catch_pos + catch_size;
+    writeln!(
+        out,
+        "{:16} {:4} -> catch, {} -> finally",
+        name, catch_pos, finally_pos
+    )
+    .unwrap();
+    next
+}
+
+fn long_jump_instruction(
+    out: &mut String,
+    name: &str,
+    sign: i32,
+    chunk: &Chunk,
+    offset: usize,
+) -> usize {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&chunk.code[offset + 1..offset + 5]);
+    let jump = u32::from_ne_bytes(bytes);
+    let target = (offset + 5) as isize + sign as isize * jump as isize;
+    writeln!(out, "{:16} {:4} -> {}", name, offset, target).unwrap();
+    offset + 5
+}
+
+fn constant_instruction(out: &mut String, name: &str, chunk: &Chunk, offset: usize) -> usize {
     let constant = u16::from_ne_bytes([chunk.code[offset + 1], chunk.code[offset + 2]]);
-    println!(
+    writeln!(
+        out,
         "{:16} {:4} '{}'",
         name, constant, chunk.constants[constant as usize]
-    );
+    )
+    .unwrap();
     offset + 3
 }
 
-fn invoke_instruction(name: &str, chunk: &Chunk, offset: usize) -> usize {
+fn invoke_instruction(out: &mut String, name: &str, chunk: &Chunk, offset: usize) -> usize {
     let constant = u16::from_ne_bytes([chunk.code[offset + 1], chunk.code[offset + 2]]);
     let arg_count = chunk.code[offset + 3];
-    println!(
+    writeln!(
+        out,
         "{:16} ({} args) {:4} '{}'",
         name, arg_count, constant, chunk.constants[constant as usize]
-    );
+    )
+    .unwrap();
     offset + 4
 }
+
+fn call_named_instruction(out: &mut String, name: &str, chunk: &Chunk, offset: usize) -> usize {
+    let arg_count = chunk.code[offset + 1];
+    let names = u16::from_ne_bytes([chunk.code[offset + 2], chunk.code[offset + 3]]);
+    writeln!(
+        out,
+        "{:16} ({} args) {:4} {}",
+        name, arg_count, names, chunk.constants[names as usize]
+    )
+    .unwrap();
+    offset + 4
+}
+
+fn invoke_named_instruction(out: &mut String, name: &str, chunk: &Chunk, offset: usize) -> usize {
+    let constant = u16::from_ne_bytes([chunk.code[offset + 1], chunk.code[offset + 2]]);
+    let arg_count = chunk.code[offset + 3];
+    let names = u16::from_ne_bytes([chunk.code[offset + 4], chunk.code[offset + 5]]);
+    writeln!(
+        out,
+        "{:16} ({} args) {:4} '{}' {:4} {}",
+        name,
+        arg_count,
+        constant,
+        chunk.constants[constant as usize],
+        names,
+        chunk.constants[names as usize]
+    )
+    .unwrap();
+    offset + 6
+}
@@ -13,8 +13,13 @@
  * limitations under the License.
  */
 
-use std::error;
-use std::fmt;
+use alloc::borrow::ToOwned;
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use core::error;
+use core::fmt;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum ErrorKind {
@@ -28,47 +33,273 @@ pub enum ErrorKind {
     ValueError,
 }
 
+impl ErrorKind {
+    pub(crate) fn code(&self) -> ErrorCode {
+        match self {
+            ErrorKind::AttributeError => ErrorCode::AttributeError,
+            ErrorKind::CompileError => ErrorCode::CompileError,
+            ErrorKind::ImportError => ErrorCode::ImportError,
+            ErrorKind::IndexError => ErrorCode::IndexError,
+            ErrorKind::NameError => ErrorCode::NameError,
+            ErrorKind::RuntimeError => ErrorCode::RuntimeError,
+            ErrorKind::TypeError => ErrorCode::TypeError,
+            ErrorKind::ValueError => ErrorCode::ValueError,
+        }
+    }
+}
+
+/// Identifies the failure an error represents, so that hosts can tell failures apart without
+/// parsing messages. Errors without a more specific code have the code named after their kind.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum ErrorCode {
+    AttributeError,
+    CompileError,
+    ImportError,
+    IndexError,
+    NameError,
+    RuntimeError,
+    TypeError,
+    ValueError,
+    /// A function was called with the wrong number of arguments.
+    ArityMismatch,
+    /// An index was outside the bounds of the value indexed.
+    IndexOutOfBounds,
+    /// An imported module couldn't be found.
+    ModuleNotFound,
+    /// A call exceeded the stack size or frame limit of a fiber.
+    StackOverflow,
+    /// A variable was used without being defined.
+    UndefinedVariable,
+    /// The script called `process.exit`, so stopped running.
+    Exit,
+}
+
+impl ErrorCode {
+    const ALL: [ErrorCode; 14] = [
+        ErrorCode::AttributeError,
+        ErrorCode::CompileError,
+        ErrorCode::ImportError,
+        ErrorCode::IndexError,
+        ErrorCode::NameError,
+        ErrorCode::RuntimeError,
+        ErrorCode::TypeError,
+        ErrorCode::ValueError,
+        ErrorCode::ArityMismatch,
+        ErrorCode::IndexOutOfBounds,
+        ErrorCode::ModuleNotFound,
+        ErrorCode::StackOverflow,
+        ErrorCode::UndefinedVariable,
+        ErrorCode::Exit,
+    ];
+
+    /// The name of the code, which doesn't change between releases.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::AttributeError => "attribute_error",
+            ErrorCode::CompileError => "compile_error",
+            ErrorCode::ImportError => "import_error",
+            ErrorCode::IndexError => "index_error",
+            ErrorCode::NameError => "name_error",
+            ErrorCode::RuntimeError => "runtime_error",
+            ErrorCode::TypeError => "type_error",
+            ErrorCode::ValueError => "value_error",
+            ErrorCode::ArityMismatch => "arity_mismatch",
+            ErrorCode::IndexOutOfBounds => "index_out_of_bounds",
+            ErrorCode::ModuleNotFound => "module_not_found",
+            ErrorCode::StackOverflow => "stack_overflow",
+            ErrorCode::UndefinedVariable => "undefined_variable",
+            ErrorCode::Exit => "exit",
+        }
+    }
+
+    /// Looks up a code by its name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        ErrorCode::ALL
+            .iter()
+            .copied()
+            .find(|code| code.as_str() == name)
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The part of a line of source code that an error message refers to.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Span {
+    pub line: usize,
+    /// The column of the first character spanned, counting from one.
+    pub column: usize,
+    /// The number of characters spanned.
+    pub length: usize,
+    /// The text of the line containing the span.
+    pub source_line: String,
+}
+
+impl Span {
+    /// Creates the span covering `length` bytes of `source` from byte `offset`, clipped to the end
+    /// of the line the span starts on.
+    pub fn from_offset(source: &str, offset: usize, length: usize) -> Self {
+        let offset = offset.min(source.len());
+        let line_start = source[..offset].rfind('\n').map_or(0, |pos| pos + 1);
+        let line_end = source[offset..]
+            .find('\n')
+            .map_or(source.len(), |pos| offset + pos);
+        let end = (offset + length).min(line_end);
+        Span {
+            line: source[..offset].matches('\n').count() + 1,
+            column: source[line_start..offset].chars().count() + 1,
+            length: source.get(offset..end).map_or(0, |s| s.chars().count()),
+            source_line: source[line_start..line_end]
+                .trim_end_matches('\r')
+                .to_owned(),
+        }
+    }
+
+    /// Renders the line of source containing the span with the span underlined by carets.
+    pub fn underline(&self) -> String {
+        let gutter = self.line.to_string();
+        // Tabs are kept in the padding so that the carets line up with the source however wide
+        // the tabs are displayed.
+        let padding: String = self
+            .source_line
+            .chars()
+            .take(self.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        format!(
+            "{} | {}\n{} | {}{}",
+            gutter,
+            self.source_line,
+            " ".repeat(gutter.len()),
+            padding,
+            "^".repeat(self.length.max(1))
+        )
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Error {
     kind: ErrorKind,
+    code: ErrorCode,
     messages: Vec<String>,
+    /// The location in the source of each message, if it refers to one.
+    spans: Vec<Option<Span>>,
+    /// The error that caused this one, if any.
+    cause: Option<Box<Error>>,
+    /// The name of the class of the exception the error was raised as, if any.
+    class_name: Option<String>,
+    /// The fields of the exception the error was raised as, other than its context and cause,
+    /// with their values formatted as strings.
+    fields: Vec<(String, String)>,
 }
 
 impl Error {
     pub fn new(kind: ErrorKind) -> Self {
         Error {
             kind,
+            code: kind.code(),
             messages: Vec::new(),
+            spans: Vec::new(),
+            cause: None,
+            class_name: None,
+            fields: Vec::new(),
         }
     }
 
     pub fn with_message(kind: ErrorKind, message: &str) -> Self {
         Error {
             kind,
+            code: kind.code(),
             messages: vec![String::from(message)],
+            spans: vec![None],
+            cause: None,
+            class_name: None,
+            fields: Vec::new(),
         }
     }
 
     pub fn with_messages(kind: ErrorKind, messages: &[&str]) -> Self {
-        let messages = messages.iter().map(|s| String::from(*s)).collect();
-        Error { kind, messages }
+        let messages: Vec<_> = messages.iter().map(|s| String::from(*s)).collect();
+        let spans = vec![None; messages.len()];
+        Error {
+            kind,
+            code: kind.code(),
+            messages,
+            spans,
+            cause: None,
+            class_name: None,
+            fields: Vec::new(),
+        }
+    }
+
+    /// Replaces the code of the error with a more specific one.
+    pub fn with_code(mut self, code: ErrorCode) -> Self {
+        self.code = code;
+        self
     }
 
     pub fn add_message(&mut self, message: &str) {
+        self.add_message_with_span(message, None);
+    }
+
+    pub fn add_message_with_span(&mut self, message: &str, span: Option<Span>) {
         self.messages.push(String::from(message));
+        self.spans.push(span);
+    }
+
+    /// Appends the messages of another error to this one.
+    pub fn merge(&mut self, other: Error) {
+        self.messages.extend(other.messages);
+        self.spans.extend(other.spans);
     }
 
     pub fn kind(&self) -> ErrorKind {
         self.kind
     }
 
+    pub fn code(&self) -> ErrorCode {
+        self.code
+    }
+
     pub fn messages(&self) -> &Vec<String> {
         &self.messages
     }
+
+    pub fn cause(&self) -> Option<&Error> {
+        self.cause.as_deref()
+    }
+
+    pub fn set_cause(&mut self, cause: Error) {
+        self.cause = Some(Box::new(cause));
+    }
+
+    pub fn class_name(&self) -> Option<&str> {
+        self.class_name.as_deref()
+    }
+
+    pub fn fields(&self) -> &[(String, String)] {
+        &self.fields
+    }
+
+    /// Records the class and fields of the exception the error was raised as.
+    pub fn set_class(&mut self, class_name: &str, fields: Vec<(String, String)>) {
+        self.class_name = Some(class_name.to_owned());
+        self.fields = fields;
+    }
+
+    /// The location in the source referred to by the message at the specified index, if any.
+    pub fn span(&self, index: usize) -> Option<&Span> {
+        self.spans.get(index).and_then(Option::as_ref)
+    }
 }
 
 impl fmt::Display for Error {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         for msg in &self.messages {
             match writeln!(f, "{}", msg) {
                 Ok(()) => {}
@@ -81,7 +312,13 @@ impl fmt::Display for Error {
     }
 }
 
-impl error::Error for Error {}
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        self.cause
+            .as_deref()
+            .map(|cause| cause as &(dyn error::Error + 'static))
+    }
+}
 
 #[macro_export]
 macro_rules! error {
@@ -104,3 +341,17 @@ fn test_sync() {
     fn assert_sync<T: Sync>() {}
     assert_sync::<Error>();
 }
+
+#[test]
+fn test_span_underline() {
+    let source = "var a = 1;\n\tvar bcd = ;\n";
+    let span = Span::from_offset(source, 16, 3);
+    assert_eq!(2, span.line);
+    assert_eq!(6, span.column);
+    assert_eq!(3, span.length);
+    assert_eq!("2 | \tvar bcd = ;\n  | \t    ^^^", span.underline());
+
+    let span = Span::from_offset(source, source.len(), 0);
+    assert_eq!(3, span.line);
+    assert_eq!("3 | \n  | ^", span.underline());
+}
@@ -0,0 +1,856 @@
+/* Copyright 2020-2021 Matt Spraggs
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Prints a syntax tree back out as source in a consistent style: four-space indentation, one
+//! statement per line, single spaces around binary operators and after commas, and no trailing
+//! comma except where a single-element tuple requires one.
+//!
+//! The tree doesn't record comments, so they're taken from the scanner and re-inserted before
+//! the first statement that starts after them. A comment that followed code on its line is kept
+//! at the end of the line printed before it.
+
+use alloc::borrow::ToOwned;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::ast::{
+    Argument, Attribute, BinaryOperator, Block, ComprehensionKind, Expr, Field, Function,
+    Identifier, LambdaBody, Literal, LogicalOperator, MethodKind, Module, Stmt, UnaryOperator,
+};
+use crate::scanner::Comment;
+
+const INDENT: &str = "    ";
+
+pub(crate) fn format_module(module: &Module, source: &str, comments: Vec<Comment>) -> String {
+    let mut formatter = Formatter {
+        output: String::new(),
+        indent: 0,
+        source_lines: source.lines().collect(),
+        comments,
+        next_comment: 0,
+        at_block_start: true,
+    };
+    formatter.module_attributes(&module.attributes);
+    for statement in &module.statements {
+        formatter.statement(statement);
+    }
+    formatter.comments_before(usize::MAX);
+
+    let mut output = formatter.output;
+    output.truncate(output.trim_end().len());
+    if !output.is_empty() {
+        output.push('\n');
+    }
+    output
+}
+
+struct Formatter<'a> {
+    output: String,
+    indent: usize,
+    source_lines: Vec<&'a str>,
+    comments: Vec<Comment>,
+    next_comment: usize,
+    /// Whether nothing has been printed yet in the current block, in which case no blank line is
+    /// kept before the next statement.
+    at_block_start: bool,
+}
+
+impl<'a> Formatter<'a> {
+    fn write(&mut self, text: &str) {
+        self.output.push_str(text);
+    }
+
+    fn write_indent(&mut self) {
+        for _ in 0..self.indent {
+            self.output.push_str(INDENT);
+        }
+    }
+
+    fn has_comment_before(&self, line: usize) -> bool {
+        self.comments
+            .get(self.next_comment)
+            .is_some_and(|comment| comment.line < line)
+    }
+
+    /// Keeps a single blank line before something starting on the specified line if there was
+    /// one in the source.
+    fn blank_line_before(&mut self, line: usize) {
+        if self.at_block_start || line < 2 {
+            return;
+        }
+        let previous_blank = self
+            .source_lines
+            .get(line - 2)
+            .is_some_and(|text| text.trim().is_empty());
+        if previous_blank && !self.output.ends_with("\n\n") {
+            self.output.push('\n');
+        }
+    }
+
+    /// Prints the comments that start before the specified line, returning the text of any doc
+    /// comments among them.
+    fn comments_before(&mut self, line: usize) -> Option<String> {
+        let mut doc_lines = Vec::new();
+        while self.has_comment_before(line) {
+            let comment = self.comments[self.next_comment].clone();
+            self.next_comment += 1;
+
+            if comment.trailing && self.output.ends_with('\n') {
+                self.output.pop();
+                self.write(" ");
+            } else {
+                self.blank_line_before(comment.line);
+                self.write_indent();
+            }
+            self.write(&comment.text);
+            self.output.push('\n');
+            self.at_block_start = false;
+
+            if let Some(text) = comment.text.strip_prefix("///") {
+                if !text.starts_with('/') {
+                    doc_lines.push(text.strip_prefix(' ').unwrap_or(text).trim_end().to_owned());
+                }
+            }
+        }
+        if doc_lines.is_empty() {
+            None
+        } else {
+            Some(doc_lines.join("\n"))
+        }
+    }
+
+    /// Prints the comments and blank line that precede a statement or method, then indents the
+    /// line it starts on.
+    fn start_line(&mut self, line: usize) -> Option<String> {
+        let doc = self.comments_before(line);
+        self.blank_line_before(line);
+        self.write_indent();
+        self.at_block_start = false;
+        doc
+    }
+
+    fn statement(&mut self, statement: &Stmt) {
+        match statement {
+            Stmt::Class(class) => {
+                let doc_comment = self.start_line(declaration_line(&class.attributes, &class.name));
+                self.attributes(&class.attributes, &class.doc, doc_comment);
+                self.write("class ");
+                self.write(&class.name.name);
+                if class.methods.is_empty()
+                    && class.fields.is_empty()
+                    && !self.has_comment_before(class.end_line)
+                {
+                    self.write(" {}");
+                } else {
+                    self.write(" {\n");
+                    self.indent += 1;
+                    self.at_block_start = true;
+                    // Fields and methods are stored separately, so interleave them as they were
+                    // declared.
+                    let mut fields = class.fields.iter().peekable();
+                    for method in &class.methods {
+                        let line = declaration_line(&method.attributes, &method.name);
+                        let start = declaration_start(&method.attributes, &method.name);
+                        while let Some(field) =
+                            fields.next_if(|f| declaration_start(&f.attributes, &f.name) < start)
+                        {
+                            self.field(field);
+                        }
+                        let doc_comment = self.start_line(line);
+                        self.attributes(&method.attributes, &method.doc, doc_comment);
+                        self.write("fn ");
+                        self.write(&method.name.name);
+                        self.function(&method.function, method.kind() != MethodKind::Static);
+                        self.output.push('\n');
+                    }
+                    for field in fields {
+                        self.field(field);
+                    }
+                    self.end_block(class.end_line);
+                }
+            }
+            Stmt::Fn(decl) => {
+                let doc_comment = self.start_line(declaration_line(&decl.attributes, &decl.name));
+                self.attributes(&decl.attributes, &decl.doc, doc_comment);
+                self.write("fn ");
+                self.write(&decl.name.name);
+                self.function(&decl.function, false);
+            }
+            Stmt::Var {
+                name, initialiser, ..
+            } => {
+                self.start_line(name.line);
+                self.write("var ");
+                self.write(&name.name);
+                if let Some(initialiser) = initialiser {
+                    self.write(" = ");
+                    self.expression(initialiser);
+                }
+                self.write(";");
+            }
+            Stmt::Import {
+                attributes,
+                path,
+                alias,
+                ..
+            } => {
+                self.start_line(declaration_line(attributes, path));
+                self.attributes(attributes, &None, None);
+                self.write("import ");
+                self.string(&path.name);
+                if let Some(alias) = alias {
+                    self.write(" as ");
+                    self.write(&alias.name);
+                }
+                self.write(";");
+            }
+            Stmt::Export { names, .. } => {
+                self.start_line(statement.line());
+                let names: Vec<&str> = names.iter().map(|n| n.name.as_str()).collect();
+                self.write("export ");
+                self.write(&names.join(", "));
+                self.write(";");
+            }
+            Stmt::Expression { expr, .. } => {
+                self.start_line(expr.line());
+                self.expression(expr);
+                self.write(";");
+            }
+            Stmt::If { .. } => {
+                self.start_line(statement.line());
+                self.if_statement(statement);
+            }
+            Stmt::Return { value, .. } => {
+                self.start_line(statement.line());
+                self.write("return");
+                if let Some(value) = value {
+                    self.write(" ");
+                    self.expression(value);
+                }
+                self.write(";");
+            }
+            Stmt::Yield { value, .. } => {
+                self.start_line(statement.line());
+                self.write("yield ");
+                self.expression(value);
+                self.write(";");
+            }
+            Stmt::Throw { value, .. } => {
+                self.start_line(statement.line());
+                self.write("throw ");
+                self.expression(value);
+                self.write(";");
+            }
+            Stmt::For {
+                variable,
+                iterable,
+                body,
+                ..
+            } => {
+                self.start_line(statement.line());
+                self.write("for ");
+                self.write(&variable.name);
+                self.write(" in ");
+                self.expression(iterable);
+                self.write(" ");
+                self.block(body);
+            }
+            Stmt::While {
+                condition, body, ..
+            } => {
+                self.start_line(statement.line());
+                self.write("while ");
+                self.expression(condition);
+                self.write(" ");
+                self.block(body);
+            }
+            Stmt::Try {
+                body,
+                catches,
+                finally,
+                ..
+            } => {
+                self.start_line(statement.line());
+                self.write("try ");
+                self.block(body);
+                for catch in catches {
+                    self.write(" catch ");
+                    self.write(&catch.variable.name);
+                    if let Some(class) = &catch.class {
+                        self.write(": ");
+                        self.expression(class);
+                    }
+                    self.write(" ");
+                    self.block(&catch.body);
+                }
+                if let Some(finally) = finally {
+                    self.write(" finally ");
+                    self.block(finally);
+                }
+            }
+            Stmt::Break { .. } => {
+                self.start_line(statement.line());
+                self.write("break;");
+            }
+            Stmt::Continue { .. } => {
+                self.start_line(statement.line());
+                self.write("continue;");
+            }
+            Stmt::Block(block) => {
+                self.start_line(statement.line());
+                self.block(block);
+            }
+            Stmt::Error => return,
+        }
+        self.output.push('\n');
+    }
+
+    fn if_statement(&mut self, statement: &Stmt) {
+        match statement {
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                self.write("if ");
+                self.expression(condition);
+                self.write(" ");
+                self.block(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.write(" else ");
+                    self.if_statement(else_branch);
+                }
+            }
+            Stmt::Block(block) => self.block(block),
+            _ => {}
+        }
+    }
+
+    /// Prints an attribute list on its own line. A doc string is only printed as an attribute if
+    /// it didn't come from the doc comments printed before the declaration.
+    fn field(&mut self, field: &Field) {
+        let doc_comment = self.start_line(declaration_line(&field.attributes, &field.name));
+        self.attributes(&field.attributes, &field.doc, doc_comment);
+        self.write("var ");
+        self.write(&field.name.name);
+        if let Some(initialiser) = &field.initialiser {
+            self.write(" = ");
+            self.expression(initialiser);
+        }
+        self.write(";\n");
+    }
+
+    fn attributes(
+        &mut self,
+        attributes: &[Attribute],
+        doc: &Option<String>,
+        doc_comment: Option<String>,
+    ) {
+        let mut printed: Vec<_> = attributes.iter().map(attribute_to_string).collect();
+        if let Some(doc) = doc {
+            if doc_comment.as_ref() != Some(doc) && is_identifier(doc) {
+                printed.insert(0, format!("doc({})", doc));
+            }
+        }
+        if printed.is_empty() {
+            return;
+        }
+        self.write("#[");
+        self.write(&printed.join(", "));
+        self.write("]\n");
+        self.write_indent();
+    }
+
+    /// Prints the attributes that apply to the whole module as a single `#![...]` list.
+    fn module_attributes(&mut self, attributes: &[Attribute]) {
+        if let Some(first) = attributes.first() {
+            self.start_line(first.name.line);
+            let printed: Vec<_> = attributes.iter().map(attribute_to_string).collect();
+            self.write("#![");
+            self.write(&printed.join(", "));
+            self.write("]\n");
+        }
+    }
+
+    fn function(&mut self, function: &Function, has_self: bool) {
+        let mut parameters: Vec<&str> = function
+            .parameters
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect();
+        if has_self {
+            parameters.insert(0, "self");
+        }
+        self.write("(");
+        self.write(&parameters.join(", "));
+        self.write(") ");
+        self.block(&function.body);
+    }
+
+    fn block(&mut self, block: &Block) {
+        if block.statements.is_empty() && !self.has_comment_before(block.end_line) {
+            self.write("{}");
+            return;
+        }
+        self.write("{\n");
+        self.indent += 1;
+        self.at_block_start = true;
+        for statement in &block.statements {
+            self.statement(statement);
+        }
+        self.end_block(block.end_line);
+    }
+
+    fn end_block(&mut self, end_line: usize) {
+        self.comments_before(end_line);
+        self.indent -= 1;
+        self.write_indent();
+        self.write("}");
+        self.at_block_start = false;
+    }
+
+    fn expressions(&mut self, expressions: &[Expr]) {
+        for (i, expr) in expressions.iter().enumerate() {
+            if i > 0 {
+                self.write(", ");
+            }
+            self.expression(expr);
+        }
+    }
+
+    fn arguments(&mut self, arguments: &[Argument]) {
+        for (i, argument) in arguments.iter().enumerate() {
+            if i > 0 {
+                self.write(", ");
+            }
+            match argument {
+                Argument::Positional(value) => self.expression(value),
+                Argument::Named(name, value) => {
+                    self.write(&name.name);
+                    self.write(": ");
+                    self.expression(value);
+                }
+                Argument::Spread { value, .. } => {
+                    self.write("*");
+                    self.expression(value);
+                }
+            }
+        }
+    }
+
+    fn string(&mut self, value: &str) {
+        self.write("\"");
+        self.write(&escape(value));
+        self.write("\"");
+    }
+
+    fn assignment(&mut self, operator: Option<BinaryOperator>, value: &Expr) {
+        self.write(" ");
+        if let Some(operator) = operator {
+            self.write(binary_operator(operator));
+        }
+        self.write("= ");
+        self.expression(value);
+    }
+
+    fn expression(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Number { text, .. } => self.write(text),
+            Expr::Str { value, .. } => self.string(value),
+            Expr::Interpolation { parts, tail, .. } => {
+                self.write("\"");
+                for part in parts {
+                    self.write(&escape(&part.text));
+                    self.write("${");
+                    self.expression(&part.expr);
+                    self.write("}");
+                }
+                self.write(&escape(tail));
+                self.write("\"");
+            }
+            Expr::Literal { value, .. } => self.write(match value {
+                Literal::Nil => "nil",
+                Literal::True => "true",
+                Literal::False => "false",
+            }),
+            Expr::Variable(name) => self.write(&name.name),
+            Expr::Assign {
+                name,
+                operator,
+                value,
+                ..
+            } => {
+                self.write(&name.name);
+                self.assignment(operator.map(|o| o.operator), value);
+            }
+            Expr::SelfRef { .. } => self.write("self"),
+            Expr::SelfClass { .. } => self.write("Self"),
+            Expr::Super { method, call, .. } => {
+                self.write("super.");
+                self.write(&method.name);
+                if let Some((arguments, _)) = call {
+                    self.write("(");
+                    self.arguments(arguments);
+                    self.write(")");
+                }
+            }
+            Expr::Grouping(expr) => {
+                self.write("(");
+                self.expression(expr);
+                self.write(")");
+            }
+            Expr::Tuple { elements, .. } => {
+                self.write("(");
+                self.expressions(elements);
+                if elements.len() == 1 {
+                    self.write(",");
+                }
+                self.write(")");
+            }
+            Expr::Vec { elements, .. } => {
+                self.write("[");
+                self.arguments(elements);
+                self.write("]");
+            }
+            Expr::HashMap { entries, .. } => {
+                self.write("{");
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        self.write(", ");
+                    }
+                    self.expression(key);
+                    self.write(": ");
+                    self.expression(value);
+                }
+                self.write("}");
+            }
+            Expr::Unary {
+                operator, operand, ..
+            } => {
+                self.write(match operator {
+                    UnaryOperator::Negate => "-",
+                    UnaryOperator::Not => "!",
+                    UnaryOperator::BitwiseNot => "~",
+                });
+                self.expression(operand);
+            }
+            Expr::Binary {
+                operator,
+                left,
+                right,
+                ..
+            } => {
+                self.expression(left);
+                self.write(" ");
+                self.write(binary_operator(*operator));
+                self.write(" ");
+                self.expression(right);
+            }
+            Expr::Logical {
+                operator,
+                left,
+                right,
+                ..
+            } => {
+                self.expression(left);
+                self.write(match operator {
+                    LogicalOperator::And => " && ",
+                    LogicalOperator::Or => " || ",
+                });
+                self.expression(right);
+            }
+            Expr::Range {
+                start, end, step, ..
+            } => {
+                self.expression(start);
+                self.write("..");
+                self.expression(end);
+                if let Some(step) = step {
+                    self.write(":");
+                    self.expression(step);
+                }
+            }
+            Expr::Call {
+                callee, arguments, ..
+            } => {
+                self.expression(callee);
+                self.write("(");
+                self.arguments(arguments);
+                self.write(")");
+            }
+            Expr::Get { object, name } => {
+                self.expression(object);
+                self.write(".");
+                self.write(&name.name);
+            }
+            Expr::Set {
+                object,
+                name,
+                operator,
+                value,
+                ..
+            } => {
+                self.expression(object);
+                self.write(".");
+                self.write(&name.name);
+                self.assignment(operator.map(|o| o.operator), value);
+            }
+            Expr::Index { object, index, .. } => {
+                self.expression(object);
+                self.write("[");
+                self.expression(index);
+                self.write("]");
+            }
+            Expr::SetIndex {
+                object,
+                index,
+                operator,
+                value,
+                ..
+            } => {
+                self.expression(object);
+                self.write("[");
+                self.expression(index);
+                self.write("]");
+                self.assignment(operator.map(|o| o.operator), value);
+            }
+            Expr::Lambda { parameters, body } => {
+                let parameters: Vec<&str> = parameters.iter().map(|p| p.name.as_str()).collect();
+                self.write("|");
+                self.write(&parameters.join(", "));
+                self.write("| ");
+                match body {
+                    LambdaBody::Block(block) => self.block(block),
+                    LambdaBody::Expr { expr, .. } => self.expression(expr),
+                }
+            }
+            Expr::Comprehension(comprehension) => {
+                let (open, close) = match comprehension.kind {
+                    ComprehensionKind::Vec => ("[", "]"),
+                    ComprehensionKind::Generator => ("(", ")"),
+                };
+                self.write(open);
+                self.expression(&comprehension.element);
+                self.write(" for ");
+                self.write(&comprehension.variable.name);
+                self.write(" in ");
+                self.expression(&comprehension.iterable);
+                if let Some(condition) = &comprehension.condition {
+                    self.write(" if ");
+                    self.expression(condition);
+                }
+                self.write(close);
+            }
+            Expr::Error => {}
+        }
+    }
+}
+
+/// The line a declaration starts on, including any attribute list before it.
+fn declaration_line(attributes: &[Attribute], name: &Identifier) -> usize {
+    attributes
+        .iter()
+        .map(|a| a.name.line)
+        .chain(Some(name.line))
+        .min()
+        .unwrap_or(name.line)
+}
+
+/// The line and byte offset at which a declaration starts, including its attributes, which
+/// orders declarations that share a line.
+fn declaration_start(attributes: &[Attribute], name: &Identifier) -> (usize, Option<usize>) {
+    attributes
+        .iter()
+        .map(|a| &a.name)
+        .chain(Some(name))
+        .map(|identifier| (identifier.line, identifier.offset))
+        .min()
+        .unwrap_or((name.line, name.offset))
+}
+
+fn attribute_to_string(attribute: &Attribute) -> String {
+    if attribute.arguments.is_empty() {
+        return attribute.name.name.clone();
+    }
+    let arguments: Vec<&str> = attribute
+        .arguments
+        .iter()
+        .map(|a| a.name.as_str())
+        .collect();
+    format!("{}({})", attribute.name.name, arguments.join(", "))
+}
+
+fn is_identifier(text: &str) -> bool {
+    !text.is_empty()
+        && !text.starts_with(|c: char| c.is_ascii_digit())
+        && text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn binary_operator(operator: BinaryOperator) -> &'static str {
+    match operator {
+        BinaryOperator::Add => "+",
+        BinaryOperator::Subtract => "-",
+        BinaryOperator::Multiply => "*",
+        BinaryOperator::Divide => "/",
+        BinaryOperator::Modulo => "%",
+        BinaryOperator::Equal => "==",
+        BinaryOperator::NotEqual => "!=",
+        BinaryOperator::Greater => ">",
+        BinaryOperator::GreaterEqual => ">=",
+        BinaryOperator::Less => "<",
+        BinaryOperator::LessEqual => "<=",
+        BinaryOperator::Is => "is",
+        BinaryOperator::BitwiseAnd => "&",
+        BinaryOperator::BitwiseOr => "|",
+        BinaryOperator::BitwiseXor => "^",
+        BinaryOperator::BitShiftLeft => "<<",
+        BinaryOperator::BitShiftRight => ">>",
+    }
+}
+
+/// Escapes the contents of a string literal so that the scanner reads back the same value.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '$' => escaped.push_str("\\$"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            '\0' => escaped.push_str("\\0"),
+            '\x07' => escaped.push_str("\\a"),
+            '\x08' => escaped.push_str("\\b"),
+            '\x0b' => escaped.push_str("\\v"),
+            '\x0c' => escaped.push_str("\\f"),
+            c if c.is_ascii_control() => escaped.push_str(&format!("\\x{:02x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[test]
+fn test_format_source() {
+    let source = "\
+import \"lib/foo\"  as   foo ;
+/// Adds things.
+#[generator]
+fn  add( a,b ){
+  var c=[a,b,*foo];  // Trailing.
+  yield {\"x\":(c,),\"y\":|x|x+1};
+
+
+  if a>b&&!c { print(\"a\\n\\$\"); } else if b {} else {
+    /* Block. */
+  }
+}
+#[constructor(new)]
+class Foo {
+fn init(self,x) { self.x=x;self.y+=-x;self.v[0]*=x; }
+  #[static] fn make(){return Foo.new(x:\"${1..2}\");}
+}
+";
+    let expected = "\
+import \"lib/foo\" as foo;
+/// Adds things.
+#[generator]
+fn add(a, b) {
+    var c = [a, b, *foo]; // Trailing.
+    yield {\"x\": (c,), \"y\": |x| x + 1};
+
+    if a > b && !c {
+        print(\"a\\n\\$\");
+    } else if b {} else {
+        /* Block. */
+    }
+}
+#[constructor(new)]
+class Foo {
+    fn init(self, x) {
+        self.x = x;
+        self.y += -x;
+        self.v[0] *= x;
+    }
+    #[static]
+    fn make() {
+        return Foo.new(x: \"${1..2}\");
+    }
+}
+";
+    let formatted = crate::compiler::format_source(source);
+    assert_eq!(expected, formatted);
+    assert_eq!(formatted, crate::compiler::format_source(&formatted));
+}
+
+#[test]
+fn test_format_source_with_shebang() {
+    let source = "#!/usr/bin/env yarel\nprint( 1 );\n";
+    let expected = "#!/usr/bin/env yarel\nprint(1);\n";
+    assert_eq!(expected, crate::compiler::format_source(source));
+}
+
+#[test]
+fn test_format_source_with_syntax_error() {
+    let source = "var x = ;\n";
+    assert_eq!(source, crate::compiler::format_source(source));
+}
+
+#[test]
+fn test_format_source_with_comprehensions() {
+    let source = "var v=[ x*2 for x in xs if x>0 ];\nvar g=(x for x in 0..3);\n";
+    let expected = "var v = [x * 2 for x in xs if x > 0];\nvar g = (x for x in 0..3);\n";
+    assert_eq!(expected, crate::compiler::format_source(source));
+}
+
+#[test]
+fn test_format_source_with_fields() {
+    let source = "class A {\n#[static]\nvar count=0;\nfn f(self) {}\nvar names;\n}\n";
+    let expected =
+        "class A {\n    #[static]\n    var count = 0;\n    fn f(self) {}\n    var names;\n}\n";
+    assert_eq!(expected, crate::compiler::format_source(source));
+}
+
+#[test]
+fn test_format_source_with_class_on_one_line() {
+    let source = "class A { var x = 1; fn g(self) { return self.x; } var y; }\n";
+    let expected =
+        "class A {\n    var x = 1;\n    fn g(self) {\n        return self.x;\n    }\n    var y;\n}\n";
+    assert_eq!(expected, crate::compiler::format_source(source));
+}
+
+#[test]
+fn test_format_source_with_number_literals() {
+    let source = "print(0xFF_FF+1_000.5+2.5e-3+0b101);\n";
+    let expected = "print(0xFF_FF + 1_000.5 + 2.5e-3 + 0b101);\n";
+    assert_eq!(expected, crate::compiler::format_source(source));
+}
+
+#[test]
+fn test_format_source_with_lazy_import() {
+    let source = "#[lazy]   import  \"lib/foo\" as foo;\n";
+    let expected = "#[lazy]\nimport \"lib/foo\" as foo;\n";
+    assert_eq!(expected, crate::compiler::format_source(source));
+}
+
+#[test]
+fn test_format_source_with_module_attributes() {
+    let source = "// Header.\n#![ strict ]\n#![lang( 1 )]\n\nvar x = 1;\n";
+    let expected = "// Header.\n#![strict, lang(1)]\n\nvar x = 1;\n";
+    assert_eq!(expected, crate::compiler::format_source(source));
+}
@@ -13,10 +13,22 @@
  * limitations under the License.
  */
 
-use std::convert::TryInto;
-use std::hash::{BuildHasher, Hasher};
+use core::convert::TryInto;
+#[cfg(not(feature = "std"))]
+use core::hash::BuildHasherDefault;
+use core::hash::{BuildHasher, Hasher};
+#[cfg(feature = "std")]
+pub(crate) use std::collections::{HashMap, HashSet};
 
-pub(crate) struct FnvHasher {
+/// Without `std` there's no source of random keys for hashing, so maps and sets that don't
+/// specify a hasher use FNV instead.
+#[cfg(not(feature = "std"))]
+pub(crate) type HashMap<K, V, S = BuildHasherDefault<FnvHasher>> = hashbrown::HashMap<K, V, S>;
+
+#[cfg(not(feature = "std"))]
+pub(crate) type HashSet<T, S = BuildHasherDefault<FnvHasher>> = hashbrown::HashSet<T, S>;
+
+pub struct FnvHasher {
     hash: u64,
 }
 
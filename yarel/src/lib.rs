@@ -13,19 +13,62 @@
  * limitations under the License.
  */
 
+//! An embeddable scripting language with a bytecode virtual machine and garbage-collected heap.
+//!
+//! # Embedded targets
+//!
+//! With the default features disabled, the crate is `no_std` and only needs `core` and `alloc`.
+//! The virtual machine then doesn't use the file system, stdio or the system clock, so the host
+//! provides each of them instead, using `Vm::set_module_loader`, `Vm::set_printer` and
+//! `Vm::set_clock`. Until it does, imports fail to find any module and `print` and `clock` raise
+//! errors.
+//!
+//! Without `std`, scripts can't import the `os` and `env` modules, debugging output is discarded
+//! unless a diagnostics sink is set, and output can't be sent to an `std::io::Write`. There are
+//! also no thread-locals, so the garbage-collected heap is shared by the whole program and only
+//! accessed within a critical section. The host provides critical sections by linking an
+//! implementation of the `critical-section` crate for its target. A `Vm` and the objects it
+//! allocates can't be sent to other threads either way.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+// Allows the paths generated by embed_modules! to be used within this crate.
+extern crate self as yarel;
+
 #[macro_use]
 pub mod error;
+mod arena;
+pub mod ast;
 pub mod chunk;
 pub mod class_store;
 mod common;
 pub mod compiler;
 mod core;
+pub mod coverage;
 mod debug;
+pub mod diagnostic;
+mod formatter;
 mod hash;
+pub mod loader;
 pub mod memory;
+#[cfg(feature = "native_modules")]
+pub mod native;
 pub mod object;
+mod parser;
+pub mod profiler;
+pub mod program;
+mod resolver;
 mod scanner;
+#[cfg(feature = "serde")]
+mod serialization;
 mod stack;
+pub mod stats;
+pub mod symbol;
+pub mod trace;
 mod utils;
 pub mod value;
+mod verify;
 pub mod vm;
+pub mod warning;
@@ -0,0 +1,315 @@
+/* Copyright 2020-2021 Matt Spraggs
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use alloc::borrow::ToOwned;
+use alloc::format;
+use alloc::string::String;
+#[cfg(feature = "fs")]
+use std::fs;
+#[cfg(feature = "fs")]
+use std::io;
+#[cfg(feature = "fs")]
+use std::path::PathBuf;
+
+pub use yarel_embed::embed_modules;
+
+use crate::error::{Error, ErrorCode, ErrorKind};
+use crate::hash::HashMap;
+
+/// The source code of a module, as provided by a module loader.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ModuleSource {
+    pub source: String,
+    /// Whether the module is the index of a package, in which case relative imports within it are
+    /// resolved relative to the package rather than its parent.
+    pub is_package: bool,
+}
+
+impl ModuleSource {
+    pub fn new(source: String) -> Self {
+        ModuleSource {
+            source,
+            is_package: false,
+        }
+    }
+}
+
+/// Provides the source code of imported modules to the virtual machine.
+pub trait ModuleLoader {
+    /// Maps the path of an imported module, after any relative path has been resolved, to the
+    /// path the module is identified by. By default the path is used unchanged.
+    fn resolve(&self, path: &str) -> Result<String, Error> {
+        Ok(path.to_owned())
+    }
+
+    /// Returns the source of the module with the specified resolved path.
+    fn load(&mut self, path: &str) -> Result<ModuleSource, Error>;
+
+    /// Called when a loaded module is discarded, e.g. because it failed to compile, so that any
+    /// data cached for it can be dropped before it's loaded again.
+    fn invalidate(&mut self, _path: &str) {}
+}
+
+impl<F> ModuleLoader for F
+where
+    F: FnMut(&str) -> Result<String, Error>,
+{
+    fn load(&mut self, path: &str) -> Result<ModuleSource, Error> {
+        self(path).map(ModuleSource::new)
+    }
+}
+
+/// Reads modules from the first search directory that holds either a file with the module's path
+/// and a `.yl` extension or a package directory with a `mod.yl` index. Modules are looked for
+/// relative to the current directory first, then in each search directory in the order they were
+/// added.
+#[cfg(feature = "fs")]
+#[derive(Clone, Debug)]
+pub struct FileSystemLoader {
+    search_paths: Vec<PathBuf>,
+}
+
+#[cfg(feature = "fs")]
+impl FileSystemLoader {
+    pub fn new() -> Self {
+        FileSystemLoader {
+            search_paths: vec![PathBuf::new()],
+        }
+    }
+
+    pub fn add_search_path(&mut self, path: impl Into<PathBuf>) {
+        self.search_paths.push(path.into());
+    }
+}
+
+#[cfg(feature = "fs")]
+impl Default for FileSystemLoader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "fs")]
+impl ModuleLoader for FileSystemLoader {
+    fn load(&mut self, path: &str) -> Result<ModuleSource, Error> {
+        let mut candidates = Vec::new();
+        for dir in &self.search_paths {
+            let base = dir.join(path);
+            candidates.push((base.with_extension("yl"), false));
+            candidates.push((base.join("mod.yl"), true));
+        }
+        // If the module can't be found, the error refers to the first place it was looked for.
+        let (first_candidate, _) = candidates.first().cloned().unwrap_or_default();
+        let (filename, is_package) = candidates
+            .into_iter()
+            .find(|(candidate, _)| candidate.exists())
+            .unwrap_or((first_candidate, false));
+
+        let filename = match filename.as_path().to_str() {
+            Some(p) => p,
+            None => {
+                return Err(error!(
+                    ErrorKind::RuntimeError,
+                    "Error converting module path to string."
+                ));
+            }
+        };
+
+        let source = match fs::read_to_string(filename) {
+            Ok(s) => s,
+            Err(e) => {
+                let reason = match e.kind() {
+                    io::ErrorKind::NotFound => "file not found",
+                    io::ErrorKind::PermissionDenied => "permission denied",
+                    io::ErrorKind::ConnectionRefused => "connection refused",
+                    io::ErrorKind::ConnectionReset => "connection reset",
+                    io::ErrorKind::ConnectionAborted => "connection aborted",
+                    io::ErrorKind::NotConnected => "not connected",
+                    io::ErrorKind::AddrInUse => "address in use",
+                    io::ErrorKind::AddrNotAvailable => "address not available",
+                    io::ErrorKind::BrokenPipe => "broken pipe",
+                    io::ErrorKind::AlreadyExists => "already exists",
+                    io::ErrorKind::WouldBlock => "would block",
+                    io::ErrorKind::InvalidInput => "invalid input",
+                    io::ErrorKind::InvalidData => "invalid data",
+                    io::ErrorKind::TimedOut => "timed out",
+                    io::ErrorKind::WriteZero => "write zero",
+                    io::ErrorKind::Interrupted => "interrupted",
+                    io::ErrorKind::Other => "other",
+                    io::ErrorKind::UnexpectedEof => "unexpected end-of-file",
+                    _ => "other",
+                };
+                let error = error!(
+                    ErrorKind::ImportError,
+                    "Unable to read file '{}' ({}).", filename, reason
+                );
+                return Err(if e.kind() == io::ErrorKind::NotFound {
+                    error.with_code(ErrorCode::ModuleNotFound)
+                } else {
+                    error
+                });
+            }
+        };
+
+        Ok(ModuleSource { source, is_package })
+    }
+}
+
+/// Serves modules from source held in memory, e.g. embedded in the executable using
+/// [`embed_modules!`]. A module whose path ends in `/mod` is also the index of the package with
+/// the remainder of the path.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryModuleLoader {
+    sources: HashMap<String, String>,
+}
+
+impl MemoryModuleLoader {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn insert(&mut self, path: impl Into<String>, source: impl Into<String>) {
+        self.sources.insert(path.into(), source.into());
+    }
+}
+
+impl From<HashMap<String, String>> for MemoryModuleLoader {
+    fn from(sources: HashMap<String, String>) -> Self {
+        MemoryModuleLoader { sources }
+    }
+}
+
+impl ModuleLoader for MemoryModuleLoader {
+    fn load(&mut self, path: &str) -> Result<ModuleSource, Error> {
+        if let Some(source) = self.sources.get(path) {
+            return Ok(ModuleSource::new(source.clone()));
+        }
+        match self.sources.get(&format!("{}/mod", path)) {
+            Some(source) => Ok(ModuleSource {
+                source: source.clone(),
+                is_package: true,
+            }),
+            None => Err(
+                error!(ErrorKind::ImportError, "Unable to find module '{}'.", path)
+                    .with_code(ErrorCode::ModuleNotFound),
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "fs")]
+#[test]
+fn test_file_system_loader() {
+    let root = std::env::temp_dir().join(format!("yarel_search_paths_{}", std::process::id()));
+    fs::create_dir_all(root.join("lib/pkg")).unwrap();
+    fs::write(root.join("lib/single.yl"), "var a = 1;").unwrap();
+    fs::write(
+        root.join("lib/pkg/mod.yl"),
+        "import \"./helper\";\nvar b = helper.c;",
+    )
+    .unwrap();
+    fs::write(root.join("lib/pkg/helper.yl"), "var c = 2;").unwrap();
+
+    let mut loader = FileSystemLoader {
+        search_paths: vec![root.join("missing"), root.join("lib")],
+    };
+    assert_eq!(
+        ModuleSource::new("var a = 1;".to_string()),
+        loader.load("single").unwrap()
+    );
+    assert!(loader.load("pkg").unwrap().is_package);
+    let error = loader.load("other").unwrap_err();
+    assert_eq!(
+        format!(
+            "Unable to read file '{}' (file not found).",
+            root.join("missing/other.yl").display()
+        ),
+        error.messages()[0]
+    );
+
+    let mut loader = FileSystemLoader::new();
+    loader.add_search_path(root.join("lib"));
+    let mut vm = crate::vm::Vm::with_built_ins();
+    vm.set_module_loader(loader);
+    let source = "import \"pkg\";\nif pkg.b != 2 {\n    throw Error.new(\"Wrong value.\");\n}\n";
+    let result = crate::vm::interpret(&mut vm, source.to_string(), None);
+    fs::remove_dir_all(root).unwrap();
+    result.unwrap();
+}
+
+#[test]
+fn test_stateful_module_loader() {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    struct MapLoader {
+        sources: HashMap<String, String>,
+        invalidated: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl ModuleLoader for MapLoader {
+        fn resolve(&self, path: &str) -> Result<String, Error> {
+            Ok(path.trim_start_matches("app:").to_owned())
+        }
+
+        fn load(&mut self, path: &str) -> Result<ModuleSource, Error> {
+            match self.sources.remove(path) {
+                Some(source) => Ok(ModuleSource::new(source)),
+                None => Err(error!(ErrorKind::ImportError, "No module '{}'.", path)),
+            }
+        }
+
+        fn invalidate(&mut self, path: &str) {
+            self.invalidated.borrow_mut().push(path.to_owned());
+        }
+    }
+
+    let mut sources = HashMap::new();
+    sources.insert("a".to_string(), "var x = 1;".to_string());
+    sources.insert("b".to_string(), "var y = ;".to_string());
+    let invalidated = Rc::new(RefCell::new(Vec::new()));
+    let mut vm = crate::vm::Vm::with_built_ins();
+    vm.set_module_loader(MapLoader {
+        sources,
+        invalidated: invalidated.clone(),
+    });
+
+    let source = "import \"app:a\";\nimport \"a\";\nif a.x != 1 {\n    throw Error.new(\"Wrong value.\");\n}\n";
+    crate::vm::interpret(&mut vm, source.to_string(), None).unwrap();
+    let error = crate::vm::interpret(&mut vm, "import \"b\";".to_string(), None).unwrap_err();
+    assert_eq!(ErrorKind::ImportError, error.kind());
+    assert_eq!(vec!["b".to_string()], *invalidated.borrow());
+}
+
+#[test]
+fn test_memory_module_loader() {
+    let mut loader = embed_modules!("tests/scripts/modules");
+    let foo = loader.load("foo").unwrap();
+    assert!(foo.source.contains("fn print_name()"));
+    assert!(!foo.is_package);
+    assert!(loader.load("relative/sibling").is_ok());
+    let error = loader.load("missing").unwrap_err();
+    assert_eq!("Unable to find module 'missing'.", error.messages()[0]);
+    assert_eq!(ErrorCode::ModuleNotFound, error.code());
+
+    loader.insert("pkg/mod", "import \"./helper\";\nvar b = helper.c;");
+    loader.insert("pkg/helper", "var c = 2;");
+    assert!(loader.load("pkg").unwrap().is_package);
+    let mut vm = crate::vm::Vm::with_built_ins();
+    vm.set_module_loader(loader);
+    let source = "import \"pkg\";\nif pkg.b != 2 {\n    throw Error.new(\"Wrong value.\");\n}\n";
+    crate::vm::interpret(&mut vm, source.to_string(), None).unwrap();
+}
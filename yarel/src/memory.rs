@@ -16,20 +16,85 @@
 // The code below is in part inspired by the mark-and-sweep GC implemented here:
 // https://github.com/Darksecond/lox
 
-use std::any;
-use std::cell::{Cell, RefCell};
-use std::collections::HashMap;
-use std::fmt::Debug;
-use std::marker::PhantomPinned;
-use std::mem;
-use std::ops::{Deref, DerefMut};
-use std::pin::Pin;
-use std::ptr::NonNull;
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::any;
+#[cfg(not(feature = "std"))]
+use core::cell::OnceCell;
+use core::cell::{Cell, RefCell};
+use core::fmt::Debug;
+use core::marker::PhantomPinned;
+use core::mem;
+use core::ops::{Deref, DerefMut};
+use core::panic::Location;
+use core::pin::Pin;
+use core::ptr::NonNull;
+use core::time::Duration;
+#[cfg(feature = "system_clock")]
+use std::time::Instant;
 
 use crate::common;
+use crate::hash::HashMap;
 
+#[cfg(feature = "std")]
 thread_local! {
     static HEAP: RefCell<Heap> = RefCell::new(Heap::new());
+    /// Whether freed objects are being kept in place, poisoned, which is tracked outside the heap
+    /// so that it can be checked whilst the heap is borrowed.
+    static KEEP_FREED: Cell<bool> = const { Cell::new(cfg!(feature = "gc_poison")) };
+}
+
+#[cfg(not(feature = "std"))]
+static HEAP: Global<RefCell<Heap>> = Global::new(|| RefCell::new(Heap::new()));
+#[cfg(not(feature = "std"))]
+static KEEP_FREED: Global<Cell<bool>> = Global::new(|| Cell::new(cfg!(feature = "gc_poison")));
+
+/// A value shared by the whole program, initialised when it's first used, which stands in for a
+/// thread-local without `std`. It's only accessed within a critical section, which the host
+/// provides by linking an implementation of the `critical-section` crate for its target.
+#[cfg(not(feature = "std"))]
+struct Global<T> {
+    value: critical_section::Mutex<OnceCell<T>>,
+    init: fn() -> T,
+}
+
+#[cfg(not(feature = "std"))]
+impl<T: Send> Global<T> {
+    const fn new(init: fn() -> T) -> Self {
+        Global {
+            value: critical_section::Mutex::new(OnceCell::new()),
+            init,
+        }
+    }
+
+    fn with<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        critical_section::with(|cs| f(self.value.borrow(cs).get_or_init(self.init)))
+    }
+}
+
+// SAFETY: The heap refers to objects that aren't Send, so it isn't Send itself. Without `std` it's
+// only accessed within a critical section, and neither `Vm` nor the `Gc` and `Root` pointers to
+// its objects are Send, so those objects are only used by the thread that allocated them and by
+// the collector, which runs within the critical section.
+#[cfg(not(feature = "std"))]
+unsafe impl Send for Heap {}
+
+/// Prints what the garbage collector is doing with the `debug_trace_gc` feature.
+#[cfg(feature = "debug_trace_gc")]
+macro_rules! trace_gc {
+    ($($arg:tt)*) => {
+        std::println!($($arg)*)
+    };
+}
+
+#[cfg(not(feature = "debug_trace_gc"))]
+macro_rules! trace_gc {
+    ($($arg:tt)*) => {
+        if false {
+            let _ = format_args!($($arg)*);
+        }
+    };
 }
 
 #[derive(Copy, Clone, PartialEq)]
@@ -37,6 +102,8 @@ enum Colour {
     Black,
     Grey,
     White,
+    /// The object has been collected but is kept in place so that any further use is caught.
+    Freed,
 }
 
 pub trait GcManaged {
@@ -46,10 +113,16 @@ pub trait GcManaged {
 }
 
 type GcBoxPtr<T> = NonNull<GcBox<T>>;
+/// An object referred to by weak references, along with the flag that tells them whether it's
+/// still alive.
+type WeakTarget = (GcBoxPtr<dyn GcManaged>, Rc<Cell<bool>>);
 
 struct GcBox<T: GcManaged + ?Sized> {
     colour: Cell<Colour>,
     num_roots: Cell<usize>,
+    /// Where the object was allocated, reported if it's used after being freed.
+    #[cfg(any(debug_assertions, feature = "gc_poison"))]
+    allocated_at: &'static Location<'static>,
     _pin: PhantomPinned,
     pub(crate) data: T,
 }
@@ -60,12 +133,12 @@ impl<T: 'static + GcManaged + ?Sized> GcBox<T> {
     }
 
     fn mark(&self) {
-        if self.colour.replace(Colour::Grey) == Colour::Grey {
-            return;
-        }
-        if cfg!(feature = "debug_trace_gc") {
-            println!("{:?} mark", self as *const _);
+        match self.colour.replace(Colour::Grey) {
+            Colour::Grey => return,
+            Colour::Freed => self.use_after_free(),
+            _ => {}
         }
+        trace_gc!("{:?} mark", self as *const _);
         self.data.mark();
     }
 
@@ -73,9 +146,7 @@ impl<T: 'static + GcManaged + ?Sized> GcBox<T> {
         if self.colour.replace(Colour::Black) == Colour::Black {
             return;
         }
-        if cfg!(feature = "debug_trace_gc") {
-            println!("{:?} blacken", self as *const _);
-        }
+        trace_gc!("{:?} blacken", self as *const _);
         self.data.blacken();
     }
 
@@ -86,6 +157,21 @@ impl<T: 'static + GcManaged + ?Sized> GcBox<T> {
     fn dec_num_roots(&self) {
         self.num_roots.replace(self.num_roots.get() - 1);
     }
+
+    #[cold]
+    fn use_after_free(&self) -> ! {
+        #[cfg(any(debug_assertions, feature = "gc_poison"))]
+        panic!(
+            "{:?} allocated at {} used after being freed by the garbage collector, so is missing \
+             a root.",
+            self as *const _, self.allocated_at
+        );
+        #[cfg(not(any(debug_assertions, feature = "gc_poison")))]
+        panic!(
+            "{:?} used after being freed by the garbage collector, so is missing a root.",
+            self as *const _
+        );
+    }
 }
 
 pub struct Root<T: 'static + GcManaged + ?Sized> {
@@ -93,8 +179,10 @@ pub struct Root<T: 'static + GcManaged + ?Sized> {
 }
 
 impl<T: GcManaged> Root<T> {
+    #[track_caller]
     pub fn new(data: T) -> Root<T> {
-        HEAP.with(|heap| heap.borrow_mut().allocate_root(data))
+        let location = Location::caller();
+        HEAP.with(|heap| heap.borrow_mut().allocate_root(data, location))
     }
 
     pub fn as_gc(&self) -> Gc<T> {
@@ -149,7 +237,7 @@ impl<T: 'static + GcManaged + ?Sized> Clone for Root<T> {
 }
 
 impl<T: 'static + Debug + GcManaged> Debug for Root<T> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "{:?} @ {:p}", **self, self.as_ptr())
     }
 }
@@ -197,8 +285,10 @@ pub struct UniqueRoot<T: 'static + GcManaged + ?Sized> {
 }
 
 impl<T: GcManaged> UniqueRoot<T> {
+    #[track_caller]
     pub fn new(data: T) -> UniqueRoot<T> {
-        HEAP.with(|heap| heap.borrow_mut().allocate_unique(data))
+        let location = Location::caller();
+        HEAP.with(|heap| heap.borrow_mut().allocate_unique(data, location))
     }
 
     fn as_ptr(&self) -> *const T {
@@ -237,7 +327,7 @@ impl<T: 'static + GcManaged + ?Sized> GcManaged for UniqueRoot<T> {
 }
 
 impl<T: 'static + Debug + GcManaged> Debug for UniqueRoot<T> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "{:?} @ {:p}", **self, self.as_ptr())
     }
 }
@@ -282,11 +372,29 @@ impl<T: 'static + GcManaged> Gc<T> {
     pub(crate) fn as_ptr(&self) -> *const T {
         &self.gc_box().data
     }
+
+    pub(crate) fn downgrade(&self) -> WeakGc<T> {
+        let alive = Rc::new(Cell::new(true));
+        HEAP.with(|heap| heap.borrow_mut().weak_refs.push((self.ptr, alive.clone())));
+        WeakGc {
+            ptr: self.ptr,
+            alive,
+        }
+    }
 }
 
 impl<T: 'static + GcManaged + ?Sized> Gc<T> {
     fn gc_box(&self) -> &GcBox<T> {
-        unsafe { self.ptr.as_ref() }
+        let gc_box = unsafe { self.ptr.as_ref() };
+        // Objects are only kept in place once freed whilst poisoning them, so reading the colour
+        // of one otherwise reads deallocated memory.
+        if cfg!(any(debug_assertions, feature = "gc_poison"))
+            && KEEP_FREED.with(Cell::get)
+            && gc_box.colour.get() == Colour::Freed
+        {
+            gc_box.use_after_free();
+        }
+        gc_box
     }
 }
 
@@ -309,7 +417,7 @@ impl<T: GcManaged> Clone for Gc<T> {
 impl<T: GcManaged> Copy for Gc<T> {}
 
 impl<T: 'static + Debug + GcManaged> Debug for Gc<T> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "{:?} @ {:p}", **self, self.as_ptr())
     }
 }
@@ -328,10 +436,153 @@ impl<T: GcManaged> PartialEq for Gc<T> {
     }
 }
 
+pub(crate) fn collect() {
+    HEAP.with(|heap| heap.borrow_mut().collect());
+}
+
+pub(crate) fn configure(options: GcOptions) {
+    HEAP.with(|heap| heap.borrow_mut().configure(options));
+}
+
+pub(crate) fn stats() -> GcStats {
+    HEAP.with(|heap| {
+        let heap = heap.borrow();
+        GcStats {
+            heap_bytes: heap.bytes_allocated,
+            ..heap.stats
+        }
+    })
+}
+
+/// Settings for the garbage collector. The heap is shared by every virtual machine on a thread,
+/// so the settings apply to all of them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GcOptions {
+    /// The number of bytes that may be allocated before the next collection.
+    pub initial_heap_bytes: usize,
+    /// The factor by which the heap may grow after a collection before the next is triggered.
+    pub growth_factor: f64,
+    /// Whether to collect on every allocation, so that objects that aren't rooted are collected
+    /// as soon as they can be rather than whenever the heap next fills up.
+    pub stress: bool,
+    /// Whether to keep freed objects in place, poisoned, so that objects that are used after
+    /// being collected because they weren't rooted are caught deterministically. Freed objects
+    /// are never reused, so memory use grows without bound. Uses are caught whenever the
+    /// collector reaches a freed object, and in debug builds or with the `gc_poison` feature
+    /// whenever a freed object is dereferenced, in which case the panic reports where the object
+    /// was allocated.
+    pub poison_freed: bool,
+    /// If set, each collection that takes longer than the target halves the growth allowed
+    /// before the next, so that less garbage builds up, and each that doesn't restores it. Pauses
+    /// are only measured with the `system_clock` feature.
+    pub pause_target: Option<Duration>,
+}
+
+impl Default for GcOptions {
+    fn default() -> Self {
+        GcOptions {
+            initial_heap_bytes: common::HEAP_INIT_BYTES_MAX,
+            growth_factor: common::HEAP_GROWTH_FACTOR as f64,
+            stress: cfg!(feature = "gc_stress"),
+            poison_freed: cfg!(feature = "gc_poison"),
+            pause_target: None,
+        }
+    }
+}
+
+/// Statistics about the garbage collector, counted from when the current thread started.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GcStats {
+    pub collections: u64,
+    /// The total number of bytes allocated.
+    pub bytes_allocated: u64,
+    /// The total number of bytes freed by collections.
+    pub bytes_freed: u64,
+    /// The number of bytes currently allocated.
+    pub heap_bytes: usize,
+    /// The total time spent collecting, which is zero without the `system_clock` feature.
+    pub total_pause: Duration,
+    /// The duration of the longest collection.
+    pub max_pause: Duration,
+}
+
+/// Measures how long a collection takes, which is always zero without the `system_clock` feature.
+#[cfg(feature = "system_clock")]
+struct Timer(Instant);
+
+#[cfg(not(feature = "system_clock"))]
+struct Timer;
+
+#[cfg(feature = "system_clock")]
+impl Timer {
+    fn start() -> Self {
+        Timer(Instant::now())
+    }
+
+    fn elapsed(&self) -> Duration {
+        self.0.elapsed()
+    }
+}
+
+#[cfg(not(feature = "system_clock"))]
+impl Timer {
+    fn start() -> Self {
+        Timer
+    }
+
+    fn elapsed(&self) -> Duration {
+        Duration::default()
+    }
+}
+
+/// A reference to a garbage-collected object that doesn't keep the object alive.
+pub(crate) struct WeakGc<T: GcManaged + ?Sized> {
+    ptr: GcBoxPtr<T>,
+    alive: Rc<Cell<bool>>,
+}
+
+impl<T: GcManaged> WeakGc<T> {
+    /// Returns the object if it hasn't been collected.
+    pub(crate) fn upgrade(&self) -> Option<Gc<T>> {
+        if self.alive.get() {
+            Some(Gc { ptr: self.ptr })
+        } else {
+            None
+        }
+    }
+}
+
+impl<T: GcManaged> Clone for WeakGc<T> {
+    fn clone(&self) -> Self {
+        WeakGc {
+            ptr: self.ptr,
+            alive: self.alive.clone(),
+        }
+    }
+}
+
+impl<T: 'static + Debug + GcManaged> Debug for WeakGc<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.upgrade() {
+            Some(gc) => write!(f, "WeakGc({:?})", gc),
+            None => write!(f, "WeakGc(<collected>)"),
+        }
+    }
+}
+
 pub(crate) struct Heap {
     collection_threshold: usize,
     bytes_allocated: usize,
+    options: GcOptions,
+    /// The growth factor currently in use, which is reduced when collections exceed the pause
+    /// target.
+    growth_factor: f64,
+    stats: GcStats,
     objects: Vec<Pin<Box<GcBox<dyn GcManaged>>>>,
+    /// The objects freed whilst poisoning them, which are kept so that their use can be detected.
+    freed: Vec<Pin<Box<GcBox<dyn GcManaged>>>>,
+    /// The objects referred to by weak references.
+    weak_refs: Vec<WeakTarget>,
 }
 
 impl Heap {
@@ -339,24 +590,47 @@ impl Heap {
         Default::default()
     }
 
-    fn allocate_root<T: 'static + GcManaged>(&mut self, data: T) -> Root<T> {
+    fn configure(&mut self, options: GcOptions) {
+        self.options = options;
+        KEEP_FREED.with(|keep_freed| keep_freed.set(options.poison_freed));
+        self.growth_factor = options.growth_factor;
+        self.collection_threshold = self.bytes_allocated + options.initial_heap_bytes;
+    }
+
+    fn allocate_root<T: 'static + GcManaged>(
+        &mut self,
+        data: T,
+        location: &'static Location<'static>,
+    ) -> Root<T> {
         let root = Root {
-            ptr: self.allocate_raw(data),
+            ptr: self.allocate_raw(data, location),
         };
         root.inc_num_roots();
         root
     }
 
-    fn allocate_unique<T: 'static + GcManaged>(&mut self, data: T) -> UniqueRoot<T> {
+    fn allocate_unique<T: 'static + GcManaged>(
+        &mut self,
+        data: T,
+        location: &'static Location<'static>,
+    ) -> UniqueRoot<T> {
         let root = UniqueRoot {
-            ptr: self.allocate_raw(data),
+            ptr: self.allocate_raw(data, location),
         };
         root.inc_num_roots();
         root
     }
 
-    fn allocate_raw<T: 'static + GcManaged>(&mut self, data: T) -> GcBoxPtr<T> {
-        if cfg!(any(debug_assertions, feature = "debug_stress_gc")) {
+    #[cfg_attr(
+        not(any(debug_assertions, feature = "gc_poison")),
+        allow(unused_variables)
+    )]
+    fn allocate_raw<T: 'static + GcManaged>(
+        &mut self,
+        data: T,
+        location: &'static Location<'static>,
+    ) -> GcBoxPtr<T> {
+        if cfg!(debug_assertions) || self.options.stress {
             self.collect();
         } else {
             self.collect_if_required();
@@ -364,6 +638,8 @@ impl Heap {
         let mut boxed = Box::pin(GcBox {
             colour: Cell::new(Colour::White),
             num_roots: Cell::new(0),
+            #[cfg(any(debug_assertions, feature = "gc_poison"))]
+            allocated_at: location,
             _pin: PhantomPinned,
             data,
         });
@@ -374,40 +650,51 @@ impl Heap {
         let size = mem::size_of::<T>();
 
         self.bytes_allocated += size;
+        self.stats.bytes_allocated += size as u64;
 
-        if cfg!(feature = "debug_trace_gc") {
-            let new_ptr = self.objects.last().unwrap();
-            println!(
-                "{:?} allocate {} for {:?}",
-                new_ptr.as_ref().get_ref() as *const _,
-                size,
-                any::type_name::<T>(),
-            )
-        }
+        trace_gc!(
+            "{:?} allocate {} for {:?}",
+            self.objects.last().unwrap().as_ref().get_ref() as *const _,
+            size,
+            any::type_name::<T>(),
+        );
 
         gc_box_ptr
     }
 
     fn collect(&mut self) {
-        if cfg!(feature = "debug_trace_gc") {
-            println!("-- gc begin")
-        }
+        trace_gc!("-- gc begin");
 
+        let timer = Timer::start();
         self.mark_roots();
         self.trace_references();
         let bytes_freed = self.sweep();
+        let pause = timer.elapsed();
+
+        if let Some(target) = self.options.pause_target {
+            self.growth_factor = if pause > target {
+                1.0 + (self.growth_factor - 1.0) / 2.0
+            } else {
+                (1.0 + (self.growth_factor - 1.0) * 2.0).min(self.options.growth_factor)
+            };
+        }
+        self.stats.collections += 1;
+        self.stats.bytes_freed += bytes_freed as u64;
+        self.stats.total_pause += pause;
+        self.stats.max_pause = self.stats.max_pause.max(pause);
 
         let prev_bytes_allocated = self.bytes_allocated;
         self.bytes_allocated -= bytes_freed;
-        self.collection_threshold = self.bytes_allocated * common::HEAP_GROWTH_FACTOR;
-
-        if cfg!(feature = "debug_trace_gc") {
-            println!("-- gc end (freed {} bytes)", bytes_freed);
-            println!(
-                "   collected {} bytes (from {} to {}) next at {}",
-                bytes_freed, prev_bytes_allocated, self.bytes_allocated, self.collection_threshold,
-            )
-        }
+        self.collection_threshold = (self.bytes_allocated as f64 * self.growth_factor) as usize;
+
+        trace_gc!("-- gc end (freed {} bytes)", bytes_freed);
+        trace_gc!(
+            "   collected {} bytes (from {} to {}) next at {}",
+            bytes_freed,
+            prev_bytes_allocated,
+            self.bytes_allocated,
+            self.collection_threshold,
+        );
     }
 
     fn collect_if_required(&mut self) {
@@ -443,19 +730,40 @@ impl Heap {
     }
 
     fn sweep(&mut self) -> usize {
+        self.weak_refs.retain(|(ptr, alive)| {
+            // References that have all been dropped needn't be told about the object any more.
+            if Rc::strong_count(alive) == 1 {
+                return false;
+            }
+            let is_collected = unsafe { ptr.as_ref() }.colour.get() == Colour::White;
+            if is_collected {
+                alive.set(false);
+            }
+            !is_collected
+        });
+
         let bytes_marked: usize = self
             .objects
             .iter()
             .filter(|obj| obj.colour.get() == Colour::White)
             .map(|obj| {
-                if cfg!(feature = "debug_trace_gc") {
-                    println!("{:?} free", obj.as_ref().get_ref() as *const _);
-                }
+                trace_gc!("{:?} free", obj.as_ref().get_ref() as *const _);
                 mem::size_of_val(&obj.data)
             })
             .sum();
 
-        self.objects.retain(|obj| obj.colour.get() == Colour::Black);
+        if self.options.poison_freed {
+            let (live, freed) = mem::take(&mut self.objects)
+                .into_iter()
+                .partition(|obj| obj.colour.get() == Colour::Black);
+            self.objects = live;
+            for obj in &freed {
+                obj.colour.set(Colour::Freed);
+            }
+            self.freed.extend(freed);
+        } else {
+            self.objects.retain(|obj| obj.colour.get() == Colour::Black);
+        }
 
         bytes_marked
     }
@@ -466,7 +774,12 @@ impl Default for Heap {
         Heap {
             collection_threshold: common::HEAP_INIT_BYTES_MAX,
             bytes_allocated: 0,
+            options: GcOptions::default(),
+            growth_factor: common::HEAP_GROWTH_FACTOR as f64,
+            stats: GcStats::default(),
             objects: Vec::new(),
+            freed: Vec::new(),
+            weak_refs: Vec::new(),
         }
     }
 }
@@ -495,15 +808,17 @@ impl<T: GcManaged> GcManaged for Vec<T> {
     }
 }
 
-impl<K, V: GcManaged, S> GcManaged for HashMap<K, V, S> {
+impl<K: GcManaged, V: GcManaged, S> GcManaged for HashMap<K, V, S> {
     fn mark(&self) {
-        for v in self.values() {
+        for (k, v) in self {
+            k.mark();
             v.mark();
         }
     }
 
     fn blacken(&self) {
-        for v in self.values() {
+        for (k, v) in self {
+            k.blacken();
             v.blacken();
         }
     }
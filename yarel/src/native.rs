@@ -0,0 +1,114 @@
+/* Copyright 2020-2021 Matt Spraggs
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Support for extension modules compiled to shared libraries and loaded at runtime.
+//!
+//! An extension module exports two functions with C linkage:
+//!
+//! * `yarel_abi_version`, returning the [`ABI_VERSION`] the module was built against.
+//! * `yarel_module_init`, which is passed a pointer to the virtual machine and defines the
+//!   module's contents, e.g. using `Vm::define_native`. It returns zero on success. Otherwise it
+//!   may write the reason it failed to the `Option<Error>` its second argument points to.
+//!
+//! The [`native_module!`](crate::native_module) macro generates both from a Rust function.
+//! Because the virtual machine is passed as a Rust type, extensions must be built with the same
+//! compiler and version of this crate as the host, and should link to it dynamically so that
+//! both share the same heap.
+
+use std::os::raw::c_int;
+use std::path::Path;
+
+use libloading::Library;
+
+use crate::error::{Error, ErrorKind};
+use crate::vm::Vm;
+
+/// The version of the interface between the virtual machine and extension modules. This changes
+/// whenever a change to this crate would break previously built extensions.
+pub const ABI_VERSION: u32 = 2;
+
+pub type AbiVersionFn = unsafe extern "C" fn() -> u32;
+
+pub type ModuleInitFn = unsafe extern "C" fn(vm: *mut Vm, error: *mut Option<Error>) -> c_int;
+
+const ABI_VERSION_SYMBOL: &[u8] = b"yarel_abi_version\0";
+const MODULE_INIT_SYMBOL: &[u8] = b"yarel_module_init\0";
+
+/// Exports the functions making up the extension module interface, with the module's contents
+/// defined by the specified function of type `fn(&mut Vm) -> Result<(), Error>`.
+#[macro_export]
+macro_rules! native_module {
+    ($init:path) => {
+        #[no_mangle]
+        pub extern "C" fn yarel_abi_version() -> u32 {
+            $crate::native::ABI_VERSION
+        }
+
+        /// # Safety
+        /// The pointers must refer to a valid virtual machine that isn't otherwise borrowed and
+        /// to a place the error raised by the initialisation function, if any, can be written.
+        #[no_mangle]
+        pub unsafe extern "C" fn yarel_module_init(
+            vm: *mut $crate::vm::Vm,
+            error: *mut ::std::option::Option<$crate::error::Error>,
+        ) -> ::std::os::raw::c_int {
+            match $init(&mut *vm) {
+                Ok(()) => 0,
+                Err(e) => {
+                    *error = Some(e);
+                    1
+                }
+            }
+        }
+    };
+}
+
+/// Opens the shared library at the specified path, checks it was built against the current ABI
+/// and returns it along with its initialisation function.
+pub(crate) fn open_library(path: &Path) -> Result<(Library, ModuleInitFn), Error> {
+    let load_error = |e: libloading::Error| {
+        error!(
+            ErrorKind::ImportError,
+            "Unable to load native module '{}': {}",
+            path.display(),
+            e
+        )
+    };
+
+    // # Safety
+    // Loading a library runs its initialisation routines, which we have to trust. The symbol
+    // types match those generated by native_module!, and the ABI version is checked before the
+    // initialisation function is returned.
+    unsafe {
+        let library = Library::new(path).map_err(load_error)?;
+        let abi_version = library
+            .get::<AbiVersionFn>(ABI_VERSION_SYMBOL)
+            .map_err(load_error)?;
+        let version = abi_version();
+        if version != ABI_VERSION {
+            return Err(error!(
+                ErrorKind::ImportError,
+                "Native module '{}' was built for ABI version {} but version {} is required.",
+                path.display(),
+                version,
+                ABI_VERSION
+            ));
+        }
+        let init = *library
+            .get::<ModuleInitFn>(MODULE_INIT_SYMBOL)
+            .map_err(load_error)?;
+        Ok((library, init))
+    }
+}
@@ -13,28 +13,45 @@
  * limitations under the License.
  */
 
-use std::cell::{Cell, RefCell};
-use std::cmp::{self, Eq};
-use std::collections::HashMap;
-use std::fmt;
-use std::hash::{Hash, Hasher};
-use std::ops::Deref;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cell::{Cell, RefCell};
+use core::cmp::{self, Eq};
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::mem;
+use core::ops::{Deref, Range};
+use core::ptr;
 
 use crate::chunk::Chunk;
 use crate::common;
 use crate::error::{Error, ErrorKind};
-use crate::hash::{BuildPassThroughHasher, PassThroughHasher};
+use crate::hash::{BuildPassThroughHasher, HashMap, HashSet, PassThroughHasher};
 use crate::memory::{Gc, GcManaged};
 use crate::stack::Stack;
-use crate::value::Value;
+use crate::utils;
+use crate::value::{Repr, Value};
 use crate::vm::Vm;
 
-const STACK_MAX: usize = common::LOCALS_MAX * common::FRAMES_MAX;
+const STACK_INIT_SIZE: usize = common::FRAME_STACK_SLOTS;
+
+/// The characters of a string, which are either owned by the string or are a range of the
+/// characters owned by another string.
+#[derive(Clone, Debug)]
+enum StringData {
+    Owned(String),
+    Slice {
+        parent: Gc<ObjString>,
+        begin: usize,
+        end: usize,
+    },
+}
 
 #[derive(Clone, Debug)]
 pub struct ObjString {
     pub(crate) class: Gc<ObjClass>,
-    string: String,
+    data: StringData,
     pub(crate) hash: u64,
 }
 
@@ -42,13 +59,39 @@ impl ObjString {
     pub(crate) fn new(class: Gc<ObjClass>, string: &str, hash: u64) -> Self {
         ObjString {
             class,
-            string: String::from(string),
+            data: StringData::Owned(String::from(string)),
+            hash,
+        }
+    }
+
+    /// Creates a string that shares the characters of another within the specified byte range,
+    /// rather than copying them. The other string is kept alive for as long as this one is.
+    pub(crate) fn slice(
+        class: Gc<ObjClass>,
+        parent: Gc<ObjString>,
+        range: Range<usize>,
+        hash: u64,
+    ) -> Self {
+        let (parent, offset) = match parent.data {
+            StringData::Owned(_) => (parent, 0),
+            StringData::Slice { parent, begin, .. } => (parent, begin),
+        };
+        ObjString {
+            class,
+            data: StringData::Slice {
+                parent,
+                begin: offset + range.start,
+                end: offset + range.end,
+            },
             hash,
         }
     }
 
     pub fn as_str(&self) -> &str {
-        self.string.as_str()
+        match &self.data {
+            StringData::Owned(string) => string.as_str(),
+            StringData::Slice { parent, begin, end } => &parent.as_str()[*begin..*end],
+        }
     }
 
     pub fn validate_char_boundary(&self, pos: usize, desc: &str) -> Result<(), Error> {
@@ -64,7 +107,7 @@ impl ObjString {
 
 impl fmt::Display for ObjString {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.string)
+        write!(f, "{}", self.as_str())
     }
 }
 
@@ -76,7 +119,7 @@ impl Hash for Gc<ObjString> {
 
 impl PartialEq for ObjString {
     fn eq(&self, other: &Self) -> bool {
-        self.hash == other.hash && self.string.eq(&other.string)
+        self.hash == other.hash && self.as_str() == other.as_str()
     }
 }
 
@@ -84,13 +127,13 @@ impl Eq for ObjString {}
 
 impl PartialOrd for ObjString {
     fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
-        self.string.partial_cmp(&other.string)
+        self.as_str().partial_cmp(other.as_str())
     }
 }
 
 impl Ord for ObjString {
     fn cmp(&self, other: &Self) -> cmp::Ordering {
-        self.string.cmp(&other.string)
+        self.as_str().cmp(other.as_str())
     }
 }
 
@@ -98,14 +141,22 @@ impl Deref for ObjString {
     type Target = str;
 
     fn deref(&self) -> &str {
-        self.string.as_str()
+        self.as_str()
     }
 }
 
 impl GcManaged for ObjString {
-    fn mark(&self) {}
+    fn mark(&self) {
+        if let StringData::Slice { parent, .. } = self.data {
+            parent.mark();
+        }
+    }
 
-    fn blacken(&self) {}
+    fn blacken(&self) {
+        if let StringData::Slice { parent, .. } = self.data {
+            parent.blacken();
+        }
+    }
 }
 
 impl Eq for Gc<ObjString> {}
@@ -222,6 +273,15 @@ impl ObjUpvalue {
         let value = self.get();
         self.data = ObjUpvalueState::Closed(value);
     }
+
+    /// Points an open upvalue at the same slot of a stack that's been moved between the specified
+    /// addresses.
+    pub(crate) fn relocate(&mut self, old_address: *const Value, new_address: *const Value) {
+        if let ObjUpvalueState::Open(address) = self.data {
+            let index = (address as usize - old_address as usize) / mem::size_of::<Value>();
+            self.data = ObjUpvalueState::Open(unsafe { new_address.add(index) as *mut _ });
+        }
+    }
 }
 
 impl GcManaged for ObjUpvalue {
@@ -246,13 +306,19 @@ impl GcManaged for ObjUpvalue {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub struct ObjFunction {
     pub arity: usize,
     pub upvalue_count: usize,
     pub chunk: Gc<Chunk>,
     pub name: Gc<ObjString>,
     pub(crate) module_path: Gc<ObjString>,
+    pub(crate) parameters: Vec<Gc<ObjString>>,
+    pub(crate) doc: Option<Gc<ObjString>>,
+    /// Whether the function is a method declared with an empty body. A class used as a protocol
+    /// requires the classes implementing it to define such methods, whereas its other methods are
+    /// default implementations.
+    pub(crate) is_stub: bool,
 }
 
 impl ObjFunction {
@@ -269,6 +335,9 @@ impl ObjFunction {
             upvalue_count,
             chunk,
             module_path,
+            parameters: Vec::new(),
+            doc: None,
+            is_stub: false,
         }
     }
 }
@@ -277,11 +346,19 @@ impl GcManaged for ObjFunction {
     fn mark(&self) {
         self.name.mark();
         self.chunk.mark();
+        self.parameters.mark();
+        if let Some(doc) = self.doc {
+            doc.mark();
+        }
     }
 
     fn blacken(&self) {
         self.name.blacken();
         self.chunk.blacken();
+        self.parameters.blacken();
+        if let Some(doc) = self.doc {
+            doc.blacken();
+        }
     }
 }
 
@@ -341,6 +418,9 @@ pub struct ObjClosure {
     pub function: Gc<ObjFunction>,
     pub upvalues: RefCell<Vec<Gc<RefCell<ObjUpvalue>>>>,
     pub(crate) module: Gc<RefCell<ObjModule>>,
+    /// The class whose method this closure is, or was created within, which determines whether
+    /// the closure may access private properties.
+    pub(crate) class: Cell<Option<Gc<ObjClass>>>,
 }
 
 impl ObjClosure {
@@ -353,6 +433,7 @@ impl ObjClosure {
             function,
             upvalues: RefCell::new(upvalues),
             module,
+            class: Cell::new(None),
         }
     }
 }
@@ -361,11 +442,17 @@ impl GcManaged for ObjClosure {
     fn mark(&self) {
         self.function.mark();
         self.upvalues.mark();
+        if let Some(class) = self.class.get() {
+            class.mark();
+        }
     }
 
     fn blacken(&self) {
         self.function.blacken();
         self.upvalues.blacken();
+        if let Some(class) = self.class.get() {
+            class.blacken();
+        }
     }
 }
 
@@ -381,6 +468,8 @@ pub struct ObjClass {
     pub metaclass: Gc<ObjClass>,
     pub superclass: Option<Gc<ObjClass>>,
     pub methods: HashMap<Gc<ObjString>, Value, BuildPassThroughHasher>,
+    /// The static fields declared in the class body.
+    pub(crate) fields: RefCell<ObjStringValueMap>,
 }
 
 impl ObjClass {
@@ -403,19 +492,62 @@ impl ObjClass {
             metaclass,
             superclass,
             methods: merged_methods,
+            fields: RefCell::new(new_obj_string_value_map()),
         }
     }
+
+    /// Returns the value of the static field with the specified name, which may have been declared
+    /// by a superclass.
+    pub(crate) fn static_field(&self, name: Gc<ObjString>) -> Option<Value> {
+        if let Some(&value) = self.fields.borrow().get(&name) {
+            return Some(value);
+        }
+        self.superclass.and_then(|s| s.static_field(name))
+    }
+
+    /// Assigns to the static field with the specified name on the class that declared it,
+    /// returning whether there was such a field.
+    pub(crate) fn set_static_field(&self, name: Gc<ObjString>, value: Value) -> bool {
+        if let Some(field) = self.fields.borrow_mut().get_mut(&name) {
+            *field = value;
+            return true;
+        }
+        self.superclass
+            .is_some_and(|s| s.set_static_field(name, value))
+    }
+}
+
+/// Returns whether a property with the specified name is private, as indicated by a leading
+/// underscore. Names like `__call__` belong to protocols that are used from outside the class, so
+/// aren't private.
+pub(crate) fn is_private_name(name: &str) -> bool {
+    let is_dunder = name.len() > 4 && name.starts_with("__") && name.ends_with("__");
+    name.starts_with('_') && !is_dunder
+}
+
+/// Returns whether the class is the same as or derived from the other class.
+pub(crate) fn class_derives(class: Gc<ObjClass>, other: Gc<ObjClass>) -> bool {
+    let mut current = Some(class);
+    while let Some(ancestor) = current {
+        if ancestor == other {
+            return true;
+        }
+        current = ancestor.superclass;
+    }
+    false
 }
 
 impl GcManaged for ObjClass {
     fn mark(&self) {
         self.metaclass.mark();
         self.methods.mark();
+        self.fields.mark();
     }
 
     fn blacken(&self) {
         self.metaclass.blacken();
         self.methods.blacken();
+        self.fields.blacken();
     }
 }
 
@@ -520,11 +652,45 @@ impl fmt::Debug for ObjBoundMethod<ObjClosure> {
     }
 }
 
+/// A callable that calls another with some leading arguments already supplied.
+#[derive(Debug)]
+pub struct ObjPartial {
+    pub callee: Value,
+    pub args: Vec<Value>,
+}
+
+impl ObjPartial {
+    pub(crate) fn new(callee: Value, args: Vec<Value>) -> Self {
+        ObjPartial { callee, args }
+    }
+}
+
+impl GcManaged for ObjPartial {
+    fn mark(&self) {
+        self.callee.mark();
+        self.args.mark();
+    }
+
+    fn blacken(&self) {
+        self.callee.mark();
+        self.args.blacken();
+    }
+}
+
+impl fmt::Display for ObjPartial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "partial {}", self.callee)
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct ObjVec {
     pub class: Gc<ObjClass>,
     pub elements: Vec<Value>,
+    frozen: bool,
     disp_lock: Cell<bool>,
+    eq_lock: Cell<bool>,
+    hash_lock: Cell<bool>,
 }
 
 impl ObjVec {
@@ -532,7 +698,10 @@ impl ObjVec {
         ObjVec {
             class,
             elements: Vec::new(),
+            frozen: false,
             disp_lock: Cell::new(false),
+            eq_lock: Cell::new(false),
+            hash_lock: Cell::new(false),
         }
     }
 
@@ -540,9 +709,42 @@ impl ObjVec {
         ObjVec {
             class,
             elements,
+            frozen: false,
             disp_lock: Cell::new(false),
+            eq_lock: Cell::new(false),
+            hash_lock: Cell::new(false),
         }
     }
+
+    pub(crate) fn freeze(&mut self) {
+        self.frozen = true;
+    }
+
+    pub(crate) fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
+    pub(crate) fn check_mutable(&self) -> Result<(), Error> {
+        if self.frozen {
+            return Err(error!(
+                ErrorKind::RuntimeError,
+                "Cannot modify a frozen Vec."
+            ));
+        }
+        Ok(())
+    }
+
+    /// A Vec can only be hashed once it's frozen, and only if its elements can be hashed. A Vec
+    /// that contains itself can't be hashed.
+    pub(crate) fn has_hash(&self) -> bool {
+        if !self.frozen || self.hash_lock.get() {
+            return false;
+        }
+        let prev_hash_lock = self.hash_lock.replace(true);
+        let ret = self.elements.iter().all(|v| v.has_hash());
+        self.hash_lock.set(prev_hash_lock);
+        ret
+    }
 }
 
 impl GcManaged for ObjVec {
@@ -566,7 +768,12 @@ impl fmt::Display for ObjVec {
         write!(f, "[")?;
         let num_elems = self.elements.len();
         for (i, e) in self.elements.iter().enumerate() {
-            write!(f, "{}{}", e, if i == num_elems - 1 { "" } else { ", " })?;
+            write!(
+                f,
+                "{}{}",
+                Repr(e),
+                if i == num_elems - 1 { "" } else { ", " }
+            )?;
         }
         self.disp_lock.set(prev_disp_lock);
         write!(f, "]")
@@ -575,10 +782,15 @@ impl fmt::Display for ObjVec {
 
 impl cmp::PartialEq for ObjVec {
     fn eq(&self, other: &ObjVec) -> bool {
-        if self as *const _ == other as *const _ {
+        // A Vec reached again through a cycle is assumed to be equal, leaving the rest of the
+        // comparison to decide.
+        if ptr::eq(self, other) || self.eq_lock.get() {
             return true;
         }
-        self.elements == other.elements
+        let prev_eq_lock = self.eq_lock.replace(true);
+        let eq = self.elements == other.elements;
+        self.eq_lock.set(prev_eq_lock);
+        eq
     }
 }
 
@@ -630,44 +842,31 @@ pub struct ObjRange {
     pub class: Gc<ObjClass>,
     pub begin: isize,
     pub end: isize,
+    /// The step given after the end of the range, as in `0..10:2`, if any.
+    pub step: Option<isize>,
 }
 
 impl ObjRange {
-    pub(crate) fn new(class: Gc<ObjClass>, begin: isize, end: isize) -> Self {
-        ObjRange { class, begin, end }
+    pub(crate) fn new(class: Gc<ObjClass>, begin: isize, end: isize, step: Option<isize>) -> Self {
+        ObjRange {
+            class,
+            begin,
+            end,
+            step,
+        }
     }
 
-    pub(crate) fn make_bounded_range(
-        &self,
-        limit: isize,
-        type_name: &str,
-    ) -> Result<(usize, usize), Error> {
-        let begin = if self.begin < 0 {
-            self.begin + limit
-        } else {
-            self.begin
-        };
-        if begin < 0 || begin >= limit {
-            return Err(error!(
-                ErrorKind::IndexError,
-                "{} slice start out of range.", type_name
-            ));
-        }
-        let end = if self.end < 0 {
-            self.end + limit
-        } else {
-            self.end
-        };
-        if end < 0 || end > limit {
-            return Err(error!(
-                ErrorKind::IndexError,
-                "{} slice end out of range.", type_name
-            ));
-        }
-        Ok((
-            begin as usize,
-            if end >= begin { end } else { begin } as usize,
-        ))
+    /// The step between the values produced when iterating over the range. Ranges without an
+    /// explicit step count down if their end is before their beginning.
+    pub(crate) fn iter_step(&self) -> isize {
+        self.step
+            .unwrap_or(if self.begin <= self.end { 1 } else { -1 })
+    }
+
+    /// Validates the range as a slice of a sequence with the specified length. Ranges without an
+    /// explicit step always slice forwards.
+    pub(crate) fn make_slice(&self, len: isize, type_name: &str) -> Result<utils::Slice, Error> {
+        utils::Slice::new(self.begin, self.end, self.step.unwrap_or(1), len, type_name)
     }
 }
 
@@ -683,7 +882,10 @@ impl GcManaged for ObjRange {
 
 impl fmt::Display for ObjRange {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Range({}, {})", self.begin, self.end)
+        match self.step {
+            Some(step) => write!(f, "Range({}, {}, {})", self.begin, self.end, step),
+            None => write!(f, "Range({}, {})", self.begin, self.end),
+        }
     }
 }
 
@@ -702,12 +904,13 @@ impl ObjRangeIter {
             class,
             iterable,
             current,
-            step: if iterable.begin < iterable.end { 1 } else { -1 },
+            step: iterable.iter_step(),
         }
     }
 
     pub(crate) fn next(&mut self) -> Option<Value> {
-        if self.current == self.iterable.end {
+        let end = self.iterable.end;
+        if (self.step > 0 && self.current >= end) || (self.step < 0 && self.current <= end) {
             return None;
         }
         let ret = Value::Number(self.current as f64);
@@ -736,7 +939,13 @@ impl fmt::Display for ObjRangeIter {
 pub struct ObjHashMap {
     pub class: Gc<ObjClass>,
     pub elements: HashMap<Value, Value, BuildPassThroughHasher>,
+    /// The instances used as keys, grouped by the hashes returned by their `hash` methods. Each
+    /// is also a key in `elements`, so needn't be marked separately.
+    pub(crate) instance_keys: HashMap<u64, Vec<Value>>,
+    frozen: bool,
     disp_lock: Cell<bool>,
+    eq_lock: Cell<bool>,
+    hash_lock: Cell<bool>,
 }
 
 impl ObjHashMap {
@@ -744,8 +953,75 @@ impl ObjHashMap {
         ObjHashMap {
             class,
             elements: HashMap::with_hasher(BuildPassThroughHasher::default()),
+            instance_keys: HashMap::default(),
+            frozen: false,
             disp_lock: Cell::new(false),
+            eq_lock: Cell::new(false),
+            hash_lock: Cell::new(false),
+        }
+    }
+
+    pub(crate) fn freeze(&mut self) {
+        self.frozen = true;
+    }
+
+    pub(crate) fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
+    pub(crate) fn check_mutable(&self) -> Result<(), Error> {
+        if self.frozen {
+            return Err(error!(
+                ErrorKind::RuntimeError,
+                "Cannot modify a frozen HashMap."
+            ));
+        }
+        Ok(())
+    }
+
+    /// As with Vecs, a HashMap can only be hashed once it's frozen, and only if its values can be
+    /// hashed.
+    pub(crate) fn has_hash(&self) -> bool {
+        if !self.frozen || self.hash_lock.get() {
+            return false;
+        }
+        let prev_hash_lock = self.hash_lock.replace(true);
+        let ret = self.elements.values().all(|v| v.has_hash());
+        self.hash_lock.set(prev_hash_lock);
+        ret
+    }
+
+    /// Inserts an entry, recording the key by the hash returned by its `hash` method if it's an
+    /// instance.
+    pub(crate) fn insert(
+        &mut self,
+        key: Value,
+        instance_hash: Option<u64>,
+        value: Value,
+    ) -> Option<Value> {
+        let prev_value = self.elements.insert(key, value);
+        if let (None, Some(hash)) = (prev_value, instance_hash) {
+            self.instance_keys.entry(hash).or_default().push(key);
         }
+        prev_value
+    }
+
+    pub(crate) fn remove(&mut self, key: Value, instance_hash: Option<u64>) -> Option<Value> {
+        let prev_value = self.elements.remove(&key);
+        if let (Some(_), Some(hash)) = (prev_value, instance_hash) {
+            if let Some(keys) = self.instance_keys.get_mut(&hash) {
+                keys.retain(|&k| k != key);
+                if keys.is_empty() {
+                    self.instance_keys.remove(&hash);
+                }
+            }
+        }
+        prev_value
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.elements.clear();
+        self.instance_keys.clear();
     }
 }
 
@@ -773,8 +1049,8 @@ impl fmt::Display for ObjHashMap {
             write!(
                 f,
                 "{}: {}{}",
-                k,
-                v,
+                Repr(&k),
+                Repr(&v),
                 if i == num_elems - 1 { "" } else { ", " }
             )?;
         }
@@ -785,10 +1061,101 @@ impl fmt::Display for ObjHashMap {
 
 impl cmp::PartialEq for ObjHashMap {
     fn eq(&self, other: &ObjHashMap) -> bool {
-        if self as *const _ == other as *const _ {
+        // As with Vecs, a HashMap reached again through a cycle is assumed to be equal.
+        if ptr::eq(self, other) || self.eq_lock.get() {
             return true;
         }
-        self.elements == other.elements
+        let prev_eq_lock = self.eq_lock.replace(true);
+        let eq = self.elements == other.elements;
+        self.eq_lock.set(prev_eq_lock);
+        eq
+    }
+}
+
+/// A key in an `ObjIdentityMap`, which is hashed and compared by the address of the object it
+/// refers to. Nil, booleans and numbers aren't objects, so are compared by value instead.
+#[derive(Clone, Copy, Debug)]
+pub struct IdentityKey(pub Value);
+
+impl GcManaged for IdentityKey {
+    fn mark(&self) {
+        self.0.mark();
+    }
+
+    fn blacken(&self) {
+        self.0.blacken();
+    }
+}
+
+impl Hash for IdentityKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self.0.address() {
+            Some(address) => state.write_u64(utils::hash_number(address as f64)),
+            None => self.0.hash(state),
+        }
+    }
+}
+
+impl cmp::PartialEq for IdentityKey {
+    fn eq(&self, other: &IdentityKey) -> bool {
+        match (self.0.address(), other.0.address()) {
+            (Some(first), Some(second)) => first == second,
+            (None, None) => self.0 == other.0,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for IdentityKey {}
+
+#[derive(Clone, Debug)]
+pub struct ObjIdentityMap {
+    pub class: Gc<ObjClass>,
+    pub elements: HashMap<IdentityKey, Value, BuildPassThroughHasher>,
+    disp_lock: Cell<bool>,
+}
+
+impl ObjIdentityMap {
+    pub(crate) fn new(class: Gc<ObjClass>) -> Self {
+        ObjIdentityMap {
+            class,
+            elements: HashMap::with_hasher(BuildPassThroughHasher),
+            disp_lock: Cell::new(false),
+        }
+    }
+}
+
+impl GcManaged for ObjIdentityMap {
+    fn mark(&self) {
+        self.class.mark();
+        self.elements.mark();
+    }
+
+    fn blacken(&self) {
+        self.class.blacken();
+        self.elements.blacken();
+    }
+}
+
+impl fmt::Display for ObjIdentityMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.disp_lock.get() {
+            return write!(f, "{{...}}");
+        }
+        let prev_disp_lock = self.disp_lock.replace(true);
+        write!(f, "{{")?;
+        let num_elems = self.elements.len();
+        for (i, (k, v)) in self.elements.iter().enumerate() {
+            write!(
+                f,
+                "{}: {}{}",
+                Repr(&k.0),
+                Repr(v),
+                if i == num_elems - 1 { "" } else { ", " }
+            )?;
+        }
+        self.disp_lock.set(prev_disp_lock);
+        write!(f, "}}")
     }
 }
 
@@ -851,7 +1218,7 @@ impl fmt::Display for ObjTuple {
             } else {
                 ", "
             };
-            write!(f, "{}{}", e, suffix)?;
+            write!(f, "{}{}", Repr(e), suffix)?;
         }
         self.self_lock.set(prev_self_lock);
         write!(f, ")")
@@ -869,15 +1236,11 @@ impl cmp::PartialEq for ObjTuple {
 
 impl Hash for Gc<ObjTuple> {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        let hash = self
-            .elements
-            .iter()
-            .map(|v| {
-                let mut hasher = PassThroughHasher::default();
-                v.hash(&mut hasher);
-                hasher.finish()
-            })
-            .fold(0_64, |a, b| a ^ b);
+        let hash = utils::hash_sequence(self.elements.iter().map(|v| {
+            let mut hasher = PassThroughHasher::default();
+            v.hash(&mut hasher);
+            hasher.finish()
+        }));
         state.write_u64(hash);
     }
 }
@@ -927,29 +1290,150 @@ impl fmt::Display for ObjTupleIter {
 #[derive(Clone, Debug)]
 pub struct ObjModule {
     pub(crate) imported: bool,
+    /// Whether the module was bound by a lazy import and has yet to be loaded.
+    pub(crate) lazy: bool,
+    /// Whether the module's body is running, in which case imports that form a cycle back to the
+    /// module bind it as it is so far.
+    pub(crate) initialising: bool,
+    /// Whether the module was loaded from a package's `mod.yl` index.
+    pub(crate) is_package: bool,
     pub(crate) class: Gc<ObjClass>,
     pub(crate) path: Gc<ObjString>,
-    pub attributes: HashMap<Gc<ObjString>, Value, BuildPassThroughHasher>,
+    slots: Vec<Option<Value>>,
+    slot_names: Vec<Gc<ObjString>>,
+    slot_indices: HashMap<Gc<ObjString>, usize, BuildPassThroughHasher>,
+    /// Whether each slot still holds the built-in global it was given, as opposed to an attribute
+    /// the module defined itself.
+    built_ins: Vec<bool>,
+    /// The slots of the attributes that can be accessed from outside the module, if it declares
+    /// any exports.
+    exports: Option<HashSet<usize>>,
 }
 
 impl ObjModule {
     pub(crate) fn new(class: Gc<ObjClass>, path: Gc<ObjString>) -> Self {
         ObjModule {
             imported: false,
+            lazy: false,
+            initialising: false,
+            is_package: false,
             class,
             path,
-            attributes: new_obj_string_value_map(),
+            slots: Vec::new(),
+            slot_names: Vec::new(),
+            slot_indices: HashMap::with_hasher(BuildPassThroughHasher::default()),
+            built_ins: Vec::new(),
+            exports: None,
+        }
+    }
+
+    /// Returns the slot index of the attribute with the specified name, allocating an empty slot
+    /// if the module doesn't yet have one for it.
+    pub(crate) fn slot(&mut self, name: Gc<ObjString>) -> usize {
+        if let Some(&index) = self.slot_indices.get(&name) {
+            return index;
+        }
+        let index = self.slots.len();
+        self.slots.push(None);
+        self.slot_names.push(name);
+        self.built_ins.push(false);
+        self.slot_indices.insert(name, index);
+        index
+    }
+
+    pub(crate) fn num_slots(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub(crate) fn slot_value(&self, index: usize) -> Option<Value> {
+        self.slots[index]
+    }
+
+    pub(crate) fn set_slot_value(&mut self, index: usize, value: Value) {
+        self.slots[index] = Some(value);
+        self.built_ins[index] = false;
+    }
+
+    pub(crate) fn slot_name(&self, index: usize) -> Gc<ObjString> {
+        self.slot_names[index]
+    }
+
+    pub fn attribute(&self, name: Gc<ObjString>) -> Option<Value> {
+        let index = *self.slot_indices.get(&name)?;
+        self.slots[index]
+    }
+
+    pub fn set_attribute(&mut self, name: Gc<ObjString>, value: Value) {
+        let index = self.slot(name);
+        self.slots[index] = Some(value);
+        self.built_ins[index] = false;
+    }
+
+    pub(crate) fn set_built_in(&mut self, name: Gc<ObjString>, value: Value) {
+        let index = self.slot(name);
+        self.slots[index] = Some(value);
+        self.built_ins[index] = true;
+    }
+
+    pub(crate) fn export(&mut self, name: Gc<ObjString>) {
+        let index = self.slot(name);
+        self.exports
+            .get_or_insert_with(HashSet::default)
+            .insert(index);
+    }
+
+    /// Whether the attribute can be accessed from outside the module, which is the case for every
+    /// attribute of a module that doesn't declare any exports.
+    pub fn is_exported(&self, name: Gc<ObjString>) -> bool {
+        match &self.exports {
+            Some(exports) => self
+                .slot_indices
+                .get(&name)
+                .is_some_and(|index| exports.contains(index)),
+            None => true,
+        }
+    }
+
+    /// Returns the names of the attributes the module defines and allows access to from outside.
+    pub(crate) fn members(&self) -> impl Iterator<Item = Gc<ObjString>> + '_ {
+        self.slot_names
+            .iter()
+            .enumerate()
+            .filter(move |&(index, &name)| {
+                self.slots[index].is_some() && !self.built_ins[index] && self.is_exported(name)
+            })
+            .map(|(_, &name)| name)
+    }
+
+    pub fn attributes(&self) -> impl Iterator<Item = (Gc<ObjString>, Value)> + '_ {
+        self.slot_names
+            .iter()
+            .zip(self.slots.iter())
+            .filter_map(|(&name, value)| value.map(|v| (name, v)))
+    }
+
+    /// Undefines every attribute in the module. Slot indices are retained, as compiled code may
+    /// still refer to them.
+    pub(crate) fn clear_attributes(&mut self) {
+        for slot in self.slots.iter_mut() {
+            *slot = None;
         }
     }
 }
 
 impl GcManaged for ObjModule {
     fn mark(&self) {
-        self.attributes.mark();
+        self.slot_names.mark();
+        for value in self.slots.iter().flatten() {
+            value.mark();
+        }
     }
 
     fn blacken(&self) {
-        self.attributes.blacken();
+        self.slot_names.blacken();
+        for value in self.slots.iter().flatten() {
+            value.blacken();
+        }
     }
 }
 
@@ -990,16 +1474,19 @@ impl ExcHandler {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub struct ObjFiber {
     pub(crate) class: Gc<ObjClass>,
     pub(crate) caller: Option<Gc<RefCell<ObjFiber>>>,
-    pub(crate) stack: Stack<Value, STACK_MAX>,
+    pub(crate) stack: Stack<Value>,
     pub(crate) frames: Vec<CallFrame>,
     pub(crate) native_arity: Option<usize>,
     pub(crate) open_upvalues: Option<Gc<RefCell<ObjUpvalue>>>,
     pub(crate) call_arity: usize,
+    pub(crate) catches_errors: bool,
     pub(crate) return_value: Value,
+    /// The exception that stopped the fiber, if it raised one that it didn't handle.
+    pub(crate) error: Option<Value>,
     pub(crate) exc_handlers: Vec<ExcHandler>,
     pub(crate) return_ip: Option<*const u8>,
     pub(crate) error_ip: Option<*const u8>,
@@ -1007,7 +1494,7 @@ pub struct ObjFiber {
 
 impl ObjFiber {
     pub(crate) fn new(class: Gc<ObjClass>, closure: Gc<ObjClosure>) -> Self {
-        let mut frames = Vec::with_capacity(common::FRAMES_MAX);
+        let mut frames = Vec::new();
         let (ip, arity) = { (closure.function.chunk.code.as_ptr(), closure.function.arity) };
         frames.push(CallFrame {
             closure,
@@ -1017,12 +1504,14 @@ impl ObjFiber {
         ObjFiber {
             class,
             caller: None,
-            stack: Stack::new(),
+            stack: Stack::with_capacity(STACK_INIT_SIZE),
             frames,
             native_arity: None,
             open_upvalues: None,
             call_arity: arity as usize,
+            catches_errors: false,
             return_value: Value::None,
+            error: None,
             exc_handlers: Vec::new(),
             return_ip: None,
             error_ip: None,
@@ -1035,7 +1524,27 @@ impl ObjFiber {
             closure,
             ip,
             slot_base: self.stack.len() - arity,
-        })
+        });
+    }
+
+    /// Ensures that the specified number of values can be pushed onto the stack, moving any open
+    /// upvalues along with the stack if it has to be reallocated. Returns false without changing
+    /// the stack if the values would take it past the specified limit.
+    pub(crate) fn reserve_stack(&mut self, additional: usize, limit: usize) -> bool {
+        match self.stack.reserve(additional, limit) {
+            Ok(Some(old_address)) => {
+                let new_address = self.stack.as_ptr();
+                let mut upvalue = self.open_upvalues;
+                while let Some(current) = upvalue {
+                    let mut borrowed_upvalue = current.borrow_mut();
+                    borrowed_upvalue.relocate(old_address, new_address);
+                    upvalue = borrowed_upvalue.next;
+                }
+                true
+            }
+            Ok(None) => true,
+            Err(()) => false,
+        }
     }
 
     pub(crate) fn set_native_arity(&mut self, arity: usize) {
@@ -1088,6 +1597,15 @@ impl ObjFiber {
         self.frames.is_empty()
     }
 
+    pub(crate) fn abandon(&mut self) {
+        if !self.stack.is_empty() {
+            self.close_upvalues(0);
+        }
+        self.stack.clear();
+        self.frames.clear();
+        self.exc_handlers.clear();
+    }
+
     pub(crate) fn push_exc_handler(&mut self, catch_ip: *const u8, finally_ip: *const u8) {
         self.exc_handlers.push(ExcHandler {
             catch_ip,
@@ -1143,6 +1661,9 @@ impl GcManaged for ObjFiber {
             caller.mark();
         }
         self.return_value.mark();
+        if let Some(error) = self.error.as_ref() {
+            error.mark();
+        }
     }
 
     fn blacken(&self) {
@@ -1155,6 +1676,9 @@ impl GcManaged for ObjFiber {
             caller.blacken();
         }
         self.return_value.blacken();
+        if let Some(error) = self.error.as_ref() {
+            error.blacken();
+        }
     }
 }
 
@@ -0,0 +1,2147 @@
+/* Copyright 2020-2021 Matt Spraggs
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use alloc::borrow::ToOwned;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use core::cell::{Cell, RefCell};
+use core::fmt::Write;
+
+use crate::arena::Arena;
+use crate::ast::{
+    self, Argument, AssignOperator, BinaryOperator, Block, ClassDecl, Comprehension,
+    ComprehensionKind, Expr, Field, FnDecl, Function, Identifier, InterpolationPart, LambdaBody,
+    Literal, LogicalOperator, Method, Stmt, UnaryOperator,
+};
+use crate::common;
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::error::{Error, ErrorKind, Span};
+use crate::hash::HashMap;
+use crate::scanner::{Comment, Scanner, Token, TokenKind};
+use crate::utils;
+
+#[derive(Copy, Clone, Debug)]
+enum Precedence {
+    None,
+    Assignment,
+    Or,
+    And,
+    Equality,
+    Comparison,
+    BitwiseOr,
+    BitwiseXor,
+    BitwiseAnd,
+    BitShift,
+    Term,
+    Factor,
+    Range,
+    Unary,
+    Call,
+    Primary,
+}
+
+impl From<usize> for Precedence {
+    fn from(value: usize) -> Self {
+        match value {
+            value if value == Precedence::None as usize => Precedence::None,
+            value if value == Precedence::Assignment as usize => Precedence::Assignment,
+            value if value == Precedence::Or as usize => Precedence::Or,
+            value if value == Precedence::And as usize => Precedence::And,
+            value if value == Precedence::Equality as usize => Precedence::Equality,
+            value if value == Precedence::Comparison as usize => Precedence::Comparison,
+            value if value == Precedence::BitwiseOr as usize => Precedence::BitwiseOr,
+            value if value == Precedence::BitwiseXor as usize => Precedence::BitwiseXor,
+            value if value == Precedence::BitwiseAnd as usize => Precedence::BitwiseAnd,
+            value if value == Precedence::BitShift as usize => Precedence::BitShift,
+            value if value == Precedence::Term as usize => Precedence::Term,
+            value if value == Precedence::Factor as usize => Precedence::Factor,
+            value if value == Precedence::Range as usize => Precedence::Range,
+            value if value == Precedence::Unary as usize => Precedence::Unary,
+            value if value == Precedence::Call as usize => Precedence::Call,
+            value if value == Precedence::Primary as usize => Precedence::Primary,
+            _ => panic!("Unknown precedence {}", value),
+        }
+    }
+}
+
+type PrefixFn = fn(&mut Parser, bool) -> Expr;
+type InfixFn = fn(&mut Parser, Expr, bool) -> Expr;
+
+#[derive(Copy, Clone)]
+struct ParseRule {
+    prefix: Option<PrefixFn>,
+    infix: Option<InfixFn>,
+    precedence: Precedence,
+}
+
+/// The outcome of parsing a module. Parsing recovers from each syntax error, so the tree is always
+/// complete, with the parts that couldn't be parsed replaced by error nodes.
+pub(crate) struct Parsed {
+    pub module: ast::Module,
+    /// An error holding all the syntax errors encountered, if there were any.
+    pub error: Option<Error>,
+    /// The same syntax errors as diagnostics.
+    pub diagnostics: Vec<Diagnostic>,
+    /// The comments skipped over while parsing.
+    pub comments: Vec<Comment>,
+}
+
+/// Parses the source of a module into its syntax tree.
+pub(crate) fn parse(source: String, module_path: &str) -> Parsed {
+    // Tokens borrow from the source and the arena, which are freed together once parsing is done.
+    let arena = Arena::new();
+    let mut scanner = Scanner::from_source(&source, &arena);
+    let mut parser = Parser::new(&mut scanner, module_path);
+    let module = parser.parse();
+
+    let error = parser.errors.into_inner();
+    Parsed {
+        module,
+        error: if error.messages().is_empty() {
+            None
+        } else {
+            Some(error)
+        },
+        diagnostics: parser.diagnostics.into_inner(),
+        comments: scanner.take_comments(),
+    }
+}
+
+struct Attribute<'a> {
+    name: Token<'a>,
+    arguments: Vec<Token<'a>>,
+    position: usize,
+}
+
+impl Attribute<'_> {
+    fn to_ast(&self) -> ast::Attribute {
+        ast::Attribute {
+            name: identifier(&self.name),
+            arguments: self.arguments.iter().map(identifier).collect(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionKind {
+    Function,
+    Initialiser,
+    Method,
+    Script,
+    StaticMethod,
+    /// The initialiser of a static field, which is evaluated when the class is declared.
+    StaticField,
+}
+
+/// What's known about the function being parsed, used to reject statements and expressions that
+/// aren't valid within it.
+struct FunctionContext {
+    kind: FunctionKind,
+    is_generator: bool,
+    loop_depth: usize,
+}
+
+fn identifier(token: &Token) -> Identifier {
+    Identifier {
+        offset: Some(token.offset),
+        ..Identifier::new(token.source, token.line)
+    }
+}
+
+/// Finds the last component of a module path, which is the name a module is bound to when it's
+/// imported without an alias. Paths ending in ".." have no such name.
+fn module_file_name(path: &str) -> Option<&str> {
+    path.split('/')
+        .rfind(|component| !component.is_empty() && *component != ".")
+        .filter(|component| *component != "..")
+}
+
+struct Parser<'s, 'a> {
+    current: Token<'a>,
+    previous: Token<'a>,
+    panic_mode: Cell<bool>,
+    single_target_mode: bool,
+    scanner: &'s mut Scanner<'a>,
+    errors: RefCell<Error>,
+    diagnostics: RefCell<Vec<Diagnostic>>,
+    module_path: String,
+    attributes: HashMap<&'a str, Attribute<'a>>,
+    attribute_opener: Option<Token<'a>>,
+    doc_comment: Option<&'a str>,
+    functions: Vec<FunctionContext>,
+    classes: Vec<bool>,
+}
+
+impl<'s, 'a> Parser<'s, 'a> {
+    fn new(scanner: &'s mut Scanner<'a>, module_path: &str) -> Parser<'s, 'a> {
+        Parser {
+            current: Token::new(),
+            previous: Token::new(),
+            panic_mode: Cell::new(false),
+            single_target_mode: false,
+            scanner,
+            errors: RefCell::new(Error::new(ErrorKind::CompileError)),
+            diagnostics: RefCell::new(Vec::new()),
+            module_path: module_path.to_owned(),
+            attributes: HashMap::default(),
+            attribute_opener: None,
+            doc_comment: None,
+            functions: Vec::new(),
+            classes: Vec::new(),
+        }
+    }
+
+    fn parse(&mut self) -> ast::Module {
+        self.push_function(FunctionKind::Script, false);
+        self.advance();
+
+        let attributes = self.module_attributes();
+        let mut statements = Vec::new();
+        while !self.match_token(TokenKind::Eof) {
+            if let Some(statement) = self.declaration() {
+                statements.push(statement);
+            }
+        }
+        self.check_no_attributes();
+
+        ast::Module {
+            attributes,
+            statements,
+            end_line: self.previous.line,
+        }
+    }
+
+    fn advance(&mut self) {
+        self.previous = self.current.clone();
+
+        loop {
+            self.current = self.scanner.scan_token();
+            if self.current.kind != TokenKind::Error {
+                break;
+            }
+
+            self.error_at_current(self.current.source);
+        }
+        self.doc_comment = self.scanner.take_doc_comment();
+    }
+
+    fn consume(&mut self, kind: TokenKind, message: &str) {
+        if self.current.kind == kind {
+            self.advance();
+            return;
+        }
+        self.error_at_current(message);
+    }
+
+    fn check(&self, kind: TokenKind) -> bool {
+        self.current.kind == kind
+    }
+
+    fn check_any(&self, kinds: &[TokenKind]) -> bool {
+        kinds.iter().any(|k| self.check(*k))
+    }
+
+    fn match_token(&mut self, kind: TokenKind) -> bool {
+        if !self.check(kind) {
+            return false;
+        }
+        self.advance();
+        true
+    }
+
+    fn match_binary_assignment(&mut self) -> Option<AssignOperator> {
+        let operator = match self.current.kind {
+            TokenKind::MinusEqual => BinaryOperator::Subtract,
+            TokenKind::PlusEqual => BinaryOperator::Add,
+            TokenKind::SlashEqual => BinaryOperator::Divide,
+            TokenKind::StarEqual => BinaryOperator::Multiply,
+            TokenKind::AmpEqual => BinaryOperator::BitwiseAnd,
+            TokenKind::BarEqual => BinaryOperator::BitwiseOr,
+            TokenKind::CaretEqual => BinaryOperator::BitwiseXor,
+            TokenKind::PercentEqual => BinaryOperator::Modulo,
+            TokenKind::LessLessEqual => BinaryOperator::BitShiftLeft,
+            TokenKind::GreaterGreaterEqual => BinaryOperator::BitShiftRight,
+            _ => return None,
+        };
+        self.advance();
+        Some(AssignOperator {
+            operator,
+            line: self.previous.line,
+        })
+    }
+
+    fn expression(&mut self) -> Expr {
+        let precedence = if self.single_target_mode {
+            Precedence::BitwiseOr
+        } else {
+            Precedence::Assignment
+        };
+        self.parse_precedence(precedence)
+    }
+
+    fn block(&mut self) -> Block {
+        let mut statements = Vec::new();
+        while !self.check(TokenKind::RightBrace) && !self.check(TokenKind::Eof) {
+            if let Some(statement) = self.declaration() {
+                statements.push(statement);
+            }
+        }
+
+        self.consume(TokenKind::RightBrace, "Expected '}' after block.");
+        Block {
+            statements,
+            end_line: self.previous.line,
+        }
+    }
+
+    fn push_function(&mut self, kind: FunctionKind, is_generator: bool) {
+        self.functions.push(FunctionContext {
+            kind,
+            is_generator,
+            loop_depth: 0,
+        });
+    }
+
+    fn function_context(&mut self) -> &mut FunctionContext {
+        self.functions.last_mut().expect("Function stack empty.")
+    }
+
+    fn function(&mut self, kind: FunctionKind, is_generator: bool) -> Function {
+        // The body of a generator is run as a separate function inside a fiber.
+        if is_generator {
+            self.push_function(FunctionKind::Function, true);
+        } else {
+            self.push_function(kind, false);
+        }
+
+        self.consume(TokenKind::LeftParen, "Expected '(' after function name.");
+        if kind == FunctionKind::Initialiser || kind == FunctionKind::Method {
+            self.consume(
+                TokenKind::Self_,
+                "Expected 'self' as first parameter in method.",
+            );
+            self.match_token(TokenKind::Comma);
+        } else if self.match_token(TokenKind::Self_) {
+            self.error("Expected parameter name.");
+            self.match_token(TokenKind::Comma);
+        }
+        let parameters = self.parameter_list(
+            TokenKind::RightParen,
+            "Cannot have more than 255 parameters.",
+            "Expected parameter name.",
+        );
+        self.consume(TokenKind::RightParen, "Expected ')' after parameters.");
+
+        self.consume(TokenKind::LeftBrace, "Expected '{' before function body.");
+        let body_line = self.previous.line;
+        let body = self.block();
+        self.functions.pop();
+
+        Function {
+            parameters,
+            body_line,
+            body,
+        }
+    }
+
+    fn class_member(&mut self, methods: &mut Vec<Method>, fields: &mut Vec<Field>) {
+        let doc_comment = self.doc_comment.take();
+        if self.match_token(TokenKind::Hash) {
+            self.attributes_declaration(&[TokenKind::Identifier]);
+        }
+        self.add_doc_attribute(doc_comment);
+        let doc_comment = self.doc_comment.take();
+        self.add_doc_attribute(doc_comment);
+
+        let static_attr = self.take_attribute("static", 0);
+        if self.match_token(TokenKind::Var) {
+            fields.push(self.field(static_attr));
+        } else {
+            methods.push(self.method(static_attr));
+        }
+    }
+
+    fn field(&mut self, static_attr: Option<Attribute<'a>>) -> Field {
+        let doc = self.take_doc();
+        self.check_supported_attributes("field");
+
+        self.consume(TokenKind::Identifier, "Expected field name.");
+        let name = identifier(&self.previous);
+        // Instance fields are initialised by constructors, where 'self' is the new instance.
+        let kind = if static_attr.is_some() {
+            FunctionKind::StaticField
+        } else {
+            FunctionKind::Initialiser
+        };
+        self.push_function(kind, false);
+        let initialiser = if self.match_token(TokenKind::Equal) {
+            Some(self.expression())
+        } else {
+            None
+        };
+        self.functions.pop();
+        self.consume(
+            TokenKind::SemiColon,
+            "Expected ';' after field declaration.",
+        );
+
+        Field {
+            name,
+            attributes: attributes_to_ast(vec![static_attr]),
+            doc,
+            initialiser,
+            end_line: self.previous.line,
+        }
+    }
+
+    fn method(&mut self, static_attr: Option<Attribute<'a>>) -> Method {
+        let constructor_attr = self.take_attribute("constructor", 0);
+        let doc = self.take_doc();
+        let generator_attr = self.take_attribute("generator", 0);
+        self.check_supported_attributes("method");
+
+        self.consume(TokenKind::Fn, "Expected 'fn' before method name.");
+        self.consume(TokenKind::Identifier, "Expected method name.");
+        let name = identifier(&self.previous);
+
+        let kind = if constructor_attr.is_some() {
+            if let Some(attr) = &static_attr {
+                self.error_at(attr.name.clone(), "Constructors cannot be static.");
+            }
+            if let Some(attr) = &generator_attr {
+                self.error_at(attr.name.clone(), "Constructors cannot be generators.");
+            }
+            FunctionKind::Initialiser
+        } else if static_attr.is_some() {
+            FunctionKind::StaticMethod
+        } else {
+            FunctionKind::Method
+        };
+        let is_generator = generator_attr.is_some() && kind != FunctionKind::Initialiser;
+        let attributes = attributes_to_ast(vec![static_attr, constructor_attr, generator_attr]);
+        let function = self.function(kind, is_generator);
+
+        Method {
+            name,
+            attributes,
+            doc,
+            function,
+        }
+    }
+
+    fn class_declaration(&mut self) -> Stmt {
+        let constructor_attr = self.take_attribute("constructor", 1);
+        let superclass_attr = self.take_attribute("derive", 1);
+        let implements_attr = self.take_attribute("implements", 1);
+        let mixin_attr = self.take_variadic_attribute("mixin");
+        // Doc comments are accepted on classes but aren't yet retained at runtime.
+        let doc = self.take_doc();
+        self.check_supported_attributes("class");
+
+        self.consume(TokenKind::Identifier, "Expected class name.");
+        let name = identifier(&self.previous);
+
+        if let Some(superclass) = superclass_attr.as_ref().map(|a| &a.arguments[0]) {
+            if superclass.source == name.name {
+                self.error("A class cannot inherit from itself.");
+            }
+        }
+        if let Some(mixins) = mixin_attr.as_ref().map(|a| &a.arguments) {
+            if mixins.iter().any(|mixin| mixin.source == name.name) {
+                self.error("A class cannot mix in itself.");
+            }
+        }
+
+        self.consume(TokenKind::LeftBrace, "Expected '{' before class body.");
+        let body_line = self.previous.line;
+
+        self.classes.push(superclass_attr.is_some());
+        let mut methods = Vec::new();
+        let mut fields = Vec::new();
+        while !self.check(TokenKind::RightBrace) && !self.check(TokenKind::Eof) {
+            self.class_member(&mut methods, &mut fields);
+        }
+        self.consume(TokenKind::RightBrace, "Expected '}' after class body.");
+        self.classes.pop();
+
+        Stmt::Class(ClassDecl {
+            name,
+            attributes: attributes_to_ast(vec![
+                constructor_attr,
+                superclass_attr,
+                implements_attr,
+                mixin_attr,
+            ]),
+            doc,
+            body_line,
+            fields,
+            methods,
+            end_line: self.previous.line,
+        })
+    }
+
+    fn fn_declaration(&mut self) -> Stmt {
+        let doc = self.take_doc();
+        let generator_attr = self.take_attribute("generator", 0);
+        let test_attr = self.take_attribute("test", 0);
+        let should_fail_attr = self.take_optional_argument_attribute("should_fail");
+        self.check_supported_attributes("function");
+        self.consume(TokenKind::Identifier, "Expected function name.");
+        let name = identifier(&self.previous);
+        let is_generator = generator_attr.is_some();
+        let function = self.function(FunctionKind::Function, is_generator);
+
+        Stmt::Fn(FnDecl {
+            name,
+            attributes: attributes_to_ast(vec![generator_attr, test_attr, should_fail_attr]),
+            doc,
+            function,
+        })
+    }
+
+    fn take_attribute(&mut self, name: &str, num_args: usize) -> Option<Attribute<'a>> {
+        let attr = self.attributes.remove(name);
+        if let Some(attr) = attr {
+            if attr.arguments.len() != num_args {
+                let msg = format!(
+                    "Expected {} argument{} to '{}' attribute.",
+                    num_args,
+                    if num_args != 1 { "s" } else { "" },
+                    attr.name.source
+                );
+                self.error_at(attr.name, &msg);
+                None
+            } else {
+                Some(attr)
+            }
+        } else {
+            None
+        }
+    }
+
+    fn take_variadic_attribute(&mut self, name: &str) -> Option<Attribute<'a>> {
+        let attr = self.attributes.remove(name)?;
+        if attr.arguments.is_empty() {
+            let msg = format!("Expected at least 1 argument to '{}' attribute.", name);
+            self.error_at(attr.name, &msg);
+            None
+        } else {
+            Some(attr)
+        }
+    }
+
+    fn take_optional_argument_attribute(&mut self, name: &str) -> Option<Attribute<'a>> {
+        let attr = self.attributes.remove(name)?;
+        if attr.arguments.len() > 1 {
+            let msg = format!("Expected at most 1 argument to '{}' attribute.", name);
+            self.error_at(attr.name, &msg);
+            None
+        } else {
+            Some(attr)
+        }
+    }
+
+    fn take_doc(&mut self) -> Option<String> {
+        self.take_attribute("doc", 1)
+            .map(|attr| attr.arguments[0].source.to_owned())
+    }
+
+    fn add_doc_attribute(&mut self, doc_comment: Option<&'a str>) {
+        if let Some(doc_comment) = doc_comment {
+            let line = self.current.line;
+            let attr = Attribute {
+                name: Token::from_string_and_line("doc", line),
+                arguments: vec![Token::from_string_and_line(doc_comment, line)],
+                position: usize::MAX,
+            };
+            self.attributes.insert(attr.name.source, attr);
+        }
+    }
+
+    fn attribute(
+        &mut self,
+        position: usize,
+        argument_kinds: &[TokenKind],
+    ) -> Option<Attribute<'a>> {
+        if !self.match_token(TokenKind::Identifier) {
+            return None;
+        }
+        let name = self.previous.clone();
+        let mut arguments = Vec::new();
+
+        if self.match_token(TokenKind::LeftParen) {
+            loop {
+                if !self.check_any(argument_kinds) {
+                    self.error_at_current("Expected an attribute argument.");
+                    return None;
+                }
+                self.advance();
+                arguments.push(self.previous.clone());
+
+                if !self.match_token(TokenKind::Comma) {
+                    break;
+                }
+            }
+
+            if !self.match_token(TokenKind::RightParen) {
+                self.error_at_current("Expected ')' after attribute arguments.");
+                return None;
+            }
+        }
+
+        Some(Attribute {
+            name,
+            arguments,
+            position,
+        })
+    }
+
+    fn attributes_declaration(&mut self, argument_kinds: &[TokenKind]) {
+        self.check_no_attributes();
+        let opener = self.previous.clone();
+        if !self.match_token(TokenKind::LeftBracket) {
+            self.error_at_current("Expected '[' after '#'.");
+            return;
+        }
+        let mut attributes = HashMap::default();
+
+        while let Some(attribute) = self.attribute(attributes.len(), argument_kinds) {
+            if attributes
+                .insert(attribute.name.source, attribute)
+                .is_some()
+            {
+                self.error(&format!("Duplicate attribute '{}'.", self.previous.source));
+                break;
+            }
+
+            if !self.match_token(TokenKind::Comma) {
+                break;
+            }
+        }
+        if attributes.is_empty() {
+            self.error_at_current("Expected at least one attribute.");
+        }
+        if !self.match_token(TokenKind::RightBracket) {
+            self.error_at_current("Expected ']' after attribute list.");
+            return;
+        }
+        self.attribute_opener = Some(opener);
+        self.attributes = attributes;
+    }
+
+    /// Parses the `#![...]` attribute lists at the start of a module, which apply to the whole
+    /// file rather than the declaration that follows them.
+    fn module_attributes(&mut self) -> Vec<ast::Attribute> {
+        let mut module_attributes = Vec::new();
+        while self.check(TokenKind::Hash) {
+            let doc_comment = self.doc_comment.take();
+            self.advance();
+            if !self.match_token(TokenKind::Bang) {
+                self.attributes_declaration(&[TokenKind::Identifier]);
+                self.add_doc_attribute(doc_comment);
+                break;
+            }
+            self.attributes_declaration(&[TokenKind::Identifier, TokenKind::Number]);
+            let strict_attr = self.take_attribute("strict", 0);
+            let lang_attr = self.take_attribute("lang", 1);
+            self.check_supported_attributes("module");
+
+            if let Some(attr) = &lang_attr {
+                let version = &attr.arguments[0];
+                let is_supported = version
+                    .source
+                    .parse::<u32>()
+                    .is_ok_and(|v| (1..=common::LANG_VERSION).contains(&v));
+                if !is_supported {
+                    let msg = format!("Unsupported language version '{}'.", version.source);
+                    self.error_at(version.clone(), &msg);
+                }
+            }
+            for attr in strict_attr.iter().chain(lang_attr.iter()) {
+                if ast::find_attribute(&module_attributes, attr.name.source).is_some() {
+                    let msg = format!("Duplicate attribute '{}'.", attr.name.source);
+                    self.error_at(attr.name.clone(), &msg);
+                }
+            }
+            module_attributes.extend(attributes_to_ast(vec![strict_attr, lang_attr]));
+        }
+        module_attributes
+    }
+
+    fn var_declaration(&mut self) -> Stmt {
+        self.check_no_attributes();
+        self.consume(TokenKind::Identifier, "Expected variable name.");
+        let name = identifier(&self.previous);
+
+        let initialiser = if self.match_token(TokenKind::Equal) {
+            Some(self.expression())
+        } else {
+            None
+        };
+        self.consume(
+            TokenKind::SemiColon,
+            "Expected ';' after variable declaration.",
+        );
+
+        Stmt::Var {
+            name,
+            initialiser,
+            end_line: self.previous.line,
+        }
+    }
+
+    fn expression_statement(&mut self) -> Stmt {
+        let expr = self.expression();
+        self.consume(TokenKind::SemiColon, "Expected ';' after expression.");
+        Stmt::Expression {
+            expr,
+            end_line: self.previous.line,
+        }
+    }
+
+    fn import_statement(&mut self) -> Stmt {
+        let lazy_attr = self.take_attribute("lazy", 0);
+        self.check_supported_attributes("import");
+        self.consume(TokenKind::Str, "Expected a module path.");
+        let path = self.previous.clone();
+        if path.source == "main" {
+            self.error("Cannot import top-level module.");
+        }
+
+        let alias = if self.match_token(TokenKind::As) {
+            self.consume(TokenKind::Identifier, "Expected module name.");
+            Some(identifier(&self.previous))
+        } else {
+            None
+        };
+        let binding = match &alias {
+            Some(alias) => alias.clone(),
+            None => {
+                if let Some(filename) = module_file_name(path.source) {
+                    Identifier::new(filename, self.current.line)
+                } else {
+                    self.error("Expected a module path.");
+                    return Stmt::Error;
+                }
+            }
+        };
+
+        self.consume(TokenKind::SemiColon, "Expected ';' after module import.");
+
+        Stmt::Import {
+            attributes: attributes_to_ast(vec![lazy_attr]),
+            path: identifier(&path),
+            alias,
+            binding,
+            end_line: self.previous.line,
+        }
+    }
+
+    fn export_statement(&mut self) -> Stmt {
+        let keyword_line = self.previous.line;
+        let mut names = Vec::new();
+        loop {
+            self.consume(TokenKind::Identifier, "Expected name to export.");
+            names.push(identifier(&self.previous));
+            if !self.match_token(TokenKind::Comma) {
+                break;
+            }
+        }
+        self.consume(TokenKind::SemiColon, "Expected ';' after exported names.");
+
+        Stmt::Export {
+            keyword_line,
+            names,
+            end_line: self.previous.line,
+        }
+    }
+
+    fn for_statement(&mut self) -> Stmt {
+        if !self.match_token(TokenKind::Identifier) {
+            self.error_at_current("Expected loop variable name.");
+            return Stmt::Error;
+        }
+        let variable = identifier(&self.previous);
+
+        self.consume(TokenKind::In, "Expected 'in' after loop variable.");
+        let iterable = self.expression();
+        let iterable_end_line = self.previous.line;
+
+        self.consume(TokenKind::LeftBrace, "Expected '{' after loop expression.");
+        let body_line = self.previous.line;
+        let body = self.loop_body();
+
+        Stmt::For {
+            variable,
+            iterable,
+            iterable_end_line,
+            body_line,
+            body,
+        }
+    }
+
+    fn loop_body(&mut self) -> Block {
+        self.function_context().loop_depth += 1;
+        let body = self.block();
+        self.function_context().loop_depth -= 1;
+        body
+    }
+
+    fn if_statement(&mut self) -> Stmt {
+        let condition = self.expression();
+        let condition_end_line = self.previous.line;
+
+        self.consume(TokenKind::LeftBrace, "Expected '{' after condition.");
+        let then_branch = self.block();
+
+        let else_branch = if self.match_token(TokenKind::Else) {
+            if !self.check_any(&[TokenKind::If, TokenKind::LeftBrace]) {
+                self.error_at_current("Expected '{' after 'else'.");
+            }
+            Some(Box::new(self.statement()))
+        } else {
+            None
+        };
+
+        Stmt::If {
+            condition,
+            condition_end_line,
+            then_branch,
+            else_branch,
+        }
+    }
+
+    fn return_statement(&mut self) -> Stmt {
+        let keyword_line = self.previous.line;
+        if self.function_context().kind == FunctionKind::Script {
+            self.error("Cannot return from top-level code.");
+        }
+        let value = if self.match_token(TokenKind::SemiColon) {
+            None
+        } else {
+            if self.function_context().kind == FunctionKind::Initialiser {
+                self.error("Cannot return a value from an initialiser.");
+            }
+            let value = self.expression();
+            self.consume(TokenKind::SemiColon, "Expected ';' after return value.");
+            Some(value)
+        };
+
+        Stmt::Return {
+            keyword_line,
+            value,
+            end_line: self.previous.line,
+        }
+    }
+
+    fn yield_statement(&mut self) -> Stmt {
+        let keyword_line = self.previous.line;
+        if !self.function_context().is_generator {
+            self.error("Can only yield from inside a generator function.");
+        }
+        let value = self.expression();
+        self.consume(TokenKind::SemiColon, "Expected ';' after yield value.");
+
+        Stmt::Yield {
+            keyword_line,
+            value,
+            end_line: self.previous.line,
+        }
+    }
+
+    fn break_statement(&mut self) -> Stmt {
+        let keyword_line = self.previous.line;
+        let in_loop = self.function_context().loop_depth > 0;
+        if !in_loop {
+            self.error("Cannot use 'break' statement outside of loop body.");
+        }
+        self.consume(TokenKind::SemiColon, "Expected ';' after 'break'.");
+        // The code generator relies on there being an enclosing loop to break out of.
+        if in_loop {
+            Stmt::Break { keyword_line }
+        } else {
+            Stmt::Error
+        }
+    }
+
+    fn continue_statement(&mut self) -> Stmt {
+        let keyword_line = self.previous.line;
+        let in_loop = self.function_context().loop_depth > 0;
+        if !in_loop {
+            self.error("Cannot use 'continue' statement outside of loop body.");
+        }
+        self.consume(TokenKind::SemiColon, "Expected ';' after 'continue'.");
+        if in_loop {
+            Stmt::Continue { keyword_line }
+        } else {
+            Stmt::Error
+        }
+    }
+
+    fn throw_statement(&mut self) -> Stmt {
+        let value = self.expression();
+        self.consume(TokenKind::SemiColon, "Expected ';' after throw value.");
+        Stmt::Throw {
+            value,
+            end_line: self.previous.line,
+        }
+    }
+
+    fn try_statement(&mut self) -> Stmt {
+        let keyword_line = self.previous.line;
+
+        self.consume(TokenKind::LeftBrace, "Expected '{' after 'try'.");
+        let body = self.block();
+
+        let mut catches: Vec<ast::Catch> = Vec::new();
+        while self.match_token(TokenKind::Catch) {
+            let keyword_line = self.previous.line;
+            if catches.last().is_some_and(|c| c.class.is_none()) {
+                self.error(
+                    "Cannot follow a 'catch' without an exception class with another 'catch'.",
+                );
+            }
+            if !self.match_token(TokenKind::Identifier) {
+                self.error_at_current("Expected exception variable name.");
+                return Stmt::Error;
+            }
+            let variable = identifier(&self.previous);
+
+            let class = if self.match_token(TokenKind::Colon) {
+                let class = self.expression();
+                self.consume(TokenKind::LeftBrace, "Expected '{' after exception class.");
+                Some(class)
+            } else {
+                self.consume(TokenKind::LeftBrace, "Expected '{' after variable.");
+                None
+            };
+            let body = self.block();
+
+            catches.push(ast::Catch {
+                keyword_line,
+                variable,
+                class,
+                body,
+            });
+        }
+
+        let finally = if self.match_token(TokenKind::Finally) {
+            self.consume(TokenKind::LeftBrace, "Expected '{' after 'finally'.");
+            Some(self.block())
+        } else {
+            None
+        };
+
+        if catches.is_empty() && finally.is_none() {
+            self.error("Expected 'catch' or 'finally' after 'try' block.");
+        }
+
+        Stmt::Try {
+            keyword_line,
+            body,
+            catches,
+            finally,
+        }
+    }
+
+    fn while_statement(&mut self) -> Stmt {
+        let condition = self.expression();
+        let condition_end_line = self.previous.line;
+
+        self.consume(TokenKind::LeftBrace, "Expected '{' after condition.");
+        let body = self.loop_body();
+
+        Stmt::While {
+            condition,
+            condition_end_line,
+            body,
+        }
+    }
+
+    fn synchronise(&mut self) {
+        self.panic_mode.set(false);
+
+        while self.current.kind != TokenKind::Eof {
+            if self.previous.kind == TokenKind::SemiColon {
+                return;
+            }
+
+            match self.current.kind {
+                TokenKind::Hash => return,
+                TokenKind::Class => return,
+                TokenKind::Fn => return,
+                TokenKind::Var => return,
+                TokenKind::For => return,
+                TokenKind::If => return,
+                TokenKind::While => return,
+                TokenKind::Break => return,
+                TokenKind::Continue => return,
+                TokenKind::Return => return,
+                TokenKind::Yield => return,
+                _ => {}
+            }
+
+            self.advance();
+        }
+    }
+
+    fn statement(&mut self) -> Stmt {
+        self.check_no_attributes();
+        if self.match_token(TokenKind::Import) {
+            self.import_statement()
+        } else if self.match_token(TokenKind::Export) {
+            self.export_statement()
+        } else if self.match_token(TokenKind::For) {
+            self.for_statement()
+        } else if self.match_token(TokenKind::If) {
+            self.if_statement()
+        } else if self.match_token(TokenKind::Return) {
+            self.return_statement()
+        } else if self.match_token(TokenKind::Yield) {
+            self.yield_statement()
+        } else if self.match_token(TokenKind::Break) {
+            self.break_statement()
+        } else if self.match_token(TokenKind::Continue) {
+            self.continue_statement()
+        } else if self.match_token(TokenKind::Throw) {
+            self.throw_statement()
+        } else if self.match_token(TokenKind::Try) {
+            self.try_statement()
+        } else if self.match_token(TokenKind::While) {
+            self.while_statement()
+        } else if self.match_token(TokenKind::LeftBrace) {
+            Stmt::Block(self.block())
+        } else {
+            self.expression_statement()
+        }
+    }
+
+    /// Parses a declaration or statement. An attribute list is attached to the declaration that
+    /// follows it, so on its own it doesn't produce a statement.
+    fn declaration(&mut self) -> Option<Stmt> {
+        let doc_comment = self.doc_comment.take();
+        let statement = if self.match_token(TokenKind::Class) {
+            self.add_doc_attribute(doc_comment);
+            Some(self.class_declaration())
+        } else if self.match_token(TokenKind::Fn) {
+            self.add_doc_attribute(doc_comment);
+            Some(self.fn_declaration())
+        } else if self.match_token(TokenKind::Hash) {
+            if self.match_token(TokenKind::Bang) {
+                self.error("Module attributes must come before any declarations.");
+            } else {
+                self.attributes_declaration(&[TokenKind::Identifier]);
+                self.add_doc_attribute(doc_comment);
+            }
+            None
+        } else if self.match_token(TokenKind::Var) {
+            Some(self.var_declaration())
+        } else if self.match_token(TokenKind::Import) {
+            Some(self.import_statement())
+        } else {
+            Some(self.statement())
+        };
+
+        if self.panic_mode.get() {
+            self.synchronise();
+        }
+
+        statement
+    }
+
+    fn parse_precedence(&mut self, precedence: Precedence) -> Expr {
+        self.advance();
+        let kind = self.previous.kind;
+        let prefix_rule = self.get_rule(kind).prefix;
+        let can_assign = precedence as usize <= Precedence::Assignment as usize;
+
+        let mut expr = match prefix_rule {
+            Some(ref handler) => handler(self, can_assign),
+            None => {
+                self.error("Expected expression.");
+                return Expr::Error;
+            }
+        };
+
+        while precedence as usize <= self.get_rule(self.current.kind).precedence as usize {
+            self.advance();
+            let infix_rule = self.get_rule(self.previous.kind).infix;
+            expr = infix_rule.unwrap()(self, expr, can_assign);
+        }
+
+        if can_assign && self.match_token(TokenKind::Equal) {
+            self.error("Invalid assignment target.");
+        }
+
+        expr
+    }
+
+    fn argument_list(
+        &mut self,
+        right_delim: TokenKind,
+        count_msg: Option<&str>,
+        delim_msg: &str,
+        allow_names: bool,
+    ) -> Vec<Argument> {
+        let mut arguments = Vec::new();
+        let mut arg_count: usize = 0;
+        let mut spread = false;
+        let mut names: Vec<Token> = Vec::new();
+        if !self.check(right_delim) {
+            loop {
+                if self.match_token(TokenKind::Star) {
+                    if !names.is_empty() {
+                        self.error("Cannot spread arguments in a call with named arguments.");
+                    }
+                    spread = true;
+                    arg_count = 0;
+                    let value = self.expression();
+                    arguments.push(Argument::Spread {
+                        value,
+                        line: self.previous.line,
+                    });
+                } else {
+                    let mut name = None;
+                    if self.check(TokenKind::Identifier)
+                        && self.scanner.peek_token().kind == TokenKind::Colon
+                    {
+                        self.advance();
+                        let token = self.previous.clone();
+                        if !allow_names {
+                            self.error("Named arguments are only allowed in calls.");
+                        } else if spread {
+                            self.error("Cannot spread arguments in a call with named arguments.");
+                        } else if names.iter().any(|n| n.source == token.source) {
+                            let msg = format!("Duplicate named argument '{}'.", token.source);
+                            self.error(&msg);
+                        }
+                        name = Some(identifier(&token));
+                        names.push(token);
+                        self.advance();
+                    } else if !names.is_empty() {
+                        self.error_at_current("Positional argument follows named arguments.");
+                    }
+                    let value = self.expression();
+                    if let (255, Some(count_msg)) = (arg_count, count_msg) {
+                        self.error(count_msg);
+                    }
+                    arg_count += 1;
+                    arguments.push(match name {
+                        Some(name) => Argument::Named(name, value),
+                        None => Argument::Positional(value),
+                    });
+                }
+
+                if !self.match_token(TokenKind::Comma) || self.check(right_delim) {
+                    break;
+                }
+            }
+        }
+
+        self.consume(right_delim, delim_msg);
+        arguments
+    }
+
+    fn parameter_list(
+        &mut self,
+        right_delim: TokenKind,
+        count_msg: &str,
+        param_msg: &str,
+    ) -> Vec<Identifier> {
+        let mut parameters = Vec::new();
+        if !self.check(right_delim) {
+            loop {
+                if parameters.len() == 255 {
+                    self.error_at_current(count_msg);
+                }
+
+                self.consume(TokenKind::Identifier, param_msg);
+                // Excess parameters are dropped so that they aren't reported again as too many
+                // variables when the function is compiled.
+                if parameters.len() < 255 {
+                    parameters.push(identifier(&self.previous));
+                }
+
+                if !self.match_token(TokenKind::Comma) {
+                    break;
+                }
+            }
+        }
+        parameters
+    }
+
+    fn get_rule(&self, kind: TokenKind) -> &ParseRule {
+        &RULES[kind as usize]
+    }
+
+    fn error_at_current(&self, message: &str) {
+        self.error_at(self.current.clone(), message);
+    }
+
+    fn error(&self, message: &str) {
+        self.error_at(self.previous.clone(), message);
+    }
+
+    fn error_at(&self, token: Token, message: &str) {
+        if self.panic_mode.get() {
+            return;
+        }
+        self.panic_mode.set(true);
+
+        let mut error_string = String::new();
+
+        write!(
+            error_string,
+            "[module \"{}\", line {}] Error",
+            self.module_path, token.line
+        )
+        .unwrap();
+
+        match token.kind {
+            TokenKind::Eof => write!(error_string, " at end").unwrap(),
+            TokenKind::Error => {}
+            _ => write!(error_string, " at '{}'", token.source).unwrap(),
+        };
+
+        write!(error_string, ": {}", message).unwrap();
+        let span = Span::from_offset(self.scanner.source(), token.offset, token.length);
+        self.errors
+            .borrow_mut()
+            .add_message_with_span(&error_string, Some(span.clone()));
+        self.diagnostics.borrow_mut().push(Diagnostic {
+            severity: Severity::Error,
+            code: "syntax-error",
+            line: token.line,
+            span: Some(span),
+            message: message.to_owned(),
+        });
+    }
+
+    fn check_no_attributes(&mut self) {
+        if let Some(opener) = self.attribute_opener.take() {
+            self.error_at(opener, "Unexpected attribute list.");
+        }
+        self.attributes.clear();
+    }
+
+    fn check_supported_attributes(&mut self, kind: &str) {
+        for attr in self.attributes.values() {
+            let msg = format!("Unsupported {} attribute '{}'.", kind, attr.name.source);
+            self.error_at(attr.name.clone(), &msg);
+        }
+        self.attributes.clear();
+        self.attribute_opener = None;
+    }
+
+    fn assignment(&mut self, can_assign: bool) -> Option<(Option<AssignOperator>, Expr)> {
+        if !can_assign {
+            return None;
+        }
+        if self.match_token(TokenKind::Equal) {
+            return Some((None, self.expression()));
+        }
+        let operator = self.match_binary_assignment()?;
+        self.single_target_mode = true;
+        let value = self.expression();
+        self.single_target_mode = false;
+        Some((Some(operator), value))
+    }
+
+    fn grouping(s: &mut Parser, _can_assign: bool) -> Expr {
+        let mut trailing_comma = false;
+        let mut elements = Vec::new();
+        if !s.check(TokenKind::RightParen) {
+            loop {
+                elements.push(s.expression());
+                if elements.len() == 1 && s.match_token(TokenKind::For) {
+                    let element = elements.pop().unwrap();
+                    return s.comprehension(ComprehensionKind::Generator, element);
+                }
+                if elements.len() == 256 {
+                    s.error("Cannot have more than 255 Tuple elements.");
+                }
+
+                if !s.match_token(TokenKind::Comma) {
+                    break;
+                }
+                if s.check(TokenKind::RightParen) {
+                    trailing_comma = true;
+                    break;
+                }
+            }
+        }
+
+        let is_tuple = elements.len() != 1 || trailing_comma;
+        let line = s.previous.line;
+
+        let msg = &format!(
+            "Expected ')' after {}.",
+            if is_tuple { "elements" } else { "expression" }
+        );
+        s.consume(TokenKind::RightParen, msg);
+
+        if is_tuple {
+            Expr::Tuple { elements, line }
+        } else {
+            Expr::Grouping(Box::new(elements.pop().unwrap()))
+        }
+    }
+
+    fn binary(s: &mut Parser, left: Expr, _can_assign: bool) -> Expr {
+        let operator_kind = s.previous.kind;
+        let rule_precedence = s.get_rule(operator_kind).precedence;
+        let right = s.parse_precedence(Precedence::from(rule_precedence as usize + 1));
+
+        let operator = match operator_kind {
+            TokenKind::BangEqual => BinaryOperator::NotEqual,
+            TokenKind::EqualEqual => BinaryOperator::Equal,
+            TokenKind::Greater => BinaryOperator::Greater,
+            TokenKind::GreaterEqual => BinaryOperator::GreaterEqual,
+            TokenKind::Less => BinaryOperator::Less,
+            TokenKind::LessEqual => BinaryOperator::LessEqual,
+            TokenKind::Plus => BinaryOperator::Add,
+            TokenKind::Minus => BinaryOperator::Subtract,
+            TokenKind::Star => BinaryOperator::Multiply,
+            TokenKind::Slash => BinaryOperator::Divide,
+            TokenKind::Amp => BinaryOperator::BitwiseAnd,
+            TokenKind::Bar => BinaryOperator::BitwiseOr,
+            TokenKind::Caret => BinaryOperator::BitwiseXor,
+            TokenKind::Percent => BinaryOperator::Modulo,
+            TokenKind::LessLess => BinaryOperator::BitShiftLeft,
+            TokenKind::GreaterGreater => BinaryOperator::BitShiftRight,
+            TokenKind::Is => BinaryOperator::Is,
+            _ => unreachable!(),
+        };
+
+        Expr::Binary {
+            operator,
+            left: Box::new(left),
+            right: Box::new(right),
+            line: s.previous.line,
+        }
+    }
+
+    fn call(s: &mut Parser, callee: Expr, _can_assign: bool) -> Expr {
+        let arguments = s.argument_list(
+            TokenKind::RightParen,
+            Some("Cannot have more than 255 arguments."),
+            "Expected ')' after arguments.",
+            true,
+        );
+        Expr::Call {
+            callee: Box::new(callee),
+            arguments,
+            end_line: s.previous.line,
+        }
+    }
+
+    fn dot(s: &mut Parser, object: Expr, can_assign: bool) -> Expr {
+        // 'yield' is still usable as a property name so that Fiber.yield() keeps working.
+        if !s.match_token(TokenKind::Yield) {
+            s.consume(TokenKind::Identifier, "Expected property name after '.'.");
+        }
+        let name = identifier(&s.previous);
+        let object = Box::new(object);
+
+        match s.assignment(can_assign) {
+            Some((operator, value)) => Expr::Set {
+                object,
+                name,
+                operator,
+                value: Box::new(value),
+                line: s.previous.line,
+            },
+            None => Expr::Get { object, name },
+        }
+    }
+
+    fn dotdot(s: &mut Parser, start: Expr, _can_assign: bool) -> Expr {
+        let end = s.parse_precedence(Precedence::Unary);
+        let step = if s.match_token(TokenKind::Colon) {
+            Some(Box::new(s.parse_precedence(Precedence::Unary)))
+        } else {
+            None
+        };
+        Expr::Range {
+            start: Box::new(start),
+            end: Box::new(end),
+            step,
+            line: s.previous.line,
+        }
+    }
+
+    fn index(s: &mut Parser, object: Expr, can_assign: bool) -> Expr {
+        let index = s.expression();
+        s.consume(TokenKind::RightBracket, "Expected ']' after index.");
+
+        match s.assignment(can_assign) {
+            Some((operator, value)) => Expr::SetIndex {
+                object: Box::new(object),
+                index: Box::new(index),
+                operator,
+                value: Box::new(value),
+                line: s.previous.line,
+            },
+            None => Expr::Index {
+                object: Box::new(object),
+                index: Box::new(index),
+                end_line: s.previous.line,
+            },
+        }
+    }
+
+    fn lambda(s: &mut Parser, _can_assign: bool) -> Expr {
+        s.push_function(FunctionKind::Function, false);
+        let parameters = if s.previous.kind == TokenKind::Bar {
+            let parameters = s.parameter_list(
+                TokenKind::Bar,
+                "Cannot have more than 255 parameters.",
+                "Expected parameter name.",
+            );
+            s.consume(TokenKind::Bar, "Expected ')' after parameters.");
+            parameters
+        } else {
+            Vec::new()
+        };
+
+        let body = if s.match_token(TokenKind::LeftBrace) {
+            LambdaBody::Block(s.block())
+        } else {
+            let expr = s.expression();
+            LambdaBody::Expr {
+                expr: Box::new(expr),
+                end_line: s.previous.line,
+            }
+        };
+
+        s.functions.pop();
+
+        Expr::Lambda { parameters, body }
+    }
+
+    fn hash_map(s: &mut Parser, _can_assign: bool) -> Expr {
+        let mut entries = Vec::new();
+        if !s.check(TokenKind::RightBrace) {
+            loop {
+                let key = s.expression();
+                s.consume(TokenKind::Colon, "Expected ':' after key.");
+                let value = s.expression();
+                entries.push((key, value));
+
+                if !s.match_token(TokenKind::Comma) || s.check(TokenKind::RightBrace) {
+                    break;
+                }
+            }
+        }
+
+        s.consume(TokenKind::RightBrace, "Expected '}' after elements.");
+        Expr::HashMap {
+            entries,
+            end_line: s.previous.line,
+        }
+    }
+
+    fn vector(s: &mut Parser, _can_assign: bool) -> Expr {
+        let msg = "Expected ']' after elements.";
+        // The first element is parsed here if it could start a comprehension, which is recognised
+        // by the 'for' that follows it.
+        let mut elements = Vec::new();
+        if !s.check(TokenKind::RightBracket)
+            && !s.check(TokenKind::Star)
+            && s.scanner.peek_token().kind != TokenKind::Colon
+        {
+            let element = s.expression();
+            if s.match_token(TokenKind::For) {
+                return s.comprehension(ComprehensionKind::Vec, element);
+            }
+            elements.push(Argument::Positional(element));
+            if !s.match_token(TokenKind::Comma) || s.check(TokenKind::RightBracket) {
+                s.consume(TokenKind::RightBracket, msg);
+                return Expr::Vec {
+                    elements,
+                    end_line: s.previous.line,
+                };
+            }
+        }
+        elements.extend(s.argument_list(TokenKind::RightBracket, None, msg, false));
+        Expr::Vec {
+            elements,
+            end_line: s.previous.line,
+        }
+    }
+
+    /// Parses the rest of a comprehension, following the 'for' after its element.
+    fn comprehension(&mut self, kind: ComprehensionKind, element: Expr) -> Expr {
+        self.consume(TokenKind::Identifier, "Expected loop variable name.");
+        let variable = identifier(&self.previous);
+        self.consume(TokenKind::In, "Expected 'in' after loop variable.");
+        let iterable = self.expression();
+        let condition = if self.match_token(TokenKind::If) {
+            Some(self.expression())
+        } else {
+            None
+        };
+
+        match kind {
+            ComprehensionKind::Vec => {
+                self.consume(TokenKind::RightBracket, "Expected ']' after comprehension.")
+            }
+            ComprehensionKind::Generator => {
+                self.consume(TokenKind::RightParen, "Expected ')' after comprehension.")
+            }
+        }
+        Expr::Comprehension(Box::new(Comprehension {
+            kind,
+            element,
+            variable,
+            iterable,
+            condition,
+            end_line: self.previous.line,
+        }))
+    }
+
+    fn unary(s: &mut Parser, _can_assign: bool) -> Expr {
+        let operator = match s.previous.kind {
+            TokenKind::Minus => UnaryOperator::Negate,
+            TokenKind::Bang => UnaryOperator::Not,
+            TokenKind::Tilde => UnaryOperator::BitwiseNot,
+            _ => unreachable!(),
+        };
+        let operand = s.parse_precedence(Precedence::Unary);
+
+        Expr::Unary {
+            operator,
+            operand: Box::new(operand),
+            line: s.previous.line,
+        }
+    }
+
+    fn number(s: &mut Parser, _can_assign: bool) -> Expr {
+        match utils::parse_number(s.previous.source) {
+            Some(value) => Expr::Number {
+                value,
+                text: s.previous.source.to_owned(),
+                line: s.previous.line,
+            },
+            None => {
+                s.error("Unable to parse number.");
+                Expr::Error
+            }
+        }
+    }
+
+    fn literal(s: &mut Parser, _can_assign: bool) -> Expr {
+        let value = match s.previous.kind {
+            TokenKind::False => Literal::False,
+            TokenKind::Nil => Literal::Nil,
+            TokenKind::True => Literal::True,
+            _ => unreachable!(),
+        };
+        Expr::Literal {
+            value,
+            line: s.previous.line,
+        }
+    }
+
+    fn string(s: &mut Parser, _can_assign: bool) -> Expr {
+        Expr::Str {
+            value: s.previous.source.to_owned(),
+            line: s.previous.line,
+        }
+    }
+
+    fn interpolation(s: &mut Parser, _can_assign: bool) -> Expr {
+        let mut parts = Vec::new();
+        loop {
+            let text = s.previous.source.to_owned();
+            let text_line = s.previous.line;
+            let expr = s.expression();
+            parts.push(InterpolationPart {
+                text,
+                text_line,
+                expr,
+                expr_end_line: s.previous.line,
+            });
+            if !s.match_token(TokenKind::Interpolation) {
+                break;
+            }
+        }
+
+        s.advance();
+        Expr::Interpolation {
+            parts,
+            tail: s.previous.source.to_owned(),
+            end_line: s.previous.line,
+        }
+    }
+
+    fn variable(s: &mut Parser, can_assign: bool) -> Expr {
+        let name = identifier(&s.previous);
+        match s.assignment(can_assign) {
+            Some((operator, value)) => Expr::Assign {
+                name,
+                operator,
+                value: Box::new(value),
+                line: s.previous.line,
+            },
+            None => Expr::Variable(name),
+        }
+    }
+
+    fn self_(s: &mut Parser, _can_assign: bool) -> Expr {
+        if s.classes.is_empty() {
+            s.error("Cannot use 'self' outside of a class.");
+        } else if s.function_context().kind == FunctionKind::StaticMethod {
+            s.error("Cannot use 'self' in a static method.");
+        } else if s.function_context().kind == FunctionKind::StaticField {
+            s.error("Cannot use 'self' in a static field.");
+        }
+        Expr::SelfRef {
+            line: s.previous.line,
+        }
+    }
+
+    fn cap_self(s: &mut Parser, _can_assign: bool) -> Expr {
+        if s.classes.is_empty() {
+            s.error("Cannot use 'Self' outside of a class.");
+        }
+        Expr::SelfClass {
+            line: s.previous.line,
+        }
+    }
+
+    fn super_(s: &mut Parser, _can_assign: bool) -> Expr {
+        let keyword_line = s.previous.line;
+        match s.classes.last() {
+            None => s.error("Cannot use 'super' outside of a class."),
+            Some(false) => s.error("Cannot use 'super' in a class with no superclass."),
+            Some(true) => {}
+        }
+        s.consume(TokenKind::Dot, "Expected '.' after 'super'.");
+        s.consume(TokenKind::Identifier, "Expected superclass method name.");
+        let method = identifier(&s.previous);
+
+        let call = if s.match_token(TokenKind::LeftParen) {
+            let arguments = s.argument_list(
+                TokenKind::RightParen,
+                Some("Cannot have more than 255 arguments."),
+                "Expected ')' after arguments.",
+                true,
+            );
+            if arguments
+                .iter()
+                .any(|a| matches!(a, Argument::Spread { .. }))
+            {
+                s.error("Cannot spread arguments in a call to a superclass method.");
+            } else if arguments.iter().any(|a| matches!(a, Argument::Named(..))) {
+                s.error("Cannot use named arguments in a call to a superclass method.");
+            }
+            Some((arguments, s.previous.line))
+        } else {
+            None
+        };
+
+        Expr::Super {
+            keyword_line,
+            method,
+            call,
+        }
+    }
+
+    fn and(s: &mut Parser, left: Expr, _can_assign: bool) -> Expr {
+        let line = s.previous.line;
+        let right = s.parse_precedence(Precedence::And);
+        Expr::Logical {
+            operator: LogicalOperator::And,
+            left: Box::new(left),
+            right: Box::new(right),
+            line,
+        }
+    }
+
+    fn or(s: &mut Parser, left: Expr, _can_assign: bool) -> Expr {
+        let line = s.previous.line;
+        let right = s.parse_precedence(Precedence::Or);
+        Expr::Logical {
+            operator: LogicalOperator::Or,
+            left: Box::new(left),
+            right: Box::new(right),
+            line,
+        }
+    }
+}
+
+/// Converts the attributes that were accepted for a declaration, ordering them as they appeared
+/// in the source.
+fn attributes_to_ast(attributes: Vec<Option<Attribute>>) -> Vec<ast::Attribute> {
+    let mut attributes: Vec<_> = attributes.into_iter().flatten().collect();
+    attributes.sort_by_key(|a| a.position);
+    attributes.iter().map(Attribute::to_ast).collect()
+}
+
+const RULES: [ParseRule; 75] = [
+    // LeftParen
+    ParseRule {
+        prefix: Some(Parser::grouping),
+        infix: Some(Parser::call),
+        precedence: Precedence::Call,
+    },
+    // RightParen
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // LeftBrace
+    ParseRule {
+        prefix: Some(Parser::hash_map),
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // RightBrace
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // LeftBracket
+    ParseRule {
+        prefix: Some(Parser::vector),
+        infix: Some(Parser::index),
+        precedence: Precedence::Call,
+    },
+    // RightBracket
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // Comma
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // Dot
+    ParseRule {
+        prefix: None,
+        infix: Some(Parser::dot),
+        precedence: Precedence::Call,
+    },
+    // DotDot
+    ParseRule {
+        prefix: None,
+        infix: Some(Parser::dotdot),
+        precedence: Precedence::Range,
+    },
+    // Minus
+    ParseRule {
+        prefix: Some(Parser::unary),
+        infix: Some(Parser::binary),
+        precedence: Precedence::Term,
+    },
+    // MinusEqual
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // Plus
+    ParseRule {
+        prefix: None,
+        infix: Some(Parser::binary),
+        precedence: Precedence::Term,
+    },
+    // PlusEqual
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // Colon
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // SemiColon
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // Slash
+    ParseRule {
+        prefix: None,
+        infix: Some(Parser::binary),
+        precedence: Precedence::Factor,
+    },
+    // SlashEqual
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // Star
+    ParseRule {
+        prefix: None,
+        infix: Some(Parser::binary),
+        precedence: Precedence::Factor,
+    },
+    // StarEqual
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // Bang
+    ParseRule {
+        prefix: Some(Parser::unary),
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // BangEqual
+    ParseRule {
+        prefix: None,
+        infix: Some(Parser::binary),
+        precedence: Precedence::Equality,
+    },
+    // Equal
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // EqualEqual
+    ParseRule {
+        prefix: None,
+        infix: Some(Parser::binary),
+        precedence: Precedence::Equality,
+    },
+    // Greater
+    ParseRule {
+        prefix: None,
+        infix: Some(Parser::binary),
+        precedence: Precedence::Comparison,
+    },
+    // GreaterEqual
+    ParseRule {
+        prefix: None,
+        infix: Some(Parser::binary),
+        precedence: Precedence::Comparison,
+    },
+    // Less
+    ParseRule {
+        prefix: None,
+        infix: Some(Parser::binary),
+        precedence: Precedence::Comparison,
+    },
+    // LessEqual
+    ParseRule {
+        prefix: None,
+        infix: Some(Parser::binary),
+        precedence: Precedence::Comparison,
+    },
+    // Amp
+    ParseRule {
+        prefix: None,
+        infix: Some(Parser::binary),
+        precedence: Precedence::BitwiseAnd,
+    },
+    // AmpEqual
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // Bar
+    ParseRule {
+        prefix: Some(Parser::lambda),
+        infix: Some(Parser::binary),
+        precedence: Precedence::BitwiseOr,
+    },
+    // BarEqual
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // Caret
+    ParseRule {
+        prefix: None,
+        infix: Some(Parser::binary),
+        precedence: Precedence::BitwiseXor,
+    },
+    // CaretEqual
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // Percent
+    ParseRule {
+        prefix: None,
+        infix: Some(Parser::binary),
+        precedence: Precedence::Factor,
+    },
+    // PercentEqual
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // GreaterGreater
+    ParseRule {
+        prefix: None,
+        infix: Some(Parser::binary),
+        precedence: Precedence::BitShift,
+    },
+    // GreaterGreaterEqual
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // LessLess
+    ParseRule {
+        prefix: None,
+        infix: Some(Parser::binary),
+        precedence: Precedence::BitShift,
+    },
+    // LessLessEqual
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // AmpAmp
+    ParseRule {
+        prefix: None,
+        infix: Some(Parser::and),
+        precedence: Precedence::And,
+    },
+    // BarBar
+    ParseRule {
+        prefix: Some(Parser::lambda),
+        infix: Some(Parser::or),
+        precedence: Precedence::Or,
+    },
+    // Tilde
+    ParseRule {
+        prefix: Some(Parser::unary),
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // Hash
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // Identifier
+    ParseRule {
+        prefix: Some(Parser::variable),
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // Str
+    ParseRule {
+        prefix: Some(Parser::string),
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // Interpolation
+    ParseRule {
+        prefix: Some(Parser::interpolation),
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // Number
+    ParseRule {
+        prefix: Some(Parser::number),
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // CapSelf
+    ParseRule {
+        prefix: Some(Parser::cap_self),
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // Catch
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // Class
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // Else
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // Export
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // False
+    ParseRule {
+        prefix: Some(Parser::literal),
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // Finally
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // For
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // Fn
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // If
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // Import
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // As
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // In
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // Is
+    ParseRule {
+        prefix: None,
+        infix: Some(Parser::binary),
+        precedence: Precedence::Comparison,
+    },
+    // Nil
+    ParseRule {
+        prefix: Some(Parser::literal),
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // Return
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // Self
+    ParseRule {
+        prefix: Some(Parser::self_),
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // Super
+    ParseRule {
+        prefix: Some(Parser::super_),
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // Break
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // Continue
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // Throw
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // True
+    ParseRule {
+        prefix: Some(Parser::literal),
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // Try
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // Var
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // While
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // Yield
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // Error
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    // Eof
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+];
@@ -0,0 +1,187 @@
+/* Copyright 2020-2021 Matt Spraggs
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::hash::HashMap;
+use crate::memory::{Gc, Root};
+use crate::object::ObjFunction;
+
+/// Counts the bytecode instructions executed by each function and on each source line while
+/// the profiler is enabled.
+#[derive(Default)]
+pub(crate) struct Profiler {
+    functions: Vec<FunctionProfile>,
+    function_indices: HashMap<*const ObjFunction, usize>,
+}
+
+struct FunctionProfile {
+    // Rooting the function guarantees that its address can't be reused by another function
+    // while it's being used as a key.
+    function: Root<ObjFunction>,
+    instructions: u64,
+    lines: HashMap<i32, u64>,
+}
+
+impl Profiler {
+    pub(crate) fn new() -> Self {
+        Default::default()
+    }
+
+    pub(crate) fn record(&mut self, function: Gc<ObjFunction>, line: i32) {
+        let key = &*function as *const ObjFunction;
+        let index = match self.function_indices.get(&key) {
+            Some(&index) => index,
+            None => {
+                let index = self.functions.len();
+                self.functions.push(FunctionProfile {
+                    function: Root::from(function),
+                    instructions: 0,
+                    lines: HashMap::default(),
+                });
+                self.function_indices.insert(key, index);
+                index
+            }
+        };
+        let profile = &mut self.functions[index];
+        profile.instructions += 1;
+        *profile.lines.entry(line).or_insert(0) += 1;
+    }
+
+    pub(crate) fn report(&self) -> ProfileReport {
+        let mut functions = Vec::new();
+        let mut lines: HashMap<(String, i32), u64> = HashMap::default();
+
+        for profile in &self.functions {
+            let module_path = profile.function.module_path.as_str();
+            let name = if profile.function.name.is_empty() {
+                "script".to_string()
+            } else {
+                format!("{}()", *profile.function.name)
+            };
+            functions.push(ProfileEntry {
+                location: format!("[module \"{}\"] {}", module_path, name),
+                instructions: profile.instructions,
+            });
+            for (&line, &count) in &profile.lines {
+                *lines.entry((module_path.to_string(), line)).or_insert(0) += count;
+            }
+        }
+
+        let lines = lines
+            .into_iter()
+            .map(|((module_path, line), instructions)| ProfileEntry {
+                location: format!("[module \"{}\", line {}]", module_path, line),
+                instructions,
+            })
+            .collect();
+
+        ProfileReport::new(functions, lines)
+    }
+}
+
+/// The number of instructions executed at a particular location in a script.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProfileEntry {
+    pub location: String,
+    pub instructions: u64,
+}
+
+/// A summary of the data gathered by the profiler, with the functions and lines sorted so that
+/// those that executed the most instructions come first.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProfileReport {
+    pub total_instructions: u64,
+    pub functions: Vec<ProfileEntry>,
+    pub lines: Vec<ProfileEntry>,
+}
+
+impl ProfileReport {
+    fn new(mut functions: Vec<ProfileEntry>, mut lines: Vec<ProfileEntry>) -> Self {
+        let total_instructions = functions.iter().map(|e| e.instructions).sum();
+        sort_entries(&mut functions);
+        sort_entries(&mut lines);
+        ProfileReport {
+            total_instructions,
+            functions,
+            lines,
+        }
+    }
+}
+
+fn sort_entries(entries: &mut [ProfileEntry]) {
+    entries.sort_by(|a, b| {
+        b.instructions
+            .cmp(&a.instructions)
+            .then_with(|| a.location.cmp(&b.location))
+    });
+}
+
+impl fmt::Display for ProfileReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_table(f, "Function", &self.functions, self.total_instructions)?;
+        writeln!(f)?;
+        write_table(f, "Line", &self.lines, self.total_instructions)
+    }
+}
+
+fn write_table(
+    f: &mut fmt::Formatter<'_>,
+    heading: &str,
+    entries: &[ProfileEntry],
+    total: u64,
+) -> fmt::Result {
+    writeln!(f, "{:>12} {:>7}  {}", "Instructions", "%", heading)?;
+    for entry in entries {
+        let percentage = if total == 0 {
+            0.0
+        } else {
+            100.0 * entry.instructions as f64 / total as f64
+        };
+        writeln!(
+            f,
+            "{:>12} {:>6.2}%  {}",
+            entry.instructions, percentage, entry.location
+        )?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_profile_report() {
+    let mut vm = crate::vm::Vm::with_built_ins();
+    vm.enable_profiler();
+    let source = "fn f() {\n    return 1;\n}\nf();\nf();\n";
+    crate::vm::interpret(&mut vm, source.to_string(), None).unwrap();
+
+    let report = vm.profile_report().unwrap();
+    let locations: Vec<&str> = report
+        .functions
+        .iter()
+        .map(|e| e.location.as_str())
+        .collect();
+    assert_eq!(
+        vec!["[module \"main\"] script", "[module \"main\"] f()"],
+        locations
+    );
+    assert_eq!(4, report.functions[1].instructions);
+    assert_eq!(
+        report.total_instructions,
+        report.lines.iter().map(|e| e.instructions).sum::<u64>()
+    );
+}
@@ -0,0 +1,267 @@
+/* Copyright 2020-2021 Matt Spraggs
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Compiled modules that can be shared between threads.
+//!
+//! Every object a virtual machine allocates lives on the heap of the thread that allocated it, so
+//! neither a `Vm` nor the functions it compiles can be sent to another thread. A `Program` holds
+//! the bytecode of a compiled module without reference to any heap, so a host can compile a
+//! script once and then load it into a `Vm` on each thread that runs it:
+//!
+//! ```
+//! use std::thread;
+//! use yarel::program;
+//! use yarel::vm::Vm;
+//!
+//! let mut vm = Vm::with_built_ins();
+//! let program = program::compile(&mut vm, "var answer = 6 * 7;".to_string(), None).unwrap();
+//! let handle = thread::spawn(move || {
+//!     let mut vm = Vm::with_built_ins();
+//!     let function = program.load(&mut vm);
+//!     vm.execute(function, &[]).unwrap();
+//!     vm.global("main", "answer").unwrap().try_as_number()
+//! });
+//! assert_eq!(Some(42.0), handle.join().unwrap());
+//! ```
+//!
+//! Modules imported by a program are still compiled by each virtual machine that imports them.
+
+use alloc::borrow::ToOwned;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::chunk::{Chunk, LineTable, OpCode};
+use crate::compiler;
+use crate::error::Error;
+use crate::memory::Root;
+use crate::object::{ObjFunction, ObjTuple};
+use crate::value::Value;
+use crate::vm::Vm;
+
+#[derive(Clone, Debug)]
+enum Constant {
+    Number(f64),
+    String(String),
+    Tuple(Vec<Constant>),
+    Function(Arc<Function>),
+}
+
+#[derive(Debug)]
+struct Function {
+    name: String,
+    arity: usize,
+    upvalue_count: usize,
+    parameters: Vec<String>,
+    doc: Option<String>,
+    is_stub: bool,
+    code: Vec<u8>,
+    lines: LineTable,
+    constants: Vec<Constant>,
+}
+
+/// A compiled module that can be sent to other threads and loaded into any virtual machine.
+#[derive(Clone, Debug)]
+pub struct Program {
+    module_path: String,
+    /// The names of the module's globals, in the order of the slots the bytecode refers to them
+    /// by.
+    globals: Vec<String>,
+    function: Arc<Function>,
+}
+
+/// Compiles the source of a module into a program. Any errors are reported as they would be by
+/// `compiler::compile`.
+pub fn compile(vm: &mut Vm, source: String, module_path: Option<&str>) -> Result<Program, Error> {
+    let function = compiler::compile(vm, source, module_path)?;
+    let module_path = module_path.unwrap_or("main");
+    let module = vm.module(module_path);
+    let module = module.borrow();
+    let globals = (0..module.num_slots())
+        .map(|slot| module.slot_name(slot).as_str().to_owned())
+        .collect();
+    Ok(Program {
+        module_path: module_path.to_owned(),
+        globals,
+        function: Arc::new(Function::from_obj_function(&function)),
+    })
+}
+
+impl Program {
+    /// Creates the module-level function of the program in a virtual machine, ready to be run
+    /// with `Vm::execute`.
+    pub fn load(&self, vm: &mut Vm) -> Root<ObjFunction> {
+        let module = vm.module(&self.module_path);
+        let slots: Vec<_> = self
+            .globals
+            .iter()
+            .map(|name| {
+                let name = vm.new_gc_obj_string(name);
+                module.borrow_mut().slot(name) as u16
+            })
+            .collect();
+        self.function.load(vm, &self.module_path, &slots)
+    }
+}
+
+impl Constant {
+    fn from_value(value: Value) -> Self {
+        match value {
+            Value::Number(number) => Constant::Number(number),
+            Value::ObjString(string) => Constant::String(string.as_str().to_owned()),
+            Value::ObjTuple(tuple) => Constant::Tuple(
+                tuple
+                    .elements
+                    .iter()
+                    .copied()
+                    .map(Self::from_value)
+                    .collect(),
+            ),
+            Value::ObjFunction(function) => {
+                Constant::Function(Arc::new(Function::from_obj_function(&function)))
+            }
+            _ => panic!("Unexpected constant '{}'.", value),
+        }
+    }
+
+    /// Creates the constant in a virtual machine. Any objects allocated are kept alive by the
+    /// specified roots.
+    fn load(
+        &self,
+        vm: &mut Vm,
+        module_path: &str,
+        slots: &[u16],
+        tuples: &mut Vec<Root<ObjTuple>>,
+        functions: &mut Vec<Root<ObjFunction>>,
+    ) -> Value {
+        match self {
+            Constant::Number(number) => Value::Number(*number),
+            Constant::String(string) => Value::ObjString(vm.new_gc_obj_string(string)),
+            Constant::Tuple(elements) => {
+                let elements = elements
+                    .iter()
+                    .map(|e| e.load(vm, module_path, slots, tuples, functions))
+                    .collect();
+                let tuple = vm.new_root_obj_tuple(elements);
+                let value = Value::ObjTuple(tuple.as_gc());
+                tuples.push(tuple);
+                value
+            }
+            Constant::Function(function) => {
+                let function = function.load(vm, module_path, slots);
+                let value = Value::ObjFunction(function.as_gc());
+                functions.push(function);
+                value
+            }
+        }
+    }
+}
+
+impl Function {
+    fn from_obj_function(function: &ObjFunction) -> Self {
+        let chunk = &*function.chunk;
+        Function {
+            name: function.name.as_str().to_owned(),
+            arity: function.arity,
+            upvalue_count: function.upvalue_count,
+            parameters: function
+                .parameters
+                .iter()
+                .map(|p| p.as_str().to_owned())
+                .collect(),
+            doc: function.doc.map(|d| d.as_str().to_owned()),
+            is_stub: function.is_stub,
+            code: chunk.code.clone(),
+            lines: chunk.lines.clone(),
+            constants: chunk
+                .constants
+                .iter()
+                .copied()
+                .map(Constant::from_value)
+                .collect(),
+        }
+    }
+
+    /// Creates the function in a virtual machine, replacing the global slots in its bytecode with
+    /// the specified slots of the module in that virtual machine.
+    fn load(&self, vm: &mut Vm, module_path: &str, slots: &[u16]) -> Root<ObjFunction> {
+        let mut tuples = Vec::new();
+        let mut functions = Vec::new();
+        let mut chunk = Chunk::new();
+        chunk.code = self.code.clone();
+        chunk.lines = self.lines.clone();
+        chunk.constants = self
+            .constants
+            .iter()
+            .map(|c| c.load(vm, module_path, slots, &mut tuples, &mut functions))
+            .collect();
+        relocate_globals(&mut chunk, slots);
+
+        let chunk = vm.add_chunk(chunk, module_path);
+        let name = vm.new_gc_obj_string(&self.name);
+        let module_path = vm.new_gc_obj_string(module_path);
+        let mut function = ObjFunction::new(
+            name,
+            self.arity,
+            self.upvalue_count,
+            chunk.as_gc(),
+            module_path,
+        );
+        function.parameters = self
+            .parameters
+            .iter()
+            .map(|p| vm.new_gc_obj_string(p))
+            .collect();
+        function.doc = self.doc.as_ref().map(|d| vm.new_gc_obj_string(d));
+        function.is_stub = self.is_stub;
+        Root::new(function)
+    }
+}
+
+fn relocate_globals(chunk: &mut Chunk, slots: &[u16]) {
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        let opcode = OpCode::from(chunk.code[offset]);
+        if let OpCode::GetGlobal | OpCode::SetGlobal | OpCode::DefineGlobal = opcode {
+            let bytes = [chunk.code[offset + 1], chunk.code[offset + 2]];
+            let slot = slots[u16::from_ne_bytes(bytes) as usize];
+            chunk.code[offset + 1..offset + 3].copy_from_slice(&slot.to_ne_bytes());
+        }
+        offset += chunk.instruction_len(offset);
+    }
+}
+
+#[test]
+fn test_send() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Program>();
+}
+
+#[test]
+fn test_load() {
+    use crate::vm;
+
+    let mut vm = Vm::with_built_ins();
+    vm::interpret(&mut vm, "var unused = 1;".to_string(), None).unwrap();
+    let source = "fn pair(a, b) {\n    return (a, b);\n}\nfn adder(n) {\n    return |x| x + n;\n}\nvar result = (pair(b: 2, a: 1), adder(3)(4), \"done\");\n";
+    let program = compile(&mut vm, source.to_string(), None).unwrap();
+
+    let mut vm = Vm::with_built_ins();
+    vm::interpret(&mut vm, "var other = 2;".to_string(), None).unwrap();
+    let function = program.load(&mut vm);
+    vm.execute(function, &[]).unwrap();
+    let result = vm.global("main", "result").unwrap();
+    assert_eq!("((1, 2), 7, \"done\")", format!("{}", result));
+}
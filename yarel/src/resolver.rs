@@ -0,0 +1,647 @@
+/* Copyright 2020-2021 Matt Spraggs
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Resolves the variables in a syntax tree without generating any code, using the same scoping
+//! rules as the code generator, and reports the problems found along the way.
+
+use alloc::borrow::ToOwned;
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use core::mem;
+
+use crate::ast::{
+    self, Argument, Block, ClassDecl, ComprehensionKind, Expr, Identifier, LambdaBody, MethodKind,
+    Stmt,
+};
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::error::Span;
+use crate::hash::HashSet;
+use crate::warning::{Warning, WarningKind};
+
+struct Local {
+    name: String,
+    depth: Option<usize>,
+    is_used: bool,
+    /// The warning to raise if the local goes out of scope without being used.
+    unused_warning: Option<(WarningKind, Identifier)>,
+}
+
+impl Local {
+    fn new(name: &str, depth: Option<usize>) -> Self {
+        Local {
+            name: name.to_owned(),
+            depth,
+            is_used: false,
+            unused_warning: None,
+        }
+    }
+}
+
+struct Function {
+    locals: Vec<Local>,
+    scope_depth: usize,
+}
+
+impl Function {
+    fn new(receiver: &str) -> Self {
+        Function {
+            locals: vec![Local::new(receiver, Some(0))],
+            scope_depth: 0,
+        }
+    }
+}
+
+/// The problems found whilst resolving a module.
+pub(crate) struct Resolution {
+    /// Every error and warning found.
+    pub diagnostics: Vec<Diagnostic>,
+    /// The warnings found, in the form they're reported by the `Vm`.
+    pub warnings: Vec<Warning>,
+}
+
+pub(crate) fn resolve(module: &ast::Module, source: &str, module_path: &str) -> Resolution {
+    let mut resolver = Resolver {
+        functions: vec![Function::new("")],
+        imports: Vec::new(),
+        strict_globals: if module.is_strict() {
+            Some(module.declared_globals())
+        } else {
+            None
+        },
+        source,
+        module_path,
+        warnings: Vec::new(),
+        resolution: Resolution {
+            diagnostics: Vec::new(),
+            warnings: Vec::new(),
+        },
+    };
+    resolver.statements(&module.statements);
+    resolver.end_function();
+
+    for (name, _) in mem::take(&mut resolver.imports)
+        .iter()
+        .filter(|(_, used)| !used)
+    {
+        resolver.warn_at(
+            WarningKind::UnusedImport,
+            name,
+            "Imported module is never used.",
+        );
+    }
+
+    // Warnings are found in whatever order the tree is walked, so sort them by source location.
+    resolver.warnings.sort_by_key(|(line, _)| *line);
+    resolver.resolution.warnings = resolver.warnings.into_iter().map(|(_, w)| w).collect();
+    resolver.resolution
+}
+
+struct Resolver<'a> {
+    functions: Vec<Function>,
+    /// The imports bound to globals and whether each has been used.
+    imports: Vec<(Identifier, bool)>,
+    /// The globals declared by a strict module, which are the only ones it may assign to.
+    strict_globals: Option<HashSet<String>>,
+    source: &'a str,
+    module_path: &'a str,
+    /// The warnings found and the lines they were found on, which are sorted once resolution is
+    /// complete.
+    warnings: Vec<(usize, Warning)>,
+    resolution: Resolution,
+}
+
+impl<'a> Resolver<'a> {
+    fn statements(&mut self, statements: &[Stmt]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+
+        for (statement, next) in statements.iter().zip(statements.iter().skip(1)) {
+            let keyword = match statement {
+                Stmt::Return { .. } => "return",
+                Stmt::Break { .. } => "break",
+                Stmt::Continue { .. } => "continue",
+                Stmt::Throw { .. } => "throw",
+                _ => continue,
+            };
+            let message = format!("Code after '{}' is never executed.", keyword);
+            self.report_warning(WarningKind::UnreachableCode, next.line(), None, &message);
+            return;
+        }
+    }
+
+    fn block(&mut self, block: &Block) {
+        self.begin_scope();
+        self.statements(&block.statements);
+        self.end_scope();
+    }
+
+    fn statement(&mut self, statement: &Stmt) {
+        match statement {
+            Stmt::Class(class) => self.class_declaration(class),
+            Stmt::Fn(decl) => {
+                self.declare(&decl.name, Some(WarningKind::UnusedVariable));
+                self.define();
+                let is_generator = ast::find_attribute(&decl.attributes, "generator").is_some();
+                self.function("", &decl.function.parameters, is_generator, |r| {
+                    r.statements(&decl.function.body.statements)
+                });
+            }
+            Stmt::Var {
+                name, initialiser, ..
+            } => {
+                self.declare(name, Some(WarningKind::UnusedVariable));
+                if let Some(initialiser) = initialiser {
+                    self.expression(initialiser);
+                }
+                self.define();
+            }
+            Stmt::Import { binding, .. } => {
+                self.declare(binding, Some(WarningKind::UnusedImport));
+                if self.current_function().scope_depth == 0 {
+                    self.imports.push((binding.clone(), false));
+                }
+                self.define();
+            }
+            Stmt::Export { names, .. } => {
+                // Exporting an imported module re-exports it, so counts as using it.
+                for name in names {
+                    self.resolve(name);
+                }
+            }
+            Stmt::Expression { expr, .. } => self.expression(expr),
+            Stmt::For {
+                variable,
+                iterable,
+                body,
+                ..
+            } => {
+                self.begin_scope();
+                // The loop variable is declared whilst the iterable is resolved so that referring
+                // to it there is an error, then bound afresh inside the loop.
+                self.declare(variable, None);
+                self.expression(iterable);
+                let function = self.current_function_mut();
+                let scope_depth = function.scope_depth;
+                let loop_var = function.locals.last_mut().unwrap();
+                loop_var.name.clear();
+                loop_var.depth = Some(scope_depth);
+
+                let mut local = Local::new(&variable.name, Some(scope_depth + 1));
+                if !variable.name.starts_with('_') {
+                    local.unused_warning = Some((WarningKind::UnusedVariable, variable.clone()));
+                }
+                self.begin_scope();
+                self.current_function_mut().locals.push(local);
+                self.block(body);
+                self.end_scope();
+                self.end_scope();
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                self.expression(condition);
+                self.block(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+            }
+            Stmt::Return { value, .. } => {
+                if let Some(value) = value {
+                    self.expression(value);
+                }
+            }
+            Stmt::Yield { value, .. } | Stmt::Throw { value, .. } => self.expression(value),
+            Stmt::Try {
+                body,
+                catches,
+                finally,
+                ..
+            } => {
+                self.block(body);
+                for catch in catches {
+                    if let Some(class) = &catch.class {
+                        self.expression(class);
+                    }
+                    self.begin_scope();
+                    self.declare(&catch.variable, Some(WarningKind::UnusedVariable));
+                    self.define();
+                    self.statements(&catch.body.statements);
+                    self.end_scope();
+                }
+                if let Some(finally) = finally {
+                    self.block(finally);
+                }
+            }
+            Stmt::While {
+                condition, body, ..
+            } => {
+                self.expression(condition);
+                self.block(body);
+            }
+            Stmt::Block(block) => self.block(block),
+            Stmt::Break { .. } | Stmt::Continue { .. } | Stmt::Error => {}
+        }
+    }
+
+    fn class_declaration(&mut self, class: &ClassDecl) {
+        self.declare(&class.name, None);
+        self.define();
+
+        let superclass = class.superclass();
+        if let Some(superclass) = superclass {
+            self.resolve(superclass);
+            self.begin_scope();
+            let scope_depth = self.current_function().scope_depth;
+            self.current_function_mut()
+                .locals
+                .push(Local::new("super", Some(scope_depth)));
+        }
+        for mixin in class.mixins() {
+            self.resolve(mixin);
+        }
+        self.resolve(&class.name);
+
+        for field in &class.fields {
+            if let Some(initialiser) = &field.initialiser {
+                // Instance fields are initialised within constructors.
+                if field.is_static() {
+                    self.expression(initialiser);
+                } else {
+                    self.function("self", &[], false, |r| r.expression(initialiser));
+                }
+            }
+        }
+        for method in &class.methods {
+            let receiver = if method.kind() == MethodKind::Static {
+                "Self"
+            } else {
+                "self"
+            };
+            let function = &method.function;
+            self.function(receiver, &function.parameters, method.is_generator(), |r| {
+                r.statements(&function.body.statements)
+            });
+        }
+
+        if let Some(protocol) = class.protocol() {
+            self.resolve(protocol);
+        }
+        if superclass.is_some() {
+            self.end_scope();
+        }
+    }
+
+    /// Resolves a function with the specified parameters, using `body` to resolve its body. The
+    /// body of a generator is resolved in a function of its own, as it is compiled.
+    fn function<F>(
+        &mut self,
+        receiver: &str,
+        parameters: &[Identifier],
+        is_generator: bool,
+        body: F,
+    ) where
+        F: FnOnce(&mut Self),
+    {
+        self.functions.push(Function::new(receiver));
+        self.begin_scope();
+        for parameter in parameters {
+            self.declare(parameter, None);
+            self.define();
+        }
+        if is_generator {
+            self.functions.push(Function::new(""));
+            self.begin_scope();
+            body(self);
+            self.end_function();
+        } else {
+            body(self);
+        }
+        self.end_function();
+    }
+
+    fn expression(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Interpolation { parts, .. } => {
+                for part in parts {
+                    self.expression(&part.expr);
+                }
+            }
+            Expr::Variable(name) => {
+                self.resolve(name);
+            }
+            Expr::Assign { name, value, .. } => {
+                self.expression(value);
+                if !self.resolve(name) {
+                    self.check_global_assignment(name);
+                }
+            }
+            Expr::SelfRef { line } => {
+                self.resolve(&Identifier::new("self", *line));
+            }
+            Expr::SelfClass { line } => {
+                self.resolve(&Identifier::new("Self", *line));
+            }
+            Expr::Super {
+                keyword_line, call, ..
+            } => {
+                if let Some((arguments, _)) = call {
+                    self.arguments(arguments);
+                }
+                self.resolve(&Identifier::new("super", *keyword_line));
+            }
+            Expr::Grouping(expr) => self.expression(expr),
+            Expr::Tuple { elements, .. } => {
+                for element in elements {
+                    self.expression(element);
+                }
+            }
+            Expr::Vec { elements, .. } => self.arguments(elements),
+            Expr::HashMap { entries, .. } => {
+                for (key, value) in entries {
+                    self.expression(key);
+                    self.expression(value);
+                }
+            }
+            Expr::Unary { operand, .. } => self.expression(operand),
+            Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+                self.expression(left);
+                self.expression(right);
+            }
+            Expr::Range {
+                start, end, step, ..
+            } => {
+                self.expression(start);
+                self.expression(end);
+                if let Some(step) = step {
+                    self.expression(step);
+                }
+            }
+            Expr::Call {
+                callee, arguments, ..
+            } => {
+                self.expression(callee);
+                self.arguments(arguments);
+            }
+            Expr::Get { object, .. } => self.expression(object),
+            Expr::Set { object, value, .. } => {
+                self.expression(object);
+                self.expression(value);
+            }
+            Expr::Index { object, index, .. } => {
+                self.expression(object);
+                self.expression(index);
+            }
+            Expr::SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => {
+                self.expression(object);
+                self.expression(index);
+                self.expression(value);
+            }
+            Expr::Lambda { parameters, body } => {
+                self.function("", parameters, false, |r| match body {
+                    LambdaBody::Block(block) => r.statements(&block.statements),
+                    LambdaBody::Expr { expr, .. } => r.expression(expr),
+                })
+            }
+            Expr::Comprehension(comprehension) => {
+                self.expression(&comprehension.iterable);
+                let is_generator = comprehension.kind == ComprehensionKind::Generator;
+                let function = comprehension.function();
+                self.function("", &function.parameters, is_generator, |r| {
+                    r.statements(&function.body.statements)
+                })
+            }
+            Expr::Number { .. } | Expr::Str { .. } | Expr::Literal { .. } | Expr::Error => {}
+        }
+    }
+
+    fn arguments(&mut self, arguments: &[Argument]) {
+        for argument in arguments {
+            match argument {
+                Argument::Positional(value)
+                | Argument::Named(_, value)
+                | Argument::Spread { value, .. } => self.expression(value),
+            }
+        }
+    }
+
+    fn current_function(&self) -> &Function {
+        self.functions.last().unwrap()
+    }
+
+    fn current_function_mut(&mut self) -> &mut Function {
+        self.functions.last_mut().unwrap()
+    }
+
+    fn begin_scope(&mut self) {
+        self.current_function_mut().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        self.current_function_mut().scope_depth -= 1;
+        let scope_depth = self.current_function().scope_depth;
+        self.pop_locals(scope_depth);
+    }
+
+    fn end_function(&mut self) {
+        self.pop_locals(0);
+        self.functions.pop();
+    }
+
+    fn pop_locals(&mut self, scope_depth: usize) {
+        let mut unused = Vec::new();
+        let locals = &mut self.functions.last_mut().unwrap().locals;
+        while let Some(local) = locals.last() {
+            if matches!(local.depth, Some(depth) if depth <= scope_depth) {
+                break;
+            }
+            let local = locals.pop().unwrap();
+            if let (false, Some(warning)) = (local.is_used, local.unused_warning) {
+                unused.push(warning);
+            }
+        }
+
+        for (kind, name) in unused.iter().rev() {
+            let message = if *kind == WarningKind::UnusedImport {
+                "Imported module is never used."
+            } else {
+                "Variable is never used."
+            };
+            self.warn_at(*kind, name, message);
+        }
+    }
+
+    /// Declares a local variable in the current scope, arranging for the specified warning to be
+    /// raised if it's never used. Globals aren't declared, as they can be used by other modules.
+    fn declare(&mut self, name: &Identifier, unused_warning: Option<WarningKind>) {
+        let scope_depth = self.current_function().scope_depth;
+        if scope_depth == 0 {
+            return;
+        }
+
+        let already_declared = self
+            .current_function()
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| !matches!(local.depth, Some(depth) if depth < scope_depth))
+            .any(|local| local.name == name.name);
+        let is_shadowing = self
+            .functions
+            .iter()
+            .any(|function| function.locals.iter().any(|local| local.name == name.name));
+
+        if already_declared {
+            self.error_at(
+                name,
+                "duplicate-variable",
+                "Variable with this name already declared in this scope.",
+            );
+        } else if is_shadowing && !name.name.starts_with('_') {
+            self.warn_at(
+                WarningKind::ShadowedVariable,
+                name,
+                "Variable shadows a variable in an enclosing scope.",
+            );
+        }
+
+        let mut local = Local::new(&name.name, None);
+        if !name.name.starts_with('_') {
+            local.unused_warning = unused_warning.map(|kind| (kind, name.clone()));
+        }
+        self.current_function_mut().locals.push(local);
+    }
+
+    fn define(&mut self) {
+        let function = self.current_function_mut();
+        if function.scope_depth > 0 {
+            function.locals.last_mut().unwrap().depth = Some(function.scope_depth);
+        }
+    }
+
+    /// Marks the variable with the specified name as used, returning whether it's a local or an
+    /// upvalue.
+    fn resolve(&mut self, name: &Identifier) -> bool {
+        let current = self.functions.len() - 1;
+        let position = self.functions[current]
+            .locals
+            .iter()
+            .rposition(|local| local.name == name.name);
+        if let Some(index) = position {
+            let local = &mut self.functions[current].locals[index];
+            if local.depth.is_none() {
+                self.error_at(
+                    name,
+                    "uninitialised-variable",
+                    "Cannot read local variable in its own initialiser.",
+                );
+            } else {
+                local.is_used = true;
+            }
+            return true;
+        }
+
+        for function in self.functions[..current].iter_mut().rev() {
+            let local = function
+                .locals
+                .iter_mut()
+                .rev()
+                .find(|local| local.name == name.name);
+            if let Some(local) = local {
+                if local.depth.is_some() {
+                    local.is_used = true;
+                    return true;
+                }
+            }
+        }
+
+        for (import, used) in self.imports.iter_mut() {
+            if import.name == name.name {
+                *used = true;
+            }
+        }
+        false
+    }
+
+    fn check_global_assignment(&mut self, name: &Identifier) {
+        let is_declared = self
+            .strict_globals
+            .as_ref()
+            .is_none_or(|globals| globals.contains(&name.name));
+        if !is_declared {
+            self.error_at(
+                name,
+                "undeclared-variable",
+                "Cannot assign to undeclared global variable in strict mode.",
+            );
+        }
+    }
+
+    fn span(&self, name: &Identifier) -> Option<Span> {
+        name.offset
+            .map(|offset| Span::from_offset(self.source, offset, name.name.len()))
+    }
+
+    fn error_at(&mut self, name: &Identifier, code: &'static str, message: &str) {
+        let diagnostic = Diagnostic {
+            severity: Severity::Error,
+            code,
+            line: name.line,
+            span: self.span(name),
+            message: message.to_owned(),
+        };
+        self.resolution.diagnostics.push(diagnostic);
+    }
+
+    fn warn_at(&mut self, kind: WarningKind, name: &Identifier, message: &str) {
+        self.report_warning(kind, name.line, Some(name), message);
+    }
+
+    fn report_warning(
+        &mut self,
+        kind: WarningKind,
+        line: usize,
+        name: Option<&Identifier>,
+        message: &str,
+    ) {
+        let location = name.map_or(String::new(), |name| format!(" at '{}'", name.name));
+        let warning_string = format!(
+            "[module \"{}\", line {}] Warning ({}){}: {}",
+            self.module_path,
+            line,
+            kind.name(),
+            location,
+            message
+        );
+        self.warnings
+            .push((line, Warning::new(kind, &warning_string)));
+        self.resolution.diagnostics.push(Diagnostic {
+            severity: Severity::Warning,
+            code: kind.name(),
+            line,
+            span: name.and_then(|name| self.span(name)),
+            message: message.to_owned(),
+        });
+    }
+}
@@ -13,6 +13,12 @@
  * limitations under the License.
  */
 
+use alloc::borrow::ToOwned;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::arena::Arena;
 use crate::common;
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -69,6 +75,7 @@ pub enum TokenKind {
     Catch,
     Class,
     Else,
+    Export,
     False,
     Finally,
     For,
@@ -77,6 +84,7 @@ pub enum TokenKind {
     Import,
     As,
     In,
+    Is,
     Nil,
     Return,
     Self_,
@@ -88,6 +96,7 @@ pub enum TokenKind {
     Try,
     Var,
     While,
+    Yield,
     Error,
     Eof,
 }
@@ -99,34 +108,43 @@ impl Default for TokenKind {
 }
 
 #[derive(Default, Clone, PartialEq)]
-pub(crate) struct Token {
+pub(crate) struct Token<'a> {
     pub kind: TokenKind,
     pub line: usize,
-    pub source: String,
+    pub source: &'a str,
+    /// The byte offset of the start of the token in the source.
+    pub offset: usize,
+    /// The length of the token in the source in bytes, which may differ from the length of
+    /// `source` for strings and errors.
+    pub length: usize,
 }
 
-impl Token {
+impl<'a> Token<'a> {
     pub fn new() -> Self {
         Default::default()
     }
 
-    pub fn from_string(source: &str) -> Self {
-        Token {
-            kind: Default::default(),
-            line: Default::default(),
-            source: String::from(source),
-        }
-    }
-
-    pub fn from_string_and_line(source: &str, line: usize) -> Self {
+    pub fn from_string_and_line(source: &'a str, line: usize) -> Self {
         Token {
             kind: Default::default(),
             line,
-            source: String::from(source),
+            source,
+            offset: 0,
+            length: 0,
         }
     }
 }
 
+/// A comment in the source, kept so that tools that rewrite the source can preserve it.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Comment {
+    /// The line the comment starts on.
+    pub line: usize,
+    pub text: String,
+    /// Whether the comment follows other code on the line it starts on.
+    pub trailing: bool,
+}
+
 fn is_alpha(s: &str) -> bool {
     !s.is_empty() && s.chars().all(|c| c.is_ascii_alphabetic() || c == '_')
 }
@@ -135,27 +153,50 @@ fn is_digit(s: &str) -> bool {
     !s.is_empty() && s.chars().all(|c| c.is_ascii_digit())
 }
 
-pub(crate) struct Scanner {
-    source: String,
+fn is_radix_digit(s: &str, radix: u32) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_digit(radix))
+}
+
+/// Scans tokens that borrow from the source, with any text that isn't a slice of the source, such
+/// as string literals with escape sequences, allocated in an arena.
+pub(crate) struct Scanner<'a> {
+    source: &'a str,
+    arena: &'a Arena,
     start: usize,
     current: usize,
     line: usize,
     parantheses: Vec<usize>,
+    doc_lines: Vec<String>,
+    comments: Vec<Comment>,
 }
 
-impl Scanner {
-    pub fn from_source(source: String) -> Self {
-        Scanner {
+impl<'a> Scanner<'a> {
+    pub fn from_source(source: &'a str, arena: &'a Arena) -> Self {
+        let mut scanner = Scanner {
             source,
+            arena,
             start: 0,
             current: 0,
             line: 1,
             parantheses: Vec::new(),
+            doc_lines: Vec::new(),
+            comments: Vec::new(),
+        };
+        // A leading shebang line lets scripts be run as executables, so is skipped like a comment.
+        // Module attributes such as `#![strict]` share its prefix, so are left for the parser.
+        if scanner.source.starts_with("#!") && !scanner.source.starts_with("#![") {
+            scanner.current = scanner.source.find('\n').unwrap_or(scanner.source.len());
+            scanner.add_comment(0, 1);
         }
+        scanner
     }
 
-    pub fn scan_token(&mut self) -> Token {
-        self.skip_whitespace();
+    pub fn scan_token(&mut self) -> Token<'a> {
+        self.doc_lines.clear();
+        if !self.skip_whitespace() {
+            self.start = self.current;
+            return self.error_token("Unterminated block comment.");
+        }
 
         self.start = self.current;
 
@@ -262,12 +303,44 @@ impl Scanner {
             "\"" => self.string(),
             c => {
                 let msg = format!("Unexpected character: '{}'.", c);
-                self.error_token(msg.as_str())
+                self.error_token(self.arena.alloc_str(&msg))
             }
         }
     }
 
-    fn binary_token(&mut self, bare_kind: TokenKind, assign_kind: TokenKind) -> Token {
+    pub fn peek_token(&mut self) -> Token<'a> {
+        let (start, current, line) = (self.start, self.current, self.line);
+        let parantheses = self.parantheses.clone();
+        let doc_lines = self.doc_lines.clone();
+        let num_comments = self.comments.len();
+        let token = self.scan_token();
+        self.start = start;
+        self.current = current;
+        self.line = line;
+        self.parantheses = parantheses;
+        self.doc_lines = doc_lines;
+        self.comments.truncate(num_comments);
+        token
+    }
+
+    pub fn source(&self) -> &'a str {
+        self.source
+    }
+
+    pub fn take_doc_comment(&mut self) -> Option<&'a str> {
+        if self.doc_lines.is_empty() {
+            return None;
+        }
+        let lines = core::mem::take(&mut self.doc_lines);
+        Some(self.arena.alloc_str(&lines.join("\n")))
+    }
+
+    /// Takes the comments skipped over so far.
+    pub fn take_comments(&mut self) -> Vec<Comment> {
+        core::mem::take(&mut self.comments)
+    }
+
+    fn binary_token(&mut self, bare_kind: TokenKind, assign_kind: TokenKind) -> Token<'a> {
         let match_char = self.match_char("=");
         self.make_token(if match_char { assign_kind } else { bare_kind })
     }
@@ -308,26 +381,28 @@ impl Scanner {
         true
     }
 
-    fn make_token(&self, kind: TokenKind) -> Token {
-        Token {
-            kind,
-            line: self.line,
-            source: String::from(&self.source[self.start..self.current]),
-        }
+    fn make_token(&self, kind: TokenKind) -> Token<'a> {
+        self.token_with_source(kind, &self.source()[self.start..self.current])
+    }
+
+    fn error_token(&self, message: &'a str) -> Token<'a> {
+        self.token_with_source(TokenKind::Error, message)
     }
 
-    fn error_token(&self, message: &str) -> Token {
+    fn token_with_source(&self, kind: TokenKind, source: &'a str) -> Token<'a> {
         Token {
-            kind: TokenKind::Error,
+            kind,
             line: self.line,
-            source: String::from(message),
+            source,
+            offset: self.start,
+            length: self.current - self.start,
         }
     }
 
-    fn skip_whitespace(&mut self) {
+    fn skip_whitespace(&mut self) -> bool {
         loop {
             if self.is_at_end() {
-                return;
+                return true;
             }
             let c = self.peek();
             match c {
@@ -346,20 +421,75 @@ impl Scanner {
                 }
                 "/" => {
                     if self.peek_next() == "/" {
+                        let comment_start = self.current;
                         while !self.is_at_end() && self.peek() != "\n" {
                             self.advance();
                         }
+                        self.doc_comment_line(comment_start);
+                        self.add_comment(comment_start, self.line);
+                    } else if self.peek_next() == "*" {
+                        let (comment_start, line) = (self.current, self.line);
+                        if !self.block_comment() {
+                            return false;
+                        }
+                        self.add_comment(comment_start, line);
                     } else {
-                        return;
+                        return true;
                     }
                 }
                 _ => {
-                    return;
+                    return true;
                 }
             };
         }
     }
 
+    fn doc_comment_line(&mut self, comment_start: usize) {
+        let comment = &self.source[comment_start..self.current];
+        if let Some(line) = comment.strip_prefix("///") {
+            if !line.starts_with('/') {
+                let line = line.strip_prefix(' ').unwrap_or(line).trim_end();
+                self.doc_lines.push(line.to_owned());
+            }
+        }
+    }
+
+    fn add_comment(&mut self, comment_start: usize, line: usize) {
+        let line_start = self.source[..comment_start]
+            .rfind('\n')
+            .map_or(0, |pos| pos + 1);
+        self.comments.push(Comment {
+            line,
+            text: self.source[comment_start..self.current]
+                .trim_end()
+                .to_owned(),
+            trailing: !self.source[line_start..comment_start].trim().is_empty(),
+        });
+    }
+
+    fn block_comment(&mut self) -> bool {
+        self.advance();
+        self.advance();
+        let mut depth = 1;
+
+        while depth > 0 {
+            if self.is_at_end() {
+                return false;
+            }
+            if self.peek() == "/" && self.peek_next() == "*" {
+                self.advance();
+                depth += 1;
+            } else if self.peek() == "*" && self.peek_next() == "/" {
+                self.advance();
+                depth -= 1;
+            } else if self.peek() == "\n" {
+                self.line += 1;
+            }
+            self.advance();
+        }
+        true
+    }
+
     fn check_keyword(&self, start: usize, rest: &str, kind: TokenKind) -> TokenKind {
         let slice_begin = self.start + start;
         let slice_end = slice_begin + rest.len();
@@ -389,7 +519,17 @@ impl Scanner {
                 }
                 TokenKind::Identifier
             }
-            "e" => self.check_keyword(1, "lse", TokenKind::Else),
+            "e" => {
+                if self.current - self.start > 1 {
+                    let next = &self.source[self.start + 1..self.start + 2];
+                    return match next {
+                        "l" => self.check_keyword(2, "se", TokenKind::Else),
+                        "x" => self.check_keyword(2, "port", TokenKind::Export),
+                        _ => TokenKind::Identifier,
+                    };
+                }
+                TokenKind::Identifier
+            }
             "f" => {
                 if self.current - self.start > 1 {
                     let next = &self.source[self.start + 1..self.start + 2];
@@ -409,6 +549,7 @@ impl Scanner {
                     return match next {
                         "f" => self.check_keyword(2, "", TokenKind::If),
                         "n" => self.check_keyword(2, "", TokenKind::In),
+                        "s" => self.check_keyword(2, "", TokenKind::Is),
                         "m" => self.check_keyword(2, "port", TokenKind::Import),
                         _ => TokenKind::Identifier,
                     };
@@ -453,33 +594,65 @@ impl Scanner {
             }
             "v" => self.check_keyword(1, "ar", TokenKind::Var),
             "w" => self.check_keyword(1, "hile", TokenKind::While),
+            "y" => self.check_keyword(1, "ield", TokenKind::Yield),
             _ => TokenKind::Identifier,
         }
     }
 
-    fn identifier(&mut self) -> Token {
+    fn identifier(&mut self) -> Token<'a> {
         while is_alpha(self.peek()) || is_digit(self.peek()) {
             self.advance();
         }
         self.make_token(self.identifier_type())
     }
 
-    fn number(&mut self) -> Token {
-        while is_digit(self.peek()) {
-            self.advance();
+    fn number(&mut self) -> Token<'a> {
+        if &self.source[self.start..self.current] == "0" {
+            let radix = match self.peek() {
+                "x" | "X" => 16,
+                "o" | "O" => 8,
+                "b" | "B" => 2,
+                _ => 10,
+            };
+            if radix != 10 && is_radix_digit(self.peek_next(), radix) {
+                self.advance();
+                self.digits(radix);
+                return self.make_token(TokenKind::Number);
+            }
         }
 
+        self.digits(10);
+
         if self.peek() == "." && is_digit(self.peek_next()) {
             self.advance();
+            self.digits(10);
+        }
 
-            while is_digit(self.peek()) {
-                self.advance();
+        if matches!(self.peek(), "e" | "E") {
+            let signed = matches!(self.peek_next(), "+" | "-");
+            let exponent_start = self.current + if signed { 2 } else { 1 };
+            if self.source[exponent_start..].starts_with(|c: char| c.is_ascii_digit()) {
+                self.current = exponent_start;
+                self.digits(10);
             }
         }
 
         self.make_token(TokenKind::Number)
     }
 
+    /// Consumes digits in the specified radix, along with underscores between them.
+    fn digits(&mut self, radix: u32) {
+        loop {
+            if is_radix_digit(self.peek(), radix)
+                || (self.peek() == "_" && is_radix_digit(self.peek_next(), radix))
+            {
+                self.advance();
+            } else {
+                return;
+            }
+        }
+    }
+
     fn read_escaped_bytes(&mut self, num_bytes: usize) -> Result<String, ()> {
         let mut bytes = Vec::with_capacity(num_bytes);
         for _ in 0..num_bytes {
@@ -514,7 +687,7 @@ impl Scanner {
         }
     }
 
-    fn string(&mut self) -> Token {
+    fn string(&mut self) -> Token<'a> {
         let mut error = None;
         let mut buffer = String::new();
 
@@ -531,11 +704,8 @@ impl Scanner {
                         return self.error_token("Max interpolation depth exceeded.");
                     }
                     self.parantheses.push(1);
-                    return Token {
-                        line: self.line,
-                        source: buffer,
-                        kind: TokenKind::Interpolation,
-                    };
+                    let source = self.arena.alloc_str(&buffer);
+                    return self.token_with_source(TokenKind::Interpolation, source);
                 }
                 "\\" => {
                     let s = self.advance();
@@ -599,11 +769,8 @@ impl Scanner {
             return self.error_token(msg);
         }
 
-        Token {
-            line: self.line,
-            source: buffer,
-            kind: TokenKind::Str,
-        }
+        let source = self.arena.alloc_str(&buffer);
+        self.token_with_source(TokenKind::Str, source)
     }
 
     fn get_next_char_boundary(&self, start: usize) -> usize {
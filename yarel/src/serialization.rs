@@ -0,0 +1,731 @@
+/* Copyright 2020-2021 Matt Spraggs
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::cell::RefCell;
+use std::fmt;
+use std::vec;
+
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+use serde::ser::{self, Serialize};
+
+use crate::error::{Error, ErrorKind};
+use crate::memory::{Gc, Root};
+use crate::object::{ObjHashMap, ObjVec};
+use crate::value::Value;
+use crate::vm::Vm;
+
+/// The depth to which script values are converted to Rust values, which prevents cyclic values
+/// from overflowing the stack.
+const MAX_DEPTH: usize = 128;
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error::with_message(ErrorKind::ValueError, &msg.to_string())
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error::with_message(ErrorKind::ValueError, &msg.to_string())
+    }
+}
+
+/// Converts a Rust value to a script value, keeping each object created alive by adding it to
+/// the specified Vec.
+pub(crate) fn to_value<T: Serialize + ?Sized>(
+    vm: &mut Vm,
+    keep_alive: Gc<RefCell<ObjVec>>,
+    value: &T,
+) -> Result<Value, Error> {
+    value.serialize(ValueSerializer { vm, keep_alive })
+}
+
+pub(crate) fn from_value<T: DeserializeOwned>(value: Value) -> Result<T, Error> {
+    T::deserialize(ValueDeserializer { value, depth: 0 })
+}
+
+struct ValueSerializer<'a> {
+    vm: &'a mut Vm,
+    keep_alive: Gc<RefCell<ObjVec>>,
+}
+
+impl<'a> ValueSerializer<'a> {
+    fn keep(&self, value: Value) -> Value {
+        self.keep_alive.borrow_mut().elements.push(value);
+        value
+    }
+
+    fn new_string(&mut self, string: &str) -> Value {
+        let string = self.vm.new_root_obj_string(string);
+        self.keep(Value::ObjString(string.as_gc()))
+    }
+
+    fn new_hash_map(&mut self) -> Root<RefCell<ObjHashMap>> {
+        let hash_map = self.vm.new_root_obj_hash_map();
+        self.keep(Value::ObjHashMap(hash_map.as_gc()));
+        hash_map
+    }
+
+    /// Wraps the value of an enum variant in a HashMap with the variant name as its only key.
+    fn wrap_variant(&mut self, variant: &str, value: Value) -> Result<Value, Error> {
+        let hash_map = self.new_hash_map();
+        let key = self.new_string(variant);
+        hash_map.borrow_mut().insert(key, None, value);
+        Ok(Value::ObjHashMap(hash_map.as_gc()))
+    }
+
+    fn reborrow(&mut self) -> ValueSerializer<'_> {
+        ValueSerializer {
+            vm: &mut *self.vm,
+            keep_alive: self.keep_alive,
+        }
+    }
+}
+
+impl<'a> ser::Serializer for ValueSerializer<'a> {
+    type Ok = Value;
+    type Error = Error;
+    type SerializeSeq = SerializeSequence<'a>;
+    type SerializeTuple = SerializeSequence<'a>;
+    type SerializeTupleStruct = SerializeSequence<'a>;
+    type SerializeTupleVariant = SerializeSequence<'a>;
+    type SerializeMap = SerializeHashMap<'a>;
+    type SerializeStruct = SerializeHashMap<'a>;
+    type SerializeStructVariant = SerializeHashMap<'a>;
+
+    fn serialize_bool(self, v: bool) -> Result<Value, Error> {
+        Ok(Value::Boolean(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Value, Error> {
+        Ok(Value::Number(v as f64))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Value, Error> {
+        Ok(Value::Number(v as f64))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Value, Error> {
+        Ok(Value::Number(v as f64))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Value, Error> {
+        Ok(Value::Number(v as f64))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Value, Error> {
+        Ok(Value::Number(v as f64))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Value, Error> {
+        Ok(Value::Number(v as f64))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Value, Error> {
+        Ok(Value::Number(v as f64))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Value, Error> {
+        Ok(Value::Number(v as f64))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Value, Error> {
+        Ok(Value::Number(v as f64))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Value, Error> {
+        Ok(Value::Number(v))
+    }
+
+    fn serialize_char(mut self, v: char) -> Result<Value, Error> {
+        Ok(self.new_string(v.encode_utf8(&mut [0; 4])))
+    }
+
+    fn serialize_str(mut self, v: &str) -> Result<Value, Error> {
+        Ok(self.new_string(v))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Value, Error> {
+        let vec = self.vm.new_root_obj_vec();
+        vec.borrow_mut().elements = v.iter().map(|&b| Value::Number(b as f64)).collect();
+        Ok(self.keep(Value::ObjVec(vec.as_gc())))
+    }
+
+    fn serialize_none(self) -> Result<Value, Error> {
+        Ok(Value::None)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Value, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Value, Error> {
+        Ok(Value::None)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Value, Error> {
+        Ok(Value::None)
+    }
+
+    fn serialize_unit_variant(
+        mut self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Value, Error> {
+        Ok(self.new_string(variant))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Value, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        mut self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Value, Error> {
+        let value = value.serialize(self.reborrow())?;
+        self.wrap_variant(variant, value)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeSequence<'a>, Error> {
+        Ok(SerializeSequence::new(self, len, false, None))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeSequence<'a>, Error> {
+        Ok(SerializeSequence::new(self, Some(len), true, None))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SerializeSequence<'a>, Error> {
+        Ok(SerializeSequence::new(self, Some(len), true, None))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeSequence<'a>, Error> {
+        Ok(SerializeSequence::new(self, Some(len), true, Some(variant)))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<SerializeHashMap<'a>, Error> {
+        Ok(SerializeHashMap::new(self, None))
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<SerializeHashMap<'a>, Error> {
+        Ok(SerializeHashMap::new(self, None))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<SerializeHashMap<'a>, Error> {
+        Ok(SerializeHashMap::new(self, Some(variant)))
+    }
+}
+
+/// Builds a Vec from a sequence, or a Tuple from a tuple or tuple struct.
+struct SerializeSequence<'a> {
+    ser: ValueSerializer<'a>,
+    elements: Vec<Value>,
+    is_tuple: bool,
+    variant: Option<&'static str>,
+}
+
+impl<'a> SerializeSequence<'a> {
+    fn new(
+        ser: ValueSerializer<'a>,
+        len: Option<usize>,
+        is_tuple: bool,
+        variant: Option<&'static str>,
+    ) -> Self {
+        SerializeSequence {
+            ser,
+            elements: Vec::with_capacity(len.unwrap_or(0)),
+            is_tuple,
+            variant,
+        }
+    }
+
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let value = value.serialize(self.ser.reborrow())?;
+        self.elements.push(value);
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Value, Error> {
+        let value = if self.is_tuple {
+            let tuple = self.ser.vm.new_root_obj_tuple(self.elements);
+            self.ser.keep(Value::ObjTuple(tuple.as_gc()))
+        } else {
+            let vec = self.ser.vm.new_root_obj_vec();
+            vec.borrow_mut().elements = self.elements;
+            self.ser.keep(Value::ObjVec(vec.as_gc()))
+        };
+        match self.variant {
+            Some(variant) => self.ser.wrap_variant(variant, value),
+            None => Ok(value),
+        }
+    }
+}
+
+impl<'a> ser::SerializeSeq for SerializeSequence<'a> {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeTuple for SerializeSequence<'a> {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeTupleStruct for SerializeSequence<'a> {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeTupleVariant for SerializeSequence<'a> {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        self.finish()
+    }
+}
+
+/// Builds a HashMap from a map or a struct.
+struct SerializeHashMap<'a> {
+    ser: ValueSerializer<'a>,
+    hash_map: Root<RefCell<ObjHashMap>>,
+    next_key: Option<Value>,
+    variant: Option<&'static str>,
+}
+
+impl<'a> SerializeHashMap<'a> {
+    fn new(mut ser: ValueSerializer<'a>, variant: Option<&'static str>) -> Self {
+        let hash_map = ser.new_hash_map();
+        SerializeHashMap {
+            ser,
+            hash_map,
+            next_key: None,
+            variant,
+        }
+    }
+
+    fn insert(&mut self, key: Value, value: Value) -> Result<(), Error> {
+        let (key, instance_hash) = self.ser.vm.hash_map_key(self.hash_map.as_gc(), key)?;
+        self.hash_map.borrow_mut().insert(key, instance_hash, value);
+        Ok(())
+    }
+
+    fn insert_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        let key = self.ser.new_string(key);
+        let value = value.serialize(self.ser.reborrow())?;
+        self.insert(key, value)
+    }
+
+    fn finish(mut self) -> Result<Value, Error> {
+        let value = Value::ObjHashMap(self.hash_map.as_gc());
+        match self.variant {
+            Some(variant) => self.ser.wrap_variant(variant, value),
+            None => Ok(value),
+        }
+    }
+}
+
+impl<'a> ser::SerializeMap for SerializeHashMap<'a> {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        self.next_key = Some(key.serialize(self.ser.reborrow())?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let key = self.next_key.take().expect("Expected key.");
+        let value = value.serialize(self.ser.reborrow())?;
+        self.insert(key, value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeStruct for SerializeHashMap<'a> {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.insert_field(key, value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeStructVariant for SerializeHashMap<'a> {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.insert_field(key, value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        self.finish()
+    }
+}
+
+struct ValueDeserializer {
+    value: Value,
+    depth: usize,
+}
+
+impl ValueDeserializer {
+    fn nested(&self, value: Value) -> Result<ValueDeserializer, Error> {
+        if self.depth == MAX_DEPTH {
+            return Err(error!(
+                ErrorKind::ValueError,
+                "Cannot convert values nested more than {} deep.", MAX_DEPTH
+            ));
+        }
+        Ok(ValueDeserializer {
+            value,
+            depth: self.depth + 1,
+        })
+    }
+
+    fn sequence(&self, elements: Vec<Value>) -> SequenceAccess {
+        SequenceAccess {
+            elements: elements.into_iter(),
+            depth: self.depth,
+        }
+    }
+}
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.value {
+            Value::None => visitor.visit_unit(),
+            Value::Boolean(b) => visitor.visit_bool(b),
+            Value::Number(n) => {
+                #[allow(clippy::float_cmp)]
+                let is_integer = n.trunc() == n && n.abs() < i64::MAX as f64;
+                if is_integer && n >= 0.0 {
+                    visitor.visit_u64(n as u64)
+                } else if is_integer {
+                    visitor.visit_i64(n as i64)
+                } else {
+                    visitor.visit_f64(n)
+                }
+            }
+            Value::ObjString(string) => visitor.visit_str(string.as_str()),
+            Value::ObjVec(vec) => {
+                let elements = vec.borrow().elements.clone();
+                visitor.visit_seq(self.sequence(elements))
+            }
+            Value::ObjTuple(tuple) => visitor.visit_seq(self.sequence(tuple.elements.clone())),
+            Value::ObjHashMap(hash_map) => {
+                let entries: Vec<_> = hash_map
+                    .borrow()
+                    .elements
+                    .iter()
+                    .map(|(&k, &v)| (k, v))
+                    .collect();
+                visitor.visit_map(MapAccess::new(entries, self.depth))
+            }
+            Value::ObjInstance(instance) => {
+                let entries: Vec<_> = instance
+                    .borrow()
+                    .fields
+                    .iter()
+                    .map(|(&k, &v)| (Value::ObjString(k), v))
+                    .collect();
+                visitor.visit_map(MapAccess::new(entries, self.depth))
+            }
+            value => Err(error!(
+                ErrorKind::TypeError,
+                "Cannot convert value '{}'.", value
+            )),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.value {
+            Value::None => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let (variant, value) = match self.value {
+            Value::ObjString(string) => (string.as_str().to_owned(), None),
+            Value::ObjHashMap(hash_map) if hash_map.borrow().elements.len() == 1 => {
+                let (key, value) = hash_map
+                    .borrow()
+                    .elements
+                    .iter()
+                    .map(|(&k, &v)| (k, v))
+                    .next()
+                    .expect("Expected entry.");
+                match key {
+                    Value::ObjString(string) => (string.as_str().to_owned(), Some(value)),
+                    _ => {
+                        return Err(error!(
+                            ErrorKind::TypeError,
+                            "Expected an enum variant name but found '{}'.", key
+                        ));
+                    }
+                }
+            }
+            value => {
+                return Err(error!(
+                    ErrorKind::TypeError,
+                    "Expected a string or a HashMap with one entry but found '{}'.", value
+                ));
+            }
+        };
+        let value = value.map(|v| self.nested(v)).transpose()?;
+        visitor.visit_enum(EnumAccess { variant, value })
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf unit
+        unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+struct SequenceAccess {
+    elements: vec::IntoIter<Value>,
+    depth: usize,
+}
+
+impl<'de> de::SeqAccess<'de> for SequenceAccess {
+    type Error = Error;
+
+    fn next_element_seed<T: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        match self.elements.next() {
+            Some(value) => {
+                let parent = ValueDeserializer {
+                    value,
+                    depth: self.depth,
+                };
+                seed.deserialize(parent.nested(value)?).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.elements.len())
+    }
+}
+
+struct MapAccess {
+    entries: vec::IntoIter<(Value, Value)>,
+    next_value: Option<Value>,
+    depth: usize,
+}
+
+impl MapAccess {
+    fn new(entries: Vec<(Value, Value)>, depth: usize) -> Self {
+        MapAccess {
+            entries: entries.into_iter(),
+            next_value: None,
+            depth,
+        }
+    }
+
+    fn nested(&self, value: Value) -> Result<ValueDeserializer, Error> {
+        let parent = ValueDeserializer {
+            value,
+            depth: self.depth,
+        };
+        parent.nested(value)
+    }
+}
+
+impl<'de> de::MapAccess<'de> for MapAccess {
+    type Error = Error;
+
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        match self.entries.next() {
+            Some((key, value)) => {
+                self.next_value = Some(value);
+                seed.deserialize(self.nested(key)?).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        let value = self.next_value.take().expect("Expected value.");
+        seed.deserialize(self.nested(value)?)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.entries.len())
+    }
+}
+
+struct EnumAccess {
+    variant: String,
+    value: Option<ValueDeserializer>,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumAccess {
+    type Error = Error;
+    type Variant = VariantAccess;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, VariantAccess), Error> {
+        let variant = seed.deserialize(self.variant.into_deserializer())?;
+        Ok((variant, VariantAccess { value: self.value }))
+    }
+}
+
+struct VariantAccess {
+    value: Option<ValueDeserializer>,
+}
+
+impl VariantAccess {
+    fn value(self) -> Result<ValueDeserializer, Error> {
+        self.value.ok_or_else(|| {
+            error!(
+                ErrorKind::TypeError,
+                "Expected an enum variant with a value."
+            )
+        })
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for VariantAccess {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        match self.value {
+            None => Ok(()),
+            Some(ValueDeserializer {
+                value: Value::None, ..
+            }) => Ok(()),
+            Some(deserializer) => Err(error!(
+                ErrorKind::TypeError,
+                "Expected a unit enum variant but found '{}'.", deserializer.value
+            )),
+        }
+    }
+
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        seed.deserialize(self.value()?)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_any(self.value()?, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_any(self.value()?, visitor)
+    }
+}
@@ -13,22 +13,31 @@
  * limitations under the License.
  */
 
-use std::fmt::{self, Debug, Display};
-use std::ops::{Index, IndexMut};
-use std::ptr;
-use std::slice;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{self, Debug, Display};
+use core::ops::{Index, IndexMut};
+use core::ptr;
+use core::slice;
 
 use crate::memory::GcManaged;
 
-#[derive(Clone)]
-pub(crate) struct Stack<T: Clone + Copy + Default, const N: usize> {
-    stack: Box<[T; N]>,
+/// A stack of values that grows when space is reserved for more of them. Pushing a value
+/// without first reserving space for it is only checked with the `safe_stack` feature or in
+/// debug builds.
+pub(crate) struct Stack<T: Clone + Copy + Default> {
+    stack: Vec<T>,
     top: *mut T,
 }
 
-impl<T: Clone + Copy + Default, const N: usize> Stack<T, N> {
-    pub(crate) fn new() -> Self {
-        Default::default()
+impl<T: Clone + Copy + Default> Stack<T> {
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        let mut stack = Stack {
+            stack: vec![Default::default(); capacity],
+            top: ptr::null_mut(),
+        };
+        stack.clear();
+        stack
     }
 
     pub(crate) fn peek(&self, depth: usize) -> &T {
@@ -46,7 +55,7 @@ impl<T: Clone + Copy + Default, const N: usize> Stack<T, N> {
     }
 
     pub(crate) fn push(&mut self, data: T) {
-        if cfg!(any(debug_assertions, feature = "safe_stack")) && self.len() == N {
+        if cfg!(any(debug_assertions, feature = "safe_stack")) && self.is_full() {
             panic!("Stack overflow.");
         }
         unsafe {
@@ -56,7 +65,7 @@ impl<T: Clone + Copy + Default, const N: usize> Stack<T, N> {
     }
 
     pub(crate) fn pop(&mut self) -> Option<T> {
-        if cfg!(any(debug_assertions, feature = "safe_stack")) && self.len() == 0 {
+        if cfg!(any(debug_assertions, feature = "safe_stack")) && self.is_empty() {
             return None;
         }
         unsafe {
@@ -71,23 +80,67 @@ impl<T: Clone + Copy + Default, const N: usize> Stack<T, N> {
         } else {
             size
         };
-        self.top = unsafe { self.stack.as_ptr().offset(size as isize) as *mut _ };
+        self.truncate_unchecked(size);
+    }
+
+    fn truncate_unchecked(&mut self, size: usize) {
+        self.top = unsafe { self.stack.as_mut_ptr().add(size) };
     }
 
     pub(crate) fn len(&self) -> usize {
         unsafe { self.top.offset_from(self.stack.as_ptr() as *mut _) as usize }
     }
 
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub(crate) fn is_full(&self) -> bool {
+        self.len() == self.capacity()
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Ensures that the specified number of values can be pushed onto the stack, doubling its
+    /// capacity up to the specified limit if there isn't space. If the stack has to be moved to
+    /// make space, the address it was moved from is returned, since any pointers into the stack
+    /// then need updating. Fails without growing the stack if the values would take it past the
+    /// limit.
+    pub(crate) fn reserve(
+        &mut self,
+        additional: usize,
+        limit: usize,
+    ) -> Result<Option<*const T>, ()> {
+        let len = self.len();
+        if len + additional <= self.capacity() {
+            return Ok(None);
+        }
+        if len + additional > limit {
+            return Err(());
+        }
+        let old_address = self.as_ptr();
+        let capacity = (2 * self.capacity()).min(limit).max(len + additional);
+        self.stack.resize(capacity, Default::default());
+        self.truncate_unchecked(len);
+        if self.as_ptr() == old_address {
+            Ok(None)
+        } else {
+            Ok(Some(old_address))
+        }
+    }
+
     pub(crate) fn as_ptr(&self) -> *const T {
         self.stack.as_ptr()
     }
 
     pub(crate) fn clear(&mut self) {
-        self.top = self.stack.as_ptr() as *mut _;
+        self.truncate_unchecked(0);
     }
 }
 
-impl<T, const N: usize> GcManaged for Stack<T, N>
+impl<T> GcManaged for Stack<T>
 where
     T: Clone + Copy + Default + GcManaged,
 {
@@ -104,21 +157,7 @@ where
     }
 }
 
-impl<T, const N: usize> Default for Stack<T, N>
-where
-    T: Clone + Copy + Default,
-{
-    fn default() -> Self {
-        let mut stack = Stack {
-            stack: Box::new([Default::default(); N]),
-            top: ptr::null_mut(),
-        };
-        stack.clear();
-        stack
-    }
-}
-
-impl<T, const N: usize> Display for Stack<T, N>
+impl<T> Display for Stack<T>
 where
     T: Clone + Copy + Default + Display,
 {
@@ -130,7 +169,7 @@ where
     }
 }
 
-impl<T, const N: usize> Debug for Stack<T, N>
+impl<T> Debug for Stack<T>
 where
     T: Clone + Copy + Default + Debug,
 {
@@ -144,7 +183,7 @@ where
     }
 }
 
-impl<T, Idx, const N: usize> Index<Idx> for Stack<T, N>
+impl<T, Idx> Index<Idx> for Stack<T>
 where
     T: Clone + Copy + Default + GcManaged,
     Idx: slice::SliceIndex<[T]>,
@@ -156,7 +195,7 @@ where
     }
 }
 
-impl<T: Clone + Copy + Default + GcManaged, Idx, const N: usize> IndexMut<Idx> for Stack<T, N>
+impl<T: Clone + Copy + Default + GcManaged, Idx> IndexMut<Idx> for Stack<T>
 where
     Idx: slice::SliceIndex<[T]>,
 {
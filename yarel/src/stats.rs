@@ -0,0 +1,222 @@
+/* Copyright 2021 Matt Spraggs
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::chunk::OpCode;
+use crate::hash::HashMap;
+use crate::memory::{Gc, Root};
+use crate::object::ObjFunction;
+
+/// Counts the instructions executed with each opcode, along with the calls made from each call
+/// site, while gathering statistics is enabled.
+pub(crate) struct OpcodeCounter {
+    opcodes: [u64; OpCode::COUNT as usize],
+    call_sites: Vec<CallSite>,
+    call_site_indices: HashMap<(*const ObjFunction, usize), usize>,
+}
+
+struct CallSite {
+    // Rooting the function guarantees that its address can't be reused by another function
+    // while it's being used as a key.
+    function: Root<ObjFunction>,
+    line: i32,
+    opcode: OpCode,
+    calls: u64,
+}
+
+fn is_call(opcode: OpCode) -> bool {
+    matches!(
+        opcode,
+        OpCode::Call
+            | OpCode::CallSpread
+            | OpCode::CallNamed
+            | OpCode::Invoke
+            | OpCode::InvokeSpread
+            | OpCode::InvokeNamed
+            | OpCode::Construct
+            | OpCode::SuperInvoke
+    )
+}
+
+impl OpcodeCounter {
+    pub(crate) fn new() -> Self {
+        OpcodeCounter {
+            opcodes: [0; OpCode::COUNT as usize],
+            call_sites: Vec::new(),
+            call_site_indices: HashMap::default(),
+        }
+    }
+
+    pub(crate) fn record(
+        &mut self,
+        opcode: OpCode,
+        function: Gc<ObjFunction>,
+        offset: usize,
+        line: i32,
+    ) {
+        self.opcodes[opcode as usize] += 1;
+        if !is_call(opcode) {
+            return;
+        }
+        let key = (&*function as *const ObjFunction, offset);
+        let index = match self.call_site_indices.get(&key) {
+            Some(&index) => index,
+            None => {
+                let index = self.call_sites.len();
+                self.call_sites.push(CallSite {
+                    function: Root::from(function),
+                    line,
+                    opcode,
+                    calls: 0,
+                });
+                self.call_site_indices.insert(key, index);
+                index
+            }
+        };
+        self.call_sites[index].calls += 1;
+    }
+
+    pub(crate) fn stats(&self) -> OpcodeStats {
+        let mut opcodes: Vec<_> = self
+            .opcodes
+            .iter()
+            .enumerate()
+            .filter(|(_, &count)| count > 0)
+            .map(|(opcode, &count)| OpcodeCount {
+                opcode: format!("{:?}", OpCode::from(opcode as u8)),
+                count,
+            })
+            .collect();
+        opcodes.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.opcode.cmp(&b.opcode)));
+
+        let mut call_sites: Vec<_> = self
+            .call_sites
+            .iter()
+            .map(|site| {
+                let name = if site.function.name.is_empty() {
+                    "script".to_string()
+                } else {
+                    format!("{}()", *site.function.name)
+                };
+                CallSiteCount {
+                    location: format!(
+                        "[module \"{}\", line {}] in {}",
+                        site.function.module_path.as_str(),
+                        site.line,
+                        name
+                    ),
+                    opcode: format!("{:?}", site.opcode),
+                    count: site.calls,
+                }
+            })
+            .collect();
+        call_sites.sort_by(|a, b| {
+            b.count
+                .cmp(&a.count)
+                .then_with(|| a.location.cmp(&b.location))
+        });
+
+        OpcodeStats {
+            total_instructions: opcodes.iter().map(|c| c.count).sum(),
+            opcodes,
+            call_sites,
+        }
+    }
+}
+
+/// The number of times instructions with a particular opcode were executed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OpcodeCount {
+    pub opcode: String,
+    pub count: u64,
+}
+
+/// The number of calls made by a particular call instruction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CallSiteCount {
+    pub location: String,
+    /// The opcode of the call instruction.
+    pub opcode: String,
+    pub count: u64,
+}
+
+/// A histogram of the opcodes executed whilst gathering statistics, along with the hottest call
+/// sites, each sorted so that the most frequent come first.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OpcodeStats {
+    pub total_instructions: u64,
+    pub opcodes: Vec<OpcodeCount>,
+    pub call_sites: Vec<CallSiteCount>,
+}
+
+impl fmt::Display for OpcodeStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:>12} {:>7}  Opcode", "Instructions", "%")?;
+        for entry in &self.opcodes {
+            // Any opcode counted means the total is non-zero.
+            let percentage = 100.0 * entry.count as f64 / self.total_instructions as f64;
+            writeln!(
+                f,
+                "{:>12} {:>6.2}%  {}",
+                entry.count, percentage, entry.opcode
+            )?;
+        }
+        writeln!(f)?;
+        writeln!(f, "{:>12}  Call site", "Calls")?;
+        for entry in &self.call_sites {
+            writeln!(
+                f,
+                "{:>12}  {} ({})",
+                entry.count, entry.location, entry.opcode
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_opcode_stats() {
+    let mut vm = crate::vm::Vm::with_built_ins();
+    vm.enable_opcode_stats();
+    let source = "fn f() {\n    return 1;\n}\nfor i in 0..3 {\n    f();\n}\n";
+    crate::vm::interpret(&mut vm, source.to_string(), None).unwrap();
+
+    let stats = vm.opcode_stats().unwrap();
+    let count = |name: &str| {
+        stats
+            .opcodes
+            .iter()
+            .find(|c| c.opcode == name)
+            .map_or(0, |c| c.count)
+    };
+    assert_eq!(3, count("Call"));
+    assert!(count("Return") >= 4);
+    assert_eq!(
+        stats.total_instructions,
+        stats.opcodes.iter().map(|c| c.count).sum::<u64>()
+    );
+    assert_eq!(
+        CallSiteCount {
+            location: "[module \"main\", line 5] in script".to_string(),
+            opcode: "Call".to_string(),
+            count: 3,
+        },
+        stats.call_sites[0]
+    );
+}
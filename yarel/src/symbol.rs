@@ -0,0 +1,95 @@
+/* Copyright 2020-2021 Matt Spraggs
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::ast::{self, Identifier, MethodKind, Stmt};
+use crate::error::Span;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SymbolKind {
+    Class,
+    Function,
+    Variable,
+    Import,
+    Method,
+    StaticMethod,
+    Field,
+    StaticField,
+}
+
+/// A name declared in a module, along with where it's declared.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub line: usize,
+    pub span: Option<Span>,
+    pub doc: Option<String>,
+    /// The methods and fields of a class, or its constructor if it has one.
+    pub members: Vec<Symbol>,
+}
+
+/// Collects the symbols declared at the top level of a module.
+pub(crate) fn module_symbols(module: &ast::Module, source: &str) -> Vec<Symbol> {
+    let symbol = |name: &Identifier, kind, doc: &Option<String>, members| Symbol {
+        name: name.name.clone(),
+        kind,
+        line: name.line,
+        span: name
+            .offset
+            .map(|offset| Span::from_offset(source, offset, name.name.len())),
+        doc: doc.clone(),
+        members,
+    };
+
+    module
+        .statements
+        .iter()
+        .filter_map(|statement| match statement {
+            Stmt::Class(class) => {
+                let mut members = Vec::new();
+                if let Some(constructor) = class.constructor() {
+                    members.push(symbol(constructor, SymbolKind::StaticMethod, &None, vec![]));
+                }
+                members.extend(class.fields.iter().map(|field| {
+                    let kind = if field.is_static() {
+                        SymbolKind::StaticField
+                    } else {
+                        SymbolKind::Field
+                    };
+                    symbol(&field.name, kind, &field.doc, vec![])
+                }));
+                members.extend(class.methods.iter().map(|method| {
+                    let kind = if method.kind() == MethodKind::Method {
+                        SymbolKind::Method
+                    } else {
+                        SymbolKind::StaticMethod
+                    };
+                    symbol(&method.name, kind, &method.doc, vec![])
+                }));
+                Some(symbol(&class.name, SymbolKind::Class, &class.doc, members))
+            }
+            Stmt::Fn(decl) => Some(symbol(&decl.name, SymbolKind::Function, &decl.doc, vec![])),
+            Stmt::Var { name, .. } => Some(symbol(name, SymbolKind::Variable, &None, vec![])),
+            Stmt::Import { binding, .. } => {
+                Some(symbol(binding, SymbolKind::Import, &None, vec![]))
+            }
+            _ => None,
+        })
+        .collect()
+}
@@ -0,0 +1,253 @@
+/* Copyright 2020-2021 Matt Spraggs
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::io::{self, Write};
+
+use crate::chunk::OpCode;
+
+/// The state of the virtual machine just before it executes an instruction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceEvent {
+    pub module_path: String,
+    /// The name of the function being executed, or "script" for module-level code.
+    pub function: String,
+    /// The number of call frames on the active fiber, including the current one.
+    pub frame_depth: usize,
+    pub line: i32,
+    /// The offset of the instruction in the function's bytecode.
+    pub offset: usize,
+    pub opcode: OpCode,
+    /// The disassembled instruction, including its operands.
+    pub instruction: String,
+    /// The values on the active fiber's stack, from the bottom up.
+    pub stack: Vec<String>,
+}
+
+impl fmt::Display for TraceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "          ")?;
+        for value in &self.stack {
+            write!(f, "[ {} ]", value)?;
+        }
+        writeln!(f)?;
+        write!(
+            f,
+            "[module \"{}\"] {} {:04} {:4} {}",
+            self.module_path, self.function, self.offset, self.line, self.instruction
+        )
+    }
+}
+
+/// What the virtual machine should do after reporting a trace event to a callback.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceStep {
+    Continue,
+    /// Stops execution with a runtime error before the traced instruction is executed.
+    Stop,
+}
+
+/// The error returned by a diagnostics sink that fails to write a trace event, which is an I/O
+/// error with the `std` feature.
+#[cfg(feature = "std")]
+pub type TraceError = io::Error;
+
+#[cfg(not(feature = "std"))]
+pub type TraceError = fmt::Error;
+
+/// Receives the debugging output of a virtual machine, so that embedders and tools can collect it
+/// rather than have it printed.
+pub trait DiagnosticsSink {
+    /// Receives the disassembled bytecode of a function that has just been compiled, with one
+    /// instruction per line.
+    fn disassembly(&mut self, function: &str, listing: &str);
+
+    /// Receives the state of the virtual machine before it executes an instruction, unless a
+    /// separate trace writer or callback has been set.
+    fn trace(&mut self, event: &TraceEvent) -> Result<TraceStep, TraceError>;
+}
+
+/// Discards debugging output, which is the default without the `std` feature.
+pub struct NullSink;
+
+impl DiagnosticsSink for NullSink {
+    fn disassembly(&mut self, _function: &str, _listing: &str) {}
+
+    fn trace(&mut self, _event: &TraceEvent) -> Result<TraceStep, TraceError> {
+        Ok(TraceStep::Continue)
+    }
+}
+
+/// Writes debugging output as text, which goes to stdout by default, or is discarded if the
+/// `stdio` feature is disabled.
+#[cfg(feature = "std")]
+pub struct WriterSink {
+    writer: Box<dyn Write>,
+}
+
+#[cfg(feature = "std")]
+impl WriterSink {
+    pub fn new(writer: impl Write + 'static) -> Self {
+        WriterSink {
+            writer: Box::new(writer),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for WriterSink {
+    #[cfg(feature = "stdio")]
+    fn default() -> Self {
+        WriterSink::new(io::stdout())
+    }
+
+    #[cfg(not(feature = "stdio"))]
+    fn default() -> Self {
+        WriterSink::new(io::sink())
+    }
+}
+
+#[cfg(feature = "std")]
+impl DiagnosticsSink for WriterSink {
+    fn disassembly(&mut self, function: &str, listing: &str) {
+        // Compilation can't fail because of debugging output, so errors are ignored.
+        let _ = write!(self.writer, "=== {} ===\n{}", function, listing);
+    }
+
+    fn trace(&mut self, event: &TraceEvent) -> Result<TraceStep, TraceError> {
+        writeln!(self.writer, "{}", event)?;
+        Ok(TraceStep::Continue)
+    }
+}
+
+pub(crate) enum TraceSink {
+    #[cfg(feature = "std")]
+    Writer(Box<dyn Write>),
+    Callback(Box<dyn FnMut(&TraceEvent) -> TraceStep>),
+}
+
+impl TraceSink {
+    pub(crate) fn emit(&mut self, event: &TraceEvent) -> Result<TraceStep, TraceError> {
+        match self {
+            #[cfg(feature = "std")]
+            TraceSink::Writer(writer) => {
+                writeln!(writer, "{}", event)?;
+                Ok(TraceStep::Continue)
+            }
+            TraceSink::Callback(callback) => Ok(callback(event)),
+        }
+    }
+}
+
+#[test]
+fn test_trace_callback() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let mut vm = crate::vm::Vm::with_built_ins();
+    let recorded = events.clone();
+    vm.set_trace_callback(move |event| {
+        recorded.borrow_mut().push(event.clone());
+        TraceStep::Continue
+    });
+    vm.set_trace(true);
+    let source = "fn f() {\n    return 1;\n}\nf();\n";
+    crate::vm::interpret(&mut vm, source.to_string(), None).unwrap();
+
+    let events = events.borrow();
+    let f_events: Vec<_> = events.iter().filter(|e| e.function == "f()").collect();
+    assert_eq!(2, f_events.len());
+    assert_eq!(OpCode::Constant, f_events[0].opcode);
+    assert_eq!(2, f_events[0].line);
+    assert_eq!(2, f_events[0].frame_depth);
+    assert!(f_events[0].instruction.starts_with("CONSTANT"));
+    assert_eq!(OpCode::Return, f_events[1].opcode);
+    assert_eq!("1", f_events[1].stack.last().unwrap());
+    assert!(events.iter().all(|e| e.module_path == "main"));
+}
+
+#[test]
+fn test_trace_stop() {
+    let mut vm = crate::vm::Vm::with_built_ins();
+    vm.set_trace_callback(|event| {
+        if event.opcode == OpCode::Return && event.function == "f()" {
+            TraceStep::Stop
+        } else {
+            TraceStep::Continue
+        }
+    });
+    vm.set_trace(true);
+    let source = "fn f() {\n    return 1;\n}\nf();\n";
+    let error = crate::vm::interpret(&mut vm, source.to_string(), None).unwrap_err();
+    assert_eq!(crate::error::ErrorKind::RuntimeError, error.kind());
+    assert_eq!("Execution stopped by tracer.", error.messages()[0]);
+}
+
+#[test]
+#[cfg_attr(feature = "debug_trace", ignore)]
+fn test_trace_disabled_by_default() {
+    let mut vm = crate::vm::Vm::with_built_ins();
+    vm.set_trace_callback(|_| panic!("Unexpected trace event."));
+    crate::vm::interpret(&mut vm, "var x = 1;".to_string(), None).unwrap();
+}
+
+#[test]
+fn test_diagnostics_sink() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[derive(Default)]
+    struct Recorder {
+        listings: Vec<(String, String)>,
+        num_events: usize,
+    }
+
+    struct RecordingSink(Rc<RefCell<Recorder>>);
+
+    impl DiagnosticsSink for RecordingSink {
+        fn disassembly(&mut self, function: &str, listing: &str) {
+            let listing = (function.to_owned(), listing.to_owned());
+            self.0.borrow_mut().listings.push(listing);
+        }
+
+        fn trace(&mut self, _event: &TraceEvent) -> io::Result<TraceStep> {
+            self.0.borrow_mut().num_events += 1;
+            Ok(TraceStep::Continue)
+        }
+    }
+
+    let recorder = Rc::new(RefCell::new(Recorder::default()));
+    let mut vm = crate::vm::Vm::with_built_ins();
+    vm.set_diagnostics_sink(RecordingSink(recorder.clone()));
+    vm.set_disassemble(true);
+    vm.set_trace(true);
+    let source = "fn f() {\n    return 1;\n}\n";
+    crate::vm::interpret(&mut vm, source.to_string(), None).unwrap();
+
+    let recorder = recorder.borrow();
+    assert_eq!(2, recorder.listings.len());
+    let (name, listing) = &recorder.listings[0];
+    assert_eq!("f()", name);
+    let expected =
+        "0000    2 CONSTANT            0 '1'\n0003    | RETURN\n0004    3 NIL\n0005    | RETURN\n";
+    assert_eq!(expected, listing);
+    assert_eq!("script", recorder.listings[1].0);
+    assert!(recorder.num_events > 0);
+}
@@ -13,9 +13,48 @@
  * limitations under the License.
  */
 
-use crate::error::{Error, ErrorKind};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::error::{Error, ErrorCode, ErrorKind};
 use crate::value::Value;
 
+/// The rounding functions `f64` only provides with `std`, which are otherwise implemented by
+/// libm.
+#[cfg(not(feature = "std"))]
+pub(crate) trait FloatExt {
+    fn floor(self) -> Self;
+    fn ceil(self) -> Self;
+    fn round(self) -> Self;
+    fn trunc(self) -> Self;
+    fn fract(self) -> Self;
+}
+
+#[cfg(not(feature = "std"))]
+impl FloatExt for f64 {
+    fn floor(self) -> Self {
+        libm::floor(self)
+    }
+
+    fn ceil(self) -> Self {
+        libm::ceil(self)
+    }
+
+    fn round(self) -> Self {
+        libm::round(self)
+    }
+
+    fn trunc(self) -> Self {
+        libm::trunc(self)
+    }
+
+    fn fract(self) -> Self {
+        self - libm::trunc(self)
+    }
+}
+
 pub(crate) fn validate_integer(value: Value) -> Result<isize, Error> {
     if let Value::Number(n) = value {
         #[allow(clippy::float_cmp)]
@@ -34,6 +73,93 @@ pub(crate) fn validate_integer(value: Value) -> Result<isize, Error> {
     }
 }
 
+/// Converts an index into a sequence of the specified length to an offset from the start of the
+/// sequence, counting negative indices back from its end. The result may still be out of bounds.
+pub(crate) fn normalise_index(index: isize, len: isize) -> isize {
+    if index < 0 {
+        index + len
+    } else {
+        index
+    }
+}
+
+/// Validates an index into a sequence of the specified length, which may be negative to count
+/// back from the end of the sequence.
+pub(crate) fn bounded_index(index: isize, len: isize, kind: &str) -> Result<usize, Error> {
+    let index = normalise_index(index, len);
+    if index < 0 || index >= len {
+        return Err(
+            error!(ErrorKind::IndexError, "{} index out of bounds.", kind)
+                .with_code(ErrorCode::IndexOutOfBounds),
+        );
+    }
+    Ok(index as usize)
+}
+
+/// The positions in a sequence selected by slicing it with a range.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Slice {
+    begin: usize,
+    count: usize,
+    step: isize,
+}
+
+impl Slice {
+    /// Validates the bounds of a slice of a sequence of the specified length, which may be
+    /// negative to count back from the end of the sequence. The slice selects the positions that
+    /// a range with the converted bounds and the specified step would produce.
+    pub(crate) fn new(
+        begin: isize,
+        end: isize,
+        step: isize,
+        len: isize,
+        kind: &str,
+    ) -> Result<Self, Error> {
+        let begin = normalise_index(begin, len);
+        if begin < 0 || begin >= len {
+            return Err(error!(
+                ErrorKind::IndexError,
+                "{} slice start out of range.", kind
+            ));
+        }
+        let end = normalise_index(end, len);
+        if end < 0 || end > len {
+            return Err(error!(
+                ErrorKind::IndexError,
+                "{} slice end out of range.", kind
+            ));
+        }
+        if step == 0 {
+            return Err(error!(ErrorKind::ValueError, "Slice step cannot be zero."));
+        }
+        let distance = if step > 0 { end - begin } else { begin - end };
+        let count = if distance > 0 {
+            (distance + step.abs() - 1) / step.abs()
+        } else {
+            0
+        };
+        Ok(Slice {
+            begin: begin as usize,
+            count: count as usize,
+            step,
+        })
+    }
+
+    /// The contiguous positions selected by the slice, if its step is one.
+    pub(crate) fn as_range(&self) -> Option<Range<usize>> {
+        if self.step == 1 {
+            Some(self.begin..self.begin + self.count)
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn indices(&self) -> impl Iterator<Item = usize> {
+        let Slice { begin, count, step } = *self;
+        (0..count).map(move |i| (begin as isize + i as isize * step) as usize)
+    }
+}
+
 pub(crate) fn hash_number(num: f64) -> u64 {
     let mut hash = u64::from_ne_bytes(num.to_ne_bytes()) as u128;
     hash = (!hash).wrapping_add(hash.wrapping_shl(18));
@@ -44,3 +170,179 @@ pub(crate) fn hash_number(num: f64) -> u64 {
     hash = hash ^ hash.wrapping_shr(22);
     hash as u64
 }
+
+/// Truncates a number to a 64-bit integer, returning `None` if it's not finite or lies outside
+/// the range of `i64`.
+pub(crate) fn to_i64(num: f64) -> Option<i64> {
+    let num = num.trunc();
+    if num >= i64::MIN as f64 && num < -(i64::MIN as f64) {
+        Some(num as i64)
+    } else {
+        None
+    }
+}
+
+/// Parses a number written as it would be in source code, with an optional sign. Integers may be
+/// written in hexadecimal, octal or binary with a `0x`, `0o` or `0b` prefix, and digits may be
+/// separated by single underscores. Decimal numbers may have an exponent.
+pub(crate) fn parse_number(text: &str) -> Option<f64> {
+    let (negative, unsigned) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let prefix = unsigned.get(..2).map(|p| p.to_ascii_lowercase());
+    let radix = match prefix.as_deref() {
+        Some("0x") => 16,
+        Some("0o") => 8,
+        Some("0b") => 2,
+        _ => 10,
+    };
+    let digits = if radix == 10 { text } else { &unsigned[2..] };
+
+    let chars: Vec<char> = digits.chars().collect();
+    let is_digit = |c: Option<&char>| matches!(c, Some(c) if c.is_digit(radix));
+    for (i, c) in chars.iter().enumerate() {
+        if *c == '_' && (i == 0 || !is_digit(chars.get(i - 1)) || !is_digit(chars.get(i + 1))) {
+            return None;
+        }
+    }
+    let digits: String = chars.into_iter().filter(|&c| c != '_').collect();
+
+    if radix == 10 {
+        return digits.parse::<f64>().ok();
+    }
+    if digits.is_empty() {
+        return None;
+    }
+    // Accumulate as a float so that literals too wide for any integer type still parse.
+    let value = digits.chars().try_fold(0.0, |value, c| {
+        Some(value * radix as f64 + c.to_digit(radix)? as f64)
+    })?;
+    Some(if negative { -value } else { value })
+}
+
+/// Combines a sequence of hashes into one, such that the same hashes in a different order give a
+/// different result.
+pub(crate) fn hash_sequence(hashes: impl IntoIterator<Item = u64>) -> u64 {
+    hashes.into_iter().fold(0xcbf2_9ce4_8422_2325, |acc, hash| {
+        (acc ^ hash).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Sorts the items with a stable merge sort, using the fallible comparison to determine whether
+/// one item should come before another. Unlike the standard library's sorts, the comparison may be
+/// inconsistent without causing a panic, which matters when it calls back into script code.
+pub(crate) fn merge_sort_by<T: Copy, E>(
+    items: &mut Vec<T>,
+    mut less: impl FnMut(T, T) -> Result<bool, E>,
+) -> Result<(), E> {
+    let len = items.len();
+    let mut buffer = Vec::with_capacity(len);
+    let mut width = 1;
+    while width < len {
+        buffer.clear();
+        for start in (0..len).step_by(2 * width) {
+            let mid = (start + width).min(len);
+            let end = (start + 2 * width).min(len);
+            let (mut left, mut right) = (start, mid);
+            while left < mid && right < end {
+                if less(items[right], items[left])? {
+                    buffer.push(items[right]);
+                    right += 1;
+                } else {
+                    buffer.push(items[left]);
+                    left += 1;
+                }
+            }
+            buffer.extend_from_slice(&items[left..mid]);
+            buffer.extend_from_slice(&items[right..end]);
+        }
+        core::mem::swap(items, &mut buffer);
+        width *= 2;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_to_i64() {
+    assert_eq!(to_i64(12.7), Some(12));
+    assert_eq!(to_i64(-12.7), Some(-12));
+    assert_eq!(to_i64(-9223372036854775808.0), Some(i64::MIN));
+    assert_eq!(to_i64(9223372036854775808.0), None);
+    assert_eq!(to_i64(-9223372036854777856.0), None);
+    assert_eq!(to_i64(f64::NAN), None);
+    assert_eq!(to_i64(f64::INFINITY), None);
+}
+
+#[test]
+fn test_parse_number() {
+    assert_eq!(parse_number("123"), Some(123.0));
+    assert_eq!(parse_number("-1.5e3"), Some(-1500.0));
+    assert_eq!(parse_number("+2.5E-1"), Some(0.25));
+    assert_eq!(parse_number("1_000_000.000_1"), Some(1_000_000.000_1));
+    assert_eq!(parse_number("0xff"), Some(255.0));
+    assert_eq!(parse_number("-0XFF_FF"), Some(-65535.0));
+    assert_eq!(parse_number("0o17"), Some(15.0));
+    assert_eq!(parse_number("0b1010_1010"), Some(170.0));
+    assert_eq!(parse_number("_1"), None);
+    assert_eq!(parse_number("1_"), None);
+    assert_eq!(parse_number("1__0"), None);
+    assert_eq!(parse_number("1_.0"), None);
+    assert_eq!(
+        parse_number("0x1_0000_0000_0000_0000_0000_0000_0000_0000"),
+        Some(2f64.powi(128))
+    );
+    assert_eq!(parse_number("0x_1"), None);
+    assert_eq!(parse_number("0x"), None);
+    assert_eq!(parse_number("0b102"), None);
+    assert_eq!(parse_number("-0x-1"), None);
+    assert_eq!(parse_number("abc"), None);
+}
+
+#[test]
+fn test_hash_sequence() {
+    assert_eq!(hash_sequence([1, 2]), hash_sequence(vec![1, 2]));
+    assert_ne!(hash_sequence([1, 2]), hash_sequence([2, 1]));
+    assert_ne!(hash_sequence([1, 1]), hash_sequence([2, 2]));
+    assert_ne!(hash_sequence([]), hash_sequence([0]));
+}
+
+#[test]
+fn test_merge_sort_by() {
+    let mut items = vec![(3, 'a'), (1, 'b'), (2, 'c'), (1, 'd'), (5, 'e')];
+    let result: Result<(), ()> = merge_sort_by(&mut items, |a, b| Ok(a.0 < b.0));
+    assert!(result.is_ok());
+    assert_eq!(
+        items,
+        vec![(1, 'b'), (1, 'd'), (2, 'c'), (3, 'a'), (5, 'e')]
+    );
+
+    let mut items = vec![2, 1];
+    assert_eq!(Err("error"), merge_sort_by(&mut items, |_, _| Err("error")));
+}
+
+#[test]
+fn test_slice() {
+    let indices = |slice: Slice| slice.indices().collect::<Vec<_>>();
+
+    assert_eq!(vec![1, 2], indices(Slice::new(1, 3, 1, 5, "Vec").unwrap()));
+    assert_eq!(
+        vec![0, 2, 4],
+        indices(Slice::new(0, 5, 2, 5, "Vec").unwrap())
+    );
+    assert_eq!(
+        vec![4, 2],
+        indices(Slice::new(-1, 1, -2, 5, "Vec").unwrap())
+    );
+    assert_eq!(
+        Vec::<usize>::new(),
+        indices(Slice::new(3, 1, 1, 5, "Vec").unwrap())
+    );
+    assert_eq!(
+        Some(1..3),
+        Slice::new(1, 3, 1, 5, "Vec").unwrap().as_range()
+    );
+    assert_eq!(None, Slice::new(1, 3, 2, 5, "Vec").unwrap().as_range());
+    assert!(Slice::new(5, 5, 1, 5, "Vec").is_err());
+    assert!(Slice::new(0, 6, 1, 5, "Vec").is_err());
+}
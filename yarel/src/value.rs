@@ -13,18 +13,18 @@
  * limitations under the License.
  */
 
-use std::cell::RefCell;
-use std::cmp;
-use std::fmt;
-use std::hash::{Hash, Hasher};
+use core::cell::RefCell;
+use core::cmp;
+use core::fmt;
+use core::hash::{Hash, Hasher};
 
-use crate::error::{Error, ErrorKind};
+use crate::error::Error;
 use crate::hash::PassThroughHasher;
 use crate::memory::{self, Gc};
 use crate::object::{
-    ObjBoundMethod, ObjClass, ObjClosure, ObjFiber, ObjFunction, ObjHashMap, ObjInstance,
-    ObjModule, ObjNative, ObjRange, ObjRangeIter, ObjString, ObjStringIter, ObjTuple, ObjTupleIter,
-    ObjVec, ObjVecIter,
+    ObjBoundMethod, ObjClass, ObjClosure, ObjFiber, ObjFunction, ObjHashMap, ObjIdentityMap,
+    ObjInstance, ObjModule, ObjNative, ObjPartial, ObjRange, ObjRangeIter, ObjString,
+    ObjStringIter, ObjTuple, ObjTupleIter, ObjVec, ObjVecIter,
 };
 use crate::utils;
 
@@ -41,6 +41,7 @@ pub enum Value {
     ObjInstance(Gc<RefCell<ObjInstance>>),
     ObjBoundMethod(Gc<RefCell<ObjBoundMethod<ObjClosure>>>),
     ObjBoundNative(Gc<RefCell<ObjBoundMethod<ObjNative>>>),
+    ObjPartial(Gc<ObjPartial>),
     ObjTuple(Gc<ObjTuple>),
     ObjTupleIter(Gc<RefCell<ObjTupleIter>>),
     ObjVec(Gc<RefCell<ObjVec>>),
@@ -48,6 +49,7 @@ pub enum Value {
     ObjRange(Gc<ObjRange>),
     ObjRangeIter(Gc<RefCell<ObjRangeIter>>),
     ObjHashMap(Gc<RefCell<ObjHashMap>>),
+    ObjIdentityMap(Gc<RefCell<ObjIdentityMap>>),
     ObjModule(Gc<RefCell<ObjModule>>),
     ObjFiber(Gc<RefCell<ObjFiber>>),
     None,
@@ -69,12 +71,42 @@ impl Value {
             Value::ObjString(_) => true,
             Value::ObjClass(_) => true,
             Value::ObjTuple(t) => t.has_hash(),
+            Value::ObjVec(v) => v.borrow().has_hash(),
+            Value::ObjHashMap(m) => m.borrow().has_hash(),
             Value::ObjRange(_) => true,
             Value::None => true,
             _ => false,
         }
     }
 
+    /// Returns the address of the object this value refers to, or nothing if it isn't an object.
+    pub(crate) fn address(&self) -> Option<usize> {
+        let address = match self {
+            Value::ObjString(inner) => inner.as_ptr() as usize,
+            Value::ObjStringIter(inner) => inner.as_ptr() as usize,
+            Value::ObjFunction(inner) => inner.as_ptr() as usize,
+            Value::ObjNative(inner) => inner.as_ptr() as usize,
+            Value::ObjClosure(inner) => inner.as_ptr() as usize,
+            Value::ObjClass(inner) => inner.as_ptr() as usize,
+            Value::ObjInstance(inner) => inner.as_ptr() as usize,
+            Value::ObjBoundMethod(inner) => inner.as_ptr() as usize,
+            Value::ObjBoundNative(inner) => inner.as_ptr() as usize,
+            Value::ObjPartial(inner) => inner.as_ptr() as usize,
+            Value::ObjTuple(inner) => inner.as_ptr() as usize,
+            Value::ObjTupleIter(inner) => inner.as_ptr() as usize,
+            Value::ObjVec(inner) => inner.as_ptr() as usize,
+            Value::ObjVecIter(inner) => inner.as_ptr() as usize,
+            Value::ObjRange(inner) => inner.as_ptr() as usize,
+            Value::ObjRangeIter(inner) => inner.as_ptr() as usize,
+            Value::ObjHashMap(inner) => inner.as_ptr() as usize,
+            Value::ObjIdentityMap(inner) => inner.as_ptr() as usize,
+            Value::ObjModule(inner) => inner.as_ptr() as usize,
+            Value::ObjFiber(inner) => inner.as_ptr() as usize,
+            Value::Boolean(_) | Value::Number(_) | Value::None => return None,
+        };
+        Some(address)
+    }
+
     pub fn try_into_bool(&self) -> Option<bool> {
         match self {
             Value::Boolean(inner) => Some(*inner),
@@ -146,6 +178,13 @@ impl Value {
             _ => None,
         }
     }
+
+    pub fn try_as_obj_partial(&self) -> Option<Gc<ObjPartial>> {
+        match self {
+            Value::ObjPartial(inner) => Some(*inner),
+            _ => None,
+        }
+    }
     pub fn try_as_obj_tuple(&self) -> Option<Gc<ObjTuple>> {
         match self {
             Value::ObjTuple(inner) => Some(*inner),
@@ -188,6 +227,12 @@ impl Value {
             _ => None,
         }
     }
+    pub fn try_as_obj_identity_map(&self) -> Option<Gc<RefCell<ObjIdentityMap>>> {
+        match self {
+            Value::ObjIdentityMap(inner) => Some(*inner),
+            _ => None,
+        }
+    }
     pub fn try_as_obj_module(&self) -> Option<Gc<RefCell<ObjModule>>> {
         match self {
             Value::ObjModule(inner) => Some(*inner),
@@ -201,18 +246,7 @@ impl Value {
         }
     }
     pub fn try_as_bounded_index(&self, bound: isize, kind: &str) -> Result<usize, Error> {
-        let mut index = utils::validate_integer(*self)?;
-        if index < 0 {
-            index += bound;
-        }
-        if index < 0 || index >= bound {
-            return Err(error!(
-                ErrorKind::IndexError,
-                "{} index out of bounds.", kind
-            ));
-        }
-
-        Ok(index as usize)
+        utils::bounded_index(utils::validate_integer(*self)?, bound, kind)
     }
 }
 
@@ -234,6 +268,7 @@ impl memory::GcManaged for Value {
             Value::ObjInstance(inner) => inner.mark(),
             Value::ObjBoundMethod(inner) => inner.mark(),
             Value::ObjBoundNative(inner) => inner.mark(),
+            Value::ObjPartial(inner) => inner.mark(),
             Value::ObjTuple(inner) => inner.mark(),
             Value::ObjTupleIter(inner) => inner.mark(),
             Value::ObjVec(inner) => inner.mark(),
@@ -241,6 +276,7 @@ impl memory::GcManaged for Value {
             Value::ObjRange(inner) => inner.mark(),
             Value::ObjRangeIter(inner) => inner.mark(),
             Value::ObjHashMap(inner) => inner.mark(),
+            Value::ObjIdentityMap(inner) => inner.mark(),
             Value::ObjModule(inner) => inner.mark(),
             Value::ObjFiber(inner) => inner.mark(),
             _ => {}
@@ -258,6 +294,7 @@ impl memory::GcManaged for Value {
             Value::ObjInstance(inner) => inner.blacken(),
             Value::ObjBoundMethod(inner) => inner.blacken(),
             Value::ObjBoundNative(inner) => inner.blacken(),
+            Value::ObjPartial(inner) => inner.blacken(),
             Value::ObjTuple(inner) => inner.blacken(),
             Value::ObjTupleIter(inner) => inner.blacken(),
             Value::ObjVec(inner) => inner.blacken(),
@@ -265,6 +302,7 @@ impl memory::GcManaged for Value {
             Value::ObjRange(inner) => inner.blacken(),
             Value::ObjRangeIter(inner) => inner.blacken(),
             Value::ObjHashMap(inner) => inner.blacken(),
+            Value::ObjIdentityMap(inner) => inner.blacken(),
             Value::ObjModule(inner) => inner.blacken(),
             Value::ObjFiber(inner) => inner.blacken(),
             _ => {}
@@ -309,6 +347,9 @@ impl fmt::Display for Value {
             Value::ObjBoundNative(underlying) => {
                 write!(f, "<{} @ {:p}>", *underlying.borrow(), underlying.as_ptr())
             }
+            Value::ObjPartial(underlying) => {
+                write!(f, "<{} @ {:p}>", **underlying, underlying.as_ptr())
+            }
             Value::ObjTuple(underlying) => write!(f, "{}", **underlying),
             Value::ObjTupleIter(underlying) => {
                 write!(f, "<{} @ {:p}>", *underlying.borrow(), underlying.as_ptr())
@@ -320,6 +361,7 @@ impl fmt::Display for Value {
             Value::ObjRange(underlying) => write!(f, "{}", **underlying),
             Value::ObjRangeIter(underlying) => write!(f, "{}", *underlying.borrow()),
             Value::ObjHashMap(underlying) => write!(f, "{}", *underlying.borrow()),
+            Value::ObjIdentityMap(underlying) => write!(f, "{}", *underlying.borrow()),
             Value::ObjModule(underlying) => write!(f, "<{}>", *underlying.borrow()),
             Value::ObjFiber(underlying) => {
                 write!(f, "<{} @ {:p}>", *underlying.borrow(), underlying.as_ptr())
@@ -329,6 +371,33 @@ impl fmt::Display for Value {
     }
 }
 
+/// Formats a value as it appears inside a collection. Strings are quoted and escaped so that
+/// `["a"]` can be told apart from `[a]`; all other values are formatted as usual.
+pub struct Repr<'a>(pub &'a Value);
+
+impl<'a> fmt::Display for Repr<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let string = match self.0 {
+            Value::ObjString(string) => string,
+            value => return write!(f, "{}", value),
+        };
+        write!(f, "\"")?;
+        for c in string.as_str().chars() {
+            match c {
+                '"' => write!(f, "\\\"")?,
+                '\\' => write!(f, "\\\\")?,
+                '\n' => write!(f, "\\n")?,
+                '\r' => write!(f, "\\r")?,
+                '\t' => write!(f, "\\t")?,
+                '\0' => write!(f, "\\0")?,
+                c if c.is_ascii_control() => write!(f, "\\x{:02x}", c as u32)?,
+                c => write!(f, "{}", c)?,
+            }
+        }
+        write!(f, "\"")
+    }
+}
+
 impl cmp::Eq for Value {}
 
 impl cmp::PartialEq for Value {
@@ -336,7 +405,12 @@ impl cmp::PartialEq for Value {
         match (self, other) {
             (Value::Boolean(first), Value::Boolean(second)) => first == second,
             (Value::Number(first), Value::Number(second)) => first == second,
-            (Value::ObjString(first), Value::ObjString(second)) => *first == *second,
+            // Strings longer than the intern limit aren't interned, so may share contents without
+            // sharing an address.
+            (Value::ObjString(first), Value::ObjString(second)) => {
+                *first == *second
+                    || (first.hash == second.hash && first.as_str() == second.as_str())
+            }
             (Value::ObjStringIter(first), Value::ObjStringIter(second)) => *first == *second,
             (Value::ObjFunction(first), Value::ObjFunction(second)) => *first == *second,
             (Value::ObjNative(first), Value::ObjNative(second)) => *first == *second,
@@ -344,6 +418,7 @@ impl cmp::PartialEq for Value {
             (Value::ObjClass(first), Value::ObjClass(second)) => *first == *second,
             (Value::ObjInstance(first), Value::ObjInstance(second)) => *first == *second,
             (Value::ObjBoundMethod(first), Value::ObjBoundMethod(second)) => *first == *second,
+            (Value::ObjPartial(first), Value::ObjPartial(second)) => *first == *second,
             (Value::ObjTuple(first), Value::ObjTuple(second)) => **first == **second,
             (Value::ObjTupleIter(first), Value::ObjTupleIter(second)) => *first == *second,
             (Value::ObjVec(first), Value::ObjVec(second)) => *first.borrow() == *second.borrow(),
@@ -353,6 +428,7 @@ impl cmp::PartialEq for Value {
             (Value::ObjHashMap(first), Value::ObjHashMap(second)) => {
                 *first.borrow() == *second.borrow()
             }
+            (Value::ObjIdentityMap(first), Value::ObjIdentityMap(second)) => *first == *second,
             (Value::ObjModule(first), Value::ObjModule(second)) => *first == *second,
             (Value::ObjFiber(first), Value::ObjFiber(second)) => *first == *second,
             (Value::None, Value::None) => true,
@@ -361,8 +437,14 @@ impl cmp::PartialEq for Value {
     }
 }
 
+fn hash_of(value: &Value) -> u64 {
+    let mut hasher = PassThroughHasher::default();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
 impl Hash for Value {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
         let hash = match self {
             Value::Boolean(b) => {
                 if *b {
@@ -382,9 +464,22 @@ impl Hash for Value {
                 t.hash(&mut hasher);
                 hasher.finish()
             }
+            Value::ObjVec(v) => utils::hash_sequence(v.borrow().elements.iter().map(hash_of)),
+            // Entries are combined independently of their order.
+            Value::ObjHashMap(m) => m
+                .borrow()
+                .elements
+                .iter()
+                .map(|(k, v)| utils::hash_sequence([hash_of(k), hash_of(v)]))
+                .fold(3_u64, |a, b| a ^ b),
             Value::ObjRange(r) => {
-                utils::hash_number(r.begin as f64) ^ utils::hash_number(r.end as f64)
+                utils::hash_number(r.begin as f64)
+                    ^ utils::hash_number(r.end as f64)
+                    ^ utils::hash_number(r.iter_step() as f64)
             }
+            // Instances used as keys are first replaced by any equal instance already in the map,
+            // so they can be hashed by address.
+            Value::ObjInstance(i) => utils::hash_number(i.as_ptr() as usize as f64),
             Value::None => 2_u64,
             _ => {
                 panic!("Unhashable value type: {}", self);
@@ -0,0 +1,448 @@
+/* Copyright 2020-2021 Matt Spraggs
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Verification of bytecode that didn't come from the compiler, which the virtual machine
+//! otherwise trusts to be well formed.
+//!
+//! Verified bytecode decodes into known instructions whose operands refer to existing constants,
+//! globals, locals and upvalues, and whose jumps land on other instructions. Each instruction is
+//! reached with the same stack depth however it's reached, and never pops more values than its
+//! function has pushed. Only the instructions needed for expressions, variables, control flow,
+//! calls and closures are supported: bytecode that defines classes, handles exceptions, imports
+//! modules or uses generators is rejected.
+
+use alloc::borrow::ToOwned;
+use alloc::vec::Vec;
+use alloc::{format, vec};
+
+use crate::chunk::{Chunk, OpCode};
+use crate::common;
+use crate::error::{Error, ErrorKind};
+use crate::object::ObjFunction;
+use crate::value::Value;
+
+/// The number of values an instruction pops from and pushes onto the stack. Values that are
+/// only inspected count as both popped and pushed.
+struct Effect {
+    pops: usize,
+    pushes: usize,
+}
+
+fn effect(pops: usize, pushes: usize) -> Effect {
+    Effect { pops, pushes }
+}
+
+struct Instruction {
+    opcode: OpCode,
+    len: usize,
+}
+
+struct Verifier<'a> {
+    function: &'a ObjFunction,
+    chunk: &'a Chunk,
+    num_globals: usize,
+    /// The stack depth on reaching each offset, where known.
+    depths: Vec<Option<usize>>,
+    /// The instruction starting at each offset, if any.
+    instructions: Vec<Option<Instruction>>,
+}
+
+/// Verifies the bytecode of a function and any functions defined within it. Global slots must
+/// be less than the specified number of globals in the function's module.
+pub(crate) fn verify_function(function: &ObjFunction, num_globals: usize) -> Result<(), Error> {
+    let chunk = &*function.chunk;
+    let mut verifier = Verifier {
+        function,
+        chunk,
+        num_globals,
+        depths: vec![None; chunk.code.len()],
+        instructions: Vec::new(),
+    };
+    verifier.decode()?;
+    verifier.check_stack()?;
+
+    for constant in &chunk.constants {
+        if let Value::ObjFunction(nested) = constant {
+            verify_function(nested, num_globals)?;
+        }
+    }
+    Ok(())
+}
+
+impl<'a> Verifier<'a> {
+    fn error(&self, offset: usize, reason: &str) -> Error {
+        error!(
+            ErrorKind::ValueError,
+            "Invalid bytecode at offset {} of {}: {}.",
+            offset,
+            if self.function.name.as_str().is_empty() {
+                "script".to_owned()
+            } else {
+                format!("{}()", *self.function.name)
+            },
+            reason
+        )
+    }
+
+    fn byte(&self, offset: usize) -> usize {
+        self.chunk.code[offset] as usize
+    }
+
+    fn short(&self, offset: usize) -> usize {
+        u16::from_ne_bytes([self.chunk.code[offset], self.chunk.code[offset + 1]]) as usize
+    }
+
+    fn long(&self, offset: usize) -> usize {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&self.chunk.code[offset..offset + 4]);
+        u32::from_ne_bytes(bytes) as usize
+    }
+
+    fn constant(&self, offset: usize) -> Result<Value, Error> {
+        let index = self.short(offset);
+        self.chunk
+            .constants
+            .get(index)
+            .copied()
+            .ok_or_else(|| self.error(offset, &format!("no constant {}", index)))
+    }
+
+    fn check_string(&self, offset: usize) -> Result<(), Error> {
+        match self.constant(offset)? {
+            Value::ObjString(_) => Ok(()),
+            _ => Err(self.error(offset, "expected a string constant")),
+        }
+    }
+
+    /// Splits the bytecode into instructions, checking that each one is supported and complete.
+    fn decode(&mut self) -> Result<(), Error> {
+        let code = &self.chunk.code;
+        if code.is_empty() {
+            return Err(self.error(0, "expected an instruction"));
+        }
+        if self.function.arity == 0 {
+            return Err(self.error(0, "expected a slot for the callee"));
+        }
+        if self.chunk.lines.len() != code.len() {
+            return Err(self.error(0, "expected a line number for each byte"));
+        }
+
+        let mut instructions = Vec::new();
+        instructions.resize_with(code.len(), || None);
+        let mut offset = 0;
+        while offset < code.len() {
+            let byte = code[offset];
+            if byte >= OpCode::COUNT {
+                return Err(self.error(offset, &format!("unknown opcode {}", byte)));
+            }
+            let opcode = OpCode::from(byte);
+            if !is_supported(opcode) {
+                return Err(self.error(offset, &format!("unsupported opcode {:?}", opcode)));
+            }
+            let mut len = 1 + opcode.arg_sizes().iter().sum::<usize>();
+            if offset + len > code.len() {
+                return Err(self.error(offset, "incomplete instruction"));
+            }
+            if opcode == OpCode::Closure {
+                match self.constant(offset + 1)? {
+                    Value::ObjFunction(function) => len += 2 * function.upvalue_count,
+                    _ => return Err(self.error(offset + 1, "expected a function constant")),
+                }
+                if offset + len > code.len() {
+                    return Err(self.error(offset, "incomplete instruction"));
+                }
+            }
+            instructions[offset] = Some(Instruction { opcode, len });
+            offset += len;
+        }
+
+        self.instructions = instructions;
+        Ok(())
+    }
+
+    /// Follows every path through the bytecode, tracking the depth of the stack.
+    fn check_stack(&mut self) -> Result<(), Error> {
+        let mut pending = vec![(0, self.function.arity)];
+        while let Some((offset, depth)) = pending.pop() {
+            if offset >= self.chunk.code.len() {
+                return Err(self.error(offset, "execution continues past the end"));
+            }
+            match self.depths[offset] {
+                Some(known) if known == depth => continue,
+                Some(_) => {
+                    return Err(self.error(offset, "stack depth differs between paths"));
+                }
+                None => self.depths[offset] = Some(depth),
+            }
+            let (len, opcode) = match &self.instructions[offset] {
+                Some(instruction) => (instruction.len, instruction.opcode),
+                None => return Err(self.error(offset, "jump into an instruction")),
+            };
+
+            let Effect { pops, pushes } = self.check_operands(offset, opcode, depth)?;
+            // The function's arguments, including the callee in slot 0, stay on the stack until
+            // it returns.
+            if depth < self.function.arity + pops {
+                return Err(self.error(offset, "stack underflow"));
+            }
+            let new_depth = depth - pops + pushes;
+            if new_depth > common::LOCALS_MAX {
+                return Err(self.error(offset, "stack overflow"));
+            }
+
+            let next = offset + len;
+            match opcode {
+                OpCode::Jump => pending.push((next + self.short(offset + 1), new_depth)),
+                OpCode::JumpLong => pending.push((next + self.long(offset + 1), new_depth)),
+                OpCode::Loop => match next.checked_sub(self.short(offset + 1)) {
+                    Some(target) => pending.push((target, new_depth)),
+                    None => return Err(self.error(offset, "jump before the start")),
+                },
+                OpCode::LoopLong => match next.checked_sub(self.long(offset + 1)) {
+                    Some(target) => pending.push((target, new_depth)),
+                    None => return Err(self.error(offset, "jump before the start")),
+                },
+                OpCode::JumpIfFalse | OpCode::JumpIfStopIter | OpCode::JumpIfNotInstance => {
+                    pending.push((next + self.short(offset + 1), new_depth));
+                    pending.push((next, new_depth));
+                }
+                OpCode::Construct => {
+                    pending.push((next + self.byte(offset + 2), new_depth));
+                    pending.push((next, new_depth));
+                }
+                OpCode::Return | OpCode::Throw => {}
+                _ => pending.push((next, new_depth)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the operands of an instruction, returning its effect on the stack.
+    fn check_operands(&self, offset: usize, opcode: OpCode, depth: usize) -> Result<Effect, Error> {
+        let check_local = |slot: usize| {
+            if slot < depth {
+                Ok(())
+            } else {
+                Err(self.error(offset, &format!("no local in slot {}", slot)))
+            }
+        };
+        let check_upvalue = |index: usize| {
+            if index < self.function.upvalue_count {
+                Ok(())
+            } else {
+                Err(self.error(offset, &format!("no upvalue {}", index)))
+            }
+        };
+        let check_global = |slot: usize| {
+            if slot < self.num_globals {
+                Ok(())
+            } else {
+                Err(self.error(offset, &format!("no global in slot {}", slot)))
+            }
+        };
+
+        let result = match opcode {
+            OpCode::Constant => {
+                self.constant(offset + 1)?;
+                effect(0, 1)
+            }
+            OpCode::Nil | OpCode::True | OpCode::False => effect(0, 1),
+            OpCode::Pop | OpCode::CloseUpvalue | OpCode::Return | OpCode::Throw => effect(1, 0),
+            OpCode::CopyTop | OpCode::IterNext => effect(1, 2),
+            OpCode::CopyTopTwo => effect(2, 4),
+            OpCode::GetLocal => {
+                check_local(self.byte(offset + 1))?;
+                effect(0, 1)
+            }
+            OpCode::SetLocal => {
+                check_local(self.byte(offset + 1))?;
+                effect(1, 1)
+            }
+            OpCode::GetGlobal => {
+                check_global(self.short(offset + 1))?;
+                effect(0, 1)
+            }
+            OpCode::DefineGlobal => {
+                check_global(self.short(offset + 1))?;
+                effect(1, 0)
+            }
+            OpCode::SetGlobal => {
+                check_global(self.short(offset + 1))?;
+                effect(1, 1)
+            }
+            OpCode::GetUpvalue => {
+                check_upvalue(self.byte(offset + 1))?;
+                effect(0, 1)
+            }
+            OpCode::SetUpvalue => {
+                check_upvalue(self.byte(offset + 1))?;
+                effect(1, 1)
+            }
+            OpCode::GetProperty => {
+                self.check_string(offset + 1)?;
+                effect(1, 1)
+            }
+            OpCode::SetProperty => {
+                self.check_string(offset + 1)?;
+                effect(2, 1)
+            }
+            OpCode::GetClass
+            | OpCode::LogicalNot
+            | OpCode::BitwiseNot
+            | OpCode::Negate
+            | OpCode::FormatString
+            | OpCode::GetIter
+            | OpCode::JumpIfFalse
+            | OpCode::JumpIfStopIter => effect(1, 1),
+            OpCode::Equal
+            | OpCode::Greater
+            | OpCode::Less
+            | OpCode::Is
+            | OpCode::Add
+            | OpCode::Subtract
+            | OpCode::Multiply
+            | OpCode::Divide
+            | OpCode::BitwiseAnd
+            | OpCode::BitwiseOr
+            | OpCode::BitwiseXor
+            | OpCode::Modulo
+            | OpCode::BitShiftLeft
+            | OpCode::BitShiftRight
+            | OpCode::GetItem
+            | OpCode::JumpIfNotInstance => effect(2, 1),
+            OpCode::SetItem => effect(3, 1),
+            OpCode::BuildHashMap => effect(2 * self.byte(offset + 1), 1),
+            // The operand is non-zero if the range has a step.
+            OpCode::BuildRange => effect(2 + (self.byte(offset + 1) != 0) as usize, 1),
+            OpCode::ExtendHashMap => effect(2 * self.byte(offset + 1) + 1, 1),
+            OpCode::BuildString | OpCode::BuildTuple | OpCode::BuildVec => {
+                effect(self.byte(offset + 1), 1)
+            }
+            OpCode::ExtendVec => effect(self.byte(offset + 1) + 1, 1),
+            // The Vec and the iterator the value came from stay on the stack.
+            OpCode::Splice => effect(3, 2),
+            OpCode::Jump | OpCode::JumpLong | OpCode::Loop | OpCode::LoopLong => effect(0, 0),
+            OpCode::Call => effect(self.byte(offset + 1) + 1, 1),
+            OpCode::Invoke => {
+                self.check_string(offset + 1)?;
+                effect(self.byte(offset + 3) + 1, 1)
+            }
+            OpCode::Construct => {
+                let arg_count = self.byte(offset + 1);
+                effect(arg_count + 1, arg_count + 1)
+            }
+            OpCode::Closure => {
+                if let Value::ObjFunction(function) = self.constant(offset + 1)? {
+                    for i in 0..function.upvalue_count {
+                        let is_local = self.byte(offset + 3 + 2 * i);
+                        let index = self.byte(offset + 4 + 2 * i);
+                        match is_local {
+                            0 => check_upvalue(index)?,
+                            1 => check_local(index)?,
+                            _ => return Err(self.error(offset, "invalid upvalue capture")),
+                        }
+                    }
+                }
+                effect(0, 1)
+            }
+            _ => return Err(self.error(offset, &format!("unsupported opcode {:?}", opcode))),
+        };
+        Ok(result)
+    }
+}
+
+fn is_supported(opcode: OpCode) -> bool {
+    !matches!(
+        opcode,
+        OpCode::GetSuper
+            | OpCode::JumpFinally
+            | OpCode::EndFinally
+            | OpCode::PushExcHandler
+            | OpCode::PushExcHandlerLong
+            | OpCode::PopExcHandler
+            | OpCode::Rethrow
+            | OpCode::CallSpread
+            | OpCode::CallNamed
+            | OpCode::InvokeSpread
+            | OpCode::InvokeNamed
+            | OpCode::SuperInvoke
+            | OpCode::Generator
+            | OpCode::Yield
+            | OpCode::DeclareClass
+            | OpCode::DefineClass
+            | OpCode::Inherit
+            | OpCode::Implements
+            | OpCode::Mixin
+            | OpCode::Method
+            | OpCode::StaticMethod
+            | OpCode::StaticField
+            | OpCode::StartImport
+            | OpCode::LazyImport
+            | OpCode::FinishImport
+    )
+}
+
+#[test]
+fn test_verify_bytecode() {
+    use crate::vm::Vm;
+
+    fn chunk(constants: &[Value], code: &[u8]) -> Chunk {
+        let mut chunk = Chunk::new();
+        for &constant in constants {
+            chunk.add_constant(constant);
+        }
+        for &byte in code {
+            chunk.write(byte, 1);
+        }
+        chunk
+    }
+
+    let mut vm = Vm::with_built_ins();
+    let constants = [Value::Number(1.0), Value::Number(2.0)];
+    let constant = OpCode::Constant as u8;
+    let add = OpCode::Add as u8;
+    let ret = OpCode::Return as u8;
+
+    let valid = chunk(&constants, &[constant, 0, 0, constant, 1, 0, add, ret]);
+    assert!(vm.execute_bytecode(valid).is_ok());
+
+    let invalid = [
+        (vec![OpCode::Pop as u8, ret], "stack underflow"),
+        (vec![constant, 0, 0], "execution continues past the end"),
+        (vec![constant, 2, 0, ret], "no constant 2"),
+        (vec![constant, 0], "incomplete instruction"),
+        (vec![OpCode::COUNT, ret], "unknown opcode"),
+        (
+            vec![OpCode::Jump as u8, 1, 0, constant, 0, 0, ret],
+            "jump into an instruction",
+        ),
+        (vec![OpCode::GetLocal as u8, 1, ret], "no local in slot 1"),
+        (
+            vec![OpCode::GetGlobal as u8, 255, 255, ret],
+            "no global in slot 65535",
+        ),
+        (vec![OpCode::Throw as u8], "stack underflow"),
+        (vec![OpCode::PopExcHandler as u8, ret], "unsupported opcode"),
+    ];
+    for (code, reason) in &invalid {
+        let error = vm.execute_bytecode(chunk(&constants, code)).unwrap_err();
+        assert_eq!(ErrorKind::ValueError, error.kind());
+        assert!(
+            error.messages()[0].contains(reason),
+            "{:?}",
+            error.messages()
+        );
+    }
+}
@@ -14,43 +14,153 @@
  */
 
 #[allow(unused_imports)]
-use std::cell::{Ref, RefCell, RefMut};
-use std::collections::HashMap;
-use std::fmt::Write;
-use std::fs;
-use std::hash::{Hash, Hasher};
-use std::hint;
+use ::core::cell::{Ref, RefCell, RefMut};
+#[cfg(not(feature = "std"))]
+use ::core::convert::Infallible;
+use ::core::convert::TryFrom;
+use ::core::fmt::Write;
+use ::core::hash::{Hash, Hasher};
+use ::core::hint;
+use ::core::mem;
+use ::core::ops::Range;
+use ::core::ptr;
+use alloc::borrow::ToOwned;
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use alloc::{format, vec};
+#[cfg(feature = "std")]
 use std::io;
+#[cfg(feature = "native_modules")]
 use std::path::Path;
-use std::ptr;
-use std::time;
 
+#[cfg(feature = "serde")]
+use serde::{de::DeserializeOwned, Serialize};
+
+pub use self::string_store::StringStoreStats;
 use crate::chunk::{Chunk, OpCode};
 use crate::class_store::CoreClassStore;
 use crate::common;
 use crate::compiler;
 use crate::core;
+use crate::coverage::{Coverage, CoverageReport};
 use crate::debug;
-use crate::error::{Error, ErrorKind};
-use crate::hash::{BuildPassThroughHasher, FnvHasher};
-use crate::memory::{self, Gc, Root, UniqueRoot};
+use crate::error::{Error, ErrorCode, ErrorKind};
+#[cfg(feature = "native_modules")]
+use crate::hash::HashSet;
+use crate::hash::{BuildPassThroughHasher, FnvHasher, HashMap};
+#[cfg(feature = "fs")]
+use crate::loader::FileSystemLoader;
+#[cfg(not(feature = "fs"))]
+use crate::loader::MemoryModuleLoader;
+use crate::loader::{ModuleLoader, ModuleSource};
+use crate::memory::{self, Gc, GcOptions, GcStats, Root, UniqueRoot};
+#[cfg(feature = "native_modules")]
+use crate::native;
 use crate::object::{
     self, NativeFn, ObjBoundMethod, ObjClass, ObjClosure, ObjFiber, ObjFunction, ObjHashMap,
-    ObjInstance, ObjModule, ObjNative, ObjRange, ObjRangeIter, ObjString, ObjStringIter,
-    ObjStringValueMap, ObjTuple, ObjTupleIter, ObjUpvalue, ObjVec, ObjVecIter,
+    ObjIdentityMap, ObjInstance, ObjModule, ObjNative, ObjPartial, ObjRange, ObjRangeIter,
+    ObjString, ObjStringIter, ObjStringValueMap, ObjTuple, ObjTupleIter, ObjUpvalue, ObjVec,
+    ObjVecIter,
 };
+use crate::profiler::{ProfileReport, Profiler};
+#[cfg(feature = "serde")]
+use crate::serialization;
+use crate::stats::{OpcodeCounter, OpcodeStats};
+#[cfg(not(feature = "std"))]
+use crate::trace::NullSink;
+#[cfg(feature = "std")]
+use crate::trace::WriterSink;
+use crate::trace::{DiagnosticsSink, TraceEvent, TraceSink, TraceStep};
 use crate::utils;
-use crate::value::Value;
+use crate::value::{Repr, Value};
+use crate::verify;
+use crate::warning::{Warning, WarningKind};
+
+/// A module along with the function that runs its body, if it has yet to be imported.
+type LoadedModule = (Gc<RefCell<ObjModule>>, Option<Root<ObjFunction>>);
+
+type WarningCallback = Box<dyn FnMut(&Warning)>;
+
+/// Limits on the resources used by a virtual machine, which trade the depth of recursion that
+/// scripts can reach against the memory the virtual machine may use.
+#[derive(Clone, Debug)]
+pub struct VmOptions {
+    /// The maximum number of values on the stack of each fiber. Calling a function raises an
+    /// error unless there's space on the stack for its locals and temporaries, which may need up
+    /// to 512 values.
+    pub stack_size: usize,
+    /// The maximum number of function calls that may be in progress at once in each fiber.
+    pub frame_limit: usize,
+    /// The number of ranges kept for reuse when a range expression is evaluated.
+    pub range_cache_size: usize,
+    /// The length in bytes above which strings created whilst running a script aren't interned.
+    /// Such strings are compared by their contents rather than by their identity.
+    pub intern_limit: usize,
+    /// The access scripts have to the environment variables of the process. The `env` module is
+    /// only available with the `std` feature.
+    pub env_access: EnvAccess,
+    /// Whether scripts can import the `os` module, whose `run` function runs other programs. The
+    /// `os` module is only available with the `std` feature.
+    pub allow_subprocess: bool,
+    /// Whether arithmetic that produces NaN or an infinity from finite operands raises a
+    /// ValueError instead.
+    pub strict_math: bool,
+}
+
+impl Default for VmOptions {
+    fn default() -> Self {
+        VmOptions {
+            stack_size: 1 << 20,
+            frame_limit: 65536,
+            range_cache_size: 8,
+            intern_limit: 1 << 12,
+            env_access: EnvAccess::None,
+            allow_subprocess: false,
+            strict_math: false,
+        }
+    }
+}
+
+/// Controls whether scripts can import the `env` module, whose `get`, `set` and `vars` functions
+/// read and modify the environment variables of the process.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EnvAccess {
+    /// The `env` module isn't defined.
+    None,
+    /// Environment variables can be read, but calling `set` raises an error.
+    ReadOnly,
+    ReadWrite,
+}
 
-const RANGE_CACHE_SIZE: usize = 8;
+/// Identifies a call to a native function whose result is provided later by the host with
+/// `Vm::resume_pending`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PendingToken(u64);
 
-type LoadModuleFn = fn(&str) -> Result<String, Error>;
+fn string_hash(data: &str) -> u64 {
+    let mut hasher = FnvHasher::new();
+    data.hash(&mut hasher);
+    hasher.finish()
+}
 
 pub fn interpret(vm: &mut Vm, source: String, module_path: Option<&str>) -> Result<Value, Error> {
     let compile_result = compiler::compile(vm, source, module_path);
     match compile_result {
-        Ok(function) => vm.execute(function, &[]),
-        Err(error) => Err(error),
+        Ok(function) => {
+            // The result is kept alive whilst any fibers the module scheduled are run. The root is
+            // allocated up front, as nothing else refers to the result once it's returned.
+            let result = Root::new(RefCell::new(Value::None));
+            *result.borrow_mut() = vm.execute(function, &[])?;
+            vm.pump()?;
+            let result = *result.borrow();
+            Ok(result)
+        }
+        Err(error) => {
+            vm.report_error(&error);
+            Err(error)
+        }
     }
 }
 
@@ -66,50 +176,37 @@ impl ClassDef {
     }
 }
 
-fn default_read_module_source(path: &str) -> Result<String, Error> {
-    let path = Path::new(path).with_extension("yl");
-    let filename = match path.as_path().to_str() {
-        Some(p) => p,
-        None => {
-            return Err(error!(
-                ErrorKind::RuntimeError,
-                "Error converting module path to string."
-            ));
-        }
-    };
+fn unexported_error(module: Gc<RefCell<ObjModule>>, name: Gc<ObjString>) -> Error {
+    error!(
+        ErrorKind::AttributeError,
+        "Module \"{}\" does not export '{}'.",
+        *module.borrow().path,
+        *name
+    )
+}
 
-    let source = match fs::read_to_string(filename) {
-        Ok(s) => s,
-        Err(e) => {
-            let reason = match e.kind() {
-                io::ErrorKind::NotFound => "file not found",
-                io::ErrorKind::PermissionDenied => "permission denied",
-                io::ErrorKind::ConnectionRefused => "connection refused",
-                io::ErrorKind::ConnectionReset => "connection reset",
-                io::ErrorKind::ConnectionAborted => "connection aborted",
-                io::ErrorKind::NotConnected => "not connected",
-                io::ErrorKind::AddrInUse => "address in use",
-                io::ErrorKind::AddrNotAvailable => "address not available",
-                io::ErrorKind::BrokenPipe => "broken pipe",
-                io::ErrorKind::AlreadyExists => "already exists",
-                io::ErrorKind::WouldBlock => "would block",
-                io::ErrorKind::InvalidInput => "invalid input",
-                io::ErrorKind::InvalidData => "invalid data",
-                io::ErrorKind::TimedOut => "timed out",
-                io::ErrorKind::WriteZero => "write zero",
-                io::ErrorKind::Interrupted => "interrupted",
-                io::ErrorKind::Other => "other",
-                io::ErrorKind::UnexpectedEof => "unexpected end-of-file",
-                _ => "other",
-            };
-            return Err(error!(
-                crate::error::ErrorKind::ImportError,
-                "Unable to read file '{}' ({}).", filename, reason
-            ));
-        }
-    };
+fn uninitialised_attribute_error(module: Gc<RefCell<ObjModule>>, name: Gc<ObjString>) -> Error {
+    error!(
+        ErrorKind::ImportError,
+        "Cannot access '{}' before module \"{}\" has finished initialising.",
+        *name,
+        *module.borrow().path
+    )
+}
+
+fn not_iterable_error(value: Value) -> Error {
+    error!(
+        ErrorKind::TypeError,
+        "Expected an iterable but found '{}'.", value
+    )
+}
 
-    Ok(source)
+fn push_repeats(traceback: &mut Vec<String>, repeats: usize) {
+    match repeats {
+        0 => {}
+        1 => traceback.push("[Previous line repeated 1 more time.]".to_string()),
+        _ => traceback.push(format!("[Previous line repeated {} more times.]", repeats)),
+    }
 }
 
 enum IndexResult {
@@ -123,18 +220,61 @@ pub struct Vm {
     active_chunk: Gc<Chunk>,
     fiber: Option<Root<RefCell<ObjFiber>>>,
     unsafe_fiber: *mut ObjFiber,
+    suspended_fibers: Vec<Root<RefCell<ObjFiber>>>,
+    scheduled_fibers: VecDeque<Root<RefCell<ObjFiber>>>,
+    scheduler: Option<Root<ObjClosure>>,
+    scheduler_fiber: Option<Root<RefCell<ObjFiber>>>,
+    pending_call: Option<PendingToken>,
+    /// The fiber of a method called with `call_method` that left an exception unhandled, which
+    /// is raised again in place of the error it was converted to.
+    failed_method_fiber: Option<Root<RefCell<ObjFiber>>>,
+    /// The number of closures currently being run to completion on behalf of natives.
+    reentrant_depth: usize,
+    pending_fibers: HashMap<PendingToken, Root<RefCell<ObjFiber>>>,
+    pending_errors: Vec<(Gc<RefCell<ObjFiber>>, Error)>,
+    next_pending_token: u64,
+    iter_string: Gc<ObjString>,
     next_string: Gc<ObjString>,
+    method_missing_string: Gc<ObjString>,
+    field_initialiser_string: Gc<ObjString>,
+    call_string: Gc<ObjString>,
     class_store: CoreClassStore,
-    chunks: Vec<Root<Chunk>>,
     modules: HashMap<Gc<ObjString>, Root<RefCell<ObjModule>>, BuildPassThroughHasher>,
-    core_chunks: Vec<Root<Chunk>>,
     string_class: Option<Root<ObjClass>>,
     string_store: string_store::ObjStringStore,
-    range_cache: Vec<(Root<ObjRange>, time::Instant)>,
+    /// Cached ranges along with the order in which they were cached.
+    range_cache: Vec<(Root<ObjRange>, u64)>,
+    num_cached_ranges: u64,
     working_class_def: Option<ClassDef>,
-    module_loader: LoadModuleFn,
+    module_loader: Box<dyn ModuleLoader>,
     printer: NativeFn,
+    captured_output: Option<String>,
+    #[cfg(feature = "std")]
+    error_writer: Option<Box<dyn io::Write>>,
+    exit_code: Option<i32>,
+    clock: NativeFn,
+    options: VmOptions,
     handling_exception: bool,
+    instruction_count: Option<u64>,
+    fiber_traceback: Vec<String>,
+    profiler: Option<Profiler>,
+    opcode_counter: Option<OpcodeCounter>,
+    coverage: Option<Coverage>,
+    tracing: bool,
+    /// Whether any of the above are recording instructions, so that the dispatch loop only has
+    /// to check this to skip them all.
+    hooks_enabled: bool,
+    trace_sink: Option<TraceSink>,
+    disassembling: bool,
+    diagnostics: Box<dyn DiagnosticsSink>,
+    enabled_warnings: Vec<WarningKind>,
+    warnings: Vec<Warning>,
+    warning_callback: Option<WarningCallback>,
+    #[cfg(feature = "serde")]
+    serde_root: Option<Root<RefCell<ObjVec>>>,
+    // Declared last so that the libraries are unloaded after everything else is dropped.
+    #[cfg(feature = "native_modules")]
+    native_libraries: Vec<libloading::Library>,
 }
 
 impl Vm {
@@ -148,41 +288,382 @@ impl Vm {
             active_chunk: Gc::dangling(),
             fiber: None,
             unsafe_fiber: ptr::null_mut(),
+            suspended_fibers: Vec::new(),
+            scheduled_fibers: VecDeque::new(),
+            scheduler: None,
+            scheduler_fiber: None,
+            pending_call: None,
+            failed_method_fiber: None,
+            reentrant_depth: 0,
+            pending_fibers: HashMap::default(),
+            pending_errors: Vec::new(),
+            next_pending_token: 0,
+            iter_string: Gc::dangling(),
             next_string: Gc::dangling(),
+            method_missing_string: Gc::dangling(),
+            field_initialiser_string: Gc::dangling(),
+            call_string: Gc::dangling(),
             class_store: CoreClassStore::new_empty(),
-            chunks: Vec::new(),
             modules: HashMap::with_hasher(BuildPassThroughHasher::default()),
-            core_chunks: Vec::new(),
             string_class: None,
             string_store: string_store::ObjStringStore::new(),
-            range_cache: Vec::with_capacity(RANGE_CACHE_SIZE),
-            module_loader: default_read_module_source,
+            range_cache: Vec::new(),
+            num_cached_ranges: 0,
+            #[cfg(feature = "fs")]
+            module_loader: Box::new(FileSystemLoader::new()),
+            #[cfg(not(feature = "fs"))]
+            module_loader: Box::new(MemoryModuleLoader::new()),
             printer: core::print,
+            captured_output: None,
+            #[cfg(feature = "std")]
+            error_writer: None,
+            exit_code: None,
+            clock: core::clock,
+            options: VmOptions::default(),
             working_class_def: None,
             handling_exception: false,
+            instruction_count: None,
+            fiber_traceback: Vec::new(),
+            profiler: None,
+            opcode_counter: None,
+            coverage: None,
+            tracing: cfg!(feature = "debug_trace"),
+            hooks_enabled: cfg!(feature = "debug_trace"),
+            trace_sink: None,
+            disassembling: cfg!(feature = "debug_bytecode"),
+            #[cfg(feature = "std")]
+            diagnostics: Box::new(WriterSink::default()),
+            #[cfg(not(feature = "std"))]
+            diagnostics: Box::new(NullSink),
+            enabled_warnings: Vec::new(),
+            warnings: Vec::new(),
+            warning_callback: None,
+            #[cfg(feature = "serde")]
+            serde_root: None,
+            #[cfg(feature = "native_modules")]
+            native_libraries: Vec::new(),
         };
         vm.init_heap_allocated_data();
         vm
     }
 
     pub fn with_built_ins() -> Self {
+        Self::with_options(VmOptions::default())
+    }
+
+    /// Creates a virtual machine with the built-in globals, limited as specified.
+    pub fn with_options(options: VmOptions) -> Self {
         let mut vm = Self::new();
+        vm.options = options;
         vm.init_built_in_globals("main");
+        vm.init_process_module();
+        vm.init_gc_module();
+        #[cfg(feature = "std")]
+        if vm.options.env_access != EnvAccess::None {
+            vm.init_env_module();
+        }
+        #[cfg(feature = "std")]
+        if vm.options.allow_subprocess {
+            vm.define_native("os", "run", core::os_run);
+            vm.module("os").borrow_mut().imported = true;
+        }
         vm
     }
 
+    /// Sets the global `args` in the main module to a Vec of the specified strings, e.g. the
+    /// command-line arguments passed to a script.
+    pub fn set_args(&mut self, args: &[String]) {
+        let vec = self.new_root_obj_vec();
+        for arg in args {
+            let arg = self.new_gc_obj_string(arg);
+            vec.borrow_mut().elements.push(Value::ObjString(arg));
+        }
+        self.set_global("main", "args", Value::ObjVec(vec.as_gc()));
+    }
+
+    /// Replaces the built-in print function, which otherwise writes to stdout, or raises an error if
+    /// the `stdio` feature is disabled.
     pub fn set_printer(&mut self, printer: NativeFn) {
         self.printer = printer;
         self.define_native("main", "print", self.printer);
     }
 
-    pub fn set_module_loader(&mut self, loader: fn(&str) -> Result<String, Error>) {
-        self.module_loader = loader;
+    /// Buffers the output of the built-in print function in place of writing it, along with the
+    /// messages of any errors returned from running scripts and the output of eprint, so that the
+    /// output appears as it would on a terminal. The output is retrieved with `take_output`.
+    /// Setting an error writer separates the errors and eprint output from the rest.
+    pub fn capture_output(&mut self) {
+        self.captured_output = Some(String::new());
+        self.set_printer(core::capture_print);
+    }
+
+    /// Returns the output captured since `capture_output` was called or the output was last taken.
+    pub fn take_output(&mut self) -> String {
+        self.captured_output
+            .as_mut()
+            .map(mem::take)
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn capture(&mut self, text: &str) {
+        if let Some(output) = self.captured_output.as_mut() {
+            output.push_str(text);
+        }
+    }
+
+    /// Writes the output of the built-in eprint function, along with the messages of any errors
+    /// returned from running scripts, to the specified writer. Otherwise eprint writes to stderr,
+    /// or nowhere if the `stdio` feature is disabled, and errors are only returned.
+    #[cfg(feature = "std")]
+    pub fn set_error_writer(&mut self, writer: impl io::Write + 'static) {
+        self.error_writer = Some(Box::new(writer));
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn write_error_output(&mut self, text: &str) -> io::Result<()> {
+        match (self.error_writer.as_mut(), self.captured_output.as_mut()) {
+            (Some(writer), _) => writer.write_all(text.as_bytes()),
+            (None, Some(output)) => {
+                output.push_str(text);
+                Ok(())
+            }
+            #[cfg(feature = "stdio")]
+            (None, None) => io::Write::write_all(&mut io::stderr(), text.as_bytes()),
+            #[cfg(not(feature = "stdio"))]
+            (None, None) => Ok(()),
+        }
+    }
+
+    /// Without `std` there's no error writer, so error output is only kept if it's being
+    /// captured.
+    #[cfg(not(feature = "std"))]
+    pub(crate) fn write_error_output(&mut self, text: &str) -> Result<(), Infallible> {
+        self.capture(text);
+        Ok(())
+    }
+
+    fn report_error(&mut self, error: &Error) {
+        #[cfg(feature = "std")]
+        let has_writer = self.error_writer.is_some();
+        #[cfg(not(feature = "std"))]
+        let has_writer = false;
+        if !has_writer && self.captured_output.is_none() {
+            return;
+        }
+        for msg in error.messages() {
+            // The error is returned whether or not it can be written.
+            let _ = self.write_error_output(&format!("{}\n", msg));
+        }
+    }
+
+    /// Replaces the built-in clock function, which otherwise reads the system clock, or raises an
+    /// error if the `system_clock` feature is disabled.
+    pub fn set_clock(&mut self, clock: NativeFn) {
+        self.clock = clock;
+        self.define_native("main", "clock", self.clock);
+    }
+
+    /// Starts counting the instructions executed by each function and on each line, discarding
+    /// any previously gathered profile.
+    pub fn enable_profiler(&mut self) {
+        self.profiler = Some(Profiler::new());
+        self.update_hooks();
+    }
+
+    pub fn profile_report(&self) -> Option<ProfileReport> {
+        self.profiler.as_ref().map(|p| p.report())
+    }
+
+    /// Changes the settings of the garbage collector. The heap is shared by every virtual machine
+    /// on the current thread, so this affects all of them.
+    pub fn set_gc_options(&mut self, options: GcOptions) {
+        memory::configure(options);
+    }
+
+    /// Returns statistics about the garbage collector, which are shared by every virtual machine on
+    /// the current thread.
+    pub fn gc_stats(&self) -> GcStats {
+        memory::stats()
+    }
+
+    /// Starts counting the instructions executed with each opcode and the calls made from each
+    /// call site, discarding any previously gathered statistics.
+    pub fn enable_opcode_stats(&mut self) {
+        self.opcode_counter = Some(OpcodeCounter::new());
+        self.update_hooks();
+    }
+
+    pub fn opcode_stats(&self) -> Option<OpcodeStats> {
+        self.opcode_counter.as_ref().map(|c| c.stats())
+    }
+
+    /// Starts recording which lines of code compiled from this point on are executed.
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(Coverage::new());
+        self.update_hooks();
+    }
+
+    pub fn coverage_report(&self) -> Option<CoverageReport> {
+        self.coverage.as_ref().map(|c| c.report())
+    }
+
+    /// Turns on or off the reporting of each instruction, along with the stack, before it's
+    /// executed. Events are passed to the diagnostics sink unless a trace writer or callback has
+    /// been set. Tracing starts enabled if the `debug_trace` feature is.
+    pub fn set_trace(&mut self, enabled: bool) {
+        self.tracing = enabled;
+        self.update_hooks();
+    }
+
+    #[cfg(feature = "std")]
+    pub fn set_trace_writer(&mut self, writer: impl io::Write + 'static) {
+        self.trace_sink = Some(TraceSink::Writer(Box::new(writer)));
+    }
+
+    /// Passes each trace event to the specified callback, which can stop execution before the
+    /// traced instruction runs.
+    pub fn set_trace_callback(&mut self, callback: impl FnMut(&TraceEvent) -> TraceStep + 'static) {
+        self.trace_sink = Some(TraceSink::Callback(Box::new(callback)));
+    }
+
+    /// Turns on or off the disassembly of each function as it's compiled, which is passed to the
+    /// diagnostics sink. Disassembly starts enabled if the `debug_bytecode` feature is.
+    pub fn set_disassemble(&mut self, enabled: bool) {
+        self.disassembling = enabled;
+    }
+
+    /// Sets where debugging output is sent, which is stdout by default, or nowhere if the `stdio`
+    /// feature is disabled.
+    pub fn set_diagnostics_sink(&mut self, sink: impl DiagnosticsSink + 'static) {
+        self.diagnostics = Box::new(sink);
+    }
+
+    /// Disassembles the bytecode of a function or closure, with one instruction per line. Returns
+    /// `None` for any other value.
+    pub fn disassemble_value(&self, value: Value) -> Option<String> {
+        let function = value
+            .try_as_obj_closure()
+            .map(|closure| closure.function)
+            .or_else(|| value.try_as_obj_function())?;
+        Some(debug::disassemble_chunk(&function.chunk))
+    }
+
+    pub(crate) fn disassembling(&self) -> bool {
+        self.disassembling
+    }
+
+    pub(crate) fn disassemble(&mut self, function: &ObjFunction) {
+        let name = if function.name.is_empty() {
+            "script".to_owned()
+        } else {
+            format!("{}()", *function.name)
+        };
+        let listing = debug::disassemble_chunk(&function.chunk);
+        self.diagnostics.disassembly(&name, &listing);
+    }
+
+    /// Reports the specified kinds of warning for code compiled from this point on.
+    pub fn enable_warnings(&mut self, kinds: &[WarningKind]) {
+        for &kind in kinds {
+            if !self.enabled_warnings.contains(&kind) {
+                self.enabled_warnings.push(kind);
+            }
+        }
+    }
+
+    /// Returns the warnings raised whilst compiling, including those from any imported modules,
+    /// since this was last called.
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        mem::take(&mut self.warnings)
+    }
+
+    /// Passes each warning to the specified callback as soon as the module it was raised in has
+    /// been compiled, and so before that module runs. Warnings passed to the callback aren't
+    /// returned by `take_warnings`.
+    pub fn set_warning_callback(&mut self, callback: impl FnMut(&Warning) + 'static) {
+        self.warning_callback = Some(Box::new(callback));
+    }
+
+    /// Starts counting the bytecode instructions executed, discarding any previous count.
+    pub fn enable_instruction_count(&mut self) {
+        self.instruction_count = Some(0);
+        self.update_hooks();
+    }
+
+    /// Returns the number of bytecode instructions executed since counting was enabled.
+    /// Unlike timings, the count doesn't vary between runs, so it's suited to spotting
+    /// regressions in the bytecode generated for a script.
+    pub fn instruction_count(&self) -> Option<u64> {
+        self.instruction_count
+    }
+
+    fn update_hooks(&mut self) {
+        self.hooks_enabled = self.tracing
+            || self.profiler.is_some()
+            || self.opcode_counter.is_some()
+            || self.coverage.is_some()
+            || self.instruction_count.is_some();
+    }
+
+    /// Returns the exit code passed to `process.exit` if a script called it since the code was
+    /// last taken. Running the script then returns an error with the code `ErrorCode::Exit`.
+    pub fn take_exit_code(&mut self) -> Option<i32> {
+        self.exit_code.take()
+    }
+
+    /// Stops the running script with the specified exit code, returning the error that unwinds
+    /// it, which can't be handled by the script.
+    pub(crate) fn exit(&mut self, code: i32) -> Error {
+        self.exit_code = Some(code);
+        error!(ErrorKind::RuntimeError, "Exited with code {}.", code).with_code(ErrorCode::Exit)
+    }
+
+    /// Replaces the search of the file system for imported modules with the specified loader.
+    /// Functions taking the path of each module to load and returning its source can also be used.
+    pub fn set_module_loader(&mut self, loader: impl ModuleLoader + 'static) {
+        self.module_loader = Box::new(loader);
+    }
+
+    /// Loads the extension module in the shared library at the specified path and runs its
+    /// initialisation function. Any modules the extension defines can then be imported.
+    #[cfg(feature = "native_modules")]
+    pub fn load_native_module(&mut self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
+        let (library, init) = native::open_library(path)?;
+        let existing_modules: HashSet<_> = self.modules.keys().copied().collect();
+
+        // # Safety
+        // The virtual machine isn't borrowed anywhere else for the duration of the call.
+        let mut init_error = None;
+        let status = unsafe { init(self, &mut init_error) };
+        // Keep the library loaded even if initialisation failed, since it may have already
+        // defined native functions.
+        self.native_libraries.push(library);
+        if status != 0 {
+            let mut error = error!(
+                ErrorKind::ImportError,
+                "Native module '{}' failed to initialise.",
+                path.display()
+            );
+            if let Some(cause) = init_error {
+                error.set_cause(cause);
+            }
+            return Err(error);
+        }
+
+        for (path, module) in &self.modules {
+            if !existing_modules.contains(path) {
+                module.borrow_mut().imported = true;
+            }
+        }
+        Ok(())
     }
 
     pub fn execute(&mut self, function: Root<ObjFunction>, args: &[Value]) -> Result<Value, Error> {
         self.ip = ptr::null();
         self.fiber = None;
+        self.suspended_fibers.clear();
+        self.handling_exception = false;
         let module = self.module(&function.module_path);
         let closure = self.new_root_obj_closure(function.as_gc(), module);
         let fiber = self.new_root_obj_fiber(closure.as_gc());
@@ -193,7 +674,8 @@ impl Vm {
                 "Expected {} arguments but found {}.",
                 arity,
                 args.len()
-            ));
+            )
+            .with_code(ErrorCode::ArityMismatch));
         }
         self.load_fiber(fiber.as_gc(), None)?;
         for &arg in args {
@@ -205,21 +687,117 @@ impl Vm {
         }
     }
 
+    /// Runs a chunk of bytecode as module-level code in the main module. Unlike functions passed
+    /// to `execute`, the bytecode needn't have been generated by the compiler, so it's verified
+    /// first and rejected with an error if running it could crash the virtual machine.
+    pub fn execute_bytecode(&mut self, chunk: Chunk) -> Result<Value, Error> {
+        let name = self.new_gc_obj_string("");
+        let module_path = self.new_gc_obj_string("main");
+        let chunk = self.add_chunk(chunk, "main");
+        let function = self.new_root_obj_function(name, 1, 0, chunk.as_gc(), module_path);
+        let num_globals = self.module("main").borrow().num_slots();
+        verify::verify_function(&function, num_globals)?;
+        self.execute(function, &[])
+    }
+
+    /// Resumes each fiber scheduled with `Fiber.schedule`, in the order they were scheduled, until
+    /// it finishes or suspends itself, including any fibers scheduled in the meantime. Returns the
+    /// number of fibers resumed, or the first unhandled error raised by any of them, in which case
+    /// the fibers still scheduled are left to be resumed by the next call.
+    pub fn pump(&mut self) -> Result<usize, Error> {
+        let mut num_resumed = 0;
+        while let Some(fiber) = self.scheduled_fibers.pop_front() {
+            if fiber.borrow().has_finished() {
+                continue;
+            }
+            self.ip = ptr::null();
+            self.fiber = None;
+            self.handling_exception = false;
+            // The fiber is resumed from the scheduler's own fiber, which receives control when
+            // the resumed fiber suspends or finishes and then returns straight away.
+            let scheduler = self.new_root_obj_scheduler_fiber();
+            self.load_fiber(scheduler.as_gc(), None)?;
+            self.push(Value::None);
+            self.scheduler_fiber = Some(scheduler);
+            let result = self
+                .load_fiber(fiber.as_gc(), None)
+                .and_then(|_| self.raise_pending_error(fiber.as_gc()))
+                .and_then(|_| self.run())
+                .map_err(|mut error| self.runtime_error(&mut error));
+            self.scheduler_fiber = None;
+            result?;
+            num_resumed += 1;
+        }
+        Ok(num_resumed)
+    }
+
+    /// Suspends the fiber that called the native function being run once the native function
+    /// returns, discarding the value it returns. The fiber is resumed by `pump` after the result
+    /// of the call has been provided with `resume_pending`, using the returned token. Only fibers
+    /// resumed by the scheduler can be suspended, and the native function mustn't manage the
+    /// stack itself.
+    pub fn suspend_native_call(&mut self) -> Result<PendingToken, Error> {
+        self.check_suspendable()?;
+        if self.pending_call.is_some() {
+            return Err(error!(
+                ErrorKind::RuntimeError,
+                "Native function call is already pending."
+            ));
+        }
+        let token = PendingToken(self.next_pending_token);
+        self.next_pending_token += 1;
+        self.pending_call = Some(token);
+        Ok(token)
+    }
+
+    /// Provides the result of a native function call that suspended its fiber and schedules the
+    /// fiber to be resumed, either receiving the value or raising the error from the call.
+    pub fn resume_pending(
+        &mut self,
+        token: PendingToken,
+        result: Result<Value, Error>,
+    ) -> Result<(), Error> {
+        let fiber = self.pending_fibers.remove(&token).ok_or_else(|| {
+            error!(
+                ErrorKind::ValueError,
+                "No native function call is pending for the specified token."
+            )
+        })?;
+        match result {
+            Ok(value) => *fiber.borrow_mut().stack.peek_mut(0) = value,
+            Err(error) => self.pending_errors.push((fiber.as_gc(), error)),
+        }
+        self.schedule_fiber(fiber.as_gc())
+    }
+
     pub fn global(&mut self, module_name: &str, var_name: &str) -> Option<Value> {
         let var_name = self.new_gc_obj_string(var_name);
-        self.module(module_name)
-            .borrow()
-            .attributes
-            .get(&var_name)
-            .copied()
+        self.module(module_name).borrow().attribute(var_name)
     }
 
     pub fn set_global(&mut self, module_name: &str, var_name: &str, value: Value) {
         let var_name = self.new_gc_obj_string(var_name);
         self.module(module_name)
             .borrow_mut()
-            .attributes
-            .insert(var_name, value);
+            .set_attribute(var_name, value);
+    }
+
+    /// Converts a Rust value to a script value. Structs and maps become HashMaps, sequences become
+    /// Vecs, tuples become Tuples and enum variants with values become HashMaps with the variant
+    /// name as their only key. The result is only kept alive until the next conversion, so it
+    /// should be stored somewhere the virtual machine can reach, e.g. with `set_global`.
+    #[cfg(feature = "serde")]
+    pub fn value_from_serde<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<Value, Error> {
+        let keep_alive = self.new_root_obj_vec();
+        self.serde_root = Some(keep_alive.clone());
+        serialization::to_value(self, keep_alive.as_gc(), value)
+    }
+
+    /// Converts a script value to a Rust value, the reverse of `value_from_serde`. Instances are
+    /// converted as maps of their fields.
+    #[cfg(feature = "serde")]
+    pub fn value_to_serde<T: DeserializeOwned>(&self, value: Value) -> Result<T, Error> {
+        serialization::from_value(value)
     }
 
     pub fn define_native(&mut self, module_name: &str, var_name: &str, function: NativeFn) {
@@ -227,8 +805,22 @@ impl Vm {
         let native = self.new_root_obj_native(var_name, function);
         self.module(module_name)
             .borrow_mut()
-            .attributes
-            .insert(var_name, Value::ObjNative(native.as_gc()));
+            .set_attribute(var_name, Value::ObjNative(native.as_gc()));
+    }
+
+    fn set_built_in(&mut self, module_name: &str, var_name: &str, value: Value) {
+        let var_name = self.new_gc_obj_string(var_name);
+        self.module(module_name)
+            .borrow_mut()
+            .set_built_in(var_name, value);
+    }
+
+    fn define_built_in_native(&mut self, module_name: &str, var_name: &str, function: NativeFn) {
+        let var_name = self.new_gc_obj_string(var_name);
+        let native = self.new_root_obj_native(var_name, function);
+        self.module(module_name)
+            .borrow_mut()
+            .set_built_in(var_name, Value::ObjNative(native.as_gc()));
     }
 
     pub fn get_class(&self, value: Value) -> Gc<ObjClass> {
@@ -250,6 +842,7 @@ impl Vm {
             Value::ObjInstance(instance) => instance.borrow().class,
             Value::ObjBoundMethod(_) => self.class_store.closure_method_class(),
             Value::ObjBoundNative(_) => self.class_store.native_method_class(),
+            Value::ObjPartial(_) => self.class_store.partial_class(),
             Value::ObjTuple(tuple) => tuple.class,
             Value::ObjTupleIter(iter) => iter.borrow().class,
             Value::ObjVec(vec) => vec.borrow().class,
@@ -257,36 +850,90 @@ impl Vm {
             Value::ObjRange(range) => range.class,
             Value::ObjRangeIter(iter) => iter.borrow().class,
             Value::ObjHashMap(hash_map) => hash_map.borrow().class,
+            Value::ObjIdentityMap(identity_map) => identity_map.borrow().class,
             Value::ObjModule(module) => module.borrow().class,
             Value::ObjFiber(fiber) => fiber.borrow().class,
             Value::None => self.class_store.nil_class(),
         }
     }
 
+    /// Returns the interned string with the specified contents, which is kept alive for the
+    /// lifetime of the virtual machine.
+    #[track_caller]
     pub fn new_gc_obj_string(&mut self, data: &str) -> Gc<ObjString> {
-        let hash = {
-            let mut hasher = FnvHasher::new();
-            (*data).hash(&mut hasher);
-            hasher.finish()
-        };
-        let key = (hash, data);
-        if let Some(string) = self.string_store.get(key) {
-            return string.as_gc();
-        }
-        let string = Root::new(ObjString::new(
-            self.string_class.as_ref().expect("Expected Root.").as_gc(),
-            data,
-            hash,
-        ));
-        let ret = string.as_gc();
-        self.string_store.insert(string);
-        ret
+        let hash = string_hash(data);
+        if let Some(string) = self.string_store.pin((hash, data)) {
+            return string;
+        }
+        let string = self.new_obj_string(data, hash);
+        self.string_store.insert(string.as_gc(), true);
+        string.as_gc()
+    }
+
+    /// Returns the interned string with the specified contents, which is collected once it's no
+    /// longer used. Strings longer than the intern limit are created afresh each time.
+    #[track_caller]
+    pub fn new_root_obj_string(&mut self, data: &str) -> Root<ObjString> {
+        let hash = string_hash(data);
+        if data.len() > self.options.intern_limit {
+            return self.new_obj_string(data, hash);
+        }
+        if let Some(string) = self.string_store.get((hash, data)) {
+            return string.as_root();
+        }
+        let string = self.new_obj_string(data, hash);
+        self.string_store.insert(string.as_gc(), false);
+        string
     }
 
+    /// Returns the string made of the specified byte range of another string. Substrings that
+    /// aren't too short share the other string's characters rather than copying them, though
+    /// those that are short enough are still interned.
+    #[track_caller]
+    pub(crate) fn new_root_obj_string_slice(
+        &mut self,
+        parent: Gc<ObjString>,
+        range: Range<usize>,
+    ) -> Root<ObjString> {
+        if range.start == 0 && range.end == parent.len() {
+            return parent.as_root();
+        }
+        let data = &parent.as_str()[range.clone()];
+        if data.len() < common::STRING_SLICE_LEN_MIN {
+            return self.new_root_obj_string(data);
+        }
+        let hash = string_hash(data);
+        let intern = data.len() <= self.options.intern_limit;
+        if intern {
+            if let Some(string) = self.string_store.get((hash, data)) {
+                return string.as_root();
+            }
+        }
+        let class = self.string_class.as_ref().expect("Expected Root.").as_gc();
+        let string = Root::new(ObjString::slice(class, parent, range, hash));
+        if intern {
+            self.string_store.insert(string.as_gc(), false);
+        }
+        string
+    }
+
+    /// Returns statistics describing the strings interned by the virtual machine.
+    pub fn string_stats(&self) -> StringStoreStats {
+        self.string_store.stats()
+    }
+
+    /// Removes the entries of collected strings from the store of interned strings, shrinking it
+    /// if possible.
+    pub fn shrink_string_store(&mut self) {
+        self.string_store.resize();
+    }
+
+    #[track_caller]
     pub fn new_root_obj_upvalue(&mut self, value: &mut Value) -> Root<RefCell<ObjUpvalue>> {
         Root::new(RefCell::new(ObjUpvalue::new(value)))
     }
 
+    #[track_caller]
     pub fn new_root_obj_function(
         &mut self,
         name: Gc<ObjString>,
@@ -304,6 +951,7 @@ impl Vm {
         ))
     }
 
+    #[track_caller]
     pub fn new_root_obj_native(
         &mut self,
         name: Gc<ObjString>,
@@ -312,6 +960,7 @@ impl Vm {
         Root::new(ObjNative::new(name, function, false))
     }
 
+    #[track_caller]
     pub fn new_root_obj_closure(
         &mut self,
         function: Gc<ObjFunction>,
@@ -324,6 +973,7 @@ impl Vm {
         Root::new(ObjClosure::new(function, upvalues, module))
     }
 
+    #[track_caller]
     pub fn new_root_obj_class(
         &mut self,
         name: Gc<ObjString>,
@@ -334,10 +984,12 @@ impl Vm {
         Root::new(ObjClass::new(name, metaclass, superclass, methods))
     }
 
+    #[track_caller]
     pub fn new_root_obj_instance(&mut self, class: Gc<ObjClass>) -> Root<RefCell<ObjInstance>> {
         Root::new(RefCell::new(ObjInstance::new(class)))
     }
 
+    #[track_caller]
     pub fn new_root_obj_bound_method<T: 'static + memory::GcManaged>(
         &mut self,
         receiver: Value,
@@ -346,6 +998,12 @@ impl Vm {
         Root::new(RefCell::new(ObjBoundMethod::new(receiver, method)))
     }
 
+    #[track_caller]
+    pub fn new_root_obj_partial(&mut self, callee: Value, args: Vec<Value>) -> Root<ObjPartial> {
+        Root::new(ObjPartial::new(callee, args))
+    }
+
+    #[track_caller]
     pub fn new_root_obj_string_iter(
         &mut self,
         string: Gc<ObjString>,
@@ -354,40 +1012,53 @@ impl Vm {
         Root::new(RefCell::new(ObjStringIter::new(class, string)))
     }
 
+    #[track_caller]
     pub fn new_root_obj_hash_map(&mut self) -> Root<RefCell<ObjHashMap>> {
         let class = self.class_store.hash_map_class();
         Root::new(RefCell::new(ObjHashMap::new(class)))
     }
 
+    #[track_caller]
+    pub fn new_root_obj_identity_map(&mut self) -> Root<RefCell<ObjIdentityMap>> {
+        let class = self.class_store.identity_map_class();
+        Root::new(RefCell::new(ObjIdentityMap::new(class)))
+    }
+
     pub fn new_root_obj_range(&mut self, begin: isize, end: isize) -> Root<ObjRange> {
-        self.build_range(begin, end).as_root()
+        self.build_range(begin, end, None).as_root()
     }
 
+    #[track_caller]
     pub fn new_root_obj_range_iter(&mut self, range: Gc<ObjRange>) -> Root<RefCell<ObjRangeIter>> {
         let class = self.class_store.range_iter_class();
         Root::new(RefCell::new(ObjRangeIter::new(class, range)))
     }
 
+    #[track_caller]
     pub fn new_root_obj_tuple(&mut self, elements: Vec<Value>) -> Root<ObjTuple> {
         let class = self.class_store.tuple_class();
         Root::new(ObjTuple::new(class, elements))
     }
 
+    #[track_caller]
     pub fn new_root_obj_tuple_iter(&mut self, tuple: Gc<ObjTuple>) -> Root<RefCell<ObjTupleIter>> {
         let class = self.class_store.tuple_iter_class();
         Root::new(RefCell::new(ObjTupleIter::new(class, tuple)))
     }
 
+    #[track_caller]
     pub fn new_root_obj_vec(&mut self) -> Root<RefCell<ObjVec>> {
         let class = self.class_store.vec_class();
         Root::new(RefCell::new(ObjVec::new(class)))
     }
 
+    #[track_caller]
     pub fn new_root_obj_vec_iter(&mut self, vec: Gc<RefCell<ObjVec>>) -> Root<RefCell<ObjVecIter>> {
         let class = self.class_store.vec_iter_class();
         Root::new(RefCell::new(ObjVecIter::new(class, vec)))
     }
 
+    #[track_caller]
     pub fn new_root_obj_module(
         &mut self,
         class: Gc<ObjClass>,
@@ -396,11 +1067,13 @@ impl Vm {
         Root::new(RefCell::new(ObjModule::new(class, path)))
     }
 
+    #[track_caller]
     pub fn new_root_obj_err(&mut self, context: Value) -> Root<RefCell<ObjInstance>> {
         let class = self.class_store.error_class();
         self.new_root_obj_err_with_class(class, context)
     }
 
+    #[track_caller]
     pub fn new_root_obj_stop_iter(&mut self) -> Root<RefCell<ObjInstance>> {
         let class = self.class_store.stop_iter_class();
         self.new_root_obj_err_with_class(class, Value::None)
@@ -408,10 +1081,9 @@ impl Vm {
 
     pub fn reset(&mut self) {
         self.reset_stack();
-        self.chunks = self.core_chunks.clone();
         self.modules.retain(|&k, _| k.as_str() == "main");
         self.active_module = self.module("main");
-        self.active_module.borrow_mut().attributes = object::new_obj_string_value_map();
+        self.active_module.borrow_mut().clear_attributes();
         self.init_built_in_globals("main");
     }
 
@@ -423,6 +1095,7 @@ impl Vm {
         self.active_fiber().unchecked_native_frame_slot(index)
     }
 
+    #[track_caller]
     pub(crate) fn new_root_obj_fiber(
         &mut self,
         closure: Gc<ObjClosure>,
@@ -431,6 +1104,31 @@ impl Vm {
         Root::new(RefCell::new(ObjFiber::new(class, closure)))
     }
 
+    #[track_caller]
+    fn new_obj_string(&mut self, data: &str, hash: u64) -> Root<ObjString> {
+        let class = self.string_class.as_ref().expect("Expected Root.").as_gc();
+        Root::new(ObjString::new(class, data, hash))
+    }
+
+    fn new_root_obj_scheduler_fiber(&mut self) -> Root<RefCell<ObjFiber>> {
+        if self.scheduler.is_none() {
+            let mut chunk = Chunk::new();
+            chunk.write(OpCode::Return as u8, 0);
+            let chunk = self.add_chunk(chunk, "main");
+            let name = self.new_gc_obj_string("scheduler");
+            let module_path = self.new_gc_obj_string("main");
+            let function = self.new_root_obj_function(name, 1, 0, chunk.as_gc(), module_path);
+            let module = self.module("main");
+            self.scheduler = Some(self.new_root_obj_closure(function.as_gc(), module));
+        }
+        let closure = self
+            .scheduler
+            .as_ref()
+            .expect("Expected ObjClosure.")
+            .as_gc();
+        self.new_root_obj_fiber(closure)
+    }
+
     pub(crate) fn module(&mut self, path: &str) -> Gc<RefCell<ObjModule>> {
         let path = self.new_gc_obj_string(path);
         if let Some(module) = self.modules.get(&path) {
@@ -453,6 +1151,68 @@ impl Vm {
         self.active_fiber_mut().stack.push(value)
     }
 
+    /// Returns whether there's space on the stack for the values any one instruction pushes.
+    fn has_instruction_slots(&self) -> bool {
+        let stack = &self.active_fiber().stack;
+        stack.capacity() - stack.len() >= common::INSTRUCTION_STACK_SLOTS
+    }
+
+    /// Makes space for more values when a frame has pushed more temporaries than were reserved
+    /// for it when it was called. The stack is never grown past its maximum size, so a frame
+    /// that needs more space than is left there raises a stack overflow instead.
+    #[cold]
+    #[inline(never)]
+    fn grow_stack(&mut self) -> Result<(), Error> {
+        let stack_size = self.options.stack_size;
+        let additional = common::FRAME_STACK_SLOTS
+            .min(stack_size.saturating_sub(self.stack_size()))
+            .max(common::INSTRUCTION_STACK_SLOTS);
+        if !self
+            .active_fiber_mut()
+            .reserve_stack(additional, stack_size)
+        {
+            return Err(Self::stack_overflow_error());
+        }
+        Ok(())
+    }
+
+    /// Makes space for the values the next instruction pushes and passes it to any hooks that
+    /// are enabled, returning false if there isn't any space and the resulting stack overflow has
+    /// been handled instead.
+    #[inline(never)]
+    fn prepare_instruction(&mut self) -> Result<bool, Error> {
+        if !self.has_instruction_slots() {
+            if let Err(error) = self.grow_stack() {
+                self.try_handle_error(error)?;
+                return Ok(false);
+            }
+        }
+        if !self.hooks_enabled {
+            return Ok(true);
+        }
+        if self.tracing {
+            self.trace_instruction()?;
+        }
+        if self.profiler.is_some() {
+            self.record_profile_sample();
+        }
+        if self.coverage.is_some() {
+            self.record_coverage();
+        }
+        if self.opcode_counter.is_some() {
+            self.record_opcode();
+        }
+        if let Some(count) = self.instruction_count.as_mut() {
+            *count += 1;
+        }
+        Ok(true)
+    }
+
+    #[cold]
+    fn stack_overflow_error() -> Error {
+        error!(ErrorKind::IndexError, "Stack overflow.").with_code(ErrorCode::StackOverflow)
+    }
+
     pub(crate) fn pop(&mut self) -> Value {
         self.active_fiber_mut()
             .stack
@@ -460,11 +1220,32 @@ impl Vm {
             .expect("Expected Value.")
     }
 
-    pub(crate) fn add_chunk(&mut self, chunk: Chunk) -> Gc<Chunk> {
-        let root = Root::new(chunk);
-        let ret = root.as_gc();
-        self.chunks.push(root);
-        ret
+    pub(crate) fn warnings_enabled(&self) -> bool {
+        !self.enabled_warnings.is_empty()
+    }
+
+    /// Keeps the warnings that have been enabled. Shadowed variables are always reported in
+    /// modules using `#![strict]`.
+    pub(crate) fn add_warnings(&mut self, warnings: Vec<Warning>, is_strict: bool) {
+        let enabled_warnings = &self.enabled_warnings;
+        let warnings = warnings.into_iter().filter(|w| {
+            enabled_warnings.contains(&w.kind())
+                || (is_strict && w.kind() == WarningKind::ShadowedVariable)
+        });
+        match self.warning_callback.as_mut() {
+            Some(callback) => warnings.for_each(|w| callback(&w)),
+            None => self.warnings.extend(warnings),
+        }
+    }
+
+    /// Allocates a chunk of bytecode. The chunk is kept alive by the functions that reference it,
+    /// so the returned root need only be held until the chunk has been given to a function.
+    pub(crate) fn add_chunk(&mut self, chunk: Chunk, module_path: &str) -> Root<Chunk> {
+        let chunk = Root::new(chunk);
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.add_chunk(chunk.as_gc(), module_path);
+        }
+        chunk
     }
 
     pub(crate) fn load_fiber(
@@ -495,10 +1276,59 @@ impl Vm {
             self.active_fiber_mut().current_frame_mut().unwrap().ip = self.ip;
         }
 
+        self.suspended_fibers.retain(|f| f.as_gc() != fiber);
         self.unsafe_fiber = (*fiber).as_ptr();
         let caller = self.fiber.replace(fiber.as_root());
         self.active_fiber_mut().caller = caller.map(|p| p.as_gc());
+        self.enter_fiber(arg);
+        Ok(())
+    }
+
+    pub(crate) fn transfer_fiber(
+        &mut self,
+        fiber: Gc<RefCell<ObjFiber>>,
+        arg: Option<Value>,
+    ) -> Result<(), Error> {
+        if fiber.borrow().has_finished() {
+            return Err(error!(
+                ErrorKind::RuntimeError,
+                "Cannot transfer to a finished fiber."
+            ));
+        }
+        let mut current = self.fiber.as_ref().map(|f| f.as_gc());
+        while let Some(caller) = current {
+            if caller == fiber {
+                return Err(error!(
+                    ErrorKind::RuntimeError,
+                    "Cannot transfer to a fiber that has already been called."
+                ));
+            }
+            current = caller.borrow().caller;
+        }
+
+        // Pop the arg and target fiber off the current fiber's stack, leaving a slot for the value
+        // the current fiber receives when it's resumed.
+        if arg.is_some() {
+            self.pop();
+        }
+        self.pop();
+        self.poke(0, Value::None);
+        self.active_fiber_mut().current_frame_mut().unwrap().ip = self.ip;
+
+        // The target fiber takes over the current fiber's caller, and the current fiber is kept
+        // alive until it's resumed, since closures may still refer to values on its stack.
+        let caller = self.active_fiber_mut().caller.take();
+        self.suspended_fibers.retain(|f| f.as_gc() != fiber);
+        self.unsafe_fiber = (*fiber).as_ptr();
+        let previous = self.fiber.replace(fiber.as_root());
+        self.suspended_fibers
+            .push(previous.expect("Expected ObjFiber."));
+        self.active_fiber_mut().caller = caller;
+        self.enter_fiber(arg);
+        Ok(())
+    }
 
+    fn enter_fiber(&mut self, arg: Option<Value>) {
         if self.active_fiber().is_new() {
             let closure = self.active_fiber().frames[0].closure;
             self.push(Value::ObjClosure(closure));
@@ -510,7 +1340,6 @@ impl Vm {
         }
 
         self.load_frame();
-        Ok(())
     }
 
     pub(crate) fn unload_fiber(&mut self, arg: Option<Value>) -> Result<(), Error> {
@@ -525,6 +1354,13 @@ impl Vm {
             let mut current = self.fiber.replace(caller.as_root());
             self.unsafe_fiber = (*caller).as_ptr();
             current.as_mut().unwrap().borrow_mut().caller = None;
+        } else if self.reentrant_depth > 0 {
+            // The fiber was started by a native calling back into script code, which has to run
+            // to completion before the native can carry on.
+            return Err(error!(
+                ErrorKind::RuntimeError,
+                "Cannot yield across a native call."
+            ));
         } else {
             return Err(error!(
                 ErrorKind::RuntimeError,
@@ -536,134 +1372,716 @@ impl Vm {
         Ok(())
     }
 
-    fn run(&mut self) -> Result<Value, Error> {
-        debug_assert!(self.modules.len() == 1);
+    pub(crate) fn current_fiber(&self) -> Gc<RefCell<ObjFiber>> {
+        self.fiber.as_ref().expect("Expected ObjFiber.").as_gc()
+    }
 
-        loop {
-            if cfg!(feature = "debug_trace") {
-                println!("          {}", self.active_fiber().stack);
-                let offset = self.active_chunk.code_offset(self.ip);
-                debug::disassemble_instruction(&self.active_chunk, offset);
+    pub(crate) fn schedule_fiber(&mut self, fiber: Gc<RefCell<ObjFiber>>) -> Result<(), Error> {
+        {
+            let borrowed_fiber = fiber.borrow();
+            if borrowed_fiber.has_finished() {
+                return Err(error!(
+                    ErrorKind::RuntimeError,
+                    "Cannot schedule a finished fiber."
+                ));
+            }
+            if borrowed_fiber.is_new() && borrowed_fiber.call_arity != 1 {
+                return Err(error!(
+                    ErrorKind::ValueError,
+                    "Cannot schedule a fiber that expects an argument."
+                ));
             }
-            let byte = self.read_byte();
+        }
+        if !self.scheduled_fibers.iter().any(|f| f.as_gc() == fiber) {
+            self.scheduled_fibers.push_back(fiber.as_root());
+        }
+        Ok(())
+    }
 
-            match byte {
-                byte if byte == OpCode::Constant as u8 => {
-                    let constant = self.read_constant();
-                    self.push(constant);
-                }
-                byte if byte == OpCode::Nil as u8 => self.push(Value::None),
-                byte if byte == OpCode::True as u8 => self.push(Value::Boolean(true)),
-                byte if byte == OpCode::False as u8 => self.push(Value::Boolean(false)),
-                byte if byte == OpCode::Pop as u8 => {
-                    self.pop();
-                }
-                byte if byte == OpCode::CopyTop as u8 => {
-                    let top = self.peek(0);
-                    self.push(top);
-                }
-                byte if byte == OpCode::GetLocal as u8 => self.get_local_impl(),
-                byte if byte == OpCode::SetLocal as u8 => self.set_local_impl(),
-                byte if byte == OpCode::GetGlobal as u8 => self.get_global_impl()?,
-                byte if byte == OpCode::DefineGlobal as u8 => self.define_global_impl(),
-                byte if byte == OpCode::SetGlobal as u8 => self.set_global_impl()?,
-                byte if byte == OpCode::GetUpvalue as u8 => self.get_upvalue_impl(),
-                byte if byte == OpCode::SetUpvalue as u8 => self.set_upvalue_impl(),
-                byte if byte == OpCode::GetProperty as u8 => self.get_property_impl()?,
-                byte if byte == OpCode::SetProperty as u8 => self.set_property_impl()?,
-                byte if byte == OpCode::GetClass as u8 => self.get_class_impl(),
-                byte if byte == OpCode::GetSuper as u8 => self.get_super_impl()?,
-                byte if byte == OpCode::Equal as u8 => self.equal_impl(),
-                byte if byte == OpCode::Greater as u8 => {
-                    self.binary_op_impl(|a, b| Value::Boolean(a > b))?;
+    pub(crate) fn suspend_fiber(&mut self) -> Result<(), Error> {
+        self.check_suspendable()?;
+        // The fiber receives nil when it's next resumed.
+        self.poke(0, Value::None);
+        self.unload_fiber(None)
+    }
+
+    /// Calls the method with the specified name on the receiver, running it to completion in a
+    /// fiber of its own, and returns its result.
+    pub(crate) fn call_method(
+        &mut self,
+        receiver: Value,
+        name: Gc<ObjString>,
+        args: &[Value],
+    ) -> Result<Value, Error> {
+        let class = self.get_class(receiver);
+        match class.methods.get(&name).copied() {
+            Some(Value::ObjClosure(closure)) => self.run_closure(closure, receiver, args),
+            Some(Value::ObjNative(native)) if !native.manages_stack => {
+                self.run_native(native, receiver, args)
+            }
+            None if class.methods.contains_key(&self.method_missing_string) => {
+                let args = self.new_root_obj_tuple(args.to_vec());
+                let args = [Value::ObjString(name), Value::ObjTuple(args.as_gc())];
+                self.call_method(receiver, self.method_missing_string, &args)
+            }
+            _ => Err(error!(
+                ErrorKind::AttributeError,
+                "Undefined property '{}'.", *name
+            )),
+        }
+    }
+
+    /// Calls the method or callable field with the specified name on the receiver, as invoking it
+    /// by name in script would. This is how `Object.send` calls methods chosen at runtime.
+    pub(crate) fn send(
+        &mut self,
+        receiver: Value,
+        name: Gc<ObjString>,
+        args: &[Value],
+    ) -> Result<Value, Error> {
+        let name = self.new_root_obj_string(name.as_str());
+        let name = name.as_gc();
+        self.check_private_access(receiver, name)?;
+        self.load_if_lazy(receiver)?;
+        let property = match receiver {
+            Value::ObjInstance(instance) => instance.borrow().fields.get(&name).copied(),
+            Value::ObjClass(class) => class.static_field(name),
+            Value::ObjModule(module) if module.borrow().is_exported(name) => {
+                module.borrow().attribute(name)
+            }
+            _ => None,
+        };
+        match property {
+            Some(callee) => self.call_value_reentrant(callee, args),
+            None => self.call_method(receiver, name, args),
+        }
+    }
+
+    /// Returns the value of the property with the specified name on the receiver, as a property
+    /// access in script would. Methods are returned bound to the receiver.
+    pub(crate) fn get_property(
+        &mut self,
+        receiver: Value,
+        name: Gc<ObjString>,
+    ) -> Result<Value, Error> {
+        let name = self.interned_property_name(name)?;
+        self.property(receiver, name)
+    }
+
+    /// Assigns the value to the property with the specified name on the receiver, as a property
+    /// assignment in script would.
+    pub(crate) fn set_property(
+        &mut self,
+        receiver: Value,
+        name: Gc<ObjString>,
+        value: Value,
+    ) -> Result<(), Error> {
+        let name = self.new_root_obj_string(name.as_str());
+        self.store_property(receiver, name.as_gc(), value)
+    }
+
+    /// Returns whether reading the property with the specified name on the receiver would succeed.
+    pub(crate) fn has_property(&self, receiver: Value, name: Gc<ObjString>) -> bool {
+        let name = match self.interned_property_name(name) {
+            Ok(name) => name,
+            Err(_) => return false,
+        };
+        if self.check_private_access(receiver, name).is_err() {
+            return false;
+        }
+        if let Value::ObjModule(module) = receiver {
+            let module = module.borrow();
+            if module.attribute(name).is_some() {
+                return module.is_exported(name);
+            }
+        }
+        self.has_method(receiver, name)
+    }
+
+    fn interned_property_name(&self, name: Gc<ObjString>) -> Result<Gc<ObjString>, Error> {
+        // Property names are interned, so a name that isn't interned can't refer to anything.
+        self.string_store
+            .get((name.hash, name.as_str()))
+            .ok_or_else(|| error!(ErrorKind::AttributeError, "Undefined property '{}'.", *name))
+    }
+
+    /// Calls the specified function or bound method with the specified arguments and returns its
+    /// result. This is how natives call back into script code: closures are run to completion in
+    /// a fiber of their own, and the calling fiber's instruction pointer and exception state are
+    /// restored afterwards, so the native may carry on using the stack as before.
+    ///
+    /// If the callee raises an exception that it doesn't handle, the error is returned, and
+    /// returning it from the native re-raises the original exception in the calling fiber. The
+    /// callee can't yield or suspend, since there's no fiber to return to until it's finished.
+    pub fn call_value_reentrant(&mut self, callee: Value, args: &[Value]) -> Result<Value, Error> {
+        match callee {
+            Value::ObjClosure(closure) => self.run_closure(closure, callee, args),
+            Value::ObjNative(native) if !native.manages_stack => {
+                self.run_native(native, callee, args)
+            }
+            Value::ObjBoundMethod(bound) => {
+                let (method, receiver) = {
+                    let bound = bound.borrow();
+                    (bound.method, bound.receiver)
+                };
+                self.run_closure(method, receiver, args)
+            }
+            Value::ObjBoundNative(bound) => {
+                let (method, receiver) = {
+                    let bound = bound.borrow();
+                    (bound.method, bound.receiver)
+                };
+                if method.manages_stack {
+                    return Err(error!(
+                        ErrorKind::TypeError,
+                        "Can only call functions and methods."
+                    ));
                 }
-                byte if byte == OpCode::Less as u8 => {
-                    self.binary_op_impl(|a, b| Value::Boolean(a < b))?;
+                self.run_native(method, receiver, args)
+            }
+            Value::ObjPartial(partial) => {
+                let args: Vec<_> = partial.args.iter().chain(args.iter()).copied().collect();
+                self.call_value_reentrant(partial.callee, &args)
+            }
+            Value::ObjInstance(instance)
+                if instance
+                    .borrow()
+                    .class
+                    .methods
+                    .contains_key(&self.call_string) =>
+            {
+                self.call_method(callee, self.call_string, args)
+            }
+            _ => Err(error!(
+                ErrorKind::TypeError,
+                "Can only call functions and methods."
+            )),
+        }
+    }
+
+    fn run_closure(
+        &mut self,
+        closure: Gc<ObjClosure>,
+        receiver: Value,
+        args: &[Value],
+    ) -> Result<Value, Error> {
+        let arity = closure.function.arity - 1;
+        if arity != args.len() {
+            return Err(error!(
+                ErrorKind::TypeError,
+                "Expected {} arguments but found {}.",
+                arity,
+                args.len()
+            )
+            .with_code(ErrorCode::ArityMismatch));
+        }
+        if self.reentrant_depth >= common::REENTRANT_DEPTH_MAX {
+            return Err(error!(ErrorKind::IndexError, "Stack overflow.")
+                .with_code(ErrorCode::StackOverflow));
+        }
+
+        let fiber = self.new_root_obj_fiber(closure);
+        let prev_ip = self.ip;
+        let prev_unsafe_fiber = self.unsafe_fiber;
+        let prev_handling_exception = self.handling_exception;
+        self.unsafe_fiber = (*fiber).as_ptr();
+        let prev_fiber = self.fiber.replace(fiber.clone());
+        self.push(receiver);
+        for &arg in args {
+            self.push(arg);
+        }
+        self.load_frame();
+        self.reentrant_depth += 1;
+        let result = self.run();
+        self.reentrant_depth -= 1;
+        if result.is_err() {
+            // An unhandled exception is left on the top of the fiber's stack.
+            self.failed_method_fiber = Some(fiber);
+        }
+
+        self.fiber = prev_fiber;
+        self.unsafe_fiber = prev_unsafe_fiber;
+        self.handling_exception = prev_handling_exception;
+        // The closure may be called from outside the interpreter loop once the previous fiber has
+        // finished, in which case there's no frame to restore.
+        let resumable = self
+            .fiber
+            .as_ref()
+            .is_some_and(|f| !f.borrow().has_finished());
+        if resumable {
+            self.load_frame();
+        }
+        self.ip = prev_ip;
+        result
+    }
+
+    /// Calls the native on the current fiber's stack, as though it had been invoked from the
+    /// instruction currently executing.
+    fn run_native(
+        &mut self,
+        native: Gc<ObjNative>,
+        receiver: Value,
+        args: &[Value],
+    ) -> Result<Value, Error> {
+        let stack_size = self.options.stack_size;
+        if !self
+            .active_fiber_mut()
+            .reserve_stack(args.len() + 1, stack_size)
+        {
+            return Err(Self::stack_overflow_error());
+        }
+        self.push(receiver);
+        for &arg in args {
+            self.push(arg);
+        }
+        let prev_arity = self.active_fiber_mut().take_native_arity();
+        self.active_fiber_mut().set_native_arity(args.len());
+        let result = (native.function)(self, args.len());
+        self.active_fiber_mut().take_native_arity();
+        if let Some(arity) = prev_arity {
+            self.active_fiber_mut().set_native_arity(arity);
+        }
+        self.discard(args.len() + 1);
+        result
+    }
+
+    /// Returns the value to use as the specified key of a HashMap, along with the hash returned by
+    /// the key's `hash` method if it's an instance. Instances are replaced by the instance already
+    /// used as a key that they're equal to according to their `eq` method, if there is one.
+    pub(crate) fn hash_map_key(
+        &mut self,
+        hash_map: Gc<RefCell<ObjHashMap>>,
+        key: Value,
+    ) -> Result<(Value, Option<u64>), Error> {
+        let hash_name = self.new_gc_obj_string("hash");
+        let eq_name = self.new_gc_obj_string("eq");
+        let hashable = match key {
+            Value::ObjInstance(instance) => {
+                let class = instance.borrow().class;
+                class.methods.contains_key(&hash_name) && class.methods.contains_key(&eq_name)
+            }
+            _ => key.has_hash(),
+        };
+        if !hashable {
+            return Err(error!(
+                ErrorKind::ValueError,
+                "Cannot use unhashable value '{}' as HashMap key.", key
+            ));
+        }
+        if key.try_as_obj_instance().is_none() {
+            return Ok((key, None));
+        }
+
+        let hash = match self.call_method(key, hash_name, &[])? {
+            Value::Number(n) => utils::hash_number(n),
+            value => {
+                return Err(error!(
+                    ErrorKind::TypeError,
+                    "Expected hash to return a number but found '{}'.", value
+                ));
+            }
+        };
+        let candidates = hash_map
+            .borrow()
+            .instance_keys
+            .get(&hash)
+            .cloned()
+            .unwrap_or_default();
+        for candidate in candidates {
+            if candidate == key || self.call_method(key, eq_name, &[candidate])?.into_bool() {
+                return Ok((candidate, Some(hash)));
+            }
+        }
+        Ok((key, Some(hash)))
+    }
+
+    /// Returns the text that `print` and string interpolation show for the specified value. An
+    /// instance whose class defines a `to_string` method is shown as that method's result, including
+    /// when it's inside a Vec, Tuple or HashMap, so custom printers should use this rather than
+    /// formatting values directly.
+    pub fn display_string(&mut self, value: Value) -> Result<String, Error> {
+        let mut text = String::new();
+        self.write_display_string(value, false, &mut Vec::new(), &mut text)?;
+        Ok(text)
+    }
+
+    /// Appends the display text of a value to `text`. Elements of collections are formatted as
+    /// the collections' own formatting would, with strings quoted, and collections that are
+    /// already being shown further up are elided.
+    fn write_display_string(
+        &mut self,
+        value: Value,
+        is_element: bool,
+        containers: &mut Vec<usize>,
+        text: &mut String,
+    ) -> Result<(), Error> {
+        let (open, close, entries, is_map) = match value {
+            Value::ObjInstance(_) => {
+                text.push_str(&self.instance_display_string(value)?);
+                return Ok(());
+            }
+            Value::ObjVec(vec) => ("[", "]", vec.borrow().elements.clone(), false),
+            Value::ObjTuple(tuple) => ("(", ")", tuple.elements.clone(), false),
+            Value::ObjHashMap(hash_map) => {
+                let entries = hash_map
+                    .borrow()
+                    .elements
+                    .iter()
+                    .flat_map(|(&k, &v)| [k, v])
+                    .collect();
+                ("{", "}", entries, true)
+            }
+            _ if is_element => {
+                write!(text, "{}", Repr(&value)).unwrap();
+                return Ok(());
+            }
+            _ => {
+                write!(text, "{}", value).unwrap();
+                return Ok(());
+            }
+        };
+        let address = value.address().expect("Expected object.");
+        if containers.contains(&address) {
+            write!(text, "{}...{}", open, close).unwrap();
+            return Ok(());
+        }
+        // The entries are rooted in case a `to_string` method removes them from the collection.
+        let entries = self.new_root_obj_tuple(entries);
+        containers.push(address);
+        text.push_str(open);
+        let step = if is_map { 2 } else { 1 };
+        for (i, pair) in entries.elements.chunks(step).enumerate() {
+            if i > 0 {
+                text.push_str(", ");
+            }
+            self.write_display_string(pair[0], true, containers, text)?;
+            if is_map {
+                text.push_str(": ");
+                self.write_display_string(pair[1], true, containers, text)?;
+            }
+        }
+        if matches!(value, Value::ObjTuple(_)) && entries.elements.len() == 1 {
+            text.push(',');
+        }
+        text.push_str(close);
+        containers.pop();
+        Ok(())
+    }
+
+    fn instance_display_string(&mut self, value: Value) -> Result<String, Error> {
+        let name = self.new_gc_obj_string("to_string");
+        let has_method = match value {
+            Value::ObjInstance(instance) => instance.borrow().class.methods.contains_key(&name),
+            _ => false,
+        };
+        if !has_method {
+            return Ok(format!("{}", value));
+        }
+        match self.call_method(value, name, &[])? {
+            Value::ObjString(string) => Ok(string.as_str().to_owned()),
+            result => Err(error!(
+                ErrorKind::TypeError,
+                "Expected to_string to return a String but found '{}'.", result
+            )),
+        }
+    }
+
+    /// Collects the values produced by iterating over the specified value into a new Vec, calling
+    /// the value's `iter` method and the resulting iterator's `next` method as a `for` loop would.
+    pub(crate) fn collect_iterable(
+        &mut self,
+        iterable: Value,
+    ) -> Result<Root<RefCell<ObjVec>>, Error> {
+        let collected = self.new_root_obj_vec();
+        match iterable {
+            Value::ObjVec(vec) => {
+                collected.borrow_mut().elements = vec.borrow().elements.clone();
+                return Ok(collected);
+            }
+            Value::ObjTuple(tuple) => {
+                collected.borrow_mut().elements = tuple.elements.clone();
+                return Ok(collected);
+            }
+            _ => {}
+        }
+
+        let iter = Root::new(RefCell::new(Value::None));
+        *iter.borrow_mut() = if self.has_method(iterable, self.iter_string) {
+            self.call_method(iterable, self.iter_string, &[])?
+        } else if self.has_method(iterable, self.next_string) {
+            iterable
+        } else {
+            return Err(not_iterable_error(iterable));
+        };
+        loop {
+            let next = self.call_method(*iter.borrow(), self.next_string, &[])?;
+            if self.is_stop_iter(next) {
+                return Ok(collected);
+            }
+            collected.borrow_mut().elements.push(next);
+        }
+    }
+
+    /// Returns the indices of the specified values in sorted order, ordering the values by the
+    /// result of the key function if one is given. The sort is stable.
+    pub(crate) fn sorted_indices(
+        &mut self,
+        values: Gc<RefCell<ObjVec>>,
+        key: Option<Value>,
+    ) -> Result<Vec<usize>, Error> {
+        let keys = match key {
+            Some(key) => {
+                let keys = self.new_root_obj_vec();
+                let len = values.borrow().elements.len();
+                for i in 0..len {
+                    let value = values.borrow().elements[i];
+                    let key = self.call_value_reentrant(key, &[value])?;
+                    keys.borrow_mut().elements.push(key);
                 }
-                byte if byte == OpCode::Add as u8 => self.add_impl()?,
-                byte if byte == OpCode::Subtract as u8 => {
-                    self.binary_op_impl(|a, b| Value::Number(a - b))?
+                keys
+            }
+            None => Root::from(values),
+        };
+        let mut indices = (0..keys.borrow().elements.len()).collect();
+        utils::merge_sort_by(&mut indices, |a, b| {
+            let (a, b) = {
+                let keys = keys.borrow();
+                (keys.elements[a], keys.elements[b])
+            };
+            self.less_than(a, b)
+        })?;
+        Ok(indices)
+    }
+
+    /// Returns whether the first value orders before the second. Numbers and strings have a
+    /// natural ordering and Vecs and Tuples are ordered lexicographically. Instances are ordered
+    /// by their `cmp` method, which should return a negative number, zero or a positive number, or
+    /// failing that by their `lt` method.
+    pub(crate) fn less_than(&mut self, a: Value, b: Value) -> Result<bool, Error> {
+        match (a, b) {
+            (Value::Number(a), Value::Number(b)) => Ok(a < b),
+            (Value::ObjString(a), Value::ObjString(b)) => Ok(a.as_str() < b.as_str()),
+            (Value::ObjVec(_), Value::ObjVec(_)) | (Value::ObjTuple(_), Value::ObjTuple(_)) => {
+                self.sequence_less_than(a, b)
+            }
+            (Value::ObjInstance(instance), _) => {
+                let cmp_name = self.new_gc_obj_string("cmp");
+                let lt_name = self.new_gc_obj_string("lt");
+                let class = instance.borrow().class;
+                if class.methods.contains_key(&cmp_name) {
+                    return match self.call_method(a, cmp_name, &[b])? {
+                        Value::Number(n) => Ok(n < 0.0),
+                        value => Err(error!(
+                            ErrorKind::TypeError,
+                            "Expected cmp to return a number but found '{}'.", value
+                        )),
+                    };
                 }
-                byte if byte == OpCode::Multiply as u8 => {
-                    self.binary_op_impl(|a, b| Value::Number(a * b))?
+                if class.methods.contains_key(&lt_name) {
+                    return Ok(self.call_method(a, lt_name, &[b])?.into_bool());
                 }
-                byte if byte == OpCode::Divide as u8 => {
-                    self.binary_op_impl(|a, b| Value::Number(a / b))?
+                Err(error!(
+                    ErrorKind::TypeError,
+                    "Cannot order '{}' and '{}'.", a, b
+                ))
+            }
+            _ => Err(error!(
+                ErrorKind::TypeError,
+                "Cannot order '{}' and '{}'.", a, b
+            )),
+        }
+    }
+
+    fn sequence_less_than(&mut self, a: Value, b: Value) -> Result<bool, Error> {
+        let element = |value: Value, index: usize| match value {
+            Value::ObjVec(vec) => vec.borrow().elements.get(index).copied(),
+            Value::ObjTuple(tuple) => tuple.elements.get(index).copied(),
+            _ => None,
+        };
+        let mut index = 0;
+        loop {
+            match (element(a, index), element(b, index)) {
+                (Some(x), Some(y)) => {
+                    if self.less_than(x, y)? {
+                        return Ok(true);
+                    }
+                    if self.less_than(y, x)? {
+                        return Ok(false);
+                    }
                 }
-                byte if byte == OpCode::BitwiseAnd as u8 => {
-                    self.binary_op_impl(|a, b| Value::Number(((a as i64) & (b as i64)) as f64))?;
+                (x, y) => return Ok(x.is_none() && y.is_some()),
+            }
+            index += 1;
+        }
+    }
+
+    fn check_suspendable(&self) -> Result<(), Error> {
+        let scheduler = self.scheduler_fiber.as_ref().map(|f| f.as_gc());
+        if scheduler.is_none() || self.active_fiber().caller != scheduler {
+            return Err(error!(
+                ErrorKind::RuntimeError,
+                "Cannot suspend a fiber that wasn't resumed by the scheduler."
+            ));
+        }
+        Ok(())
+    }
+
+    fn suspend_pending_call(&mut self, token: PendingToken) -> Result<(), Error> {
+        let fiber = self.current_fiber().as_root();
+        self.pending_fibers.insert(token, fiber);
+        self.unload_fiber(None)
+    }
+
+    fn raise_pending_error(&mut self, fiber: Gc<RefCell<ObjFiber>>) -> Result<(), Error> {
+        let index = self.pending_errors.iter().position(|(f, _)| *f == fiber);
+        if let Some(index) = index {
+            let (_, error) = self.pending_errors.remove(index);
+            return self.try_handle_error(error);
+        }
+        Ok(())
+    }
+
+    fn run(&mut self) -> Result<Value, Error> {
+        loop {
+            // Values are pushed without checking for space, so the space for them is made here.
+            if (self.hooks_enabled || !self.has_instruction_slots())
+                && !self.prepare_instruction()?
+            {
+                continue;
+            }
+            let opcode = self.read_opcode();
+
+            match opcode {
+                OpCode::Constant => {
+                    let constant = self.read_constant();
+                    self.push(constant);
                 }
-                byte if byte == OpCode::BitwiseOr as u8 => {
-                    self.binary_op_impl(|a, b| Value::Number(((a as i64) | (b as i64)) as f64))?;
+                OpCode::Nil => self.push(Value::None),
+                OpCode::True => self.push(Value::Boolean(true)),
+                OpCode::False => self.push(Value::Boolean(false)),
+                OpCode::Pop => {
+                    self.pop();
                 }
-                byte if byte == OpCode::BitwiseXor as u8 => {
-                    self.binary_op_impl(|a, b| Value::Number(((a as i64) ^ (b as i64)) as f64))?;
+                OpCode::CopyTop => {
+                    let top = self.peek(0);
+                    self.push(top);
                 }
-                byte if byte == OpCode::Modulo as u8 => {
-                    self.binary_op_impl(|a, b| Value::Number(a % b))?;
+                OpCode::CopyTopTwo => {
+                    let (second, top) = (self.peek(1), self.peek(0));
+                    self.push(second);
+                    self.push(top);
                 }
-                byte if byte == OpCode::LogicalNot as u8 => self.logical_not_impl(),
-                byte if byte == OpCode::BitwiseNot as u8 => self.bitwise_not_impl()?,
-                byte if byte == OpCode::BitShiftLeft as u8 => {
-                    self.binary_op_impl(|a, b| {
-                        Value::Number((a as i64).checked_shl(b as u32).unwrap_or_default() as f64)
-                    })?;
+                OpCode::GetLocal => self.get_local_impl(),
+                OpCode::SetLocal => self.set_local_impl(),
+                OpCode::GetGlobal => self.get_global_impl()?,
+                OpCode::DefineGlobal => self.define_global_impl(),
+                OpCode::SetGlobal => self.set_global_impl()?,
+                OpCode::GetUpvalue => self.get_upvalue_impl(),
+                OpCode::SetUpvalue => self.set_upvalue_impl(),
+                OpCode::GetProperty => self.get_property_impl()?,
+                OpCode::SetProperty => self.set_property_impl()?,
+                OpCode::GetClass => self.get_class_impl(),
+                OpCode::GetSuper => self.get_super_impl()?,
+                OpCode::Equal => self.equal_impl(),
+                OpCode::Greater => {
+                    self.binary_op_impl(|a, b| Value::Boolean(a > b))?;
                 }
-                byte if byte == OpCode::BitShiftRight as u8 => {
-                    self.binary_op_impl(|a, b| {
-                        Value::Number((a as i64).checked_shr(b as u32).unwrap_or_default() as f64)
-                    })?;
+                OpCode::Less => {
+                    self.binary_op_impl(|a, b| Value::Boolean(a < b))?;
                 }
-                byte if byte == OpCode::Negate as u8 => self.negate_impl()?,
-                byte if byte == OpCode::GetItem as u8 => self.get_item_impl()?,
-                byte if byte == OpCode::SetItem as u8 => self.set_item_impl()?,
-                byte if byte == OpCode::FormatString as u8 => self.format_string_impl(),
-                byte if byte == OpCode::BuildHashMap as u8 => self.build_hash_map_impl()?,
-                byte if byte == OpCode::BuildRange as u8 => self.build_range_impl()?,
-                byte if byte == OpCode::BuildString as u8 => self.build_string_impl(),
-                byte if byte == OpCode::BuildTuple as u8 => self.build_tuple_impl(),
-                byte if byte == OpCode::BuildVec as u8 => self.build_vec_impl(),
-                byte if byte == OpCode::IterNext as u8 => self.iter_next_impl()?,
-                byte if byte == OpCode::Jump as u8 => self.jump_impl(),
-                byte if byte == OpCode::JumpIfFalse as u8 => self.jump_if_false_impl(),
-                byte if byte == OpCode::JumpIfStopIter as u8 => self.jump_if_stop_iter(),
-                byte if byte == OpCode::Loop as u8 => self.loop_impl(),
-                byte if byte == OpCode::JumpFinally as u8 => self.jump_finally_impl(),
-                byte if byte == OpCode::EndFinally as u8 => self.end_finally_impl()?,
-                byte if byte == OpCode::PushExcHandler as u8 => self.push_exc_handler_impl(),
-                byte if byte == OpCode::PopExcHandler as u8 => self.pop_exc_handler_impl(),
-                byte if byte == OpCode::Throw as u8 => self.throw_impl()?,
-                byte if byte == OpCode::Call as u8 => self.call_impl()?,
-                byte if byte == OpCode::Construct as u8 => self.construct_impl(),
-                byte if byte == OpCode::Invoke as u8 => self.invoke_impl()?,
-                byte if byte == OpCode::SuperInvoke as u8 => self.super_invoke_impl()?,
-                byte if byte == OpCode::Closure as u8 => self.closure_impl(),
-                byte if byte == OpCode::CloseUpvalue as u8 => self.close_upvalue_impl(),
-                byte if byte == OpCode::Return as u8 => {
+                OpCode::Is => self.is_impl()?,
+                OpCode::Add => self.add_impl()?,
+                OpCode::Subtract => self.arithmetic_op_impl(|a, b| a - b)?,
+                OpCode::Multiply => self.arithmetic_op_impl(|a, b| a * b)?,
+                OpCode::Divide => self.arithmetic_op_impl(|a, b| a / b)?,
+                OpCode::BitwiseAnd => self.bitwise_op_impl(|a, b| Some(a & b))?,
+                OpCode::BitwiseOr => self.bitwise_op_impl(|a, b| Some(a | b))?,
+                OpCode::BitwiseXor => self.bitwise_op_impl(|a, b| Some(a ^ b))?,
+                OpCode::Modulo => self.arithmetic_op_impl(|a, b| a % b)?,
+                OpCode::LogicalNot => self.logical_not_impl(),
+                OpCode::BitwiseNot => self.bitwise_not_impl()?,
+                OpCode::BitShiftLeft => self.bitwise_op_impl(|a, b| {
+                    let shift = u32::try_from(b).ok()?;
+                    Some(a.checked_shl(shift).unwrap_or(0))
+                })?,
+                OpCode::BitShiftRight => self.bitwise_op_impl(|a, b| {
+                    let shift = u32::try_from(b).ok()?;
+                    Some(a.checked_shr(shift).unwrap_or(if a < 0 { -1 } else { 0 }))
+                })?,
+                OpCode::Negate => self.negate_impl()?,
+                OpCode::GetItem => self.get_item_impl()?,
+                OpCode::SetItem => self.set_item_impl()?,
+                OpCode::FormatString => self.format_string_impl()?,
+                OpCode::BuildHashMap => self.build_hash_map_impl()?,
+                OpCode::ExtendHashMap => self.extend_hash_map_impl()?,
+                OpCode::BuildRange => self.build_range_impl()?,
+                OpCode::BuildString => self.build_string_impl(),
+                OpCode::BuildTuple => self.build_tuple_impl(),
+                OpCode::BuildVec => self.build_vec_impl(),
+                OpCode::ExtendVec => self.extend_vec_impl(),
+                OpCode::Splice => self.splice_impl(),
+                OpCode::GetIter => self.get_iter_impl()?,
+                OpCode::IterNext => self.iter_next_impl()?,
+                OpCode::Jump => self.jump_impl(),
+                OpCode::JumpLong => self.jump_long_impl(),
+                OpCode::JumpIfFalse => self.jump_if_false_impl(),
+                OpCode::JumpIfStopIter => self.jump_if_stop_iter(),
+                OpCode::JumpIfNotInstance => self.jump_if_not_instance_impl()?,
+                OpCode::Loop => self.loop_impl(),
+                OpCode::LoopLong => self.loop_long_impl(),
+                OpCode::JumpFinally => self.jump_finally_impl(),
+                OpCode::EndFinally => self.end_finally_impl()?,
+                OpCode::PushExcHandler => self.push_exc_handler_impl(),
+                OpCode::PushExcHandlerLong => self.push_exc_handler_long_impl(),
+                OpCode::PopExcHandler => self.pop_exc_handler_impl(),
+                OpCode::Throw => self.throw_impl()?,
+                OpCode::Rethrow => self.handling_exception = true,
+                OpCode::Call => self.call_impl()?,
+                OpCode::CallSpread => self.call_spread_impl()?,
+                OpCode::CallNamed => self.call_named_impl()?,
+                OpCode::Construct => self.construct_impl(),
+                OpCode::Invoke => self.invoke_impl()?,
+                OpCode::InvokeSpread => self.invoke_spread_impl()?,
+                OpCode::InvokeNamed => self.invoke_named_impl()?,
+                OpCode::SuperInvoke => self.super_invoke_impl()?,
+                OpCode::Closure => self.closure_impl(),
+                OpCode::Generator => self.generator_impl(),
+                OpCode::CloseUpvalue => self.close_upvalue_impl(),
+                OpCode::Return => {
                     if let Some(value) = self.return_impl()? {
                         return Ok(value);
                     }
                 }
-                byte if byte == OpCode::DeclareClass as u8 => self.declare_class_impl(),
-                byte if byte == OpCode::DefineClass as u8 => self.define_class_impl(),
-                byte if byte == OpCode::Inherit as u8 => self.inherit_impl()?,
-                byte if byte == OpCode::Method as u8 => self.method_impl()?,
-                byte if byte == OpCode::StaticMethod as u8 => self.static_method_impl()?,
-                byte if byte == OpCode::StartImport as u8 => self.start_import_impl()?,
-                byte if byte == OpCode::FinishImport as u8 => self.finish_import_impl(),
-                _ => {
-                    if cfg!(any(debug_assertions, feature = "safe_vm_opcodes")) {
-                        panic!("Unknown opcode {}", byte);
-                    } else {
-                        unsafe {
-                            hint::unreachable_unchecked();
-                        }
-                    }
-                }
+                OpCode::Yield => self.yield_impl()?,
+                OpCode::DeclareClass => self.declare_class_impl(),
+                OpCode::DefineClass => self.define_class_impl(),
+                OpCode::Inherit => self.inherit_impl()?,
+                OpCode::Implements => self.implements_impl()?,
+                OpCode::Mixin => self.mixin_impl()?,
+                OpCode::Method => self.method_impl()?,
+                OpCode::StaticMethod => self.static_method_impl()?,
+                OpCode::StaticField => self.static_field_impl(),
+                OpCode::StartImport | OpCode::LazyImport => self.start_import_impl(opcode)?,
+                OpCode::FinishImport => self.finish_import_impl(),
             }
         }
     }
 
+    fn read_opcode(&mut self) -> OpCode {
+        let byte = self.read_byte();
+        if cfg!(any(debug_assertions, feature = "safe_vm_opcodes")) {
+            OpCode::from(byte)
+        } else {
+            // # Safety
+            // The compiler only ever emits valid opcodes, so the byte is always in range.
+            unsafe { OpCode::from_unchecked(byte) }
+        }
+    }
+
+    fn skip_opcode(&mut self) {
+        self.ip = unsafe { self.ip.offset(1) };
+    }
+
     fn read_byte(&mut self) -> u8 {
         unsafe {
             let ret = *self.ip;
@@ -680,6 +2098,19 @@ impl Vm {
         }
     }
 
+    fn read_long(&mut self) -> u32 {
+        unsafe {
+            let ret = u32::from_ne_bytes([
+                *self.ip,
+                *self.ip.offset(1),
+                *self.ip.offset(2),
+                *self.ip.offset(3),
+            ]);
+            self.ip = self.ip.offset(4);
+            ret
+        }
+    }
+
     fn read_constant(&mut self) -> Value {
         let index = self.read_short() as usize;
         self.active_chunk.constants[index]
@@ -705,46 +2136,37 @@ impl Vm {
     }
 
     fn get_global_impl(&mut self) -> Result<(), Error> {
-        let name = self.read_string();
-        let value = self
-            .active_module
-            .borrow()
-            .attributes
-            .get(&name)
-            .map(|&v| v);
+        let slot = self.read_short() as usize;
+        let value = self.active_module.borrow().slot_value(slot);
         if let Some(value) = value {
             self.push(value);
         } else {
-            let err = error!(ErrorKind::NameError, "Undefined variable '{}'.", *name);
+            let name = self.active_module.borrow().slot_name(slot);
+            let err = error!(ErrorKind::NameError, "Undefined variable '{}'.", *name)
+                .with_code(ErrorCode::UndefinedVariable);
             self.try_handle_error(err)?;
         }
         Ok(())
     }
 
     fn define_global_impl(&mut self) {
-        let name = self.read_string();
+        let slot = self.read_short() as usize;
         let value = self.peek(0);
-        self.active_module
-            .borrow_mut()
-            .attributes
-            .insert(name, value);
+        self.active_module.borrow_mut().set_slot_value(slot, value);
         self.pop();
     }
 
     fn set_global_impl(&mut self) -> Result<(), Error> {
-        let name = self.read_string();
+        let slot = self.read_short() as usize;
         let value = self.peek(0);
-        let global_is_undefined = {
-            let globals = &mut self.active_module.borrow_mut().attributes;
-            let prev = globals.insert(name, value);
-            if prev.is_none() {
-                globals.remove(&name);
-            }
-            prev.is_none()
-        };
+        let global_is_undefined = self.active_module.borrow().slot_value(slot).is_none();
         if global_is_undefined {
-            let err = error!(ErrorKind::NameError, "Undefined variable '{}'.", *name);
+            let name = self.active_module.borrow().slot_name(slot);
+            let err = error!(ErrorKind::NameError, "Undefined variable '{}'.", *name)
+                .with_code(ErrorCode::UndefinedVariable);
             self.try_handle_error(err)?;
+        } else {
+            self.active_module.borrow_mut().set_slot_value(slot, value);
         }
         Ok(())
     }
@@ -774,53 +2196,134 @@ impl Vm {
 
     fn get_property_impl(&mut self) -> Result<(), Error> {
         let name = self.read_string();
-
-        if let Some(instance) = self.peek(0).try_as_obj_instance() {
-            let borrowed_instance = instance.borrow();
-            if let Some(&property) = borrowed_instance.fields.get(&name) {
-                self.pop();
-                self.push(property);
-                return Ok(());
-            }
-        }
-        if let Some(module) = self.peek(0).try_as_obj_module() {
-            if let Some(&property) = module.borrow().attributes.get(&name) {
+        match self.property(self.peek(0), name) {
+            Ok(property) => {
                 self.pop();
                 self.push(property);
-                return Ok(());
+                Ok(())
             }
+            Err(err) => self.try_handle_error(err),
         }
-
-        let class = self.get_class(self.peek(0));
-        self.bind_method(class, name)
     }
 
     fn set_property_impl(&mut self) -> Result<(), Error> {
-        if let Some(module) = self.peek(1).try_as_obj_module() {
-            let name = self.read_string();
-            let value = self.peek(0);
-            module.borrow_mut().attributes.insert(name, value);
-            self.pop();
-            self.pop();
-            self.push(value);
-            return Ok(());
-        }
-        let instance = if let Some(ptr) = self.peek(1).try_as_obj_instance() {
-            ptr
-        } else {
-            let err = error!(ErrorKind::AttributeError, "Only instances have fields.");
-            return self.try_handle_error(err);
-        };
         let name = self.read_string();
         let value = self.peek(0);
-        instance.borrow_mut().fields.insert(name, value);
-
+        if let Err(err) = self.store_property(self.peek(1), name, value) {
+            return self.try_handle_error(err);
+        }
         self.pop();
         self.pop();
         self.push(value);
         Ok(())
     }
 
+    /// Looks up the property with the specified interned name on the receiver, binding it if it's a
+    /// method.
+    fn property(&mut self, receiver: Value, name: Gc<ObjString>) -> Result<Value, Error> {
+        self.check_private_access(receiver, name)?;
+
+        if let Some(instance) = receiver.try_as_obj_instance() {
+            if let Some(&property) = instance.borrow().fields.get(&name) {
+                return Ok(property);
+            }
+        }
+        if let Some(class) = receiver.try_as_obj_class() {
+            if let Some(property) = class.static_field(name) {
+                return Ok(property);
+            }
+        }
+        if let Some(module) = receiver.try_as_obj_module() {
+            if module.borrow().lazy {
+                self.load_lazy_module(module)?;
+            }
+            let property = module.borrow().attribute(name);
+            if let Some(property) = property {
+                if !module.borrow().is_exported(name) {
+                    return Err(unexported_error(module, name));
+                }
+                return Ok(property);
+            }
+            // The attribute may yet be defined once the module has finished initialising.
+            let class = module.borrow().class;
+            if module.borrow().initialising && !class.methods.contains_key(&name) {
+                return Err(uninitialised_attribute_error(module, name));
+            }
+        }
+
+        let class = self.get_class(receiver);
+        self.bound_method(receiver, class, name)
+    }
+
+    /// Assigns the value to the property with the specified interned name on the receiver.
+    fn store_property(
+        &mut self,
+        receiver: Value,
+        name: Gc<ObjString>,
+        value: Value,
+    ) -> Result<(), Error> {
+        self.check_private_access(receiver, name)?;
+        match receiver {
+            Value::ObjModule(module) => {
+                if module.borrow().lazy {
+                    self.load_lazy_module(module)?;
+                }
+                if !module.borrow().is_exported(name) {
+                    return Err(unexported_error(module, name));
+                }
+                module.borrow_mut().set_attribute(name, value);
+                Ok(())
+            }
+            Value::ObjClass(class) => {
+                if !class.set_static_field(name, value) {
+                    return Err(error!(
+                        ErrorKind::AttributeError,
+                        "Undefined static field '{}'.", *name
+                    ));
+                }
+                Ok(())
+            }
+            Value::ObjInstance(instance) => {
+                instance.borrow_mut().fields.insert(name, value);
+                Ok(())
+            }
+            _ => Err(error!(
+                ErrorKind::AttributeError,
+                "Only instances have fields."
+            )),
+        }
+    }
+
+    /// Returns an error if the property is private and the running closure can't access the
+    /// receiver's private properties.
+    fn check_private_access(&self, receiver: Value, name: Gc<ObjString>) -> Result<(), Error> {
+        if !object::is_private_name(name.as_str()) || self.can_access_private(receiver) {
+            return Ok(());
+        }
+        Err(error!(
+            ErrorKind::AttributeError,
+            "Cannot access private property '{}' from outside its class.", *name
+        ))
+    }
+
+    /// Returns whether the running closure belongs to a class that the receiver is or is an
+    /// instance of, so can access the receiver's private properties.
+    pub(crate) fn can_access_private(&self, receiver: Value) -> bool {
+        let class = match receiver {
+            Value::ObjInstance(instance) => instance.borrow().class,
+            Value::ObjClass(class) => class,
+            _ => return true,
+        };
+        let context = self
+            .active_fiber()
+            .current_frame()
+            .unwrap()
+            .closure
+            .class
+            .get();
+        context.is_some_and(|context| object::class_derives(class, context))
+    }
+
     fn get_class_impl(&mut self) {
         let value = self.peek(0);
         match value {
@@ -839,7 +2342,8 @@ impl Vm {
         let name = self.read_string();
         let superclass = self.pop().try_as_obj_class().expect("Expected ObjClass.");
 
-        self.bind_method(superclass, name)
+        let class = self.super_dispatch_class(superclass, self.peek(0));
+        self.bind_method(class, name)
     }
 
     fn equal_impl(&mut self) {
@@ -865,18 +2369,111 @@ impl Vm {
         Ok(())
     }
 
+    fn arithmetic_op_impl(&mut self, op: fn(f64, f64) -> f64) -> Result<(), Error> {
+        let second_value = self.pop();
+        let first_value = self.pop();
+        let (first, second) = match (first_value, second_value) {
+            (Value::Number(first), Value::Number(second)) => (first, second),
+            _ => {
+                let err = error!(
+                    ErrorKind::TypeError,
+                    "Binary operands must both be numbers."
+                );
+                return self.try_handle_error(err);
+            }
+        };
+        self.push_arithmetic_result(first, second, op(first, second))
+    }
+
+    /// Pushes the result of an arithmetic operation, unless strict math is enabled and the result
+    /// is NaN or infinite when the operands are both finite, in which case a ValueError is raised.
+    fn push_arithmetic_result(&mut self, a: f64, b: f64, result: f64) -> Result<(), Error> {
+        if self.options.strict_math && !result.is_finite() && a.is_finite() && b.is_finite() {
+            let err = error!(
+                ErrorKind::ValueError,
+                "Arithmetic on '{}' and '{}' produced '{}'.",
+                Value::Number(a),
+                Value::Number(b),
+                Value::Number(result)
+            );
+            return self.try_handle_error(err);
+        }
+        self.push(Value::Number(result));
+        Ok(())
+    }
+
+    /// Applies a bitwise operator to the two numbers at the top of the stack. Both operands are
+    /// truncated to 64-bit two's complement integers, and must be finite and within range. Shifts
+    /// by 64 or more bits shift out every bit, and the operator returns `None` for negative shift
+    /// amounts.
+    fn bitwise_op_impl(&mut self, op: fn(i64, i64) -> Option<i64>) -> Result<(), Error> {
+        let (first, second) = match (self.peek(1), self.peek(0)) {
+            (Value::Number(first), Value::Number(second)) => (first, second),
+            _ => {
+                let err = error!(
+                    ErrorKind::TypeError,
+                    "Binary operands must both be numbers."
+                );
+                return self.try_handle_error(err);
+            }
+        };
+        let (first, second) = match (utils::to_i64(first), utils::to_i64(second)) {
+            (Some(first), Some(second)) => (first, second),
+            (converted, _) => {
+                let value = if converted.is_none() { first } else { second };
+                let err = error!(
+                    ErrorKind::ValueError,
+                    "Expected a number within the 64-bit integer range but found '{}'.",
+                    Value::Number(value)
+                );
+                return self.try_handle_error(err);
+            }
+        };
+        let result = match op(first, second) {
+            Some(result) => result,
+            None => {
+                let err = error!(
+                    ErrorKind::ValueError,
+                    "Expected a non-negative shift amount but found '{}'.", second
+                );
+                return self.try_handle_error(err);
+            }
+        };
+        self.pop();
+        self.pop();
+        self.push(Value::Number(result as f64));
+        Ok(())
+    }
+
+    fn is_impl(&mut self) -> Result<(), Error> {
+        let class = match self.peek(0) {
+            Value::ObjClass(class) => class,
+            value => {
+                let err = error!(
+                    ErrorKind::TypeError,
+                    "Expected a class on the right of 'is' but found '{}'.", value
+                );
+                return self.try_handle_error(err);
+            }
+        };
+        self.pop();
+        let value = self.pop();
+        let derives = object::class_derives(self.get_class(value), class);
+        self.push(Value::Boolean(derives));
+        Ok(())
+    }
+
     fn add_impl(&mut self) -> Result<(), Error> {
         let b = self.pop();
         let a = self.pop();
         match (a, b) {
             (Value::ObjString(a), Value::ObjString(b)) => {
-                let value =
-                    Value::ObjString(self.new_gc_obj_string(format!("{}{}", *a, *b).as_str()));
-                self.push(value)
+                let string = self.new_root_obj_string(format!("{}{}", *a, *b).as_str());
+                self.push(Value::ObjString(string.as_gc()))
             }
 
             (Value::Number(a), Value::Number(b)) => {
-                self.push(Value::Number(a + b));
+                self.push_arithmetic_result(a, b, a + b)?;
             }
 
             _ => {
@@ -898,7 +2495,16 @@ impl Vm {
     fn bitwise_not_impl(&mut self) -> Result<(), Error> {
         let value = self.pop();
         if let Some(num) = value.try_as_number() {
-            self.push(Value::Number(!(num as i64) as f64));
+            match utils::to_i64(num) {
+                Some(num) => self.push(Value::Number(!num as f64)),
+                None => {
+                    let err = error!(
+                        ErrorKind::ValueError,
+                        "Expected a number within the 64-bit integer range but found '{}'.", value
+                    );
+                    self.try_handle_error(err)?;
+                }
+            }
         } else {
             let err = error!(ErrorKind::TypeError, "Unary operand must be a number.");
             self.try_handle_error(err)?;
@@ -945,10 +2551,12 @@ impl Vm {
                 return self.try_handle_error(err);
             }
         };
-        let result = self.peek(1).try_as_bounded_index(
-            vec.borrow().elements.len() as isize,
-            "Vec",
-        );
+        if let Err(err) = vec.borrow().check_mutable() {
+            return self.try_handle_error(err);
+        }
+        let result = self
+            .peek(1)
+            .try_as_bounded_index(vec.borrow().elements.len() as isize, "Vec");
         let index = match result {
             Ok(i) => i,
             Err(err) => {
@@ -963,13 +2571,21 @@ impl Vm {
         Ok(())
     }
 
-    fn format_string_impl(&mut self) {
+    fn format_string_impl(&mut self) -> Result<(), Error> {
         let value = self.peek(0);
         if value.try_as_obj_string().is_some() {
-            return;
+            return Ok(());
         }
-        let obj = Value::ObjString(self.new_gc_obj_string(format!("{}", value).as_str()));
-        self.poke(0, obj);
+        match self.display_string(value) {
+            Ok(text) => {
+                let string = self.new_root_obj_string(text.as_str());
+                self.poke(0, Value::ObjString(string.as_gc()));
+            }
+            Err(e) => {
+                self.try_handle_error(e)?;
+            }
+        }
+        Ok(())
     }
 
     fn build_hash_map_impl(&mut self) -> Result<(), Error> {
@@ -985,6 +2601,18 @@ impl Vm {
         Ok(())
     }
 
+    fn extend_hash_map_impl(&mut self) -> Result<(), Error> {
+        let num_elements = self.read_byte() as usize;
+        let map = self
+            .peek(2 * num_elements)
+            .try_as_obj_hash_map()
+            .expect("Expected ObjHashMap.");
+        if let Err(e) = self.insert_hash_map_entries(map, num_elements) {
+            self.try_handle_error(e)?;
+        }
+        Ok(())
+    }
+
     fn build_range_impl(&mut self) -> Result<(), Error> {
         macro_rules! pop_integer {
             () => {
@@ -996,9 +2624,19 @@ impl Vm {
                 }
             };
         }
+        let step = if self.read_byte() != 0 {
+            let step = pop_integer!();
+            if step == 0 {
+                let err = error!(ErrorKind::ValueError, "Range step cannot be zero.");
+                return self.try_handle_error(err);
+            }
+            Some(step)
+        } else {
+            None
+        };
         let end = pop_integer!();
         let begin = pop_integer!();
-        let range = self.build_range(begin, end);
+        let range = self.build_range(begin, end, step);
         self.push(Value::ObjRange(range));
         Ok(())
     }
@@ -1010,21 +2648,21 @@ impl Vm {
         }
         let mut new_string = String::new();
         for pos in (0..num_operands).rev() {
-            new_string.push_str(self.peek(pos).try_as_obj_string().unwrap().as_str())
+            match self.peek(pos) {
+                Value::ObjString(string) => new_string.push_str(string.as_str()),
+                value => new_string.push_str(&format!("{}", value)),
+            }
         }
         self.discard(num_operands);
-        let value = Value::ObjString(self.new_gc_obj_string(new_string.as_str()));
-        self.push(value);
+        let string = self.new_root_obj_string(new_string.as_str());
+        self.push(Value::ObjString(string.as_gc()));
     }
 
     fn build_tuple_impl(&mut self) {
         let num_operands = self.read_byte() as usize;
         let begin = self.stack_size() - num_operands;
         let end = self.stack_size();
-        let elements = self.active_fiber().stack[begin..end]
-            .iter()
-            .copied()
-            .collect();
+        let elements = self.active_fiber().stack[begin..end].to_vec();
         let tuple = self.new_root_obj_tuple(elements);
         self.discard(num_operands);
         self.push(Value::ObjTuple(tuple.as_gc()));
@@ -1035,14 +2673,217 @@ impl Vm {
         let vec = self.new_root_obj_vec();
         let begin = self.stack_size() - num_operands;
         let end = self.stack_size();
-        vec.borrow_mut().elements = self.active_fiber().stack[begin..end]
-            .iter()
-            .copied()
-            .collect();
+        vec.borrow_mut().elements = self.active_fiber().stack[begin..end].to_vec();
         self.discard(num_operands);
         self.push(Value::ObjVec(vec.as_gc()));
     }
 
+    /// Appends the values on top of the stack to the Vec beneath them.
+    fn extend_vec_impl(&mut self) {
+        let num_operands = self.read_byte() as usize;
+        let vec = self
+            .peek(num_operands)
+            .try_as_obj_vec()
+            .expect("Expected ObjVec.");
+        let begin = self.stack_size() - num_operands;
+        let end = self.stack_size();
+        vec.borrow_mut()
+            .elements
+            .extend_from_slice(&self.active_fiber().stack[begin..end]);
+        self.discard(num_operands);
+    }
+
+    /// Appends the value on top of the stack, which was produced by the iterator beneath it, to
+    /// the Vec beneath that.
+    fn splice_impl(&mut self) {
+        let value = self.pop();
+        let vec = self.peek(1).try_as_obj_vec().expect("Expected ObjVec.");
+        vec.borrow_mut().elements.push(value);
+    }
+
+    fn unpack_spread_arguments(&mut self) -> Result<Option<usize>, Error> {
+        let args = self.pop().try_as_obj_vec().expect("Expected ObjVec.");
+        let arg_count = args.borrow().elements.len();
+        if arg_count > 255 {
+            let err = error!(
+                ErrorKind::TypeError,
+                "Cannot have more than 255 arguments but found {}.", arg_count
+            );
+            self.try_handle_error(err)?;
+            return Ok(None);
+        }
+        let stack_size = self.options.stack_size;
+        if !self.active_fiber_mut().reserve_stack(arg_count, stack_size) {
+            self.try_handle_error(Self::stack_overflow_error())?;
+            return Ok(None);
+        }
+        for i in 0..arg_count {
+            let arg = args.borrow().elements[i];
+            self.push(arg);
+        }
+        Ok(Some(arg_count))
+    }
+
+    fn call_spread_impl(&mut self) -> Result<(), Error> {
+        if let Some(arg_count) = self.unpack_spread_arguments()? {
+            self.call_value(self.peek(arg_count), arg_count)?;
+        }
+        Ok(())
+    }
+
+    fn invoke_spread_impl(&mut self) -> Result<(), Error> {
+        let method = self.read_string();
+        if let Some(arg_count) = self.unpack_spread_arguments()? {
+            self.invoke(method, arg_count)?;
+        }
+        Ok(())
+    }
+
+    fn call_named_impl(&mut self) -> Result<(), Error> {
+        let arg_count = self.read_byte() as usize;
+        let names = self
+            .read_constant()
+            .try_as_obj_tuple()
+            .expect("Expected ObjTuple.");
+        let callee = self.peek(arg_count);
+        if self.reorder_named_arguments(callee, arg_count, names)? {
+            self.call_value(callee, arg_count)?;
+        }
+        Ok(())
+    }
+
+    fn invoke_named_impl(&mut self) -> Result<(), Error> {
+        let method = self.read_string();
+        let arg_count = self.read_byte() as usize;
+        let names = self
+            .read_constant()
+            .try_as_obj_tuple()
+            .expect("Expected ObjTuple.");
+        let receiver = self.peek(arg_count);
+        if let Err(err) = self.check_private_access(receiver, method) {
+            return self.try_handle_error(err);
+        }
+        let field = match receiver {
+            Value::ObjInstance(instance) => instance.borrow().fields.get(&method).copied(),
+            Value::ObjModule(module) => {
+                if module.borrow().lazy && !self.load_lazy_module_to_invoke(module)? {
+                    return Ok(());
+                }
+                let global = module.borrow().attribute(method);
+                if global.is_some() && !module.borrow().is_exported(method) {
+                    return self.try_handle_error(unexported_error(module, method));
+                }
+                global
+            }
+            _ => None,
+        };
+        let callee = field.or_else(|| {
+            let class = match receiver {
+                Value::ObjInstance(instance) => instance.borrow().class,
+                Value::ObjModule(module) => module.borrow().class,
+                _ => self.get_class(receiver),
+            };
+            class.methods.get(&method).copied()
+        });
+        if let Some(callee) = callee {
+            if !self.reorder_named_arguments(callee, arg_count, names)? {
+                return Ok(());
+            }
+        }
+        self.invoke(method, arg_count)
+    }
+
+    fn reorder_named_arguments(
+        &mut self,
+        callee: Value,
+        arg_count: usize,
+        names: Gc<ObjTuple>,
+    ) -> Result<bool, Error> {
+        let function = match callee {
+            Value::ObjClosure(closure) => closure.function,
+            Value::ObjBoundMethod(bound) => bound.borrow().method.function,
+            Value::ObjInstance(instance) => {
+                let class = instance.borrow().class;
+                match class.methods.get(&self.call_string) {
+                    Some(Value::ObjClosure(closure)) => closure.function,
+                    _ => return Ok(true),
+                }
+            }
+            Value::ObjNative(_) | Value::ObjBoundNative(_) => {
+                let err = error!(
+                    ErrorKind::TypeError,
+                    "Cannot pass named arguments to a built-in function."
+                );
+                self.try_handle_error(err)?;
+                return Ok(false);
+            }
+            Value::ObjPartial(_) => {
+                let err = error!(
+                    ErrorKind::TypeError,
+                    "Cannot pass named arguments to a partial application."
+                );
+                self.try_handle_error(err)?;
+                return Ok(false);
+            }
+            _ => return Ok(true),
+        };
+        let num_positional = arg_count - names.elements.len();
+        let parameters = &function.parameters;
+        if arg_count > parameters.len() {
+            // Leave the arity check to the call itself.
+            return Ok(true);
+        }
+
+        let begin = self.stack_size() - arg_count;
+        let mut slots = vec![None; parameters.len()];
+        for (i, slot) in slots.iter_mut().enumerate().take(num_positional) {
+            *slot = Some(self.active_fiber().stack[begin + i]);
+        }
+        for (i, name) in names.elements.iter().enumerate() {
+            let name = name.try_as_obj_string().expect("Expected ObjString.");
+            let error = match parameters.iter().position(|&p| p == name) {
+                Some(pos) if slots[pos].is_none() => {
+                    slots[pos] = Some(self.active_fiber().stack[begin + num_positional + i]);
+                    continue;
+                }
+                Some(_) => error!(
+                    ErrorKind::TypeError,
+                    "Received multiple values for parameter '{}'.", *name
+                ),
+                None => error!(
+                    ErrorKind::TypeError,
+                    "Unknown parameter name '{}' in call to '{}'.", *name, *function.name
+                ),
+            };
+            self.try_handle_error(error)?;
+            return Ok(false);
+        }
+        if let Some(pos) = slots.iter().position(Option::is_none) {
+            let err = error!(
+                ErrorKind::TypeError,
+                "Missing argument for parameter '{}'.", *parameters[pos]
+            );
+            self.try_handle_error(err)?;
+            return Ok(false);
+        }
+        for (i, slot) in slots.into_iter().enumerate() {
+            self.active_fiber_mut().stack[begin + i] = slot.expect("Expected Value.");
+        }
+        Ok(true)
+    }
+
+    fn get_iter_impl(&mut self) -> Result<(), Error> {
+        let iterable = self.peek(0);
+        if self.has_method(iterable, self.iter_string) {
+            return self.invoke(self.iter_string, 0);
+        }
+        if !self.has_method(iterable, self.next_string) {
+            let err = not_iterable_error(iterable);
+            return self.try_handle_error(err);
+        }
+        Ok(())
+    }
+
     fn iter_next_impl(&mut self) -> Result<(), Error> {
         let iter = self.peek(0);
         self.push(iter);
@@ -1054,6 +2895,11 @@ impl Vm {
         self.ip = unsafe { self.ip.offset(offset as isize) };
     }
 
+    fn jump_long_impl(&mut self) {
+        let offset = self.read_long();
+        self.ip = unsafe { self.ip.offset(offset as isize) };
+    }
+
     fn jump_if_false_impl(&mut self) {
         let offset = self.read_short();
         if !self.peek(0).into_bool() {
@@ -1061,21 +2907,64 @@ impl Vm {
         }
     }
 
+    fn jump_if_not_instance_impl(&mut self) -> Result<(), Error> {
+        let offset = self.read_short();
+        let value = self.pop();
+        let class = match value.try_as_obj_class() {
+            Some(class) => class,
+            None => {
+                let err = error!(
+                    ErrorKind::TypeError,
+                    "Expected a class to catch but found '{}'.", value
+                );
+                return self.try_handle_error(err);
+            }
+        };
+        if !object::class_derives(self.get_class(self.peek(0)), class) {
+            self.ip = unsafe { self.ip.offset(offset as isize) };
+        }
+        Ok(())
+    }
+
     fn jump_if_stop_iter(&mut self) {
         let offset = self.read_short();
+        if self.is_stop_iter(self.peek(0)) {
+            self.ip = unsafe { self.ip.offset(offset as isize) };
+        }
+    }
+
+    /// Returns whether the value is an instance of StopIter or one of its subclasses, which
+    /// iterators return from next once they're exhausted.
+    fn is_stop_iter(&self, value: Value) -> bool {
         let stop_iter_class = self.class_store.stop_iter_class();
-        if let Some(instance) = self.peek(0).try_as_obj_instance() {
-            if instance.borrow().class == stop_iter_class {
-                self.ip = unsafe { self.ip.offset(offset as isize) };
-            }
+        match value.try_as_obj_instance() {
+            Some(instance) => object::class_derives(instance.borrow().class, stop_iter_class),
+            None => false,
         }
     }
 
+    /// Returns whether invoking the named method on the value would find something to call,
+    /// either a method of its class or a field, static field or module attribute.
+    fn has_method(&self, value: Value, name: Gc<ObjString>) -> bool {
+        let found = match value {
+            Value::ObjInstance(instance) => instance.borrow().fields.contains_key(&name),
+            Value::ObjModule(module) => module.borrow().attribute(name).is_some(),
+            Value::ObjClass(class) => class.static_field(name).is_some(),
+            _ => false,
+        };
+        found || self.get_class(value).methods.contains_key(&name)
+    }
+
     fn loop_impl(&mut self) {
         let offset = self.read_short();
         self.ip = unsafe { self.ip.offset(-(offset as isize)) };
     }
 
+    fn loop_long_impl(&mut self) {
+        let offset = self.read_long();
+        self.ip = unsafe { self.ip.offset(-(offset as isize)) };
+    }
+
     fn jump_finally_impl(&mut self) {
         let return_value = self.peek(0);
         self.active_fiber_mut().return_ip = Some(self.ip);
@@ -1107,7 +2996,16 @@ impl Vm {
     fn push_exc_handler_impl(&mut self) {
         let try_size = self.read_short() as usize;
         let catch_size = self.read_short() as usize;
+        self.push_exc_handler(try_size, catch_size);
+    }
 
+    fn push_exc_handler_long_impl(&mut self) {
+        let try_size = self.read_long() as usize;
+        let catch_size = self.read_long() as usize;
+        self.push_exc_handler(try_size, catch_size);
+    }
+
+    fn push_exc_handler(&mut self, try_size: usize, catch_size: usize) {
         let catch_ip = unsafe { self.ip.offset(try_size as isize) };
         let finally_ip = unsafe { self.ip.offset((try_size + catch_size) as isize) };
 
@@ -1121,8 +3019,7 @@ impl Vm {
 
     fn throw_impl(&mut self) -> Result<(), Error> {
         self.handling_exception = true;
-        self.active_fiber_mut().error_ip = Some(self.ip);
-        self.unwind_stack()
+        self.raise()
     }
 
     fn call_impl(&mut self) -> Result<(), Error> {
@@ -1130,12 +3027,19 @@ impl Vm {
         self.call_value(self.peek(arg_count), arg_count)
     }
 
+    /// Replaces the class a constructor was called on with a new instance, leaving the following
+    /// call to the instance's `<fields>` method to initialise its fields. Constructors reached
+    /// through `super` already have an instance, so skip the call.
     fn construct_impl(&mut self) {
         let arg_count = self.read_byte() as usize;
+        let skip = self.read_byte() as usize;
         let value = self.peek(arg_count);
-        if let Some(class) = value.try_as_obj_class() {
-            let instance = self.new_root_obj_instance(class);
-            self.poke(arg_count, Value::ObjInstance(instance.as_gc()));
+        match value.try_as_obj_class() {
+            Some(class) => {
+                let instance = self.new_root_obj_instance(class);
+                self.poke(arg_count, Value::ObjInstance(instance.as_gc()));
+            }
+            None => self.ip = unsafe { self.ip.add(skip) },
         }
     }
 
@@ -1152,7 +3056,18 @@ impl Vm {
             Value::ObjClass(ptr) => ptr,
             _ => unreachable!(),
         };
-        self.invoke_from_class(superclass, method, arg_count)
+        let class = self.super_dispatch_class(superclass, self.peek(arg_count));
+        self.invoke_from_class(class, method, arg_count)
+    }
+
+    /// The class whose methods a `super` expression in a method with the specified receiver
+    /// refers to. Static methods receive their class, so they dispatch through the superclass's
+    /// metaclass.
+    fn super_dispatch_class(&self, superclass: Gc<ObjClass>, receiver: Value) -> Gc<ObjClass> {
+        match receiver {
+            Value::ObjClass(_) => superclass.metaclass,
+            _ => superclass,
+        }
     }
 
     fn closure_impl(&mut self) {
@@ -1165,6 +3080,15 @@ impl Vm {
 
         let closure = self.new_root_obj_closure(function, self.active_module);
         self.push(Value::ObjClosure(closure.as_gc()));
+        // Closures created by methods can access the same private properties as the method.
+        let class = self
+            .active_fiber()
+            .current_frame()
+            .unwrap()
+            .closure
+            .class
+            .get();
+        closure.class.set(class);
 
         for i in 0..upvalue_count {
             let is_local = self.read_byte() != 0;
@@ -1183,6 +3107,22 @@ impl Vm {
         }
     }
 
+    fn generator_impl(&mut self) {
+        let closure = self
+            .peek(0)
+            .try_as_obj_closure()
+            .expect("Expected ObjClosure.");
+        let fiber = self.new_root_obj_fiber(closure);
+        let fiber_string = self.new_gc_obj_string("fiber");
+        let class = self.class_store.generator_class();
+        let instance = self.new_root_obj_instance(class);
+        instance
+            .borrow_mut()
+            .fields
+            .insert(fiber_string, Value::ObjFiber(fiber.as_gc()));
+        self.poke(0, Value::ObjInstance(instance.as_gc()));
+    }
+
     fn close_upvalue_impl(&mut self) {
         let stack_size = self.stack_size();
         self.active_fiber_mut().close_upvalues(stack_size - 1);
@@ -1201,7 +3141,7 @@ impl Vm {
                 self.poke(0, result);
                 return Ok(None);
             }
-            return Ok(Some(self.pop()));
+            return Ok(Some(result));
         }
         self.load_frame();
         self.active_fiber_mut().stack.truncate(prev_stack_size);
@@ -1209,13 +3149,23 @@ impl Vm {
         Ok(None)
     }
 
+    fn yield_impl(&mut self) -> Result<(), Error> {
+        // Leave a copy of the yielded value on the stack to be replaced when the fiber is resumed.
+        let value = self.peek(0);
+        self.push(value);
+        if let Err(error) = self.unload_fiber(Some(value)) {
+            return self.try_handle_error(error);
+        }
+        Ok(())
+    }
+
     fn declare_class_impl(&mut self) {
         let name = self.read_string();
         let metaclass_name = self.new_gc_obj_string(format!("{}Class", *name).as_str());
         let metaclass = UniqueRoot::new(ObjClass::new(
             metaclass_name,
             self.class_store.base_metaclass(),
-            Some(self.class_store.object_class()),
+            Some(self.class_store.base_metaclass()),
             object::new_obj_string_value_map(),
         ));
         let class = UniqueRoot::new(ObjClass::new(
@@ -1234,6 +3184,18 @@ impl Vm {
         let defined_metaclass: Root<ObjClass> = class_def.metaclass.into();
         class_def.class.metaclass = defined_metaclass.as_gc();
         let defined_class: Root<ObjClass> = class_def.class.into();
+        // Methods inherited from a superclass already belong to that class.
+        for method in defined_class
+            .methods
+            .values()
+            .chain(defined_metaclass.methods.values())
+        {
+            if let Value::ObjClosure(closure) = method {
+                if closure.class.get().is_none() {
+                    closure.class.set(Some(defined_class.as_gc()));
+                }
+            }
+        }
 
         self.poke(0, Value::ObjClass(defined_class.as_gc()));
     }
@@ -1245,14 +3207,77 @@ impl Vm {
             let err = error!(ErrorKind::RuntimeError, "Superclass must be a class.");
             return self.try_handle_error(err);
         };
-        self.working_class_def.as_mut().unwrap().class.superclass = Some(superclass);
+        let base_metaclass = self.class_store.base_metaclass();
+        let class_def = self.working_class_def.as_mut().unwrap();
+        class_def.class.superclass = Some(superclass);
         for (name, method) in &superclass.methods {
+            class_def.class.methods.insert(*name, *method);
+        }
+        // Static methods are inherited along the metaclass chain.
+        if superclass.metaclass != base_metaclass {
+            class_def.metaclass.superclass = Some(superclass.metaclass);
+            for (name, method) in &superclass.metaclass.methods {
+                class_def.metaclass.methods.insert(*name, *method);
+            }
+        }
+        self.pop();
+        Ok(())
+    }
+
+    fn mixin_impl(&mut self) -> Result<(), Error> {
+        let mixin = if let Some(ptr) = self.peek(0).try_as_obj_class() {
+            ptr
+        } else {
+            let err = error!(ErrorKind::RuntimeError, "Mixin must be a class.");
+            return self.try_handle_error(err);
+        };
+        for (name, method) in &mixin.methods {
+            // Copied closures belong to the class being defined so they can access its private
+            // properties.
+            let method = match method {
+                Value::ObjClosure(closure) => {
+                    let upvalues = closure.upvalues.borrow().clone();
+                    let copy =
+                        Root::new(ObjClosure::new(closure.function, upvalues, closure.module));
+                    Value::ObjClosure(copy.as_gc())
+                }
+                _ => *method,
+            };
             self.working_class_def
                 .as_mut()
                 .unwrap()
                 .class
                 .methods
-                .insert(*name, *method);
+                .insert(*name, method);
+        }
+        self.pop();
+        Ok(())
+    }
+
+    fn implements_impl(&mut self) -> Result<(), Error> {
+        let protocol = if let Some(ptr) = self.peek(0).try_as_obj_class() {
+            ptr
+        } else {
+            let err = error!(ErrorKind::RuntimeError, "Protocol must be a class.");
+            return self.try_handle_error(err);
+        };
+        let class = self.peek(1).try_as_obj_class().expect("Expected ObjClass.");
+        let missing = core::missing_protocol_methods(class, protocol);
+        if !missing.is_empty() {
+            let names = missing
+                .iter()
+                .map(|name| format!("'{}'", name.as_str()))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let err = error!(
+                ErrorKind::TypeError,
+                "Class '{}' does not implement '{}' (missing method{} {}).",
+                *class.name,
+                *protocol.name,
+                if missing.len() == 1 { "" } else { "s" },
+                names
+            );
+            return self.try_handle_error(err);
         }
         self.pop();
         Ok(())
@@ -1268,52 +3293,261 @@ impl Vm {
         self.define_method(name, true)
     }
 
-    fn start_import_impl(&mut self) -> Result<(), Error> {
-        let path = self.read_string();
+    fn static_field_impl(&mut self) {
+        let name = self.read_string();
+        let value = self.pop();
+        let class_def = self.working_class_def.as_mut().unwrap();
+        class_def.class.fields.borrow_mut().insert(name, value);
+    }
+
+    /// Starts importing a module. A lazy import binds the module without loading it, leaving it
+    /// to be loaded when it's first used.
+    fn start_import_impl(&mut self, opcode: OpCode) -> Result<(), Error> {
+        let is_lazy = opcode == OpCode::LazyImport;
+        let requested_path = self.read_string();
+        let loaded = self
+            .resolve_module_path(requested_path.as_str())
+            .and_then(|path| {
+                if is_lazy {
+                    Ok((self.lazy_module(path), None))
+                } else {
+                    self.load_module(path)
+                }
+            });
+        let (module, function) = match loaded {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                return self.try_handle_error(e);
+            }
+        };
+        self.push(Value::ObjModule(module));
+        let function = match function {
+            Some(function) => function,
+            None => {
+                // There's no module body to run, so skip the FinishImport that follows an eager
+                // import. The module may still be initialising if it's part of an import cycle.
+                if !is_lazy {
+                    self.skip_opcode();
+                }
+                return Ok(());
+            }
+        };
+
+        let closure = self.new_root_obj_closure(function.as_gc(), module);
+        self.push(Value::ObjClosure(closure.as_gc()));
+
+        self.call_value(self.peek(0), 0)?;
+        let active_module_path = self.active_module.borrow().path;
+        self.init_built_in_globals(&active_module_path);
+        Ok(())
+    }
+
+    /// Returns the module with the specified resolved path for a lazy import to bind. If the
+    /// module has already been imported, or is in the middle of being imported, it's bound as is.
+    fn lazy_module(&mut self, path: Gc<ObjString>) -> Gc<RefCell<ObjModule>> {
+        if let Some(module) = self.modules.get(&path) {
+            return module.as_gc();
+        }
+        let module = self.module(&path);
+        module.borrow_mut().lazy = true;
+        module
+    }
+
+    /// Loads the value if it's a module bound by a lazy import that has yet to be loaded.
+    pub(crate) fn load_if_lazy(&mut self, value: Value) -> Result<(), Error> {
+        match value {
+            Value::ObjModule(module) if module.borrow().lazy => self.load_lazy_module(module),
+            _ => Ok(()),
+        }
+    }
+
+    /// Loads a module bound by a lazy import now that it's being used. If loading the module
+    /// fails, it's left to be loaded again the next time it's used.
+    #[cold]
+    fn load_lazy_module(&mut self, module: Gc<RefCell<ObjModule>>) -> Result<(), Error> {
+        let path = module.borrow().path;
+        if let Err(error) = self.import_resolved_module(path) {
+            // A module that fails to load is discarded, so put it back in its place.
+            module.borrow_mut().lazy = true;
+            self.modules.insert(path, module.as_root());
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    /// Loads a module bound by a lazy import so that a method can be invoked on it, returning
+    /// whether the invocation can go ahead or an exception was raised instead.
+    #[cold]
+    fn load_lazy_module_to_invoke(
+        &mut self,
+        module: Gc<RefCell<ObjModule>>,
+    ) -> Result<bool, Error> {
+        match self.load_lazy_module(module) {
+            Ok(()) => Ok(true),
+            Err(err) => self.try_handle_error(err).map(|_| false),
+        }
+    }
+
+    /// Imports the module at the specified path, running its body to completion if it hasn't
+    /// been imported already. This is how the `module` function imports modules at runtime.
+    pub(crate) fn import_module(&mut self, path: &str) -> Result<Gc<RefCell<ObjModule>>, Error> {
+        let path = self.resolve_module_path(path)?;
+        self.import_resolved_module(path)
+    }
+
+    fn import_resolved_module(
+        &mut self,
+        path: Gc<ObjString>,
+    ) -> Result<Gc<RefCell<ObjModule>>, Error> {
+        let (module, function) = self.load_module(path)?;
+        let function = match function {
+            Some(function) => function,
+            None => return Ok(module),
+        };
+
+        let module_path = module.borrow().path;
+        self.init_built_in_globals(&module_path);
+        let closure = self.new_root_obj_closure(function.as_gc(), module);
+        let callee = Value::ObjClosure(closure.as_gc());
+        if let Err(cause) = self.run_closure(closure.as_gc(), callee, &[]) {
+            return Err(self.module_execution_error(module, cause));
+        }
+        {
+            let mut module = module.borrow_mut();
+            module.imported = true;
+            module.initialising = false;
+        }
+        Ok(module)
+    }
+
+    /// Discards a module whose body failed to run so the import can be attempted again, returning
+    /// the error to raise in place of the one the module raised.
+    #[cold]
+    fn module_execution_error(&mut self, module: Gc<RefCell<ObjModule>>, cause: Error) -> Error {
+        let path = module.borrow().path;
+        module.borrow_mut().initialising = false;
+        self.modules.remove(&path);
+        self.module_loader.invalidate(&path);
+        let mut error = error!(
+            ErrorKind::ImportError,
+            "Error executing module '{}'.",
+            path.as_str()
+        );
+        // The exception left unhandled by the module becomes the cause of the import error, which
+        // is raised in its place.
+        match self.failed_method_fiber.clone() {
+            Some(fiber) => {
+                let exc_object = *fiber.borrow().stack.peek(0);
+                let obj_err = self.new_root_obj_err_with_cause(error.clone(), exc_object);
+                *fiber.borrow_mut().stack.peek_mut(0) = Value::ObjInstance(obj_err.as_gc());
+            }
+            None => error.set_cause(cause),
+        }
+        error
+    }
+
+    /// Resolves the path of an import to the path the module is cached under.
+    fn resolve_module_path(&mut self, requested_path: &str) -> Result<Gc<ObjString>, Error> {
+        let resolved = self
+            .resolve_import_path(requested_path)
+            .and_then(|path| self.module_loader.resolve(&path))?;
+        Ok(self.new_gc_obj_string(&resolved))
+    }
 
+    /// Finds the module with the specified resolved path, returning it along with the function
+    /// that runs its body if it has yet to be imported.
+    fn load_module(&mut self, path: Gc<ObjString>) -> Result<LoadedModule, Error> {
         if let Some(module) = self.modules.get(&path).map(|m| m.as_gc()) {
-            if module.borrow().imported {
-                self.push(Value::ObjModule(module));
-                self.push(Value::None);
-            } else {
-                let err = error!(
+            // A module that's still initialising is part of an import cycle, so it's bound as it
+            // is so far, leaving its attributes to be looked up once they're used.
+            if module.borrow().imported || module.borrow().initialising {
+                return Ok((module, None));
+            }
+            // A module bound by a lazy import is loaded by whichever import uses it first.
+            if !module.borrow().lazy {
+                return Err(error!(
                     ErrorKind::ImportError,
                     "Circular dependency encountered when importing module '{}'.",
                     path.as_str()
-                );
-                self.try_handle_error(err)?;
+                ));
             }
-            return Ok(());
         }
 
-        let source = match (self.module_loader)(&path) {
-            Ok(s) => s,
-            Err(e) => {
-                return self.try_handle_error(e);
-            }
+        let ModuleSource { source, is_package } = match self.module_loader.load(&path) {
+            Ok(source) => source,
+            Err(error) => return Err(self.disabled_module_error(&path).unwrap_or(error)),
         };
 
         let function = match compiler::compile(self, source, Some(&path)) {
             Ok(f) => f,
             Err(e) => {
-                let mut error = error!(ErrorKind::ImportError, "Error compiling module:");
-                for msg in e.messages() {
-                    error.add_message(&format!("    {}", msg));
-                }
-                return self.try_handle_error(error);
+                // Compilation allocates the module's global slots, so discard the partially
+                // populated module to allow the import to be attempted again.
+                self.modules.remove(&path);
+                self.module_loader.invalidate(&path);
+                let mut error = error!(
+                    ErrorKind::ImportError,
+                    "Error compiling module '{}'.",
+                    path.as_str()
+                );
+                error.set_cause(e);
+                return Err(error);
             }
         };
 
         let module = self.module(&path);
-        self.push(Value::ObjModule(module));
+        {
+            let mut module = module.borrow_mut();
+            module.is_package = is_package;
+            module.lazy = false;
+            module.initialising = true;
+        }
+        Ok((module, Some(function)))
+    }
 
-        let closure = self.new_root_obj_closure(function.as_gc(), module);
-        self.push(Value::ObjClosure(closure.as_gc()));
+    /// Explains how to enable the built-in module at the specified path if it's been left out
+    /// by the `VmOptions` this `Vm` was created with.
+    #[cold]
+    fn disabled_module_error(&self, path: &str) -> Option<Error> {
+        let field = match path {
+            "os" if !self.options.allow_subprocess => "allow_subprocess",
+            "env" if self.options.env_access == EnvAccess::None => "env_access",
+            _ => return None,
+        };
+        let error = error!(
+            ErrorKind::ImportError,
+            "Module '{}' is disabled. Enable it by setting VmOptions::{}.", path, field
+        );
+        Some(error.with_code(ErrorCode::ModuleNotFound))
+    }
 
-        self.call_value(self.peek(0), 0)?;
-        let active_module_path = self.active_module.borrow().path;
-        self.init_built_in_globals(&active_module_path);
-        Ok(())
+    /// Resolves an import path starting with "./" or "../" against the path of the importing
+    /// module. Other paths are returned unchanged. A package is resolved against its own
+    /// directory, whereas any other module is resolved against the directory that contains it.
+    fn resolve_import_path(&self, path: &str) -> Result<String, Error> {
+        if !path.starts_with("./") && !path.starts_with("../") {
+            return Ok(path.to_owned());
+        }
+        let importer = self.active_module.borrow();
+        let mut components: Vec<&str> = importer.path.as_str().split('/').collect();
+        if !importer.is_package {
+            components.pop();
+        }
+        for component in path.split('/') {
+            match component {
+                "" | "." => {}
+                ".." => {
+                    if components.pop().is_none() {
+                        return Err(error!(
+                            ErrorKind::ImportError,
+                            "Relative import '{}' is outside the top-level directory.", path
+                        ));
+                    }
+                }
+                _ => components.push(component),
+            }
+        }
+        Ok(components.join("/"))
     }
 
     fn finish_import_impl(&mut self) {
@@ -1322,7 +3556,9 @@ impl Vm {
             .peek(0)
             .try_as_obj_module()
             .expect("Expected ObjModule.");
-        module.borrow_mut().imported = true;
+        let mut module = module.borrow_mut();
+        module.imported = true;
+        module.initialising = false;
     }
 
     fn string_get_item(&mut self) -> Result<(), Error> {
@@ -1332,23 +3568,40 @@ impl Vm {
             .expect("Expected ObjString.");
         let string_len = string.len() as isize;
 
-        let (begin, end) = match self.peek(0) {
+        let char_end = |begin: usize| {
+            let mut end = begin + 1;
+            while end <= string.len() && !string.as_str().is_char_boundary(end) {
+                end += 1;
+            }
+            end
+        };
+        let new_string = match self.peek(0) {
             Value::Number(_) => {
-                let begin = self
-                    .peek(0)
-                    .try_as_bounded_index(string_len, "String")?;
+                let begin = self.peek(0).try_as_bounded_index(string_len, "String")?;
                 string.validate_char_boundary(begin, "string index")?;
-                let mut end = begin + 1;
-                while end <= string.len() && !string.as_str().is_char_boundary(end) {
-                    end += 1;
-                }
-                (begin, end)
+                string.as_str()[begin..char_end(begin)].to_owned()
             }
             Value::ObjRange(r) => {
-                let (begin, end) = r.make_bounded_range(string_len, "String")?;
-                string.validate_char_boundary(begin, "string slice start")?;
-                string.validate_char_boundary(end, "string slice end")?;
-                (begin, end)
+                let slice = r.make_slice(string_len, "String")?;
+                match slice.as_range() {
+                    Some(range) => {
+                        string.validate_char_boundary(range.start, "string slice start")?;
+                        string.validate_char_boundary(range.end, "string slice end")?;
+                        let new_string = self.new_root_obj_string_slice(string, range);
+                        self.pop();
+                        self.poke(0, Value::ObjString(new_string.as_gc()));
+                        return Ok(());
+                    }
+                    // A slice with a step selects the character starting at each position.
+                    None => {
+                        let mut new_string = String::new();
+                        for begin in slice.indices() {
+                            string.validate_char_boundary(begin, "string slice index")?;
+                            new_string.push_str(&string.as_str()[begin..char_end(begin)]);
+                        }
+                        new_string
+                    }
+                }
             }
             _ => {
                 return Err(error!(
@@ -1358,9 +3611,9 @@ impl Vm {
             }
         };
 
-        let new_string = self.new_gc_obj_string(&string.as_str()[begin..end]);
+        let new_string = self.new_root_obj_string(&new_string);
         self.pop();
-        self.poke(0, Value::ObjString(new_string));
+        self.poke(0, Value::ObjString(new_string.as_gc()));
         Ok(())
     }
 
@@ -1405,8 +3658,12 @@ impl Vm {
                 Ok(IndexResult::Scalar(elements[index]))
             }
             Value::ObjRange(r) => {
-                let (begin, end) = r.make_bounded_range(elems_len, kind)?;
-                Ok(IndexResult::Slice(Vec::from(&elements[begin..end])))
+                let slice = r.make_slice(elems_len, kind)?;
+                let values = match slice.as_range() {
+                    Some(range) => Vec::from(&elements[range]),
+                    None => slice.indices().map(|i| elements[i]).collect(),
+                };
+                Ok(IndexResult::Slice(values))
             }
             _ => {
                 return Err(error!(
@@ -1434,6 +3691,18 @@ impl Vm {
 
             Value::ObjNative(wrapped) => self.call_native(wrapped, arg_count),
 
+            Value::ObjPartial(partial) => self.call_partial(partial, arg_count),
+
+            Value::ObjInstance(instance)
+                if instance
+                    .borrow()
+                    .class
+                    .methods
+                    .contains_key(&self.call_string) =>
+            {
+                self.call_instance(instance, arg_count)
+            }
+
             _ => {
                 let err = error!(ErrorKind::TypeError, "Can only call functions and methods.");
                 self.try_handle_error(err)
@@ -1441,6 +3710,46 @@ impl Vm {
         }
     }
 
+    #[cold]
+    fn call_instance(
+        &mut self,
+        instance: Gc<RefCell<ObjInstance>>,
+        arg_count: usize,
+    ) -> Result<(), Error> {
+        let class = instance.borrow().class;
+        self.invoke_from_class(class, self.call_string, arg_count)
+    }
+
+    /// Inserts the partial application's arguments ahead of those already on the stack, then calls
+    /// the underlying callee with all of them.
+    #[cold]
+    fn call_partial(&mut self, partial: Gc<ObjPartial>, arg_count: usize) -> Result<(), Error> {
+        let total_count = partial.args.len() + arg_count;
+        if total_count > 255 {
+            let err = error!(
+                ErrorKind::TypeError,
+                "Cannot have more than 255 arguments but found {}.", total_count
+            );
+            return self.try_handle_error(err);
+        }
+        let stack_size = self.options.stack_size;
+        if !self
+            .active_fiber_mut()
+            .reserve_stack(partial.args.len(), stack_size)
+        {
+            return self.try_handle_error(Self::stack_overflow_error());
+        }
+        let begin = self.stack_size() - arg_count;
+        let end = self.stack_size();
+        let args: Vec<_> = self.active_fiber().stack[begin..end].to_vec();
+        self.discard(arg_count);
+        self.poke(0, partial.callee);
+        for &arg in partial.args.iter().chain(args.iter()) {
+            self.push(arg);
+        }
+        self.call_value(partial.callee, total_count)
+    }
+
     #[inline(always)]
     fn invoke_from_class(
         &mut self,
@@ -1455,13 +3764,56 @@ impl Vm {
                 _ => unreachable!(),
             };
         }
+        self.invoke_undefined(class, name, arg_count)
+    }
+
+    /// Handles the invocation of a method the class doesn't define, which is passed on to the
+    /// class's `__method_missing__` method if it has one. Classes without instance fields, such as
+    /// built-in ones, have no field initialiser, so invoking it does nothing.
+    #[cold]
+    fn invoke_undefined(
+        &mut self,
+        class: Gc<ObjClass>,
+        name: Gc<ObjString>,
+        arg_count: usize,
+    ) -> Result<(), Error> {
+        if name == self.field_initialiser_string {
+            self.discard(arg_count);
+            self.poke(0, Value::None);
+            return Ok(());
+        }
+        if class.methods.contains_key(&self.method_missing_string) {
+            return self.invoke_method_missing(class, name, arg_count);
+        }
         let err = error!(ErrorKind::AttributeError, "Undefined property '{}'.", *name);
         self.try_handle_error(err)
     }
 
+    /// Replaces the arguments of a call to an undefined method with the method's name and a tuple
+    /// of those arguments, then invokes the class's `__method_missing__` method.
+    #[cold]
+    fn invoke_method_missing(
+        &mut self,
+        class: Gc<ObjClass>,
+        name: Gc<ObjString>,
+        arg_count: usize,
+    ) -> Result<(), Error> {
+        let begin = self.stack_size() - arg_count;
+        let end = self.stack_size();
+        let args = self.active_fiber().stack[begin..end].to_vec();
+        let args = self.new_root_obj_tuple(args);
+        self.discard(arg_count);
+        self.push(Value::ObjString(name));
+        self.push(Value::ObjTuple(args.as_gc()));
+        self.invoke_from_class(class, self.method_missing_string, 2)
+    }
+
     #[inline(always)]
     fn invoke(&mut self, name: Gc<ObjString>, arg_count: usize) -> Result<(), Error> {
         let receiver = self.peek(arg_count);
+        if let Err(err) = self.check_private_access(receiver, name) {
+            return self.try_handle_error(err);
+        }
         let class = match receiver {
             Value::ObjInstance(instance) => {
                 if let Some(value) = instance.borrow().fields.get(&name) {
@@ -1471,12 +3823,29 @@ impl Vm {
                 instance.borrow().class
             }
             Value::ObjModule(module) => {
-                let global = module.borrow().attributes.get(&name).copied();
+                if module.borrow().lazy && !self.load_lazy_module_to_invoke(module)? {
+                    return Ok(());
+                }
+                let global = module.borrow().attribute(name);
                 if let Some(value) = global {
+                    if !module.borrow().is_exported(name) {
+                        return self.try_handle_error(unexported_error(module, name));
+                    }
+                    self.poke(arg_count, value);
+                    return self.call_value(value, arg_count);
+                }
+                let class = module.borrow().class;
+                if module.borrow().initialising && !class.methods.contains_key(&name) {
+                    return self.try_handle_error(uninitialised_attribute_error(module, name));
+                }
+                class
+            }
+            Value::ObjClass(class) => {
+                if let Some(value) = class.static_field(name) {
                     self.poke(arg_count, value);
                     return self.call_value(value, arg_count);
                 }
-                module.borrow().class
+                class.metaclass
             }
             _ => self.get_class(receiver),
         };
@@ -1487,12 +3856,20 @@ impl Vm {
     pub fn call_closure(&mut self, closure: Gc<ObjClosure>, arg_count: usize) -> Result<(), Error> {
         let arity = closure.function.arity - 1;
         let err = if arg_count != arity {
-            Some(error!(
-                ErrorKind::TypeError,
-                "Expected {} arguments but found {}.", arity, arg_count
-            ))
-        } else if self.active_fiber().frames.len() == common::FRAMES_MAX {
-            Some(error!(ErrorKind::IndexError, "Stack overflow."))
+            Some(
+                error!(
+                    ErrorKind::TypeError,
+                    "Expected {} arguments but found {}.", arity, arg_count
+                )
+                .with_code(ErrorCode::ArityMismatch),
+            )
+        } else if self.active_fiber().frames.len() >= self.options.frame_limit
+            || self.stack_size() + common::FRAME_STACK_SLOTS > self.options.stack_size
+        {
+            Some(
+                error!(ErrorKind::IndexError, "Stack overflow.")
+                    .with_code(ErrorCode::StackOverflow),
+            )
         } else {
             None
         };
@@ -1502,7 +3879,13 @@ impl Vm {
         }
 
         self.active_fiber_mut().current_frame_mut().unwrap().ip = self.ip;
+        let stack_size = self.options.stack_size;
         self.active_fiber_mut().push_call_frame(closure);
+        // There's space for the frame's slots, since the size of the stack was checked above.
+        let reserved = self
+            .active_fiber_mut()
+            .reserve_stack(common::FRAME_STACK_SLOTS, stack_size);
+        debug_assert!(reserved);
         self.load_frame();
         Ok(())
     }
@@ -1518,52 +3901,286 @@ impl Vm {
         }
         match result {
             Ok(value) => {
+                // The native may have handled an error from a closure it called.
+                self.failed_method_fiber = None;
                 if !native.manages_stack {
                     self.poke(0, value);
                 }
             }
             Err(error) => {
-                let exc_object = self.new_root_obj_err_from_error(error);
-                self.poke(0, Value::ObjInstance(exc_object.as_gc()));
-                self.unwind_stack()?;
+                self.pending_call = None;
+                if error.code() == ErrorCode::Exit && self.exit_code.is_some() {
+                    self.failed_method_fiber = None;
+                    return Err(error);
+                }
+                let exc_object = self.new_exception_from_error(error);
+                self.poke(0, exc_object);
+                self.raise()?;
             }
         }
+        if let Some(token) = self.pending_call.take() {
+            self.suspend_pending_call(token)?;
+        }
         Ok(())
     }
 
+    /// Raises the exception on the top of the stack from the current instruction.
+    fn raise(&mut self) -> Result<(), Error> {
+        self.active_fiber_mut().error_ip = Some(self.ip);
+        self.unwind_stack()
+    }
+
     fn unwind_stack(&mut self) -> Result<(), Error> {
+        let exc_handler = self.active_fiber_mut().pop_exc_handler();
+        let frame_count = exc_handler.as_ref().map_or(0, |h| h.frame_count);
+        self.wrap_import_errors(frame_count);
         let exc_object = self.peek(0);
 
-        let exc_handler = self.active_fiber_mut().pop_exc_handler();
         let handler = if let Some(h) = exc_handler {
             h
+        } else if self.active_fiber().caller.is_some() {
+            return self.unwind_fiber(exc_object);
         } else {
             return Err(self.new_error_from_value(exc_object));
         };
+        self.fiber_traceback.clear();
+
+        {
+            // If the exception is raised again, e.g. after a finally block, it appears to come
+            // from wherever it left the frame that handles it.
+            let ip = self.ip;
+            let mut fiber = self.active_fiber_mut();
+            let error_ip = if fiber.frames.len() > handler.frame_count {
+                fiber.frames[handler.frame_count - 1].ip
+            } else {
+                fiber.error_ip.unwrap_or(ip)
+            };
+            fiber.error_ip = Some(error_ip);
+            fiber.stack.truncate(handler.init_stack_size);
+            fiber.frames.truncate(handler.frame_count);
+        }
+        self.push(exc_object);
+        self.handling_exception = handler.has_catch_block();
+        self.active_fiber_mut().current_frame_mut().unwrap().ip = handler.catch_ip;
+        self.load_frame();
+
+        Ok(())
+    }
+
+    /// Wraps the exception being raised in an ImportError for each module whose execution it
+    /// aborts, from the innermost outwards. The frames of each module are discarded once their
+    /// traceback has been recorded with the wrapped exception, and the module itself is discarded
+    /// so that the import can be attempted again.
+    fn wrap_import_errors(&mut self, frame_count: usize) {
+        loop {
+            // The first frame on a fiber is either the main script or a function, so only
+            // module-level frames after it are running imported modules.
+            let import_frame = self
+                .active_fiber()
+                .frames
+                .iter()
+                .enumerate()
+                .skip(frame_count.max(1))
+                .rev()
+                .find(|(_, frame)| {
+                    frame.closure.function.name.is_empty()
+                        && !frame.closure.module.borrow().imported
+                })
+                .map(|(index, frame)| (index, frame.closure.module));
+            let (index, module) = match import_frame {
+                Some(import_frame) => import_frame,
+                None => return,
+            };
+
+            let num_module_frames = self.active_fiber().frames.len() - index;
+            let mut traceback = mem::take(&mut self.fiber_traceback);
+            traceback.extend(self.traceback().into_iter().take(num_module_frames));
+            let exc_object = self.peek(0);
+            if let Some(instance) = exc_object.try_as_obj_instance() {
+                let traceback_string = self.new_gc_obj_string("traceback");
+                if !instance.borrow().fields.contains_key(&traceback_string) {
+                    let traceback = self.new_root_obj_string(&traceback.join("\n"));
+                    instance
+                        .borrow_mut()
+                        .fields
+                        .insert(traceback_string, Value::ObjString(traceback.as_gc()));
+                }
+            }
+
+            {
+                let mut fiber = self.active_fiber_mut();
+                fiber.frames.truncate(index);
+                fiber.error_ip = None;
+            }
+            self.load_frame();
+
+            let path = module.borrow().path;
+            module.borrow_mut().initialising = false;
+            self.modules.remove(&path);
+            self.module_loader.invalidate(&path);
+            let error = error!(
+                ErrorKind::ImportError,
+                "Error executing module '{}'.",
+                path.as_str()
+            );
+            let obj_err = self.new_root_obj_err_with_cause(error, exc_object);
+            self.poke(0, Value::ObjInstance(obj_err.as_gc()));
+        }
+    }
+
+    fn unwind_fiber(&mut self, exc_object: Value) -> Result<(), Error> {
+        // The exception isn't handled anywhere in the active fiber, so abandon the fiber and
+        // re-raise the exception in its caller, keeping hold of the fiber's traceback in case
+        // the exception turns out to be unhandled altogether.
+        let mut traceback = self.traceback();
+        self.fiber_traceback.append(&mut traceback);
+        let catches_errors = {
+            let mut fiber = self.active_fiber_mut();
+            fiber.abandon();
+            fiber.error = Some(exc_object);
+            fiber.catches_errors
+        };
+        let caller = self.active_fiber().caller.expect("Expected ObjFiber.");
+        let mut current = self.fiber.replace(caller.as_root());
+        self.unsafe_fiber = (*caller).as_ptr();
+        current.as_mut().unwrap().borrow_mut().caller = None;
+        self.poke(0, exc_object);
+        self.load_frame();
+        if catches_errors {
+            self.fiber_traceback.clear();
+            self.handling_exception = false;
+            return Ok(());
+        }
+        self.raise()
+    }
+
+    fn reset_stack(&mut self) {
+        if let Some(fiber) = self.fiber.as_ref() {
+            let mut borrowed_fiber = fiber.borrow_mut();
+            borrowed_fiber.stack.clear();
+            borrowed_fiber.frames.clear();
+        }
+    }
+
+    #[cold]
+    fn record_profile_sample(&mut self) {
+        let function = self
+            .active_fiber()
+            .current_frame()
+            .unwrap()
+            .closure
+            .function;
+        let offset = self.active_chunk.code_offset(self.ip);
+        let line = self.active_chunk.line(offset);
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.record(function, line);
+        }
+    }
+
+    #[cold]
+    fn record_opcode(&mut self) {
+        let function = self
+            .active_fiber()
+            .current_frame()
+            .unwrap()
+            .closure
+            .function;
+        let offset = self.active_chunk.code_offset(self.ip);
+        let opcode = OpCode::from(self.active_chunk.code[offset]);
+        let line = self.active_chunk.line(offset);
+        if let Some(counter) = self.opcode_counter.as_mut() {
+            counter.record(opcode, function, offset, line);
+        }
+    }
 
-        self.active_fiber_mut()
-            .stack
-            .truncate(handler.init_stack_size);
-        self.push(exc_object);
-        self.active_fiber_mut().frames.truncate(handler.frame_count);
-        self.handling_exception = handler.has_catch_block();
-        self.active_fiber_mut().current_frame_mut().unwrap().ip = handler.catch_ip;
-        self.load_frame();
+    #[cold]
+    fn trace_instruction(&mut self) -> Result<(), Error> {
+        let offset = self.active_chunk.code_offset(self.ip);
+        let mut instruction = String::new();
+        debug::write_operation(&mut instruction, &self.active_chunk, offset);
+
+        let event = {
+            let fiber = self.active_fiber();
+            let function = fiber.current_frame().unwrap().closure.function;
+            let stack = &fiber.stack;
+            TraceEvent {
+                module_path: function.module_path.as_str().to_owned(),
+                function: if function.name.is_empty() {
+                    "script".to_owned()
+                } else {
+                    format!("{}()", *function.name)
+                },
+                frame_depth: fiber.frames.len(),
+                line: self.active_chunk.line(offset),
+                offset,
+                opcode: OpCode::from(self.active_chunk.code[offset]),
+                instruction: instruction.trim_end().to_owned(),
+                stack: (0..stack.len())
+                    .rev()
+                    .map(|depth| stack.peek(depth).to_string())
+                    .collect(),
+            }
+        };
 
-        Ok(())
+        let step = match self.trace_sink.as_mut() {
+            Some(sink) => sink.emit(&event),
+            None => self.diagnostics.trace(&event),
+        };
+        match step {
+            Ok(TraceStep::Continue) => Ok(()),
+            Ok(TraceStep::Stop) => Err(error!(
+                ErrorKind::RuntimeError,
+                "Execution stopped by tracer."
+            )),
+            Err(e) => Err(error!(
+                ErrorKind::RuntimeError,
+                "Unable to write trace: {}", e
+            )),
+        }
     }
 
-    fn reset_stack(&mut self) {
-        if let Some(fiber) = self.fiber.as_ref() {
-            let mut borrowed_fiber = fiber.borrow_mut();
-            borrowed_fiber.stack.clear();
-            borrowed_fiber.frames.clear();
+    #[cold]
+    fn record_coverage(&mut self) {
+        let offset = self.active_chunk.code_offset(self.ip);
+        let chunk = self.active_chunk;
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.record(chunk, offset);
         }
     }
 
     fn runtime_error(&mut self, error: &mut Error) -> Error {
+        if error.code() == ErrorCode::Exit && self.exit_code.is_some() {
+            self.reset_stack();
+            return error.clone();
+        }
+        let mut traceback = mem::take(&mut self.fiber_traceback);
+        traceback.append(&mut self.traceback());
+        for msg in traceback {
+            error.add_message(msg.as_str());
+        }
+        let mut cause = error.cause().cloned();
+        while let Some(inner) = cause {
+            for msg in inner.messages() {
+                error.add_message(msg);
+            }
+            cause = inner.cause().cloned();
+        }
+
+        self.reset_stack();
+        self.report_error(error);
+
+        error.clone()
+    }
+
+    fn traceback(&mut self) -> Vec<String> {
         let ip = self.ip;
         self.active_fiber_mut().store_error_ip_or(ip);
+        let mut traceback = Vec::new();
+        let scheduler = self.scheduler_fiber.as_ref().map(|f| f.as_gc());
+        if scheduler.is_some() && self.fiber.as_ref().map(|f| f.as_gc()) == scheduler {
+            return traceback;
+        }
+        let mut repeats = 0;
         for frame in self.active_fiber().frames.iter().rev() {
             let (function, module) = (frame.closure.function, frame.closure.module);
 
@@ -1574,7 +4191,7 @@ impl Vm {
                 new_msg,
                 "[{}, line {}] in ",
                 *module.borrow(),
-                chunk.lines[instruction]
+                chunk.line(instruction)
             )
             .expect("Unable to write error to buffer.");
             if function.name.is_empty() {
@@ -1582,16 +4199,25 @@ impl Vm {
             } else {
                 write!(new_msg, "{}()", *function.name).expect("Unable to write error to buffer.");
             }
-            error.add_message(new_msg.as_str());
+            // Deep recursion would otherwise bury the rest of the traceback.
+            if traceback.last() == Some(&new_msg) {
+                repeats += 1;
+                continue;
+            }
+            push_repeats(&mut traceback, repeats);
+            repeats = 0;
+            traceback.push(new_msg);
         }
-
-        self.reset_stack();
-
-        error.clone()
+        push_repeats(&mut traceback, repeats);
+        traceback
     }
 
     fn define_method(&mut self, name: Gc<ObjString>, is_static: bool) -> Result<(), Error> {
         let method = self.peek(0);
+        if let Value::ObjClosure(closure) = method {
+            // The method is given its class once the class has been defined.
+            closure.class.set(None);
+        }
         let class_def = self.working_class_def.as_mut().unwrap();
         class_def.class.methods.insert(name, method);
         if is_static {
@@ -1605,23 +4231,35 @@ impl Vm {
     }
 
     fn bind_method(&mut self, class: Gc<ObjClass>, name: Gc<ObjString>) -> Result<(), Error> {
-        let instance = self.peek(0);
-        let bound = match class.methods.get(&name) {
-            Some(Value::ObjClosure(ptr)) => {
-                Value::ObjBoundMethod(self.new_root_obj_bound_method(instance, *ptr).as_gc())
-            }
-            Some(Value::ObjNative(ptr)) => {
-                Value::ObjBoundNative(self.new_root_obj_bound_method(instance, *ptr).as_gc())
-            }
-            None => {
-                let err = error!(ErrorKind::AttributeError, "Undefined property '{}'.", *name);
-                return self.try_handle_error(err);
+        match self.bound_method(self.peek(0), class, name) {
+            Ok(bound) => {
+                self.pop();
+                self.push(bound);
+                Ok(())
             }
+            Err(err) => self.try_handle_error(err),
+        }
+    }
+
+    fn bound_method(
+        &mut self,
+        receiver: Value,
+        class: Gc<ObjClass>,
+        name: Gc<ObjString>,
+    ) -> Result<Value, Error> {
+        match class.methods.get(&name) {
+            Some(Value::ObjClosure(ptr)) => Ok(Value::ObjBoundMethod(
+                self.new_root_obj_bound_method(receiver, *ptr).as_gc(),
+            )),
+            Some(Value::ObjNative(ptr)) => Ok(Value::ObjBoundNative(
+                self.new_root_obj_bound_method(receiver, *ptr).as_gc(),
+            )),
+            None => Err(error!(
+                ErrorKind::AttributeError,
+                "Undefined property '{}'.", *name
+            )),
             _ => unreachable!(),
-        };
-        self.pop();
-        self.push(bound);
-        Ok(())
+        }
     }
 
     fn capture_upvalue(&mut self, location: usize) -> Gc<RefCell<ObjUpvalue>> {
@@ -1652,13 +4290,13 @@ impl Vm {
         created_upvalue.as_gc()
     }
 
-    fn build_range(&mut self, begin: isize, end: isize) -> Gc<ObjRange> {
+    fn build_range(&mut self, begin: isize, end: isize, step: Option<isize>) -> Gc<ObjRange> {
         // Ranges are cached using a crude LRU cache. Since the cache size is small it's reasonable
         // to store the cache elements in a Vec and just iterate.
         let result = self
             .range_cache
             .iter()
-            .find(|&(r, _)| r.begin == begin && r.end == end);
+            .find(|&(r, _)| r.begin == begin && r.end == end && r.step == step);
 
         if let Some((range, _)) = result {
             return range.as_gc();
@@ -1667,43 +4305,52 @@ impl Vm {
         // Cache miss! Create the range and cache it.
 
         let class = self.class_store.range_class();
-        let range = Root::new(ObjRange::new(class, begin, end));
+        let range = Root::new(ObjRange::new(class, begin, end, step));
         let range_gc = range.as_gc();
 
         // Check the cache size. If we're at the limit, evict the oldest element.
-        if self.range_cache.len() >= RANGE_CACHE_SIZE {
+        if self.options.range_cache_size == 0 {
+            return range_gc;
+        } else if self.range_cache.len() >= self.options.range_cache_size {
             let stale_pos = self
                 .range_cache
                 .iter()
                 .enumerate()
-                .max_by(|first, second| first.1 .1.elapsed().cmp(&second.1 .1.elapsed()))
+                .min_by_key(|(_, (_, order))| *order)
                 .map(|e| e.0)
-                .expect("Expect to find max given non-empty Vec.");
+                .expect("Expect to find min given non-empty Vec.");
 
-            self.range_cache[stale_pos] = (range, time::Instant::now());
+            self.range_cache[stale_pos] = (range, self.num_cached_ranges);
         } else {
-            self.range_cache.push((range, time::Instant::now()));
+            self.range_cache.push((range, self.num_cached_ranges));
         }
+        self.num_cached_ranges += 1;
 
         range_gc
     }
 
+    #[track_caller]
     fn new_root_obj_err_with_class(
         &mut self,
         class: Gc<ObjClass>,
         context: Value,
     ) -> Root<RefCell<ObjInstance>> {
         let context_string = self.new_gc_obj_string("context");
+        let cause_string = self.new_gc_obj_string("cause");
         let instance = self.new_root_obj_instance(class);
         instance.borrow_mut().fields.insert(context_string, context);
         instance
+            .borrow_mut()
+            .fields
+            .insert(cause_string, Value::None);
+        instance
     }
 
     fn new_root_obj_err_from_error(&mut self, error: Error) -> Root<RefCell<ObjInstance>> {
-        let msg = self.new_gc_obj_string(&error.messages().join("\n"));
+        let msg = self.new_root_obj_string(&error.messages().join("\n"));
         let class = match error.kind() {
             ErrorKind::AttributeError => self.class_store.attribute_error_class(),
-            ErrorKind::CompileError => self.class_store.runtime_error_class(),
+            ErrorKind::CompileError => self.class_store.compile_error_class(),
             ErrorKind::ImportError => self.class_store.import_error_class(),
             ErrorKind::IndexError => self.class_store.index_error_class(),
             ErrorKind::NameError => self.class_store.name_error_class(),
@@ -1712,31 +4359,64 @@ impl Vm {
             ErrorKind::ValueError => self.class_store.value_error_class(),
         };
 
-        self.new_root_obj_err_with_class(class, Value::ObjString(msg))
+        let obj_err = self.new_root_obj_err_with_class(class, Value::ObjString(msg.as_gc()));
+        if let Some(cause) = error.cause() {
+            let cause = self.new_root_obj_err_from_error(cause.clone());
+            let cause_string = self.new_gc_obj_string("cause");
+            obj_err
+                .borrow_mut()
+                .fields
+                .insert(cause_string, Value::ObjInstance(cause.as_gc()));
+        }
+        // Codes more specific than the error's kind are kept with the exception so that they
+        // survive being raised.
+        if error.code() != error.kind().code() {
+            let code_string = self.new_gc_obj_string("error_code");
+            let code = self.new_root_obj_string(error.code().as_str());
+            obj_err
+                .borrow_mut()
+                .fields
+                .insert(code_string, Value::ObjString(code.as_gc()));
+        }
+        obj_err
+    }
+
+    fn new_root_obj_err_with_cause(
+        &mut self,
+        error: Error,
+        cause: Value,
+    ) -> Root<RefCell<ObjInstance>> {
+        let obj_err = self.new_root_obj_err_from_error(error);
+        let cause_string = self.new_gc_obj_string("cause");
+        obj_err.borrow_mut().fields.insert(cause_string, cause);
+        obj_err
     }
 
     fn new_error_from_value(&mut self, value: Value) -> Error {
+        self.error_from_value(value, false)
+    }
+
+    /// Converts an exception to an error, along with the chain of exceptions that caused it.
+    /// Causes are described along with the traceback from where they were raised.
+    fn error_from_value(&mut self, value: Value, is_cause: bool) -> Error {
         let (kind, exc_description, context) = if let Some(instance) = value.try_as_obj_instance() {
             let class = instance.borrow().class;
-            let kind = if class == self.class_store.attribute_error_class() {
-                ErrorKind::AttributeError
-            } else if class == self.class_store.runtime_error_class() {
-                ErrorKind::CompileError
-            } else if class == self.class_store.import_error_class() {
-                ErrorKind::ImportError
-            } else if class == self.class_store.index_error_class() {
-                ErrorKind::IndexError
-            } else if class == self.class_store.name_error_class() {
-                ErrorKind::NameError
-            } else if class == self.class_store.runtime_error_class() {
-                ErrorKind::RuntimeError
-            } else if class == self.class_store.type_error_class() {
-                ErrorKind::TypeError
-            } else if class == self.class_store.value_error_class() {
-                ErrorKind::ValueError
-            } else {
-                ErrorKind::RuntimeError
-            };
+            // Exceptions derived from the built-in error classes keep the corresponding kind.
+            let store = &self.class_store;
+            let kinds = [
+                (store.attribute_error_class(), ErrorKind::AttributeError),
+                (store.compile_error_class(), ErrorKind::CompileError),
+                (store.import_error_class(), ErrorKind::ImportError),
+                (store.index_error_class(), ErrorKind::IndexError),
+                (store.name_error_class(), ErrorKind::NameError),
+                (store.runtime_error_class(), ErrorKind::RuntimeError),
+                (store.type_error_class(), ErrorKind::TypeError),
+                (store.value_error_class(), ErrorKind::ValueError),
+            ];
+            let kind = kinds
+                .iter()
+                .find(|(kind_class, _)| object::class_derives(class, *kind_class))
+                .map_or(ErrorKind::RuntimeError, |&(_, kind)| kind);
             let context_string = self.new_gc_obj_string("context");
             let borrowed_instance = instance.borrow();
             let context = borrowed_instance
@@ -1749,34 +4429,92 @@ impl Vm {
             (ErrorKind::RuntimeError, "exception".to_owned(), value)
         };
 
-        let msg = format!("Unhandled {}: {}", exc_description, context);
+        let prefix = if is_cause { "Caused by" } else { "Unhandled" };
+        let msg = format!("{} {}: {}", prefix, exc_description, context);
         let lines = msg.lines().collect::<Vec<_>>();
+        let mut error = Error::with_messages(kind, &lines);
+
+        if let Some(instance) = value.try_as_obj_instance() {
+            let mut fields: Vec<_> = instance
+                .borrow()
+                .fields
+                .iter()
+                .filter(|(name, _)| {
+                    !["context", "cause", "traceback", "error_code"].contains(&name.as_str())
+                })
+                .map(|(name, value)| (name.as_str().to_owned(), format!("{}", value)))
+                .collect();
+            fields.sort();
+            error.set_class(instance.borrow().class.name.as_str(), fields);
+
+            let traceback_string = self.new_gc_obj_string("traceback");
+            let cause_string = self.new_gc_obj_string("cause");
+            let code_string = self.new_gc_obj_string("error_code");
+            let (traceback, cause, code) = {
+                let borrowed_instance = instance.borrow();
+                (
+                    borrowed_instance.fields.get(&traceback_string).copied(),
+                    borrowed_instance.fields.get(&cause_string).copied(),
+                    borrowed_instance.fields.get(&code_string).copied(),
+                )
+            };
+            if let Some(code) = code
+                .and_then(|c| c.try_as_obj_string())
+                .and_then(|c| ErrorCode::from_name(c.as_str()))
+            {
+                error = error.with_code(code);
+            }
+            if let (true, Some(Value::ObjString(traceback))) = (is_cause, traceback) {
+                for line in traceback.as_str().lines() {
+                    error.add_message(line);
+                }
+            }
+            if let Some(cause) = cause.filter(|c| *c != Value::None) {
+                let cause = self.error_from_value(cause, true);
+                error.set_cause(cause);
+            }
+        }
 
-        Error::with_messages(kind, &lines)
+        error
     }
 
     fn try_handle_error(&mut self, error: Error) -> Result<(), Error> {
-        let obj_err = self.new_root_obj_err_from_error(error);
-        self.push(Value::ObjInstance(obj_err.as_gc()));
-        self.unwind_stack()
+        let obj_err = self.new_exception_from_error(error);
+        self.push(obj_err);
+        self.raise()
+    }
+
+    /// Returns the exception to raise for an error, which is the exception left unhandled by a
+    /// method called with `call_method` if that's where the error came from.
+    fn new_exception_from_error(&mut self, error: Error) -> Value {
+        match self.failed_method_fiber.take() {
+            Some(fiber) => *fiber.borrow().stack.peek(0),
+            None => Value::ObjInstance(self.new_root_obj_err_from_error(error).as_gc()),
+        }
     }
 
     fn build_hash_map(&mut self, num_elements: usize) -> Result<Root<RefCell<ObjHashMap>>, Error> {
         let map = self.new_root_obj_hash_map();
+        self.insert_hash_map_entries(map.as_gc(), num_elements)?;
+        Ok(map)
+    }
+
+    /// Inserts the specified number of key-value pairs on the top of the stack into the map,
+    /// removing them from the stack.
+    fn insert_hash_map_entries(
+        &mut self,
+        map: Gc<RefCell<ObjHashMap>>,
+        num_elements: usize,
+    ) -> Result<(), Error> {
         let begin = self.stack_size() - num_elements * 2;
         for i in 0..num_elements {
             let key = self.active_fiber().stack[begin + 2 * i];
-            if !key.has_hash() {
-                return Err(error!(
-                    ErrorKind::ValueError,
-                    "Cannot use unhashable value '{}' as HashMap key.", key
-                ));
-            }
+            let (key, instance_hash) = self.hash_map_key(map, key)?;
             let value = self.active_fiber().stack[begin + 2 * i + 1];
-            map.borrow_mut().elements.insert(key, value);
+            map.borrow_mut().insert(key, instance_hash, value);
         }
         self.discard(num_elements * 2);
-        Ok(map)
+        Ok(())
     }
 
     fn init_heap_allocated_data(&mut self) {
@@ -1786,11 +4524,12 @@ impl Vm {
             metaclass: root_base_metaclass.as_gc(),
             superclass: None,
             methods: object::new_obj_string_value_map(),
+            fields: RefCell::new(object::new_obj_string_value_map()),
         });
         let mut root_string_metaclass = Root::new(ObjClass::new(
             Gc::dangling(),
             root_base_metaclass.as_gc(),
-            Some(root_object_class.as_gc()),
+            Some(root_base_metaclass.as_gc()),
             object::new_obj_string_value_map(),
         ));
         let mut string_class = Root::new(ObjClass::new(
@@ -1823,70 +4562,206 @@ impl Vm {
         }
 
         let empty_chunk = Root::new(Chunk::new()).as_gc();
+        let iter_string = self.new_gc_obj_string("iter");
         let next_string = self.new_gc_obj_string("next");
+        let method_missing_string = self.new_gc_obj_string("__method_missing__");
+        let field_initialiser_string = self.new_gc_obj_string(common::FIELD_INITIALISER_NAME);
+        let call_string = self.new_gc_obj_string("__call__");
         self.active_chunk = empty_chunk;
+        self.iter_string = iter_string;
         self.next_string = next_string;
+        self.method_missing_string = method_missing_string;
+        self.field_initialiser_string = field_initialiser_string;
+        self.call_string = call_string;
         let class_store =
             CoreClassStore::new(self, root_base_metaclass.clone(), root_object_class.clone());
         self.class_store = class_store;
         let class_store =
             CoreClassStore::new_with_built_ins(self, root_base_metaclass, root_object_class);
-        self.core_chunks = self.chunks.clone();
         self.class_store = class_store;
     }
 
+    fn init_process_module(&mut self) {
+        self.define_native("process", "exit", core::process_exit);
+        #[cfg(feature = "std")]
+        self.define_native("process", "pid", core::process_pid);
+        self.module("process").borrow_mut().imported = true;
+    }
+
+    fn init_gc_module(&mut self) {
+        self.define_native("gc", "collect", core::gc_collect);
+        self.module("gc").borrow_mut().imported = true;
+    }
+
+    #[cfg(feature = "std")]
+    fn init_env_module(&mut self) {
+        self.define_native("env", "get", core::env_get);
+        self.define_native("env", "vars", core::env_vars);
+        let set = if self.options.env_access == EnvAccess::ReadWrite {
+            core::env_set
+        } else {
+            core::env_set_denied
+        };
+        self.define_native("env", "set", set);
+        self.module("env").borrow_mut().imported = true;
+    }
+
     fn init_built_in_globals(&mut self, module_path: &str) {
-        self.define_native(module_path, "clock", core::clock);
-        self.define_native(module_path, "type", core::type_);
-        self.define_native(module_path, "print", self.printer);
+        self.define_built_in_native(module_path, "clock", self.clock);
+        self.define_built_in_native(module_path, "type", core::type_);
+        self.define_built_in_native(module_path, "print", self.printer);
+        self.define_built_in_native(module_path, "eprint", core::eprint);
+        self.define_built_in_native(module_path, "sorted", core::sorted);
+        self.define_built_in_native(module_path, "min", core::min);
+        self.define_built_in_native(module_path, "max", core::max);
+        self.define_built_in_native(module_path, "module", core::module);
+        // Scripts check these to tell whether they're being run directly or were imported.
+        let name = self.new_gc_obj_string(module_path);
+        self.set_built_in(module_path, "__name__", Value::ObjString(name));
+        let is_main = Value::Boolean(module_path == "main");
+        self.set_built_in(module_path, "__main__", is_main);
         let base_metaclass = self.class_store.base_metaclass();
-        self.set_global(module_path, "Type", Value::ObjClass(base_metaclass));
+        self.set_built_in(module_path, "Type", Value::ObjClass(base_metaclass));
         let object_class = self.class_store.object_class();
-        self.set_global(module_path, "Object", Value::ObjClass(object_class));
+        self.set_built_in(module_path, "Object", Value::ObjClass(object_class));
         let nil_class = self.class_store.nil_class();
-        self.set_global(module_path, "Nil", Value::ObjClass(nil_class));
+        self.set_built_in(module_path, "Nil", Value::ObjClass(nil_class));
         let boolean_class = self.class_store.boolean_class();
-        self.set_global(module_path, "Bool", Value::ObjClass(boolean_class));
+        self.set_built_in(module_path, "Bool", Value::ObjClass(boolean_class));
         let number_class = self.class_store.num_class();
-        self.set_global(module_path, "Num", Value::ObjClass(number_class));
+        self.set_built_in(module_path, "Num", Value::ObjClass(number_class));
         let obj_closure_class = self.class_store.closure_class();
-        self.set_global(module_path, "Func", Value::ObjClass(obj_closure_class));
+        self.set_built_in(module_path, "Func", Value::ObjClass(obj_closure_class));
         let obj_native_class = self.class_store.native_class();
-        self.set_global(module_path, "BuiltIn", Value::ObjClass(obj_native_class));
+        self.set_built_in(module_path, "BuiltIn", Value::ObjClass(obj_native_class));
         let obj_closure_method_class = self.class_store.closure_method_class();
-        self.set_global(
+        self.set_built_in(
             module_path,
             "Method",
             Value::ObjClass(obj_closure_method_class),
         );
         let obj_native_method_class = self.class_store.native_method_class();
-        self.set_global(
+        self.set_built_in(
             module_path,
             "BuiltInMethod",
             Value::ObjClass(obj_native_method_class),
         );
+        let obj_partial_class = self.class_store.partial_class();
+        self.set_built_in(module_path, "Partial", Value::ObjClass(obj_partial_class));
         let obj_string_class = self.string_class.as_ref().expect("Expected Root.").as_gc();
-        self.set_global(module_path, "String", Value::ObjClass(obj_string_class));
+        self.set_built_in(module_path, "String", Value::ObjClass(obj_string_class));
+        let obj_iterable_class = self.class_store.iterable_class();
+        self.set_built_in(module_path, "Iterable", Value::ObjClass(obj_iterable_class));
         let obj_iter_class = self.class_store.iter_class();
-        self.set_global(module_path, "Iter", Value::ObjClass(obj_iter_class));
+        self.set_built_in(module_path, "Iter", Value::ObjClass(obj_iter_class));
         let obj_map_iter_class = self.class_store.map_iter_class();
-        self.set_global(module_path, "MapIter", Value::ObjClass(obj_map_iter_class));
+        self.set_built_in(module_path, "MapIter", Value::ObjClass(obj_map_iter_class));
         let obj_filter_iter_class = self.class_store.filter_iter_class();
-        self.set_global(
+        self.set_built_in(
             module_path,
             "FilterIter",
             Value::ObjClass(obj_filter_iter_class),
         );
+        let obj_take_iter_class = self.class_store.take_iter_class();
+        self.set_built_in(
+            module_path,
+            "TakeIter",
+            Value::ObjClass(obj_take_iter_class),
+        );
+        let obj_skip_iter_class = self.class_store.skip_iter_class();
+        self.set_built_in(
+            module_path,
+            "SkipIter",
+            Value::ObjClass(obj_skip_iter_class),
+        );
+        let obj_zip_iter_class = self.class_store.zip_iter_class();
+        self.set_built_in(module_path, "ZipIter", Value::ObjClass(obj_zip_iter_class));
+        let obj_enumerate_iter_class = self.class_store.enumerate_iter_class();
+        self.set_built_in(
+            module_path,
+            "EnumerateIter",
+            Value::ObjClass(obj_enumerate_iter_class),
+        );
+        let obj_chain_iter_class = self.class_store.chain_iter_class();
+        self.set_built_in(
+            module_path,
+            "ChainIter",
+            Value::ObjClass(obj_chain_iter_class),
+        );
+        let obj_generator_class = self.class_store.generator_class();
+        self.set_built_in(
+            module_path,
+            "Generator",
+            Value::ObjClass(obj_generator_class),
+        );
+        let obj_channel_class = self.class_store.channel_class();
+        self.set_built_in(module_path, "Channel", Value::ObjClass(obj_channel_class));
+        let obj_string_builder_class = self.class_store.string_builder_class();
+        self.set_built_in(
+            module_path,
+            "StringBuilder",
+            Value::ObjClass(obj_string_builder_class),
+        );
         let obj_tuple_class = self.class_store.tuple_class();
-        self.set_global(module_path, "Tuple", Value::ObjClass(obj_tuple_class));
+        self.set_built_in(module_path, "Tuple", Value::ObjClass(obj_tuple_class));
         let obj_vec_class = self.class_store.vec_class();
-        self.set_global(module_path, "Vec", Value::ObjClass(obj_vec_class));
+        self.set_built_in(module_path, "Vec", Value::ObjClass(obj_vec_class));
         let obj_range_class = self.class_store.range_class();
-        self.set_global(module_path, "Range", Value::ObjClass(obj_range_class));
+        self.set_built_in(module_path, "Range", Value::ObjClass(obj_range_class));
         let obj_hash_map_class = self.class_store.hash_map_class();
-        self.set_global(module_path, "HashMap", Value::ObjClass(obj_hash_map_class));
+        self.set_built_in(module_path, "HashMap", Value::ObjClass(obj_hash_map_class));
+        let obj_identity_map_class = self.class_store.identity_map_class();
+        self.set_built_in(
+            module_path,
+            "IdentityMap",
+            Value::ObjClass(obj_identity_map_class),
+        );
         let obj_fiber_class = self.class_store.fiber_class();
-        self.set_global(module_path, "Fiber", Value::ObjClass(obj_fiber_class));
+        self.set_built_in(module_path, "Fiber", Value::ObjClass(obj_fiber_class));
+        let error_class = self.class_store.error_class();
+        self.set_built_in(module_path, "Error", Value::ObjClass(error_class));
+        let stop_iter_class = self.class_store.stop_iter_class();
+        self.set_built_in(module_path, "StopIter", Value::ObjClass(stop_iter_class));
+        let runtime_error_class = self.class_store.runtime_error_class();
+        self.set_built_in(
+            module_path,
+            "RuntimeError",
+            Value::ObjClass(runtime_error_class),
+        );
+        let compile_error_class = self.class_store.compile_error_class();
+        self.set_built_in(
+            module_path,
+            "CompileError",
+            Value::ObjClass(compile_error_class),
+        );
+        let attribute_error_class = self.class_store.attribute_error_class();
+        self.set_built_in(
+            module_path,
+            "AttributeError",
+            Value::ObjClass(attribute_error_class),
+        );
+        let index_error_class = self.class_store.index_error_class();
+        self.set_built_in(
+            module_path,
+            "IndexError",
+            Value::ObjClass(index_error_class),
+        );
+        let import_error_class = self.class_store.import_error_class();
+        self.set_built_in(
+            module_path,
+            "ImportError",
+            Value::ObjClass(import_error_class),
+        );
+        let name_error_class = self.class_store.name_error_class();
+        self.set_built_in(module_path, "NameError", Value::ObjClass(name_error_class));
+        let type_error_class = self.class_store.type_error_class();
+        self.set_built_in(module_path, "TypeError", Value::ObjClass(type_error_class));
+        let value_error_class = self.class_store.value_error_class();
+        self.set_built_in(
+            module_path,
+            "ValueError",
+            Value::ObjClass(value_error_class),
+        );
     }
 
     fn load_frame(&mut self) {
@@ -1939,22 +4814,54 @@ impl Vm {
 }
 
 mod string_store {
-    use std::mem;
+    use alloc::vec;
+    use alloc::vec::Vec;
 
-    use crate::memory::Root;
+    use crate::memory::{Gc, Root, WeakGc};
     use crate::object::ObjString;
 
     const INIT_CAPACITY: usize = 4;
     const MAX_LOAD: f64 = 0.75;
 
+    #[derive(Clone, Debug)]
+    struct Entry {
+        hash: u64,
+        string: WeakGc<ObjString>,
+        /// Keeps the string alive for the lifetime of the store.
+        pin: Option<Root<ObjString>>,
+    }
+
+    impl Entry {
+        fn upgrade(&self) -> Option<Gc<ObjString>> {
+            self.string.upgrade()
+        }
+    }
+
+    /// Statistics describing the strings interned by a virtual machine.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub struct StringStoreStats {
+        /// The number of interned strings that are alive.
+        pub num_strings: usize,
+        /// The number of those strings that are kept alive by the store itself.
+        pub num_pinned: usize,
+        /// The number of entries whose strings have been collected but which haven't yet been
+        /// removed from the store.
+        pub num_dead: usize,
+        pub capacity: usize,
+    }
+
     // We want somewhere to intern our heap-allocated strings, and the built-in HashSet
     // unfortunately doesn't meet our requirements. We need fine-grained hash control, since we're
     // using a custom hash algorithm along with caching of hash on the stored ObjString, meaning the
     // &str objects we use for look-up and the ObjString objects we store have different
     // implementations of Hash.
+    //
+    // Entries refer to their strings weakly unless they're pinned, so strings created whilst
+    // running a script are collected once they're no longer used. The entries of collected strings
+    // act as tombstones until the store is next resized.
     #[derive(Clone, Debug)]
     pub(super) struct ObjStringStore {
-        entries: Vec<Option<Root<ObjString>>>,
+        entries: Vec<Option<Entry>>,
         size: usize,
         mask: usize,
     }
@@ -1964,67 +4871,123 @@ mod string_store {
             Default::default()
         }
 
-        pub(super) fn get(&self, key: (u64, &str)) -> Option<&Root<ObjString>> {
-            let entry = &self.entries[find_index(&self.entries, key, self.mask)];
-            if entry.is_some() {
-                entry.as_ref()
-            } else {
-                None
+        pub(super) fn get(&self, key: (u64, &str)) -> Option<Gc<ObjString>> {
+            find_index(&self.entries, key, self.mask).ok().map(|index| {
+                self.entries[index]
+                    .as_ref()
+                    .and_then(Entry::upgrade)
+                    .expect("Expected ObjString.")
+            })
+        }
+
+        /// Pins the string with the specified key, if it's in the store, so that it's never
+        /// collected.
+        pub(super) fn pin(&mut self, key: (u64, &str)) -> Option<Gc<ObjString>> {
+            let index = find_index(&self.entries, key, self.mask).ok()?;
+            let entry = self.entries[index].as_mut().expect("Expected Entry.");
+            let string = entry.upgrade().expect("Expected ObjString.");
+            if entry.pin.is_none() {
+                entry.pin = Some(string.as_root());
             }
+            Some(string)
         }
 
-        pub(super) fn insert(&mut self, value: Root<ObjString>) -> Option<Root<ObjString>> {
+        pub(super) fn insert(&mut self, string: Gc<ObjString>, pinned: bool) {
             if self.size + 1 > (self.entries.len() as f64 * MAX_LOAD) as usize {
-                self.adjust_capacity(self.entries.len() * 2);
+                self.resize();
             }
 
-            let key = (value.hash, value.as_str());
-            let index = find_index(&self.entries, key, self.mask);
-            let entry = &mut self.entries[index];
-
-            let is_new_key = entry.is_none();
-            if is_new_key {
+            let key = (string.hash, string.as_str());
+            let index = match find_index(&self.entries, key, self.mask) {
+                Ok(index) | Err(index) => index,
+            };
+            if self.entries[index].is_none() {
                 self.size += 1;
             }
-            entry.replace(value)
+            self.entries[index] = Some(Entry {
+                hash: string.hash,
+                string: string.downgrade(),
+                pin: if pinned { Some(string.as_root()) } else { None },
+            });
         }
 
-        fn adjust_capacity(&mut self, new_capacity: usize) {
-            let mut new_entries: Vec<Option<Root<ObjString>>> = vec![None; new_capacity];
-            let mask = new_capacity - 1;
-
-            for entry in self.entries.iter_mut() {
-                if entry.is_none() {
-                    continue;
+        pub(super) fn stats(&self) -> StringStoreStats {
+            let mut stats = StringStoreStats {
+                capacity: self.entries.len(),
+                ..Default::default()
+            };
+            for entry in self.entries.iter().flatten() {
+                if entry.upgrade().is_none() {
+                    stats.num_dead += 1;
+                } else {
+                    stats.num_strings += 1;
+                    if entry.pin.is_some() {
+                        stats.num_pinned += 1;
+                    }
                 }
+            }
+            stats
+        }
+
+        /// Discards the entries of collected strings, growing or shrinking the store to suit the
+        /// number of strings left.
+        pub(super) fn resize(&mut self) {
+            let num_strings = self
+                .entries
+                .iter()
+                .flatten()
+                .filter(|e| e.upgrade().is_some())
+                .count();
+            let mut capacity = INIT_CAPACITY;
+            while num_strings + 1 > (capacity as f64 * MAX_LOAD) as usize {
+                capacity *= 2;
+            }
 
-                let key = {
-                    let entry = entry.as_ref().unwrap();
-                    (entry.hash, entry.as_str())
+            let mut new_entries: Vec<Option<Entry>> = vec![None; capacity];
+            let mask = capacity - 1;
+            for entry in self.entries.drain(..).flatten() {
+                let string = match entry.upgrade() {
+                    Some(string) => string,
+                    None => continue,
                 };
-                let index = find_index(&new_entries, key, mask);
-                let dest = &mut new_entries[index];
-                *dest = mem::take(entry);
+                let key = (entry.hash, string.as_str());
+                let index = match find_index(&new_entries, key, mask) {
+                    Ok(index) | Err(index) => index,
+                };
+                new_entries[index] = Some(entry);
             }
 
             self.entries = new_entries;
+            self.size = num_strings;
             self.mask = mask;
         }
     }
 
-    fn find_index(entries: &Vec<Option<Root<ObjString>>>, key: (u64, &str), mask: usize) -> usize {
+    /// Returns the index of the entry with the specified key, or otherwise the index at which an
+    /// entry with the key should be inserted, which is the first tombstone or empty entry found.
+    fn find_index(
+        entries: &[Option<Entry>],
+        key: (u64, &str),
+        mask: usize,
+    ) -> Result<usize, usize> {
         let (hash, string) = key;
         let mut index = (hash as usize) & mask;
+        let mut tombstone = None;
 
         loop {
             match entries[index].as_ref() {
-                Some(entry) => {
-                    if entry.hash == hash && entry.as_str() == string {
-                        return index;
+                Some(entry) => match entry.upgrade() {
+                    Some(entry_string) => {
+                        if entry.hash == hash && entry_string.as_str() == string {
+                            return Ok(index);
+                        }
                     }
-                }
+                    None => {
+                        tombstone.get_or_insert(index);
+                    }
+                },
                 None => {
-                    return index;
+                    return Err(tombstone.unwrap_or(index));
                 }
             }
 
@@ -2035,10 +4998,858 @@ mod string_store {
     impl Default for ObjStringStore {
         fn default() -> Self {
             ObjStringStore {
-                entries: vec![Default::default(); INIT_CAPACITY],
+                entries: vec![None; INIT_CAPACITY],
                 size: 0,
                 mask: INIT_CAPACITY - 1,
             }
         }
     }
 }
+
+#[test]
+#[cfg(feature = "native_modules")]
+fn test_load_missing_native_module() {
+    let mut vm = Vm::with_built_ins();
+    let path = std::env::temp_dir().join("yarel_missing_native_module.so");
+    let error = vm.load_native_module(&path).unwrap_err();
+    assert_eq!(ErrorKind::ImportError, error.kind());
+    assert!(error.messages()[0].starts_with(&format!(
+        "Unable to load native module '{}':",
+        path.display()
+    )));
+}
+
+#[test]
+fn test_unhandled_error_subclass() {
+    let mut vm = Vm::with_built_ins();
+    let source = "#[derive(ValueError)]\nclass RangeError {\n    #[constructor]\n    fn new(self, context, low) {\n        super.new(context);\n        self.low = low;\n    }\n}\nthrow RangeError.new(\"Out of range.\", 2);\n";
+    let error = interpret(&mut vm, source.to_string(), None).unwrap_err();
+    assert_eq!(ErrorKind::ValueError, error.kind());
+    assert_eq!(Some("RangeError"), error.class_name());
+    assert_eq!(&[("low".to_string(), "2".to_string())], error.fields());
+}
+
+#[test]
+fn test_capture_output() {
+    let mut vm = Vm::with_built_ins();
+    assert_eq!("", vm.take_output());
+    vm.capture_output();
+    let source = "print(\"one\");\nprint([1, 2]);\nmissing;\n";
+    interpret(&mut vm, source.to_string(), None).unwrap_err();
+    assert_eq!(
+        "one\n[1, 2]\nUnhandled NameError: Undefined variable 'missing'.\n[module \"main\", line 3] in script\n",
+        vm.take_output()
+    );
+    assert_eq!("", vm.take_output());
+
+    interpret(&mut vm, "print(1);\nvar a = ;\n".to_string(), None).unwrap_err();
+    assert_eq!(
+        "[module \"main\", line 2] Error at ';': Expected expression.\n",
+        vm.take_output()
+    );
+}
+
+#[test]
+fn test_error_writer() {
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Rc<RefCell<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut vm = Vm::with_built_ins();
+    vm.capture_output();
+    let source = "print(1);\neprint(\"warning\");\nprint(2);\nmissing;\n";
+    interpret(&mut vm, source.to_string(), None).unwrap_err();
+    assert_eq!(
+        "1\nwarning\n2\nUnhandled NameError: Undefined variable 'missing'.\n[module \"main\", line 4] in script\n",
+        vm.take_output()
+    );
+
+    let buffer = Buffer::default();
+    vm.set_error_writer(buffer.clone());
+    interpret(&mut vm, source.to_string(), None).unwrap_err();
+    assert_eq!("1\n2\n", vm.take_output());
+    assert_eq!(
+        "warning\nUnhandled NameError: Undefined variable 'missing'.\n[module \"main\", line 4] in script\n",
+        String::from_utf8(buffer.0.borrow().clone()).unwrap()
+    );
+}
+
+#[test]
+fn test_error_codes() {
+    use std::error::Error as _;
+
+    let mut vm = Vm::with_built_ins();
+    let error = interpret(&mut vm, "print(missing);".to_string(), None).unwrap_err();
+    assert_eq!(ErrorCode::UndefinedVariable, error.code());
+
+    let source = "try {\n    [1].push();\n} catch e {\n    code = e.error_code;\n    throw e;\n}\n";
+    interpret(&mut vm, "var code;".to_string(), None).unwrap();
+    let error = interpret(&mut vm, source.to_string(), None).unwrap_err();
+    let code = vm.global("main", "code").unwrap();
+    assert_eq!("arity_mismatch", format!("{}", code));
+    assert_eq!(ErrorKind::TypeError, error.kind());
+    assert_eq!(ErrorCode::ArityMismatch, error.code());
+    assert!(error.fields().is_empty());
+
+    let source = "#[derive(ValueError)]\nclass BadIndex {\n    #[constructor]\n    fn new(self, context) {\n        super.new(context);\n    }\n}\ntry {\n    [1][2];\n} catch e {\n    var error = BadIndex.new(\"Bad.\");\n    error.cause = e;\n    throw error;\n}\n";
+    let error = interpret(&mut vm, source.to_string(), None).unwrap_err();
+    assert_eq!(ErrorCode::ValueError, error.code());
+    let cause = error.source().unwrap().downcast_ref::<Error>().unwrap();
+    assert_eq!(ErrorCode::IndexOutOfBounds, cause.code());
+    assert_eq!("index_out_of_bounds", cause.code().as_str());
+}
+
+#[test]
+fn test_frame_limit() {
+    let options = VmOptions {
+        frame_limit: 10,
+        ..Default::default()
+    };
+    let mut vm = Vm::with_options(options);
+    let source = "fn count(n) {\n    if n == 0 { return 0; }\n    return count(n - 1) + 1;\n}\ncount(8);\ncount(9);\n";
+    let error = interpret(&mut vm, source.to_string(), None).unwrap_err();
+    assert_eq!(ErrorKind::IndexError, error.kind());
+    assert_eq!(ErrorCode::StackOverflow, error.code());
+    assert_eq!("Unhandled IndexError: Stack overflow.", error.messages()[0]);
+    assert_eq!("[module \"main\", line 6] in script", error.messages()[3]);
+}
+
+#[test]
+fn test_stack_size() {
+    let options = VmOptions {
+        stack_size: 1024,
+        range_cache_size: 0,
+        ..Default::default()
+    };
+    let mut vm = Vm::with_options(options);
+    let source = "fn count(n) {\n    if n == 0 { return 0; }\n    return count(n - 1) + 1;\n}\nfor i in 0..2 {\n    count(100);\n}\ncount(1000);\n";
+    let error = interpret(&mut vm, source.to_string(), None).unwrap_err();
+    assert_eq!("Unhandled IndexError: Stack overflow.", error.messages()[0]);
+    assert_eq!("[module \"main\", line 8] in script", error.messages()[3]);
+}
+
+#[test]
+fn test_stack_size_temporaries() {
+    let options = VmOptions {
+        stack_size: 1024,
+        ..Default::default()
+    };
+    let mut vm = Vm::with_options(options);
+    vm.capture_output();
+    // The arguments of each enclosing call are left on the stack whilst the next is evaluated,
+    // so the temporaries of the script's frame outgrow the stack.
+    let zeros = vec!["0"; 250].join(", ");
+    let mut call = "0".to_string();
+    for _ in 0..5 {
+        call = format!("f({}, {})", zeros, call);
+    }
+    let source = format!(
+        "fn f(a) {{ return a; }}\ntry {{\n    {};\n}} catch e {{\n    print(e.error_code);\n}}\n{};\n",
+        call, call
+    );
+    let error = interpret(&mut vm, source, None).unwrap_err();
+    assert_eq!(ErrorKind::IndexError, error.kind());
+    assert_eq!(ErrorCode::StackOverflow, error.code());
+    assert_eq!(
+        "stack_overflow\nUnhandled IndexError: Stack overflow.\n[module \"main\", line 7] in script\n",
+        vm.take_output()
+    );
+}
+
+#[test]
+fn test_stack_size_spread() {
+    let options = VmOptions {
+        stack_size: 1024,
+        ..Default::default()
+    };
+    let mut vm = Vm::with_options(options);
+    vm.capture_output();
+    // The arguments of each enclosing call are left on the stack whilst the next is evaluated,
+    // so there's no space left for the spread arguments of the innermost call.
+    let zeros = vec!["0"; 250].join(", ");
+    let mut call = "f(*args)".to_string();
+    for _ in 0..4 {
+        call = format!("f({}, {})", zeros, call);
+    }
+    let source = format!(
+        "fn f(a, b, c) {{ return a + b + c; }}\nvar args = [*0..200];\nprint(f(*[1, 2, 3]));\n{};\n",
+        call
+    );
+    let error = interpret(&mut vm, source, None).unwrap_err();
+    assert_eq!(ErrorCode::StackOverflow, error.code());
+    assert_eq!(
+        "6\nUnhandled IndexError: Stack overflow.\n[module \"main\", line 4] in script\n",
+        vm.take_output()
+    );
+}
+
+#[test]
+fn test_pump() {
+    let mut vm = Vm::with_built_ins();
+    let source = "var inbox = Channel.new();\nvar total = 0;\nFiber.schedule(Fiber.new(|| {\n    while true {\n        total += inbox.receive();\n    }\n}));\n";
+    let function = compiler::compile(&mut vm, source.to_string(), None).unwrap();
+    vm.execute(function, &[]).unwrap();
+    assert_eq!(Ok(1), vm.pump());
+    assert_eq!(Ok(0), vm.pump());
+
+    let source = "inbox.send(2);\ninbox.send(3);\n";
+    let function = compiler::compile(&mut vm, source.to_string(), None).unwrap();
+    vm.execute(function, &[]).unwrap();
+    let total = vm.global("main", "total").unwrap();
+    assert_eq!(Some(0.0), total.try_as_number());
+    assert_eq!(Ok(1), vm.pump());
+    let total = vm.global("main", "total").unwrap();
+    assert_eq!(Some(5.0), total.try_as_number());
+}
+
+#[test]
+fn test_resume_pending() {
+    use std::cell::Cell;
+
+    thread_local!(static TOKEN: Cell<Option<PendingToken>> = const { Cell::new(None) });
+
+    fn fetch(vm: &mut Vm, _num_args: usize) -> Result<Value, Error> {
+        let token = vm.suspend_native_call()?;
+        TOKEN.with(|t| t.set(Some(token)));
+        Ok(Value::None)
+    }
+
+    let mut vm = Vm::with_built_ins();
+    vm.define_native("main", "fetch", fetch);
+    let source = "var results = [];\nFiber.schedule(Fiber.new(|| {\n    results.push(fetch());\n    try {\n        fetch();\n    }\n    catch e: ValueError {\n        results.push(e.context);\n    }\n}));\n";
+    interpret(&mut vm, source.to_string(), None).unwrap();
+    let token = TOKEN.with(|t| t.take()).unwrap();
+    assert_eq!("[]", format!("{}", vm.global("main", "results").unwrap()));
+
+    vm.resume_pending(token, Ok(Value::Number(1.0))).unwrap();
+    assert!(vm.resume_pending(token, Ok(Value::None)).is_err());
+    assert_eq!(Ok(1), vm.pump());
+    let token = TOKEN.with(|t| t.take()).unwrap();
+    let error = Error::with_message(ErrorKind::ValueError, "Timed out.");
+    vm.resume_pending(token, Err(error)).unwrap();
+    assert_eq!(Ok(1), vm.pump());
+    let results = vm.global("main", "results").unwrap();
+    assert_eq!("[1, \"Timed out.\"]", format!("{}", results));
+
+    let error = interpret(&mut vm, "fetch();".to_string(), None).unwrap_err();
+    assert_eq!(
+        "Unhandled RuntimeError: Cannot suspend a fiber that wasn't resumed by the scheduler.",
+        error.messages()[0]
+    );
+}
+
+#[test]
+fn test_call_value_reentrant() {
+    fn apply(vm: &mut Vm, _num_args: usize) -> Result<Value, Error> {
+        vm.call_value_reentrant(vm.peek(1), &[vm.peek(0)])
+    }
+
+    fn try_apply(vm: &mut Vm, _num_args: usize) -> Result<Value, Error> {
+        Ok(vm
+            .call_value_reentrant(vm.peek(1), &[vm.peek(0)])
+            .unwrap_or_default())
+    }
+
+    let mut vm = Vm::with_built_ins();
+    vm.define_native("main", "apply", apply);
+    vm.define_native("main", "try_apply", try_apply);
+    let source = "var results = [apply(|x| apply(|y| y * 2, x) + 1, 3), apply(type, 1)];\nclass Foo {\n    #[constructor]\n    fn new(self) {}\n    fn inc(self, n) { return n + 1; }\n}\nresults.push(apply(Foo.new().inc, 1));\ntry {\n    apply(|x| { throw \"bad\"; }, 1);\n}\ncatch e {\n    results.push(e);\n}\nresults.push(try_apply(|x| { throw \"ignored\"; }, 1));\nfn double(n) { return 2 * n; }\n";
+    interpret(&mut vm, source.to_string(), None).unwrap();
+    let results = vm.global("main", "results").unwrap();
+    assert_eq!("[7, <class Num>, 2, \"bad\", nil]", format!("{}", results));
+
+    // An error handled by a native isn't raised again by the next error.
+    let source = "try_apply(|x| { throw 1; }, 1);\n1 + nil;\n";
+    let error = interpret(&mut vm, source.to_string(), None).unwrap_err();
+    assert_eq!(
+        "Unhandled TypeError: Binary operands must be two numbers or two strings.",
+        error.messages()[0]
+    );
+
+    let source = "fn recurse(n) { return apply(recurse, n + 1); }\nrecurse(0);\n";
+    let error = interpret(&mut vm, source.to_string(), None).unwrap_err();
+    assert_eq!(ErrorCode::StackOverflow, error.code());
+    assert_eq!(0, vm.reentrant_depth);
+
+    let double = vm.global("main", "double").unwrap();
+    let result = vm.call_value_reentrant(double, &[Value::Number(2.0)]);
+    assert_eq!(Some(4.0), result.unwrap().try_as_number());
+    assert!(vm.call_value_reentrant(Value::Number(1.0), &[]).is_err());
+}
+
+#[test]
+fn test_set_clock() {
+    fn clock(_vm: &mut Vm, _num_args: usize) -> Result<Value, Error> {
+        Ok(Value::Number(1.5))
+    }
+
+    let mut vm = Vm::with_built_ins();
+    vm.set_clock(clock);
+    interpret(&mut vm, "var time = clock();".to_string(), None).unwrap();
+    let time = vm.global("main", "time").unwrap();
+    assert_eq!(Some(1.5), time.try_as_number());
+}
+
+#[test]
+fn test_string_store() {
+    let mut vm = Vm::with_built_ins();
+    let initial = vm.string_stats();
+    let source =
+        "var kept = \"kept\" + \"!\";\nfor i in 0..1000 {\n    var s = \"string ${i}\";\n}\n";
+    interpret(&mut vm, source.to_string(), None).unwrap();
+    memory::collect();
+    let stats = vm.string_stats();
+    assert!(stats.num_strings < initial.num_strings + 20);
+
+    vm.shrink_string_store();
+    let shrunk = vm.string_stats();
+    assert_eq!(0, shrunk.num_dead);
+    assert_eq!(stats.num_strings, shrunk.num_strings);
+    assert!(shrunk.capacity <= stats.capacity);
+    let kept = vm.global("main", "kept").unwrap();
+    assert_eq!("kept!", format!("{}", kept));
+
+    let pinned = vm.new_gc_obj_string("pinned");
+    memory::collect();
+    assert_eq!(pinned, vm.new_gc_obj_string("pinned"));
+}
+
+#[test]
+fn test_string_store_runtime_names() {
+    let mut vm = Vm::with_built_ins();
+    let source = "#[constructor(new)]\nclass Foo {}\nvar foo = Foo.new();\n";
+    interpret(&mut vm, source.to_string(), None).unwrap();
+    memory::collect();
+    vm.shrink_string_store();
+    let initial = vm.string_stats();
+
+    let source = concat!(
+        "for i in 0..1000 {\n",
+        "    try {\n",
+        "        foo.send(\"missing_${i}\");\n",
+        "    } catch e {}\n",
+        "    var bar = Foo.new();\n",
+        "    bar.set(\"field_${i}\", i);\n",
+        "}\n",
+    );
+    interpret(&mut vm, source.to_string(), None).unwrap();
+    memory::collect();
+    vm.shrink_string_store();
+    let stats = vm.string_stats();
+    assert!(stats.num_strings < initial.num_strings + 20);
+}
+
+#[test]
+fn test_new_root_obj_string_slice() {
+    let mut vm = Vm::new();
+    let text = "abcdefghijklmnopqrstuvwxyz".repeat(4);
+    let parent = vm.new_root_obj_string(&text);
+
+    let slice = vm.new_root_obj_string_slice(parent.as_gc(), 10..80);
+    assert_eq!(slice.as_str(), &text[10..80]);
+    assert_eq!(slice.as_str().as_ptr(), parent.as_str()[10..].as_ptr());
+    // Slices are interned like any other string.
+    assert_eq!(
+        slice.as_gc(),
+        vm.new_root_obj_string_slice(parent.as_gc(), 10..80).as_gc()
+    );
+    assert_eq!(slice.as_gc(), vm.new_root_obj_string(&text[10..80]).as_gc());
+
+    // Slices of slices refer to the original string.
+    let nested = vm.new_root_obj_string_slice(slice.as_gc(), 5..60);
+    assert_eq!(nested.as_str(), &text[15..70]);
+    assert_eq!(nested.as_str().as_ptr(), parent.as_str()[15..].as_ptr());
+
+    let whole = vm.new_root_obj_string_slice(parent.as_gc(), 0..text.len());
+    assert_eq!(whole.as_gc(), parent.as_gc());
+    let short = vm.new_root_obj_string_slice(parent.as_gc(), 0..3);
+    assert_eq!(short.as_gc(), vm.new_root_obj_string("abc").as_gc());
+
+    // The original string is kept alive by its slices.
+    let ptr = parent.as_str().as_ptr();
+    drop(parent);
+    drop(slice);
+    memory::collect();
+    assert_eq!(nested.as_str(), &text[15..70]);
+    assert_eq!(nested.as_str().as_ptr(), ptr.wrapping_add(15));
+}
+
+#[test]
+fn test_intern_limit() {
+    let options = VmOptions {
+        intern_limit: 8,
+        ..Default::default()
+    };
+    let mut vm = Vm::with_options(options);
+    let source = "var short = \"abc\" + \"def\";\nvar long = \"abcdef\" + \"ghijkl\";\nvar same = long == \"abcdefghijk\" + \"l\";\nvar map = {long: 1};\nvar found = map.get(\"abcdefghijk\" + \"l\");\n";
+    let before = vm.string_stats();
+    interpret(&mut vm, source.to_string(), None).unwrap();
+    let after = vm.string_stats();
+    let short = vm
+        .global("main", "short")
+        .unwrap()
+        .try_as_obj_string()
+        .unwrap();
+    assert_eq!(short, vm.new_root_obj_string("abcdef").as_gc());
+    let long = vm
+        .global("main", "long")
+        .unwrap()
+        .try_as_obj_string()
+        .unwrap();
+    assert_ne!(long, vm.new_root_obj_string("abcdefghijkl").as_gc());
+    assert_eq!(Value::Boolean(true), vm.global("main", "same").unwrap());
+    assert_eq!(
+        Some(1.0),
+        vm.global("main", "found").unwrap().try_as_number()
+    );
+    assert_eq!(vm.string_stats().num_strings, after.num_strings);
+    assert!(after.num_strings > before.num_strings);
+}
+
+#[cfg(all(test, feature = "serde"))]
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+enum SerdeShape {
+    Empty,
+    Circle(f64),
+    Rect { width: u32, height: u32 },
+}
+
+#[cfg(all(test, feature = "serde"))]
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+struct SerdeConfig {
+    name: String,
+    retries: i32,
+    ratio: f64,
+    tags: Vec<String>,
+    limits: HashMap<String, u8>,
+    origin: (i64, i64),
+    parent: Option<String>,
+    shapes: Vec<SerdeShape>,
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_serde_round_trip() {
+    let mut vm = Vm::with_built_ins();
+    let config = SerdeConfig {
+        name: "main".to_string(),
+        retries: -3,
+        ratio: 0.5,
+        tags: vec!["a".to_string(), "b".to_string()],
+        limits: vec![("depth".to_string(), 4)].into_iter().collect(),
+        origin: (1, 2),
+        parent: None,
+        shapes: vec![
+            SerdeShape::Empty,
+            SerdeShape::Circle(1.5),
+            SerdeShape::Rect {
+                width: 2,
+                height: 3,
+            },
+        ],
+    };
+    let value = vm.value_from_serde(&config).unwrap();
+    vm.set_global("main", "config", value);
+    let source = "var name = config.get(\"name\");\nvar second = config.get(\"tags\")[1];\nvar x = config.get(\"origin\")[0];\nconfig.insert(\"retries\", 5);\nconfig.insert(\"parent\", \"root\");\n";
+    interpret(&mut vm, source.to_string(), None).unwrap();
+    assert_eq!("main", format!("{}", vm.global("main", "name").unwrap()));
+    assert_eq!("b", format!("{}", vm.global("main", "second").unwrap()));
+    assert_eq!(Some(1.0), vm.global("main", "x").unwrap().try_as_number());
+
+    let value = vm.global("main", "config").unwrap();
+    let updated: SerdeConfig = vm.value_to_serde(value).unwrap();
+    assert_eq!(
+        SerdeConfig {
+            retries: 5,
+            parent: Some("root".to_string()),
+            ..config
+        },
+        updated
+    );
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_serde_instance_and_errors() {
+    let mut vm = Vm::with_built_ins();
+    let source = "class Point {\n    #[constructor]\n    fn new(self, x, y) {\n        self.x = x;\n        self.y = y;\n    }\n}\nvar point = Point.new(1, 2);\nvar cycle = [];\ncycle.push(cycle);\n";
+    interpret(&mut vm, source.to_string(), None).unwrap();
+
+    let point = vm.global("main", "point").unwrap();
+    let fields: HashMap<String, i32> = vm.value_to_serde(point).unwrap();
+    assert_eq!(Some(&2), fields.get("y"));
+
+    let function = vm.global("main", "Point").unwrap();
+    let error = vm.value_to_serde::<String>(function).unwrap_err();
+    assert_eq!(ErrorKind::TypeError, error.kind());
+
+    let error = vm.value_to_serde::<u8>(Value::Number(1.5)).unwrap_err();
+    assert_eq!(ErrorKind::ValueError, error.kind());
+
+    #[allow(dead_code)]
+    #[derive(Debug, serde::Deserialize)]
+    struct Nested(Vec<Nested>);
+    let cycle = vm.global("main", "cycle").unwrap();
+    let error = vm.value_to_serde::<Nested>(cycle).unwrap_err();
+    assert_eq!(ErrorKind::ValueError, error.kind());
+}
+
+#[test]
+fn test_set_args() {
+    let mut vm = Vm::with_built_ins();
+    vm.capture_output();
+    vm.set_args(&["first".to_string(), "second".to_string()]);
+    let source = "print(args.len());\nprint(args[1]);\n";
+    interpret(&mut vm, source.to_string(), None).unwrap();
+    assert_eq!("2\nsecond\n", vm.take_output());
+}
+
+#[test]
+fn test_env_access() {
+    let mut vm = Vm::with_built_ins();
+    let error = interpret(&mut vm, "import \"env\";\n".to_string(), None).unwrap_err();
+    assert_eq!(ErrorCode::ModuleNotFound, error.code());
+    assert!(error.messages()[0]
+        .contains("Module 'env' is disabled. Enable it by setting VmOptions::env_access."));
+
+    let options = VmOptions {
+        env_access: EnvAccess::ReadWrite,
+        ..Default::default()
+    };
+    let mut vm = Vm::with_options(options);
+    vm.capture_output();
+    let source = "import \"env\";\nenv.set(\"YAREL_TEST_ENV_ACCESS\", \"value\");\nprint(env.get(\"YAREL_TEST_ENV_ACCESS\"));\nprint(env.vars().get(\"YAREL_TEST_ENV_ACCESS\"));\nprint(env.get(\"YAREL_TEST_ENV_MISSING\"));\n";
+    interpret(&mut vm, source.to_string(), None).unwrap();
+    assert_eq!("value\nvalue\nnil\n", vm.take_output());
+
+    let options = VmOptions {
+        env_access: EnvAccess::ReadOnly,
+        ..Default::default()
+    };
+    let mut vm = Vm::with_options(options);
+    vm.capture_output();
+    let source = "import \"env\";\nprint(env.get(\"YAREL_TEST_ENV_ACCESS\"));\nenv.set(\"YAREL_TEST_ENV_ACCESS\", \"other\");\n";
+    let error = interpret(&mut vm, source.to_string(), None).unwrap_err();
+    assert_eq!(
+        "Unhandled RuntimeError: Setting environment variables is not permitted.",
+        error.messages()[0]
+    );
+    let value = std::env::var("YAREL_TEST_ENV_ACCESS");
+    assert_eq!(Ok("value"), value.as_deref());
+}
+
+#[test]
+fn test_strict_math() {
+    let mut vm = Vm::with_built_ins();
+    vm.capture_output();
+    let source = "print(1 / 0);\nprint(0 % 0);\nprint(1e308 * 10);\n";
+    interpret(&mut vm, source.to_string(), None).unwrap();
+    assert_eq!("inf\nNaN\ninf\n", vm.take_output());
+
+    let options = VmOptions {
+        strict_math: true,
+        ..Default::default()
+    };
+    let mut vm = Vm::with_options(options);
+    vm.capture_output();
+    let source = "var one = 1;\nvar inf = one / 0;\n";
+    let error = interpret(&mut vm, source.to_string(), None).unwrap_err();
+    assert_eq!(
+        "Unhandled ValueError: Arithmetic on '1' and '0' produced 'inf'.",
+        error.messages()[0]
+    );
+    vm.take_output();
+
+    let source = "var x = 1;\ntry {\n    x /= 0;\n} catch e {\n    print(e.context);\n}\nprint(x);\nprint(0 - 1e308 - 1e308 + 1);\n";
+    let error = interpret(&mut vm, source.to_string(), None).unwrap_err();
+    assert_eq!(ErrorKind::ValueError, error.kind());
+    let output = vm.take_output();
+    assert!(
+        output.starts_with("Arithmetic on '1' and '0' produced 'inf'.\n1\nUnhandled ValueError")
+    );
+
+    let source = "print(0 % 0);\n";
+    let error = interpret(&mut vm, source.to_string(), None).unwrap_err();
+    assert_eq!(
+        "Unhandled ValueError: Arithmetic on '0' and '0' produced 'NaN'.",
+        error.messages()[0]
+    );
+    vm.take_output();
+
+    let source = "var inf = \"inf\".to_num();\nprint(inf + 1);\nprint(inf - inf);\n";
+    interpret(&mut vm, source.to_string(), None).unwrap();
+    assert_eq!("inf\nNaN\n", vm.take_output());
+}
+
+#[test]
+fn test_shebang() {
+    let mut vm = Vm::with_built_ins();
+    vm.capture_output();
+    let source = "#!/usr/bin/env yarel\nprint(1);\nmissing;\n";
+    interpret(&mut vm, source.to_string(), None).unwrap_err();
+    assert_eq!(
+        "1\nUnhandled NameError: Undefined variable 'missing'.\n[module \"main\", line 3] in script\n",
+        vm.take_output()
+    );
+
+    let source = "print(2);\n#!/usr/bin/env yarel\n";
+    let error = interpret(&mut vm, source.to_string(), None).unwrap_err();
+    assert_eq!(ErrorKind::CompileError, error.kind());
+}
+
+#[test]
+fn test_process_exit() {
+    let mut vm = Vm::with_built_ins();
+    vm.capture_output();
+    let source = "import \"process\";\nfn stop() {\n    try {\n        process.exit(3);\n    } catch e {\n        print(\"caught\");\n    } finally {\n        print(\"finally\");\n    }\n}\nstop();\nprint(\"after\");\n";
+    let error = interpret(&mut vm, source.to_string(), None).unwrap_err();
+    assert_eq!(ErrorCode::Exit, error.code());
+    assert_eq!(Some(3), vm.take_exit_code());
+    assert_eq!(None, vm.take_exit_code());
+    assert_eq!("", vm.take_output());
+
+    let source = "var fiber = Fiber.new(|| process.exit(0));\nfiber.call();\n";
+    let error = interpret(&mut vm, source.to_string(), None).unwrap_err();
+    assert_eq!(ErrorCode::Exit, error.code());
+    assert_eq!(Some(0), vm.take_exit_code());
+
+    let source = "print(process.pid() > 0);\nprocess.exit(1.5);\n";
+    let error = interpret(&mut vm, source.to_string(), None).unwrap_err();
+    assert_eq!(ErrorKind::TypeError, error.kind());
+    assert_eq!(None, vm.take_exit_code());
+    let output = vm.take_output();
+    assert!(output.starts_with("true\nUnhandled TypeError: Expected an integer"));
+
+    for code in ["300", "-1"] {
+        let source = format!("process.exit({});\n", code);
+        let error = interpret(&mut vm, source, None).unwrap_err();
+        assert_eq!(ErrorKind::ValueError, error.kind());
+        assert_eq!(
+            format!(
+                "Unhandled ValueError: Exit code {} is out of range 0 to 255.",
+                code
+            ),
+            error.messages()[0]
+        );
+        assert_eq!(None, vm.take_exit_code());
+    }
+    vm.take_output();
+    let error = interpret(&mut vm, "process.exit(255);\n".to_string(), None).unwrap_err();
+    assert_eq!(ErrorCode::Exit, error.code());
+    assert_eq!(Some(255), vm.take_exit_code());
+}
+
+#[test]
+#[cfg(unix)]
+fn test_os_run() {
+    let mut vm = Vm::with_built_ins();
+    let error = interpret(&mut vm, "import \"os\";\n".to_string(), None).unwrap_err();
+    assert_eq!(ErrorCode::ModuleNotFound, error.code());
+    assert!(error.messages()[0]
+        .contains("Module 'os' is disabled. Enable it by setting VmOptions::allow_subprocess."));
+
+    let options = VmOptions {
+        allow_subprocess: true,
+        ..Default::default()
+    };
+    let mut vm = Vm::with_options(options);
+    vm.capture_output();
+    let source =
+        "import \"os\";\nprint(os.run(\"sh\", [\"-c\", \"echo out; echo err >&2; exit 3\"]));\n";
+    interpret(&mut vm, source.to_string(), None).unwrap();
+    assert_eq!("(3, \"out\\n\", \"err\\n\")\n", vm.take_output());
+
+    let source = "os.run(\"yarel-missing-program\", []);\n";
+    let error = interpret(&mut vm, source.to_string(), None).unwrap_err();
+    assert_eq!(ErrorKind::RuntimeError, error.kind());
+    let source = "os.run(\"sh\", [1]);\n";
+    let error = interpret(&mut vm, source.to_string(), None).unwrap_err();
+    assert_eq!(ErrorKind::TypeError, error.kind());
+}
+
+#[test]
+fn test_instruction_count() {
+    let mut vm = Vm::with_built_ins();
+    assert_eq!(None, vm.instruction_count());
+    vm.enable_instruction_count();
+    let mut counts = Vec::new();
+    for n in &[10, 20, 30] {
+        let start = vm.instruction_count().unwrap();
+        let source = format!("var i = 0;\nwhile i < {} {{\n    i += 1;\n}}\n", n);
+        interpret(&mut vm, source, None).unwrap();
+        counts.push(vm.instruction_count().unwrap() - start);
+    }
+    assert!(counts[0] > 0);
+    assert_eq!(counts[1] - counts[0], counts[2] - counts[1]);
+}
+
+#[test]
+fn test_gc_stats() {
+    let mut vm = Vm::with_built_ins();
+    let before = vm.gc_stats();
+    let source = "import \"gc\";\nfor i in 0..10 {\n    [i];\n}\ngc.collect();\n";
+    interpret(&mut vm, source.to_string(), None).unwrap();
+    let after = vm.gc_stats();
+    assert!(after.collections > before.collections);
+    assert!(after.bytes_allocated > before.bytes_allocated);
+    assert!(after.bytes_freed > before.bytes_freed);
+    assert!(after.total_pause >= after.max_pause);
+    assert_eq!(
+        after.bytes_allocated - after.bytes_freed,
+        after.heap_bytes as u64
+    );
+
+    vm.set_gc_options(GcOptions {
+        initial_heap_bytes: 1 << 20,
+        growth_factor: 1.5,
+        pause_target: Some(std::time::Duration::from_micros(1)),
+        ..Default::default()
+    });
+    let source = "var v = [];\nfor i in 0..100 {\n    v.push([i]);\n}\nprint(v.len());\n";
+    vm.capture_output();
+    interpret(&mut vm, source.to_string(), None).unwrap();
+    assert_eq!("100\n", vm.take_output());
+    vm.set_gc_options(GcOptions::default());
+}
+
+#[test]
+fn test_long_jumps() {
+    // Each statement compiles to 9 bytes, so the bodies below are too large for 16-bit jumps.
+    let body = "        x = x + 1;\n".repeat(8000);
+    let source = format!(
+        "fn f(n) {{
+    var x = 0;
+    if n > 0 {{
+{body}    }} else {{
+        x = -1;
+    }}
+    while x < 3 * 8000 {{
+{body}        if n > 1 {{ break; }}
+    }}
+    for i in 0..2 {{
+{body}    }}
+    return x;
+}}
+print(f(0));
+print(f(1));
+print(f(2));
+",
+        body = body
+    );
+    let mut vm = Vm::with_built_ins();
+    vm.capture_output();
+    interpret(&mut vm, source, None).unwrap();
+    assert_eq!("47999\n40000\n32000\n", vm.take_output());
+}
+
+#[test]
+fn test_long_exception_handler() {
+    // The try block is too large for 16-bit offsets to the catch and finally blocks.
+    let body = "        x = x + 1;\n".repeat(9000);
+    let source = format!(
+        "fn f(n) {{
+    var x = 0;
+    try {{
+{body}        if n > 0 {{ throw n; }}
+    }} catch e {{
+        x = -x;
+    }} finally {{
+        x = x + 1;
+    }}
+    return x;
+}}
+print(f(0));
+print(f(1));
+try {{
+    var x = 0;
+{body}    throw x;
+}} catch e {{
+    print(e);
+}}
+",
+        body = body
+    );
+    let mut vm = Vm::with_built_ins();
+    vm.capture_output();
+    interpret(&mut vm, source, None).unwrap();
+    assert_eq!("9001\n-8999\n9000\n", vm.take_output());
+}
+
+#[test]
+fn test_disassemble_value() {
+    let mut vm = Vm::with_built_ins();
+    let source = "fn f() {\n    return nil;\n}\n";
+    interpret(&mut vm, source.to_string(), None).unwrap();
+    let f = vm.global("main", "f").unwrap();
+    let listing = vm.disassemble_value(f).unwrap();
+    assert_eq!(
+        "0000    2 NIL\n0001    | RETURN\n0002    3 NIL\n0003    | RETURN\n",
+        listing
+    );
+    assert_eq!(None, vm.disassemble_value(Value::Number(1.0)));
+}
+
+#[test]
+fn test_interpret_returns_final_expression() {
+    let mut vm = Vm::with_built_ins();
+    let result = interpret(&mut vm, "var a = 1;\na + 1;\n".to_string(), None).unwrap();
+    assert_eq!(Value::Number(2.0), result);
+    let result = interpret(&mut vm, "a = 3;\n".to_string(), None).unwrap();
+    assert_eq!(Value::None, result);
+    let result = interpret(&mut vm, "var b = a;\n".to_string(), None).unwrap();
+    assert_eq!(Value::None, result);
+
+    // The result outlives any fibers the module scheduled.
+    let source = "Fiber.schedule(Fiber.new(|| [1]));\n[2, 3];\n";
+    let result = interpret(&mut vm, source.to_string(), None).unwrap();
+    assert_eq!("[2, 3]", format!("{}", result));
+}
+
+#[test]
+fn test_dead_chunks_are_freed() {
+    let mut vm = Vm::with_built_ins();
+    let source = "1 + 2;\n".repeat(100);
+    interpret(&mut vm, source.clone(), None).unwrap();
+    memory::collect();
+    let heap_bytes = vm.gc_stats().heap_bytes;
+    for _ in 0..10 {
+        interpret(&mut vm, source.clone(), None).unwrap();
+    }
+    memory::collect();
+    assert_eq!(heap_bytes, vm.gc_stats().heap_bytes);
+}
+
+#[test]
+#[cfg(any(debug_assertions, feature = "gc_poison"))]
+fn test_gc_poison_catches_missing_root() {
+    use std::panic::{self, AssertUnwindSafe};
+
+    let mut vm = Vm::with_built_ins();
+    vm.set_gc_options(GcOptions {
+        stress: true,
+        poison_freed: true,
+        ..Default::default()
+    });
+    // The root is dropped straight away, so the next allocation frees the Vec.
+    let vec = vm.new_root_obj_vec().as_gc();
+    let line = line!() - 1;
+    vm.new_root_obj_vec();
+    let result = panic::catch_unwind(AssertUnwindSafe(|| vec.borrow().elements.len()));
+    let payload = result.unwrap_err();
+    let message = payload.downcast_ref::<String>().unwrap();
+    assert!(message.contains(&format!("allocated at {}:{}:", file!(), line)));
+    assert!(message.ends_with("so is missing a root."));
+    vm.set_gc_options(GcOptions::default());
+}
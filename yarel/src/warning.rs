@@ -0,0 +1,186 @@
+/* Copyright 2020-2021 Matt Spraggs
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use alloc::string::String;
+use core::fmt;
+
+/// The kinds of suspicious code the compiler can warn about. None of these are reported unless
+/// they've been enabled on the `Vm`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum WarningKind {
+    UnusedVariable,
+    UnusedImport,
+    UnreachableCode,
+    ShadowedVariable,
+}
+
+impl WarningKind {
+    pub const ALL: [WarningKind; 4] = [
+        WarningKind::UnusedVariable,
+        WarningKind::UnusedImport,
+        WarningKind::UnreachableCode,
+        WarningKind::ShadowedVariable,
+    ];
+
+    /// The name used to refer to this kind of warning on the command line.
+    pub fn name(&self) -> &'static str {
+        match self {
+            WarningKind::UnusedVariable => "unused-variable",
+            WarningKind::UnusedImport => "unused-import",
+            WarningKind::UnreachableCode => "unreachable-code",
+            WarningKind::ShadowedVariable => "shadowed-variable",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|k| k.name() == name)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Warning {
+    kind: WarningKind,
+    message: String,
+}
+
+impl Warning {
+    pub fn new(kind: WarningKind, message: &str) -> Self {
+        Warning {
+            kind,
+            message: String::from(message),
+        }
+    }
+
+    pub fn kind(&self) -> WarningKind {
+        self.kind
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+#[cfg(test)]
+fn compile_warnings(source: &str) -> Vec<String> {
+    let mut vm = crate::vm::Vm::with_built_ins();
+    vm.enable_warnings(&WarningKind::ALL);
+    crate::compiler::compile(&mut vm, source.to_string(), None).unwrap();
+    vm.take_warnings()
+        .iter()
+        .map(|w| w.message().to_string())
+        .collect()
+}
+
+#[test]
+fn test_warning_kind_names() {
+    for &kind in &WarningKind::ALL {
+        assert_eq!(Some(kind), WarningKind::from_name(kind.name()));
+    }
+    assert_eq!(None, WarningKind::from_name("all"));
+}
+
+#[test]
+fn test_compile_warnings() {
+    let source = "\
+import \"lib/foo\";
+import \"lib/bar\";
+fn f(a) {
+    var x = 1;
+    var _y = 2;
+    {
+        var a = 3;
+        return a;
+        print(_y);
+    }
+}
+print(bar);
+";
+    assert_eq!(
+        vec![
+            "[module \"main\", line 1] Warning (unused-import) at 'foo': \
+             Imported module is never used."
+                .to_string(),
+            "[module \"main\", line 4] Warning (unused-variable) at 'x': \
+             Variable is never used."
+                .to_string(),
+            "[module \"main\", line 7] Warning (shadowed-variable) at 'a': \
+             Variable shadows a variable in an enclosing scope."
+                .to_string(),
+            "[module \"main\", line 9] Warning (unreachable-code): \
+             Code after 'return' is never executed."
+                .to_string(),
+        ],
+        compile_warnings(source)
+    );
+}
+
+#[test]
+fn test_warnings_disabled_by_default() {
+    let mut vm = crate::vm::Vm::with_built_ins();
+    let source = "{ var x = 1; }";
+    crate::compiler::compile(&mut vm, source.to_string(), None).unwrap();
+    assert!(vm.take_warnings().is_empty());
+}
+
+#[test]
+fn test_strict_module_warns_about_shadowing() {
+    let mut vm = crate::vm::Vm::with_built_ins();
+    let source = "\
+#![strict]
+fn f(a) {
+    var x = 1;
+    {
+        var a = 2;
+        print(a);
+    }
+}
+";
+    crate::compiler::compile(&mut vm, source.to_string(), None).unwrap();
+    let warnings: Vec<_> = vm
+        .take_warnings()
+        .iter()
+        .map(|w| w.message().to_string())
+        .collect();
+    assert_eq!(
+        vec![
+            "[module \"main\", line 5] Warning (shadowed-variable) at 'a': \
+             Variable shadows a variable in an enclosing scope."
+                .to_string()
+        ],
+        warnings
+    );
+}
+
+#[test]
+fn test_warning_callback() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let mut vm = crate::vm::Vm::with_built_ins();
+    vm.enable_warnings(&WarningKind::ALL);
+    let reported = Rc::new(RefCell::new(Vec::new()));
+    let sink = reported.clone();
+    vm.set_warning_callback(move |w| sink.borrow_mut().push(w.kind()));
+    let source = "{ var x = 1; }";
+    crate::compiler::compile(&mut vm, source.to_string(), None).unwrap();
+    assert_eq!(vec![WarningKind::UnusedVariable], *reported.borrow());
+    assert!(vm.take_warnings().is_empty());
+}
@@ -0,0 +1,54 @@
+/* Copyright 2021 Matt Spraggs
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#![cfg(feature = "native_modules")]
+
+use std::env::consts::{DLL_PREFIX, DLL_SUFFIX};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use yarel::error::ErrorKind;
+use yarel::vm::Vm;
+
+/// Builds the yarel-native-fixture crate and returns the path to the shared library. The build
+/// uses its own target directory, since the one the tests were built in is locked by cargo.
+fn build_fixture() -> PathBuf {
+    let target_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("native");
+    let status = Command::new(env!("CARGO"))
+        .args(["build", "-q", "-p", "yarel-native-fixture", "--target-dir"])
+        .arg(&target_dir)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .status()
+        .expect("Unable to run cargo.");
+    assert!(status.success(), "Unable to build yarel-native-fixture.");
+    target_dir
+        .join("debug")
+        .join(format!("{}yarel_native_fixture{}", DLL_PREFIX, DLL_SUFFIX))
+}
+
+#[test]
+fn test_native_module_init_error() {
+    let path = build_fixture();
+    let mut vm = Vm::with_built_ins();
+    let error = vm.load_native_module(&path).unwrap_err();
+    assert_eq!(ErrorKind::ImportError, error.kind());
+    assert_eq!(
+        format!("Native module '{}' failed to initialise.", path.display()),
+        error.messages()[0]
+    );
+    let cause = error.cause().expect("Expected the initialisation error.");
+    assert_eq!(ErrorKind::RuntimeError, cause.kind());
+    assert_eq!("Fixture module refuses to initialise.", cause.messages()[0]);
+}